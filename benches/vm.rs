@@ -0,0 +1,55 @@
+//! Compile-time and VM throughput benchmarks.
+//!
+//! Run with `cargo bench --features bench`.
+
+use abra_lang::cli::{compile_source, run_captured};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::hint::black_box;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("loop", include_str!("programs/loop.abra")),
+    ("arith", include_str!("programs/arith.abra")),
+    ("fib", include_str!("programs/fib.abra")),
+    ("sort", include_str!("programs/sort.abra")),
+    ("strings", include_str!("programs/strings.abra")),
+    ("maps", include_str!("programs/maps.abra")),
+];
+
+fn bench_compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    for (name, source) in PROGRAMS {
+        if let Err(err) = compile_source(source, 0) {
+            eprintln!("skipping '{}': {}", name, err);
+            continue;
+        }
+        group.bench_function(*name, |b| {
+            b.iter(|| compile_source(black_box(source), 0).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    for (name, source) in PROGRAMS {
+        let code = match compile_source(source, 0) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("skipping '{}': {}", name, err);
+                continue;
+            }
+        };
+        // The VM consumes its program, so hand each iteration a fresh copy outside the timing.
+        group.bench_function(*name, |b| {
+            b.iter_batched(
+                || code.clone(),
+                |code| run_captured(code).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compile, bench_run);
+criterion_main!(benches);
@@ -0,0 +1,415 @@
+//! Debug adapter behind `abra dap`, speaking the Debug Adapter Protocol over stdio.
+//!
+//! The program runs on an in-memory platform, since stdin and stdout carry the
+//! protocol: what it prints is forwarded as `output` events and its input comes from
+//! the launch configuration's `input`. Running is synchronous, so a `pause` can't
+//! interrupt a program that never reaches a breakpoint.
+
+use crate::cli::{
+    compile_named,
+    lsp::{read_message, write_message},
+    render_error, render_message,
+};
+use crate::runtime::{
+    debug::stack_entry,
+    debugger::{Debugger, Resume, Stop},
+    platform::{InMemory, SharedBuffer},
+    value::Value as AbraValue,
+};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    io::{BufRead, Write},
+};
+
+/// The only thread a program has.
+const THREAD_ID: u64 = 1;
+
+/// Scopes each stack frame shows; a frame's `variablesReference`s are its index
+/// times their count plus one of these.
+const LOCALS_SCOPE: u64 = 1;
+const GLOBALS_SCOPE: u64 = 2;
+const REGISTERS_SCOPE: u64 = 3;
+const SCOPE_COUNT: u64 = 3;
+
+/// Serves the client on stdin and stdout until it disconnects.
+pub fn run_dap() -> Result<()> {
+    let stdin = std::io::stdin();
+    Adapter::default().serve(&mut stdin.lock(), &mut std::io::stdout())
+}
+
+#[derive(Default)]
+pub struct Adapter {
+    /// Sequence number of the last message sent.
+    seq: u64,
+    /// The launched program, until it ends.
+    debugger: Option<Debugger>,
+    /// What the program printed that hasn't been sent yet.
+    output: SharedBuffer,
+    sent_output: usize,
+    /// Lines the client asked to break at before launching, by file, each with its
+    /// condition.
+    pending_breakpoints: HashMap<String, Vec<(usize, Option<String>)>>,
+    /// Functions the client asked to break in before launching.
+    pending_function_breakpoints: Vec<(String, Option<String>)>,
+    stop_on_entry: bool,
+}
+
+impl Adapter {
+    /// Answers the requests on `input` until `disconnect` or the end of the input.
+    pub fn serve(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+        while let Some(request) = read_message(input)? {
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let mut events = Vec::new();
+            let result = self.handle(&command, &request["arguments"], &mut events);
+            let mut response = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(body) => response["body"] = body,
+                Err(message) => response["message"] = message.into(),
+            }
+            self.send(output, response)?;
+            for (event, body) in events {
+                self.send(
+                    output,
+                    json!({ "type": "event", "event": event, "body": body }),
+                )?;
+            }
+            if command == "disconnect" || command == "terminate" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, output: &mut impl Write, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        write_message(output, &message)
+    }
+
+    fn handle(
+        &mut self,
+        command: &str,
+        arguments: &Value,
+        events: &mut Vec<(&'static str, Value)>,
+    ) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsFunctionBreakpoints": true,
+            })),
+            "launch" => {
+                self.launch(arguments, events)?;
+                // Breakpoints can be checked against the code from now on.
+                events.push(("initialized", Value::Null));
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let file = arguments["source"]["path"]
+                    .as_str()
+                    .ok_or("setBreakpoints needs a source path")?;
+                let lines: Vec<(usize, Option<String>)> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| {
+                        let line = breakpoint["line"].as_u64()? as usize;
+                        Some((line, condition(breakpoint)))
+                    })
+                    .collect();
+                let breakpoints: Vec<Value> = match &mut self.debugger {
+                    Some(debugger) => place_breakpoints(debugger, file, &lines),
+                    None => {
+                        let breakpoints = lines
+                            .iter()
+                            .map(|(line, _)| json!({ "verified": false, "line": line }))
+                            .collect();
+                        self.pending_breakpoints.insert(file.to_string(), lines);
+                        breakpoints
+                    }
+                };
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                let names: Vec<(String, Option<String>)> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| {
+                        let name = breakpoint["name"].as_str()?.to_string();
+                        Some((name, condition(breakpoint)))
+                    })
+                    .collect();
+                let breakpoints: Vec<Value> = match &mut self.debugger {
+                    Some(debugger) => place_function_breakpoints(debugger, &names),
+                    None => {
+                        let breakpoints = vec![json!({ "verified": false }); names.len()];
+                        self.pending_function_breakpoints = names;
+                        breakpoints
+                    }
+                };
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push((
+                        "stopped",
+                        json!({ "reason": "entry", "threadId": THREAD_ID }),
+                    ));
+                } else {
+                    self.resume(Resume::Continue, events);
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => {
+                let frames: Vec<Value> = self
+                    .debugger()?
+                    .machine()
+                    .backtrace()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, frame)| {
+                        let mut entry = json!({
+                            "id": id,
+                            "name": frame.function,
+                            "line": 0,
+                            "column": 0,
+                        });
+                        if let Some(source) = frame.source {
+                            entry["line"] = source.line.into();
+                            entry["column"] = source.col.into();
+                            entry["source"] = json!({ "path": source.file });
+                        }
+                        entry
+                    })
+                    .collect();
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => {
+                let frame = arguments["frameId"].as_u64().unwrap_or(0);
+                let scope = |name: &str, kind: u64| {
+                    json!({
+                        "name": name,
+                        "variablesReference": frame * SCOPE_COUNT + kind,
+                        "expensive": false,
+                    })
+                };
+                Ok(json!({ "scopes": [
+                    scope("Locals", LOCALS_SCOPE),
+                    scope("Globals", GLOBALS_SCOPE),
+                    scope("Registers", REGISTERS_SCOPE),
+                ] }))
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+                let machine = self.debugger()?.machine();
+                let variables = match reference
+                    .checked_sub(1)
+                    .map(|r| (r / SCOPE_COUNT, r % SCOPE_COUNT + 1))
+                {
+                    Some((frame, LOCALS_SCOPE)) => machine.frame_locals(frame as usize),
+                    Some((_, GLOBALS_SCOPE)) => machine.globals(),
+                    Some((_, REGISTERS_SCOPE)) => machine
+                        .registers()
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| (format!("R{}", index), value.clone()))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                Ok(json!({ "variables": variables.iter().map(variable).collect::<Vec<_>>() }))
+            }
+            "continue" => {
+                self.debugger()?;
+                self.resume(Resume::Continue, events);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.debugger()?;
+                let mode = match command {
+                    "next" => Resume::StepOver,
+                    "stepIn" => Resume::StepIn,
+                    _ => Resume::StepOut,
+                };
+                self.resume(mode, events);
+                Ok(Value::Null)
+            }
+            "pause" | "disconnect" | "terminate" => Ok(Value::Null),
+            _ => Err(format!("Unsupported command '{}'", command)),
+        }
+    }
+
+    /// Compiles the program the configuration names and sets it up to run.
+    fn launch(
+        &mut self,
+        arguments: &Value,
+        events: &mut Vec<(&'static str, Value)>,
+    ) -> Result<(), String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("launch needs the program to debug")?;
+        let program = &crate::compiler::modules::entry_point(program);
+        let source = read_to_string(program)
+            .map_err(|e| format!("Failed to read input file '{}': {}", program, e))?;
+        let code = match compile_named(&source, program, 0, &Vec::new(), &Default::default()) {
+            Ok((code, messages)) => {
+                for message in messages.iter() {
+                    let warning = render_message(message, program, &source) + "\n";
+                    events.push(("output", json!({ "category": "stderr", "output": warning })));
+                }
+                code
+            }
+            Err(e) => return Err(render_error(&e, program, &source)),
+        };
+        let mut debugger = Debugger::new(code);
+        let args = arguments["args"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|arg| arg.as_str().map(String::from))
+            .collect();
+        debugger.machine_mut().set_args(args);
+        let input = arguments["input"].as_str().unwrap_or_default();
+        debugger.machine_mut().set_platform(Box::new(InMemory::new(
+            input,
+            Box::new(self.output.clone()),
+        )));
+        for (file, lines) in self.pending_breakpoints.drain() {
+            place_breakpoints(&mut debugger, &file, &lines);
+        }
+        let names = std::mem::take(&mut self.pending_function_breakpoints);
+        place_function_breakpoints(&mut debugger, &names);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.debugger = Some(debugger);
+        Ok(())
+    }
+
+    fn debugger(&self) -> Result<&Debugger, String> {
+        self.debugger
+            .as_ref()
+            .ok_or_else(|| "No program is running".to_string())
+    }
+
+    /// Runs the program as far as `mode` says and reports where it stopped.
+    fn resume(&mut self, mode: Resume, events: &mut Vec<(&'static str, Value)>) {
+        let Some(debugger) = &mut self.debugger else {
+            return;
+        };
+        let stop = debugger.resume(mode);
+        let printed = self.output.contents();
+        if printed.len() > self.sent_output {
+            let output = &printed[self.sent_output..];
+            events.push(("output", json!({ "category": "stdout", "output": output })));
+            self.sent_output = printed.len();
+        }
+        if let Some(error) = debugger.take_condition_error() {
+            let report = format!("Error in a breakpoint condition: {}\n", error);
+            events.push(("output", json!({ "category": "stderr", "output": report })));
+        }
+        let reason = match stop {
+            Stop::Breakpoint => "breakpoint",
+            Stop::Step => "step",
+            Stop::Exited(exit_code) => {
+                if let Some(error) = debugger.machine().runtime_error() {
+                    let report = error.to_string();
+                    events.push(("output", json!({ "category": "stderr", "output": report })));
+                }
+                events.push(("exited", json!({ "exitCode": exit_code })));
+                events.push(("terminated", json!({})));
+                self.debugger = None;
+                return;
+            }
+        };
+        events.push((
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID }),
+        ));
+    }
+}
+
+fn variable((name, value): &(String, AbraValue)) -> Value {
+    json!({ "name": name, "value": stack_entry(value), "variablesReference": 0 })
+}
+
+/// The condition of a breakpoint the client sent, if it has a non-empty one.
+fn condition(breakpoint: &Value) -> Option<String> {
+    breakpoint["condition"]
+        .as_str()
+        .map(str::trim)
+        .filter(|condition| !condition.is_empty())
+        .map(String::from)
+}
+
+/// Replaces the breakpoints of `file` with `lines`, each with its condition, and
+/// describes them for the client. One whose condition doesn't compile is left out.
+fn place_breakpoints(
+    debugger: &mut Debugger,
+    file: &str,
+    lines: &[(usize, Option<String>)],
+) -> Vec<Value> {
+    let requested: Vec<usize> = lines.iter().map(|(line, _)| *line).collect();
+    let mut kept = Vec::new();
+    let breakpoints = debugger
+        .set_breakpoints(file, &requested)
+        .into_iter()
+        .zip(lines)
+        .map(|(placed, (_, condition))| {
+            let Some(line) = placed else {
+                return json!({ "verified": false, "message": "No code here" });
+            };
+            match debugger.set_line_condition(file, line, condition.as_deref()) {
+                Ok(()) => {
+                    kept.push(line);
+                    json!({ "verified": true, "line": line })
+                }
+                Err(message) => json!({ "verified": false, "line": line, "message": message }),
+            }
+        })
+        .collect();
+    if kept.len() < requested.len() {
+        debugger.set_breakpoints(file, &kept);
+    }
+    breakpoints
+}
+
+/// Replaces the function breakpoints with `names`, each with its condition, and
+/// describes them for the client as [`place_breakpoints`] does.
+fn place_function_breakpoints(
+    debugger: &mut Debugger,
+    names: &[(String, Option<String>)],
+) -> Vec<Value> {
+    let requested: Vec<String> = names.iter().map(|(name, _)| name.clone()).collect();
+    let mut kept = Vec::new();
+    let breakpoints = debugger
+        .set_function_breakpoints(&requested)
+        .into_iter()
+        .zip(names)
+        .map(|(placed, (name, condition))| {
+            let Some(location) = placed else {
+                let message = format!("No function '{}'", name);
+                return json!({ "verified": false, "message": message });
+            };
+            let source = json!({ "path": debugger.files()[location.file] });
+            match debugger.set_function_condition(name, condition.as_deref()) {
+                Ok(()) => {
+                    kept.push(name.clone());
+                    json!({ "verified": true, "line": location.line, "source": source })
+                }
+                Err(message) => json!({ "verified": false, "message": message }),
+            }
+        })
+        .collect();
+    if kept.len() < requested.len() {
+        debugger.set_function_breakpoints(&kept);
+    }
+    breakpoints
+}
@@ -0,0 +1,682 @@
+//! `abra debug`: runs a program under a debugger driven by typed commands, what
+//! `abra dap` is to editors for the terminal.
+//!
+//! The program starts stopped before its first statement. It shares stdin with the
+//! prompt, so a program reading input takes the next lines typed at it.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+use crate::runtime::{
+    debug::stack_entry,
+    debugger::{Debugger, Resume, Stop},
+};
+use anyhow::Result;
+
+const PROMPT: &str = "(abra) ";
+/// Lines `list` shows.
+const LIST_LENGTH: usize = 10;
+const HELP: &str = "\
+r, run, c, continue  run until a breakpoint, a watch changing or the end
+s, step              go to the next statement, into calls
+n, next              go to the next statement of this function or a caller
+finish               run until this function returns
+b, break [WHERE]     stop at FILE:LINE, a LINE of this file or a function;
+                     'break WHERE if COND' stops only where COND is true;
+                     without WHERE, list the breakpoints and watches
+condition N [COND]   stop at breakpoint N only where COND is true, or always
+w, watch NAME        stop when the variable NAME changes
+d, delete N          remove breakpoint or watch N
+p, print [NAME]      show a variable, or every local of this function
+bt, backtrace        show the calls in progress
+l, list [WHERE]      show the source around a line or function, or this statement
+history              show the commands entered; !N runs number N again, !! the last
+q, quit              stop debugging
+An empty line runs the last command again.
+";
+
+/// A breakpoint or watch, numbered by its place in `DebugRepl::points`.
+enum Point {
+    Breakpoint {
+        /// A line with code on it, or a function.
+        at: Target,
+        /// Expression that has to be true to stop.
+        condition: Option<String>,
+    },
+    Watch(Watch),
+}
+
+struct Watch {
+    name: String,
+    /// Calls in progress where it was set, counting from the outermost; `None` for
+    /// a global.
+    depth: Option<usize>,
+    /// What the variable showed last.
+    value: String,
+}
+
+/// Where a `break` or `list` points.
+enum Target {
+    /// By index into `Debugger::files`.
+    Line {
+        file: usize,
+        line: usize,
+    },
+    Function(String),
+}
+
+pub struct DebugRepl {
+    debugger: Debugger,
+    /// Breakpoints and watches; deleting one leaves its number unused.
+    points: Vec<Option<Point>>,
+    /// Lines of each file read so far, or `None` when it can't be read.
+    sources: HashMap<usize, Option<Vec<String>>>,
+    /// Commands entered, `!N` ones as the command they repeated.
+    history: Vec<String>,
+    /// Whether the program hasn't ended yet.
+    running: bool,
+}
+
+impl DebugRepl {
+    /// Debugs the program `debugger` has loaded, as set up with its arguments and
+    /// platform.
+    pub fn new(debugger: Debugger) -> Self {
+        DebugRepl {
+            debugger,
+            points: Vec::new(),
+            sources: HashMap::new(),
+            history: Vec::new(),
+            running: true,
+        }
+    }
+
+    /// Reads commands from `input` until it ends or says `quit`, answering on `output`.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<()> {
+        writeln!(
+            output,
+            "Stopped before the program starts. Type 'help' for the commands."
+        )?;
+        loop {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let line = match line.trim() {
+                "" => match self.history.last() {
+                    Some(last) => last.clone(),
+                    None => continue,
+                },
+                "!!" | "!-1" => match self.history.last() {
+                    Some(last) => {
+                        let repeated = last.clone();
+                        writeln!(output, "{}", repeated)?;
+                        self.history.push(repeated.clone());
+                        repeated
+                    }
+                    None => {
+                        writeln!(output, "No commands yet.")?;
+                        continue;
+                    }
+                },
+                line => match line.strip_prefix('!').map(str::parse::<usize>) {
+                    Some(Ok(number)) if (1..=self.history.len()).contains(&number) => {
+                        let repeated = self.history[number - 1].clone();
+                        writeln!(output, "{}", repeated)?;
+                        self.history.push(repeated.clone());
+                        repeated
+                    }
+                    Some(_) => {
+                        writeln!(output, "No command {} in the history.", &line[1..])?;
+                        continue;
+                    }
+                    None => {
+                        self.history.push(line.to_string());
+                        line.to_string()
+                    }
+                },
+            };
+            let (command, argument) = match line.split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.as_str(), ""),
+            };
+            if !self.command(command, argument, output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs one command; returns whether to read another.
+    fn command<W: Write>(&mut self, command: &str, argument: &str, output: &mut W) -> Result<bool> {
+        match command {
+            "r" | "run" | "c" | "continue" => self.resume(Resume::Continue, output)?,
+            "s" | "step" => self.resume(Resume::StepIn, output)?,
+            "n" | "next" => self.resume(Resume::StepOver, output)?,
+            "finish" => self.resume(Resume::StepOut, output)?,
+            "b" | "break" if argument.is_empty() => self.list_points(output)?,
+            "b" | "break" => {
+                let (spec, condition) = match argument.split_once(" if ") {
+                    Some((spec, condition)) => (spec.trim(), Some(condition.trim())),
+                    None => (argument, None),
+                };
+                match self.target(spec) {
+                    Ok(target) => self.add_breakpoint(target, condition, output)?,
+                    Err(message) => writeln!(output, "{}", message)?,
+                }
+            }
+            "condition" => {
+                let (number, condition) = match argument.split_once(char::is_whitespace) {
+                    Some((number, condition)) => (number, Some(condition.trim())),
+                    None => (argument, None),
+                };
+                match number.parse::<usize>() {
+                    Ok(number) => self.set_condition(number, condition, output)?,
+                    Err(_) => writeln!(output, "Usage: condition N [COND]")?,
+                }
+            }
+            "w" | "watch" if argument.is_empty() => writeln!(output, "Usage: watch NAME")?,
+            "w" | "watch" => self.add_watch(argument, output)?,
+            "d" | "delete" => match argument.parse::<usize>() {
+                Ok(number)
+                    if self
+                        .points
+                        .get(number.wrapping_sub(1))
+                        .is_some_and(Option::is_some) =>
+                {
+                    self.points[number - 1] = None;
+                    self.sync_breakpoints();
+                }
+                Ok(number) => writeln!(output, "No breakpoint or watch {}.", number)?,
+                Err(_) => writeln!(output, "Usage: delete N")?,
+            },
+            "p" | "print" if argument.is_empty() => {
+                let locals = self.debugger.machine().frame_locals(0);
+                if locals.is_empty() {
+                    writeln!(output, "No locals.")?;
+                }
+                for (name, value) in locals.iter() {
+                    writeln!(output, "{} = {}", name, stack_entry(value))?;
+                }
+            }
+            "p" | "print" => match self.visible(argument) {
+                Some((_, value)) => writeln!(output, "{} = {}", argument, value)?,
+                None => writeln!(output, "No variable '{}' here.", argument)?,
+            },
+            "bt" | "backtrace" => {
+                let frames = self.debugger.machine().backtrace();
+                if frames.is_empty() {
+                    writeln!(output, "No calls in progress.")?;
+                }
+                for (depth, frame) in frames.iter().enumerate() {
+                    match &frame.source {
+                        Some(source) => writeln!(
+                            output,
+                            "#{} {} at {}:{}",
+                            depth, frame.function, source.file, source.line
+                        )?,
+                        None => writeln!(output, "#{} {}", depth, frame.function)?,
+                    }
+                }
+            }
+            "l" | "list" => {
+                let target = if argument.is_empty() {
+                    self.here()
+                } else {
+                    self.target(argument).map(|target| self.line_of(&target))
+                };
+                match target {
+                    Ok((file, line)) => self.list(file, line, output)?,
+                    Err(message) => writeln!(output, "{}", message)?,
+                }
+            }
+            "history" => {
+                for (number, command) in self.history.iter().enumerate() {
+                    writeln!(output, "{:>4}  {}", number + 1, command)?;
+                }
+            }
+            "h" | "help" => write!(output, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(output, "Unknown command '{}', try 'help'.", command)?,
+        }
+        Ok(true)
+    }
+
+    /// Runs as far as `mode` says and shows where the program stopped.
+    fn resume<W: Write>(&mut self, mode: Resume, output: &mut W) -> Result<()> {
+        if !self.running {
+            writeln!(output, "The program is not running.")?;
+            return Ok(());
+        }
+        let (stop, changes) = self.advance(mode);
+        self.debugger.machine_mut().stdout().flush()?;
+        let number = match stop {
+            Stop::Exited(exit_code) => {
+                if let Some(error) = self.debugger.machine().runtime_error() {
+                    writeln!(output, "{}", error.to_string().trim_end())?;
+                }
+                writeln!(output, "Program exited with code {}.", exit_code)?;
+                self.running = false;
+                return Ok(());
+            }
+            Stop::Breakpoint => self.breakpoint_here(),
+            Stop::Step => None,
+        };
+        if let Some(error) = self.debugger.take_condition_error() {
+            let number = number
+                .map(|number| format!(" {}", number))
+                .unwrap_or_default();
+            writeln!(
+                output,
+                "Error in the condition of breakpoint{}: {}",
+                number, error
+            )?;
+        }
+        for change in changes.iter() {
+            writeln!(output, "{}", change)?;
+        }
+        let backtrace = self.debugger.machine().backtrace();
+        let function = backtrace.first().map_or("<start>", |frame| &frame.function);
+        let Some(here) = self.debugger.current_location() else {
+            return Ok(writeln!(output, "In {}.", function)?);
+        };
+        let file = &self.debugger.files()[here.file];
+        match (stop, number) {
+            (Stop::Breakpoint, Some(number)) => write!(output, "Breakpoint {}, ", number)?,
+            (Stop::Breakpoint, None) => write!(output, "Breakpoint, ")?,
+            _ => {}
+        }
+        writeln!(output, "{} at {}:{}", function, file, here.line)?;
+        if let Some(text) = self.source_line(here.file, here.line) {
+            writeln!(output, "{:>4}  {}", here.line, text)?;
+        }
+        Ok(())
+    }
+
+    /// Runs like `Debugger::resume`, but a statement at a time while anything is
+    /// watched, to stop as soon as a watched variable changes. Also gives what each
+    /// change was.
+    fn advance(&mut self, mode: Resume) -> (Stop, Vec<String>) {
+        let watching = self
+            .points
+            .iter()
+            .flatten()
+            .any(|point| matches!(point, Point::Watch(_)));
+        if !watching {
+            return (self.debugger.resume(mode), Vec::new());
+        }
+        let depth = self.debugger.machine().call_depth();
+        loop {
+            let stop = self.debugger.resume(Resume::StepIn);
+            if let Stop::Exited(_) = stop {
+                return (stop, Vec::new());
+            }
+            let changes = self.check_watches();
+            let now = self.debugger.machine().call_depth();
+            let arrived = match mode {
+                Resume::Continue => false,
+                Resume::StepIn => true,
+                Resume::StepOver => now <= depth,
+                Resume::StepOut => now < depth,
+            };
+            if arrived || stop == Stop::Breakpoint || !changes.is_empty() {
+                return (stop, changes);
+            }
+        }
+    }
+
+    /// Updates the watches with the values their variables show now, describing each
+    /// that changed. Watches whose function returned are deleted.
+    fn check_watches(&mut self) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (index, point) in self.points.iter_mut().enumerate() {
+            let Some(Point::Watch(watch)) = point else {
+                continue;
+            };
+            let Some(value) = watched_value(&self.debugger, watch) else {
+                changes.push(format!(
+                    "Watch {} deleted: '{}' went out of scope.",
+                    index + 1,
+                    watch.name
+                ));
+                *point = None;
+                continue;
+            };
+            if value != watch.value {
+                changes.push(format!(
+                    "Watch {}: {}\nOld value = {}\nNew value = {}",
+                    index + 1,
+                    watch.name,
+                    watch.value,
+                    value
+                ));
+                watch.value = value;
+            }
+        }
+        changes
+    }
+
+    /// Number of the breakpoint the program stopped at.
+    fn breakpoint_here(&self) -> Option<usize> {
+        let here = self.debugger.current_location()?;
+        let backtrace = self.debugger.machine().backtrace();
+        let function = backtrace.first().map(|frame| frame.function.as_str());
+        let position = self.points.iter().position(|point| match point {
+            Some(Point::Breakpoint {
+                at: Target::Line { file, line },
+                ..
+            }) => (*file, *line) == (here.file, here.line),
+            Some(Point::Breakpoint {
+                at: Target::Function(name),
+                ..
+            }) => {
+                function == Some(name.as_str())
+                    && self.debugger.function_location(name) == Some(here)
+            }
+            _ => false,
+        })?;
+        Some(position + 1)
+    }
+
+    fn list_points<W: Write>(&self, output: &mut W) -> Result<()> {
+        if self.points.iter().all(Option::is_none) {
+            writeln!(output, "No breakpoints or watches.")?;
+        }
+        for (index, point) in self.points.iter().enumerate() {
+            let Some(point) = point else {
+                continue;
+            };
+            match point {
+                Point::Breakpoint { at, condition } => {
+                    let at = match at {
+                        Target::Line { file, line } => {
+                            format!("{}:{}", self.debugger.files()[*file], line)
+                        }
+                        Target::Function(name) => name.clone(),
+                    };
+                    let condition = condition
+                        .as_ref()
+                        .map(|condition| format!(" if {}", condition))
+                        .unwrap_or_default();
+                    writeln!(output, "{:<4} breakpoint at {}{}", index + 1, at, condition)?
+                }
+                Point::Watch(watch) => writeln!(
+                    output,
+                    "{:<4} watch {} = {}",
+                    index + 1,
+                    watch.name,
+                    watch.value
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint<W: Write>(
+        &mut self,
+        at: Target,
+        condition: Option<&str>,
+        output: &mut W,
+    ) -> Result<()> {
+        let number = self.points.len() + 1;
+        let condition = condition.map(String::from);
+        self.points.push(Some(Point::Breakpoint { at, condition }));
+        let placed = self.sync_breakpoints();
+        let Some(Some(Point::Breakpoint { at, condition })) = self.points.last_mut() else {
+            unreachable!("the breakpoint was just added");
+        };
+        if let Target::Line { file, line } = at {
+            let Some(Some(placed)) = placed.last().copied() else {
+                let file = &self.debugger.files()[*file];
+                let message = format!("No code at or after {}:{}.", file, line);
+                self.points.pop();
+                self.sync_breakpoints();
+                return Ok(writeln!(output, "{}", message)?);
+            };
+            // Moved down to the line the code is on.
+            *line = placed;
+        }
+        let condition = condition.clone();
+        if let Err(error) = self.apply_condition(number, condition.as_deref()) {
+            self.points.pop();
+            self.sync_breakpoints();
+            return Ok(writeln!(output, "Can't use the condition: {}.", error)?);
+        }
+        let Some(Some(Point::Breakpoint { at, .. })) = self.points.last() else {
+            unreachable!("the breakpoint was just added");
+        };
+        let (file, line) = self.line_of(at);
+        let file = &self.debugger.files()[file];
+        match at {
+            Target::Line { .. } => writeln!(output, "Breakpoint {} at {}:{}", number, file, line)?,
+            Target::Function(name) => writeln!(
+                output,
+                "Breakpoint {} at {}, {}:{}",
+                number, name, file, line
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Changes the condition of breakpoint `number`.
+    fn set_condition<W: Write>(
+        &mut self,
+        number: usize,
+        condition: Option<&str>,
+        output: &mut W,
+    ) -> Result<()> {
+        let Some(Some(Point::Breakpoint { .. })) = self.points.get(number.wrapping_sub(1)) else {
+            return Ok(writeln!(output, "No breakpoint {}.", number)?);
+        };
+        match self.apply_condition(number, condition) {
+            Ok(()) => match condition {
+                Some(condition) => writeln!(
+                    output,
+                    "Breakpoint {} stops only where {} is true.",
+                    number, condition
+                )?,
+                None => writeln!(output, "Breakpoint {} stops every time.", number)?,
+            },
+            Err(error) => writeln!(output, "Can't use the condition: {}.", error)?,
+        }
+        Ok(())
+    }
+
+    /// Hands `condition` to the debugger for breakpoint `number`, and keeps it there
+    /// if it compiles.
+    fn apply_condition(&mut self, number: usize, condition: Option<&str>) -> Result<(), String> {
+        let Some(Some(Point::Breakpoint {
+            at,
+            condition: kept,
+        })) = self.points.get_mut(number - 1)
+        else {
+            return Err(format!("No breakpoint {}", number));
+        };
+        match at {
+            Target::Line { file, line } => {
+                let file = self.debugger.files()[*file].clone();
+                self.debugger.set_line_condition(&file, *line, condition)?
+            }
+            Target::Function(name) => self.debugger.set_function_condition(name, condition)?,
+        }
+        *kept = condition.map(String::from);
+        Ok(())
+    }
+
+    /// Hands the breakpoints to the debugger. Gives where those in the file of the
+    /// last line breakpoint ended up, in order.
+    fn sync_breakpoints(&mut self) -> Vec<Option<usize>> {
+        let mut lines: Vec<Vec<usize>> = vec![Vec::new(); self.debugger.files().len()];
+        let mut functions = Vec::new();
+        let mut last_file = None;
+        for point in self.points.iter().flatten() {
+            match point {
+                Point::Breakpoint {
+                    at: Target::Line { file, line },
+                    ..
+                } => {
+                    lines[*file].push(*line);
+                    last_file = Some(*file);
+                }
+                Point::Breakpoint {
+                    at: Target::Function(name),
+                    ..
+                } => functions.push(name.clone()),
+                Point::Watch(_) => {}
+            }
+        }
+        self.debugger.set_function_breakpoints(&functions);
+        let mut placed = Vec::new();
+        for (index, lines) in lines.iter().enumerate() {
+            let file = self.debugger.files()[index].clone();
+            let placed_here = self.debugger.set_breakpoints(&file, lines);
+            if last_file == Some(index) {
+                placed = placed_here;
+            }
+        }
+        placed
+    }
+
+    fn add_watch<W: Write>(&mut self, name: &str, output: &mut W) -> Result<()> {
+        let Some((depth, value)) = self.visible(name) else {
+            return Ok(writeln!(output, "No variable '{}' here.", name)?);
+        };
+        self.points.push(Some(Point::Watch(Watch {
+            name: name.to_string(),
+            depth,
+            value: value.clone(),
+        })));
+        writeln!(output, "Watch {}: {} = {}", self.points.len(), name, value)?;
+        Ok(())
+    }
+
+    /// The local of the innermost call named `name`, else the global, shown as a
+    /// value; with the depth a watch on it has.
+    fn visible(&self, name: &str) -> Option<(Option<usize>, String)> {
+        let machine = self.debugger.machine();
+        let local = machine
+            .frame_locals(0)
+            .into_iter()
+            .find(|(local, _)| local == name);
+        if let Some((_, value)) = local {
+            return Some((Some(machine.call_depth()), stack_entry(&value)));
+        }
+        let (_, value) = machine
+            .globals()
+            .into_iter()
+            .find(|(global, _)| global == name)?;
+        Some((None, stack_entry(&value)))
+    }
+
+    /// What `spec` points at: `FILE:LINE`, a `LINE` of the current file or a function.
+    fn target(&self, spec: &str) -> Result<Target, String> {
+        if let Some((file, line)) = spec.rsplit_once(':') {
+            if let Ok(line) = line.parse() {
+                let file = self
+                    .debugger
+                    .file_index(file)
+                    .ok_or_else(|| format!("No file '{}' in the program.", file))?;
+                return Ok(Target::Line { file, line });
+            }
+        }
+        if let Ok(line) = spec.parse() {
+            let (file, _) = self.here()?;
+            return Ok(Target::Line { file, line });
+        }
+        match self.debugger.function_location(spec) {
+            Some(_) => Ok(Target::Function(spec.to_string())),
+            None => Err(format!("No function '{}' in the program.", spec)),
+        }
+    }
+
+    /// The file and line of `target`.
+    fn line_of(&self, target: &Target) -> (usize, usize) {
+        match target {
+            Target::Line { file, line } => (*file, *line),
+            Target::Function(name) => self
+                .debugger
+                .function_location(name)
+                .map_or((0, 1), |location| (location.file, location.line)),
+        }
+    }
+
+    /// The statement the program is stopped at, or before it starts, where `main` does.
+    fn here(&self) -> Result<(usize, usize), String> {
+        self.debugger
+            .current_location()
+            .or_else(|| self.debugger.function_location("main"))
+            .map(|location| (location.file, location.line))
+            .ok_or_else(|| "The program has no line information.".to_string())
+    }
+
+    /// Shows the lines of `file` around `line`, marking the statement the program is
+    /// stopped at with `->` and breakpoints with `*`.
+    fn list<W: Write>(&mut self, file: usize, line: usize, output: &mut W) -> Result<()> {
+        let current = self
+            .debugger
+            .current_location()
+            .filter(|location| location.file == file)
+            .map(|location| location.line);
+        let breakpoints: Vec<usize> = self
+            .points
+            .iter()
+            .flatten()
+            .filter_map(|point| match point {
+                Point::Breakpoint {
+                    at: Target::Line { file: at, line },
+                    ..
+                } if *at == file => Some(*line),
+                _ => None,
+            })
+            .collect();
+        let name = self.debugger.files()[file].clone();
+        let Some(lines) = self.source(file) else {
+            return Ok(writeln!(output, "No source for '{}'.", name)?);
+        };
+        let start = line.saturating_sub(LIST_LENGTH / 2).max(1);
+        for (index, text) in lines.iter().enumerate().skip(start - 1).take(LIST_LENGTH) {
+            let number = index + 1;
+            let marker = if current == Some(number) {
+                "->"
+            } else if breakpoints.contains(&number) {
+                " *"
+            } else {
+                "  "
+            };
+            writeln!(output, "{} {:>4}  {}", marker, number, text)?;
+        }
+        Ok(())
+    }
+
+    fn source_line(&mut self, file: usize, line: usize) -> Option<String> {
+        self.source(file)?.get(line.checked_sub(1)?).cloned()
+    }
+
+    /// Lines of `file`, read the first time they are needed.
+    fn source(&mut self, file: usize) -> Option<&Vec<String>> {
+        let path = &self.debugger.files()[file];
+        self.sources
+            .entry(file)
+            .or_insert_with(|| {
+                let text = std::fs::read_to_string(path).ok()?;
+                Some(text.lines().map(String::from).collect())
+            })
+            .as_ref()
+    }
+}
+
+/// What the variable `watch` is on shows now, or `None` once the call it is in has
+/// returned.
+fn watched_value(debugger: &Debugger, watch: &Watch) -> Option<String> {
+    let machine = debugger.machine();
+    let variables = match watch.depth {
+        Some(depth) => machine.frame_locals(machine.call_depth().checked_sub(depth)?),
+        None => machine.globals(),
+    };
+    let (_, value) = variables
+        .into_iter()
+        .find(|(variable, _)| *variable == watch.name)?;
+    Some(stack_entry(&value))
+}
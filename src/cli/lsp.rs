@@ -0,0 +1,523 @@
+//! Language server behind `abra lsp`, speaking the Language Server Protocol over
+//! stdio.
+//!
+//! Every request analyzes the open document from scratch: it is loaded with its
+//! imports, then typechecked with the variables in scope recorded at each statement.
+//! Programs are small enough for that to be quick, and the only state kept between
+//! requests is the text of the open documents.
+
+use crate::compiler::{
+    lint::Lint,
+    modules::{load_program, Program},
+    typecheck::{Type, TypeChecker, TypeCheckerMessage},
+};
+use crate::errors::AbraError;
+use crate::frontend::{
+    ast::{Expression, Item},
+    span::{SourceError, Span},
+    tokenizer::{Token, TokenLiteral, Tokenizer},
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+/// `CompletionItemKind`s the server reports.
+const METHOD_KIND: u32 = 2;
+const FUNCTION_KIND: u32 = 3;
+const FIELD_KIND: u32 = 5;
+const VARIABLE_KIND: u32 = 6;
+const CLASS_KIND: u32 = 7;
+const INTERFACE_KIND: u32 = 8;
+const ENUM_KIND: u32 = 13;
+
+/// Serves the client on stdin and stdout until it exits. Returns whether it asked
+/// the server to shut down first, as the protocol's exit status depends on it.
+pub fn run_lsp() -> Result<bool> {
+    let stdin = std::io::stdin();
+    let mut server = Server::default();
+    server.serve(&mut stdin.lock(), &mut std::io::stdout())?;
+    Ok(server.shut_down)
+}
+
+#[derive(Default)]
+pub struct Server {
+    /// Text of each open document, by uri.
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    /// Answers the messages on `input` until `exit` or the end of the input.
+    pub fn serve(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+        while let Some(message) = read_message(input)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            if method == "exit" {
+                break;
+            }
+            let mut notifications = Vec::new();
+            let result = self.handle(method, &message["params"], &mut notifications);
+            for notification in notifications {
+                write_message(output, &notification)?;
+            }
+            // Notifications get no response.
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            write_message(output, &response)?;
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &Value,
+        notifications: &mut Vec<Value>,
+    ) -> Result<Value, (i64, String)> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, "The server has been shut down".to_string()));
+        }
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                },
+                "serverInfo": { "name": "abra", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "initialized" | "$/setTrace" | "$/cancelRequest" => Ok(Value::Null),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                notifications.push(self.publish_diagnostics(uri));
+                Ok(Value::Null)
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole text.
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                notifications.push(self.publish_diagnostics(uri));
+                Ok(Value::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                notifications.push(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                }));
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => Ok(self.at_position(params, hover).unwrap_or(Value::Null)),
+            "textDocument/definition" => {
+                Ok(self.at_position(params, definition).unwrap_or(Value::Null))
+            }
+            "textDocument/completion" => Ok(self
+                .at_position(params, completion)
+                .unwrap_or_else(|| json!([]))),
+            _ if method.starts_with("$/") => Ok(Value::Null),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method '{}'", method))),
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics(&uri_to_path(uri), source) },
+        })
+    }
+
+    /// Runs `query` on the open document and the byte offset of the request's position.
+    fn at_position(
+        &self,
+        params: &Value,
+        query: fn(&Cursor, &Program, &TypeChecker) -> Option<Value>,
+    ) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let source = self.documents.get(uri)?;
+        let cursor = Cursor {
+            path: uri_to_path(uri),
+            offset: offset(source, &params["position"]),
+            source,
+        };
+        let analyze = |source: &str| {
+            let program = load_program(&cursor.path, source).ok()?;
+            let mut checker = TypeChecker::new(&program.items).record_scopes();
+            checker.check();
+            query(&cursor, &program, &checker)
+        };
+        analyze(source).or_else(|| {
+            // Half-typed code often doesn't parse; try again without the cursor's line.
+            let line_start = source[..cursor.offset].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = source[cursor.offset..]
+                .find('\n')
+                .map_or(source.len(), |i| cursor.offset + i);
+            let mut blanked = source.to_string();
+            blanked.replace_range(line_start..line_end, &" ".repeat(line_end - line_start));
+            analyze(&blanked)
+        })
+    }
+}
+
+/// Where a request points: a byte offset into the text of the document at `path`.
+struct Cursor<'s> {
+    path: String,
+    offset: usize,
+    source: &'s str,
+}
+
+impl Cursor<'_> {
+    /// The identifier the cursor is in or just after, and the identifier before the
+    /// `.` in front of it, if any. The name is empty right after a `.`.
+    fn word(&self) -> Option<(String, Option<String>)> {
+        let tokens: Vec<(usize, Token, usize)> = Tokenizer::new(self.source)
+            .map_while(|token| token.ok())
+            .take_while(|(start, _, _)| *start <= self.offset)
+            .collect();
+        let identifier = |index: usize| match tokens.get(index) {
+            Some((_, Token::Literal(TokenLiteral::Identifier(name)), _)) => Some(name.clone()),
+            _ => None,
+        };
+        let receiver = |dot: usize| match dot.checked_sub(1) {
+            Some(before) if matches!(tokens.get(dot), Some((_, Token::Dot, _))) => {
+                identifier(before)
+            }
+            _ => None,
+        };
+        let last = tokens.len().checked_sub(1)?;
+        match &tokens[last] {
+            (_, Token::Dot, end) if *end <= self.offset => {
+                Some((String::new(), Some(identifier(last.checked_sub(1)?)?)))
+            }
+            (_, Token::Literal(TokenLiteral::Identifier(name)), end) if *end >= self.offset => {
+                Some((name.clone(), last.checked_sub(1).and_then(receiver)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Name of the class of `receiver`'s value where the cursor is.
+    fn class_of(&self, receiver: &str, checker: &TypeChecker) -> Option<String> {
+        let scope = checker.scope_at(&self.path, self.offset)?;
+        let variable = Expression::Literal(TokenLiteral::Identifier(receiver.to_string()));
+        let ty = checker.type_of(&variable, scope);
+        match ty.without_null().unwrap_or(ty) {
+            Type::Abra(name) | Type::Generic(name, _) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+fn hover(cursor: &Cursor, _program: &Program, checker: &TypeChecker) -> Option<Value> {
+    let (name, receiver) = cursor.word()?;
+    let (types, functions) = checker.export();
+    let text = match receiver {
+        Some(receiver) => {
+            let class = cursor.class_of(&receiver, checker)?;
+            let definition = types.get(&class)?;
+            if let Some((ty, _)) = definition.variables.get(&name) {
+                format!("{}.{}: {}", class, name, ty)
+            } else {
+                format!("func {}.{}", class, definition.functions.get(&name)?)
+            }
+        }
+        None => {
+            let scope = checker.scope_at(&cursor.path, cursor.offset);
+            if let Some((ty, _)) = scope.and_then(|scope| scope.get(&name)) {
+                format!("{}: {}", name, ty)
+            } else if let Some(signature) = functions.get(&name) {
+                format!("func {}", signature)
+            } else if types.contains_key(&name) {
+                format!("class {}", name)
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(json!({ "contents": { "kind": "markdown", "value": format!("```abra\n{}\n```", text) } }))
+}
+
+fn definition(cursor: &Cursor, program: &Program, checker: &TypeChecker) -> Option<Value> {
+    let (name, receiver) = cursor.word()?;
+    let key = match receiver {
+        Some(receiver) => format!("{}.{}", cursor.class_of(&receiver, checker)?, name),
+        None => name,
+    };
+    let span = symbols(&program.items).remove(&key)?;
+    let file = span.file.as_deref().unwrap_or(&cursor.path);
+    let (_, source) = program.sources.iter().find(|(path, _)| path == file)?;
+    Some(json!({ "uri": path_to_uri(file), "range": range(source, &span) }))
+}
+
+fn completion(cursor: &Cursor, program: &Program, checker: &TypeChecker) -> Option<Value> {
+    let (types, functions) = checker.export();
+    let item = |label: &String, kind: u32, detail: String| json!({ "label": label, "kind": kind, "detail": detail });
+    let mut items = Vec::new();
+    match cursor.word().and_then(|(_, receiver)| receiver) {
+        Some(receiver) => {
+            let definition = types.get(&cursor.class_of(&receiver, checker)?)?;
+            for (name, (ty, _)) in definition.variables.iter() {
+                items.push(item(name, FIELD_KIND, ty.to_string()));
+            }
+            for (name, signature) in definition.functions.iter() {
+                items.push(item(name, METHOD_KIND, signature.to_string()));
+            }
+        }
+        None => {
+            if let Some(scope) = checker.scope_at(&cursor.path, cursor.offset) {
+                for (name, (ty, _)) in scope.iter() {
+                    items.push(item(name, VARIABLE_KIND, ty.to_string()));
+                }
+            }
+            for (name, signature) in functions.iter() {
+                items.push(item(name, FUNCTION_KIND, signature.to_string()));
+            }
+            for declared in program.items.iter() {
+                match declared {
+                    Item::Class(class) => {
+                        items.push(item(&class.name, CLASS_KIND, "class".to_string()))
+                    }
+                    Item::Interface(interface) => items.push(item(
+                        &interface.name,
+                        INTERFACE_KIND,
+                        "interface".to_string(),
+                    )),
+                    Item::Enum(enumeration) => {
+                        items.push(item(&enumeration.name, ENUM_KIND, "enum".to_string()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    items.sort_by(|a, b| a["label"].as_str().cmp(&b["label"].as_str()));
+    Some(Value::Array(items))
+}
+
+/// Where each function, class, enum, interface and global is defined, with methods
+/// as `Class.method` and enum variants as `Enum::Variant`.
+fn symbols(items: &[Item]) -> HashMap<String, Span> {
+    let mut symbols = HashMap::new();
+    for item in items {
+        match item {
+            Item::Function(function) => {
+                symbols.insert(function.name.clone(), function.span.clone());
+            }
+            Item::Class(class) => {
+                for method in class.functions.iter() {
+                    let name = format!("{}.{}", class.name, method.name);
+                    symbols.insert(name, method.span.clone());
+                }
+                symbols.insert(class.name.clone(), class.span.clone());
+            }
+            Item::Enum(enumeration) => {
+                for (variant, _) in enumeration.variants.iter() {
+                    let name = format!("{}::{}", enumeration.name, variant);
+                    symbols.insert(name, enumeration.span.clone());
+                }
+                symbols.insert(enumeration.name.clone(), enumeration.span.clone());
+            }
+            Item::Interface(interface) => {
+                symbols.insert(interface.name.clone(), interface.span.clone());
+            }
+            Item::Global(global) => {
+                symbols.insert(global.name.clone(), global.span.clone());
+            }
+            Item::Import(_) => {}
+        }
+    }
+    symbols
+}
+
+/// Errors and warnings in the document at `path`; those in files it imports are
+/// reported when those files are open.
+fn diagnostics(path: &str, source: &str) -> Vec<Value> {
+    let diagnostic = |span: Option<&Span>, severity: u32, code: &str, message: &str| {
+        let whole = Span::default();
+        let span = span.unwrap_or(&whole);
+        if span.file.as_deref().is_some_and(|file| file != path) {
+            return None;
+        }
+        Some(json!({
+            "range": range(source, span),
+            "severity": severity,
+            "code": code,
+            "source": "abra",
+            "message": message,
+        }))
+    };
+    let program = match load_program(path, source) {
+        Ok(program) => program,
+        Err(e) => {
+            let found = match &e {
+                AbraError::Lex(e) => diagnostic(Some(e.span()), 1, e.code(), e.message()),
+                AbraError::Parse(e) => diagnostic(Some(e.span()), 1, e.code(), e.message()),
+                AbraError::Compile(e) => diagnostic(e.span(), 1, e.code(), &e.message()),
+                e => diagnostic(None, 1, e.code(), &e.to_string()),
+            };
+            return found.into_iter().collect();
+        }
+    };
+    let mut checker = TypeChecker::new(&program.items);
+    checker.check();
+    checker
+        .messages
+        .iter()
+        .filter_map(|message| match message {
+            TypeCheckerMessage::Error(e) => diagnostic(Some(e.span()), 1, e.code(), e.message()),
+            TypeCheckerMessage::Warning(e) | TypeCheckerMessage::Info(e) => {
+                let severity = if matches!(message, TypeCheckerMessage::Warning(_)) {
+                    2
+                } else {
+                    3
+                };
+                match e.downcast_ref::<SourceError>() {
+                    Some(located) => {
+                        let code = located.lint.map_or("", Lint::name);
+                        diagnostic(Some(&located.span), severity, code, &located.message)
+                    }
+                    None => diagnostic(None, severity, "", &e.to_string()),
+                }
+            }
+        })
+        .collect()
+}
+
+/// LSP range of `span` in `source`.
+fn range(source: &str, span: &Span) -> Value {
+    json!({
+        "start": position(source, span.byte_range.start),
+        "end": position(source, span.byte_range.end),
+    })
+}
+
+/// LSP position of byte `offset`: the 0-based line and the column in UTF-16 units.
+fn position(source: &str, offset: usize) -> Value {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = source[..line_start].matches('\n').count();
+    let character: usize = source[line_start..offset]
+        .chars()
+        .map(char::len_utf16)
+        .sum();
+    json!({ "line": line, "character": character })
+}
+
+/// Byte offset of an LSP position, clamped to its line.
+fn offset(source: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let mut character = position["character"].as_u64().unwrap_or(0) as usize;
+    let Some(line_start) = (0..line).try_fold(0, |start, _| {
+        source[start..].find('\n').map(|i| start + i + 1)
+    }) else {
+        return source.len();
+    };
+    let mut offset = line_start;
+    for c in source[line_start..].chars() {
+        if c == '\n' || character < c.len_utf16() {
+            break;
+        }
+        character -= c.len_utf16();
+        offset += c.len_utf8();
+    }
+    offset
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| std::str::from_utf8(tail.get(..2)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Reads a `Content-Length` framed message, `None` at the end of the input. The
+/// debug adapter protocol frames its messages the same way.
+pub(super) fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| anyhow!("Message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub(super) fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
@@ -1,9 +1,32 @@
 //! Command-line interface handling.
 
-use crate::compiler::Code; // Only Code is needed here from compiler
-use crate::runtime::vm::ByteCodeMachine; // Only ByteCodeMachine is needed here
+pub mod dap;
+pub mod debug_repl;
+pub mod lsp;
+pub mod repl;
+pub mod test_runner;
+pub mod trace_viewer;
+
+use crate::compiler::{
+    assembly::{assemble, to_assembly},
+    container,
+    link::link,
+    lint::{Lint, LintLevels},
+    typecheck::{SignatureProvider, TypeCheckerMessage},
+    verify::verify,
+    wasm_backend::compile_to_wasm,
+    Code,
+};
+use crate::errors::AbraError;
+use crate::frontend::format::format_source;
+use crate::frontend::span::{LineIndex, Span};
+use crate::runtime::debugger::Debugger;
+use crate::runtime::platform::SharedBuffer;
+use crate::runtime::session::Session;
+use crate::runtime::trace::{read_trace, Tracer};
+use crate::runtime::vm::{ByteCodeMachine, IntOverflow};
 use anyhow::Result;
-use clap::{arg, command, value_parser, Arg, Command}; // Removed ArgAction
+use clap::{arg, command, value_parser, Arg, ArgAction, Command};
 use std::{
     fs::{read_to_string, File},
     io::Write,
@@ -22,45 +45,374 @@ fn build_cli() -> Command {
                 .value_parser(value_parser!(u16))
                 .default_value("0"),
         )
+        .arg(
+            arg!(-q --quiet "only report errors")
+                .global(true)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            arg!(-v --verbose ... "report what the compiler and VM are doing, more with -vv")
+                .global(true),
+        )
         .subcommand(
             Command::new("run")
                 .short_flag('r')
                 .about("Compiles and runs file")
-                .arg(arg!([IN] "file to compile and run").value_parser(value_parser!(String))),
+                .arg(arg!([IN] "file to compile and run").value_parser(value_parser!(String)))
+                .arg(warnings_as_errors_arg())
+                .args(lint_args())
+                .arg(backend_arg())
+                .arg(release_arg())
+                .args(jit_args())
+                .arg(int_overflow_arg())
+                .args(session_args())
+                .args(profile_args())
+                .args(trace_args())
+                .arg(program_args()),
         )
         .subcommand(
             Command::new("compile")
                 .short_flag('c')
                 .about("Compiles file")
                 .arg(arg!([IN] "file to compile").value_parser(value_parser!(String)))
-                .arg(arg!([OUT] "output path").value_parser(value_parser!(String))),
+                .arg(arg!([OUT] "output path").value_parser(value_parser!(String)))
+                .arg(warnings_as_errors_arg())
+                .args(lint_args())
+                .arg(backend_arg())
+                .arg(release_arg())
+                .arg(
+                    arg!(--target <TARGET> "format of the output file")
+                        .value_parser(["bytecode", "wasm"])
+                        .default_value("bytecode"),
+                )
+                .arg(
+                    arg!(--emit <EMIT> "write bytecode as a binary file or as assembly text")
+                        .value_parser(["bytecode", "asm"])
+                        .default_value("bytecode"),
+                )
+                .arg(
+                    arg!(--lib "compile a unit without main, to link with a program")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--against <UNIT> "compiled unit whose functions this file calls")
+                        .value_parser(value_parser!(String))
+                        .action(ArgAction::Append),
+                ),
         )
         .subcommand(
             Command::new("execute")
                 .short_flag('x')
                 .about("Runs compiled file")
-                .arg(arg!([FILE] "file to run").value_parser(value_parser!(String))),
+                .arg(arg!([FILE] "file to run").value_parser(value_parser!(String)))
+                .arg(int_overflow_arg())
+                .args(session_args())
+                .args(profile_args())
+                .args(trace_args())
+                .arg(program_args()),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Typechecks file without compiling or running it")
+                .arg(arg!([IN] "file to check").value_parser(value_parser!(String)))
+                .arg(warnings_as_errors_arg())
+                .args(lint_args()),
+        )
+        .subcommand(
+            Command::new("test")
+                .about("Runs the test_* functions of a file or of every file in a directory")
+                .arg(
+                    arg!([PATH] "file or directory to test")
+                        .default_value(".")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("fmt")
+                .about("Rewrites source files in the canonical layout")
+                .arg(
+                    arg!([FILE] ... "files to format")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(arg!(--check "only report the files that aren't formatted, and fail if any")),
+        )
+        .subcommand(Command::new("repl").about("Starts an interactive session"))
+        .subcommand(
+            Command::new("debug")
+                .about("Runs a program under an interactive debugger")
+                .arg(
+                    arg!([IN] "file to debug")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(warnings_as_errors_arg())
+                .args(lint_args())
+                .arg(program_args()),
+        )
+        .subcommand(Command::new("dap").about("Starts a debug adapter on stdin and stdout"))
+        .subcommand(Command::new("lsp").about("Starts a language server on stdin and stdout"))
+        .subcommand(
+            Command::new("asm")
+                .about("Assembles a bytecode assembly listing into a compiled file")
+                .arg(arg!([IN] "assembly to read").value_parser(value_parser!(String)))
+                .arg(
+                    arg!(-o --output <OUT> "compiled file to write, IN with .abc by default")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Links compiled files into one program")
+                .arg(
+                    arg!([FILE] ... "compiled files to link")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-o --output <OUT> "linked file to write")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("trace")
+                .about("Steps through a trace written with --trace")
+                .arg(
+                    arg!([LOG] "trace to step through")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Prints the header of a compiled file")
+                .arg(arg!([FILE] "compiled file to inspect").value_parser(value_parser!(String))),
+        )
+        .subcommand(
+            Command::new("disasm")
+                .about("Prints the bytecode of a compiled file")
+                .arg(arg!([FILE] "compiled file to list").value_parser(value_parser!(String)))
+                .arg(arg!(--json "print the listing as JSON")),
         )
 }
 
+/// `--warnings-as-errors` flag of every subcommand that compiles source.
+fn warnings_as_errors_arg() -> Arg {
+    arg!(--"warnings-as-errors" "fail compilation on typechecker warnings")
+}
+
+/// `--deny`/`--allow` flags of every subcommand that compiles source, each naming a
+/// lint and given as often as needed.
+fn lint_args() -> [Arg; 2] {
+    let lints = Lint::ALL.map(Lint::name);
+    [
+        arg!(--deny <LINT> "fail compilation on the warnings of a lint")
+            .value_parser(lints)
+            .action(ArgAction::Append),
+        arg!(--allow <LINT> "leave out the warnings of a lint, even with --deny or --warnings-as-errors")
+            .value_parser(lints)
+            .action(ArgAction::Append),
+    ]
+}
+
+fn lint_levels_from_matches(matches: &clap::ArgMatches) -> LintLevels {
+    let lints = |flag: &str| -> Vec<Lint> {
+        matches
+            .get_many::<String>(flag)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.parse().ok())
+            .collect()
+    };
+    let mut levels =
+        LintLevels::default().with_warnings_as_errors(matches.get_flag("warnings-as-errors"));
+    for lint in lints("deny") {
+        levels = levels.deny(lint);
+    }
+    for lint in lints("allow") {
+        levels = levels.allow(lint);
+    }
+    levels
+}
+
+/// `--release` flag of every subcommand that compiles code to run.
+fn release_arg() -> Arg {
+    arg!(--release "leave out line information and local names")
+}
+
+/// `--backend` flag of every subcommand that compiles code to run.
+fn backend_arg() -> Arg {
+    arg!(--backend <BACKEND> "instruction set to compile to")
+        .value_parser(["stack", "register"])
+        .default_value("stack")
+}
+
+/// Rewrites `code` for the instruction set picked with `--backend` and strips its
+/// debug info for `--release`.
+fn apply_build_flags(code: &mut Code, matches: &clap::ArgMatches) {
+    if matches.get_one::<String>("backend").map(String::as_str) == Some("register") {
+        crate::optimizer::lower_to_registers(code);
+    }
+    if matches.get_flag("release") {
+        code.strip_debug_info();
+    }
+}
+
+/// `--int-overflow` flag of every subcommand that executes code.
+fn int_overflow_arg() -> Arg {
+    arg!(--"int-overflow" <MODE> "what int arithmetic does when the result doesn't fit")
+        .value_parser(["error", "wrap", "saturate"])
+        .default_value("error")
+}
+
+fn int_overflow_from_matches(matches: &clap::ArgMatches) -> IntOverflow {
+    match matches
+        .get_one::<String>("int-overflow")
+        .map(String::as_str)
+    {
+        Some("wrap") => IntOverflow::Wrap,
+        Some("saturate") => IntOverflow::Saturate,
+        _ => IntOverflow::Error,
+    }
+}
+
+/// `--jit` flag of `run`, in builds with the `jit` feature.
+fn jit_args() -> Vec<Arg> {
+    if cfg!(feature = "jit") {
+        vec![arg!(--jit "compile hot integer functions to native code")]
+    } else {
+        Vec::new()
+    }
+}
+
+/// `--record`/`--replay` flags shared by every subcommand that executes code.
+fn session_args() -> [Arg; 2] {
+    [
+        arg!(--record <LOG> "record stdin, random and clock inputs to a session log")
+            .value_parser(value_parser!(String))
+            .conflicts_with("replay"),
+        arg!(--replay <LOG> "feed inputs from a previously recorded session log")
+            .value_parser(value_parser!(String)),
+    ]
+}
+
+/// `--profile`/`--profile-out` flags shared by every subcommand that executes code.
+fn profile_args() -> [Arg; 2] {
+    [
+        arg!(--profile [FORMAT] "time instructions and calls, reported as text, JSON or folded stacks")
+            .value_parser(["report", "json", "folded"])
+            .require_equals(true)
+            .default_missing_value("report"),
+        arg!(--"profile-out" <FILE> "write the profile to a file rather than stderr")
+            .value_parser(value_parser!(String))
+            .requires("profile"),
+    ]
+}
+
+/// Writes the profile `--profile` asked for to `--profile-out`, or else to stderr.
+fn write_profile(machine: &ByteCodeMachine, matches: &clap::ArgMatches) -> Result<()> {
+    let (Some(format), Some(profile)) = (matches.get_one::<String>("profile"), machine.profile())
+    else {
+        return Ok(());
+    };
+    let text = match format.as_str() {
+        "json" => serde_json::to_string_pretty(&profile.to_json())? + "\n",
+        "folded" => profile.folded(),
+        _ => profile.to_string(),
+    };
+    match matches.get_one::<String>("profile-out") {
+        Some(out_file) => std::fs::write(out_file, text)
+            .map_err(|e| anyhow::anyhow!("Failed to write profile to '{}': {}", out_file, e))?,
+        None => eprint!("{}", text),
+    }
+    Ok(())
+}
+
+/// `--trace`/`--trace-last` flags shared by every subcommand that executes code.
+fn trace_args() -> [Arg; 2] {
+    [
+        arg!(--trace <LOG> "log every instruction run and the top of the stack after it")
+            .value_parser(value_parser!(String)),
+        arg!(--"trace-last" <N> "only keep the last N instructions in the trace")
+            .value_parser(value_parser!(usize))
+            .requires("trace"),
+    ]
+}
+
+/// The tracer `--trace` asks for, if any.
+fn tracer_from_matches(matches: &clap::ArgMatches) -> Result<Option<Tracer>> {
+    let Some(path) = matches.get_one::<String>("trace") else {
+        return Ok(None);
+    };
+    match matches.get_one::<usize>("trace-last") {
+        Some(capacity) => Ok(Some(Tracer::ring(path, *capacity))),
+        None => Tracer::stream(path).map(Some),
+    }
+}
+
+/// Arguments after `--`, passed on to the program.
+fn program_args() -> Arg {
+    arg!([ARGS] ... "arguments for the program, after --")
+        .value_parser(value_parser!(String))
+        .last(true)
+}
+
+fn program_args_from_matches(matches: &clap::ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("ARGS")
+        .map(|args| args.cloned().collect())
+        .unwrap_or_default()
+}
+
+fn session_from_matches(matches: &clap::ArgMatches) -> Result<Session> {
+    if let Some(path) = matches.get_one::<String>("record") {
+        return Ok(Session::record(path));
+    }
+    if let Some(path) = matches.get_one::<String>("replay") {
+        return Session::replay(path);
+    }
+    Ok(Session::live())
+}
+
 // --- Public Execution Function ---
 
 /// Runs the main application logic based on parsed arguments.
 pub fn run_app() -> Result<()> {
     let matches = build_cli().get_matches();
     let debug: u16 = *matches.get_one::<u16>("debug").unwrap_or(&0); // Get debug level safely
-    println!("Debug level: {}", debug);
+    init_logging(&matches);
+    log::debug!("Debug level: {}", debug);
 
     match matches.subcommand() {
         Some(("run", submatches)) => {
             let infile_path = submatches
                 .get_one::<String>("IN")
                 .ok_or_else(|| anyhow::anyhow!("Missing input file for 'go' command"))?;
-            println!("Compiling '{}'...", infile_path);
-            let compiled_code = compile(infile_path, debug)?;
-            println!("Running...");
-            let exit_code = run(&compiled_code, debug)?;
-            println!("Program exited with code: {}", exit_code);
+            let session = session_from_matches(submatches)?;
+            log::info!("Compiling '{}'...", infile_path);
+            let lints = lint_levels_from_matches(submatches);
+            let mut compiled_code = compile_file(infile_path, debug, &lints)?;
+            apply_build_flags(&mut compiled_code, submatches);
+            log::info!("Running...");
+            let args = program_args_from_matches(submatches);
+            let mut machine = ByteCodeMachine::new(compiled_code);
+            machine.set_int_overflow(int_overflow_from_matches(submatches));
+            #[cfg(feature = "jit")]
+            if submatches.get_flag("jit") {
+                machine.enable_jit(crate::runtime::jit::DEFAULT_HOT_CALLS)?;
+            }
+            if submatches.contains_id("profile") {
+                machine.enable_profiling();
+            }
+            if let Some(tracer) = tracer_from_matches(submatches)? {
+                machine.set_tracer(tracer);
+            }
+            let outcome = run_machine(&mut machine, session, args);
+            write_profile(&machine, submatches)?;
+            let exit_code = outcome?;
+            log::info!("Program exited with code: {}", exit_code);
+            exit_with(exit_code);
         }
         Some(("compile", submatches)) => {
             let out_file = submatches
@@ -70,101 +422,546 @@ pub fn run_app() -> Result<()> {
                 .get_one::<String>("IN")
                 .ok_or_else(|| anyhow::anyhow!("Missing input file for 'compile' command"))?;
 
-            println!("Compiling '{}' to '{}'...", in_file, out_file);
-            let compiled_code = compile(in_file, debug)?;
+            log::info!("Compiling '{}' to '{}'...", in_file, out_file);
+            let lints = lint_levels_from_matches(submatches);
+            let mut against = Vec::new();
+            for unit in submatches
+                .get_many::<String>("against")
+                .into_iter()
+                .flatten()
+            {
+                against.extend(load_code(unit)?.signatures());
+            }
+            let library = submatches.get_flag("lib");
+            let mut compiled_code = compile_unit(in_file, debug, &against, &lints, library)?;
+            apply_build_flags(&mut compiled_code, submatches);
 
+            let target = submatches.get_one::<String>("target").map(String::as_str);
+            let emit = submatches.get_one::<String>("emit").map(String::as_str);
+            let serialized = match (target, emit) {
+                (Some("wasm"), Some("asm")) => {
+                    anyhow::bail!("'--emit asm' only applies to '--target bytecode'")
+                }
+                (Some("wasm"), _) => compile_to_wasm(&compiled_code)?,
+                (_, Some("asm")) => to_assembly(&compiled_code).into_bytes(),
+                _ => container::encode(&compiled_code),
+            };
             let mut file = File::create(out_file).map_err(|e| {
                 anyhow::anyhow!("Failed to create output file '{}': {}", out_file, e)
             })?;
-            let serialized = bincode::serialize(&compiled_code)
-                .map_err(|e| anyhow::anyhow!("Failed to serialize bytecode: {}", e))?;
             file.write_all(&serialized).map_err(|e| {
                 anyhow::anyhow!("Failed to write bytecode to file '{}': {}", out_file, e)
             })?;
-            println!("Compilation successful.");
+            log::info!("Compilation successful.");
         }
         Some(("execute", submatches)) => {
             let in_file = submatches
                 .get_one::<String>("FILE")
                 .ok_or_else(|| anyhow::anyhow!("Missing input file for 'run' command"))?;
 
-            println!("Loading bytecode from '{}'...", in_file);
-            let file = File::open(in_file).map_err(|e| {
-                anyhow::anyhow!("Failed to open bytecode file '{}': {}", in_file, e)
-            })?;
-            let compiled_code: Code = bincode::deserialize_from(file).map_err(|e| {
-                anyhow::anyhow!("Failed to deserialize bytecode from '{}': {}", in_file, e)
+            let session = session_from_matches(submatches)?;
+            log::info!("Loading bytecode from '{}'...", in_file);
+            let compiled_code = load_code(in_file)?;
+            verify(&compiled_code).map_err(|e| {
+                let at = e
+                    .index()
+                    .and_then(|index| compiled_code.bytecode.get(index));
+                let at = at.map(|byte| format!(": {:?}", byte)).unwrap_or_default();
+                anyhow::anyhow!("Can't run '{}': {}{}", in_file, e, at)
             })?;
 
-            println!("Running...");
-            let exit_code = run(&compiled_code, debug)?;
-            println!("Program exited with code: {}", exit_code);
+            log::info!("Running...");
+            let args = program_args_from_matches(submatches);
+            let mut machine = ByteCodeMachine::new(compiled_code);
+            machine.set_int_overflow(int_overflow_from_matches(submatches));
+            if submatches.contains_id("profile") {
+                machine.enable_profiling();
+            }
+            if let Some(tracer) = tracer_from_matches(submatches)? {
+                machine.set_tracer(tracer);
+            }
+            let outcome = run_machine(&mut machine, session, args);
+            write_profile(&machine, submatches)?;
+            let exit_code = outcome?;
+            log::info!("Program exited with code: {}", exit_code);
+            exit_with(exit_code);
+        }
+        Some(("check", submatches)) => {
+            let in_file = submatches
+                .get_one::<String>("IN")
+                .ok_or_else(|| anyhow::anyhow!("Missing input file for 'check' command"))?;
+            check(in_file, &lint_levels_from_matches(submatches))?;
+            log::info!("No errors found in '{}'.", in_file);
+        }
+        Some(("repl", _)) => repl::run_repl()?,
+        Some(("debug", submatches)) => {
+            let infile_path = submatches
+                .get_one::<String>("IN")
+                .ok_or_else(|| anyhow::anyhow!("Missing input file for 'debug' command"))?;
+            let lints = lint_levels_from_matches(submatches);
+            let mut debugger = Debugger::new(compile_file(infile_path, debug, &lints)?);
+            debugger
+                .machine_mut()
+                .set_args(program_args_from_matches(submatches));
+            let stdin = std::io::stdin();
+            debug_repl::DebugRepl::new(debugger).run(&mut stdin.lock(), &mut std::io::stdout())?;
+        }
+        Some(("dap", _)) => dap::run_dap()?,
+        Some(("lsp", _)) => {
+            let shut_down = lsp::run_lsp()?;
+            exit_with(if shut_down { 0 } else { 1 });
+        }
+        Some(("test", submatches)) => {
+            let path = submatches
+                .get_one::<String>("PATH")
+                .ok_or_else(|| anyhow::anyhow!("Missing path for 'test' command"))?;
+            let summary = test_runner::run_tests(path, &mut std::io::stdout())?;
+            if summary.failed > 0 {
+                exit_with(1);
+            }
+        }
+        Some(("fmt", submatches)) => {
+            let check = submatches.get_flag("check");
+            let mut unformatted = false;
+            for file in submatches.get_many::<String>("FILE").into_iter().flatten() {
+                unformatted |= !format_file(file, check)?;
+            }
+            if check && unformatted {
+                exit_with(1);
+            }
+        }
+        Some(("asm", submatches)) => {
+            let in_file = submatches
+                .get_one::<String>("IN")
+                .ok_or_else(|| anyhow::anyhow!("Missing input file for 'asm' command"))?;
+            let out_file = match submatches.get_one::<String>("output") {
+                Some(out_file) => out_file.clone(),
+                None => std::path::Path::new(in_file)
+                    .with_extension("abc")
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let text = read_to_string(in_file)
+                .map_err(|e| anyhow::anyhow!("Failed to read input file '{}': {}", in_file, e))?;
+            let code = assemble(&text).map_err(|e| anyhow::anyhow!("{}: {:#}", in_file, e))?;
+            std::fs::write(&out_file, container::encode(&code)).map_err(|e| {
+                anyhow::anyhow!("Failed to write bytecode to file '{}': {}", out_file, e)
+            })?;
+            log::info!("Assembled '{}' to '{}'.", in_file, out_file);
+        }
+        Some(("link", submatches)) => {
+            let out_file = submatches
+                .get_one::<String>("output")
+                .ok_or_else(|| anyhow::anyhow!("Missing output file for 'link' command"))?;
+            let units = submatches
+                .get_many::<String>("FILE")
+                .into_iter()
+                .flatten()
+                .map(|in_file| load_code(in_file))
+                .collect::<Result<Vec<_>>>()?;
+            let code = link(units).map_err(|e| anyhow::anyhow!("Can't link: {}", e))?;
+            std::fs::write(out_file, container::encode(&code)).map_err(|e| {
+                anyhow::anyhow!("Failed to write bytecode to file '{}': {}", out_file, e)
+            })?;
+            log::info!("Linked '{}'.", out_file);
+        }
+        Some(("trace", submatches)) => {
+            let log = submatches
+                .get_one::<String>("LOG")
+                .ok_or_else(|| anyhow::anyhow!("Missing trace for 'trace' command"))?;
+            let entries = read_trace(log)?;
+            let stdin = std::io::stdin();
+            trace_viewer::run_trace_viewer(&entries, &mut stdin.lock(), &mut std::io::stdout())?;
+        }
+        Some(("inspect", submatches)) => {
+            let in_file = submatches
+                .get_one::<String>("FILE")
+                .ok_or_else(|| anyhow::anyhow!("Missing input file for 'inspect' command"))?;
+            let header = container::read_header(&read_bytecode_file(in_file)?)
+                .map_err(|e| anyhow::anyhow!("Can't inspect '{}': {}", in_file, e))?;
+            print!("{}", header);
+        }
+        Some(("disasm", submatches)) => {
+            let in_file = submatches
+                .get_one::<String>("FILE")
+                .ok_or_else(|| anyhow::anyhow!("Missing input file for 'disasm' command"))?;
+            let code = load_code(in_file)?;
+            if submatches.get_flag("json") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&code.json_representation())?
+                );
+            } else {
+                print!("{}", code.string_representation());
+            }
         }
         _ => unreachable!("Subcommand is required"),
     }
     Ok(())
 }
 
+/// Sends the messages of the compiler and the VM to stderr, so stdout only carries
+/// what the program prints. Errors and warnings show by default; `-q` leaves only
+/// errors, and each `-v` adds a level. `RUST_LOG` overrides the level per module.
+fn init_logging(matches: &clap::ArgMatches) {
+    let level = if matches.get_flag("quiet") {
+        log::LevelFilter::Error
+    } else {
+        match matches.get_count("verbose") {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Ends the process with the program's exit code, unless it is 0.
+fn exit_with(exit_code: usize) {
+    if exit_code != 0 {
+        std::process::exit(exit_code as i32);
+    }
+}
+
+/// Reads a program written by the `compile` subcommand.
+fn load_code(in_file: &str) -> Result<Code> {
+    let bytes = read_bytecode_file(in_file)?;
+    container::decode(&bytes).map_err(|e| anyhow::anyhow!("Can't load '{}': {}", in_file, e))
+}
+
+fn read_bytecode_file(in_file: &str) -> Result<Vec<u8>> {
+    std::fs::read(in_file)
+        .map_err(|e| anyhow::anyhow!("Failed to open bytecode file '{}': {}", in_file, e))
+}
+
 // --- Compile and Run Helpers (Moved from original cli.rs/main.rs) ---
 
-/// Compiles the source file, potentially optimizes, and returns the Code.
+/// Compiles the source file, or the `main.abra` of a project directory, together with
+/// everything it imports, potentially optimizes, and returns the Code.
 pub fn compile(infile_path: &str, debug: u16) -> Result<Code> {
-    // Use paths relative to the new module structure
-    use crate::compiler::Compiler;
-    use crate::frontend::{parser::Parser, tokenizer::Tokenizer};
+    compile_file(infile_path, debug, &LintLevels::default())
+}
+
+/// Compiles like [`compile`], leaving out or failing on typechecker warnings as
+/// `lints` says.
+pub fn compile_file(infile_path: &str, debug: u16, lints: &LintLevels) -> Result<Code> {
+    compile_unit(infile_path, debug, &Vec::new(), lints, false)
+}
+
+/// Compiles like [`compile_file`], against the functions `against` supplies. A
+/// `library` has no `main` to call; it is linked with a program that has.
+pub fn compile_unit(
+    infile_path: &str,
+    debug: u16,
+    against: &dyn SignatureProvider,
+    lints: &LintLevels,
+    library: bool,
+) -> Result<Code> {
+    let infile_path = &crate::compiler::modules::entry_point(infile_path);
+    let source_code = read_to_string(infile_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read input file '{}': {}", infile_path, e))?;
+    let (code, messages) =
+        compile_rendered(&source_code, infile_path, debug, against, lints, library)?;
+    // `-q` leaves out warnings and notes.
+    if log::log_enabled!(log::Level::Warn) {
+        for message in messages.iter() {
+            eprintln!("{}", render_message(message, infile_path, &source_code));
+        }
+    }
+    Ok(code)
+}
 
+/// Parses and typechecks the source file, or the `main.abra` of a project directory,
+/// together with everything it imports, and prints what the typechecker reports.
+/// Fails if there are errors, or warnings that `lints` denies.
+pub fn check(infile_path: &str, lints: &LintLevels) -> Result<()> {
+    let infile_path = &crate::compiler::modules::entry_point(infile_path);
     let source_code = read_to_string(infile_path)
         .map_err(|e| anyhow::anyhow!("Failed to read input file '{}': {}", infile_path, e))?;
+    match check_named(&source_code, infile_path, lints) {
+        Ok(messages) => {
+            // `-q` leaves out warnings and notes.
+            if log::log_enabled!(log::Level::Warn) {
+                for message in messages.iter() {
+                    eprintln!("{}", render_message(message, infile_path, &source_code));
+                }
+            }
+            Ok(())
+        }
+        Err(e) => Err(rendered(e, infile_path, &source_code)),
+    }
+}
+
+/// Rewrites a source file in the canonical layout, or with `check` only reports that
+/// it isn't in it. Returns whether it already was.
+pub fn format_file(path: &str, check: bool) -> Result<bool> {
+    let source = read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read input file '{}': {}", path, e))?;
+    let formatted = format_source(&source).map_err(|e| rendered(e, path, &source))?;
+    if formatted == source {
+        return Ok(true);
+    }
+    if check {
+        println!("{} is not formatted", path);
+    } else {
+        std::fs::write(path, formatted)
+            .map_err(|e| anyhow::anyhow!("Failed to write file '{}': {}", path, e))?;
+        log::info!("Formatted '{}'.", path);
+    }
+    Ok(false)
+}
+
+/// Compiles in-memory source text, for callers that don't have a file on disk.
+pub fn compile_source(source_code: &str, debug: u16) -> Result<Code> {
+    compile_rendered(
+        source_code,
+        SOURCE_FILE,
+        debug,
+        &Vec::new(),
+        &LintLevels::default(),
+        false,
+    )
+    .map(|(code, _)| code)
+}
+
+/// Compiles in-memory source text that may call the host functions `natives` supplies.
+pub fn compile_source_with(
+    source_code: &str,
+    debug: u16,
+    natives: &dyn SignatureProvider,
+) -> Result<Code> {
+    compile_rendered(
+        source_code,
+        SOURCE_FILE,
+        debug,
+        natives,
+        &LintLevels::default(),
+        false,
+    )
+    .map(|(code, _)| code)
+}
+
+/// File name diagnostics report for in-memory source text.
+pub(crate) const SOURCE_FILE: &str = "<source>";
+
+/// Compiles like [`compile_named`], with the error rendered into it as context.
+fn compile_rendered(
+    source_code: &str,
+    infile_path: &str,
+    debug: u16,
+    natives: &dyn SignatureProvider,
+    lints: &LintLevels,
+    library: bool,
+) -> Result<(Code, Vec<TypeCheckerMessage>)> {
+    compile_program(source_code, infile_path, debug, natives, lints, library)
+        .map_err(|e| rendered(e, infile_path, source_code))
+}
+
+/// The text of an error [`render_error`] has rendered, which `abra` prints as it is
+/// rather than after `Error:`. The error it was rendered from stays underneath.
+#[derive(Debug)]
+pub struct Rendered(pub String);
+
+impl std::fmt::Display for Rendered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Wraps `error` with its [`Rendered`] text.
+fn rendered(error: AbraError, file: &str, source: &str) -> anyhow::Error {
+    let text = render_error(&error, file, source);
+    anyhow::Error::new(error).context(Rendered(text))
+}
+
+/// Source text of `file`: `source` if that is the file, otherwise read from disk.
+fn source_of(in_file: &str, file: &str, source: &str) -> String {
+    if in_file == file {
+        source.to_string()
+    } else {
+        read_to_string(in_file).unwrap_or_default()
+    }
+}
+
+/// Renders a typechecker warning or note like [`render_error`] renders errors.
+pub fn render_message(message: &TypeCheckerMessage, file: &str, source: &str) -> String {
+    let in_file = message.file().unwrap_or(file);
+    let text = source_of(in_file, file, source);
+    message.render(in_file, &LineIndex::new(&text))
+}
+
+/// Renders `error` for the terminal. Errors found in the source show the line they
+/// point at in `source`, the contents of `file`, or in the imported file they name.
+pub fn render_error(error: &AbraError, file: &str, source: &str) -> String {
+    let located = |code: &str, span: &Span, message: &str| {
+        let severity = format!("error[{}]", code);
+        let in_file = span.file.as_deref().unwrap_or(file);
+        let text = source_of(in_file, file, source);
+        LineIndex::new(&text).render(&severity, message, in_file, span)
+    };
+    match error {
+        AbraError::Lex(e) => located(e.code(), e.span(), e.message()),
+        AbraError::Parse(e) => located(e.code(), e.span(), e.message()),
+        AbraError::Type(errors) => {
+            let rendered: Vec<String> = errors
+                .iter()
+                .map(|e| located(e.code(), e.span(), e.message()))
+                .collect();
+            rendered.join("\n")
+        }
+        AbraError::Compile(e) => match e.span() {
+            Some(span) => located(e.code(), span, &e.message()),
+            None => format!("error[{}]: {}\n --> {}", e.code(), e, file),
+        },
+        AbraError::Runtime(e) => e.to_string().trim_end().to_string(),
+        AbraError::Embed(e) => format!("error[{}]: {}", e.code(), e),
+    }
+}
+
+/// Parses, typechecks and compiles a program and everything it imports. Returns the
+/// typechecker's warnings and notes alongside the code, or fails on the warnings
+/// `lints` denies too.
+pub(crate) fn compile_named(
+    source_code: &str,
+    infile_path: &str,
+    debug: u16,
+    natives: &dyn SignatureProvider,
+    lints: &LintLevels,
+) -> Result<(Code, Vec<TypeCheckerMessage>), AbraError> {
+    compile_program(source_code, infile_path, debug, natives, lints, false)
+}
 
-    // 1. Tokenize
-    let mut tokenizer = Tokenizer::new(&source_code);
+/// Compiles a program like [`compile_named`] but without calling `main`, which it
+/// needn't have, for the caller to run its functions one by one.
+pub(crate) fn compile_library(
+    source_code: &str,
+    infile_path: &str,
+) -> Result<(Code, Vec<TypeCheckerMessage>), AbraError> {
+    compile_program(
+        source_code,
+        infile_path,
+        0,
+        &Vec::new(),
+        &LintLevels::default(),
+        true,
+    )
+}
+
+fn compile_program(
+    source_code: &str,
+    infile_path: &str,
+    debug: u16,
+    natives: &dyn SignatureProvider,
+    lints: &LintLevels,
+    library: bool,
+) -> Result<(Code, Vec<TypeCheckerMessage>), AbraError> {
+    // Use paths relative to the new module structure
+    use crate::compiler::{modules::load_program, Compiler};
+    use crate::frontend::tokenizer::Tokenizer;
+
+    // 1. Tokenize (only printed here; the module loader tokenizes every file itself)
     if debug & 1 == 1 {
         // Tokenizer debug flag
-        let tokens: Vec<_> = tokenizer.collect(); // Collect for printing
-        println!("--- Tokens ---");
+        let tokens: Vec<_> = Tokenizer::new(source_code).collect(); // Collect for printing
+        eprintln!("--- Tokens ---");
         for token_res in tokens {
             match token_res {
-                Ok((start, tok, end)) => println!("[{}..{}] {:?}", start, end, tok),
+                Ok((start, tok, end)) => eprintln!("[{}..{}] {:?}", start, end, tok),
                 Err(e) => eprintln!("Tokenizer Error: {}", e),
             }
         }
-        println!("--------------");
-        // Re-create tokenizer as it was consumed by the debug print
-        tokenizer = Tokenizer::new(&source_code);
+        eprintln!("--------------");
     }
 
-    // 2. Parse
-    let mut parser = Parser::new(tokenizer);
-    let ast_result = parser.parse_program();
-    // Check for error before unwrapping
-    if let Err(e) = &ast_result {
-        // Use eprintln for errors, provide context
-        eprintln!("Parser Error: {}", e);
-        // Consider adding more specific error context if possible from 'e'
-        return Err(anyhow::anyhow!("Parsing failed for '{}'", infile_path).context(e.to_string()));
-        // Propagate error with context
-    }
-    let ast = ast_result.unwrap(); // Safe to unwrap now
+    // 2. Parse this file and everything it imports
+    let program = load_program(infile_path, source_code)?;
 
     // 3. Optimize AST (Optional)
 
     // 4. Compile
     let mut compiler = Compiler::new();
-    compiler.compilation_pipepline(ast)?; // Compile the potentially optimized AST
-    let code: Code = compiler.into();
+    compiler.add_signatures(natives);
+    compiler.set_lint_levels(lints.clone());
+    compiler.set_library(library);
+    for (file, source) in program.sources.iter() {
+        compiler.add_source(file, source);
+    }
+    let messages = compiler.compilation_pipepline(program.items)?; // Compile the potentially optimized AST
+    let mut code: Code = compiler.into();
 
-    // 5. Optimize Bytecode (Optional)
+    // 5. Optimize Bytecode
+    crate::optimizer::optimize_bytecode(&mut code);
 
-    Ok(code)
+    Ok((code, messages))
+}
+
+/// Parses and typechecks a program and everything it imports without generating code.
+pub(crate) fn check_named(
+    source_code: &str,
+    infile_path: &str,
+    lints: &LintLevels,
+) -> Result<Vec<TypeCheckerMessage>, AbraError> {
+    let program = crate::compiler::modules::load_program(infile_path, source_code)?;
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler.set_lint_levels(lints.clone());
+    compiler.typecheck(&program.items)
+}
+
+/// Runs the compiled bytecode using the virtual machine. A runtime error is returned
+/// as a [`RuntimeError`](crate::runtime::debug::RuntimeError) with the failing line
+/// and the call stack.
+///
+/// Takes the program by value: the VM owns its bytecode, so callers that still
+/// need the `Code` afterwards have to clone it themselves.
+pub fn run(code: Code) -> Result<usize> {
+    run_with_session(code, Session::live(), Vec::new())
 }
 
-/// Runs the compiled bytecode using the virtual machine.
-pub fn run(code: &Code, debug: u16) -> Result<usize> {
-    // Debug level > 1 enables VM debug mode
-    let vm_debug_mode = debug > 1;
-    // Pass necessary context like VTables if they become separate
-    let mut machine =
-        ByteCodeMachine::new(code.clone(), vm_debug_mode /*, pass vtables here */);
+/// Runs the compiled bytecode with its external inputs recorded or replayed by `session`
+/// and `args` as its command-line arguments.
+pub fn run_with_session(code: Code, session: Session, args: Vec<String>) -> Result<usize> {
+    let mut machine = ByteCodeMachine::new(code);
+    run_machine(&mut machine, session, args)
+}
+
+/// Runs a loaded machine like [`run_with_session`].
+fn run_machine(
+    machine: &mut ByteCodeMachine,
+    session: Session,
+    args: Vec<String>,
+) -> Result<usize> {
+    machine.set_session(session);
+    machine.set_args(args);
     let exit_code = machine.run();
-    Ok(exit_code)
+    // Flush the log even when the program failed; that run is the one worth replaying.
+    machine.session().finish()?;
+    if let Some(tracer) = machine.tracer() {
+        tracer.finish()?;
+    }
+    match machine.runtime_error() {
+        Some(error) => Err(anyhow::Error::new(error.clone())),
+        None => Ok(exit_code),
+    }
+}
+
+/// Runs the compiled bytecode and returns its exit code together with everything it printed.
+/// A runtime panic becomes an error carrying the VM's panic report.
+pub fn run_captured(code: Code) -> Result<(usize, String)> {
+    let buffer = SharedBuffer::default();
+    let mut machine = ByteCodeMachine::new(code);
+    machine.set_stdout(Box::new(buffer.clone()));
+    let exit_code = machine.run();
+    if let Some(report) = machine.runtime_error() {
+        return Err(anyhow::anyhow!("{}", report.to_string().trim_end()));
+    }
+    drop(machine);
+    Ok((exit_code, buffer.contents()))
 }
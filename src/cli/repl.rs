@@ -0,0 +1,183 @@
+//! Interactive read-eval-print loop behind `abra repl`.
+//!
+//! Every input is compiled onto the end of one growing program and run on a
+//! single `ByteCodeMachine`, so functions, classes and variables defined by
+//! earlier inputs stay available to later ones.
+
+use crate::cli::render_error;
+use crate::compiler::Compiler;
+use crate::errors::AbraError;
+use crate::frontend::{
+    ast::{Item, Parameter, Statement},
+    parser::Parser,
+    span::Located,
+    tokenizer::Tokenizer,
+};
+use crate::runtime::vm::ByteCodeMachine;
+use anyhow::{bail, Result};
+use std::io::{BufRead, Write};
+
+const PROMPT: &str = ">>> ";
+const CONTINUATION_PROMPT: &str = "... ";
+/// File name diagnostics report for typed-in code.
+const REPL_FILE: &str = "<repl>";
+/// First words of an input that keeps going until a blank line.
+const BLOCK_KEYWORDS: [&str; 9] = [
+    "func",
+    "class",
+    "enum",
+    "interface",
+    "if",
+    "for",
+    "while",
+    "try",
+    "match",
+];
+
+pub struct Repl {
+    compiler: Compiler,
+    machine: ByteCodeMachine,
+    /// Every function, class and constant entered so far, needed to typecheck later inputs.
+    items: Vec<Item>,
+    /// Variables declared at the top level so far.
+    locals: Vec<Parameter>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let compiler = Compiler::new();
+        let machine = ByteCodeMachine::new(compiler.code());
+        Repl {
+            compiler,
+            machine,
+            items: Vec::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.machine.set_stdout(stdout);
+    }
+
+    /// Compiles and runs one complete input. Returns the value it evaluated to,
+    /// formatted for echoing, if it left one behind.
+    ///
+    /// An input that fails to compile leaves the session unchanged.
+    pub fn eval(&mut self, source: &str) -> Result<Option<String>> {
+        let first_word = source.split_whitespace().next();
+        let starts_item = matches!(
+            first_word,
+            Some("func" | "class" | "enum" | "interface" | "const")
+        );
+        let mut parser = Parser::new(Tokenizer::new(source)).with_source(source);
+        // Compile into a copy so a rejected input can't leave half its labels behind.
+        let mut compiler = self.compiler.clone();
+        compiler.set_source(REPL_FILE, source);
+        if starts_item {
+            let items = parser.parse_program()?;
+            compiler.compile_incremental_items(&self.items, &items)?;
+            self.compiler = compiler;
+            self.items.extend(items);
+            self.machine.load(self.compiler.code());
+            return Ok(None);
+        }
+
+        let stmts = parser.parse_statements()?;
+        if contains_return(&stmts) {
+            bail!("'return' is only allowed inside a function");
+        }
+        // Later inputs see it as a variable that already has a value.
+        if let Some(name) = stmts.iter().find_map(|stmt| match &stmt.node {
+            Statement::Declare(name, _, None) => Some(name),
+            _ => None,
+        }) {
+            bail!("'{}' needs a value when it's declared at the prompt", name);
+        }
+        let start = compiler.compile_incremental_statements(&self.items, &self.locals, &stmts)?;
+        self.compiler = compiler;
+        self.machine.load(self.compiler.code());
+        for stmt in stmts.iter() {
+            if let Statement::Declare(name, ty, _) = &stmt.node {
+                self.locals.retain(|local| &local.name != name);
+                self.locals.push(Parameter::new(name.clone(), ty.clone()));
+            }
+        }
+        match self.machine.execute_from(start)? {
+            Some(value) => Ok(Some(self.machine.display_value(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn contains_return(stmts: &[Located<Statement>]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.node {
+        Statement::Return(_) => true,
+        Statement::If(_, then_block, else_block) => {
+            contains_return(then_block) || else_block.as_deref().is_some_and(contains_return)
+        }
+        Statement::For(_, _, _, body) => body.as_deref().is_some_and(contains_return),
+        Statement::While(_, body) => contains_return(body),
+        Statement::Try(body, _, handler) => contains_return(body) || contains_return(handler),
+        Statement::Match(_, arms) => arms.iter().any(|(_, body)| contains_return(body)),
+        _ => false,
+    })
+}
+
+/// Reads one input from `input`, following a block opener with continuation
+/// lines up to the first blank one. `None` at end of input.
+fn read_input(input: &mut impl BufRead, output: &mut impl Write) -> Result<Option<String>> {
+    write!(output, "{}", PROMPT)?;
+    output.flush()?;
+    let mut source = String::new();
+    if input.read_line(&mut source)? == 0 {
+        return Ok(None);
+    }
+    let opens_block = source
+        .split_whitespace()
+        .next()
+        .is_some_and(|word| BLOCK_KEYWORDS.contains(&word));
+    if opens_block {
+        loop {
+            write!(output, "{}", CONTINUATION_PROMPT)?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            source.push_str(&line);
+        }
+    }
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    Ok(Some(source))
+}
+
+/// Runs the interactive loop until end of input or `:quit`.
+pub fn run_repl() -> Result<()> {
+    let mut repl = Repl::new();
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout();
+    while let Some(source) = read_input(&mut input, &mut output)? {
+        match source.trim() {
+            "" => continue,
+            ":quit" | ":q" => break,
+            _ => {}
+        }
+        match repl.eval(&source) {
+            Ok(Some(value)) => writeln!(output, "{}", value)?,
+            Ok(None) => {}
+            Err(e) => match e.downcast_ref::<AbraError>() {
+                Some(error) => writeln!(output, "{}", render_error(error, REPL_FILE, &source))?,
+                None => writeln!(output, "{:#}", e)?,
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! Test runner behind `abra test`.
+//!
+//! Every top-level function named `test_*` without parameters is a test. Each runs in
+//! a fresh VM, after the global variables of its file are set, and fails at the first
+//! `assert`, `assert_eq` or `fail` that doesn't hold, or on any other runtime error.
+//! What a test prints is captured and shown only when it fails.
+
+use crate::cli::{compile_library, render_error};
+use crate::compiler::Code;
+use crate::errors::AbraError;
+use crate::frontend::{ast::Item, parser::Parser, tokenizer::Tokenizer};
+use crate::runtime::{platform::SharedBuffer, vm::ByteCodeMachine};
+use anyhow::Result;
+use std::{
+    fs::read_to_string,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of the functions that are tests.
+pub const TEST_PREFIX: &str = "test_";
+
+/// Counts of a run; a file that doesn't compile counts as one failure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// A test that didn't pass.
+struct Failure {
+    test: String,
+    message: String,
+    output: String,
+}
+
+/// Runs the tests in `path`, a source file or a directory searched for `.abra` files,
+/// and writes a report to `out`.
+pub fn run_tests(path: &str, out: &mut impl Write) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut failures = Vec::new();
+    for file in source_files(Path::new(path))? {
+        let file = file.to_string_lossy().into_owned();
+        let source = read_to_string(&file)
+            .map_err(|e| anyhow::anyhow!("Failed to read input file '{}': {}", file, e))?;
+        let tests = match test_names(&source) {
+            Ok(tests) if tests.is_empty() => continue,
+            Ok(tests) => tests,
+            Err(e) => {
+                failures.push(compile_failure(&file, &source, &e));
+                summary.failed += 1;
+                continue;
+            }
+        };
+        writeln!(out, "running {} tests from {}", tests.len(), file)?;
+        let code = match compile_library(&source, &file) {
+            Ok((code, _)) => code,
+            Err(e) => {
+                writeln!(out, "error: {} doesn't compile", file)?;
+                failures.push(compile_failure(&file, &source, &e));
+                summary.failed += 1;
+                continue;
+            }
+        };
+        for test in tests {
+            match run_test(&code, &test) {
+                Ok(()) => {
+                    writeln!(out, "test {} ... ok", test)?;
+                    summary.passed += 1;
+                }
+                Err((message, output)) => {
+                    writeln!(out, "test {} ... FAILED", test)?;
+                    failures.push(Failure {
+                        test: format!("{} ({})", test, file),
+                        message,
+                        output,
+                    });
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        writeln!(out, "\nfailures:")?;
+        for failure in failures.iter() {
+            writeln!(out, "\n---- {} ----", failure.test)?;
+            writeln!(out, "{}", failure.message)?;
+            if !failure.output.is_empty() {
+                writeln!(out, "output:\n{}", failure.output.trim_end())?;
+            }
+        }
+    }
+    let result = if summary.failed == 0 { "ok" } else { "FAILED" };
+    writeln!(
+        out,
+        "\ntest result: {}. {} passed; {} failed",
+        result, summary.passed, summary.failed
+    )?;
+    Ok(summary)
+}
+
+/// `path` itself, or the `.abra` files under it in order.
+fn source_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read directory '{}': {}", path.display(), e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(source_files(&entry)?);
+        } else if entry
+            .extension()
+            .is_some_and(|extension| extension == "abra")
+        {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+/// Names of the tests `source` defines, in order.
+fn test_names(source: &str) -> Result<Vec<String>, AbraError> {
+    let items = Parser::new(Tokenizer::new(source))
+        .with_source(source)
+        .parse_program()?;
+    Ok(items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Function(function)
+                if function.name.starts_with(TEST_PREFIX) && function.params.is_empty() =>
+            {
+                Some(function.name.clone())
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+fn compile_failure(file: &str, source: &str, error: &AbraError) -> Failure {
+    Failure {
+        test: file.to_string(),
+        message: render_error(error, file, source),
+        output: String::new(),
+    }
+}
+
+/// Runs `test` in a machine of its own. A failure comes back as its message, with
+/// where it happened, and what the test printed.
+fn run_test(code: &Code, test: &str) -> Result<(), (String, String)> {
+    let buffer = SharedBuffer::default();
+    let mut machine = ByteCodeMachine::new(code.clone());
+    machine.set_stdout(Box::new(buffer.clone()));
+    // `_start` only sets the globals.
+    while machine.run_instruction().is_none() {}
+    let outcome = match machine.runtime_error() {
+        Some(error) => Err(AbraError::Runtime(error.clone())),
+        None => machine.call_function(test, Vec::new()).map(drop),
+    };
+    drop(machine);
+    outcome.map_err(|error| {
+        let message = match error {
+            AbraError::Runtime(error) => match error.source() {
+                Some(source) => format!("{}\n  at {}", error.message, source),
+                None => error.message,
+            },
+            other => other.to_string(),
+        };
+        (message, buffer.contents())
+    })
+}
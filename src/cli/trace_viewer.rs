@@ -0,0 +1,113 @@
+//! `abra trace`: steps forwards and backwards through a trace written with
+//! `--trace`, to find where a program started going wrong.
+
+use crate::runtime::trace::TraceEntry;
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+const PROMPT: &str = "(trace) ";
+/// Instructions `list` shows without a count.
+const LIST_LENGTH: usize = 10;
+const HELP: &str = "\
+n, next [N]     go forward N instructions; an empty line does the same
+b, back [N]     go back N instructions
+g, goto STEP    go to the instruction of step STEP
+f, find TEXT    go forward to the next instruction whose line contains TEXT
+r, rfind TEXT   go back to the last instruction whose line contains TEXT
+e, error        go forward to the next instruction that failed
+l, list [N]     show the N instructions around this one
+q, quit         stop
+";
+
+/// Reads commands from `input` until it ends or says `quit`, showing the entries
+/// it moves to on `output`.
+pub fn run_trace_viewer<R: BufRead, W: Write>(
+    entries: &[TraceEntry],
+    input: &mut R,
+    output: &mut W,
+) -> Result<()> {
+    let Some(first) = entries.first() else {
+        writeln!(output, "The trace is empty.")?;
+        return Ok(());
+    };
+    writeln!(
+        output,
+        "{} instructions, from step {}. Type 'help' for the commands.",
+        entries.len(),
+        first.step
+    )?;
+    let mut at = 0;
+    writeln!(output, "{}", entries[at])?;
+    loop {
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        let count = || argument.parse::<usize>().unwrap_or(1);
+        let found = |position: Option<usize>| position.ok_or("Not found.");
+        let target = match command {
+            "" | "n" | "next" => Ok((at + count()).min(entries.len() - 1)),
+            "b" | "back" => Ok(at.saturating_sub(count())),
+            "g" | "goto" => match argument.parse::<u64>() {
+                Ok(step) => found(entries.iter().position(|entry| entry.step == step)),
+                Err(_) => Err("Usage: goto STEP"),
+            },
+            "f" | "find" if !argument.is_empty() => found(
+                entries[at + 1..]
+                    .iter()
+                    .position(|entry| entry.to_string().contains(argument))
+                    .map(|offset| at + 1 + offset),
+            ),
+            "r" | "rfind" if !argument.is_empty() => found(
+                entries[..at]
+                    .iter()
+                    .rposition(|entry| entry.to_string().contains(argument)),
+            ),
+            "f" | "find" | "r" | "rfind" => Err("Usage: find TEXT, rfind TEXT"),
+            "e" | "error" => found(
+                entries[at + 1..]
+                    .iter()
+                    .position(|entry| entry.error.is_some())
+                    .map(|offset| at + 1 + offset),
+            ),
+            "l" | "list" => {
+                let length = argument.parse::<usize>().unwrap_or(LIST_LENGTH).max(1);
+                let start = at.saturating_sub(length / 2);
+                for (index, entry) in entries.iter().enumerate().skip(start).take(length) {
+                    let marker = if index == at { "->" } else { "  " };
+                    writeln!(output, "{} {}", marker, entry)?;
+                }
+                continue;
+            }
+            "h" | "help" => {
+                write!(output, "{}", HELP)?;
+                continue;
+            }
+            "q" | "quit" => return Ok(()),
+            _ => {
+                writeln!(output, "Unknown command '{}', try 'help'.", command)?;
+                continue;
+            }
+        };
+        match target {
+            Ok(target) if target == at && matches!(command, "" | "n" | "next") => {
+                writeln!(output, "At the end of the trace.")?;
+            }
+            Ok(target) if target == at && matches!(command, "b" | "back") => {
+                writeln!(output, "At the start of the trace.")?;
+            }
+            Ok(target) => {
+                at = target;
+                writeln!(output, "{}", entries[at])?;
+            }
+            Err(message) => writeln!(output, "{}", message)?,
+        }
+    }
+}
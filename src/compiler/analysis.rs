@@ -0,0 +1,408 @@
+//! What the native backends know about the values a function works on.
+//!
+//! The WebAssembly output and the JIT both lower functions whose values are all
+//! plain `i64`s, so they need to know which instructions each function reaches and
+//! which kind of value is on the stack and in each slot at every one of them. Both
+//! work that out here, and only differ in the instructions, constants and builtins
+//! they can handle, which they describe with a [`Program`].
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::compiler::{
+    typecheck::{Primitives, Type},
+    BinaryOp, ByteCode, Operand,
+};
+
+/// What a backend knows about a value; all of them are an `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// Not worked out yet, e.g. the result of a call to a function being analyzed.
+    Unknown,
+    Int,
+    Bool,
+    Str,
+}
+
+impl Kind {
+    pub(crate) fn join(self, other: Kind) -> Option<Kind> {
+        match (self, other) {
+            (Kind::Unknown, kind) | (kind, Kind::Unknown) => Some(kind),
+            (a, b) if a == b => Some(a),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn of_type(ty: &Type) -> Option<Kind> {
+        match ty {
+            Type::Primitive(Primitives::Integer) => Some(Kind::Int),
+            Type::Primitive(Primitives::Bool) => Some(Kind::Bool),
+            Type::Primitive(Primitives::String) => Some(Kind::Str),
+            _ => None,
+        }
+    }
+
+    /// How the kind shows in the names of imported builtins.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Kind::Unknown => "unknown",
+            Kind::Int => "int",
+            Kind::Bool => "bool",
+            Kind::Str => "string",
+        }
+    }
+}
+
+/// A backend's view of the program it lowers.
+pub(crate) trait Program {
+    fn bytecode(&self) -> &[ByteCode];
+
+    /// Kind of the constant at `index`, or `None` if the backend can't handle it.
+    fn constant(&self, index: usize) -> Option<Kind>;
+
+    /// Entry address of the function at `index` of the function table.
+    fn function(&self, index: usize) -> Option<usize>;
+
+    /// Where the label `name` points.
+    fn label(&self, _name: &str) -> Option<usize> {
+        None
+    }
+
+    /// Kind of a parameter of type `ty`, or `None` if the backend can't handle it.
+    fn parameter(&self, ty: &Type) -> Option<Kind> {
+        Kind::of_type(ty)
+    }
+
+    /// Whether the backend can lower `byte` at all.
+    fn supports(&self, _byte: &ByteCode) -> bool {
+        true
+    }
+
+    /// What a call to the builtin `name` with arguments of kinds `args` returns.
+    fn builtin(&self, name: &str, _args: &[Kind]) -> Result<Option<Kind>, String> {
+        Err(format!("'{}' can't be called here", name))
+    }
+}
+
+/// What is known about each function of the program, by entry address: the kinds
+/// of its parameters and of its result, if it returns one.
+pub(crate) type Signatures = HashMap<usize, (Vec<Kind>, Option<Kind>)>;
+
+/// Where a jump at `pc` goes, or `None` if `byte` isn't a jump.
+pub(crate) fn jump_target(
+    program: &impl Program,
+    pc: usize,
+    byte: &ByteCode,
+) -> Option<Result<usize, String>> {
+    let absolute = |target: i64| usize::try_from(target).map_err(|_| "a jump out of the program");
+    Some(match byte {
+        ByteCode::JMPTO(label) | ByteCode::JITL(label) => program
+            .label(label)
+            .ok_or_else(|| format!("a jump to the undefined label '{}'", label)),
+        ByteCode::JMPABS(target) | ByteCode::JITA(target) => {
+            absolute(*target).map_err(String::from)
+        }
+        ByteCode::JMPREL(offset) | ByteCode::JITR(offset) => {
+            absolute(pc as i64 + offset).map_err(String::from)
+        }
+        _ => return None,
+    })
+}
+
+pub(crate) fn is_conditional(byte: &ByteCode) -> bool {
+    matches!(
+        byte,
+        ByteCode::JITL(_) | ByteCode::JITA(_) | ByteCode::JITR(_)
+    )
+}
+
+/// Where control can go after the instruction at `pc`: the target first, for jumps.
+pub(crate) fn successors(
+    program: &impl Program,
+    pc: usize,
+    byte: &ByteCode,
+) -> Result<Vec<usize>, String> {
+    if let Some(target) = jump_target(program, pc, byte) {
+        let mut next = vec![target?];
+        if is_conditional(byte) {
+            next.push(pc + 1);
+        }
+        return Ok(next);
+    }
+    match byte {
+        ByteCode::RET(_) | ByteCode::EXIT => Ok(Vec::new()),
+        ByteCode::PUSH(_)
+        | ByteCode::POP
+        | ByteCode::DUP
+        | ByteCode::EQUALS
+        | ByteCode::NEGATE
+        | ByteCode::NOT
+        | ByteCode::AND
+        | ByteCode::OR
+        | ByteCode::XOR
+        | ByteCode::LOADSLOT(_)
+        | ByteCode::STORESLOT(_)
+        | ByteCode::DROPSLOT(_)
+        | ByteCode::BINARY(..)
+        | ByteCode::CALL(..)
+        | ByteCode::CALLFN(..) => Ok(vec![pc + 1]),
+        byte if BinaryOp::of(byte).is_some() => Ok(vec![pc + 1]),
+        byte => Err(format!("{:?} is not supported", byte)),
+    }
+}
+
+/// A function's prologue, body and the kinds of what it works on.
+pub(crate) struct Shape {
+    pub address: usize,
+    /// Slot and kind of each parameter, in order.
+    pub params: Vec<(u16, Kind)>,
+    /// First instruction after the `DEFARG`s.
+    pub body: usize,
+    /// Instructions reachable from `body`.
+    pub reachable: BTreeSet<usize>,
+    /// Whether it returns a value: its result, or the exit code for the entry.
+    pub returns_value: bool,
+    pub returns: Kind,
+    pub slots: BTreeMap<u16, Kind>,
+    /// Operand stack on entry to each reachable instruction.
+    pub stacks: HashMap<usize, Vec<Kind>>,
+}
+
+impl Shape {
+    /// Finds the prologue and the instructions reachable from the body, failing if
+    /// any of them can't be lowered.
+    pub(crate) fn scan(program: &impl Program, address: usize) -> Result<Shape, String> {
+        let bytecode = program.bytecode();
+        let mut params = Vec::new();
+        let mut body = address;
+        while let Some(ByteCode::DEFARG(slot, ty)) = bytecode.get(body) {
+            let kind = program
+                .parameter(ty)
+                .ok_or_else(|| format!("a parameter is a {}", ty))?;
+            params.push((*slot, kind));
+            body += 1;
+        }
+        let mut reachable = BTreeSet::new();
+        let mut returns = None;
+        let mut pending = vec![body];
+        while let Some(pc) = pending.pop() {
+            if !reachable.insert(pc) {
+                continue;
+            }
+            let byte = bytecode
+                .get(pc)
+                .ok_or("control runs off the end of the program")?;
+            if !program.supports(byte) {
+                return Err(format!("{:?} is not supported", byte));
+            }
+            let value = match byte {
+                ByteCode::RET(value) => Some(*value),
+                ByteCode::EXIT => Some(true),
+                ByteCode::CALLFN(index, _) if program.function(*index).is_none() => {
+                    return Err("it calls a function without code".into());
+                }
+                _ => None,
+            };
+            // Returning a value on some paths and not on others isn't worth supporting.
+            if let Some(value) = value {
+                if *returns.get_or_insert(value) != value {
+                    return Err("it returns a value on some paths only".into());
+                }
+            }
+            pending.extend(successors(program, pc, byte)?);
+        }
+        Ok(Shape {
+            address,
+            slots: params.iter().copied().collect(),
+            params,
+            body,
+            reachable,
+            returns_value: returns.unwrap_or(false),
+            returns: Kind::Unknown,
+            stacks: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn signature(&self) -> (Vec<Kind>, Option<Kind>) {
+        let params = self.params.iter().map(|(_, kind)| *kind).collect();
+        (params, self.returns_value.then_some(self.returns))
+    }
+
+    /// The arguments of the call at `pc`, which come off the top of the stack.
+    pub(crate) fn arguments(&self, pc: usize, argc: u64) -> &[Kind] {
+        let stack = &self.stacks[&pc];
+        &stack[stack.len() - argc as usize..]
+    }
+
+    /// Works out the kinds on the stack and in the slots from what is known so far
+    /// about the slots and the functions called.
+    fn infer(&mut self, program: &impl Program, signatures: &Signatures) -> Result<(), String> {
+        self.stacks.clear();
+        let mut pending = vec![(self.body, Vec::new())];
+        while let Some((pc, mut stack)) = pending.pop() {
+            if let Some(seen) = self.stacks.get(&pc) {
+                if seen.len() != stack.len() {
+                    return Err(format!("the stack depth differs at {}", pc));
+                }
+                let joined = seen
+                    .iter()
+                    .zip(stack.iter())
+                    .map(|(a, b)| a.join(*b))
+                    .collect::<Option<Vec<Kind>>>()
+                    .ok_or_else(|| format!("values of different types meet at {}", pc))?;
+                if joined == *seen {
+                    continue;
+                }
+                stack = joined;
+            }
+            self.stacks.insert(pc, stack.clone());
+            let byte = &program.bytecode()[pc];
+            self.step(program, signatures, byte, &mut stack)
+                .ok_or_else(|| format!("{:?} at {} is not supported here", byte, pc))?;
+            for next in successors(program, pc, byte)? {
+                pending.push((next, stack.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `byte` to the kinds on `stack`.
+    fn step(
+        &mut self,
+        program: &impl Program,
+        signatures: &Signatures,
+        byte: &ByteCode,
+        stack: &mut Vec<Kind>,
+    ) -> Option<()> {
+        let expect = |kind: Kind, expected: Kind| kind.join(expected).map(|_| ());
+        match byte {
+            ByteCode::PUSH(constant) => stack.push(program.constant(*constant)?),
+            ByteCode::POP => {
+                stack.pop()?;
+            }
+            ByteCode::DUP => stack.push(*stack.last()?),
+            ByteCode::LOADSLOT(slot) => {
+                stack.push(*self.slots.entry(*slot).or_insert(Kind::Unknown))
+            }
+            ByteCode::STORESLOT(slot) => {
+                let kind = stack.pop()?;
+                self.store(*slot, kind)?;
+            }
+            ByteCode::DROPSLOT(_) => {}
+            ByteCode::EQUALS => {
+                let kind = stack.pop()?.join(stack.pop()?)?;
+                // Strings would compare by address.
+                if kind == Kind::Str {
+                    return None;
+                }
+                stack.push(Kind::Bool);
+            }
+            ByteCode::NEGATE | ByteCode::NOT => {
+                expect(stack.pop()?, Kind::Bool)?;
+                stack.push(Kind::Bool);
+            }
+            ByteCode::AND | ByteCode::OR | ByteCode::XOR => {
+                expect(stack.pop()?, Kind::Bool)?;
+                expect(stack.pop()?, Kind::Bool)?;
+                stack.push(Kind::Bool);
+            }
+            ByteCode::JITL(_) | ByteCode::JITA(_) | ByteCode::JITR(_) => {
+                expect(stack.pop()?, Kind::Bool)?
+            }
+            ByteCode::BINARY(op, first, second, result) => {
+                for operand in [first, second] {
+                    let kind = match operand {
+                        Operand::Stack => stack.pop()?,
+                        Operand::Slot(slot) => *self.slots.entry(*slot).or_insert(Kind::Unknown),
+                        Operand::Const(constant) => program.constant(*constant)?,
+                    };
+                    expect(kind, Kind::Int)?;
+                }
+                let kind = binary_result(*op);
+                match result {
+                    Operand::Stack => stack.push(kind),
+                    Operand::Slot(slot) => self.store(*slot, kind)?,
+                    Operand::Const(_) => return None,
+                }
+            }
+            ByteCode::CALLFN(index, argc) => {
+                let (params, returns) = signatures.get(&program.function(*index)?)?;
+                if params.len() != *argc as usize {
+                    return None;
+                }
+                for param in params.iter().rev() {
+                    expect(stack.pop()?, *param)?;
+                }
+                if let Some(kind) = returns {
+                    stack.push(*kind);
+                }
+            }
+            ByteCode::CALL(name, argc) => {
+                let args = stack.split_off(stack.len().checked_sub(*argc as usize)?);
+                if let Some(kind) = program.builtin(name, &args).ok()? {
+                    stack.push(kind);
+                }
+            }
+            ByteCode::RET(true) => self.returns = self.returns.join(stack.pop()?)?,
+            ByteCode::EXIT => {
+                if let Some(kind) = stack.pop() {
+                    expect(kind, Kind::Int)?;
+                }
+            }
+            ByteCode::RET(false)
+            | ByteCode::JMPTO(_)
+            | ByteCode::JMPABS(_)
+            | ByteCode::JMPREL(_) => {}
+            byte => {
+                let op = BinaryOp::of(byte)?;
+                expect(stack.pop()?, Kind::Int)?;
+                expect(stack.pop()?, Kind::Int)?;
+                stack.push(binary_result(op));
+            }
+        }
+        Some(())
+    }
+
+    fn store(&mut self, slot: u16, kind: Kind) -> Option<()> {
+        let known = self.slots.entry(slot).or_insert(Kind::Unknown);
+        *known = known.join(kind)?;
+        Some(())
+    }
+}
+
+/// Works out the kinds of every function in `shapes`, which may call each other and
+/// the functions `known` describes. Kinds flow between callers and callees, and from
+/// a store back to the loads before it in a loop, so this goes round until they
+/// settle. Fails with the index of the function that can't be lowered and why.
+pub(crate) fn infer(
+    program: &impl Program,
+    shapes: &mut [&mut Shape],
+    known: &Signatures,
+) -> Result<(), (usize, String)> {
+    loop {
+        let mut signatures = known.clone();
+        signatures.extend(
+            shapes
+                .iter()
+                .map(|shape| (shape.address, shape.signature())),
+        );
+        let slots: Vec<BTreeMap<u16, Kind>> = shapes.iter().map(|s| s.slots.clone()).collect();
+        for (index, shape) in shapes.iter_mut().enumerate() {
+            shape
+                .infer(program, &signatures)
+                .map_err(|reason| (index, reason))?;
+        }
+        let settled = shapes.iter().zip(&slots).all(|(shape, slots)| {
+            shape.slots == *slots && signatures[&shape.address] == shape.signature()
+        });
+        if settled {
+            return Ok(());
+        }
+    }
+}
+
+fn binary_result(op: BinaryOp) -> Kind {
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mult | BinaryOp::Div | BinaryOp::Mod => Kind::Int,
+        BinaryOp::Lesser | BinaryOp::Greater | BinaryOp::EqLess | BinaryOp::EqGreat => Kind::Bool,
+    }
+}
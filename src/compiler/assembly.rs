@@ -0,0 +1,267 @@
+//! Textual form of [`Code`], written by `abra compile --emit asm` and read back by
+//! `abra asm`.
+//!
+//! Directives at the top give the tables an instruction refers to by index, in
+//! order; the instructions follow, indented, each under the labels pointing at it:
+//!
+//! ```text
+//! .file "fib.abra"                  ; 0
+//! .const {"Integer":1}              ; 0
+//! .function "fib"                   ; 0
+//! .locals "fib" ["n"]
+//! .type {...}
+//! .symbol "fib" {"Function":[0,{...}]}
+//! fib:
+//!     @0:1:1 DEFARG 0 {"Primitive":"Integer"}
+//!     @0:2:5 PUSH 0                 ; = 1
+//! ```
+//!
+//! An instruction is its name followed by its operands as JSON values, the way
+//! `disasm --json` shows them, optionally preceded by `@file:line:col`, the statement
+//! it was compiled from. `;` starts a comment outside a string.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+use crate::compiler::{ByteCode, Code, SourceLocation};
+
+/// `code` as assembly text, which [`assemble`] turns back into the same code.
+pub fn to_assembly(code: &Code) -> String {
+    let mut text = String::from("; Abra assembly, see `abra asm`\n");
+    for (index, file) in code.files.iter().enumerate() {
+        text.push_str(&directive("file", &[json(file)], Some(index.to_string())));
+    }
+    for (index, constant) in code.constants.iter().enumerate() {
+        text.push_str(&directive(
+            "const",
+            &[json(constant)],
+            Some(index.to_string()),
+        ));
+    }
+    for (index, function) in code.functions.iter().enumerate() {
+        text.push_str(&directive(
+            "function",
+            &[json(function)],
+            Some(index.to_string()),
+        ));
+    }
+    let local_names: BTreeMap<_, _> = code.local_names.iter().collect();
+    for (function, names) in local_names {
+        text.push_str(&directive("locals", &[json(function), json(names)], None));
+    }
+    for definition in code.types.iter() {
+        text.push_str(&directive("type", &[json(definition)], None));
+    }
+    for (name, symbol) in code.symbols.iter() {
+        text.push_str(&directive("symbol", &[json(name), json(symbol)], None));
+    }
+
+    let mut labels: BTreeMap<usize, Vec<&String>> = BTreeMap::new();
+    for (name, index) in code.labels.iter() {
+        labels.entry(*index).or_default().push(name);
+    }
+    for names in labels.values_mut() {
+        names.sort();
+    }
+    for (index, instruction) in code.bytecode.iter().enumerate() {
+        for name in labels.get(&index).into_iter().flatten() {
+            text.push_str(&format!("{}:\n", name));
+        }
+        let mut line = String::from("    ");
+        if let Some(location) = code.locations.get(index).copied().flatten() {
+            line.push_str(&format!(
+                "@{}:{}:{} ",
+                location.file, location.line, location.col
+            ));
+        }
+        line.push_str(&operation(instruction));
+        let comment = match instruction {
+            ByteCode::PUSH(constant) => code.constants.get(*constant).map(|c| format!("= {}", c)),
+            ByteCode::CALLFN(function, _) => code.functions.get(*function).cloned(),
+            _ => None,
+        };
+        text.push_str(&with_comment(line, comment));
+    }
+    // Labels past the last instruction, such as the end of code built up by the REPL.
+    for name in labels
+        .range(code.bytecode.len()..)
+        .flat_map(|(_, names)| names)
+    {
+        text.push_str(&format!("{}:\n", name));
+    }
+    text
+}
+
+/// `value` as one line of JSON, maps with their keys sorted so the text is stable.
+fn json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .expect("code serializes to JSON")
+        .to_string()
+}
+
+fn directive(name: &str, operands: &[String], comment: Option<String>) -> String {
+    with_comment(format!(".{} {}", name, operands.join(" ")), comment)
+}
+
+/// `line` with `comment` lined up after it, and a line ending.
+fn with_comment(line: String, comment: Option<String>) -> String {
+    match comment {
+        Some(comment) => format!("{:<33} ; {}\n", line, comment),
+        None => format!("{}\n", line),
+    }
+}
+
+/// `NAME operand...` for `instruction`.
+fn operation(instruction: &ByteCode) -> String {
+    match serde_json::to_value(instruction).expect("code serializes to JSON") {
+        Value::String(name) => name,
+        Value::Object(variant) => {
+            let (name, payload) = variant.into_iter().next().expect("a variant has a name");
+            // A tuple variant's fields come as an array; no single-field variant
+            // holds one.
+            let operands = match payload {
+                Value::Array(fields) => fields,
+                field => vec![field],
+            };
+            let operands: Vec<String> = operands.iter().map(Value::to_string).collect();
+            format!("{} {}", name, operands.join(" "))
+        }
+        other => unreachable!(
+            "instructions serialize to a name or a variant, not {}",
+            other
+        ),
+    }
+}
+
+/// Reads assembly text as written by [`to_assembly`] or by hand.
+pub fn assemble(text: &str) -> Result<Code> {
+    let mut code = Code {
+        bytecode: Vec::new(),
+        labels: Default::default(),
+        functions: Vec::new(),
+        constants: Vec::new(),
+        local_names: Default::default(),
+        types: Vec::new(),
+        locations: Vec::new(),
+        files: Vec::new(),
+        symbols: Vec::new(),
+    };
+    for (number, line) in text.lines().enumerate() {
+        assemble_line(&mut code, line).with_context(|| format!("line {}", number + 1))?;
+    }
+    if code.locations.iter().all(Option::is_none) {
+        code.locations.clear();
+    }
+    code.symbols.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(code)
+}
+
+fn assemble_line(code: &mut Code, line: &str) -> Result<()> {
+    let line = without_comment(line);
+    let indented = line.starts_with(char::is_whitespace);
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+    if let Some(directive) = line.strip_prefix('.') {
+        let (name, operands) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((directive, ""));
+        let mut operands = operands_of(operands)?.into_iter();
+        let mut operand = || {
+            operands
+                .next()
+                .ok_or_else(|| anyhow!("'.{}' needs more operands", name))
+        };
+        match name {
+            "file" => code.files.push(serde_json::from_value(operand()?)?),
+            "const" => code.constants.push(serde_json::from_value(operand()?)?),
+            "function" => code.functions.push(serde_json::from_value(operand()?)?),
+            "locals" => {
+                let function = serde_json::from_value(operand()?)?;
+                code.local_names
+                    .insert(function, serde_json::from_value(operand()?)?);
+            }
+            "type" => code.types.push(serde_json::from_value(operand()?)?),
+            "symbol" => {
+                let name = serde_json::from_value(operand()?)?;
+                let symbol = serde_json::from_value(operand()?)?;
+                code.symbols.push((name, symbol));
+            }
+            _ => bail!("Unknown directive '.{}'", name),
+        }
+        return Ok(());
+    }
+    if !indented {
+        let Some(label) = line.strip_suffix(':') else {
+            bail!("Expected a label ending in ':' or an indented instruction");
+        };
+        if code
+            .labels
+            .insert(label.to_string(), code.bytecode.len())
+            .is_some()
+        {
+            bail!("Label '{}' is defined twice", label);
+        }
+        return Ok(());
+    }
+
+    let (location, line) = match line.strip_prefix('@') {
+        Some(rest) => {
+            let (location, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            (Some(location_of(location)?), rest.trim_start())
+        }
+        None => (None, line),
+    };
+    let (name, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let mut operands = operands_of(operands)?;
+    let instruction = match operands.len() {
+        0 => Value::String(name.to_string()),
+        1 => serde_json::json!({ name: operands.remove(0) }),
+        _ => serde_json::json!({ name: operands }),
+    };
+    let instruction: ByteCode = serde_json::from_value(instruction)
+        .map_err(|e| anyhow!("Invalid instruction '{}': {}", line, e))?;
+    code.bytecode.push(instruction);
+    code.locations.push(location);
+    Ok(())
+}
+
+/// `line` up to a `;` that isn't inside a string.
+fn without_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The JSON values in `text`, separated by whitespace.
+fn operands_of(text: &str) -> Result<Vec<Value>> {
+    serde_json::Deserializer::from_str(text)
+        .into_iter::<Value>()
+        .collect::<Result<_, _>>()
+        .map_err(|e| anyhow!("Invalid operand: {}", e))
+}
+
+/// `file:line:col` after an `@`.
+fn location_of(text: &str) -> Result<SourceLocation> {
+    let parts: Vec<usize> = text
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("Expected '@file:line:col', found '@{}'", text))?;
+    match parts[..] {
+        [file, line, col] => Ok(SourceLocation { file, line, col }),
+        _ => bail!("Expected '@file:line:col', found '@{}'", text),
+    }
+}
@@ -0,0 +1,317 @@
+//! Definite assignment: a local declared without a value, as in `let x: int`, has to
+//! be assigned on every path to a statement that reads it.
+//!
+//! The check follows the paths through each function body: a branch that leaves its
+//! block doesn't reach what comes after it, an `if` or `match` passes on only what
+//! all its arms assign, and a loop body may not run at all, except a `while true`,
+//! which only its `break`s leave. A nested function or lambda reads the variables it
+//! captures where it is declared.
+
+use std::collections::HashSet;
+
+use crate::compiler::{
+    compile::{mentioned_in_statement, mentioned_names},
+    typecheck::TypeCheckerMessage,
+};
+use crate::errors::TypeError;
+use crate::frontend::{
+    ast::{Block, Expression, Function, Item, Pattern, Statement},
+    span::Span,
+    tokenizer::TokenLiteral,
+};
+use crate::runtime::value::StaticValue;
+
+/// Reports each read of a local that may not have been assigned yet. `exhaustive` are
+/// the spans of the `match` statements whose arms cover every value.
+pub(crate) fn check_assignments(items: &[Item], exhaustive: &[Span]) -> Vec<TypeCheckerMessage> {
+    let mut checker = Assignments {
+        exhaustive,
+        state: State::default(),
+        loops: Vec::new(),
+        errors: Vec::new(),
+    };
+    for item in items {
+        match item {
+            Item::Function(func) => checker.function_body(func),
+            Item::Class(class) => class
+                .functions
+                .iter()
+                .for_each(|func| checker.function_body(func)),
+            Item::Enum(_) | Item::Interface(_) | Item::Global(_) | Item::Import(_) => {}
+        }
+    }
+    checker.errors
+}
+
+/// A local in scope and whether every path so far has assigned it.
+#[derive(Clone)]
+struct Local {
+    name: String,
+    assigned: bool,
+}
+
+/// What is known at a point of a body.
+#[derive(Clone)]
+struct State {
+    /// Locals of each enclosing block, innermost last.
+    scopes: Vec<Vec<Local>>,
+    /// Whether any path reaches the point; after a `return` none does.
+    reachable: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            scopes: Vec::new(),
+            reachable: true,
+        }
+    }
+}
+
+impl State {
+    /// Joins the paths of `other` into these, where both have the same blocks open.
+    fn join(&mut self, other: State) {
+        if !other.reachable {
+            return;
+        }
+        if !self.reachable {
+            *self = other;
+            return;
+        }
+        for (scope, other) in self.scopes.iter_mut().zip(other.scopes) {
+            for (local, other) in scope.iter_mut().zip(other) {
+                local.assigned &= other.assigned;
+            }
+        }
+    }
+}
+
+/// The paths leaving a loop by `break`.
+struct Loop {
+    /// Blocks open where the loop starts; a `break` closes the ones inside it.
+    depth: usize,
+    breaks: Option<State>,
+}
+
+struct Assignments<'a> {
+    exhaustive: &'a [Span],
+    state: State,
+    loops: Vec<Loop>,
+    errors: Vec<TypeCheckerMessage>,
+}
+
+impl Assignments<'_> {
+    fn function_body(&mut self, func: &Function) {
+        let state = std::mem::take(&mut self.state);
+        let loops = std::mem::take(&mut self.loops);
+        self.state.scopes = state.scopes.clone();
+        self.state.scopes.push(Vec::new());
+        for param in func.params.iter() {
+            self.declare(&param.name, true);
+        }
+        self.statements(&func.body);
+        self.state = state;
+        self.loops = loops;
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.state.scopes.push(Vec::new());
+        self.statements(block);
+        self.state.scopes.pop();
+    }
+
+    fn statements(&mut self, block: &Block) {
+        for stmt in block.iter() {
+            self.statement(&stmt.node, &stmt.span);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement, span: &Span) {
+        match stmt {
+            Statement::Declare(name, _, value) => {
+                value.iter().for_each(|value| self.read(value, span));
+                self.declare(name, value.is_some());
+            }
+            Statement::Destructure(names, value) => {
+                self.read(value, span);
+                names.iter().for_each(|name| self.declare(name, true));
+            }
+            Statement::Set(None, name, value) => {
+                self.read(value, span);
+                self.assign(name);
+            }
+            Statement::Set(Some(_), _, _)
+            | Statement::SetIndex(..)
+            | Statement::Compound(..)
+            | Statement::Expression(_)
+            | Statement::Print(_) => self.read_statement(stmt, span),
+            Statement::Return(_) | Statement::Throw(_) => {
+                self.read_statement(stmt, span);
+                self.state.reachable = false;
+            }
+            Statement::Break => {
+                let state = self.state.clone();
+                if let Some(innermost) = self.loops.last_mut() {
+                    let mut state = state;
+                    state.scopes.truncate(innermost.depth);
+                    match &mut innermost.breaks {
+                        Some(breaks) => breaks.join(state),
+                        None => innermost.breaks = Some(state),
+                    }
+                }
+                self.state.reachable = false;
+            }
+            Statement::Continue => self.state.reachable = false,
+            Statement::If(condition, then, otherwise) => {
+                self.read(condition, span);
+                let before = self.state.clone();
+                self.block(then);
+                let after_then = std::mem::replace(&mut self.state, before);
+                otherwise.iter().for_each(|otherwise| self.block(otherwise));
+                self.state.join(after_then);
+            }
+            Statement::While(condition, body) => {
+                self.read(condition, span);
+                let forever =
+                    *condition == Expression::Literal(TokenLiteral::Value(StaticValue::Bool(true)));
+                self.repeat(forever, |checker| checker.block(body));
+            }
+            Statement::For(init, condition, step, body) => {
+                self.state.scopes.push(Vec::new());
+                self.statement(init, span);
+                self.read(condition, span);
+                // The step runs after a `continue` too, which may skip what the body assigns.
+                self.repeat(false, |checker| {
+                    let before = checker.state.clone();
+                    body.iter().for_each(|body| checker.block(body));
+                    checker.state = before;
+                    checker.statement(step, span);
+                });
+                self.state.scopes.pop();
+            }
+            Statement::Try(body, name, handler) => {
+                // The handler can start from anywhere in the body.
+                let before = self.state.clone();
+                self.block(body);
+                let after_body = std::mem::replace(&mut self.state, before);
+                self.state.scopes.push(Vec::new());
+                self.declare(name, true);
+                self.statements(handler);
+                self.state.scopes.pop();
+                self.state.join(after_body);
+            }
+            Statement::Match(subject, arms) => {
+                self.read(subject, span);
+                let before = self.state.clone();
+                // Without an arm for every value, the match may run none.
+                let mut after = before.clone();
+                after.reachable &= !self.exhaustive.contains(span);
+                for (pattern, body) in arms.iter() {
+                    self.state = before.clone();
+                    self.state.scopes.push(Vec::new());
+                    if let Pattern::Variant(_, bindings) = pattern {
+                        bindings
+                            .iter()
+                            .for_each(|binding| self.declare(binding, true));
+                    }
+                    self.statements(body);
+                    self.state.scopes.pop();
+                    after.join(std::mem::take(&mut self.state));
+                }
+                self.state = after;
+            }
+            // The closure copies the variables it reads when it is declared, so its
+            // body sees them as they are here.
+            Statement::Function(func) => {
+                self.declare(&func.name, true);
+                self.function_body(func);
+            }
+            Statement::Null => {}
+        }
+    }
+
+    /// Checks a loop, whose `body` may run any number of times, or, `forever`, can
+    /// only be left by a `break`.
+    fn repeat(&mut self, forever: bool, body: impl FnOnce(&mut Self)) {
+        let before = self.state.clone();
+        self.loops.push(Loop {
+            depth: self.state.scopes.len(),
+            breaks: None,
+        });
+        body(self);
+        let exit = self.loops.pop().expect("pushed above");
+        self.state = before;
+        if forever {
+            self.state.reachable = false;
+        }
+        if let Some(breaks) = exit.breaks {
+            self.state.join(breaks);
+        }
+    }
+
+    fn declare(&mut self, name: &str, assigned: bool) {
+        if name == "_" {
+            return;
+        }
+        let scope = self
+            .state
+            .scopes
+            .last_mut()
+            .expect("locals are declared in a scope");
+        scope.push(Local {
+            name: name.to_string(),
+            assigned,
+        });
+    }
+
+    fn local(&mut self, name: &str) -> Option<&mut Local> {
+        self.state
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|local| local.name == name))
+    }
+
+    fn assign(&mut self, name: &str) {
+        if let Some(local) = self.local(name) {
+            local.assigned = true;
+        }
+    }
+
+    fn read(&mut self, value: &Expression, span: &Span) {
+        let mut mentioned = HashSet::new();
+        mentioned_names(value, &mut mentioned);
+        self.read_names(mentioned, span);
+    }
+
+    /// Reads what `stmt` mentions, where it assigns nothing to a variable.
+    fn read_statement(&mut self, stmt: &Statement, span: &Span) {
+        let mut mentioned = HashSet::new();
+        mentioned_in_statement(stmt, &mut mentioned);
+        self.read_names(mentioned, span);
+    }
+
+    /// Reports the names in `mentioned` that are locals which may not be assigned yet.
+    /// Each is reported once and taken as assigned afterwards.
+    fn read_names(&mut self, mentioned: HashSet<String>, span: &Span) {
+        if !self.state.reachable {
+            return;
+        }
+        let mut names: Vec<String> = mentioned.into_iter().collect();
+        names.sort();
+        for name in names {
+            let Some(local) = self.local(&name).filter(|local| !local.assigned) else {
+                continue;
+            };
+            local.assigned = true;
+            self.errors
+                .push(TypeCheckerMessage::Error(TypeError::Unassigned(
+                    span.clone(),
+                    format!(
+                        "Variable '{}' is read before it's assigned a value on every path",
+                        name
+                    ),
+                )));
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use serde::{Deserialize, Serialize};
 
 use crate::compiler::typecheck::Type;
-use crate::runtime::value::*;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum ByteCode {
-    PUSH(StaticValue),
+    /// Pushes the value at the index into `Code::constants`.
+    PUSH(usize),
     POP,
     DUP,
     ADD,
@@ -23,23 +23,114 @@ pub enum ByteCode {
     EQLESS,
     EQGREAT,
     CAST(Type),
+    /// Replaces the value on top of the stack with whether it is of the type.
+    ISTYPE(Type),
+    /// Creates a value of the `Enum::Variant` from the payload values on the stack.
+    VARIANT(String, usize),
+    /// Replaces the value on top of the stack with whether it is the `Enum::Variant`.
+    ISVARIANT(String),
     AND,
     OR,
     XOR,
+    /// Bitwise operations on the two integers on top of the stack, the left operand
+    /// popped first like the arithmetic ones.
+    BAND,
+    BOR,
+    BXOR,
+    /// Shifts an integer by the integer below it, which must be from 0 to 63.
+    SHL,
+    SHR,
+    /// Flips the bits of the integer on top of the stack.
+    BNOT,
     JITL(String),
     JITA(i64),
     JITR(i64),
+    /// Pushes the local in the slot of the running function.
+    LOADSLOT(u16),
+    /// Pops the value on top of the stack into the local in the slot.
+    STORESLOT(u16),
+    /// Clears the slot at the end of its local's scope, so the value can be collected.
+    DROPSLOT(u16),
     GETVARLOCAL(String),
     SAVEVARLOCAL(String),
     GETVARGLOBAL(String),
     SAVEVARGLOBAL(String),
     DEFVAR(String, Type),
+    /// Binds the next argument passed to the running call to the local in the slot;
+    /// a function's prologue has one for each parameter, in order.
+    DEFARG(u16, Type),
     DROPVAR(String),
     CALL(String, u64),
+    CALLFN(usize, u64),
+    CALLVIRT(String, u64),
+    /// Calls the method as the named class defines or inherits it, on the receiver
+    /// of the method running now.
+    CALLSUPER(String, String, u64),
+    /// Creates a function value for the code at a label, capturing the named locals
+    /// from the values on top of the stack, the last one topmost.
+    CLOSURE(String, Type, Vec<String>),
+    /// Calls the function value on top of the stack with the arguments below it.
+    CALLINDIRECT(u64),
+    /// Enters a `try`: an error from here on jumps to the label with its message pushed.
+    PUSHEH(String),
+    /// Leaves the innermost `try`.
+    POPEH,
+    /// Raises the string on top of the stack as an error.
+    THROW,
     RET(bool),
     EXIT,
     INSTANCE(Type, usize),
     GETFROMREF,
+    /// Replaces the array or string on top of the stack with the part between the
+    /// bounds below it, start then end; a null bound is that end of the collection.
+    SLICE,
     SAVETOREF,
     NOT,
+    /// Register form of the arithmetic and comparison instructions, emitted by the
+    /// register backend: reads the operand the stack form pops first, then the
+    /// other one, and writes the result to the last operand.
+    BINARY(BinaryOp, Operand, Operand, Operand),
+}
+
+/// Where a `BINARY` instruction reads or writes a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Operand {
+    /// The top of the stack: popped when read, pushed when written.
+    Stack,
+    /// The local in the slot of the running function.
+    Slot(u16),
+    /// The value at the index into `Code::constants`; can't be written.
+    Const(usize),
+}
+
+/// The stack instructions `BINARY` can stand in for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Mod,
+    Lesser,
+    Greater,
+    EqLess,
+    EqGreat,
+}
+
+impl BinaryOp {
+    /// The operation `byte` performs, if it is one `BINARY` can stand in for.
+    pub fn of(byte: &ByteCode) -> Option<BinaryOp> {
+        match byte {
+            ByteCode::ADD => Some(BinaryOp::Add),
+            ByteCode::SUB => Some(BinaryOp::Sub),
+            ByteCode::MULT => Some(BinaryOp::Mult),
+            ByteCode::DIV => Some(BinaryOp::Div),
+            ByteCode::MOD => Some(BinaryOp::Mod),
+            ByteCode::LESSER => Some(BinaryOp::Lesser),
+            ByteCode::GREATER => Some(BinaryOp::Greater),
+            ByteCode::EQLESS => Some(BinaryOp::EqLess),
+            ByteCode::EQGREAT => Some(BinaryOp::EqGreat),
+            _ => None,
+        }
+    }
 }
@@ -1,92 +1,502 @@
-use std::{any, collections::HashMap};
+use std::collections::{HashMap, HashSet};
 
 use anyhow::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     compiler::{
-        typecheck::{AbraTypeDefinition, FunctionSignature, Type, TypeChecker, TypeCheckerMessage},
+        lint::{Level, LintLevels},
+        typecheck::{
+            default_value, fold_constant, AbraTypeDefinition, Composite, FunctionSignature,
+            SignatureProvider, Type, TypeChecker, TypeCheckerMessage, STRING_TYPE,
+        },
         ByteCode,
     },
+    errors::{AbraError, CompileError, TypeError},
     frontend::{
-        ast::{BinOpCode, Expression, Item, Statement},
+        ast::{
+            bind_arguments, BinOpCode, Block, Class, Expression, Function, Item, Parameter,
+            Pattern, Statement,
+        },
+        span::{LineIndex, Located, SourceError, Span},
         tokenizer::TokenLiteral,
     },
+    runtime::{inbuilt::inbuilt_constant, value::StaticValue},
 };
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Code {
     pub bytecode: Vec<ByteCode>,
-    pub labels: Vec<(String, usize)>,
+    /// Symbol table: label name -> bytecode index. Names are unique.
+    pub labels: HashMap<String, usize>,
+    /// Function table indexed by `ByteCode::CALLFN`; each name is also a label.
+    pub functions: Vec<String>,
+    /// Constant pool indexed by `ByteCode::PUSH`; each value appears once.
+    pub constants: Vec<StaticValue>,
+    /// Names of each function's local slots, by function label; only the debugger
+    /// reads locals by name.
+    pub local_names: HashMap<String, Vec<String>>,
+    /// Class layouts from the typechecker, sorted by name, for runtime instantiation.
+    pub types: Vec<AbraTypeDefinition>,
+    /// Where each instruction was compiled from, parallel to `bytecode`; empty
+    /// for code without line information.
+    pub locations: Vec<Option<SourceLocation>>,
+    /// File names `SourceLocation::file` indexes into.
+    pub files: Vec<String>,
+    /// Classes and functions the program defines, sorted by name, each function with
+    /// the index it starts at, so other code can be linked against it without its source.
+    pub symbols: Vec<(String, Symbol)>,
+}
+
+/// The statement an instruction was compiled from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// Index into `Code::files`.
+    pub file: usize,
+    /// 1-based line and column.
+    pub line: usize,
+    pub col: usize,
 }
 
 impl Code {
+    /// Drops the line table and local names, which only error reports and debuggers
+    /// read. The program runs the same; its errors just can't point at the source.
+    pub fn strip_debug_info(&mut self) {
+        self.locations.clear();
+        self.files.clear();
+        self.local_names.clear();
+    }
+
+    /// The class or function `name` the program defines.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .binary_search_by(|(symbol, _)| symbol.as_str().cmp(name))
+            .ok()
+            .map(|index| &self.symbols[index].1)
+    }
+
     pub fn string_representation(&self) -> String {
-        let mut ret = String::new();
-        for byte in self.bytecode.iter().enumerate() {
-            for label in self.labels.iter() {
-                if label.1 == byte.0 {
-                    ret.push_str(&format!("{} | {}:\n", label.1, label.0));
-                }
+        listing(
+            &self.bytecode,
+            &self.labels,
+            &self.functions,
+            &self.constants,
+        )
+    }
+
+    /// The listing as JSON: one object per instruction with its index, the labels
+    /// pointing at it, the instruction itself and the index it transfers control to.
+    pub fn json_representation(&self) -> serde_json::Value {
+        let labels_at = labels_by_index(&self.labels);
+        let instructions = self
+            .bytecode
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| {
+                serde_json::json!({
+                    "index": index,
+                    "labels": labels_at.get(&index).cloned().unwrap_or_default(),
+                    "instruction": byte,
+                    "target": jump_target(index, byte, &self.labels, &self.functions),
+                    "constant": pushed_constant(byte, &self.constants),
+                })
+            })
+            .collect();
+        serde_json::Value::Array(instructions)
+    }
+}
+
+/// A compiled unit offers the functions it defines, other than `main`, for code to be
+/// compiled against and later linked with it.
+impl SignatureProvider for Code {
+    fn signatures(&self) -> Vec<FunctionSignature> {
+        self.symbols
+            .iter()
+            .filter(|(name, _)| name != "main")
+            .filter_map(|(_, symbol)| match symbol {
+                Symbol::Function((_, signature)) => Some(signature.clone()),
+                Symbol::Class(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn labels_by_index(labels: &HashMap<String, usize>) -> HashMap<usize, Vec<&String>> {
+    let mut labels_at: HashMap<usize, Vec<&String>> = HashMap::new();
+    for (name, index) in labels {
+        labels_at.entry(*index).or_default().push(name);
+    }
+    for names in labels_at.values_mut() {
+        names.sort();
+    }
+    labels_at
+}
+
+/// The bytecode index `byte`, found at `index`, may jump or call to.
+fn jump_target(
+    index: usize,
+    byte: &ByteCode,
+    labels: &HashMap<String, usize>,
+    functions: &[String],
+) -> Option<usize> {
+    match byte {
+        ByteCode::JMPTO(label)
+        | ByteCode::JITL(label)
+        | ByteCode::CALL(label, _)
+        | ByteCode::CLOSURE(label, _, _)
+        | ByteCode::PUSHEH(label) => labels.get(label).copied(),
+        ByteCode::CALLFN(func, _) => functions
+            .get(*func)
+            .and_then(|name| labels.get(name).copied()),
+        ByteCode::JMPABS(target) | ByteCode::JITA(target) => usize::try_from(*target).ok(),
+        ByteCode::JMPREL(offset) | ByteCode::JITR(offset) => {
+            usize::try_from(index as i64 + offset).ok()
+        }
+        _ => None,
+    }
+}
+
+/// The value `byte` pushes from the constant pool, if it is a `PUSH`.
+fn pushed_constant<'a>(byte: &ByteCode, constants: &'a [StaticValue]) -> Option<&'a StaticValue> {
+    match byte {
+        ByteCode::PUSH(index) => constants.get(*index),
+        _ => None,
+    }
+}
+
+/// Renders bytecode one instruction per line, with labels above the index they point
+/// at, the resolved index after every jump or call and the value after every push.
+fn listing(
+    bytecode: &[ByteCode],
+    labels: &HashMap<String, usize>,
+    functions: &[String],
+    constants: &[StaticValue],
+) -> String {
+    let labels_at = labels_by_index(labels);
+    let mut ret = String::new();
+    for byte in bytecode.iter().enumerate() {
+        if let Some(names) = labels_at.get(&byte.0) {
+            for name in names.iter() {
+                ret.push_str(&format!("{} | {}:\n", byte.0, name));
             }
-            ret.push_str(&format!(
-                "{} | {}:\n",
-                byte.0,
-                serde_json::to_string(&byte.1).unwrap()
-            ));
         }
-        ret
+        ret.push_str(&format!(
+            "{} | {}:",
+            byte.0,
+            serde_json::to_string(&byte.1).unwrap()
+        ));
+        if let Some(target) = jump_target(byte.0, byte.1, labels, functions) {
+            ret.push_str(&format!(" -> {}", target));
+        }
+        if let Some(value) = pushed_constant(byte.1, constants) {
+            ret.push_str(&format!(" = {}", serde_json::to_string(value).unwrap()));
+        }
+        ret.push('\n');
     }
+    ret
 }
 
 impl From<Compiler> for Code {
     fn from(value: Compiler) -> Self {
+        let mut types: Vec<AbraTypeDefinition> = value
+            .symbol_table
+            .values()
+            .filter_map(|symbol| match symbol {
+                Symbol::Class(def) => Some(def.clone()),
+                Symbol::Function(_) => None,
+            })
+            .collect();
+        types.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut locations = value.locations.clone();
+        locations.resize(value.bytecode.len(), None);
+        // Builtins and host functions have no code here to link against.
+        let mut symbols: Vec<(String, Symbol)> = value
+            .symbol_table
+            .iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Class(_) => Some((name.clone(), symbol.clone())),
+                Symbol::Function((_, signature)) => value.labels.get(name).map(|start| {
+                    let start = Some(*start);
+                    (name.clone(), Symbol::Function((start, signature.clone())))
+                }),
+            })
+            .collect();
+        symbols.sort_by(|a, b| a.0.cmp(&b.0));
         Code {
             bytecode: value.get_code(),
             labels: value.get_labels(),
+            functions: value.functions,
+            constants: value.constant_pool,
+            local_names: value.local_names,
+            types,
+            locations,
+            files: value.files,
+            symbols,
         }
     }
 }
 
+/// A function's signature and, once it is compiled, the index it starts at.
 type CompFuncSig = (Option<usize>, FunctionSignature);
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Symbol {
     Class(AbraTypeDefinition),
     Function(CompFuncSig),
 }
 
+/// Name the typechecker sees for statements compiled outside any function.
+const INCREMENTAL_FUNCTION: &str = "<toplevel>";
+
+/// Method, under the class's name, that sets fields to their initializers; `new`
+/// runs it before `init`.
+pub const FIELD_INITIALIZER: &str = "$fields";
+
+#[derive(Clone)]
 pub struct Compiler {
     bytecode: Vec<ByteCode>,
-    labels: Vec<(String, usize)>,
+    labels: HashMap<String, usize>,
     label_iter: usize,
     symbol_table: HashMap<String, Symbol>,
+    functions: Vec<String>,
+    function_indices: HashMap<String, usize>,
+    /// `fn(...) -> ...` types of the top-level functions, for taking them as values.
+    function_types: HashMap<String, Type>,
+    /// Parameters of the top-level functions, to put named and left out arguments in place.
+    function_params: HashMap<String, Vec<Parameter>>,
+    /// Payload sizes of the enum variants, by `Enum::Variant` name.
+    enum_variants: HashMap<String, usize>,
+    /// Variables declared outside any function, kept in the VM's global variables.
+    globals: HashSet<String>,
+    /// Values of the `const`s, pushed wherever one is read.
+    constants: HashMap<String, StaticValue>,
+    /// Becomes `Code::constants`; `constant_indices` finds a value already in it.
+    constant_pool: Vec<StaticValue>,
+    constant_indices: HashMap<StaticValue, usize>,
+    /// The class each class extends, for `super` calls.
+    class_parents: HashMap<String, String>,
+    /// Class whose methods are being compiled.
+    current_class: Option<String>,
+    /// Variables declared so far in the function being compiled, including its parameters.
+    scope_locals: Vec<String>,
+    /// Slots of the function being compiled; a local's slot is its index here.
+    local_slots: Vec<String>,
+    /// Locals in scope and their slots, innermost last; a local declared in a block
+    /// shadows one of the same name outside it until the block ends.
+    visible_slots: Vec<(String, u16)>,
+    /// Keeps locals by name instead, for the REPL's top level, where a snippet reads
+    /// the locals of the ones before it.
+    named_locals: bool,
+    /// `local_slots` of every function compiled so far, by label.
+    local_names: HashMap<String, Vec<String>>,
+    loops: Vec<LoopLabels>,
+    /// File names and contents of the program; an instruction is attributed to the
+    /// file its span points into, or the first one.
+    sources: Vec<(String, LineIndex)>,
+    /// Source location of each instruction so far; may be shorter than `bytecode`.
+    locations: Vec<Option<SourceLocation>>,
+    files: Vec<String>,
+    /// Functions the host provides besides the builtins, see `add_signatures`.
+    natives: Vec<FunctionSignature>,
+    /// Expression statements whose value is popped, see `TypeChecker::unused_values`.
+    unused_values: HashSet<Span>,
+    /// Whether the function being compiled leaves a value on the stack when it
+    /// returns; one returning `null` leaves nothing, like the builtins that do.
+    returns_value: bool,
+    /// Which typechecker warnings are left out and which stop compilation like errors do.
+    lint_levels: LintLevels,
+    /// Compiles a program without calling `main`, see `set_library`.
+    library: bool,
+}
+
+/// Jump targets of the innermost enclosing loops, innermost last.
+#[derive(Clone)]
+struct LoopLabels {
+    break_label: String,
+    continue_label: String,
+    /// Locals declared inside the loop so far; dropped before jumping out.
+    locals: Vec<String>,
+    /// `try` blocks entered inside the loop; their handlers are removed before jumping out.
+    handlers: usize,
+}
+
+/// How many locals were in scope when a block started, see `enter_scope`.
+struct ScopeMark {
+    locals: usize,
+    slots: usize,
+    loop_locals: Option<usize>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
         Compiler {
             bytecode: Vec::new(),
-            labels: Vec::new(),
+            labels: HashMap::new(),
             label_iter: 0,
             symbol_table: HashMap::new(),
+            functions: Vec::new(),
+            function_indices: HashMap::new(),
+            function_types: HashMap::new(),
+            function_params: HashMap::new(),
+            enum_variants: HashMap::new(),
+            globals: HashSet::new(),
+            constants: HashMap::new(),
+            constant_pool: Vec::new(),
+            constant_indices: HashMap::new(),
+            class_parents: HashMap::new(),
+            current_class: None,
+            scope_locals: Vec::new(),
+            local_slots: Vec::new(),
+            visible_slots: Vec::new(),
+            named_locals: false,
+            local_names: HashMap::new(),
+            loops: Vec::new(),
+            sources: Vec::new(),
+            locations: Vec::new(),
+            files: Vec::new(),
+            natives: Vec::new(),
+            unused_values: HashSet::new(),
+            returns_value: false,
+            lint_levels: LintLevels::default(),
+            library: false,
+        }
+    }
+
+    /// Lets source locations name `file`, the file `source` was read from.
+    pub fn set_source(&mut self, file: &str, source: &str) {
+        self.sources = vec![(file.to_string(), LineIndex::new(source))];
+    }
+
+    /// Adds another file of a program made of several, see [`Compiler::set_source`].
+    pub fn add_source(&mut self, file: &str, source: &str) {
+        self.sources
+            .push((file.to_string(), LineIndex::new(source)));
+    }
+    /// Lets programs call the functions `provider` supplies, e.g. ones a host registers
+    /// with [`ByteCodeMachine::register_native`](crate::runtime::vm::ByteCodeMachine::register_native).
+    pub fn add_signatures(&mut self, provider: &dyn SignatureProvider) {
+        self.natives.extend(provider.signatures());
+    }
+
+    /// Reports typechecker warnings as [`TypeError::DeniedWarning`]s instead of
+    /// returning them.
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.lint_levels = std::mem::take(&mut self.lint_levels).with_warnings_as_errors(enabled);
+    }
+
+    /// Decides which lints' warnings are left out and which are reported as errors.
+    pub fn set_lint_levels(&mut self, levels: LintLevels) {
+        self.lint_levels = levels;
+    }
+
+    /// Compiles the program for a host to call its functions, as `abra test` does:
+    /// `_start` only sets the global variables, and there needn't be a `main`.
+    pub fn set_library(&mut self, enabled: bool) {
+        self.library = enabled;
+    }
+
+    /// Typechecks the program and, if it has no errors, compiles it. Returns the
+    /// typechecker's warnings and notes for the caller to report.
+    pub fn compilation_pipepline(
+        &mut self,
+        ast: Vec<Item>,
+    ) -> Result<Vec<TypeCheckerMessage>, AbraError> {
+        let messages = self.typecheck(&ast)?;
+        self.compile_from_ast(&ast).map_err(AbraError::recover)?;
+        Result::Ok(messages)
+    }
+
+    /// Appends `items` to a program that already contains `previous`, e.g. one
+    /// entered earlier in the REPL. Unlike a full compile, no `main` is required.
+    ///
+    /// Warnings are dropped: `previous` is checked again with every call, so they
+    /// would repeat for each input.
+    pub fn compile_incremental_items(
+        &mut self,
+        previous: &[Item],
+        items: &[Item],
+    ) -> Result<(), AbraError> {
+        let mut ast = previous.to_vec();
+        ast.extend_from_slice(items);
+        self.typecheck(&ast)?;
+        self.declare_items(items)
+            .and_then(|_| self.compile_items(items))
+            .map_err(AbraError::recover)
+    }
+
+    /// Appends `stmts` followed by `EXIT` and returns the index to start running them at.
+    /// `locals` are the variables still alive from earlier snippets; the ones `stmts`
+    /// declare are left alive too.
+    pub fn compile_incremental_statements(
+        &mut self,
+        previous: &[Item],
+        locals: &[Parameter],
+        stmts: &[Located<Statement>],
+    ) -> Result<usize, AbraError> {
+        let mut ast = previous.to_vec();
+        ast.push(Item::Function(Function {
+            name: INCREMENTAL_FUNCTION.into(),
+            type_params: Vec::new(),
+            params: locals.to_vec(),
+            return_type: Type::Null,
+            body: stmts.to_vec(),
+            span: Span::default(),
+        }));
+        self.typecheck(&ast)?;
+        self.symbol_table.remove(INCREMENTAL_FUNCTION);
+        // The REPL shows the value of a last expression, which `EXIT` leaves on the stack.
+        if let Some(last) = stmts.last() {
+            self.unused_values.remove(&last.span);
         }
+        let start = self.bytecode.len();
+        self.scope_locals = locals.iter().map(|local| local.name.clone()).collect();
+        let mut declared = Vec::new();
+        self.named_locals = true;
+        let compiled = self.compile_body(&stmts.to_vec(), Some(&mut declared));
+        self.named_locals = false;
+        compiled.map_err(AbraError::recover)?;
+        self.bytecode.push(ByteCode::EXIT);
+        Result::Ok(start)
     }
-    pub fn compilation_pipepline(&mut self, ast: Vec<Item>) -> Result<(), anyhow::Error> {
-        self.compile_from_ast(&ast);
-        let mut t = TypeChecker::new(&ast);
+
+    /// Returns the typechecker's errors, or its warnings and notes if there are none.
+    pub fn typecheck(&mut self, ast: &Vec<Item>) -> Result<Vec<TypeCheckerMessage>, AbraError> {
+        let mut t = TypeChecker::new(ast).with_signatures(&self.natives);
         t.check();
-        for msg in t.messages.iter() {
-            println!("{}", msg);
+        let mut errors = Vec::new();
+        let mut messages = Vec::new();
+        for msg in std::mem::take(&mut t.messages) {
+            let level = match &msg {
+                TypeCheckerMessage::Warning(w) => {
+                    let lint = w.downcast_ref::<SourceError>().and_then(|w| w.lint);
+                    self.lint_levels.level(lint)
+                }
+                _ => Level::Warn,
+            };
+            match msg {
+                TypeCheckerMessage::Error(e) => errors.push(e),
+                TypeCheckerMessage::Warning(_) if level == Level::Allow => {}
+                TypeCheckerMessage::Warning(w) if level == Level::Deny => {
+                    let error = match w.downcast_ref::<SourceError>() {
+                        Some(located) => {
+                            TypeError::DeniedWarning(located.span.clone(), located.message.clone())
+                        }
+                        None => TypeError::DeniedWarning(Span::default(), w.to_string()),
+                    };
+                    errors.push(error);
+                }
+                other => messages.push(other),
+            }
         }
-        if t.messages
-            .iter()
-            .filter(|f| matches!(f, TypeCheckerMessage::Error(_)))
-            .count()
-            > 0
-        {
-            println!("Compilation Failed!");
-            return Err(anyhow!("Compilation Failed!"));
+        if !errors.is_empty() {
+            return Err(AbraError::Type(errors));
         }
+        self.unused_values = std::mem::take(&mut t.unused_values);
         let (a, b) = t.export();
         let c = a.iter().map(|a| (a.0.clone(), Symbol::Class(a.1.clone())));
         let d = b
@@ -95,72 +505,431 @@ impl Compiler {
         c.chain(d).for_each(|(k, v)| {
             self.symbol_table.insert(k, v);
         });
-        Ok(())
+        Result::Ok(messages)
     }
 
-    pub fn compile_from_ast(&mut self, ast: &Vec<Item>) {
+    pub fn compile_from_ast(&mut self, ast: &[Item]) -> Result<()> {
+        self.declare_items(ast)?;
+        let main = match self.function_indices.get("main") {
+            _ if self.library => None,
+            Some(main) => Some(*main),
+            None => return Err(CompileError::MissingMain.into()),
+        };
+
         //COMPILATION
-        self.labels.push(("_start".into(), 0));
-        self.bytecode.push(ByteCode::CALL("main".into(), 0));
+        self.add_label("_start", 0)?;
+        // Global variables are set before `main` runs, in the order they are declared.
+        for item in ast.iter() {
+            if let Item::Global(global) = item {
+                if global.constant {
+                    continue;
+                }
+                let start = self.bytecode.len();
+                self.enter_function(&[], &Type::Null)?;
+                self.compile_expression(&global.value)?;
+                self.bytecode
+                    .push(ByteCode::SAVEVARGLOBAL(global.name.clone()));
+                self.locate(start, &global.span);
+            }
+        }
+        if let Some(main) = main {
+            self.bytecode.push(ByteCode::CALLFN(main, 0));
+        }
         self.bytecode.push(ByteCode::EXIT);
+        self.compile_items(ast)
+    }
 
-        for item in ast.clone() {
+    /// Numbers every function up front so calls can be bound by index, including forward calls.
+    fn declare_items(&mut self, ast: &[Item]) -> Result<()> {
+        for item in ast.iter() {
             match item {
                 Item::Function(func) => {
-                    let mut vec = Vec::new();
-                    self.labels.push((func.name, self.bytecode.len()));
-                    for arg in func.params.iter().rev() {
-                        self.bytecode
-                            .push(ByteCode::DEFVAR(arg.name.clone().into(), arg.ty.clone()));
+                    self.declare_function(func.name.clone())?;
+                    let params = func.params.iter().map(|param| param.ty.clone()).collect();
+                    self.function_types.insert(
+                        func.name.clone(),
+                        Type::function(params, func.return_type.clone()),
+                    );
+                    self.function_params
+                        .insert(func.name.clone(), func.params.clone());
+                }
+                Item::Class(class) => {
+                    if let Some(parent) = &class.parent {
+                        self.class_parents
+                            .insert(class.name.clone(), parent.clone());
+                    }
+                    if class.variables.iter().any(|(_, _, init)| init.is_some()) {
+                        self.declare_function(format!("{}::{}", class.name, FIELD_INITIALIZER))?;
+                    }
+                    for f in class.functions.iter() {
+                        self.declare_function(format!("{}::{}", class.name, f.name))?;
+                    }
+                }
+                Item::Enum(enumeration) => {
+                    for (variant, payload) in enumeration.variants.iter() {
+                        let name = format!("{}::{}", enumeration.name, variant);
+                        self.enum_variants.insert(name, payload.len());
                     }
-                    self.compile_body(&func.body, Some(&mut vec));
+                }
+                Item::Global(global) if global.constant => {
+                    let value = fold_constant(&global.value, &self.constants).ok_or_else(|| {
+                        let message =
+                            format!("Constant '{}' has no compile-time value", global.name);
+                        CompileError::Internal(message)
+                    })?;
+                    self.constants.insert(global.name.clone(), value);
+                }
+                Item::Global(global) => {
+                    self.globals.insert(global.name.clone());
+                }
+                Item::Interface(_) | Item::Import(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `args` in the order of `params`, the named ones put in place and the left out
+    /// ones filled with their parameter's default.
+    fn arranged_arguments(
+        &self,
+        params: &[Parameter],
+        args: &[Expression],
+    ) -> Result<Vec<Expression>> {
+        let bound = bind_arguments(params, args).map_err(CompileError::Internal)?;
+        params
+            .iter()
+            .zip(bound)
+            .map(|(param, arg)| match arg {
+                Some(arg) => Ok(arg.clone()),
+                None => param
+                    .default
+                    .as_ref()
+                    .and_then(|default| default_value(default, &self.constants))
+                    .map(|value| Expression::Literal(TokenLiteral::Value(value)))
+                    .ok_or_else(|| {
+                        let message = format!("Parameter '{}' has no constant default", param.name);
+                        CompileError::Internal(message).into()
+                    }),
+            })
+            .collect()
+    }
+
+    /// `args` reading back hidden locals their values are computed into first, when
+    /// some are passed by name: they run in the order they're written, not in that of
+    /// the parameters. The locals are added to `hidden`, to drop after the call.
+    fn hoist_named_arguments(
+        &mut self,
+        args: &[Expression],
+        hidden: &mut Vec<String>,
+    ) -> Result<Vec<Expression>> {
+        if !args
+            .iter()
+            .any(|arg| matches!(arg, Expression::NamedArgument(..)))
+        {
+            return Ok(args.to_vec());
+        }
+        let mut hoisted = Vec::with_capacity(args.len());
+        for arg in args {
+            let (name, value) = match arg {
+                Expression::NamedArgument(name, value) => (Some(name), &**value),
+                value => (None, value),
+            };
+            // A literal has nothing to run.
+            if let Expression::Literal(TokenLiteral::Value(_)) = value {
+                hoisted.push(arg.clone());
+                continue;
+            }
+            let local = format!("$arg{}", self.get_next_label());
+            self.compile_expression(value)?;
+            self.define_local(&local, Type::Null)?;
+            let read = Expression::Literal(TokenLiteral::Identifier(local.clone()));
+            hidden.push(local);
+            hoisted.push(match name {
+                Some(name) => Expression::NamedArgument(name.clone(), Box::new(read)),
+                None => read,
+            });
+        }
+        Ok(hoisted)
+    }
+
+    /// Whether `name` is read and written as a global or constant here, rather than as
+    /// a local or a field of the class being compiled.
+    fn is_global(&self, name: &str) -> bool {
+        let field = match self
+            .current_class
+            .as_ref()
+            .map(|c| self.symbol_table.get(c))
+        {
+            Some(Some(Symbol::Class(def))) => def.variables.contains_key(name),
+            _ => false,
+        };
+        !field
+            && !self.scope_locals.iter().any(|local| local == name)
+            && (self.globals.contains(name) || self.constants.contains_key(name))
+    }
+
+    fn compile_items(&mut self, ast: &[Item]) -> Result<()> {
+        for item in ast.iter().cloned() {
+            match item {
+                Item::Function(func) => {
+                    let mut vec = Vec::new();
+                    let start = self.bytecode.len();
+                    self.add_label(func.name.clone(), start)?;
+                    self.enter_function(&func.params, &func.return_type)?;
+                    self.compile_body(&func.body, Some(&mut vec))?;
+                    self.return_at_end(&func.body);
+                    self.leave_function(func.name);
+                    self.locate(start, &func.span);
                 }
                 Item::Class(class) => {
-                    class.functions.iter().for_each(|f| {
-                        self.labels.push((
-                            format!("{}::{}", class.name.clone(), f.name.clone()),
-                            self.bytecode.len(),
-                        ));
+                    self.current_class = Some(class.name.clone());
+                    self.compile_field_initializers(&class)?;
+                    for f in class.functions.iter() {
+                        let start = self.bytecode.len();
+                        let label = format!("{}::{}", class.name, f.name);
+                        self.add_label(label.clone(), start)?;
                         let mut vec = Vec::new();
-                        for arg in f.params.iter().rev() {
-                            self.bytecode
-                                .push(ByteCode::DEFVAR(arg.name.clone().into(), arg.ty.clone()));
-                        }
-                        self.compile_body(&f.body, Some(&mut vec));
-                    });
+                        self.enter_function(&f.params, &f.return_type)?;
+                        self.compile_body(&f.body, Some(&mut vec))?;
+                        self.return_at_end(&f.body);
+                        self.leave_function(label);
+                        self.locate(start, &f.span);
+                    }
+                    self.current_class = None;
                 }
+                // Global initializers run from `_start`, see `compile_from_ast`.
+                Item::Enum(_) | Item::Interface(_) | Item::Import(_) | Item::Global(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `null` from a function whose body can run off its end, as one returning
+    /// an optional may, instead of running on into the next function.
+    fn return_at_end(&mut self, body: &Block) {
+        if !matches!(body.last().map(|s| &s.node), Some(Statement::Return(_))) {
+            self.return_null();
+        }
+    }
+
+    /// Returns `null`, which a function returning `null` leaves off the stack.
+    fn return_null(&mut self) {
+        if self.returns_value {
+            self.push_constant(StaticValue::Null);
+        }
+        self.bytecode.push(ByteCode::RET(self.returns_value));
+    }
+
+    fn compile_field_initializers(&mut self, class: &Class) -> Result<()> {
+        if class.variables.iter().all(|(_, _, init)| init.is_none()) {
+            return Ok(());
+        }
+        let start = self.bytecode.len();
+        let label = format!("{}::{}", class.name, FIELD_INITIALIZER);
+        self.add_label(label.clone(), start)?;
+        self.enter_function(&[], &Type::Null)?;
+        for (name, _, init) in class.variables.iter() {
+            if let Some(init) = init {
+                self.compile_expression(init)?;
+                self.bytecode.push(ByteCode::SAVEVARLOCAL(name.clone()));
+            }
+        }
+        self.leave_function(label);
+        self.bytecode.push(ByteCode::RET(false));
+        self.locate(start, &class.span);
+        Ok(())
+    }
+
+    fn declare_function(&mut self, name: String) -> Result<()> {
+        if self.function_indices.contains_key(&name) {
+            bail!("Duplicate definition of function '{}'", name);
+        }
+        self.function_indices
+            .insert(name.clone(), self.functions.len());
+        self.functions.push(name);
+        Ok(())
+    }
+
+    /// Pushes `value`, adding it to the constant pool unless it is there already.
+    fn push_constant(&mut self, value: StaticValue) {
+        let index = match self.constant_indices.get(&value) {
+            Some(index) => *index,
+            None => {
+                self.constant_pool.push(value.clone());
+                self.constant_indices
+                    .insert(value, self.constant_pool.len() - 1);
+                self.constant_pool.len() - 1
+            }
+        };
+        self.bytecode.push(ByteCode::PUSH(index));
+    }
+
+    /// Starts the body of a function, method or lambda returning `return_type`,
+    /// binding its arguments to `params` in order.
+    fn enter_function(&mut self, params: &[Parameter], return_type: &Type) -> Result<()> {
+        self.returns_value = *return_type != Type::Null;
+        self.scope_locals = params.iter().map(|p| p.name.clone()).collect();
+        self.local_slots = Vec::new();
+        self.visible_slots = Vec::new();
+        for param in params.iter() {
+            let slot = self.declare_slot(&param.name)?;
+            self.bytecode.push(ByteCode::DEFARG(slot, param.ty.clone()));
+        }
+        Ok(())
+    }
+
+    /// Records the slot names of the function compiled since `enter_function`.
+    fn leave_function(&mut self, label: String) {
+        let slots = std::mem::take(&mut self.local_slots);
+        self.local_names.insert(label, slots);
+    }
+
+    /// The slot of the innermost local `name` in scope, unless locals are kept by name
+    /// or it has none.
+    fn slot_of(&self, name: &str) -> Option<u16> {
+        if self.named_locals {
+            return None;
+        }
+        self.visible_slots
+            .iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    /// Gives the local `name` a slot and brings it into scope. A slot an earlier `name`
+    /// of this function had is reused once that one went out of scope.
+    fn declare_slot(&mut self, name: &str) -> Result<u16> {
+        let in_scope = |slot: usize| self.visible_slots.iter().any(|(_, s)| *s as usize == slot);
+        let slot = match (0..self.local_slots.len())
+            .find(|slot| self.local_slots[*slot] == name && !in_scope(*slot))
+        {
+            Some(slot) => slot as u16,
+            None => {
+                let slot = u16::try_from(self.local_slots.len()).map_err(|_| {
+                    CompileError::Internal("Too many locals in one function".into())
+                })?;
+                self.local_slots.push(name.to_string());
+                slot
             }
+        };
+        self.visible_slots.push((name.to_string(), slot));
+        Ok(slot)
+    }
+
+    /// Remembers the locals in scope before a block declares its own.
+    fn enter_scope(&self) -> ScopeMark {
+        ScopeMark {
+            locals: self.scope_locals.len(),
+            slots: self.visible_slots.len(),
+            loop_locals: self.loops.last().map(|innermost| innermost.locals.len()),
+        }
+    }
+
+    /// Takes the locals declared since `mark` out of scope, once they were dropped.
+    fn leave_scope(&mut self, mark: ScopeMark) {
+        self.scope_locals.truncate(mark.locals);
+        self.visible_slots.truncate(mark.slots);
+        if let (Some(innermost), Some(len)) = (self.loops.last_mut(), mark.loop_locals) {
+            innermost.locals.truncate(len);
+        }
+    }
+
+    /// Pops the value on top of the stack into a new local `name`.
+    fn define_local(&mut self, name: &str, ty: Type) -> Result<()> {
+        if self.named_locals {
+            self.bytecode.push(ByteCode::DEFVAR(name.to_string(), ty));
+        } else {
+            let slot = self.declare_slot(name)?;
+            self.bytecode.push(ByteCode::STORESLOT(slot));
+        }
+        Ok(())
+    }
+
+    /// Pushes the local `name`. A name without a slot is looked up when it runs;
+    /// inside a method that finds the receiver's field.
+    fn load_local(&mut self, name: &str) {
+        match self.slot_of(name) {
+            Some(slot) => self.bytecode.push(ByteCode::LOADSLOT(slot)),
+            None => self.bytecode.push(ByteCode::GETVARLOCAL(name.to_string())),
         }
     }
 
+    /// Pops the value on top of the stack into the local `name`, see `load_local`.
+    fn store_local(&mut self, name: &str) {
+        match self.slot_of(name) {
+            Some(slot) => self.bytecode.push(ByteCode::STORESLOT(slot)),
+            None => self.bytecode.push(ByteCode::SAVEVARLOCAL(name.to_string())),
+        }
+    }
+
+    /// Ends the scope of the local `name`.
+    fn drop_local(&mut self, name: &str) {
+        match self.slot_of(name) {
+            Some(slot) => self.bytecode.push(ByteCode::DROPSLOT(slot)),
+            None => self.bytecode.push(ByteCode::DROPVAR(name.to_string())),
+        }
+    }
+
+    fn add_label<S: Into<String>>(&mut self, name: S, index: usize) -> Result<()> {
+        let name = name.into();
+        if self.labels.insert(name.clone(), index).is_some() {
+            bail!("Duplicate label '{}' in compiled code", name);
+        }
+        Ok(())
+    }
+
     pub fn get_code(&self) -> Vec<ByteCode> {
         self.bytecode.clone()
     }
 
-    pub fn get_labels(&self) -> Vec<(String, usize)> {
+    pub fn get_labels(&self) -> HashMap<String, usize> {
         self.labels.clone()
     }
 
     pub fn get_symbols(&self) -> HashMap<String, Symbol> {
         self.symbol_table.clone()
     }
-    ///>
+
+    /// Snapshot of everything compiled so far, for a VM that keeps running as code is appended.
+    pub fn code(&self) -> Code {
+        self.clone().into()
+    }
 
     pub fn string_representation(&self) -> String {
-        let mut ret = String::new();
-        for byte in self.bytecode.iter().enumerate() {
-            for label in self.labels.iter() {
-                if label.1 == byte.0 {
-                    ret.push_str(&format!("{} | {}:\n", label.1, label.0));
-                }
+        listing(
+            &self.bytecode,
+            &self.labels,
+            &self.functions,
+            &self.constant_pool,
+        )
+    }
+
+    /// Attributes the instructions from `start` on that have no location yet to `span`.
+    fn locate(&mut self, start: usize, span: &Span) {
+        self.locations.resize(self.bytecode.len(), None);
+        if span.line == 0 {
+            return;
+        }
+        let name = match (&span.file, self.sources.first()) {
+            (Some(file), _) => file.to_string(),
+            (None, Some((file, _))) => file.clone(),
+            (None, None) => "<source>".to_string(),
+        };
+        let file = match self.files.iter().position(|known| *known == name) {
+            Some(file) => file,
+            None => {
+                self.files.push(name);
+                self.files.len() - 1
             }
-            ret.push_str(&format!(
-                "{} | {}:\n",
-                byte.0,
-                serde_json::to_string(&byte.1).unwrap()
-            ));
+        };
+        for location in self.locations[start..].iter_mut() {
+            location.get_or_insert(SourceLocation {
+                file,
+                line: span.line,
+                col: span.col,
+            });
         }
-        ret
     }
 
     fn get_next_label(&mut self) -> String {
@@ -171,141 +940,877 @@ impl Compiler {
 
     fn compile_body(
         &mut self,
-        stmts: &Vec<Statement>,
+        stmts: &Block,
         additional_variables_to_drop_on_scope_end: Option<&mut Vec<String>>,
-    ) {
+    ) -> Result<()> {
         let drop_vars = additional_variables_to_drop_on_scope_end.is_none();
+        let scope = self.enter_scope();
         let mut vars = Vec::new();
         let vars_to_drop = additional_variables_to_drop_on_scope_end.unwrap_or(&mut vars);
         for stmt in stmts {
             let mut ret: Vec<String> = Vec::new();
-            self.compile_statement(stmt, &mut ret);
+            let start = self.bytecode.len();
+            self.compile_statement(&stmt.node, &stmt.span, &mut ret)?;
+            self.locate(start, &stmt.span);
             vars_to_drop.extend(ret);
         }
+        // Otherwise the caller drops them, and ends the scope after.
         if drop_vars {
             for var_to_drop in vars_to_drop {
-                self.bytecode.push(ByteCode::DROPVAR(var_to_drop.clone()));
+                self.drop_local(var_to_drop);
             }
+            self.leave_scope(scope);
         }
+        Ok(())
     }
 
-    fn compile_statement(&mut self, stmt: &Statement, out: &mut Vec<String>) {
+    /// Compiles `stmt`, which was parsed from `span`.
+    fn compile_statement(
+        &mut self,
+        stmt: &Statement,
+        span: &Span,
+        out: &mut Vec<String>,
+    ) -> Result<()> {
         match stmt {
             Statement::Declare(name, typedata, expr) => {
-                self.compile_expression(expr);
-                self.bytecode
-                    .push(ByteCode::DEFVAR(name.clone(), typedata.to_owned()));
+                match expr {
+                    Some(expr) => self.compile_expression(expr)?,
+                    // The typechecker makes sure it's assigned before it's read.
+                    None => self.push_constant(StaticValue::Null),
+                }
+                self.define_local(name, typedata.to_owned())?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.locals.push(name.clone());
+                }
+                self.scope_locals.push(name.clone());
                 out.push(name.clone());
             }
+            Statement::Destructure(names, expr) => {
+                let tuple = format!("$tuple{}", self.get_next_label());
+                self.compile_expression(expr)?;
+                self.define_local(&tuple, Type::Null)?;
+                for (i, name) in names.iter().enumerate().filter(|(_, n)| *n != "_") {
+                    self.push_constant(StaticValue::Integer(i as i64));
+                    self.load_local(&tuple);
+                    self.bytecode.push(ByteCode::GETFROMREF);
+                    self.define_local(name, Type::Null)?;
+                    if let Some(innermost) = self.loops.last_mut() {
+                        innermost.locals.push(name.clone());
+                    }
+                    self.scope_locals.push(name.clone());
+                    out.push(name.clone());
+                }
+                self.drop_local(&tuple);
+            }
             Statement::If(expr, block, els) => {
-                self.compile_expression(expr);
+                self.compile_expression(expr)?;
                 self.bytecode.push(ByteCode::NEGATE);
                 let lbl = self.get_next_label();
                 self.bytecode.push(ByteCode::JITL(lbl.clone()));
-                self.compile_body(block, None);
+                self.compile_body(block, None)?;
                 if els.is_none() {
-                    self.labels.push((lbl, self.bytecode.len()));
+                    self.add_label(lbl, self.bytecode.len())?;
                 } else {
-                    self.labels.push((lbl, self.bytecode.len() + 1));
+                    self.add_label(lbl, self.bytecode.len() + 1)?;
                     let lbl2 = self.get_next_label();
                     self.bytecode.push(ByteCode::JMPTO(lbl2.clone()));
-                    self.compile_body(els.as_ref().unwrap(), None);
-                    self.labels.push((lbl2, self.bytecode.len()));
+                    self.compile_body(els.as_ref().unwrap(), None)?;
+                    self.add_label(lbl2, self.bytecode.len())?;
                 }
             }
             Statement::For(stmt, expr, stmt2, body) => {
+                let scope = self.enter_scope();
                 let mut vars = Vec::new();
-                self.compile_statement(&stmt, &mut vars);
+                self.compile_statement(stmt, span, &mut vars)?;
                 let idx = self.bytecode.len();
-                self.compile_expression(&expr);
+                self.compile_expression(expr)?;
                 self.bytecode.push(ByteCode::NEGATE);
                 let lbl1 = self.get_next_label();
                 self.bytecode.push(ByteCode::JITL(lbl1.clone()));
+                let continue_lbl = self.get_next_label();
+                self.loops.push(LoopLabels {
+                    break_label: lbl1.clone(),
+                    continue_label: continue_lbl.clone(),
+                    locals: Vec::new(),
+                    handlers: 0,
+                });
                 if body.is_some() {
-                    self.compile_body(body.as_ref().unwrap(), Some(&mut vars));
+                    self.compile_body(body.as_ref().unwrap(), Some(&mut vars))?;
                 }
-                self.compile_statement(stmt2, out);
+                self.loops.pop();
+                self.add_label(continue_lbl, self.bytecode.len())?;
+                self.compile_statement(stmt2, span, out)?;
 
                 let lbl2 = self.get_next_label();
                 self.bytecode.push(ByteCode::JMPTO(lbl2.clone()));
-                self.labels.push((lbl1, self.bytecode.len()));
+                self.add_label(lbl1, self.bytecode.len())?;
                 for var_to_drop in vars {
-                    self.bytecode.push(ByteCode::DROPVAR(var_to_drop));
+                    self.drop_local(&var_to_drop);
                 }
-                self.labels.push((lbl2, idx));
+                self.leave_scope(scope);
+                self.add_label(lbl2, idx)?;
             }
-            Statement::Return(op_expr) => {
-                if op_expr.is_some() {
-                    self.compile_expression(op_expr.as_ref().unwrap());
-                    self.bytecode.push(ByteCode::RET(true));
-                } else {
-                    self.bytecode.push(ByteCode::RET(false));
+            Statement::While(expr, body) => {
+                let start = self.bytecode.len();
+                self.compile_expression(expr)?;
+                self.bytecode.push(ByteCode::NEGATE);
+                let end_lbl = self.get_next_label();
+                self.bytecode.push(ByteCode::JITL(end_lbl.clone()));
+                let start_lbl = self.get_next_label();
+                self.loops.push(LoopLabels {
+                    break_label: end_lbl.clone(),
+                    continue_label: start_lbl.clone(),
+                    locals: Vec::new(),
+                    handlers: 0,
+                });
+                // Body locals are dropped every iteration so the next DEFVAR starts fresh.
+                self.compile_body(body, None)?;
+                self.loops.pop();
+                self.bytecode.push(ByteCode::JMPTO(start_lbl.clone()));
+                self.add_label(start_lbl, start)?;
+                self.add_label(end_lbl, self.bytecode.len())?;
+            }
+            Statement::Break | Statement::Continue => {
+                let innermost = self
+                    .loops
+                    .last()
+                    .ok_or_else(|| anyhow!("'{}' outside of a loop", stmt))?;
+                let (locals, handlers) = (innermost.locals.clone(), innermost.handlers);
+                let target = match stmt {
+                    Statement::Break => innermost.break_label.clone(),
+                    _ => innermost.continue_label.clone(),
+                };
+                for local in locals.iter() {
+                    self.drop_local(local);
+                }
+                for _ in 0..handlers {
+                    self.bytecode.push(ByteCode::POPEH);
+                }
+                self.bytecode.push(ByteCode::JMPTO(target));
+            }
+            Statement::Throw(expr) => {
+                self.compile_expression(expr)?;
+                self.bytecode.push(ByteCode::THROW);
+            }
+            Statement::Try(body, name, handler) => {
+                let catch_lbl = self.get_next_label();
+                let end_lbl = self.get_next_label();
+                self.bytecode.push(ByteCode::PUSHEH(catch_lbl.clone()));
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.handlers += 1;
+                }
+                self.compile_body(body, None)?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.handlers -= 1;
+                }
+                self.bytecode.push(ByteCode::POPEH);
+                self.bytecode.push(ByteCode::JMPTO(end_lbl.clone()));
+                // The VM removes the handler and pushes the error message before jumping here.
+                self.add_label(catch_lbl, self.bytecode.len())?;
+                let scope = self.enter_scope();
+                self.define_local(name, STRING_TYPE)?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.locals.push(name.clone());
+                }
+                self.scope_locals.push(name.clone());
+                let mut vars = vec![name.clone()];
+                self.compile_body(handler, Some(&mut vars))?;
+                for var_to_drop in vars {
+                    self.drop_local(&var_to_drop);
+                }
+                self.leave_scope(scope);
+                self.add_label(end_lbl, self.bytecode.len())?;
+            }
+            Statement::Match(subject, arms) => {
+                // Tests the arms in order, each reading the value back from a hidden
+                // local. Dense integer arms could become a jump table later on.
+                let end_lbl = self.get_next_label();
+                let value = format!("$match{}", end_lbl);
+                self.compile_expression(subject)?;
+                self.define_local(&value, Type::Null)?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.locals.push(value.clone());
+                }
+                for (pattern, body) in arms {
+                    let next_lbl = self.get_next_label();
+                    if self.compile_pattern_test(&value, pattern)? {
+                        self.bytecode.push(ByteCode::NEGATE);
+                        self.bytecode.push(ByteCode::JITL(next_lbl.clone()));
+                    }
+                    let scope = self.enter_scope();
+                    let mut vars = Vec::new();
+                    if let Pattern::Variant(_, bindings) = pattern {
+                        for (i, binding) in bindings.iter().enumerate() {
+                            if binding == "_" {
+                                continue;
+                            }
+                            self.push_constant(StaticValue::Integer(i as i64));
+                            self.load_local(&value);
+                            self.bytecode.push(ByteCode::GETFROMREF);
+                            self.define_local(binding, Type::Null)?;
+                            if let Some(innermost) = self.loops.last_mut() {
+                                innermost.locals.push(binding.clone());
+                            }
+                            self.scope_locals.push(binding.clone());
+                            vars.push(binding.clone());
+                        }
+                    }
+                    self.compile_body(body, Some(&mut vars))?;
+                    for var_to_drop in vars {
+                        self.drop_local(&var_to_drop);
+                    }
+                    self.leave_scope(scope);
+                    self.bytecode.push(ByteCode::JMPTO(end_lbl.clone()));
+                    self.add_label(next_lbl, self.bytecode.len())?;
+                }
+                self.add_label(end_lbl, self.bytecode.len())?;
+                self.drop_local(&value);
+            }
+            Statement::Function(func) => {
+                self.compile_nested_function(func)?;
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.locals.push(func.name.clone());
                 }
+                self.scope_locals.push(func.name.clone());
+                out.push(func.name.clone());
+            }
+            Statement::Return(op_expr) => match op_expr {
+                // `RET` drops whatever a function returning `null` was given to return.
+                Some(expr) => {
+                    self.compile_expression(expr)?;
+                    self.bytecode.push(ByteCode::RET(self.returns_value));
+                }
+                None => self.return_null(),
+            },
+            Statement::Set(Some(object), member, expr) => {
+                self.push_constant(StaticValue::String(member.clone()));
+                self.compile_expression(object)?;
+                self.compile_expression(expr)?;
+                self.bytecode.push(ByteCode::SAVETOREF);
+            }
+            Statement::SetIndex(collection, index, expr) => {
+                self.compile_expression(index)?;
+                self.compile_expression(collection)?;
+                self.compile_expression(expr)?;
+                self.bytecode.push(ByteCode::SAVETOREF);
+            }
+            Statement::Compound(target, op, expr) => {
+                // Loads the target again to compute the new value, then stores it.
+                let value = Expression::Binary(
+                    op.clone(),
+                    Box::new(target.clone()),
+                    Box::new(expr.clone()),
+                );
+                let store = match target {
+                    Expression::Literal(TokenLiteral::Identifier(name)) => {
+                        Statement::Set(None, name.clone(), value)
+                    }
+                    Expression::Get(member, object) => {
+                        Statement::Set(Some(*object.clone()), member.clone(), value)
+                    }
+                    Expression::Index(collection, index) => {
+                        Statement::SetIndex(*collection.clone(), *index.clone(), value)
+                    }
+                    other => bail!("Cannot assign to '{}'", other),
+                };
+                self.compile_statement(&store, span, out)?;
+            }
+            Statement::Set(None, variable, expr) if self.is_global(variable) => {
+                self.compile_expression(expr)?;
+                self.bytecode
+                    .push(ByteCode::SAVEVARGLOBAL(variable.clone()));
             }
-            Statement::Set(on,variable, expr) => {
-                self.compile_expression(expr);
-                self.bytecode.push(ByteCode::SAVEVARLOCAL(variable.clone()));
+            Statement::Set(None, variable, expr) => {
+                self.compile_expression(expr)?;
+                self.store_local(variable);
             }
             Statement::Expression(expr) => {
-                self.compile_expression(expr);
+                self.compile_expression(expr)?;
+                if self.unused_values.contains(span) {
+                    self.bytecode.push(ByteCode::POP);
+                }
             }
             Statement::Print(expr) => {
-                self.compile_expression(expr);
-                //self.bytecode.push(ByteCode::SHOW);
+                self.compile_expression(&Expression::Call("print".into(), vec![expr.clone()]))?;
             }
             Statement::Null => {}
         }
+        Ok(())
     }
 
-    fn compile_expression(&mut self, expr: &Expression) {
-        match expr {
-            Expression::Get(literal, expr) => {
-                self.compile_expression(&expr);
+    /// Pushes whether the local `value` fits `pattern`; a `_` always does, so it
+    /// pushes nothing and returns false.
+    fn compile_pattern_test(&mut self, value: &str, pattern: &Pattern) -> Result<bool> {
+        let local = || Box::new(Expression::Literal(TokenLiteral::Identifier(value.into())));
+        let literal =
+            |v: &StaticValue| Box::new(Expression::Literal(TokenLiteral::Value(v.clone())));
+        let test = match pattern {
+            Pattern::Literal(v) => Expression::Binary(BinOpCode::EQ, local(), literal(v)),
+            Pattern::Range(start, end) => {
+                // Same bounds as the range expression: chars include the end.
+                let upper = match end {
+                    StaticValue::Char(_) => BinOpCode::LE,
+                    _ => BinOpCode::LT,
+                };
+                Expression::Binary(
+                    BinOpCode::AND,
+                    Box::new(Expression::Binary(BinOpCode::GE, local(), literal(start))),
+                    Box::new(Expression::Binary(upper, local(), literal(end))),
+                )
+            }
+            Pattern::Type(ty) => {
+                self.load_local(value);
+                self.bytecode.push(ByteCode::ISTYPE(ty.erase_type_args()));
+                return Ok(true);
+            }
+            Pattern::Variant(name, _) => {
+                self.load_local(value);
+                self.bytecode.push(ByteCode::ISVARIANT(name.clone()));
+                return Ok(true);
+            }
+            Pattern::Wildcard => return Ok(false),
+        };
+        self.compile_expression(&test)?;
+        Ok(true)
+    }
 
-                self.bytecode.push(ByteCode::GETVARLOCAL(literal.clone()));
+    fn compile_expression(&mut self, expr: &Expression) -> Result<()> {
+        match expr {
+            Expression::Get(member, object) => {
+                self.push_constant(StaticValue::String(member.clone()));
+                self.compile_expression(object)?;
                 self.bytecode.push(ByteCode::GETFROMREF);
             }
-            Expression::Literal(literal) => match literal {
-                TokenLiteral::Identifier(ident) => {
-                    self.bytecode.push(ByteCode::GETVARLOCAL(ident.clone()));
+            Expression::Index(collection, index) => {
+                self.compile_expression(index)?;
+                self.compile_expression(collection)?;
+                self.bytecode.push(ByteCode::GETFROMREF);
+            }
+            Expression::Range(start, end) => {
+                self.compile_expression(&Expression::range_elements(start, end))?;
+            }
+            Expression::Slice(collection, start, end) => {
+                for bound in [end, start] {
+                    match bound {
+                        Some(bound) => self.compile_expression(bound)?,
+                        None => self.push_constant(StaticValue::Null),
+                    }
+                }
+                self.compile_expression(collection)?;
+                self.bytecode.push(ByteCode::SLICE);
+            }
+            Expression::Literal(TokenLiteral::Identifier(ident))
+                if self.enum_variants.contains_key(ident) =>
+            {
+                self.bytecode.push(ByteCode::VARIANT(ident.clone(), 0))
+            }
+            Expression::Literal(TokenLiteral::Identifier(ident)) if self.is_global(ident) => {
+                match self.constants.get(ident) {
+                    Some(value) => self.push_constant(value.clone()),
+                    None => self.bytecode.push(ByteCode::GETVARGLOBAL(ident.clone())),
                 }
-                TokenLiteral::Value(v) => self.bytecode.push(ByteCode::PUSH(v.clone())),
+            }
+            Expression::Literal(literal) => match literal {
+                TokenLiteral::Identifier(ident) => match self.function_types.get(ident) {
+                    // A top-level function used as a value, unless a local shadows it.
+                    Some(ty) if !self.scope_locals.contains(ident) => self.bytecode.push(
+                        ByteCode::CLOSURE(ident.clone(), ty.erase_type_args(), Vec::new()),
+                    ),
+                    _ => match inbuilt_constant(ident) {
+                        Some(value) if !self.scope_locals.contains(ident) => {
+                            self.push_constant(value)
+                        }
+                        _ => self.load_local(ident),
+                    },
+                },
+                TokenLiteral::Value(v) => self.push_constant(v.clone()),
             },
+            Expression::Binary(op @ (BinOpCode::AND | BinOpCode::OR), lhs, rhs) => {
+                // The left operand decides alone when it's false for `and` or true for
+                // `or`; it stays on the stack as the result and the right one is skipped.
+                let end = self.get_next_label();
+                self.compile_expression(lhs)?;
+                self.bytecode.push(ByteCode::DUP);
+                if *op == BinOpCode::AND {
+                    self.bytecode.push(ByteCode::NOT);
+                }
+                self.bytecode.push(ByteCode::JITL(end.clone()));
+                self.bytecode.push(ByteCode::POP);
+                self.compile_expression(rhs)?;
+                self.add_label(end, self.bytecode.len())?;
+            }
             Expression::Binary(op, lhs, rhs) => {
-                self.compile_expression(rhs);
-                self.compile_expression(lhs);
+                // The left operand ends up on top, so the VM pops it first.
+                self.compile_expression(rhs)?;
+                self.compile_expression(lhs)?;
 
                 match op {
                     BinOpCode::ADD => self.bytecode.push(ByteCode::ADD),
                     BinOpCode::SUB => self.bytecode.push(ByteCode::SUB),
                     BinOpCode::DIV => self.bytecode.push(ByteCode::DIV),
                     BinOpCode::MULT => self.bytecode.push(ByteCode::MULT),
+                    BinOpCode::MOD => self.bytecode.push(ByteCode::MOD),
                     BinOpCode::EQ => self.bytecode.push(ByteCode::EQUALS),
+                    BinOpCode::NE => {
+                        self.bytecode.push(ByteCode::EQUALS);
+                        self.bytecode.push(ByteCode::NOT);
+                    }
                     BinOpCode::GE => self.bytecode.push(ByteCode::EQGREAT),
                     BinOpCode::LE => self.bytecode.push(ByteCode::EQLESS),
                     BinOpCode::LT => self.bytecode.push(ByteCode::LESSER),
                     BinOpCode::GT => self.bytecode.push(ByteCode::GREATER),
-                    _ => {}
+                    BinOpCode::XOR => self.bytecode.push(ByteCode::XOR),
+                    BinOpCode::BAND => self.bytecode.push(ByteCode::BAND),
+                    BinOpCode::BOR => self.bytecode.push(ByteCode::BOR),
+                    BinOpCode::BXOR => self.bytecode.push(ByteCode::BXOR),
+                    BinOpCode::SHL => self.bytecode.push(ByteCode::SHL),
+                    BinOpCode::SHR => self.bytecode.push(ByteCode::SHR),
+                    BinOpCode::AND | BinOpCode::OR => unreachable!("compiled to jumps above"),
                 }
             }
             Expression::Call(func, args) => {
+                let arranged;
+                let mut hidden = Vec::new();
+                let args = match self.function_params.get(func).cloned() {
+                    Some(params) if !self.scope_locals.contains(func) => {
+                        let args = self.hoist_named_arguments(args, &mut hidden)?;
+                        arranged = self.arranged_arguments(&params, &args)?;
+                        &arranged
+                    }
+                    _ => args,
+                };
                 for arg in args {
-                    self.compile_expression(arg);
+                    self.compile_expression(arg)?;
+                }
+                for local in &hidden {
+                    self.drop_local(local);
+                }
+                if self.enum_variants.contains_key(func) {
+                    self.bytecode
+                        .push(ByteCode::VARIANT(func.clone(), args.len()));
+                    return Ok(());
                 }
+                // Locals hold function values; user functions are bound to their table
+                // slot now; anything else is an inbuilt.
+                if self.scope_locals.contains(func) {
+                    self.load_local(func);
+                    self.bytecode
+                        .push(ByteCode::CALLINDIRECT(args.len() as u64));
+                    return Ok(());
+                }
+                match self.function_indices.get(func) {
+                    Some(index) => self
+                        .bytecode
+                        .push(ByteCode::CALLFN(*index, args.len() as u64)),
+                    None => self
+                        .bytecode
+                        .push(ByteCode::CALL(func.clone(), args.len() as u64)),
+                }
+            }
+            Expression::CallIndirect(callee, args) => {
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.compile_expression(callee)?;
                 self.bytecode
-                    .push(ByteCode::CALL(func.clone(), args.len() as u64));
+                    .push(ByteCode::CALLINDIRECT(args.len() as u64));
+            }
+            Expression::Lambda(params, return_type, body) => {
+                self.compile_lambda(params, return_type, body)?
             }
             Expression::Unary(op, expr) => {
-                self.compile_expression(expr);
+                self.compile_expression(expr)?;
                 match op {
                     crate::frontend::ast::UnaryOpCode::NEG => self.bytecode.push(ByteCode::NEGATE),
                     crate::frontend::ast::UnaryOpCode::NOT => self.bytecode.push(ByteCode::NOT),
+                    crate::frontend::ast::UnaryOpCode::BNOT => self.bytecode.push(ByteCode::BNOT),
                 }
             }
             Expression::Grouping(group) => {
-                self.compile_expression(&group);
+                self.compile_expression(group)?;
+            }
+            Expression::MethodCall(receiver, method, args) => {
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.compile_expression(receiver)?;
+                self.bytecode
+                    .push(ByteCode::CALLVIRT(method.clone(), args.len() as u64));
+            }
+            Expression::Instance(ty, args) => {
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.bytecode
+                    .push(ByteCode::INSTANCE(ty.erase_type_args(), args.len()));
+            }
+            Expression::SuperCall(method, args) => {
+                let parent = self
+                    .current_class
+                    .as_ref()
+                    .and_then(|class| self.class_parents.get(class))
+                    .cloned()
+                    .ok_or_else(|| anyhow!("'super' outside a class that extends another"))?;
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.bytecode.push(ByteCode::CALLSUPER(
+                    parent,
+                    method.clone(),
+                    args.len() as u64,
+                ));
+            }
+            // Literals leave their element types unknown for `INSTANCE` to read off
+            // the values.
+            Expression::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.bytecode.push(ByteCode::INSTANCE(
+                    Type::array(Type::Unknown),
+                    elements.len(),
+                ));
+            }
+            Expression::MapLiteral(entries) => {
+                for (key, value) in entries {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.bytecode.push(ByteCode::INSTANCE(
+                    Type::map(Type::Unknown, Type::Unknown),
+                    entries.len() * 2,
+                ));
+            }
+            Expression::TupleLiteral(elements) => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                let ty = Composite::Tuple(vec![Type::Unknown; elements.len()]);
+                self.bytecode.push(ByteCode::INSTANCE(
+                    Type::Composite(Box::new(ty)),
+                    elements.len(),
+                ));
+            }
+            Expression::TupleIndex(tuple, index) => {
+                self.push_constant(StaticValue::Integer(*index as i64));
+                self.compile_expression(tuple)?;
+                self.bytecode.push(ByteCode::GETFROMREF);
+            }
+            Expression::Is(value, ty) => {
+                self.compile_expression(value)?;
+                self.bytecode.push(ByteCode::ISTYPE(ty.erase_type_args()));
+            }
+            Expression::Coalesce(value, fallback) => {
+                // Like `or`: a non-null value stays on the stack and skips the fallback.
+                let end = self.get_next_label();
+                self.compile_expression(value)?;
+                self.bytecode.push(ByteCode::DUP);
+                self.bytecode.push(ByteCode::ISTYPE(Type::Null));
+                self.bytecode.push(ByteCode::NOT);
+                self.bytecode.push(ByteCode::JITL(end.clone()));
+                self.bytecode.push(ByteCode::POP);
+                self.compile_expression(fallback)?;
+                self.add_label(end, self.bytecode.len())?;
+            }
+            Expression::NamedArgument(name, _) => {
+                let message = format!("Named argument '{}' outside a call", name);
+                return Err(CompileError::Internal(message).into());
+            }
+            Expression::Conditional(condition, then, otherwise) => {
+                let otherwise_lbl = self.get_next_label();
+                let end = self.get_next_label();
+                self.compile_expression(condition)?;
+                self.bytecode.push(ByteCode::NOT);
+                self.bytecode.push(ByteCode::JITL(otherwise_lbl.clone()));
+                self.compile_expression(then)?;
+                self.bytecode.push(ByteCode::JMPTO(end.clone()));
+                self.add_label(otherwise_lbl, self.bytecode.len())?;
+                self.compile_expression(otherwise)?;
+                self.add_label(end, self.bytecode.len())?;
+            }
+            Expression::SafeAccess(access) => {
+                // The object is kept in a hidden local, tested, then read back by the
+                // access itself, which is skipped for a null.
+                let end = self.get_next_label();
+                let null_lbl = self.get_next_label();
+                let local = format!("$safe{}", end);
+                let hidden = Box::new(Expression::Literal(TokenLiteral::Identifier(local.clone())));
+                let (object, access) = match &**access {
+                    Expression::Get(member, object) => {
+                        (object, Expression::Get(member.clone(), hidden))
+                    }
+                    Expression::MethodCall(receiver, method, args) => (
+                        receiver,
+                        Expression::MethodCall(hidden, method.clone(), args.clone()),
+                    ),
+                    other => bail!("'?.' cannot be applied to {}", other),
+                };
+                self.compile_expression(object)?;
+                self.define_local(&local, Type::Null)?;
+                self.load_local(&local);
+                self.bytecode.push(ByteCode::ISTYPE(Type::Null));
+                self.bytecode.push(ByteCode::JITL(null_lbl.clone()));
+                self.compile_expression(&access)?;
+                self.bytecode.push(ByteCode::JMPTO(end.clone()));
+                self.add_label(null_lbl, self.bytecode.len())?;
+                self.push_constant(StaticValue::Null);
+                self.add_label(end, self.bytecode.len())?;
+                self.drop_local(&local);
             }
-            Expression::Instance(_t, _expressionss) => {}
         }
+        Ok(())
+    }
+
+    /// Emits the lambda's body out of line, jumped over, then a `CLOSURE` capturing
+    /// the enclosing locals it mentions. Captures are copied when the closure is made
+    /// and passed to the body as hidden arguments ahead of the declared ones.
+    fn compile_lambda(
+        &mut self,
+        params: &[Parameter],
+        return_type: &Type,
+        body: &Expression,
+    ) -> Result<()> {
+        let mut mentioned = HashSet::new();
+        mentioned_names(body, &mut mentioned);
+        let captures = self.captures(&mentioned, params);
+        let label = format!("<lambda>{}", self.get_next_label());
+        self.compile_closure(label, params, return_type, captures, |compiler| {
+            compiler.compile_expression(body)?;
+            compiler
+                .bytecode
+                .push(ByteCode::RET(compiler.returns_value));
+            Ok(())
+        })
+    }
+
+    /// Leaves a closure of the nested function `func` in a new local of its name,
+    /// like a lambda's. When it calls itself, its body starts by making the closure
+    /// again from its own captures.
+    fn compile_nested_function(&mut self, func: &Function) -> Result<()> {
+        let mut mentioned = HashSet::new();
+        for stmt in func.body.iter() {
+            mentioned_in_statement(&stmt.node, &mut mentioned);
+        }
+        let mut captures = self.captures(&mentioned, &func.params);
+        captures.retain(|name| *name != func.name);
+        let label = format!("<func {}>{}", func.name, self.get_next_label());
+        let ty = Type::function(
+            func.params.iter().map(|p| p.ty.clone()).collect(),
+            func.return_type.clone(),
+        );
+        let recursive = mentioned.contains(&func.name);
+        let closure = ByteCode::CLOSURE(label.clone(), ty.erase_type_args(), captures.clone());
+        self.compile_closure(
+            label,
+            &func.params,
+            &func.return_type,
+            captures.clone(),
+            |compiler| {
+                if recursive {
+                    for name in captures.iter() {
+                        compiler.load_local(name);
+                    }
+                    compiler.bytecode.push(closure);
+                    compiler.define_local(&func.name, ty.clone())?;
+                    compiler.scope_locals.push(func.name.clone());
+                }
+                compiler.compile_body(&func.body, Some(&mut Vec::new()))?;
+                compiler.return_at_end(&func.body);
+                Ok(())
+            },
+        )?;
+        self.define_local(&func.name, ty)
+    }
+
+    /// The enclosing locals among `mentioned` that a closure with `params` captures,
+    /// sorted by name.
+    fn captures(&self, mentioned: &HashSet<String>, params: &[Parameter]) -> Vec<String> {
+        let mut captures: Vec<String> = self
+            .scope_locals
+            .iter()
+            .filter(|name| mentioned.contains(*name) && !params.iter().any(|p| p.name == **name))
+            .cloned()
+            .collect();
+        captures.sort();
+        captures.dedup();
+        captures
+    }
+
+    /// Emits a function body out of line, jumped over, then a `CLOSURE` of it with
+    /// `captures`, which the body receives as hidden arguments ahead of `params`.
+    fn compile_closure(
+        &mut self,
+        label: String,
+        params: &[Parameter],
+        return_type: &Type,
+        captures: Vec<String>,
+        body: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let after = self.get_next_label();
+        self.bytecode.push(ByteCode::JMPTO(after.clone()));
+        self.add_label(label.clone(), self.bytecode.len())?;
+        let mut arguments: Vec<Parameter> = captures
+            .iter()
+            .map(|name| Parameter::new(name.clone(), Type::Null))
+            .collect();
+        arguments.extend(params.iter().cloned());
+        let enclosing = std::mem::take(&mut self.scope_locals);
+        let enclosing_slots = std::mem::take(&mut self.local_slots);
+        let enclosing_visible = std::mem::take(&mut self.visible_slots);
+        let named_locals = std::mem::replace(&mut self.named_locals, false);
+        let loops = std::mem::take(&mut self.loops);
+        let returns_value = self.returns_value;
+        let compiled = self
+            .enter_function(&arguments, return_type)
+            .and_then(|_| body(self));
+        self.leave_function(label.clone());
+        self.scope_locals = enclosing;
+        self.local_slots = enclosing_slots;
+        self.visible_slots = enclosing_visible;
+        self.named_locals = named_locals;
+        self.loops = loops;
+        self.returns_value = returns_value;
+        compiled?;
+        self.add_label(after, self.bytecode.len())?;
+        for name in captures.iter() {
+            self.load_local(name);
+        }
+
+        let ty = Type::function(
+            params.iter().map(|p| p.ty.clone()).collect(),
+            return_type.clone(),
+        );
+        self.bytecode
+            .push(ByteCode::CLOSURE(label, ty.erase_type_args(), captures));
+        Ok(())
+    }
+}
+
+/// Every name `expr` may read as a variable, including inside nested lambdas.
+pub(crate) fn mentioned_names(expr: &Expression, out: &mut HashSet<String>) {
+    match expr {
+        Expression::Literal(TokenLiteral::Identifier(name)) => {
+            out.insert(name.clone());
+        }
+        Expression::Literal(TokenLiteral::Value(_)) => {}
+        Expression::Unary(_, inner) | Expression::Grouping(inner) => mentioned_names(inner, out),
+        Expression::Binary(_, lhs, rhs) => {
+            mentioned_names(lhs, out);
+            mentioned_names(rhs, out);
+        }
+        Expression::Call(name, args) => {
+            out.insert(name.clone());
+            args.iter().for_each(|arg| mentioned_names(arg, out));
+        }
+        Expression::CallIndirect(callee, args) | Expression::MethodCall(callee, _, args) => {
+            mentioned_names(callee, out);
+            args.iter().for_each(|arg| mentioned_names(arg, out));
+        }
+        // A nested lambda's parameters are its own, not variables around it.
+        Expression::Lambda(params, _, body) => {
+            let mut inner = HashSet::new();
+            mentioned_names(body, &mut inner);
+            inner.retain(|name| params.iter().all(|param| param.name != *name));
+            out.extend(inner);
+        }
+        Expression::Get(_, object) => mentioned_names(object, out),
+        Expression::Index(collection, index) => {
+            mentioned_names(collection, out);
+            mentioned_names(index, out);
+        }
+        Expression::Slice(collection, start, end) => {
+            mentioned_names(collection, out);
+            start
+                .iter()
+                .chain(end)
+                .for_each(|bound| mentioned_names(bound, out));
+        }
+        Expression::Instance(_, args) | Expression::SuperCall(_, args) => {
+            args.iter().for_each(|arg| mentioned_names(arg, out))
+        }
+        Expression::Coalesce(value, fallback) => {
+            mentioned_names(value, out);
+            mentioned_names(fallback, out);
+        }
+        Expression::NamedArgument(_, value) => mentioned_names(value, out),
+        Expression::Conditional(condition, then, otherwise) => {
+            mentioned_names(condition, out);
+            mentioned_names(then, out);
+            mentioned_names(otherwise, out);
+        }
+        Expression::SafeAccess(access) | Expression::Is(access, _) => mentioned_names(access, out),
+        Expression::TupleIndex(tuple, _) => mentioned_names(tuple, out),
+        Expression::Range(..) => {}
+        Expression::ArrayLiteral(elements) | Expression::TupleLiteral(elements) => elements
+            .iter()
+            .for_each(|element| mentioned_names(element, out)),
+        Expression::MapLiteral(entries) => entries.iter().for_each(|(key, value)| {
+            mentioned_names(key, out);
+            mentioned_names(value, out);
+        }),
+    }
+}
+
+/// Every name `stmt` may read as a variable, see `mentioned_names`.
+pub(crate) fn mentioned_in_statement(stmt: &Statement, out: &mut HashSet<String>) {
+    let block = |block: &Block, out: &mut HashSet<String>| {
+        block
+            .iter()
+            .for_each(|stmt| mentioned_in_statement(&stmt.node, out))
+    };
+    match stmt {
+        Statement::Declare(_, _, Some(expr))
+        | Statement::Destructure(_, expr)
+        | Statement::Expression(expr)
+        | Statement::Print(expr)
+        | Statement::Throw(expr)
+        | Statement::Return(Some(expr)) => mentioned_names(expr, out),
+        Statement::Set(object, name, expr) => {
+            match object {
+                Some(object) => mentioned_names(object, out),
+                None => {
+                    out.insert(name.clone());
+                }
+            }
+            mentioned_names(expr, out);
+        }
+        Statement::SetIndex(collection, index, expr) => {
+            mentioned_names(collection, out);
+            mentioned_names(index, out);
+            mentioned_names(expr, out);
+        }
+        Statement::Compound(target, _, expr) => {
+            mentioned_names(target, out);
+            mentioned_names(expr, out);
+        }
+        Statement::If(condition, then, otherwise) => {
+            mentioned_names(condition, out);
+            block(then, out);
+            otherwise.iter().for_each(|otherwise| block(otherwise, out));
+        }
+        Statement::For(init, condition, step, body) => {
+            mentioned_in_statement(init, out);
+            mentioned_names(condition, out);
+            mentioned_in_statement(step, out);
+            body.iter().for_each(|body| block(body, out));
+        }
+        Statement::While(condition, body) => {
+            mentioned_names(condition, out);
+            block(body, out);
+        }
+        Statement::Try(body, _, handler) => {
+            block(body, out);
+            block(handler, out);
+        }
+        Statement::Match(subject, arms) => {
+            mentioned_names(subject, out);
+            arms.iter().for_each(|(_, body)| block(body, out));
+        }
+        Statement::Function(func) => block(&func.body, out),
+        Statement::Declare(_, _, None)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Null => {}
     }
 }
@@ -0,0 +1,252 @@
+//! The `.abc` file `abra compile` writes and `execute` reads.
+//!
+//! A file starts with the magic bytes `ABRC`, the format version, the flags and the
+//! version of the compiler that wrote it, followed by sections. Each section has a
+//! four-byte name, its length and a CRC-32 of its contents, so a truncated or damaged
+//! file is rejected before any of it is decoded. All integers are little-endian.
+//!
+//! | section | contents                                                         |
+//! |---------|------------------------------------------------------------------|
+//! | `CODE`  | the [`Code`] without its debug info or symbols, bincode-encoded  |
+//! | `DBUG`  | line table, file names and local names, if not stripped          |
+//! | `SYMS`  | classes and functions defined, to link other code against        |
+
+use std::{collections::HashMap, fmt::Display};
+
+use thiserror::Error;
+
+use crate::compiler::{Code, SourceLocation, Symbol};
+
+pub const MAGIC: &[u8; 4] = b"ABRC";
+/// Version of the layout described above; files of any other version are rejected.
+pub const FORMAT_VERSION: u16 = 2;
+/// Flag set when the file has a `DBUG` section.
+pub const FLAG_DEBUG_INFO: u16 = 1;
+/// Flag set when the file has a `SYMS` section.
+pub const FLAG_SYMBOLS: u16 = 2;
+
+const CODE_SECTION: &[u8; 4] = b"CODE";
+const DEBUG_SECTION: &[u8; 4] = b"DBUG";
+const SYMBOLS_SECTION: &[u8; 4] = b"SYMS";
+
+/// Contents of the `DBUG` section.
+type DebugInfo = (
+    Vec<Option<SourceLocation>>,
+    Vec<String>,
+    HashMap<String, Vec<String>>,
+);
+
+#[derive(Debug, Clone, Error)]
+pub enum ContainerError {
+    #[error("not an Abra bytecode file: it doesn't start with \"ABRC\" (files written before the format was versioned have to be recompiled)")]
+    NotBytecode,
+    #[error("bytecode format version {found} isn't supported, this build reads version {FORMAT_VERSION}; recompile the program with compiler {}", env!("CARGO_PKG_VERSION"))]
+    UnsupportedVersion { found: u16 },
+    #[error("bytecode file ends in the middle of {0}")]
+    Truncated(&'static str),
+    #[error("section '{0}' of the bytecode file is corrupt: its checksum doesn't match")]
+    Corrupt(String),
+    #[error("bytecode file has no '{0}' section")]
+    MissingSection(String),
+    #[error("section '{0}' of the bytecode file can't be decoded: {1}")]
+    Decode(String, String),
+}
+
+/// What the start of a bytecode file says about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub format_version: u16,
+    pub flags: u16,
+    pub compiler_version: String,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub length: usize,
+    pub checksum: u32,
+    /// Whether the contents match the checksum.
+    pub intact: bool,
+}
+
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "format version:   {}", self.format_version)?;
+        writeln!(f, "compiler version: {}", self.compiler_version)?;
+        let names: Vec<&str> = [(FLAG_DEBUG_INFO, "debug-info"), (FLAG_SYMBOLS, "symbols")]
+            .into_iter()
+            .filter(|(flag, _)| self.flags & flag != 0)
+            .map(|(_, name)| name)
+            .collect();
+        let flags = if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        };
+        writeln!(f, "flags:            {:#06x} ({})", self.flags, flags)?;
+        writeln!(f, "sections:")?;
+        for section in self.sections.iter() {
+            writeln!(
+                f,
+                "  {} {:>10} bytes  crc32 {:08x}  {}",
+                section.name,
+                section.length,
+                section.checksum,
+                if section.intact { "ok" } else { "CORRUPT" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `code` as the contents of a bytecode file.
+pub fn encode(code: &Code) -> Vec<u8> {
+    let mut stripped = code.clone();
+    stripped.strip_debug_info();
+    stripped.symbols.clear();
+    let mut sections = vec![(CODE_SECTION, serialize(&stripped))];
+    let mut flags = 0;
+    if !code.locations.is_empty() || !code.local_names.is_empty() {
+        flags |= FLAG_DEBUG_INFO;
+        let debug_info = (&code.locations, &code.files, &code.local_names);
+        sections.push((DEBUG_SECTION, serialize(&debug_info)));
+    }
+    if !code.symbols.is_empty() {
+        flags |= FLAG_SYMBOLS;
+        sections.push((SYMBOLS_SECTION, serialize(&code.symbols)));
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(flags.to_le_bytes());
+    let compiler_version = env!("CARGO_PKG_VERSION").as_bytes();
+    bytes.push(compiler_version.len() as u8);
+    bytes.extend(compiler_version);
+    bytes.extend((sections.len() as u16).to_le_bytes());
+    for (name, contents) in sections {
+        bytes.extend(name);
+        bytes.extend((contents.len() as u32).to_le_bytes());
+        bytes.extend(crc32(&contents).to_le_bytes());
+        bytes.extend(contents);
+    }
+    bytes
+}
+
+fn serialize<T: serde::Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("compiled code always serializes")
+}
+
+/// The program in a bytecode file, after checking its version and checksums.
+pub fn decode(bytes: &[u8]) -> Result<Code, ContainerError> {
+    let (header, contents) = parse(bytes)?;
+    if let Some(section) = header.sections.iter().find(|section| !section.intact) {
+        return Err(ContainerError::Corrupt(section.name.clone()));
+    }
+    let section = |name: &[u8; 4]| {
+        let name = String::from_utf8_lossy(name).into_owned();
+        header
+            .sections
+            .iter()
+            .position(|section| section.name == name)
+            .map(|index| (name, contents[index]))
+    };
+    let (name, code) =
+        section(CODE_SECTION).ok_or_else(|| ContainerError::MissingSection("CODE".to_string()))?;
+    let mut code: Code =
+        bincode::deserialize(code).map_err(|e| ContainerError::Decode(name, e.to_string()))?;
+    if let Some((name, debug_info)) = section(DEBUG_SECTION) {
+        let (locations, files, local_names): DebugInfo = bincode::deserialize(debug_info)
+            .map_err(|e| ContainerError::Decode(name, e.to_string()))?;
+        code.locations = locations;
+        code.files = files;
+        code.local_names = local_names;
+    }
+    if let Some((name, symbols)) = section(SYMBOLS_SECTION) {
+        code.symbols = bincode::deserialize::<Vec<(String, Symbol)>>(symbols)
+            .map_err(|e| ContainerError::Decode(name, e.to_string()))?;
+    }
+    Ok(code)
+}
+
+/// The header of a bytecode file, with each section checked against its checksum.
+pub fn read_header(bytes: &[u8]) -> Result<Header, ContainerError> {
+    parse(bytes).map(|(header, _)| header)
+}
+
+fn parse(bytes: &[u8]) -> Result<(Header, Vec<&[u8]>), ContainerError> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len(), "the header").ok() != Some(MAGIC.as_slice()) {
+        return Err(ContainerError::NotBytecode);
+    }
+    let format_version = reader.u16("the header")?;
+    if format_version != FORMAT_VERSION {
+        return Err(ContainerError::UnsupportedVersion {
+            found: format_version,
+        });
+    }
+    let flags = reader.u16("the header")?;
+    let version_length = reader.take(1, "the header")?[0] as usize;
+    let compiler_version =
+        String::from_utf8_lossy(reader.take(version_length, "the header")?).into_owned();
+    let section_count = reader.u16("the header")?;
+    let mut sections = Vec::new();
+    let mut contents = Vec::new();
+    for _ in 0..section_count {
+        let name = String::from_utf8_lossy(reader.take(4, "a section header")?).into_owned();
+        let length = reader.u32("a section header")? as usize;
+        let checksum = reader.u32("a section header")?;
+        let section = reader.take(length, "a section")?;
+        sections.push(Section {
+            name,
+            length,
+            checksum,
+            intact: crc32(section) == checksum,
+        });
+        contents.push(section);
+    }
+    let header = Header {
+        format_version,
+        flags,
+        compiler_version,
+        sections,
+    };
+    Ok((header, contents))
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, count: usize, part: &'static str) -> Result<&'b [u8], ContainerError> {
+        if self.bytes.len() < count {
+            return Err(ContainerError::Truncated(part));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self, part: &'static str) -> Result<u16, ContainerError> {
+        let bytes = self.take(2, part)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, part: &'static str) -> Result<u32, ContainerError> {
+        let bytes = self.take(4, part)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// CRC-32 (IEEE), as zip and PNG use.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
@@ -0,0 +1,230 @@
+//! Linking: merges separately compiled [`Code`] units into one program, as
+//! `abra link` does.
+//!
+//! Each unit's instructions are appended after the ones before it, with absolute
+//! jumps, symbol addresses and the line table moved along, and its function, constant
+//! and file tables merged into the linked ones. Functions and classes are shared by
+//! name, so a unit can call what another defines, and each may be defined only once;
+//! labels only a unit's own code jumps to are renamed where they clash. The start code
+//! of every unit runs in turn, setting its globals, and the one defining `main` runs
+//! last, calling it.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::compiler::{ByteCode, Code, Operand, Symbol};
+use crate::runtime::inbuilt::generate_inbuilt_function_hashmap;
+use crate::runtime::value::StaticValue;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LinkError {
+    #[error("there is nothing to link")]
+    NoUnits,
+    #[error("'{0}' is defined by more than one of the linked units")]
+    DuplicateSymbol(String),
+    #[error("call to '{0}', which none of the linked units defines")]
+    UnresolvedCall(String),
+    #[error("jump to label '{0}', which isn't defined")]
+    UnresolvedLabel(String),
+}
+
+/// Links `units` into one program, checking that every call lands on a function one
+/// of them defines or a builtin.
+pub fn link(units: Vec<Code>) -> Result<Code, LinkError> {
+    if units.is_empty() {
+        return Err(LinkError::NoUnits);
+    }
+    let (mut ordered, with_main): (Vec<Code>, Vec<Code>) = units
+        .into_iter()
+        .partition(|unit| unit.symbol("main").is_none());
+    ordered.extend(with_main);
+    let with_locations = ordered.iter().any(|unit| !unit.locations.is_empty());
+    let mut linker = Linker {
+        linked: Code {
+            bytecode: Vec::new(),
+            labels: HashMap::new(),
+            functions: Vec::new(),
+            constants: Vec::new(),
+            local_names: HashMap::new(),
+            types: Vec::new(),
+            locations: Vec::new(),
+            files: Vec::new(),
+            symbols: Vec::new(),
+        },
+        constant_indices: HashMap::new(),
+        with_locations,
+    };
+    let count = ordered.len();
+    for (index, unit) in ordered.into_iter().enumerate() {
+        linker.append(index, unit, index + 1 == count)?;
+    }
+    let mut linked = linker.linked;
+    linked.symbols.sort_by(|a, b| a.0.cmp(&b.0));
+    linked.types.sort_by(|a, b| a.name.cmp(&b.name));
+    verify(&linked)?;
+    Ok(linked)
+}
+
+struct Linker {
+    linked: Code,
+    constant_indices: HashMap<StaticValue, usize>,
+    /// Whether any unit has a line table; the linked one then covers every instruction.
+    with_locations: bool,
+}
+
+impl Linker {
+    /// Appends the `index`th unit; unless it is the `last`, its start code goes on to
+    /// the next unit's instead of exiting.
+    fn append(&mut self, index: usize, unit: Code, last: bool) -> Result<(), LinkError> {
+        let base = self.linked.bytecode.len();
+        let start = unit.labels.get("_start").copied().unwrap_or(0);
+        let start_exit = unit.bytecode[start.min(unit.bytecode.len())..]
+            .iter()
+            .position(|byte| matches!(byte, ByteCode::EXIT))
+            .map(|exit| start + exit);
+        let end = base + unit.bytecode.len();
+        for (name, symbol) in unit.symbols {
+            if self.linked.symbols.iter().any(|(other, _)| *other == name) {
+                return Err(LinkError::DuplicateSymbol(name));
+            }
+            let symbol = match symbol {
+                Symbol::Function((start, signature)) => {
+                    Symbol::Function((start.map(|start| start + base), signature))
+                }
+                class => class,
+            };
+            self.linked.symbols.push((name, symbol));
+        }
+
+        // Labels of functions are shared; the others belong to this unit's code alone.
+        let mut renamed = HashMap::new();
+        let mut labels: Vec<(String, usize)> = unit.labels.into_iter().collect();
+        labels.sort();
+        for (label, at) in labels {
+            let name = if unit.functions.contains(&label) {
+                if self.linked.labels.contains_key(&label) {
+                    return Err(LinkError::DuplicateSymbol(label));
+                }
+                label
+            } else if (index > 0 && label == "_start") || self.linked.labels.contains_key(&label) {
+                let name = (index..)
+                    .map(|suffix| format!("{}@{}", label, suffix))
+                    .find(|name| !self.linked.labels.contains_key(name))
+                    .expect("some suffix is free");
+                renamed.insert(label, name.clone());
+                name
+            } else {
+                label
+            };
+            self.linked.labels.insert(name, base + at);
+        }
+        let label = |label: String| renamed.get(&label).cloned().unwrap_or(label);
+
+        let functions: Vec<usize> = unit
+            .functions
+            .into_iter()
+            .map(|name| position_or_push(&mut self.linked.functions, name))
+            .collect();
+        let constants: Vec<usize> = unit
+            .constants
+            .into_iter()
+            .map(|value| match self.constant_indices.get(&value) {
+                Some(index) => *index,
+                None => {
+                    self.linked.constants.push(value.clone());
+                    self.constant_indices
+                        .insert(value, self.linked.constants.len() - 1);
+                    self.linked.constants.len() - 1
+                }
+            })
+            .collect();
+        let files: Vec<usize> = unit
+            .files
+            .into_iter()
+            .map(|file| position_or_push(&mut self.linked.files, file))
+            .collect();
+        let operand = |operand: Operand| match operand {
+            Operand::Const(index) => Operand::Const(constants[index]),
+            other => other,
+        };
+
+        for (at, byte) in unit.bytecode.into_iter().enumerate() {
+            let byte = match byte {
+                ByteCode::EXIT if !last && Some(at) == start_exit => ByteCode::JMPABS(end as i64),
+                ByteCode::PUSH(index) => ByteCode::PUSH(constants[index]),
+                ByteCode::JMPABS(target) => ByteCode::JMPABS(target + base as i64),
+                ByteCode::JITA(target) => ByteCode::JITA(target + base as i64),
+                ByteCode::JMPTO(target) => ByteCode::JMPTO(label(target)),
+                ByteCode::JITL(target) => ByteCode::JITL(label(target)),
+                ByteCode::PUSHEH(target) => ByteCode::PUSHEH(label(target)),
+                ByteCode::CLOSURE(target, ty, captures) => {
+                    ByteCode::CLOSURE(label(target), ty, captures)
+                }
+                ByteCode::CALLFN(func, argc) => ByteCode::CALLFN(functions[func], argc),
+                ByteCode::BINARY(op, a, b, out) => {
+                    ByteCode::BINARY(op, operand(a), operand(b), operand(out))
+                }
+                other => other,
+            };
+            self.linked.bytecode.push(byte);
+        }
+
+        if self.with_locations {
+            let mut locations = unit.locations;
+            locations.resize(end - base, None);
+            self.linked
+                .locations
+                .extend(locations.into_iter().map(|location| {
+                    location.map(|mut location| {
+                        location.file = files[location.file];
+                        location
+                    })
+                }));
+        }
+        for (func, names) in unit.local_names {
+            self.linked.local_names.insert(label(func), names);
+        }
+        self.linked.types.extend(unit.types);
+        Ok(())
+    }
+}
+
+/// Index of `name` in `table`, adding it at the end if it isn't there.
+fn position_or_push(table: &mut Vec<String>, name: String) -> usize {
+    match table.iter().position(|known| *known == name) {
+        Some(index) => index,
+        None => {
+            table.push(name);
+            table.len() - 1
+        }
+    }
+}
+
+/// Checks that every call and jump by name lands on a label of the linked code, or
+/// for a call by name, on a builtin.
+fn verify(code: &Code) -> Result<(), LinkError> {
+    let inbuilt_functions = generate_inbuilt_function_hashmap();
+    for byte in code.bytecode.iter() {
+        match byte {
+            ByteCode::CALL(func, _)
+                if !code.labels.contains_key(func) && !inbuilt_functions.contains_key(func) =>
+            {
+                return Err(LinkError::UnresolvedCall(func.clone()));
+            }
+            ByteCode::CALLFN(func, _) if !code.labels.contains_key(&code.functions[*func]) => {
+                return Err(LinkError::UnresolvedCall(code.functions[*func].clone()));
+            }
+            ByteCode::JMPTO(label)
+            | ByteCode::JITL(label)
+            | ByteCode::PUSHEH(label)
+            | ByteCode::CLOSURE(label, _, _)
+                if !code.labels.contains_key(label) =>
+            {
+                return Err(LinkError::UnresolvedLabel(label.clone()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
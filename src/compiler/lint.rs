@@ -0,0 +1,461 @@
+//! Warnings about code that typechecks but is likely a mistake: variables that are
+//! never read, functions that are never called, statements that can't run and
+//! conditions that are always true or always false.
+//!
+//! Each warning belongs to a [`Lint`], which `--allow` silences and `--deny` turns
+//! into an error. A name starting with `_` is never reported as unused.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::{
+    compiler::{
+        compile::{mentioned_in_statement, mentioned_names},
+        typecheck::{fold_constant, TypeCheckerMessage},
+    },
+    frontend::{
+        ast::{Block, Expression, Function, Item, Parameter, Pattern, Statement},
+        span::{SourceError, Span},
+        tokenizer::TokenLiteral,
+    },
+    runtime::value::StaticValue,
+};
+
+/// A kind of warning the lint pass reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A local declared but never read.
+    UnusedVariable,
+    /// A function of the program's entry file, or a nested one, that nothing calls.
+    UnusedFunction,
+    /// A statement after one that always leaves its block.
+    UnreachableCode,
+    /// An `if`, `while` or `for` condition made only of literals.
+    ConstantCondition,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [
+        Lint::UnusedVariable,
+        Lint::UnusedFunction,
+        Lint::UnreachableCode,
+        Lint::ConstantCondition,
+    ];
+
+    /// The name `--allow` and `--deny` take.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedFunction => "unused-function",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ConstantCondition => "constant-condition",
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+                format!(
+                    "Unknown lint '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// What becomes of a typechecker warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Which warnings are left out and which fail compilation. By default every warning
+/// is reported and none fails.
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+    /// Fails on every warning that isn't allowed, lint or not.
+    warnings_as_errors: bool,
+    denied: HashSet<Lint>,
+    allowed: HashSet<Lint>,
+}
+
+impl LintLevels {
+    /// Fails compilation on every warning, except those of the lints allowed.
+    pub fn with_warnings_as_errors(mut self, enabled: bool) -> Self {
+        self.warnings_as_errors = enabled;
+        self
+    }
+
+    /// Fails compilation on `lint`'s warnings, unless it is allowed later.
+    pub fn deny(mut self, lint: Lint) -> Self {
+        self.allowed.remove(&lint);
+        self.denied.insert(lint);
+        self
+    }
+
+    /// Leaves out `lint`'s warnings, unless it is denied later.
+    pub fn allow(mut self, lint: Lint) -> Self {
+        self.denied.remove(&lint);
+        self.allowed.insert(lint);
+        self
+    }
+
+    /// What becomes of a warning of `lint`, or of one that belongs to no lint.
+    pub fn level(&self, lint: Option<Lint>) -> Level {
+        match lint {
+            Some(lint) if self.allowed.contains(&lint) => Level::Allow,
+            Some(lint) if self.denied.contains(&lint) => Level::Deny,
+            _ if self.warnings_as_errors => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+/// The lint warnings about `items`, a whole program.
+pub(crate) fn lint(items: &[Item]) -> Vec<TypeCheckerMessage> {
+    let mut linter = Linter::default();
+    for item in items {
+        match item {
+            Item::Function(func) => linter.function_body(func),
+            Item::Class(class) => class
+                .functions
+                .iter()
+                .for_each(|func| linter.function_body(func)),
+            Item::Enum(_) | Item::Interface(_) | Item::Global(_) | Item::Import(_) => {}
+        }
+    }
+    linter.unused_functions(items);
+    linter.warnings
+}
+
+/// A local of the block being linted.
+struct Local {
+    name: String,
+    span: Span,
+    read: bool,
+    /// A nested function rather than a variable.
+    function: bool,
+}
+
+#[derive(Default)]
+struct Linter {
+    /// Locals of each enclosing block, innermost last.
+    scopes: Vec<Vec<Local>>,
+    warnings: Vec<TypeCheckerMessage>,
+}
+
+impl Linter {
+    fn warn(&mut self, lint: Lint, span: &Span, message: String) {
+        let warning = SourceError::new(span.clone(), message).with_lint(lint);
+        self.warnings
+            .push(TypeCheckerMessage::Warning(anyhow::Error::new(warning)));
+    }
+
+    /// Lints the body of `func`, whose parameters are never reported.
+    fn function_body(&mut self, func: &Function) {
+        self.scopes.push(Vec::new());
+        for param in func.params.iter() {
+            self.declare(&param.name, &func.span, false);
+            self.read_name(&param.name);
+            param.default.iter().for_each(|default| self.read(default));
+        }
+        self.statements(&func.body);
+        self.end_scope();
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(Vec::new());
+        self.statements(block);
+        self.end_scope();
+    }
+
+    /// Lints `block` in the innermost scope, reporting the first statement that
+    /// can't run.
+    fn statements(&mut self, block: &Block) {
+        let mut leaving = None;
+        let mut reported = false;
+        for stmt in block.iter() {
+            if let (Some(keyword), false) = (leaving, reported) {
+                self.warn(
+                    Lint::UnreachableCode,
+                    &stmt.span,
+                    format!(
+                        "Unreachable statement: the '{}' before it always leaves the block",
+                        keyword
+                    ),
+                );
+                reported = true;
+            }
+            self.statement(&stmt.node, &stmt.span);
+            leaving = leaving.or_else(|| leaves(&stmt.node));
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement, span: &Span) {
+        match stmt {
+            Statement::Declare(name, _, value) => {
+                value.iter().for_each(|value| self.read(value));
+                self.declare(name, span, false);
+            }
+            Statement::Destructure(names, value) => {
+                self.read(value);
+                for name in names.iter().filter(|name| *name != "_") {
+                    self.declare(name, span, false);
+                }
+            }
+            // Assigning a variable doesn't read it.
+            Statement::Set(object, _, value) => {
+                object.iter().for_each(|object| self.read(object));
+                self.read(value);
+            }
+            Statement::SetIndex(collection, index, value) => {
+                self.read(collection);
+                self.read(index);
+                self.read(value);
+            }
+            Statement::Compound(target, _, value) => {
+                self.read(target);
+                self.read(value);
+            }
+            Statement::Expression(value)
+            | Statement::Print(value)
+            | Statement::Throw(value)
+            | Statement::Return(Some(value)) => self.read(value),
+            Statement::If(condition, then, otherwise) => {
+                self.condition("if", condition, span);
+                self.block(then);
+                otherwise.iter().for_each(|otherwise| self.block(otherwise));
+            }
+            Statement::For(init, condition, step, body) => {
+                self.scopes.push(Vec::new());
+                self.statement(init, span);
+                self.condition("for", condition, span);
+                body.iter().for_each(|body| self.block(body));
+                self.statement(step, span);
+                self.end_scope();
+            }
+            Statement::While(condition, body) => {
+                // `while true` is how a loop that only `break` ends is written.
+                let literal_true =
+                    Expression::Literal(TokenLiteral::Value(StaticValue::Bool(true)));
+                if *condition != literal_true {
+                    self.condition("while", condition, span);
+                }
+                self.read(condition);
+                self.block(body);
+            }
+            Statement::Try(body, name, handler) => {
+                self.block(body);
+                // The caught message needs a name even when the handler ignores it.
+                self.scopes.push(Vec::new());
+                self.declare(name, span, false);
+                self.read_name(name);
+                self.statements(handler);
+                self.end_scope();
+            }
+            Statement::Match(subject, arms) => {
+                self.read(subject);
+                for (pattern, body) in arms.iter() {
+                    self.scopes.push(Vec::new());
+                    if let Pattern::Variant(_, bindings) = pattern {
+                        for binding in bindings.iter().filter(|binding| *binding != "_") {
+                            self.declare(binding, span, false);
+                        }
+                    }
+                    self.statements(body);
+                    self.end_scope();
+                }
+            }
+            // Declared after its body, so calling itself doesn't count as a use.
+            Statement::Function(func) => {
+                self.function_body(func);
+                self.declare(&func.name, &func.span, true);
+            }
+            Statement::Return(None) | Statement::Break | Statement::Continue | Statement::Null => {}
+        }
+    }
+
+    /// Reports `condition` of an `if`, `while` or `for` if it is always the same, and
+    /// marks the variables it reads.
+    fn condition(&mut self, keyword: &str, condition: &Expression, span: &Span) {
+        // Conditions on `const`s are left alone, as they are how code is switched off.
+        if let Some(StaticValue::Bool(value)) = fold_constant(condition, &HashMap::new()) {
+            self.warn(
+                Lint::ConstantCondition,
+                span,
+                format!("Condition of '{}' is always {}", keyword, value),
+            );
+        }
+        self.read(condition);
+    }
+
+    fn declare(&mut self, name: &str, span: &Span, function: bool) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("locals are declared in a scope");
+        scope.push(Local {
+            name: name.to_string(),
+            span: span.clone(),
+            read: false,
+            function,
+        });
+    }
+
+    /// Marks every local `value` reads, including inside lambdas.
+    fn read(&mut self, value: &Expression) {
+        let mut mentioned = HashSet::new();
+        mentioned_names(value, &mut mentioned);
+        mentioned.iter().for_each(|name| self.read_name(name));
+    }
+
+    fn read_name(&mut self, name: &str) {
+        let innermost = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|local| local.name == name));
+        if let Some(local) = innermost {
+            local.read = true;
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for local in scope
+            .into_iter()
+            .filter(|local| !local.read && !local.name.starts_with('_'))
+        {
+            let (lint, message) = match local.function {
+                true => (
+                    Lint::UnusedFunction,
+                    format!("Function '{}' is never called", local.name),
+                ),
+                false => (
+                    Lint::UnusedVariable,
+                    format!("Variable '{}' is never read", local.name),
+                ),
+            };
+            self.warn(lint, &local.span, message);
+        }
+    }
+
+    /// Reports the top-level functions of the entry file that can't be reached from
+    /// `main`, its tests, or anything else a caller outside the file may run. A
+    /// program without `main` is a library, whose functions are all reachable.
+    fn unused_functions(&mut self, items: &[Item]) {
+        let functions: HashMap<&str, &Function> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Function(func) => Some((func.name.as_str(), func)),
+                _ => None,
+            })
+            .collect();
+        let Some(main) = functions.get("main") else {
+            return;
+        };
+        let entry_file = &main.span.file;
+
+        let mut reached: HashSet<String> = HashSet::new();
+        let mut pending: Vec<String> = Vec::new();
+        for item in items {
+            match item {
+                Item::Function(func)
+                    if func.name == "main"
+                        || func.name.starts_with("test_")
+                        || func.span.file != *entry_file =>
+                {
+                    pending.push(func.name.clone())
+                }
+                Item::Function(_) => {}
+                Item::Class(class) => {
+                    for func in class.functions.iter() {
+                        pending.extend(function_mentions(func));
+                    }
+                    for (_, _, init) in class.variables.iter() {
+                        init.iter()
+                            .for_each(|init| pending.extend(expression_mentions(init)));
+                    }
+                }
+                Item::Global(global) => pending.extend(expression_mentions(&global.value)),
+                Item::Enum(_) | Item::Interface(_) | Item::Import(_) => {}
+            }
+        }
+        while let Some(name) = pending.pop() {
+            if let Some(func) = functions.get(name.as_str()) {
+                if reached.insert(name) {
+                    pending.extend(function_mentions(func));
+                }
+            }
+        }
+
+        for item in items {
+            if let Item::Function(func) = item {
+                if !reached.contains(&func.name) && !func.name.starts_with('_') {
+                    self.warn(
+                        Lint::UnusedFunction,
+                        &func.span,
+                        format!("Function '{}' is never called", func.name),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The keyword of `stmt` if it never finishes normally, so nothing after it runs.
+fn leaves(stmt: &Statement) -> Option<&'static str> {
+    match stmt {
+        Statement::Return(_) => Some("return"),
+        Statement::Break => Some("break"),
+        Statement::Continue => Some("continue"),
+        Statement::Throw(_) => Some("throw"),
+        Statement::If(_, then, Some(otherwise))
+            if block_leaves(then) && block_leaves(otherwise) =>
+        {
+            Some("if")
+        }
+        _ => None,
+    }
+}
+
+fn block_leaves(block: &Block) -> bool {
+    block.iter().any(|stmt| leaves(&stmt.node).is_some())
+}
+
+fn function_mentions(func: &Function) -> Vec<String> {
+    let mut mentioned = HashSet::new();
+    func.body
+        .iter()
+        .for_each(|stmt| mentioned_in_statement(&stmt.node, &mut mentioned));
+    func.params
+        .iter()
+        .filter_map(|param: &Parameter| param.default.as_ref())
+        .for_each(|default| mentioned_names(default, &mut mentioned));
+    mentioned.into_iter().collect()
+}
+
+fn expression_mentions(value: &Expression) -> Vec<String> {
+    let mut mentioned = HashSet::new();
+    mentioned_names(value, &mut mentioned);
+    mentioned.into_iter().collect()
+}
@@ -1,9 +1,18 @@
 //! Compiler components: AST to Bytecode translation.
 
+pub(crate) mod analysis;
+pub mod assembly;
+pub mod assignment;
 pub mod bytecode;
 pub mod compile; // Changed from compiler.rs to avoid name clash
+pub mod container;
+pub mod link;
+pub mod lint;
+pub mod modules;
 pub mod typecheck;
+pub mod verify;
+pub mod wasm_backend;
 
 // Re-export main components
-pub use bytecode::ByteCode;
-pub use compile::{Code, Compiler};
+pub use bytecode::{BinaryOp, ByteCode, Operand};
+pub use compile::{Code, Compiler, SourceLocation, Symbol};
@@ -0,0 +1,517 @@
+//! Loading programs spread over several files.
+//!
+//! `import "path"` pulls in the items of another file. Paths are relative to the
+//! importing file and may leave out the `.abra` extension; a file imported twice is
+//! loaded once. All items end up in one program, so a name may only be defined in
+//! one file. Imported items can be named as is or as `module::name`, where `module`
+//! is the imported file's name without extension.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+
+use crate::{
+    compiler::typecheck::{Algebraic, Composite, Type},
+    errors::{AbraError, CompileError},
+    frontend::{
+        ast::{Block, Expression, Function, Item, Pattern, Statement},
+        parser::Parser,
+        span::Span,
+        tokenizer::{TokenLiteral, Tokenizer},
+    },
+};
+
+/// File a project directory starts from.
+pub const PROJECT_MAIN: &str = "main.abra";
+
+/// Every item of a program and its imports, with the files they came from.
+pub struct Program {
+    pub items: Vec<Item>,
+    /// File name and contents of the entry point, followed by each imported file.
+    pub sources: Vec<(String, String)>,
+}
+
+struct Module {
+    file: String,
+    source: String,
+    items: Vec<Item>,
+    /// Imported module name -> index of the module.
+    imports: HashMap<String, usize>,
+}
+
+/// The file `entry` names, or the `main.abra` inside it when it is a project directory.
+pub fn entry_point(entry: &str) -> String {
+    let path = Path::new(entry);
+    if path.is_dir() {
+        return path.join(PROJECT_MAIN).to_string_lossy().into_owned();
+    }
+    entry.to_string()
+}
+
+/// Parses `source`, the contents of `file`, and loads everything it imports.
+/// Imports are resolved relative to the directory of `file`.
+pub fn load_program(file: &str, source: &str) -> Result<Program, AbraError> {
+    let mut loader = Loader::default();
+    loader.load(file, source.to_string())?;
+    loader.check_duplicates()?;
+    loader.resolve_qualified_names()?;
+    let mut program = Program {
+        items: Vec::new(),
+        sources: Vec::new(),
+    };
+    for module in loader.modules {
+        program.items.extend(
+            module
+                .items
+                .into_iter()
+                .filter(|item| !matches!(item, Item::Import(_))),
+        );
+        program.sources.push((module.file, module.source));
+    }
+    Result::Ok(program)
+}
+
+#[derive(Default)]
+struct Loader {
+    modules: Vec<Module>,
+    /// Canonical path -> index of the module loaded from it.
+    loaded: HashMap<PathBuf, usize>,
+}
+
+impl Loader {
+    fn load(&mut self, file: &str, source: String) -> Result<usize, AbraError> {
+        let tokenizer = Tokenizer::new(&source);
+        let items = Parser::new(tokenizer)
+            .with_source(&source)
+            .in_file(file)
+            .parse_program()?;
+        let index = self.modules.len();
+        if let Result::Ok(path) = Path::new(file).canonicalize() {
+            self.loaded.insert(path, index);
+        }
+        let imports: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Import(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+        self.modules.push(Module {
+            file: file.to_string(),
+            source,
+            items,
+            imports: HashMap::new(),
+        });
+
+        for import in imports {
+            let path = import_path(file, &import.node);
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let dependency = match path.canonicalize() {
+                Result::Ok(canonical) => match self.loaded.get(&canonical) {
+                    Some(dependency) => *dependency,
+                    None => match read_to_string(&path) {
+                        Result::Ok(contents) => self.load(&path.to_string_lossy(), contents)?,
+                        Err(e) => {
+                            let message = format!("Cannot import '{}': {}", import.node, e);
+                            return Err(CompileError::Import(import.span, message).into());
+                        }
+                    },
+                },
+                Err(e) => {
+                    let message = format!("Cannot import '{}': {}", import.node, e);
+                    return Err(CompileError::Import(import.span, message).into());
+                }
+            };
+            match self.modules[index].imports.insert(name.clone(), dependency) {
+                Some(other) if other != dependency => {
+                    let message = format!("Two imported modules are named '{}'", name);
+                    return Err(CompileError::Import(import.span, message).into());
+                }
+                _ => {}
+            }
+        }
+        Result::Ok(index)
+    }
+
+    /// Rejects a function or class defined in more than one file. Duplicates within
+    /// one file are left to the typechecker.
+    fn check_duplicates(&self) -> Result<(), CompileError> {
+        let mut defined: HashMap<&str, usize> = HashMap::new();
+        for (index, module) in self.modules.iter().enumerate() {
+            for item in module.items.iter() {
+                let name = match item_name(item) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                match defined.get(name) {
+                    Some(other) if *other != index => {
+                        let message = format!(
+                            "'{}' is already defined in {}",
+                            name, self.modules[*other].file
+                        );
+                        let span = item.span().clone();
+                        return Err(CompileError::DuplicateDefinition(span, message));
+                    }
+                    _ => {
+                        defined.insert(name, index);
+                    }
+                }
+            }
+        }
+        Result::Ok(())
+    }
+
+    /// Rewrites `module::name` to `name` after checking that `module` is imported by
+    /// the file and defines `name`.
+    fn resolve_qualified_names(&mut self) -> Result<(), AbraError> {
+        let defined: Vec<HashSet<String>> = self
+            .modules
+            .iter()
+            .map(|module| {
+                module
+                    .items
+                    .iter()
+                    .filter_map(item_name)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .collect();
+        let enums: HashSet<String> = self
+            .modules
+            .iter()
+            .flat_map(|module| module.items.iter())
+            .filter_map(|item| match item {
+                Item::Enum(enumeration) => Some(enumeration.name.clone()),
+                _ => None,
+            })
+            .collect();
+        for index in 0..self.modules.len() {
+            let imports = self.modules[index]
+                .imports
+                .iter()
+                .map(|(name, module)| (name.clone(), &defined[*module]))
+                .collect();
+            let qualifier = Qualifier {
+                imports,
+                enums: &enums,
+            };
+            let mut items = std::mem::take(&mut self.modules[index].items);
+            let resolved = items.iter_mut().try_for_each(|item| qualifier.item(item));
+            self.modules[index].items = items;
+            resolved.map_err(AbraError::recover)?;
+        }
+        Result::Ok(())
+    }
+}
+
+/// `path` as written in an import in `importer`, relative to the importer's directory.
+fn import_path(importer: &str, path: &str) -> PathBuf {
+    let dir = Path::new(importer).parent().unwrap_or(Path::new(""));
+    let mut resolved = dir.join(path);
+    if resolved.extension().is_none() {
+        resolved.set_extension("abra");
+    }
+    resolved
+}
+
+fn item_name(item: &Item) -> Option<&str> {
+    match item {
+        Item::Function(func) => Some(&func.name),
+        Item::Class(class) => Some(&class.name),
+        Item::Enum(enumeration) => Some(&enumeration.name),
+        Item::Interface(interface) => Some(&interface.name),
+        Item::Global(global) => Some(&global.name),
+        Item::Import(_) => None,
+    }
+}
+
+/// The names each import of one file makes available.
+struct Qualifier<'a> {
+    imports: HashMap<String, &'a HashSet<String>>,
+    /// Enums of the whole program, whose variants are named `Enum::Variant`.
+    enums: &'a HashSet<String>,
+}
+
+impl Qualifier<'_> {
+    fn name(&self, name: &mut String) -> Result<()> {
+        let (module, item) = match name.split_once("::") {
+            Some(parts) => parts,
+            None => return Ok(()),
+        };
+        if self.enums.contains(module) {
+            return Ok(());
+        }
+        let defined = self
+            .imports
+            .get(module)
+            .ok_or_else(|| anyhow!("Unknown module '{}' in '{}'", module, name))?;
+        // `module::Enum::Variant` names a variant of the module's enum.
+        let defined_name = item
+            .split_once("::")
+            .map_or(item, |(enumeration, _)| enumeration);
+        if !defined.contains(defined_name) {
+            bail!("Module '{}' has no item '{}'", module, defined_name);
+        }
+        *name = item.to_string();
+        Ok(())
+    }
+
+    fn item(&self, item: &mut Item) -> Result<()> {
+        let span = item.span().clone();
+        match item {
+            Item::Function(func) => self.function(func),
+            Item::Class(class) => {
+                if let Some(parent) = class.parent.as_mut() {
+                    self.name(parent).map_err(|e| located(e, &span))?;
+                }
+                for interface in class.interfaces.iter_mut() {
+                    self.name(interface).map_err(|e| located(e, &span))?;
+                }
+                for (_, ty, init) in class.variables.iter_mut() {
+                    self.ty(ty).map_err(|e| located(e, &span))?;
+                    if let Some(init) = init {
+                        self.expression(init).map_err(|e| located(e, &span))?;
+                    }
+                }
+                class
+                    .functions
+                    .iter_mut()
+                    .try_for_each(|func| self.function(func))
+            }
+            Item::Enum(enumeration) => enumeration
+                .variants
+                .iter_mut()
+                .flat_map(|(_, payload)| payload.iter_mut())
+                .try_for_each(|ty| self.ty(ty))
+                .map_err(|e| located(e, &span)),
+            Item::Interface(interface) => interface
+                .methods
+                .iter_mut()
+                .try_for_each(|(_, params, return_type)| {
+                    params
+                        .iter_mut()
+                        .try_for_each(|param| self.ty(&mut param.ty))?;
+                    self.ty(return_type)
+                })
+                .map_err(|e| located(e, &span)),
+            Item::Global(global) => self
+                .ty(&mut global.ty)
+                .and_then(|_| self.expression(&mut global.value))
+                .map_err(|e| located(e, &span)),
+            Item::Import(_) => Ok(()),
+        }
+    }
+
+    fn function(&self, func: &mut Function) -> Result<()> {
+        for param in func.params.iter_mut() {
+            self.ty(&mut param.ty)
+                .and_then(|_| {
+                    param
+                        .default
+                        .iter_mut()
+                        .try_for_each(|d| self.expression(d))
+                })
+                .map_err(|e| located(e, &func.span))?;
+        }
+        self.ty(&mut func.return_type)
+            .map_err(|e| located(e, &func.span))?;
+        self.block(&mut func.body)
+    }
+
+    fn block(&self, block: &mut Block) -> Result<()> {
+        for stmt in block.iter_mut() {
+            let span = stmt.span.clone();
+            self.statement(&mut stmt.node)
+                .map_err(|e| located(e, &span))?;
+        }
+        Ok(())
+    }
+
+    fn statement(&self, stmt: &mut Statement) -> Result<()> {
+        match stmt {
+            Statement::Declare(_, ty, expr) => {
+                self.ty(ty)?;
+                match expr {
+                    Some(expr) => self.expression(expr),
+                    None => Ok(()),
+                }
+            }
+            Statement::Destructure(_, expr) => self.expression(expr),
+            Statement::Set(on, _, expr) => {
+                if let Some(on) = on {
+                    self.expression(on)?;
+                }
+                self.expression(expr)
+            }
+            Statement::Compound(target, _, expr) => {
+                self.expression(target)?;
+                self.expression(expr)
+            }
+            Statement::SetIndex(collection, index, expr) => {
+                self.expression(collection)?;
+                self.expression(index)?;
+                self.expression(expr)
+            }
+            Statement::Expression(expr) | Statement::Print(expr) | Statement::Throw(expr) => {
+                self.expression(expr)
+            }
+            Statement::Return(expr) => match expr {
+                Some(expr) => self.expression(expr),
+                None => Ok(()),
+            },
+            Statement::If(cond, body, els) => {
+                self.expression(cond)?;
+                self.block(body)?;
+                match els {
+                    Some(els) => self.block(els),
+                    None => Ok(()),
+                }
+            }
+            Statement::For(init, cond, step, body) => {
+                self.statement(init)?;
+                self.expression(cond)?;
+                self.statement(step)?;
+                match body {
+                    Some(body) => self.block(body),
+                    None => Ok(()),
+                }
+            }
+            Statement::While(cond, body) => {
+                self.expression(cond)?;
+                self.block(body)
+            }
+            Statement::Try(body, _, handler) => {
+                self.block(body)?;
+                self.block(handler)
+            }
+            Statement::Match(subject, arms) => {
+                self.expression(subject)?;
+                for (pattern, body) in arms.iter_mut() {
+                    match pattern {
+                        Pattern::Type(ty) => self.ty(ty)?,
+                        Pattern::Variant(name, _) => self.name(name)?,
+                        _ => {}
+                    }
+                    self.block(body)?;
+                }
+                Ok(())
+            }
+            Statement::Function(func) => self.function(func),
+            Statement::Break | Statement::Continue | Statement::Null => Ok(()),
+        }
+    }
+
+    fn expression(&self, expr: &mut Expression) -> Result<()> {
+        match expr {
+            Expression::Literal(TokenLiteral::Identifier(name)) => self.name(name),
+            Expression::Literal(TokenLiteral::Value(_)) | Expression::Range(..) => Ok(()),
+            Expression::Unary(_, inner) | Expression::Grouping(inner) => self.expression(inner),
+            Expression::Binary(_, lhs, rhs) => {
+                self.expression(lhs)?;
+                self.expression(rhs)
+            }
+            Expression::Call(name, args) => {
+                self.name(name)?;
+                args.iter_mut().try_for_each(|arg| self.expression(arg))
+            }
+            Expression::CallIndirect(callee, args) | Expression::MethodCall(callee, _, args) => {
+                self.expression(callee)?;
+                args.iter_mut().try_for_each(|arg| self.expression(arg))
+            }
+            Expression::Lambda(params, ty, body) => {
+                for param in params.iter_mut() {
+                    self.ty(&mut param.ty)?;
+                }
+                self.ty(ty)?;
+                self.expression(body)
+            }
+            Expression::Get(_, inner) => self.expression(inner),
+            Expression::Index(collection, index) => {
+                self.expression(collection)?;
+                self.expression(index)
+            }
+            Expression::Slice(collection, start, end) => {
+                self.expression(collection)?;
+                start
+                    .iter_mut()
+                    .chain(end)
+                    .try_for_each(|bound| self.expression(bound))
+            }
+            Expression::Instance(ty, args) => {
+                self.ty(ty)?;
+                args.iter_mut().try_for_each(|arg| self.expression(arg))
+            }
+            Expression::SuperCall(_, args) => {
+                args.iter_mut().try_for_each(|arg| self.expression(arg))
+            }
+            Expression::Coalesce(value, fallback) => {
+                self.expression(value)?;
+                self.expression(fallback)
+            }
+            Expression::NamedArgument(_, value) => self.expression(value),
+            Expression::Conditional(condition, then, otherwise) => {
+                self.expression(condition)?;
+                self.expression(then)?;
+                self.expression(otherwise)
+            }
+            Expression::SafeAccess(access) | Expression::TupleIndex(access, _) => {
+                self.expression(access)
+            }
+            Expression::ArrayLiteral(elements) | Expression::TupleLiteral(elements) => elements
+                .iter_mut()
+                .try_for_each(|element| self.expression(element)),
+            Expression::MapLiteral(entries) => entries.iter_mut().try_for_each(|(key, value)| {
+                self.expression(key)?;
+                self.expression(value)
+            }),
+            Expression::Is(value, ty) => {
+                self.ty(ty)?;
+                self.expression(value)
+            }
+        }
+    }
+
+    fn ty(&self, ty: &mut Type) -> Result<()> {
+        match ty {
+            Type::Abra(name) => self.name(name),
+            Type::Generic(name, args) => {
+                self.name(name)?;
+                args.iter_mut().try_for_each(|arg| self.ty(arg))
+            }
+            Type::Composite(composite) => match &mut **composite {
+                Composite::Array(inner) | Composite::HeapValue(inner) => self.ty(inner),
+                Composite::Tuple(types) => types.iter_mut().try_for_each(|t| self.ty(t)),
+                Composite::Map(key, value) => {
+                    self.ty(key)?;
+                    self.ty(value)
+                }
+            },
+            Type::Algebraic(algebraic) => match &mut **algebraic {
+                Algebraic::Or(a, b) => {
+                    self.ty(a)?;
+                    self.ty(b)
+                }
+            },
+            Type::Function(params, ret) => {
+                params.iter_mut().try_for_each(|param| self.ty(param))?;
+                self.ty(ret)
+            }
+            Type::Null | Type::Primitive(_) | Type::Var(_) | Type::Unknown => Ok(()),
+        }
+    }
+}
+
+/// Pins `error` to `span` unless it already points somewhere more precise.
+fn located(error: Error, span: &Span) -> Error {
+    if error.is::<CompileError>() {
+        return error;
+    }
+    Error::new(CompileError::Import(span.clone(), error.to_string()))
+}
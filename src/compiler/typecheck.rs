@@ -1,22 +1,41 @@
 use std::{
-    collections::HashMap,
-    fmt::{write, Display},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Display,
 };
 
-use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    compiler::{assignment::check_assignments, lint::lint},
+    errors::TypeError,
     frontend::{
-        ast::{BinOpCode, Expression, Function, Item, Parameter, Statement, UnaryOpCode},
+        ast::{
+            bind_arguments, BinOpCode, Block, Class, Expression, Function, Global, Item, Parameter,
+            Pattern, Statement, UnaryOpCode,
+        },
+        format::expression,
+        span::{LineIndex, Located, SourceError, Span},
         tokenizer::TokenLiteral,
     },
-    runtime::{inbuilt::generate_inbuilt_function_hashmap, value::StaticValue},
+    runtime::{
+        inbuilt::{generate_inbuilt_function_hashmap, inbuilt_constant, InbuiltFuncMap},
+        value::StaticValue,
+    },
 };
 
 type VariableDefinition = (Type, StaticValue);
 
+/// A [`TypeError`] of `kind` that doesn't know its place yet; the statement it was
+/// found in gives it one.
+macro_rules! type_error {
+    ($kind:ident, $($arg:tt)*) => {
+        TypeError::$kind(Span::default(), format!($($arg)*))
+    };
+}
+
 pub const INTEGER_TYPE: Type = Type::Primitive(Primitives::Integer);
+pub const BIGINT_TYPE: Type = Type::Primitive(Primitives::BigInt);
 pub const FLOAT_TYPE: Type = Type::Primitive(Primitives::Float);
 pub const CHAR_TYPE: Type = Type::Primitive(Primitives::Char);
 pub const BOOL_TYPE: Type = Type::Primitive(Primitives::Bool);
@@ -26,6 +45,75 @@ pub struct TypeChecker<'a> {
     pub messages: Vec<TypeCheckerMessage>,
     abra_types: HashMap<String, AbraTypeDefinition>,
     global_functions: HashMap<String, FunctionSignature>,
+    /// Parameters of the program's own top-level functions, which calls can pass by
+    /// name or leave to their defaults.
+    function_params: HashMap<String, Vec<Parameter>>,
+    /// Variants of each enum with their payload types, in declaration order.
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    /// Number of loops enclosing the statement being checked.
+    loop_depth: usize,
+    /// Class whose method is being checked, which `super` refers to the parent of.
+    current_class: Option<String>,
+    /// Variables a nested function being checked captures from the functions around
+    /// it, which it can read but not assign.
+    captured: HashSet<String>,
+    /// Declared types of the variables an enclosing `is` test narrowed, which
+    /// assignments to them are checked against.
+    declared: HashMap<String, Type>,
+    /// Narrowed variables assigned since the innermost narrowing began. Each block
+    /// around the assignment widens them back to their declared type.
+    reassigned: HashSet<String>,
+    /// Spans of the `match` statements with an arm for every value of their subject,
+    /// which the definite-assignment check can't work out without the types.
+    exhaustive_matches: Vec<Span>,
+    /// Spans of the expression statements whose value is left on the stack for the
+    /// compiler to pop: those of any type but `null`, which calls leave nothing for.
+    pub(crate) unused_values: HashSet<Span>,
+    /// Names in `abra_types` that are interfaces rather than classes.
+    interfaces: HashSet<String>,
+    /// Variables and constants declared outside any function, which every body sees.
+    /// A constant's entry holds its value; a variable's holds `Null`.
+    globals: HashMap<String, VariableDefinition>,
+    /// Variables in scope after each statement checked, when recording them for the
+    /// language server.
+    scopes: Option<Vec<(Span, HashMap<String, VariableDefinition>)>>,
+}
+
+thread_local! {
+    /// Parent and interfaces of each class this thread has checked or loaded. A `Type`
+    /// only names its class, so subtyping between classes looks the hierarchy up here.
+    static CLASS_SUPERTYPES: RefCell<HashMap<String, Vec<String>>> = RefCell::new(HashMap::new());
+}
+
+/// Records the class `def` extends and the interfaces it implements, for
+/// [`Type::is_subtype_of`].
+pub fn register_class(def: &AbraTypeDefinition) {
+    let supertypes = def.parent.iter().chain(def.interfaces.iter()).cloned();
+    CLASS_SUPERTYPES.with(|all| {
+        all.borrow_mut()
+            .insert(def.name.clone(), supertypes.collect());
+    });
+}
+
+/// Whether `class` inherits from or implements `ancestor`, directly or not.
+fn extends(class: &str, ancestor: &str) -> bool {
+    CLASS_SUPERTYPES.with(|all| {
+        let all = all.borrow();
+        let mut seen = HashSet::new();
+        let mut pending = vec![class];
+        // A cycle is an error elsewhere; `seen` keeps it from looping here.
+        while let Some(current) = pending.pop() {
+            for supertype in all.get(current).into_iter().flatten() {
+                if supertype == ancestor {
+                    return true;
+                }
+                if seen.insert(supertype.as_str()) {
+                    pending.push(supertype);
+                }
+            }
+        }
+        false
+    })
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash)]
@@ -35,6 +123,16 @@ pub enum Type {
     Composite(Box<Composite>),
     Algebraic(Box<Algebraic>),
     Abra(String), // String is the AbraType name
+    /// Placeholder in a builtin signature, bound to the matching part of the
+    /// argument types at each call.
+    Var(String),
+    /// A function value: parameter types and return type.
+    Function(Vec<Type>, Box<Type>),
+    /// A generic class with its type arguments, e.g. `Box<int>`.
+    Generic(String, Vec<Type>),
+    /// The type of an expression that failed to check, whose error is already
+    /// reported, or the elements of an empty literal. It fits anywhere.
+    Unknown,
 }
 
 impl std::ops::BitOr<Type> for Type {
@@ -52,36 +150,43 @@ impl Type {
             return true;
         }
 
-        // Rule: Null <: T for any T.
-        if let Type::Null = self {
-            return true;
-        }
-
-        if let Type::Null = other {
+        // Null is only accepted where it was asked for, by an optional `T?` (`T | null`);
+        // the Or rules below handle that.
+        // An unbound variable accepts anything and, since its type is only known at
+        // runtime, is accepted anywhere. So is an unknown type.
+        if let (Type::Var(_) | Type::Unknown, _) | (_, Type::Var(_) | Type::Unknown) = (self, other)
+        {
             return true;
         }
 
         // Rule: S <: (T1 | T2) if S <: T1 or S <: T2.
         // This applies if 'other' is an Algebraic type.
         if let Type::Algebraic(other_c) = other {
-            if let Algebraic::Or(o1, o2) = &**other_c {
-                return self.is_subtype_of(o1) || self.is_subtype_of(o2);
-            }
+            let Algebraic::Or(o1, o2) = &**other_c;
+            return self.is_subtype_of(o1) || self.is_subtype_of(o2);
         }
 
         // Rule: (S1 | S2) <: T if S1 <: T and S2 <: T.
         // This applies if 'self' is an Or type and 'other' is not an Or type (that case handled above).
         if let Type::Algebraic(self_c) = self {
-            if let Algebraic::Or(s1, s2) = &**self_c {
-                return s1.is_subtype_of(other) && s2.is_subtype_of(other);
-            }
+            let Algebraic::Or(s1, s2) = &**self_c;
+            return s1.is_subtype_of(other) && s2.is_subtype_of(other);
         }
 
         // At this point, neither 'self' nor 'other' is an 'Or' type at their top level,
         // or such cases have been resolved. We compare base types or non-Or composites.
         match (self, other) {
             (Type::Primitive(p1), Type::Primitive(p2)) => p1 == p2,
-            (Type::Abra(a1), Type::Abra(a2)) => a1 == a2,
+            (Type::Abra(a1), Type::Abra(a2)) => a1 == a2 || extends(a1, a2),
+            // Type arguments are invariant; a class named without them accepts any.
+            (Type::Generic(g1, args1), Type::Generic(g2, args2)) => g1 == g2 && args1 == args2,
+            (Type::Generic(g, _), Type::Abra(a)) | (Type::Abra(a), Type::Generic(g, _)) => a == g,
+            // Contravariant parameters, covariant return type.
+            (Type::Function(sp, sr), Type::Function(op, or)) => {
+                sp.len() == op.len()
+                    && sp.iter().zip(op.iter()).all(|(s, o)| o.is_subtype_of(s))
+                    && sr.is_subtype_of(or)
+            }
             (Type::Composite(sc), Type::Composite(oc)) => {
                 // Here, sc and oc are guaranteed not to be Or.
                 match (&**sc, &**oc) {
@@ -96,6 +201,11 @@ impl Type {
                     (Composite::HeapValue(st), Composite::HeapValue(ot)) => {
                         st.is_subtype_of(ot) // Covariant heap values
                     }
+                    (Composite::Tuple(st), Composite::Tuple(ot)) => {
+                        // Same length, covariant elements
+                        st.len() == ot.len()
+                            && st.iter().zip(ot.iter()).all(|(s, o)| s.is_subtype_of(o))
+                    }
                     _ => false, // Different kinds of non-Or composites (e.g., Array vs Map)
                 }
             }
@@ -121,9 +231,159 @@ impl Type {
         Type::Algebraic(Box::new(Algebraic::Or(t1, t2)))
     }
 
+    /// Whether null is one of the type's members, as in an optional `T?`.
+    pub fn is_optional(&self) -> bool {
+        self.members().contains(&&Type::Null)
+    }
+
+    /// The type with its null member removed, or `None` when null is all it has.
+    pub fn without_null(&self) -> Option<Type> {
+        self.without(&Type::Null)
+    }
+
+    /// The type with the members that are `removed` taken out, or `None` when none
+    /// are left.
+    pub fn without(&self, removed: &Type) -> Option<Type> {
+        self.members()
+            .into_iter()
+            .filter(|member| !member.is_subtype_of(removed))
+            .cloned()
+            .reduce(Type::or)
+    }
+
+    /// The narrowest type both `self` and `other` fit: the wider of the two, or
+    /// their union when neither fits the other.
+    pub fn join(self, other: Type) -> Type {
+        match (&self, &other) {
+            (Type::Unknown, _) => other,
+            (_, Type::Unknown) => self,
+            _ if other.is_subtype_of(&self) => self,
+            _ if self.is_subtype_of(&other) => other,
+            _ => Type::or(self, other),
+        }
+    }
+
     pub fn abra<S: Into<String>>(name: S) -> Type {
         Type::Abra(name.into())
     }
+
+    pub fn var<S: Into<String>>(name: S) -> Type {
+        Type::Var(name.into())
+    }
+
+    pub fn function(params: Vec<Type>, return_type: Type) -> Type {
+        Type::Function(params, Box::new(return_type))
+    }
+
+    /// The alternatives of an `Or` type, nested ones included; any other type is its
+    /// only member.
+    pub fn members(&self) -> Vec<&Type> {
+        match self {
+            Type::Algebraic(algebraic) => match &**algebraic {
+                Algebraic::Or(t1, t2) => {
+                    let mut members = t1.members();
+                    members.extend(t2.members());
+                    members
+                }
+            },
+            other => vec![other],
+        }
+    }
+
+    /// Binds the variables in `self` to the matching parts of `actual`. A variable
+    /// keeps the first type it is bound to; null and unknown types bind nothing.
+    fn bind_vars(&self, actual: &Type, bindings: &mut HashMap<String, Type>) {
+        match (self, actual) {
+            (_, Type::Null | Type::Unknown) => {}
+            (Type::Var(name), _) => {
+                bindings
+                    .entry(name.clone())
+                    .or_insert_with(|| actual.clone());
+            }
+            (Type::Composite(expected), Type::Composite(actual)) => {
+                match (&**expected, &**actual) {
+                    (Composite::Array(e), Composite::Array(a))
+                    | (Composite::HeapValue(e), Composite::HeapValue(a)) => {
+                        e.bind_vars(a, bindings)
+                    }
+                    (Composite::Map(ek, ev), Composite::Map(ak, av)) => {
+                        ek.bind_vars(ak, bindings);
+                        ev.bind_vars(av, bindings);
+                    }
+                    (Composite::Tuple(e), Composite::Tuple(a)) => {
+                        for (e, a) in e.iter().zip(a.iter()) {
+                            e.bind_vars(a, bindings);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            (Type::Function(ep, er), Type::Function(ap, ar)) => {
+                for (e, a) in ep.iter().zip(ap.iter()) {
+                    e.bind_vars(a, bindings);
+                }
+                er.bind_vars(ar, bindings);
+            }
+            (Type::Generic(e, ea), Type::Generic(a, aa)) if e == a => {
+                for (e, a) in ea.iter().zip(aa.iter()) {
+                    e.bind_vars(a, bindings);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `self` with bound variables replaced; unbound ones are left as they are.
+    fn substitute(&self, bindings: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Var(name) => bindings.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Type::Composite(c) => Type::Composite(Box::new(match &**c {
+                Composite::Array(t) => Composite::Array(t.substitute(bindings)),
+                Composite::Map(k, v) => {
+                    Composite::Map(k.substitute(bindings), v.substitute(bindings))
+                }
+                Composite::HeapValue(t) => Composite::HeapValue(t.substitute(bindings)),
+                Composite::Tuple(types) => {
+                    Composite::Tuple(types.iter().map(|t| t.substitute(bindings)).collect())
+                }
+            })),
+            Type::Algebraic(a) => match &**a {
+                Algebraic::Or(t1, t2) => Type::or(t1.substitute(bindings), t2.substitute(bindings)),
+            },
+            Type::Function(params, ret) => Type::function(
+                params.iter().map(|p| p.substitute(bindings)).collect(),
+                ret.substitute(bindings),
+            ),
+            Type::Generic(name, args) => Type::Generic(
+                name.clone(),
+                args.iter().map(|arg| arg.substitute(bindings)).collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    /// `self` as the VM sees it: generic classes without their type arguments.
+    pub fn erase_type_args(&self) -> Type {
+        match self {
+            Type::Generic(name, _) => Type::Abra(name.clone()),
+            Type::Composite(c) => Type::Composite(Box::new(match &**c {
+                Composite::Array(t) => Composite::Array(t.erase_type_args()),
+                Composite::Map(k, v) => Composite::Map(k.erase_type_args(), v.erase_type_args()),
+                Composite::HeapValue(t) => Composite::HeapValue(t.erase_type_args()),
+                Composite::Tuple(types) => {
+                    Composite::Tuple(types.iter().map(|t| t.erase_type_args()).collect())
+                }
+            })),
+            Type::Algebraic(a) => match &**a {
+                Algebraic::Or(t1, t2) => Type::or(t1.erase_type_args(), t2.erase_type_args()),
+            },
+            Type::Function(params, ret) => Type::function(
+                params.iter().map(|p| p.erase_type_args()).collect(),
+                ret.erase_type_args(),
+            ),
+            other => other.clone(),
+        }
+    }
 }
 
 impl Display for Type {
@@ -133,7 +393,29 @@ impl Display for Type {
             Type::Composite(c) => write!(f, "{}", c),
             Type::Abra(a) => write!(f, "{}", a),
             Type::Null => write!(f, "null"),
+            Type::Unknown => write!(f, "unknown"),
             Type::Algebraic(algebraic) => write!(f, "({})", algebraic),
+            Type::Var(name) => write!(f, "{}", name),
+            Type::Generic(name, args) => {
+                write!(f, "{}<", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ">")
+            }
+            Type::Function(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
         }
     }
 }
@@ -146,6 +428,7 @@ pub enum Algebraic {
 impl Display for Algebraic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Algebraic::Or(t, Type::Null) => write!(f, "{}?", t),
             Algebraic::Or(t1, t2) => write!(f, "{} | {}", t1, t2),
         }
     }
@@ -156,6 +439,8 @@ pub enum Composite {
     Array(Type),
     Map(Type, Type),
     HeapValue(Type),
+    /// `(int, string)`: a fixed number of values, each of its own type.
+    Tuple(Vec<Type>),
 }
 
 impl Display for Composite {
@@ -164,12 +449,17 @@ impl Display for Composite {
             Composite::Array(t) => write!(f, "[{}]", t),
             Composite::Map(k, v) => write!(f, "<{} -> {}>", k, v),
             Composite::HeapValue(t) => write!(f, "Box<{}>", t),
+            Composite::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+                write!(f, "({})", types.join(", "))
+            }
         }
     }
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash)]
 pub enum Primitives {
     Integer,
+    BigInt,
     Float,
     Char,
     Bool,
@@ -180,6 +470,7 @@ impl Display for Primitives {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Primitives::Integer => write!(f, "integer"),
+            Primitives::BigInt => write!(f, "bigint"),
             Primitives::Float => write!(f, "float"),
             Primitives::Char => write!(f, "char"),
             Primitives::Bool => write!(f, "bool"),
@@ -192,6 +483,10 @@ pub struct FunctionSignature {
     name: String,
     parameters: Vec<Type>,
     return_type: Type,
+    /// Types that type variables of `parameters` must be bound to subtypes of.
+    bounds: Vec<(String, Type)>,
+    /// Type variables of `parameters` that must be bound to types `<` can order.
+    ordered: Vec<String>,
 }
 
 impl FunctionSignature {
@@ -200,9 +495,81 @@ impl FunctionSignature {
             name,
             parameters,
             return_type,
+            bounds: Vec::new(),
+            ordered: Vec::new(),
         }
     }
+
+    /// Only accepts arguments of a subtype of `bound` for the type variable `var`,
+    /// e.g. numbers for `abs(N) -> N`.
+    pub fn with_bound<S: Into<String>>(mut self, var: S, bound: Type) -> Self {
+        self.bounds.push((var.into(), bound));
+        self
+    }
+
+    /// Only accepts arguments that bind the type variable `var` to a type whose values
+    /// can be ordered, e.g. the elements for `sort([T])`.
+    pub fn with_ordered<S: Into<String>>(mut self, var: S) -> Self {
+        self.ordered.push(var.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parameters(&self) -> &[Type] {
+        &self.parameters
+    }
+
+    pub fn return_type(&self) -> &Type {
+        &self.return_type
+    }
+}
+
+/// Functions a program can call without defining them: the builtins, and ones a host
+/// embedding the VM registers. The typechecker checks calls against these.
+pub trait SignatureProvider {
+    fn signatures(&self) -> Vec<FunctionSignature>;
+}
+
+impl SignatureProvider for Vec<FunctionSignature> {
+    fn signatures(&self) -> Vec<FunctionSignature> {
+        self.clone()
+    }
 }
+
+impl SignatureProvider for InbuiltFuncMap {
+    fn signatures(&self) -> Vec<FunctionSignature> {
+        self.values()
+            .map(|(signature, _)| signature.clone())
+            .collect()
+    }
+}
+
+/// Methods the runtime calls implicitly, with the signature a class must give them:
+/// `compare(other) -> int` orders instances (negative/zero/positive), and
+/// `hash() -> int` makes instances with equal hashes the same map key,
+/// `equals(other) -> bool` is what `==` asks instead of comparing identity, and
+/// `to_string() -> string` is what `print` shows for an instance.
+fn protocol_signature(class: &str, method: &str) -> Option<FunctionSignature> {
+    match method {
+        "compare" => Some(FunctionSignature::new(
+            method.into(),
+            vec![Type::abra(class)],
+            INTEGER_TYPE,
+        )),
+        "hash" => Some(FunctionSignature::new(method.into(), vec![], INTEGER_TYPE)),
+        "equals" => Some(FunctionSignature::new(
+            method.into(),
+            vec![Type::abra(class)],
+            BOOL_TYPE,
+        )),
+        "to_string" => Some(FunctionSignature::new(method.into(), vec![], STRING_TYPE)),
+        _ => None,
+    }
+}
+
 impl Display for FunctionSignature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}(", self.name)?;
@@ -219,6 +586,15 @@ impl Display for FunctionSignature {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AbraTypeDefinition {
     pub name: String,
+    /// The class this one extends. Its fields and methods are included in this one's.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Interfaces the class implements.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// Type parameters of a generic class, which its fields and methods use as `Type::Var`s.
+    #[serde(default)]
+    pub type_params: Vec<String>,
     pub variables: HashMap<String, VariableDefinition>,
     pub functions: HashMap<String, FunctionSignature>,
 }
@@ -231,6 +607,9 @@ impl AbraTypeDefinition {
     ) -> Self {
         Self {
             name,
+            parent: None,
+            interfaces: Vec::new(),
+            type_params: Vec::new(),
             variables,
             functions,
         }
@@ -239,7 +618,10 @@ impl AbraTypeDefinition {
 
 impl Display for AbraTypeDefinition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "class {} {{", self.name)?;
+        match &self.parent {
+            Some(parent) => writeln!(f, "class {} : {} {{", self.name, parent)?,
+            None => writeln!(f, "class {} {{", self.name)?,
+        }
         for (name, (ty, init)) in &self.variables {
             writeln!(
                 // Apply the fix here
@@ -250,7 +632,7 @@ impl Display for AbraTypeDefinition {
                 init
             )?;
         }
-        for (_, func_sig) in &self.functions {
+        for func_sig in self.functions.values() {
             writeln!(f, "  func {};", func_sig)?;
         }
         write!(f, "}}")
@@ -258,7 +640,7 @@ impl Display for AbraTypeDefinition {
 }
 
 pub enum TypeCheckerMessage {
-    Error(anyhow::Error),
+    Error(TypeError),
     Warning(anyhow::Error),
     Info(anyhow::Error),
 }
@@ -273,17 +655,108 @@ impl Display for TypeCheckerMessage {
     }
 }
 
+impl TypeCheckerMessage {
+    /// File the message points into, if its location names one.
+    pub fn file(&self) -> Option<&str> {
+        let span = match self {
+            TypeCheckerMessage::Error(e) => Some(e.span()),
+            TypeCheckerMessage::Warning(e) | TypeCheckerMessage::Info(e) => {
+                e.downcast_ref::<SourceError>().map(|located| &located.span)
+            }
+        };
+        span.and_then(|span| span.file.as_deref())
+    }
+
+    /// The message with its file, line and column and an excerpt of the line it points at.
+    pub fn render(&self, file: &str, index: &LineIndex) -> String {
+        let (severity, error) = match self {
+            TypeCheckerMessage::Error(e) => {
+                let severity = format!("error[{}]", e.code());
+                return index.render(&severity, e.message(), file, e.span());
+            }
+            TypeCheckerMessage::Warning(w) => ("warning", w),
+            TypeCheckerMessage::Info(i) => ("info", i),
+        };
+        match error.downcast_ref::<SourceError>() {
+            Some(
+                located @ SourceError {
+                    lint: Some(lint), ..
+                },
+            ) => {
+                let severity = format!("{}[{}]", severity, lint);
+                index.render(&severity, &located.message, file, &located.span)
+            }
+            Some(located) => index.render(severity, &located.message, file, &located.span),
+            None => format!("{}: {}\n --> {}", severity, error, file),
+        }
+    }
+}
+
 impl<'a> TypeChecker<'a> {
     pub fn new(ast: &'a Vec<Item>) -> Self {
         Self {
-            ast: ast,
+            ast,
             messages: Vec::new(),
             abra_types: HashMap::new(),
-            global_functions: generate_inbuilt_function_hashmap()
-                .into_iter()
-                .map(|(k, v)| (k, v.0))
-                .collect(),
+            global_functions: HashMap::new(),
+            function_params: HashMap::new(),
+            enums: HashMap::new(),
+            loop_depth: 0,
+            current_class: None,
+            captured: HashSet::new(),
+            declared: HashMap::new(),
+            reassigned: HashSet::new(),
+            exhaustive_matches: Vec::new(),
+            unused_values: HashSet::new(),
+            interfaces: HashSet::new(),
+            globals: HashMap::new(),
+            scopes: None,
+        }
+        .with_signatures(&generate_inbuilt_function_hashmap())
+    }
+
+    /// Remembers the variables in scope at each statement, for [`Self::scope_at`].
+    pub fn record_scopes(mut self) -> Self {
+        self.scopes = Some(Vec::new());
+        self
+    }
+
+    /// Variables in scope at byte `offset` of `file`: those after the last statement
+    /// starting there or before, innermost first. Needs [`Self::record_scopes`].
+    pub fn scope_at(
+        &self,
+        file: &str,
+        offset: usize,
+    ) -> Option<&HashMap<String, VariableDefinition>> {
+        self.scopes
+            .as_ref()?
+            .iter()
+            .filter(|(span, _)| {
+                span.file.as_deref() == Some(file) && span.byte_range.start <= offset
+            })
+            .max_by_key(|(span, _)| span.byte_range.start)
+            .map(|(_, scope)| scope)
+    }
+
+    /// Type of `expression` with the variables in `scope`; `Unknown` if it doesn't check.
+    pub fn type_of(
+        &self,
+        expression: &Expression,
+        scope: &HashMap<String, VariableDefinition>,
+    ) -> Type {
+        match self.type_eval_expression(expression, scope) {
+            (ty, messages) if messages.is_empty() => ty,
+            _ => Type::Unknown,
+        }
+    }
+
+    /// Lets the program call the functions `provider` supplies.
+    pub fn with_signatures(mut self, provider: &dyn SignatureProvider) -> Self {
+        for signature in provider.signatures() {
+            self.global_functions
+                .insert(signature.name.clone(), signature);
         }
+        self
     }
 
     pub fn export(
@@ -295,21 +768,36 @@ impl<'a> TypeChecker<'a> {
         (self.abra_types.clone(), self.global_functions.clone())
     }
 
+    fn implements_protocol(&self, class: &str, method: &str) -> bool {
+        let defined = self
+            .abra_types
+            .get(class)
+            .and_then(|def| def.functions.get(method));
+        defined.is_some() && defined.cloned() == protocol_signature(class, method)
+    }
+
     pub fn check(&mut self) {
         //Two pass type-checking system, we don't do it top-to-bottom style like C we are civilized here.
         // First pass: Collect definitions of classes and global functions
-        for item in self.ast.iter() {
+        let ast = self.ast;
+        // A function of the program takes the place of an inbuilt of the same name.
+        let mut defined_functions = HashSet::new();
+        for item in ast.iter() {
+            let first_message = self.messages.len();
             match item {
                 Item::Class(class) => {
                     let mut ty = AbraTypeDefinition {
                         name: class.name.clone(),
+                        parent: class.parent.clone(),
+                        interfaces: class.interfaces.clone(),
+                        type_params: class.type_params.clone(),
                         variables: HashMap::new(),
                         functions: HashMap::new(),
                     };
 
                     for var in class.variables.iter() {
                         ty.variables
-                            .insert(var.0.clone(), (var.1.clone(), var.2.clone()));
+                            .insert(var.0.clone(), (var.1.clone(), StaticValue::Null));
                     }
 
                     for func in class.functions.iter() {
@@ -323,16 +811,20 @@ impl<'a> TypeChecker<'a> {
                         );
                         if ty.functions.insert(func.name.clone(), func_sig).is_some() {
                             self.messages
-                                .push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                .push(TypeCheckerMessage::Error(type_error!(
+                                    Duplicate,
                                     "Duplicate method definition: '{}' in class '{}'",
                                     func.name,
                                     class.name
                                 )));
                         }
                     }
-                    if self.abra_types.insert(class.name.clone(), ty).is_some() {
+                    if self.abra_types.insert(class.name.clone(), ty).is_some()
+                        || self.enums.contains_key(&class.name)
+                    {
                         self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            .push(TypeCheckerMessage::Error(type_error!(
+                                Duplicate,
                                 "Duplicate class definition: {}",
                                 class.name
                             )));
@@ -347,30 +839,163 @@ impl<'a> TypeChecker<'a> {
                             .collect(),
                         func.return_type.clone(),
                     );
+                    self.global_functions.insert(func.name.clone(), func_sig);
+                    self.function_params
+                        .insert(func.name.clone(), func.params.clone());
+                    if !defined_functions.insert(func.name.clone()) {
+                        self.messages
+                            .push(TypeCheckerMessage::Error(type_error!(
+                                Duplicate,
+                                "Duplicate global function definition: {}",
+                                func.name
+                            )));
+                    }
+                }
+                Item::Enum(enumeration) => {
+                    for (i, (variant, _)) in enumeration.variants.iter().enumerate() {
+                        if enumeration.variants[..i].iter().any(|(v, _)| v == variant) {
+                            self.messages
+                                .push(TypeCheckerMessage::Error(type_error!(
+                                    Duplicate,
+                                    "Duplicate variant '{}' in enum '{}'",
+                                    variant,
+                                    enumeration.name
+                                )));
+                        }
+                    }
                     if self
-                        .global_functions
-                        .insert(func.name.clone(), func_sig)
+                        .enums
+                        .insert(enumeration.name.clone(), enumeration.variants.clone())
                         .is_some()
+                        || self.abra_types.contains_key(&enumeration.name)
                     {
                         self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Duplicate global function definition: {}",
-                                func.name
+                            .push(TypeCheckerMessage::Error(type_error!(
+                                Duplicate,
+                                "Duplicate enum definition: {}",
+                                enumeration.name
+                            )));
+                    }
+                }
+                Item::Interface(interface) => {
+                    let mut ty = AbraTypeDefinition::new(
+                        interface.name.clone(),
+                        HashMap::new(),
+                        HashMap::new(),
+                    );
+                    for (method, params, return_type) in interface.methods.iter() {
+                        self.messages.extend(misplaced_default(params));
+                        let sig = FunctionSignature::new(
+                            method.clone(),
+                            params.iter().map(|p| p.ty.clone()).collect(),
+                            return_type.clone(),
+                        );
+                        if ty.functions.insert(method.clone(), sig).is_some() {
+                            self.messages
+                                .push(TypeCheckerMessage::Error(type_error!(
+                                    Duplicate,
+                                    "Duplicate method '{}' in interface '{}'",
+                                    method,
+                                    interface.name
+                                )));
+                        }
+                    }
+                    if self.abra_types.insert(interface.name.clone(), ty).is_some()
+                        || self.enums.contains_key(&interface.name)
+                    {
+                        self.messages
+                            .push(TypeCheckerMessage::Error(type_error!(
+                                Duplicate,
+                                "Duplicate interface definition: {}",
+                                interface.name
                             )));
                     }
+                    self.interfaces.insert(interface.name.clone());
+                }
+                // Checked once every function is known, since initializers may call them.
+                Item::Global(_) => {}
+                // The module loader replaces imports with the items they name.
+                Item::Import(path) => {
+                    self.messages
+                        .push(TypeCheckerMessage::Error(type_error!(
+                            Undefined,
+                            "Unresolved import of '{}'; imports are resolved when a program is loaded from a file",
+                            path.node
+                        )))
+                }
+            }
+            self.locate_messages(first_message, item.span());
+        }
+
+        let classes: HashMap<&str, &Class> = ast
+            .iter()
+            .filter_map(|item| match item {
+                Item::Class(class) => Some((class.name.as_str(), class)),
+                _ => None,
+            })
+            .collect();
+        let mut inherited = HashSet::new();
+        for item in ast.iter() {
+            if let Item::Class(class) = item {
+                self.inherit(&class.name, &classes, &mut Vec::new(), &mut inherited);
+            }
+        }
+        for class in classes.values() {
+            self.check_implements(class);
+        }
+
+        for class in ast.iter().filter_map(|item| match item {
+            Item::Class(class) => Some(class),
+            _ => None,
+        }) {
+            for func in class.functions.iter() {
+                let sig = match self
+                    .abra_types
+                    .get(&class.name)
+                    .and_then(|class_def| class_def.functions.get(&func.name))
+                {
+                    Some(sig) => sig,
+                    None => continue,
+                };
+                match protocol_signature(&class.name, &func.name) {
+                    Some(expected) if *sig != expected => {
+                        let message = format!(
+                            "Method '{}' in class '{}' must have the signature {}",
+                            func.name, class.name, expected
+                        );
+                        self.messages
+                            .push(TypeCheckerMessage::Error(TypeError::Mismatch(
+                                func.span.clone(),
+                                message,
+                            )))
+                    }
+                    _ => {}
                 }
             }
         }
 
+        for item in ast.iter() {
+            if let Item::Global(global) = item {
+                let first_message = self.messages.len();
+                self.check_global(global);
+                self.locate_messages(first_message, &global.span);
+            }
+        }
+
         // Second pass: Check function bodies and class method bodies
         for item in self.ast.iter().cloned() {
             match item {
                 Item::Class(class) => {
                     // Get the class definition collected in the first pass
                     if let Some(class_def) = self.abra_types.get(&class.name).cloned() {
+                        self.current_class = Some(class.name.clone());
+                        self.check_field_initializers(&class);
                         for func in &class.functions {
+                            let first_message = self.messages.len();
+                            self.messages.extend(misplaced_default(&func.params));
                             // Initialize scope with 'this'/'self' and class members
-                            let mut current_scope_vars = class_def.variables.clone();
+                            let mut current_scope_vars = self.globals.clone();
+                            current_scope_vars.extend(class_def.variables.clone());
                             // Add function parameters to the scope
                             for param in &func.params {
                                 if current_scope_vars
@@ -380,8 +1005,8 @@ impl<'a> TypeChecker<'a> {
                                     )
                                     .is_some()
                                 {
-                                    self.messages
-                                        .push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Duplicate,
                                         "Parameter '{}' in method '{}::{}' shadows a class member.",
                                         param.name,
                                         class.name,
@@ -394,12 +1019,15 @@ impl<'a> TypeChecker<'a> {
                                 &mut current_scope_vars,
                                 Some(&func.return_type),
                             );
+                            self.locate_messages(first_message, &func.span);
                         }
+                        self.current_class = None;
                     }
                 }
                 Item::Function(func) => {
-                    let mut current_scope_vars: HashMap<String, VariableDefinition> =
-                        HashMap::new();
+                    let first_message = self.messages.len();
+                    self.check_defaults(&func.params);
+                    let mut current_scope_vars = self.globals.clone();
                     // Add function parameters to the scope
                     for param in &func.params {
                         current_scope_vars
@@ -410,163 +1038,1154 @@ impl<'a> TypeChecker<'a> {
                         &mut current_scope_vars,
                         Some(&func.return_type),
                     );
+                    self.locate_messages(first_message, &func.span);
+                }
+                Item::Enum(_) | Item::Interface(_) | Item::Import(_) | Item::Global(_) => {}
+            }
+        }
+
+        let assignments = check_assignments(ast, &self.exhaustive_matches);
+        self.messages.extend(assignments);
+        self.messages.extend(lint(ast));
+    }
+
+    /// Globals are initialized in order, so each initializer sees the ones above it.
+    /// Each default has to be a constant of its parameter's type, or `null`, and
+    /// once one parameter has a default the ones after it need one too.
+    fn check_defaults(&mut self, params: &[Parameter]) {
+        let mut defaulted: Option<&Parameter> = None;
+        for param in params {
+            let Some(default) = &param.default else {
+                if let Some(previous) = defaulted {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Misplaced,
+                        "Parameter '{}' needs a default value, as it follows '{}', which has one",
+                        param.name,
+                        previous.name
+                    )));
                 }
+                continue;
+            };
+            defaulted = Some(param);
+            let (default_type, messages) = self.type_eval_expression(default, &self.globals);
+            let failed = !messages.is_empty();
+            self.messages.extend(messages);
+            if failed {
+                continue;
+            }
+            if !default_type.is_subtype_of(&param.ty) {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    Mismatch,
+                    "Default value of parameter '{}': expected type '{}', but got '{}'",
+                    param.name,
+                    param.ty,
+                    default_type
+                )));
+            } else if default_value(default, &self.constant_values()).is_none() {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    Constant,
+                    "Default value of parameter '{}' must be computed from literals and constants",
+                    param.name
+                )));
             }
         }
     }
 
-    fn check_statement_block(
+    /// `args` with the named ones put in place and the left out ones filled with their
+    /// parameter's default, for a call to a top-level function with `params`.
+    fn arranged_arguments(
+        &self,
+        params: &[Parameter],
+        args: &[Expression],
+    ) -> Result<Vec<Expression>, String> {
+        let constants = self.constant_values();
+        let bound = bind_arguments(params, args)?;
+        Ok(params
+            .iter()
+            .zip(bound)
+            .map(|(param, arg)| match (arg, &param.default) {
+                (Some(arg), _) => arg.clone(),
+                (None, Some(default)) => match default_value(default, &constants) {
+                    Some(value) => Expression::Literal(TokenLiteral::Value(value)),
+                    // Reported where the function is checked.
+                    None => default.clone(),
+                },
+                (None, None) => unreachable!("bind_arguments requires the argument"),
+            })
+            .collect())
+    }
+
+    /// Values of the constants declared so far.
+    fn constant_values(&self) -> HashMap<String, StaticValue> {
+        self.globals
+            .iter()
+            .filter(|(_, (_, value))| *value != StaticValue::Null)
+            .map(|(name, (_, value))| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    fn check_global(&mut self, global: &Global) {
+        let (value_type, messages) = self.type_eval_expression(&global.value, &self.globals);
+        let failed = !messages.is_empty();
+        self.messages.extend(messages);
+        if !failed && !value_type.is_subtype_of(&global.ty) {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Mismatch,
+                "Type mismatch in declaration of '{}'. Expected '{}', found '{}'",
+                global.name,
+                global.ty,
+                value_type
+            )));
+        }
+        let value = match global.constant {
+            false => StaticValue::Null,
+            true => match fold_constant(&global.value, &self.constant_values()) {
+                Some(value) => value,
+                None => {
+                    let message = match failed_arithmetic(&global.value, &self.constant_values()) {
+                        Some(failure) => format!("Constant '{}' {}", global.name, failure),
+                        None => format!(
+                            "Constant '{}' must be computed from literals and other constants",
+                            global.name
+                        ),
+                    };
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Constant, "{}", message
+                    )));
+                    StaticValue::Null
+                }
+            },
+        };
+        let redefined = self
+            .globals
+            .insert(global.name.clone(), (global.ty.clone(), value))
+            .is_some();
+        if redefined || self.global_functions.contains_key(&global.name) {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Duplicate,
+                "Duplicate global definition: {}",
+                global.name
+            )));
+        }
+    }
+
+    /// Points the messages from `first` on that don't have a location yet at `span`.
+    /// Adds the fields and methods `name` inherits to its definition, its parent's first.
+    /// A method it overrides must keep the parent's parameters and return a subtype.
+    fn inherit(
         &mut self,
-        stmts: &Vec<Statement>,
-        scope_vars: &mut HashMap<String, VariableDefinition>,
-        expected_return_type: Option<&Type>,
+        name: &str,
+        classes: &HashMap<&str, &Class>,
+        visiting: &mut Vec<String>,
+        done: &mut HashSet<String>,
     ) {
-        for stmt in stmts {
-            match stmt {
-                Statement::Declare(name, declared_type, expr) => {
-                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
-                    self.messages.extend(expr_messages);
-                    if !expr_type.is_subtype_of(declared_type) {
-                        self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Type mismatch in declaration of '{}'. Expected '{}', found '{}'",
-                                name,
-                                declared_type,
-                                expr_type
-                            )));
-                    }
-                    if scope_vars
-                        .insert(name.clone(), (declared_type.clone(), StaticValue::Null))
-                        .is_some()
-                    {
-                        self.messages
-                            .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
-                                "Variable '{}' shadows a variable in an outer scope.",
-                                name
-                            )));
-                    }
+        if done.contains(name) {
+            return;
+        }
+        let (class, mut def) = match (classes.get(name), self.abra_types.get(name)) {
+            (Some(class), Some(def)) => (*class, def.clone()),
+            _ => return,
+        };
+        let first_message = self.messages.len();
+        let parent_def = match &def.parent {
+            None => None,
+            Some(parent) if visiting.iter().any(|v| v == parent) || parent == name => {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    InvalidOperation,
+                    "Class '{}' inherits from itself",
+                    name
+                )));
+                None
+            }
+            Some(parent) if self.interfaces.contains(parent) => {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    InvalidOperation,
+                    "Class '{}' can't extend interface '{}'; use 'implements {}'",
+                    name,
+                    parent,
+                    parent
+                )));
+                None
+            }
+            Some(parent) => {
+                visiting.push(name.to_string());
+                self.inherit(parent, classes, visiting, done);
+                visiting.pop();
+                let parent_def = self.abra_types.get(parent).cloned();
+                if parent_def.is_none() {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Undefined,
+                        "Class '{}' extends unknown class '{}'",
+                        name,
+                        parent
+                    )));
                 }
-                Statement::Set(name, expr) => {
-                    if !scope_vars.contains_key(name) {
-                        self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Variable '{}' not found for assignment.",
-                                name
-                            )));
-                        continue;
+                parent_def
+            }
+        };
+        if let Some(parent_def) = parent_def {
+            for (field, variable) in parent_def.variables.iter() {
+                if def.variables.contains_key(field) {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Duplicate,
+                        "Field '{}' of '{}' is already defined by '{}'",
+                        field,
+                        name,
+                        parent_def.name
+                    )));
+                } else {
+                    def.variables.insert(field.clone(), variable.clone());
+                }
+            }
+            for (method, sig) in parent_def.functions.iter() {
+                match def.functions.get(method) {
+                    Some(own)
+                        if own.parameters != sig.parameters
+                            || !own.return_type.is_subtype_of(&sig.return_type) =>
+                    {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Method '{}::{}' must keep the signature {} of the method it overrides",
+                            name,
+                            method,
+                            sig
+                        )));
                     }
-                    let (expected_var_type, _) = scope_vars.get(name).unwrap();
-                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
-                    self.messages.extend(expr_messages);
-                    if !expr_type.is_subtype_of(expected_var_type) {
-                        self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Type mismatch in assignment to '{}'. Expected '{}', found '{}'",
-                                name,
-                                expected_var_type,
-                                expr_type
-                            )));
+                    Some(_) => {}
+                    None => {
+                        def.functions.insert(method.clone(), sig.clone());
                     }
                 }
-                Statement::Expression(expr) => {
-                    let (_, expr_messages) = self.type_eval_expression(expr, scope_vars);
-                    self.messages.extend(expr_messages);
-                    // Result of expression statement is usually ignored, but errors are collected.
-                }
-                Statement::Print(expr) => {
-                    let (_, expr_messages) = self.type_eval_expression(expr, scope_vars); // Evaluate for side-effects/errors
-                    self.messages.extend(expr_messages);
-                    // Print can usually take any type, specific checks could be added if needed.
-                }
-                Statement::Return(opt_expr) => {
-                    let return_expr_type = match opt_expr {
-                        Some(expr) => {
-                            let (t, expr_messages) = self.type_eval_expression(expr, scope_vars);
-                            self.messages.extend(expr_messages);
-                            t
-                        }
-                        None => Type::Null, // Or a specific "Void" type if your language has it
+            }
+        } else {
+            def.parent = None;
+        }
+        register_class(&def);
+        self.abra_types.insert(name.to_string(), def);
+        done.insert(name.to_string());
+        self.locate_messages(first_message, &class.span);
+    }
+
+    /// The variables whose type is narrowed while `cond` is `when`: `x is T` makes `x`
+    /// a `T` when true and takes `T` out of its type when false. `None` when there are
+    /// none, so callers can keep using the enclosing scope.
+    fn narrowed(
+        &self,
+        variables: &HashMap<String, VariableDefinition>,
+        cond: &Expression,
+        when: bool,
+    ) -> Option<HashMap<String, VariableDefinition>> {
+        fn collect(
+            cond: &Expression,
+            when: bool,
+            variables: &HashMap<String, VariableDefinition>,
+            out: &mut Vec<(String, Type)>,
+        ) {
+            match cond {
+                Expression::Is(value, ty) => {
+                    let Expression::Literal(TokenLiteral::Identifier(name)) = &**value else {
+                        return;
                     };
-                    if let Some(expected_ret_ty) = expected_return_type {
-                        if !return_expr_type.is_subtype_of(expected_ret_ty) {
-                            self.messages
-                                .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                    "Return type mismatch. Expected '{}', found '{}'",
-                                    expected_ret_ty,
-                                    return_expr_type
-                                )));
-                        }
-                    } else {
-                        self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Return statement outside of a function."
-                            )));
+                    let Some((declared, _)) = variables.get(name) else {
+                        return;
+                    };
+                    if when {
+                        out.push((name.clone(), ty.clone()));
+                    } else if let Some(rest) = declared.without(ty) {
+                        out.push((name.clone(), rest));
                     }
                 }
-                Statement::If(cond_expr, then_block, else_opt_block) => {
-                    let (cond_type, cond_messages) =
-                        self.type_eval_expression(cond_expr, scope_vars);
-                    self.messages.extend(cond_messages);
-                    if !cond_type.is_subtype_of(&BOOL_TYPE) {
+                Expression::Grouping(inner) => collect(inner, when, variables, out),
+                Expression::Unary(UnaryOpCode::NOT, inner) => collect(inner, !when, variables, out),
+                // Both sides hold when `and` is true, and neither does when `or` is false.
+                Expression::Binary(BinOpCode::AND, lhs, rhs) if when => {
+                    collect(lhs, when, variables, out);
+                    collect(rhs, when, variables, out);
+                }
+                Expression::Binary(BinOpCode::OR, lhs, rhs) if !when => {
+                    collect(lhs, when, variables, out);
+                    collect(rhs, when, variables, out);
+                }
+                _ => {}
+            }
+        }
+        let mut narrowings = Vec::new();
+        collect(cond, when, variables, &mut narrowings);
+        if narrowings.is_empty() {
+            return None;
+        }
+        let mut scope = variables.clone();
+        for (name, ty) in narrowings {
+            if let Some(var) = scope.get_mut(&name) {
+                var.0 = ty;
+            }
+        }
+        Some(scope)
+    }
+
+    /// Checks `block` in a scope of its own, with the variables `cond` narrows while it
+    /// is `when`. Assignments to them are checked against the type they had before.
+    fn check_narrowed_block(
+        &mut self,
+        block: &Block,
+        scope_vars: &HashMap<String, VariableDefinition>,
+        cond: &Expression,
+        when: bool,
+        expected_return_type: Option<&Type>,
+    ) {
+        let Some(mut scope) = self.narrowed(scope_vars, cond, when) else {
+            self.check_statement_block(block, &mut scope_vars.clone(), expected_return_type);
+            return;
+        };
+        let declared = self.declared.clone();
+        for (name, (ty, _)) in scope_vars {
+            if scope.get(name).is_some_and(|(narrowed, _)| narrowed != ty) {
+                // An enclosing narrowing has already recorded the declared type.
+                self.declared
+                    .entry(name.clone())
+                    .or_insert_with(|| ty.clone());
+            }
+        }
+        let reassigned = std::mem::take(&mut self.reassigned);
+        self.check_statement_block(block, &mut scope, expected_return_type);
+        // Assignments also undo the enclosing narrowings of the same variable.
+        self.reassigned.retain(|name| declared.contains_key(name));
+        self.reassigned.extend(reassigned);
+        self.declared = declared;
+    }
+
+    /// Widens the narrowed variables `stmts` assign back to their declared type, since
+    /// a loop comes back to its start after assigning them.
+    fn widen_assigned<'s>(
+        &self,
+        stmts: impl IntoIterator<Item = &'s Statement>,
+        scope_vars: &mut HashMap<String, VariableDefinition>,
+    ) {
+        let mut assigned = HashSet::new();
+        for stmt in stmts {
+            assigned_names(stmt, &mut assigned);
+        }
+        for name in assigned {
+            if let (Some(ty), Some(var)) = (self.declared.get(name), scope_vars.get_mut(name)) {
+                var.0 = ty.clone();
+            }
+        }
+    }
+
+    /// The class `ty` is an instance of, with its type parameters bound to the type's
+    /// arguments; a generic class named without them leaves them unbound.
+    fn class_of(&self, ty: &Type) -> Option<(String, HashMap<String, Type>)> {
+        match ty {
+            Type::Abra(name) => Some((name.clone(), HashMap::new())),
+            Type::Generic(name, args) => {
+                let params = self
+                    .abra_types
+                    .get(name)
+                    .map(|def| def.type_params.clone())
+                    .unwrap_or_default();
+                let bindings = params.into_iter().zip(args.iter().cloned()).collect();
+                Some((name.clone(), bindings))
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that `class`, with what it inherits, defines every method of the
+    /// interfaces it names, with the interface's parameters and a subtype of its
+    /// return type.
+    fn check_implements(&mut self, class: &Class) {
+        let first_message = self.messages.len();
+        let class_def = match self.abra_types.get(&class.name) {
+            Some(class_def) => class_def.clone(),
+            None => return,
+        };
+        for interface in class.interfaces.iter() {
+            let interface_def = match self.abra_types.get(interface) {
+                Some(def) if self.interfaces.contains(interface) => def.clone(),
+                _ => {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Undefined,
+                        "Class '{}' implements unknown interface '{}'",
+                        class.name,
+                        interface
+                    )));
+                    continue;
+                }
+            };
+            let mut methods: Vec<_> = interface_def.functions.iter().collect();
+            methods.sort_by(|a, b| a.0.cmp(b.0));
+            for (method, sig) in methods {
+                match class_def.functions.get(method) {
+                    Some(own)
+                        if own.parameters == sig.parameters
+                            && own.return_type.is_subtype_of(&sig.return_type) => {}
+                    Some(_) => self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Mismatch,
+                        "Method '{}::{}' must have the signature {} to implement '{}'",
+                        class.name,
+                        method,
+                        sig,
+                        interface
+                    ))),
+                    None => self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Undefined,
+                        "Class '{}' must define {} to implement '{}'",
+                        class.name,
+                        sig,
+                        interface
+                    ))),
+                }
+            }
+        }
+        self.locate_messages(first_message, &class.span);
+    }
+
+    /// Initializers run in declaration order, so each one sees the fields above it,
+    /// as well as the inherited ones.
+    fn check_field_initializers(&mut self, class: &Class) {
+        let first_message = self.messages.len();
+        let mut scope_vars = self.globals.clone();
+        if let Some(def) = self.abra_types.get(&class.name) {
+            for (name, variable) in def.variables.iter() {
+                if !class.variables.iter().any(|(own, _, _)| own == name) {
+                    scope_vars.insert(name.clone(), variable.clone());
+                }
+            }
+        }
+        for (name, ty, init) in class.variables.iter() {
+            if let Some(init) = init {
+                let (init_type, init_messages) = self.type_eval_expression(init, &scope_vars);
+                self.messages.extend(init_messages);
+                if !init_type.is_subtype_of(ty) {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Mismatch,
+                        "Type mismatch in initializer of '{}::{}'. Expected '{}', found '{}'",
+                        class.name,
+                        name,
+                        ty,
+                        init_type
+                    )));
+                }
+            } else if !starts_with_value(ty, &class.type_params) {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    Unassigned,
+                    "Field '{}::{}' of type '{}' needs an initializer, or an optional type",
+                    class.name,
+                    name,
+                    ty
+                )));
+            }
+            scope_vars.insert(name.clone(), (ty.clone(), StaticValue::Null));
+        }
+        self.locate_messages(first_message, &class.span);
+    }
+
+    fn locate_messages(&mut self, first: usize, span: &Span) {
+        for message in self.messages[first..].iter_mut() {
+            let error = match message {
+                TypeCheckerMessage::Error(e) => {
+                    if e.span().line == 0 {
+                        *e.span_mut() = span.clone();
+                    }
+                    continue;
+                }
+                TypeCheckerMessage::Warning(e) | TypeCheckerMessage::Info(e) => e,
+            };
+            if error.downcast_ref::<SourceError>().is_none() {
+                let located = SourceError::new(span.clone(), error.to_string());
+                *error = anyhow::Error::new(located);
+            }
+        }
+    }
+
+    /// Checks an `Enum::Variant(bindings)` arm of a match on `subject` and returns the
+    /// payload types for the bindings; unknown ones are null.
+    fn check_variant_pattern(
+        &mut self,
+        name: &str,
+        bindings: &[String],
+        subject: &Type,
+        subject_failed: bool,
+    ) -> Vec<Type> {
+        let (enumeration, payload) = match self.enum_variant(name) {
+            Some((enumeration, payload)) => (enumeration.to_string(), payload.to_vec()),
+            None => {
+                self.messages.push(TypeCheckerMessage::Error(type_error!(
+                    Undefined,
+                    "Enum variant '{}' not found",
+                    name
+                )));
+                return vec![Type::Unknown; bindings.len()];
+            }
+        };
+        if !subject_failed && !Type::abra(&enumeration).is_subtype_of(subject) {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Mismatch,
+                "Pattern '{}' can never match a value of type '{}'",
+                name,
+                subject
+            )));
+        }
+        if bindings.len() != payload.len() {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Arity,
+                "Variant '{}' carries {} values, but the pattern names {}",
+                name,
+                payload.len(),
+                bindings.len()
+            )));
+            return vec![Type::Unknown; bindings.len()];
+        }
+        payload
+    }
+
+    fn check_statement_block(
+        &mut self,
+        stmts: &Block,
+        scope_vars: &mut HashMap<String, VariableDefinition>,
+        expected_return_type: Option<&Type>,
+    ) {
+        // A variable declared in the block hides a captured one until the block ends.
+        let captured = self.captured.clone();
+        let declared = self.declared.clone();
+        for located in stmts {
+            let first_message = self.messages.len();
+            let stmt = &located.node;
+            match stmt {
+                Statement::Destructure(names, expr) => {
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    let types = match &expr_type {
+                        Type::Composite(composite) => match &**composite {
+                            Composite::Tuple(types) if types.len() == names.len() => {
+                                Some(types.clone())
+                            }
+                            _ => None,
+                        },
+                        Type::Unknown => Some(vec![Type::Unknown; names.len()]),
+                        _ => None,
+                    };
+                    let types = types.unwrap_or_else(|| {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Cannot unpack a value of type '{}' into {} variables",
+                            expr_type,
+                            names.len()
+                        )));
+                        vec![Type::Unknown; names.len()]
+                    });
+                    for (name, ty) in names.iter().zip(types) {
+                        if name != "_" {
+                            self.captured.remove(name);
+                            self.declared.remove(name);
+                            scope_vars.insert(name.clone(), (ty, StaticValue::Null));
+                        }
+                    }
+                }
+                Statement::Declare(name, declared_type, expr) => {
+                    // Without a value, the assignments that follow are checked against the type.
+                    let (expr_type, expr_messages) = match expr {
+                        Some(expr) => self.type_eval_expression(expr, scope_vars),
+                        None => (declared_type.clone(), Vec::new()),
+                    };
+                    self.messages.extend(expr_messages);
+                    if !expr_type.is_subtype_of(declared_type) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Type mismatch in declaration of '{}'. Expected '{}', found '{}'",
+                            name,
+                            declared_type,
+                            expr_type
+                        )));
+                    }
+                    self.captured.remove(name);
+                    self.declared.remove(name);
+                    if scope_vars
+                        .insert(name.clone(), (declared_type.clone(), StaticValue::Null))
+                        .is_some()
+                    {
                         self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "If condition must be a boolean, found '{}'",
-                                cond_type
+                            .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                                "Variable '{}' shadows a variable in an outer scope.",
+                                name
+                            )));
+                    }
+                }
+                Statement::Set(Some(object), member, expr) => {
+                    let target = Expression::Get(member.clone(), Box::new(object.clone()));
+                    let (member_type, target_messages) =
+                        self.type_eval_expression(&target, scope_vars);
+                    let failed = !target_messages.is_empty();
+                    self.messages.extend(target_messages);
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    if !failed && !expr_type.is_subtype_of(&member_type) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Type mismatch in assignment to '{}'. Expected '{}', found '{}'",
+                            expression(&target),
+                            member_type,
+                            expr_type
+                        )));
+                    }
+                }
+                Statement::SetIndex(collection, index, expr) => {
+                    let target =
+                        Expression::Index(Box::new(collection.clone()), Box::new(index.clone()));
+                    let (element_type, target_messages) =
+                        self.type_eval_expression(&target, scope_vars);
+                    let failed = !target_messages.is_empty();
+                    self.messages.extend(target_messages);
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    if !failed && !expr_type.is_subtype_of(&element_type) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Type mismatch in assignment to '{}'. Expected '{}', found '{}'",
+                            expression(&target),
+                            element_type,
+                            expr_type
+                        )));
+                    }
+                }
+                Statement::Set(None, name, _)
+                | Statement::Compound(Expression::Literal(TokenLiteral::Identifier(name)), _, _)
+                    if self.captured.contains(name) =>
+                {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Misplaced,
+                        "Cannot assign to '{}' in a nested function; it only gets a copy of the variables around it",
+                        name
+                    )));
+                }
+                Statement::Set(None, name, _)
+                | Statement::Compound(Expression::Literal(TokenLiteral::Identifier(name)), _, _)
+                    if is_constant(name, scope_vars) =>
+                {
+                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                        Constant,
+                        "Cannot assign to constant '{}'",
+                        name
+                    )));
+                }
+                Statement::Compound(target, op, expr) => {
+                    let (target_type, target_messages) =
+                        self.type_eval_expression(target, scope_vars);
+                    let failed = !target_messages.is_empty();
+                    self.messages.extend(target_messages);
+                    if failed {
+                        continue;
+                    }
+                    let narrowed = match target {
+                        Expression::Literal(TokenLiteral::Identifier(name)) => {
+                            self.declared.get(name).map(|ty| (name, ty.clone()))
+                        }
+                        _ => None,
+                    };
+                    let target_type = match &narrowed {
+                        Some((name, ty)) => {
+                            self.reassigned.insert(name.to_string());
+                            ty.clone()
+                        }
+                        None => target_type,
+                    };
+                    let value = Expression::Binary(
+                        op.clone(),
+                        Box::new(target.clone()),
+                        Box::new(expr.clone()),
+                    );
+                    let (value_type, value_messages) =
+                        self.type_eval_expression(&value, scope_vars);
+                    let failed = !value_messages.is_empty();
+                    self.messages.extend(value_messages);
+                    if !failed && !value_type.is_subtype_of(&target_type) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Type mismatch in '{} {}= {}'. Expected '{}', found '{}'",
+                            expression(target),
+                            op,
+                            expression(expr),
+                            target_type,
+                            value_type
+                        )));
+                    }
+                }
+                Statement::Set(None, name, expr) => {
+                    if !scope_vars.contains_key(name) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Undefined,
+                            "Variable '{}' not found for assignment.",
+                            name
+                        )));
+                        continue;
+                    }
+                    // A narrowed variable can be given any value of its declared type.
+                    let expected_var_type = match self.declared.get(name) {
+                        Some(ty) => ty.clone(),
+                        None => scope_vars.get(name).unwrap().0.clone(),
+                    };
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    if !expr_type.is_subtype_of(&expected_var_type) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Type mismatch in assignment to '{}'. Expected '{}', found '{}'",
+                            name,
+                            expected_var_type,
+                            expr_type
+                        )));
+                    }
+                    if self.declared.contains_key(name) {
+                        self.reassigned.insert(name.clone());
+                    }
+                }
+                Statement::Expression(expr) => {
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    if expr_type != Type::Null {
+                        self.unused_values.insert(located.span.clone());
+                    }
+                }
+                Statement::Print(expr) => {
+                    let (_, expr_messages) = self.type_eval_expression(expr, scope_vars); // Evaluate for side-effects/errors
+                    self.messages.extend(expr_messages);
+                    // Anything can be printed, see the `print` inbuilt it compiles to.
+                }
+                Statement::Return(opt_expr) => {
+                    let return_expr_type = match opt_expr {
+                        Some(expr) => {
+                            let (t, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                            self.messages.extend(expr_messages);
+                            t
+                        }
+                        None => Type::Null, // Or a specific "Void" type if your language has it
+                    };
+                    if let Some(expected_ret_ty) = expected_return_type {
+                        if !return_expr_type.is_subtype_of(expected_ret_ty) {
+                            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                                Mismatch,
+                                "Return type mismatch. Expected '{}', found '{}'",
+                                expected_ret_ty,
+                                return_expr_type
                             )));
+                        }
+                    } else {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Misplaced,
+                            "Return statement outside of a function."
+                        )));
+                    }
+                }
+                Statement::If(cond_expr, then_block, else_opt_block) => {
+                    let (cond_type, cond_messages) =
+                        self.type_eval_expression(cond_expr, scope_vars);
+                    self.messages.extend(cond_messages);
+                    if !cond_type.is_subtype_of(&BOOL_TYPE) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "If condition must be a boolean, found '{}'",
+                            cond_type
+                        )));
                     }
-                    let mut then_scope = scope_vars.clone(); // Create a new scope for the 'then' block
-                    self.check_statement_block(then_block, &mut then_scope, expected_return_type);
+                    // Each branch gets its own scope, with the variables the condition
+                    // type-tests narrowed.
+                    self.check_narrowed_block(
+                        then_block,
+                        scope_vars,
+                        cond_expr,
+                        true,
+                        expected_return_type,
+                    );
                     if let Some(else_block) = else_opt_block {
-                        let mut else_scope = scope_vars.clone(); // Create a new scope for the 'else' block
-                        self.check_statement_block(
+                        self.check_narrowed_block(
                             else_block,
-                            &mut else_scope,
+                            scope_vars,
+                            cond_expr,
+                            false,
                             expected_return_type,
                         );
                     }
                 }
                 Statement::For(init_stmt, cond_expr, incr_stmt, opt_body) => {
                     let mut for_scope = scope_vars.clone(); // New scope for the loop
-                    if let init = init_stmt.as_ref() {
-                        // Assuming For's init is Option<Box<Statement>>
-                        self.check_statement_block(
-                            &vec![init.clone()],
-                            &mut for_scope,
-                            expected_return_type,
-                        ); // Check init in the new scope
-                    }
+                    self.check_statement_block(
+                        &vec![Located::new(
+                            init_stmt.as_ref().clone(),
+                            located.span.clone(),
+                        )],
+                        &mut for_scope,
+                        expected_return_type,
+                    ); // Check init in the new scope
+                    let body_stmts = opt_body.iter().flatten().map(|located| &located.node);
+                    self.widen_assigned(body_stmts.chain([&**incr_stmt]), &mut for_scope);
 
                     let (cond_type, cond_messages) =
                         self.type_eval_expression(cond_expr, &for_scope); // Condition uses the new scope
                     self.messages.extend(cond_messages);
                     if !cond_type.is_subtype_of(&BOOL_TYPE) {
-                        self.messages
-                            .push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "For loop condition must be a boolean, found '{}'",
-                                cond_type
-                            )));
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "For loop condition must be a boolean, found '{}'",
+                            cond_type
+                        )));
                     }
 
                     if let Some(body_stmts) = opt_body {
                         let mut body_scope = for_scope.clone(); // Body also gets its own sub-scope from the for_scope
+                        self.loop_depth += 1;
                         self.check_statement_block(
                             body_stmts,
                             &mut body_scope,
                             expected_return_type,
                         );
+                        self.loop_depth -= 1;
                     }
 
-                    if let incr = incr_stmt.as_ref() {
-                        // Assuming For's incr is Option<Box<Statement>>
-                        self.check_statement_block(
-                            &vec![incr.clone()],
-                            &mut for_scope,
-                            expected_return_type,
-                        ); // Increment uses the for_scope
+                    self.check_statement_block(
+                        &vec![Located::new(
+                            incr_stmt.as_ref().clone(),
+                            located.span.clone(),
+                        )],
+                        &mut for_scope,
+                        expected_return_type,
+                    ); // Increment uses the for_scope
+                }
+                Statement::While(cond_expr, body) => {
+                    let (cond_type, cond_messages) =
+                        self.type_eval_expression(cond_expr, scope_vars);
+                    self.messages.extend(cond_messages);
+                    if !cond_type.is_subtype_of(&BOOL_TYPE) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "While loop condition must be a boolean, found '{}'",
+                            cond_type
+                        )));
+                    }
+                    // Each iteration starts from the enclosing scope, narrowed by the condition
+                    self.widen_assigned(body.iter().map(|located| &located.node), scope_vars);
+                    self.loop_depth += 1;
+                    self.check_narrowed_block(
+                        body,
+                        scope_vars,
+                        cond_expr,
+                        true,
+                        expected_return_type,
+                    );
+                    self.loop_depth -= 1;
+                }
+                Statement::Break | Statement::Continue => {
+                    if self.loop_depth == 0 {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Misplaced,
+                            "'{}' outside of a loop.",
+                            stmt
+                        )));
+                    }
+                }
+                Statement::Throw(expr) => {
+                    let (expr_type, expr_messages) = self.type_eval_expression(expr, scope_vars);
+                    self.messages.extend(expr_messages);
+                    if !expr_type.is_subtype_of(&STRING_TYPE) {
+                        self.messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Thrown value must be a string, found '{}'",
+                            expr_type
+                        )));
+                    }
+                }
+                Statement::Try(body, name, handler) => {
+                    let mut body_scope = scope_vars.clone();
+                    self.check_statement_block(body, &mut body_scope, expected_return_type);
+                    // The catch body sees the error message, whether it came from `throw`
+                    // or from a failing operation.
+                    let mut handler_scope = scope_vars.clone();
+                    if handler_scope
+                        .insert(name.clone(), (STRING_TYPE, StaticValue::Null))
+                        .is_some()
+                    {
+                        self.messages
+                            .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                                "Variable '{}' shadows a variable in an outer scope.",
+                                name
+                            )));
                     }
+                    let captured = self.captured.clone();
+                    let declared = self.declared.clone();
+                    self.captured.remove(name);
+                    self.declared.remove(name);
+                    self.check_statement_block(handler, &mut handler_scope, expected_return_type);
+                    self.captured = captured;
+                    self.declared = declared;
                 }
+                Statement::Match(subject, arms) => {
+                    let (subject_type, subject_messages) =
+                        self.type_eval_expression(subject, scope_vars);
+                    let failed = !subject_messages.is_empty();
+                    self.messages.extend(subject_messages);
+                    let mut catch_all = false;
+                    for (pattern, body) in arms {
+                        if catch_all {
+                            self.messages
+                                .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                                "Match arm '{}' is unreachable, an earlier '_' matches everything.",
+                                pattern
+                            )));
+                        }
+                        match pattern {
+                            Pattern::Literal(value) | Pattern::Range(value, _) => {
+                                let literal =
+                                    Expression::Literal(TokenLiteral::Value(value.clone()));
+                                let (pattern_type, _) =
+                                    self.type_eval_expression(&literal, scope_vars);
+                                if !failed && !pattern_type.is_subtype_of(&subject_type) {
+                                    self.messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Mismatch,
+                                        "Pattern '{}' can never match a value of type '{}'",
+                                        pattern,
+                                        subject_type
+                                    )));
+                                }
+                            }
+                            Pattern::Type(_) | Pattern::Variant(..) => {}
+                            Pattern::Wildcard => catch_all = true,
+                        }
+                        let mut arm_scope = scope_vars.clone();
+                        let captured = self.captured.clone();
+                        let declared = self.declared.clone();
+                        if let Pattern::Variant(name, bindings) = pattern {
+                            let payload =
+                                self.check_variant_pattern(name, bindings, &subject_type, failed);
+                            for (binding, ty) in bindings.iter().zip(payload) {
+                                if binding != "_" {
+                                    self.captured.remove(binding);
+                                    self.declared.remove(binding);
+                                    arm_scope.insert(binding.clone(), (ty, StaticValue::Null));
+                                }
+                            }
+                        }
+                        self.check_statement_block(body, &mut arm_scope, expected_return_type);
+                        self.captured = captured;
+                        self.declared = declared;
+                    }
+                    let variants = match &subject_type {
+                        Type::Abra(name) => self.enums.get(name).map(Vec::as_slice),
+                        _ => None,
+                    };
+                    let uncovered = uncovered_cases(&subject_type, variants, arms);
+                    // Only bools, enums and unions have cases to cover.
+                    let has_cases = variants.is_some()
+                        || subject_type == BOOL_TYPE
+                        || matches!(subject_type, Type::Algebraic(_));
+                    if catch_all || (!failed && has_cases && uncovered.is_empty()) {
+                        self.exhaustive_matches.push(located.span.clone());
+                    }
+                    if !failed && !uncovered.is_empty() {
+                        self.messages
+                            .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                                "Match on '{}' doesn't cover {}; add those arms or a '_' arm.",
+                                subject_type,
+                                uncovered.join(", ")
+                            )));
+                    }
+                }
+                Statement::Function(func) => self.check_nested_function(func, scope_vars),
                 Statement::Null => { /* No operation, no type checking needed */ }
             }
+            for name in &self.reassigned {
+                if let (Some(ty), Some(var)) = (self.declared.get(name), scope_vars.get_mut(name)) {
+                    var.0 = ty.clone();
+                }
+            }
+            self.locate_messages(first_message, &located.span);
+            if let Some(scopes) = &mut self.scopes {
+                scopes.push((located.span.clone(), scope_vars.clone()));
+            }
+        }
+        self.captured = captured;
+        self.declared = declared;
+    }
+
+    /// Declares `func` in `scope_vars` and checks its body, which sees the enclosing
+    /// variables as they are when `func` is declared, and itself.
+    fn check_nested_function(
+        &mut self,
+        func: &Function,
+        scope_vars: &mut HashMap<String, VariableDefinition>,
+    ) {
+        if self.current_class.is_some() {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Misplaced,
+                "Function '{}' can't be declared in a method, only in a function",
+                func.name
+            )));
+        }
+        if !func.type_params.is_empty() {
+            self.messages.push(TypeCheckerMessage::Error(type_error!(
+                Misplaced,
+                "Nested function '{}' can't have type parameters",
+                func.name
+            )));
+        }
+        self.messages.extend(misplaced_default(&func.params));
+        let param_types = func.params.iter().map(|param| param.ty.clone()).collect();
+        let ty = Type::function(param_types, func.return_type.clone());
+        if scope_vars
+            .insert(func.name.clone(), (ty, StaticValue::Null))
+            .is_some()
+        {
+            self.messages
+                .push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                    "Function '{}' shadows a variable in an outer scope.",
+                    func.name
+                )));
+        }
+
+        let mut body_scope = scope_vars.clone();
+        let enclosing_locals = scope_vars
+            .iter()
+            .filter(|(name, var)| self.globals.get(*name) != Some(var))
+            .map(|(name, _)| name.clone());
+        let mut captured = self.captured.clone();
+        captured.extend(enclosing_locals);
+        for param in func.params.iter() {
+            captured.remove(&param.name);
+            body_scope.insert(param.name.clone(), (param.ty.clone(), StaticValue::Null));
+        }
+        let captured = std::mem::replace(&mut self.captured, captured);
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        // The body can't assign the variables around it, narrowed or not.
+        let declared = std::mem::take(&mut self.declared);
+        let reassigned = std::mem::take(&mut self.reassigned);
+        self.check_statement_block(&func.body, &mut body_scope, Some(&func.return_type));
+        self.captured = captured;
+        self.loop_depth = loop_depth;
+        self.declared = declared;
+        self.reassigned = reassigned;
+    }
+
+    /// Enum and payload types of the variant named `Enum::Variant`.
+    fn enum_variant(&self, name: &str) -> Option<(&str, &[Type])> {
+        let (enumeration, variant) = name.rsplit_once("::")?;
+        let (enum_name, variants) = self.enums.get_key_value(enumeration)?;
+        variants
+            .iter()
+            .find(|(v, _)| v == variant)
+            .map(|(_, payload)| (enum_name.as_str(), payload.as_slice()))
+    }
+
+    /// Type of a top-level function named `name` used as a value. Builtins can only
+    /// be called directly, so they have none.
+    fn user_function_type(&self, name: &str) -> Option<Type> {
+        self.ast.iter().find_map(|item| match item {
+            Item::Function(func) if func.name == name => Some(Type::function(
+                func.params.iter().map(|param| param.ty.clone()).collect(),
+                func.return_type.clone(),
+            )),
+            _ => None,
+        })
+    }
+
+    /// Checks a call of `name` against `signature`, binding its type variables from the
+    /// arguments within their bounds, and returns the call's type.
+    fn check_call(
+        &self,
+        name: &str,
+        signature: &FunctionSignature,
+        arg_exprs_vec: &[Expression],
+        variables: &HashMap<String, VariableDefinition>,
+    ) -> (Type, Vec<TypeCheckerMessage>) {
+        let parameters = &signature.parameters;
+        let mut messages: Vec<TypeCheckerMessage> = Vec::new();
+        if arg_exprs_vec.len() != parameters.len() {
+            messages.push(TypeCheckerMessage::Error(type_error!(
+                Arity,
+                "Function '{}' expected {} arguments, but got {}",
+                name,
+                parameters.len(),
+                arg_exprs_vec.len()
+            )));
+            return (Type::Unknown, messages);
+        }
+        let mut arg_types = Vec::with_capacity(arg_exprs_vec.len());
+        let mut bindings = HashMap::new();
+        for (i, arg_expr) in arg_exprs_vec.iter().enumerate() {
+            let (arg_type_val, arg_messages) = self.type_eval_expression(arg_expr, variables);
+            messages.extend(arg_messages);
+            parameters[i].bind_vars(&arg_type_val, &mut bindings);
+            arg_types.push(arg_type_val);
+        }
+        for (i, arg_type_val) in arg_types.iter().enumerate() {
+            let bound = match &parameters[i] {
+                Type::Var(var) => signature.bounds.iter().find(|(name, _)| name == var),
+                _ => None,
+            };
+            let expected = match bound {
+                Some((_, bound)) if !arg_type_val.is_subtype_of(bound) => bound.clone(),
+                _ => parameters[i].substitute(&bindings),
+            };
+            if !arg_type_val.is_subtype_of(&expected) {
+                messages.push(TypeCheckerMessage::Error(type_error!(
+                    Mismatch,
+                    "Argument {} for function '{}': expected type '{}', but got '{}'",
+                    i + 1,
+                    name,
+                    expected,
+                    arg_type_val
+                )));
+            }
+        }
+        for var in &signature.ordered {
+            match bindings.get(var) {
+                Some(ty) if !self.is_ordered(ty) => {
+                    messages.push(TypeCheckerMessage::Error(type_error!(
+                        Mismatch,
+                        "Function '{}' can't order values of type '{}'; it needs numbers, chars, or a class with a 'compare' method",
+                        name,
+                        ty
+                    )));
+                }
+                _ => {}
+            }
+        }
+        (signature.return_type.substitute(&bindings), messages)
+    }
+
+    /// Whether `<` can order two values of type `ty`.
+    fn is_ordered(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Primitive(
+                Primitives::Integer | Primitives::Float | Primitives::BigInt | Primitives::Char,
+            )
+            | Type::Unknown => true,
+            Type::Abra(class) => self.implements_protocol(class, "compare"),
+            _ => false,
+        }
+    }
+
+    /// Checks a call through a value of type `callee_type`, which must be a function.
+    fn check_value_call(
+        &self,
+        name: &str,
+        callee_type: &Type,
+        arg_exprs_vec: &[Expression],
+        variables: &HashMap<String, VariableDefinition>,
+    ) -> (Type, Vec<TypeCheckerMessage>) {
+        match callee_type {
+            Type::Function(params, ret) => {
+                let signature = FunctionSignature::new(name.into(), params.clone(), *ret.clone());
+                self.check_call(name, &signature, arg_exprs_vec, variables)
+            }
+            // Unknown type: the error that produced it has already been reported.
+            Type::Unknown => (Type::Unknown, Vec::new()),
+            other => (
+                Type::Null,
+                vec![TypeCheckerMessage::Error(type_error!(
+                    InvalidOperation,
+                    "Cannot call '{}' of type '{}'; it is not a function",
+                    name,
+                    other
+                ))],
+            ),
         }
     }
 
@@ -580,10 +2199,31 @@ impl<'a> TypeChecker<'a> {
                 TokenLiteral::Identifier(i) => {
                     if let Some((var_type, _)) = variables.get(i) {
                         (var_type.clone(), Vec::new())
+                    } else if let Some(func_type) = self.user_function_type(i) {
+                        (func_type, Vec::new())
+                    } else if let Some((enumeration, payload)) = self.enum_variant(i) {
+                        if payload.is_empty() {
+                            (Type::abra(enumeration), Vec::new())
+                        } else {
+                            (
+                                Type::Unknown,
+                                vec![TypeCheckerMessage::Error(type_error!(
+                                    Arity,
+                                    "Variant '{}' carries {} values; create it with {}(...)",
+                                    i,
+                                    payload.len(),
+                                    i
+                                ))],
+                            )
+                        }
+                    } else if let Some(value) = inbuilt_constant(i) {
+                        let literal = Expression::Literal(TokenLiteral::Value(value));
+                        self.type_eval_expression(&literal, variables)
                     } else {
                         (
-                            Type::Null,
-                            vec![TypeCheckerMessage::Error(anyhow::anyhow!(
+                            Type::Unknown,
+                            vec![TypeCheckerMessage::Error(type_error!(
+                                Undefined,
                                 "Variable {} not found",
                                 i
                             ))],
@@ -594,6 +2234,7 @@ impl<'a> TypeChecker<'a> {
                     let ty = match static_value {
                         StaticValue::Null => Type::Null,
                         StaticValue::Integer(_) => Type::Primitive(Primitives::Integer),
+                        StaticValue::BigInt(_) => Type::Primitive(Primitives::BigInt),
                         StaticValue::Float(_) => Type::Primitive(Primitives::Float),
                         StaticValue::Char(_) => Type::Primitive(Primitives::Char),
                         StaticValue::Bool(_) => Type::Primitive(Primitives::Bool),
@@ -610,25 +2251,41 @@ impl<'a> TypeChecker<'a> {
                     UnaryOpCode::NEG => {
                         if operand_type_val.is_subtype_of(&INTEGER_TYPE) {
                             INTEGER_TYPE
+                        } else if operand_type_val.is_subtype_of(&BIGINT_TYPE) {
+                            BIGINT_TYPE
                         } else if operand_type_val.is_subtype_of(&FLOAT_TYPE) {
                             FLOAT_TYPE
                         } else {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
                                 "Unary '-' operator cannot be applied to type '{}'",
                                 operand_type_val
                             )));
-                            Type::Null // Error type
+                            Type::Unknown // Error type
                         }
                     }
                     UnaryOpCode::NOT => {
                         if operand_type_val.is_subtype_of(&BOOL_TYPE) {
                             BOOL_TYPE
                         } else {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
                                 "Unary '!' operator cannot be applied to type '{}'",
                                 operand_type_val
                             )));
-                            Type::Null // Error type
+                            Type::Unknown // Error type
+                        }
+                    }
+                    UnaryOpCode::BNOT => {
+                        if operand_type_val.is_subtype_of(&INTEGER_TYPE) {
+                            INTEGER_TYPE
+                        } else {
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
+                                "Unary '~' operator cannot be applied to type '{}'",
+                                operand_type_val
+                            )));
+                            Type::Unknown // Error type
                         }
                     }
                 };
@@ -637,7 +2294,15 @@ impl<'a> TypeChecker<'a> {
 
             Expression::Binary(op, lhs_box, rhs_box) => {
                 let (lhs_type_val, mut messages) = self.type_eval_expression(lhs_box, variables);
-                let (rhs_type_val, rhs_messages) = self.type_eval_expression(rhs_box, variables);
+                // `and` only evaluates its right side when the left is true, `or` when
+                // it is false, so type tests on the left narrow the right.
+                let narrowed = match op {
+                    BinOpCode::AND => self.narrowed(variables, lhs_box, true),
+                    BinOpCode::OR => self.narrowed(variables, lhs_box, false),
+                    _ => None,
+                };
+                let (rhs_type_val, rhs_messages) =
+                    self.type_eval_expression(rhs_box, narrowed.as_ref().unwrap_or(variables));
                 messages.extend(rhs_messages);
 
                 // For binary operators, the logic often relies on specific operand types rather than general subtyping for the operation itself.
@@ -661,18 +2326,40 @@ impl<'a> TypeChecker<'a> {
                                 Type::Primitive(Primitives::Float),
                                 Type::Primitive(Primitives::Integer),
                             ) => Type::Primitive(Primitives::Float),
+                            // Mixing with `int` promotes to `bigint`.
+                            (
+                                Type::Primitive(Primitives::BigInt),
+                                Type::Primitive(Primitives::BigInt | Primitives::Integer),
+                            )
+                            | (
+                                Type::Primitive(Primitives::Integer),
+                                Type::Primitive(Primitives::BigInt),
+                            ) => Type::Primitive(Primitives::BigInt),
+                            // A string on either side of `+` concatenates, with the other
+                            // operand converted as `print` shows it.
+                            (Type::Primitive(Primitives::String), _)
+                            | (_, Type::Primitive(Primitives::String))
+                                if *op == BinOpCode::ADD =>
+                            {
+                                Type::Primitive(Primitives::String)
+                            }
                             (
                                 Type::Primitive(Primitives::String),
+                                Type::Primitive(Primitives::Integer),
+                            )
+                            | (
+                                Type::Primitive(Primitives::Integer),
                                 Type::Primitive(Primitives::String),
-                            ) if *op == BinOpCode::ADD => Type::Primitive(Primitives::String),
+                            ) if *op == BinOpCode::MULT => Type::Primitive(Primitives::String),
                             _ => {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                messages.push(TypeCheckerMessage::Error(type_error!(
+                                    InvalidOperation,
                                     "Binary operator '{}' cannot be applied to types '{}' and '{}'",
                                     op,
                                     lhs_type_val,
                                     rhs_type_val
                                 )));
-                                Type::Null
+                                Type::Unknown
                             }
                         }
                     }
@@ -681,13 +2368,22 @@ impl<'a> TypeChecker<'a> {
                             Type::Primitive(Primitives::Integer),
                             Type::Primitive(Primitives::Integer),
                         ) => Type::Primitive(Primitives::Integer),
+                        (
+                            Type::Primitive(Primitives::BigInt),
+                            Type::Primitive(Primitives::BigInt | Primitives::Integer),
+                        )
+                        | (
+                            Type::Primitive(Primitives::Integer),
+                            Type::Primitive(Primitives::BigInt),
+                        ) => Type::Primitive(Primitives::BigInt),
                         _ => {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
                                 "Binary operator '%' cannot be applied to types '{}' and '{}'",
                                 lhs_type_val,
                                 rhs_type_val
                             )));
-                            Type::Null
+                            Type::Unknown
                         }
                     },
                     BinOpCode::AND | BinOpCode::OR | BinOpCode::XOR => {
@@ -697,11 +2393,31 @@ impl<'a> TypeChecker<'a> {
                                 Type::Primitive(Primitives::Bool),
                             ) => Type::Primitive(Primitives::Bool),
                             _ => {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Logical operator '{}' cannot be applied to types '{}' and '{}'", op, lhs_type_val, rhs_type_val)));
-                                Type::Null
+                                messages.push(TypeCheckerMessage::Error(type_error!(InvalidOperation, "Logical operator '{}' cannot be applied to types '{}' and '{}'", op, lhs_type_val, rhs_type_val)));
+                                Type::Unknown
                             }
                         }
                     }
+                    BinOpCode::BAND
+                    | BinOpCode::BOR
+                    | BinOpCode::BXOR
+                    | BinOpCode::SHL
+                    | BinOpCode::SHR => {
+                        if lhs_type_val.is_subtype_of(&INTEGER_TYPE)
+                            && rhs_type_val.is_subtype_of(&INTEGER_TYPE)
+                        {
+                            INTEGER_TYPE
+                        } else {
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
+                                "Bitwise operator '{}' cannot be applied to types '{}' and '{}'",
+                                op,
+                                lhs_type_val,
+                                rhs_type_val
+                            )));
+                            Type::Unknown
+                        }
+                    }
                     BinOpCode::LT | BinOpCode::LE | BinOpCode::GT | BinOpCode::GE => {
                         match (&lhs_type_val, &rhs_type_val) {
                             (
@@ -720,13 +2436,26 @@ impl<'a> TypeChecker<'a> {
                                 Type::Primitive(Primitives::Float),
                                 Type::Primitive(Primitives::Integer),
                             )
+                            | (
+                                Type::Primitive(Primitives::BigInt),
+                                Type::Primitive(Primitives::BigInt | Primitives::Integer),
+                            )
+                            | (
+                                Type::Primitive(Primitives::Integer),
+                                Type::Primitive(Primitives::BigInt),
+                            )
                             | (
                                 Type::Primitive(Primitives::Char),
                                 Type::Primitive(Primitives::Char),
                             ) => Type::Primitive(Primitives::Bool),
+                            (Type::Abra(a1), Type::Abra(a2))
+                                if a1 == a2 && self.implements_protocol(a1, "compare") =>
+                            {
+                                Type::Primitive(Primitives::Bool)
+                            }
                             _ => {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Comparison operator '{}' cannot be applied to types '{}' and '{}'", op, lhs_type_val, rhs_type_val)));
-                                Type::Null
+                                messages.push(TypeCheckerMessage::Error(type_error!(InvalidOperation, "Comparison operator '{}' cannot be applied to types '{}' and '{}'", op, lhs_type_val, rhs_type_val)));
+                                Type::Unknown
                             }
                         }
                     }
@@ -742,6 +2471,14 @@ impl<'a> TypeChecker<'a> {
                             | (
                                 Type::Primitive(Primitives::Float),
                                 Type::Primitive(Primitives::Integer),
+                            )
+                            | (
+                                Type::Primitive(Primitives::BigInt),
+                                Type::Primitive(Primitives::Integer),
+                            )
+                            | (
+                                Type::Primitive(Primitives::Integer),
+                                Type::Primitive(Primitives::BigInt),
                             ) => Type::Primitive(Primitives::Bool),
                             (Type::Abra(a1), Type::Abra(a2)) if a1 == a2 => {
                                 Type::Primitive(Primitives::Bool)
@@ -757,7 +2494,7 @@ impl<'a> TypeChecker<'a> {
                             _ => {
                                 // Consider if this should be a warning or if some comparisons are always false but not errors
                                 messages.push(TypeCheckerMessage::Warning(anyhow::anyhow!("Equality operator '{}' may not behave as expected for types '{}' and '{}'", op, lhs_type_val, rhs_type_val)));
-                                Type::Null
+                                Type::Unknown
                             }
                         }
                     }
@@ -766,89 +2503,444 @@ impl<'a> TypeChecker<'a> {
             }
             Expression::Grouping(expr_box) => self.type_eval_expression(expr_box, variables),
             Expression::Call(func_name, arg_exprs_vec) => {
-                let mut messages: Vec<TypeCheckerMessage> = Vec::new();
-                let mut return_ty = Type::Null;
-
-                if let Some(func_sig) = self.global_functions.get(func_name) {
-                    return_ty = func_sig.return_type.clone();
-                    if arg_exprs_vec.len() != func_sig.parameters.len() {
-                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                            "Function '{}' expected {} arguments, but got {}",
-                            func_name,
-                            func_sig.parameters.len(),
-                            arg_exprs_vec.len()
+                // A variable holding a function shadows a global function of the same name.
+                if let Some((var_type, _)) = variables.get(func_name) {
+                    return self.check_value_call(func_name, var_type, arg_exprs_vec, variables);
+                }
+                if let Some((enumeration, payload)) = self.enum_variant(func_name) {
+                    let signature = FunctionSignature::new(
+                        func_name.clone(),
+                        payload.to_vec(),
+                        Type::abra(enumeration),
+                    );
+                    return self.check_call(func_name, &signature, arg_exprs_vec, variables);
+                }
+                match self.global_functions.get(func_name) {
+                    Some(func_sig) => {
+                        let arguments = match self.function_params.get(func_name) {
+                            Some(params) => match self.arranged_arguments(params, arg_exprs_vec) {
+                                Ok(arguments) => arguments,
+                                Err(message) => {
+                                    let error =
+                                        type_error!(Arity, "Function '{}' {}", func_name, message);
+                                    return (Type::Unknown, vec![TypeCheckerMessage::Error(error)]);
+                                }
+                            },
+                            None => arg_exprs_vec.to_vec(),
+                        };
+                        self.check_call(func_name, func_sig, &arguments, variables)
+                    }
+                    None => (
+                        Type::Unknown,
+                        vec![TypeCheckerMessage::Error(type_error!(
+                            Undefined,
+                            "Global function '{}' not found",
+                            func_name
+                        ))],
+                    ),
+                }
+            }
+            Expression::CallIndirect(callee, arg_exprs_vec) => {
+                let (callee_type, mut messages) = self.type_eval_expression(callee, variables);
+                let (return_ty, call_messages) = self.check_value_call(
+                    &callee.to_string(),
+                    &callee_type,
+                    arg_exprs_vec,
+                    variables,
+                );
+                messages.extend(call_messages);
+                (return_ty, messages)
+            }
+            Expression::Lambda(params, return_type, body) => {
+                // The body sees the enclosing variables, which the closure captures.
+                let mut scope = variables.clone();
+                for param in params.iter() {
+                    scope.insert(param.name.clone(), (param.ty.clone(), StaticValue::Null));
+                }
+                let (body_type, mut messages) = self.type_eval_expression(body, &scope);
+                messages.extend(misplaced_default(params));
+                if !body_type.is_subtype_of(return_type) {
+                    messages.push(TypeCheckerMessage::Error(type_error!(
+                        Mismatch,
+                        "Lambda should return '{}', but its body has type '{}'",
+                        return_type,
+                        body_type
+                    )));
+                }
+                let param_types = params.iter().map(|param| param.ty.clone()).collect();
+                (Type::function(param_types, return_type.clone()), messages)
+            }
+            Expression::MethodCall(receiver, method_name, arg_exprs_vec) => {
+                let (receiver_type, mut messages) = self.type_eval_expression(receiver, variables);
+                let (class_name, class_bindings) = match self.class_of(&receiver_type) {
+                    Some(class) => class,
+                    None => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
+                            "Cannot call method '{}' on a value of type '{}'",
+                            method_name,
+                            receiver_type
                         )));
-                    } else {
-                        for (i, arg_expr) in arg_exprs_vec.iter().enumerate() {
-                            let (arg_type_val, arg_messages) =
-                                self.type_eval_expression(arg_expr, variables);
-                            messages.extend(arg_messages);
-                            if !arg_type_val.is_subtype_of(&func_sig.parameters[i]) {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Argument {} for function '{}': expected type '{}', but got '{}'", i + 1, func_name, func_sig.parameters[i], arg_type_val)));
-                            }
-                        }
+                        return (Type::Unknown, messages);
                     }
+                };
+                let method_sig = match self
+                    .abra_types
+                    .get(&class_name)
+                    .and_then(|class_def| class_def.functions.get(method_name))
+                {
+                    Some(method_sig) => method_sig,
+                    None => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            Undefined,
+                            "Method '{}' not found in class '{}'",
+                            method_name,
+                            class_name
+                        )));
+                        return (Type::Unknown, messages);
+                    }
+                };
+                if arg_exprs_vec.len() != method_sig.parameters.len() {
+                    messages.push(TypeCheckerMessage::Error(type_error!(
+                        Arity,
+                        "Method '{}::{}' expected {} arguments, but got {}",
+                        class_name,
+                        method_name,
+                        method_sig.parameters.len(),
+                        arg_exprs_vec.len()
+                    )));
+                    return (method_sig.return_type.substitute(&class_bindings), messages);
+                }
+                // The receiver's type arguments come first; a generic method's own type
+                // parameters are inferred from the arguments.
+                let mut bindings = class_bindings;
+                let mut arg_types = Vec::with_capacity(arg_exprs_vec.len());
+                for (i, arg_expr) in arg_exprs_vec.iter().enumerate() {
+                    let (arg_type_val, arg_messages) =
+                        self.type_eval_expression(arg_expr, variables);
+                    messages.extend(arg_messages);
+                    method_sig.parameters[i]
+                        .substitute(&bindings)
+                        .bind_vars(&arg_type_val, &mut bindings);
+                    arg_types.push(arg_type_val);
+                }
+                for (i, arg_type_val) in arg_types.iter().enumerate() {
+                    let expected = method_sig.parameters[i].substitute(&bindings);
+                    if !arg_type_val.is_subtype_of(&expected) {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Argument {} for method '{}::{}': expected type '{}', but got '{}'",
+                            i + 1,
+                            class_name,
+                            method_name,
+                            expected,
+                            arg_type_val
+                        )));
+                    }
+                }
+                (method_sig.return_type.substitute(&bindings), messages)
+            }
+            Expression::ArrayLiteral(elements) => {
+                let mut messages = Vec::new();
+                let mut element_type = Type::Unknown;
+                for element in elements {
+                    let (ty, element_messages) = self.type_eval_expression(element, variables);
+                    messages.extend(element_messages);
+                    element_type = element_type.join(ty);
+                }
+                (Type::array(element_type), messages)
+            }
+            Expression::MapLiteral(entries) => {
+                let mut messages = Vec::new();
+                let (mut key_type, mut value_type) = (Type::Unknown, Type::Unknown);
+                for (key, value) in entries {
+                    let (ty, key_messages) = self.type_eval_expression(key, variables);
+                    messages.extend(key_messages);
+                    key_type = key_type.join(ty);
+                    let (ty, value_messages) = self.type_eval_expression(value, variables);
+                    messages.extend(value_messages);
+                    value_type = value_type.join(ty);
+                }
+                (Type::map(key_type, value_type), messages)
+            }
+            Expression::Is(value, ty) => {
+                let (value_type, mut messages) = self.type_eval_expression(value, variables);
+                let related = value_type.members().into_iter().any(|member| {
+                    ty.members()
+                        .into_iter()
+                        .any(|t| member.is_subtype_of(t) || t.is_subtype_of(member))
+                });
+                if !related {
+                    messages.push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                        "'{}' is never true: the value has type '{}'",
+                        expression(e),
+                        value_type
+                    )));
+                }
+                (BOOL_TYPE, messages)
+            }
+            Expression::Coalesce(value, fallback) => {
+                let (value_type, mut messages) = self.type_eval_expression(value, variables);
+                let (fallback_type, fallback_messages) =
+                    self.type_eval_expression(fallback, variables);
+                messages.extend(fallback_messages);
+                if !value_type.is_optional() {
+                    messages.push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                        "Left operand of '??' has type '{}', which is never null",
+                        value_type
+                    )));
+                }
+                let result_type = match value_type.without_null() {
+                    Some(present) if fallback_type.is_subtype_of(&present) => present,
+                    Some(present) => Type::or(present, fallback_type),
+                    None => fallback_type,
+                };
+                (result_type, messages)
+            }
+            Expression::NamedArgument(name, _) => (
+                Type::Unknown,
+                vec![TypeCheckerMessage::Error(type_error!(
+                    Misplaced,
+                    "Named argument '{}' can only be passed to a top-level function",
+                    name
+                ))],
+            ),
+            Expression::Conditional(condition, then, otherwise) => {
+                let (condition_type, mut messages) =
+                    self.type_eval_expression(condition, variables);
+                if !condition_type.is_subtype_of(&BOOL_TYPE) {
+                    messages.push(TypeCheckerMessage::Error(type_error!(
+                        Mismatch,
+                        "Condition of '?' must be a boolean, found '{}'",
+                        condition_type
+                    )));
+                }
+                // Like an `if`, each branch sees the variables the condition narrows.
+                let then_scope = self.narrowed(variables, condition, true);
+                let (then_type, then_messages) =
+                    self.type_eval_expression(then, then_scope.as_ref().unwrap_or(variables));
+                messages.extend(then_messages);
+                let otherwise_scope = self.narrowed(variables, condition, false);
+                let (otherwise_type, otherwise_messages) = self
+                    .type_eval_expression(otherwise, otherwise_scope.as_ref().unwrap_or(variables));
+                messages.extend(otherwise_messages);
+                (then_type.join(otherwise_type), messages)
+            }
+            Expression::SafeAccess(access) => {
+                // The access is checked against a stand-in for the object that can't
+                // be null; the result is null whenever the object is.
+                let hidden = "$safe".to_string();
+                let stand_in = Box::new(Expression::Literal(TokenLiteral::Identifier(
+                    hidden.clone(),
+                )));
+                let (object, access) = match &**access {
+                    Expression::Get(member, object) => {
+                        (object, Expression::Get(member.clone(), stand_in))
+                    }
+                    Expression::MethodCall(receiver, method, args) => (
+                        receiver,
+                        Expression::MethodCall(stand_in, method.clone(), args.clone()),
+                    ),
+                    other => return self.type_eval_expression(other, variables),
+                };
+                let (object_type, mut messages) = self.type_eval_expression(object, variables);
+                if !object_type.is_optional() {
+                    messages.push(TypeCheckerMessage::Warning(anyhow::anyhow!(
+                        "'?.' on '{}', of type '{}', which is never null",
+                        expression(object),
+                        object_type
+                    )));
+                }
+                let mut scope = variables.clone();
+                let present = object_type.without_null().unwrap_or(Type::Null);
+                scope.insert(hidden, (present, StaticValue::Null));
+                let (access_type, access_messages) = self.type_eval_expression(&access, &scope);
+                messages.extend(access_messages);
+                if access_type.is_optional() {
+                    (access_type, messages)
                 } else {
-                    messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                        "Global function '{}' not found",
-                        func_name
+                    (Type::or(access_type, Type::Null), messages)
+                }
+            }
+            Expression::SuperCall(method_name, arg_exprs_vec) => {
+                let parent = self
+                    .current_class
+                    .as_ref()
+                    .and_then(|class| self.abra_types.get(class))
+                    .and_then(|class_def| class_def.parent.clone());
+                let parent = match parent {
+                    Some(parent) => parent,
+                    None => {
+                        let message = type_error!(
+                            InvalidOperation,
+                            "'super' can only be used in a class that extends another"
+                        );
+                        return (Type::Unknown, vec![TypeCheckerMessage::Error(message)]);
+                    }
+                };
+                match self
+                    .abra_types
+                    .get(&parent)
+                    .and_then(|class_def| class_def.functions.get(method_name))
+                {
+                    Some(sig) => self.check_call(
+                        &format!("{}::{}", parent, method_name),
+                        sig,
+                        arg_exprs_vec,
+                        variables,
+                    ),
+                    None => {
+                        let message = type_error!(
+                            Undefined,
+                            "Method '{}' not found in class '{}'",
+                            method_name,
+                            parent
+                        );
+                        (Type::Unknown, vec![TypeCheckerMessage::Error(message)])
+                    }
+                }
+            }
+            Expression::Index(collection, index) => {
+                let (collection_type, mut messages) =
+                    self.type_eval_expression(collection, variables);
+                let (index_type, index_messages) = self.type_eval_expression(index, variables);
+                messages.extend(index_messages);
+                let indexed = match &collection_type {
+                    Type::Composite(composite) => match &**composite {
+                        Composite::Array(element) => Some((INTEGER_TYPE, element.clone())),
+                        Composite::Map(key, value) => Some((key.clone(), value.clone())),
+                        Composite::HeapValue(_) | Composite::Tuple(_) => None,
+                    },
+                    _ => None,
+                };
+                let element_type = match indexed {
+                    Some((key_type, element_type)) => {
+                        if !index_type.is_subtype_of(&key_type) {
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                Mismatch,
+                                "Index into '{}' must be '{}', found '{}'",
+                                collection_type,
+                                key_type,
+                                index_type
+                            )));
+                        }
+                        element_type
+                    }
+                    None => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
+                            "Cannot index into a value of type '{}'",
+                            collection_type
+                        )));
+                        Type::Unknown
+                    }
+                };
+                (element_type, messages)
+            }
+            Expression::TupleLiteral(elements) => {
+                let mut messages = Vec::new();
+                let mut types = Vec::with_capacity(elements.len());
+                for element in elements {
+                    let (ty, element_messages) = self.type_eval_expression(element, variables);
+                    messages.extend(element_messages);
+                    types.push(ty);
+                }
+                (Type::Composite(Box::new(Composite::Tuple(types))), messages)
+            }
+            Expression::TupleIndex(tuple, index) => {
+                let (tuple_type, mut messages) = self.type_eval_expression(tuple, variables);
+                let element_type = match &tuple_type {
+                    Type::Composite(composite) => match &**composite {
+                        Composite::Tuple(types) => types.get(*index).cloned(),
+                        _ => None,
+                    },
+                    Type::Unknown => Some(Type::Unknown),
+                    _ => None,
+                };
+                match element_type {
+                    Some(element_type) => (element_type, messages),
+                    None => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
+                            "Cannot access '.{}' on a value of type '{}'",
+                            index,
+                            tuple_type
+                        )));
+                        (Type::Unknown, messages)
+                    }
+                }
+            }
+            Expression::Range(start, end) => {
+                self.type_eval_expression(&Expression::range_elements(start, end), variables)
+            }
+            Expression::Slice(collection, start, end) => {
+                let (collection_type, mut messages) =
+                    self.type_eval_expression(collection, variables);
+                for bound in start.iter().chain(end) {
+                    let (bound_type, bound_messages) = self.type_eval_expression(bound, variables);
+                    messages.extend(bound_messages);
+                    if !bound_type.is_subtype_of(&INTEGER_TYPE) {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            Mismatch,
+                            "Slice bound of '{}' must be '{}', found '{}'",
+                            collection_type,
+                            INTEGER_TYPE,
+                            bound_type
+                        )));
+                    }
+                }
+                let sliceable = match &collection_type {
+                    Type::Composite(composite) => matches!(**composite, Composite::Array(_)),
+                    other => *other == STRING_TYPE || *other == Type::Unknown,
+                };
+                if !sliceable {
+                    messages.push(TypeCheckerMessage::Error(type_error!(
+                        InvalidOperation,
+                        "Cannot slice a value of type '{}'",
+                        collection_type
                     )));
+                    return (Type::Unknown, messages);
                 }
-                (return_ty, messages)
+                (collection_type, messages)
             }
             Expression::Get(member_name, base_expr) => {
                 let (base_type_val, mut messages) = self.type_eval_expression(base_expr, variables);
 
-                let result_type = match base_type_val {
-                    Type::Abra(class_name_str) => {
+                let result_type = match self.class_of(&base_type_val) {
+                    Some((class_name_str, bindings)) => {
                         if let Some(class_def) = self.abra_types.get(&class_name_str) {
                             if let Some((var_type, _)) = class_def.variables.get(member_name) {
-                                var_type.clone()
+                                var_type.substitute(&bindings)
                             } else if class_def.functions.contains_key(member_name) {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Accessing method '{}' on class '{}' as a value is not directly supported. Call it with ().", member_name, class_name_str)));
-                                Type::Null // Or a specific function/method type if the language supports it
+                                messages.push(TypeCheckerMessage::Error(type_error!(InvalidOperation, "Accessing method '{}' on class '{}' as a value is not directly supported. Call it with ().", member_name, class_name_str)));
+                                Type::Unknown // Or a specific function/method type if the language supports it
                             } else {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                messages.push(TypeCheckerMessage::Error(type_error!(
+                                    Undefined,
                                     "Member '{}' not found in class '{}'",
                                     member_name,
                                     class_name_str
                                 )));
-                                Type::Null
+                                Type::Unknown
                             }
                         } else {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                Undefined,
                                 "Class definition '{}' not found for access",
                                 class_name_str
                             )));
-                            Type::Null
+                            Type::Unknown
                         }
                     }
-                    Type::Composite(ref composite_box) => match **composite_box {
-                        Composite::Array(_) if member_name == "length" => {
-                            Type::Primitive(Primitives::Integer)
-                        }
-                        Composite::Map(_, _) if member_name == "size" => {
-                            Type::Primitive(Primitives::Integer)
-                        } // Example property
-                        _ => {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
-                                "Member access '{}' not supported on type '{}'",
-                                member_name,
-                                base_type_val
-                            )));
-                            Type::Null
-                        }
-                    },
-                    Type::Primitive(Primitives::String) if member_name == "length" => {
-                        Type::Primitive(Primitives::Integer)
-                    }
-                    _ => {
-                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                    // Arrays, maps and strings have no members; `len` gives their size.
+                    None => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
                             "Cannot access member '{}' on type '{}'",
                             member_name,
                             base_type_val
                         )));
-                        Type::Null
+                        Type::Unknown
                     }
                 };
                 (result_type, messages)
@@ -858,38 +2950,77 @@ impl<'a> TypeChecker<'a> {
                 let mut messages: Vec<TypeCheckerMessage> = Vec::new();
 
                 match ty.clone() {
-                    Type::Abra(class_name) => {
+                    Type::Abra(class_name) if self.interfaces.contains(&class_name) => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
+                            "Cannot instantiate interface '{}'",
+                            class_name
+                        )));
+                    }
+                    Type::Abra(_) | Type::Generic(_, _) => {
+                        let (class_name, mut bindings) = self.class_of(ty).unwrap_or_default();
                         if let Some(class_def) = self.abra_types.get(&class_name) {
+                            if let Type::Generic(_, type_args) = ty {
+                                if type_args.len() != class_def.type_params.len() {
+                                    messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Arity,
+                                        "Class '{}' takes {} type arguments, but got {}",
+                                        class_name,
+                                        class_def.type_params.len(),
+                                        type_args.len()
+                                    )));
+                                }
+                            }
                             let constructor_sig_opt = class_def.functions.get("init"); // Assuming constructor is 'init'
                             if let Some(constructor_sig) = constructor_sig_opt {
                                 if arg_exprs_vec.len() != constructor_sig.parameters.len() {
-                                    messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                    messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Arity,
                                         "Constructor for '{}' expected {} arguments, but got {}",
                                         class_name,
                                         constructor_sig.parameters.len(),
                                         arg_exprs_vec.len()
                                     )));
                                 } else {
+                                    let mut arg_types = Vec::with_capacity(arg_exprs_vec.len());
                                     for (i, arg_expr) in arg_exprs_vec.iter().enumerate() {
                                         let (arg_type_val, arg_eval_messages) =
                                             self.type_eval_expression(arg_expr, variables);
                                         messages.extend(arg_eval_messages);
-                                        if !arg_type_val
-                                            .is_subtype_of(&constructor_sig.parameters[i])
-                                        {
-                                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Argument {} for '{}' constructor: expected type '{}', but got '{}'", i + 1, class_name, constructor_sig.parameters[i], arg_type_val)));
+                                        constructor_sig.parameters[i]
+                                            .substitute(&bindings)
+                                            .bind_vars(&arg_type_val, &mut bindings);
+                                        arg_types.push(arg_type_val);
+                                    }
+                                    for (i, arg_type_val) in arg_types.iter().enumerate() {
+                                        let expected =
+                                            constructor_sig.parameters[i].substitute(&bindings);
+                                        if !arg_type_val.is_subtype_of(&expected) {
+                                            messages.push(TypeCheckerMessage::Error(type_error!(Mismatch, "Argument {} for '{}' constructor: expected type '{}', but got '{}'", i + 1, class_name, expected, arg_type_val)));
                                         }
                                     }
                                 }
                             } else if !arg_exprs_vec.is_empty() {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Class '{}' does not have an 'init' constructor, but arguments were provided.", class_name)));
+                                messages.push(TypeCheckerMessage::Error(type_error!(Arity, "Class '{}' does not have an 'init' constructor, but arguments were provided.", class_name)));
+                            }
+                            // `new Box(1)` infers the type arguments from `init`'s.
+                            if let (Type::Abra(_), false) = (ty, class_def.type_params.is_empty()) {
+                                let type_args = class_def
+                                    .type_params
+                                    .iter()
+                                    .map(|param| {
+                                        bindings.get(param).cloned().unwrap_or(Type::Unknown)
+                                    })
+                                    .collect();
+                                result_type = Type::Generic(class_name, type_args);
                             }
                         } else {
-                            messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                Undefined,
                                 "Cannot instantiate unknown class '{}'",
                                 class_name
                             )));
-                            result_type = Type::Null;
+                            result_type = Type::Unknown;
                         }
                     }
                     Type::Composite(composite_box) => match *composite_box {
@@ -899,7 +3030,8 @@ impl<'a> TypeChecker<'a> {
                                     self.type_eval_expression(arg_expr, variables);
                                 messages.extend(arg_eval_messages);
                                 if !arg_type_val.is_subtype_of(element_type) {
-                                    messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                    messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Mismatch,
                                         "Array element expected type '{}', but got '{}'",
                                         element_type,
                                         arg_type_val
@@ -909,7 +3041,7 @@ impl<'a> TypeChecker<'a> {
                         }
                         Composite::Map(ref key_type, ref value_type) => {
                             if arg_exprs_vec.len() % 2 != 0 {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!("Map instantiation requires an even number of arguments (key-value pairs), got {}", arg_exprs_vec.len())));
+                                messages.push(TypeCheckerMessage::Error(type_error!(Arity, "Map instantiation requires an even number of arguments (key-value pairs), got {}", arg_exprs_vec.len())));
                             } else {
                                 for chunk in arg_exprs_vec.chunks_exact(2) {
                                     let (k_actual_type_val, k_eval_messages) =
@@ -920,14 +3052,16 @@ impl<'a> TypeChecker<'a> {
                                     messages.extend(v_eval_messages);
 
                                     if !k_actual_type_val.is_subtype_of(key_type) {
-                                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                        messages.push(TypeCheckerMessage::Error(type_error!(
+                                            Mismatch,
                                             "Map key expected type '{}', but got '{}'",
                                             key_type,
                                             k_actual_type_val
                                         )));
                                     }
                                     if !v_actual_type_val.is_subtype_of(value_type) {
-                                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                        messages.push(TypeCheckerMessage::Error(type_error!(
+                                            Mismatch,
                                             "Map value expected type '{}', but got '{}'",
                                             value_type,
                                             v_actual_type_val
@@ -936,9 +3070,17 @@ impl<'a> TypeChecker<'a> {
                                 }
                             }
                         }
+                        Composite::Tuple(_) => {
+                            messages.push(TypeCheckerMessage::Error(type_error!(
+                                InvalidOperation,
+                                "Cannot instantiate tuple type '{}' using 'new'; write its values in parentheses",
+                                ty
+                            )));
+                        }
                         Composite::HeapValue(ref inner_type) => {
                             if arg_exprs_vec.len() != 1 {
-                                messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                messages.push(TypeCheckerMessage::Error(type_error!(
+                                    Arity,
                                     "Box (HeapValue) instantiation expects 1 argument, got {}",
                                     arg_exprs_vec.len()
                                 )));
@@ -947,7 +3089,8 @@ impl<'a> TypeChecker<'a> {
                                     self.type_eval_expression(&arg_exprs_vec[0], variables);
                                 messages.extend(arg_eval_messages);
                                 if !arg_type_val.is_subtype_of(inner_type) {
-                                    messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                                    messages.push(TypeCheckerMessage::Error(type_error!(
+                                        Mismatch,
                                         "Box (HeapValue) expected inner type '{}', but got '{}'",
                                         inner_type,
                                         arg_type_val
@@ -957,18 +3100,24 @@ impl<'a> TypeChecker<'a> {
                         }
                     },
                     Type::Algebraic(_) => {
-                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
                             "Cannot instantiate algebraic type '{}' using 'new'",
                             ty
                         )));
-                        result_type = Type::Null;
+                        result_type = Type::Unknown;
                     }
-                    Type::Primitive(_) | Type::Null => {
-                        messages.push(TypeCheckerMessage::Error(anyhow::anyhow!(
+                    Type::Primitive(_)
+                    | Type::Null
+                    | Type::Var(_)
+                    | Type::Function(_, _)
+                    | Type::Unknown => {
+                        messages.push(TypeCheckerMessage::Error(type_error!(
+                            InvalidOperation,
                             "Cannot instantiate primitive type '{}' or Null using 'new'",
                             ty
                         )));
-                        result_type = Type::Null;
+                        result_type = Type::Unknown;
                     }
                 }
                 (result_type, messages)
@@ -976,3 +3125,280 @@ impl<'a> TypeChecker<'a> {
         }
     }
 }
+
+/// Cases of a `match` on a value of type `subject` that none of the arms handle;
+/// `variants` are the subject's when it is an enum. Only bools, enums and `Or` types
+/// have cases the checker knows; anything else needs no `_` arm.
+/// Whether `name` is a constant in `scope_vars` rather than a variable hiding one.
+fn is_constant(name: &str, scope_vars: &HashMap<String, VariableDefinition>) -> bool {
+    scope_vars
+        .get(name)
+        .is_some_and(|(_, value)| *value != StaticValue::Null)
+}
+
+/// An error for the first of `params` with a default, as only top-level functions take
+/// default values.
+/// Adds the variables `stmt` assigns, in any block nested in it, to `out`. Nested
+/// functions can't assign them, so their bodies are left out.
+fn assigned_names<'s>(stmt: &'s Statement, out: &mut HashSet<&'s str>) {
+    let blocks: Vec<&Block> = match stmt {
+        Statement::Set(None, name, _)
+        | Statement::Compound(Expression::Literal(TokenLiteral::Identifier(name)), _, _) => {
+            out.insert(name);
+            return;
+        }
+        Statement::If(_, then_block, else_block) => {
+            [then_block].into_iter().chain(else_block).collect()
+        }
+        Statement::For(init, _, incr, body) => {
+            assigned_names(init, out);
+            assigned_names(incr, out);
+            body.iter().collect()
+        }
+        Statement::While(_, body) => vec![body],
+        Statement::Try(body, _, handler) => vec![body, handler],
+        Statement::Match(_, arms) => arms.iter().map(|(_, body)| body).collect(),
+        _ => return,
+    };
+    for located in blocks.into_iter().flatten() {
+        assigned_names(&located.node, out);
+    }
+}
+
+fn misplaced_default(params: &[Parameter]) -> Option<TypeCheckerMessage> {
+    let param = params.iter().find(|param| param.default.is_some())?;
+    Some(TypeCheckerMessage::Error(type_error!(
+        Misplaced,
+        "Parameter '{}' can't have a default value; only top-level functions' parameters can",
+        param.name
+    )))
+}
+
+/// Whether a field of type `ty` has a value before its class's initializers run:
+/// primitives start at their default and optionals at `null`. A field of one of the
+/// class's `type_params` may be either, so it is left to `init`.
+fn starts_with_value(ty: &Type, type_params: &[String]) -> bool {
+    match ty {
+        Type::Primitive(_) | Type::Unknown => true,
+        Type::Abra(name) => type_params.contains(name),
+        _ => Type::Null.is_subtype_of(ty),
+    }
+}
+
+/// The value a parameter's `default` passes: a constant, like [`fold_constant`]'s, or
+/// `null`.
+pub(crate) fn default_value(
+    default: &Expression,
+    constants: &HashMap<String, StaticValue>,
+) -> Option<StaticValue> {
+    match default {
+        Expression::Literal(TokenLiteral::Value(StaticValue::Null)) => Some(StaticValue::Null),
+        _ => fold_constant(default, constants),
+    }
+}
+
+/// The value `expr` always has, if it is made only of literals and `constants` joined
+/// by arithmetic, comparisons and logic.
+pub(crate) fn fold_constant(
+    expr: &Expression,
+    constants: &HashMap<String, StaticValue>,
+) -> Option<StaticValue> {
+    match expr {
+        Expression::Literal(TokenLiteral::Value(StaticValue::Null)) => None,
+        Expression::Literal(TokenLiteral::Value(value)) => Some(value.clone()),
+        Expression::Literal(TokenLiteral::Identifier(name)) => constants
+            .get(name)
+            .cloned()
+            .or_else(|| inbuilt_constant(name)),
+        Expression::Grouping(inner) => fold_constant(inner, constants),
+        Expression::Conditional(condition, then, otherwise) => {
+            match fold_constant(condition, constants)? {
+                StaticValue::Bool(true) => fold_constant(then, constants),
+                StaticValue::Bool(false) => fold_constant(otherwise, constants),
+                _ => None,
+            }
+        }
+        Expression::Unary(op, inner) => match (op, fold_constant(inner, constants)?) {
+            (UnaryOpCode::NEG, StaticValue::Integer(i)) => {
+                i.checked_neg().map(StaticValue::Integer)
+            }
+            (UnaryOpCode::NEG, StaticValue::Float(f)) => Some(StaticValue::Float(-f)),
+            (UnaryOpCode::NOT, StaticValue::Bool(b)) => Some(StaticValue::Bool(!b)),
+            (UnaryOpCode::BNOT, StaticValue::Integer(i)) => Some(StaticValue::Integer(!i)),
+            _ => None,
+        },
+        Expression::Binary(op, lhs, rhs) => {
+            let lhs = fold_constant(lhs, constants)?;
+            let rhs = fold_constant(rhs, constants)?;
+            if std::mem::discriminant(&lhs) != std::mem::discriminant(&rhs) {
+                return None;
+            }
+            let compared = match op {
+                BinOpCode::EQ => Some(lhs == rhs),
+                BinOpCode::NE => Some(lhs != rhs),
+                BinOpCode::LT => Some(lhs < rhs),
+                BinOpCode::LE => Some(lhs <= rhs),
+                BinOpCode::GT => Some(lhs > rhs),
+                BinOpCode::GE => Some(lhs >= rhs),
+                _ => None,
+            };
+            if let Some(compared) = compared {
+                return Some(StaticValue::Bool(compared));
+            }
+            match (lhs, rhs) {
+                (StaticValue::Integer(a), StaticValue::Integer(b)) => match op {
+                    BinOpCode::ADD => a.checked_add(b),
+                    BinOpCode::SUB => a.checked_sub(b),
+                    BinOpCode::MULT => a.checked_mul(b),
+                    BinOpCode::DIV => a.checked_div(b),
+                    // Like the VM, `int::MIN % -1` is 0.
+                    BinOpCode::MOD => (b != 0).then(|| a.wrapping_rem(b)),
+                    BinOpCode::BAND => Some(a & b),
+                    BinOpCode::BOR => Some(a | b),
+                    BinOpCode::BXOR => Some(a ^ b),
+                    BinOpCode::SHL => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+                    BinOpCode::SHR => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+                    _ => None,
+                }
+                .map(StaticValue::Integer),
+                (StaticValue::Float(a), StaticValue::Float(b)) => match op {
+                    BinOpCode::ADD => Some(a + b),
+                    BinOpCode::SUB => Some(a - b),
+                    BinOpCode::MULT => Some(a * b),
+                    BinOpCode::DIV => Some(a / b),
+                    _ => None,
+                }
+                .map(StaticValue::Float),
+                (StaticValue::Bool(a), StaticValue::Bool(b)) => match op {
+                    BinOpCode::AND => Some(a && b),
+                    BinOpCode::OR => Some(a || b),
+                    _ => None,
+                }
+                .map(StaticValue::Bool),
+                (StaticValue::String(a), StaticValue::String(b)) => match op {
+                    BinOpCode::ADD => Some(StaticValue::String(a + &b)),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Why [`fold_constant`] can't compute `expr`, when an `int` operation on constant
+/// operands divides by zero or doesn't fit in an `int`.
+fn failed_arithmetic(
+    expr: &Expression,
+    constants: &HashMap<String, StaticValue>,
+) -> Option<String> {
+    match expr {
+        Expression::Grouping(inner) => failed_arithmetic(inner, constants),
+        Expression::Conditional(condition, then, otherwise) => {
+            failed_arithmetic(condition, constants).or_else(|| {
+                match fold_constant(condition, constants)? {
+                    StaticValue::Bool(true) => failed_arithmetic(then, constants),
+                    StaticValue::Bool(false) => failed_arithmetic(otherwise, constants),
+                    _ => None,
+                }
+            })
+        }
+        Expression::Unary(UnaryOpCode::NEG, inner) => {
+            failed_arithmetic(inner, constants).or_else(|| match fold_constant(inner, constants)? {
+                StaticValue::Integer(i) if i == i64::MIN => {
+                    Some(format!("overflows: -({}) doesn't fit in an int", i))
+                }
+                _ => None,
+            })
+        }
+        Expression::Binary(op, lhs, rhs) => {
+            if let Some(failure) =
+                failed_arithmetic(lhs, constants).or_else(|| failed_arithmetic(rhs, constants))
+            {
+                return Some(failure);
+            }
+            let (StaticValue::Integer(a), StaticValue::Integer(b)) = (
+                fold_constant(lhs, constants)?,
+                fold_constant(rhs, constants)?,
+            ) else {
+                return None;
+            };
+            match op {
+                BinOpCode::DIV | BinOpCode::MOD if b == 0 => {
+                    Some(format!("divides by zero: {} {} {}", a, op, b))
+                }
+                BinOpCode::ADD | BinOpCode::SUB | BinOpCode::MULT | BinOpCode::DIV
+                    if fold_constant(expr, constants).is_none() =>
+                {
+                    Some(format!(
+                        "overflows: {} {} {} doesn't fit in an int",
+                        a, op, b
+                    ))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn uncovered_cases(
+    subject: &Type,
+    variants: Option<&[(String, Vec<Type>)]>,
+    arms: &[(Pattern, Block)],
+) -> Vec<String> {
+    if arms
+        .iter()
+        .any(|(pattern, _)| *pattern == Pattern::Wildcard)
+    {
+        return Vec::new();
+    }
+    let has_literal = |value: bool| {
+        arms.iter()
+            .any(|(pattern, _)| *pattern == Pattern::Literal(StaticValue::Bool(value)))
+    };
+    let has_type = |member: &Type| {
+        arms.iter().any(|(pattern, _)| match pattern {
+            Pattern::Type(Type::Null) => *member == Type::Null,
+            Pattern::Type(ty) => *member != Type::Null && member.is_subtype_of(ty),
+            _ => false,
+        })
+    };
+    if let Some(variants) = variants {
+        if has_type(subject) {
+            return Vec::new();
+        }
+        return variants
+            .iter()
+            .map(|(variant, _)| format!("{}::{}", subject, variant))
+            .filter(|name| {
+                !arms
+                    .iter()
+                    .any(|(pattern, _)| matches!(pattern, Pattern::Variant(n, _) if n == name))
+            })
+            .map(|name| format!("'{}'", name))
+            .collect();
+    }
+    if *subject == BOOL_TYPE {
+        if has_type(subject) {
+            return Vec::new();
+        }
+        return [true, false]
+            .into_iter()
+            .filter(|value| !has_literal(*value))
+            .map(|value| format!("'{}'", value))
+            .collect();
+    }
+    if !matches!(subject, Type::Algebraic(_)) {
+        return Vec::new();
+    }
+    subject
+        .members()
+        .into_iter()
+        .filter(|member| {
+            let both_bools = **member == BOOL_TYPE && has_literal(true) && has_literal(false);
+            !has_type(member) && !both_bools
+        })
+        .map(|member| format!("'{}'", member))
+        .collect()
+}
@@ -0,0 +1,380 @@
+//! Checks that loaded bytecode can run, before any of it does, as `abra execute`
+//! does with compiled files.
+//!
+//! Every label, jump, call, constant and function table index has to point inside
+//! the code, and the types locals are declared with have to be well-formed. Then the
+//! paths through each function are followed to find how many values are on the stack
+//! before each instruction: at least as many as it pops on every path to it, starting
+//! from none where a function starts, and none of them may run past the end of the
+//! code. A call leaves a value when the function it calls returns one.
+
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::compiler::{
+    typecheck::{Algebraic, Composite, Type},
+    ByteCode, Code, Operand,
+};
+use crate::runtime::inbuilt::{generate_inbuilt_function_hashmap, InbuiltFuncMap};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum VerifyError {
+    #[error("there is no '_start' label to start running at")]
+    MissingStart,
+    #[error("label '{0}' points at {1}, past the end of the code")]
+    LabelOutOfRange(String, usize),
+    #[error("instruction {0} refers to label '{1}', which isn't defined")]
+    UnknownLabel(usize, String),
+    #[error("instruction {0} calls '{1}', which is neither defined nor a builtin")]
+    UnknownCall(usize, String),
+    #[error("instruction {0} jumps to {1}, outside the code")]
+    JumpOutOfRange(usize, i64),
+    #[error("instruction {0} calls function #{1}, which the function table doesn't have")]
+    UnknownFunction(usize, usize),
+    #[error("instruction {0} reads constant #{1}, which the constant pool doesn't have")]
+    UnknownConstant(usize, usize),
+    #[error("instruction {0} declares a local of the malformed type {1}")]
+    MalformedType(usize, String),
+    #[error("instruction {0} pops {1} values, but some path reaches it with {2} on the stack")]
+    StackUnderflow(usize, usize, usize),
+    #[error("instruction {0} runs past the end of the code")]
+    RunsPastEnd(usize),
+}
+
+impl VerifyError {
+    /// The instruction the problem is at, if it is at one.
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            VerifyError::MissingStart | VerifyError::LabelOutOfRange(..) => None,
+            VerifyError::UnknownLabel(index, _)
+            | VerifyError::UnknownCall(index, _)
+            | VerifyError::JumpOutOfRange(index, _)
+            | VerifyError::UnknownFunction(index, _)
+            | VerifyError::UnknownConstant(index, _)
+            | VerifyError::MalformedType(index, _)
+            | VerifyError::StackUnderflow(index, ..)
+            | VerifyError::RunsPastEnd(index) => Some(*index),
+        }
+    }
+}
+
+/// Checks `code` as described above, reporting the problem at the lowest index.
+pub fn verify(code: &Code) -> Result<(), VerifyError> {
+    let Some(start) = code.labels.get("_start") else {
+        return Err(VerifyError::MissingStart);
+    };
+    let mut labels: Vec<(&String, &usize)> = code.labels.iter().collect();
+    labels.sort();
+    if let Some((label, at)) = labels.iter().find(|(_, at)| **at > code.bytecode.len()) {
+        return Err(VerifyError::LabelOutOfRange(label.to_string(), **at));
+    }
+    let inbuilt_functions = generate_inbuilt_function_hashmap();
+    for (index, byte) in code.bytecode.iter().enumerate() {
+        check_operands(code, &inbuilt_functions, index, byte)?;
+    }
+
+    let mut entries = vec![*start];
+    let mut returns = HashMap::new();
+    let functions = code
+        .functions
+        .iter()
+        .filter_map(|func| code.labels.get(func));
+    let closures = code.bytecode.iter().filter_map(|byte| match byte {
+        ByteCode::CLOSURE(label, _, _) => Some(&code.labels[label]),
+        _ => None,
+    });
+    for entry in functions.chain(closures) {
+        returns.insert(*entry, returns_value(code, *entry));
+        entries.push(*entry);
+    }
+    // The function value could be any closure; if one returns a value, it may.
+    let closures_return = code.bytecode.iter().any(|byte| match byte {
+        ByteCode::CLOSURE(label, _, _) => returns.get(&code.labels[label]) == Some(&true),
+        _ => false,
+    });
+    let stack = Stack {
+        code,
+        inbuilt_functions,
+        returns,
+        closures_return,
+    };
+    stack.check(&entries)
+}
+
+/// Checks that what `byte`, at `index`, refers to is there.
+fn check_operands(
+    code: &Code,
+    inbuilt_functions: &InbuiltFuncMap,
+    index: usize,
+    byte: &ByteCode,
+) -> Result<(), VerifyError> {
+    let constant = |constant: usize| match constant < code.constants.len() {
+        true => Ok(()),
+        false => Err(VerifyError::UnknownConstant(index, constant)),
+    };
+    match byte {
+        ByteCode::JMPTO(label)
+        | ByteCode::JITL(label)
+        | ByteCode::PUSHEH(label)
+        | ByteCode::CLOSURE(label, _, _)
+            if !code.labels.contains_key(label) =>
+        {
+            Err(VerifyError::UnknownLabel(index, label.clone()))
+        }
+        ByteCode::CALL(func, _)
+            if !code.labels.contains_key(func) && !inbuilt_functions.contains_key(func) =>
+        {
+            Err(VerifyError::UnknownCall(index, func.clone()))
+        }
+        ByteCode::CALLFN(func, _) => match code.functions.get(*func) {
+            None => Err(VerifyError::UnknownFunction(index, *func)),
+            Some(name) if !code.labels.contains_key(name) => {
+                Err(VerifyError::UnknownCall(index, name.clone()))
+            }
+            Some(_) => Ok(()),
+        },
+        ByteCode::JMPABS(target) | ByteCode::JITA(target) => jump_target(code, index, *target),
+        ByteCode::JMPREL(offset) | ByteCode::JITR(offset) => {
+            jump_target(code, index, index as i64 + offset)
+        }
+        ByteCode::PUSH(index) => constant(*index),
+        ByteCode::BINARY(_, first, second, result) => [first, second, result]
+            .into_iter()
+            .try_for_each(|operand| match operand {
+                Operand::Const(index) => constant(*index),
+                _ => Ok(()),
+            }),
+        ByteCode::DEFVAR(_, ty) | ByteCode::DEFARG(_, ty) if !well_formed(ty) => {
+            Err(VerifyError::MalformedType(index, ty.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn jump_target(code: &Code, index: usize, target: i64) -> Result<(), VerifyError> {
+    match usize::try_from(target) {
+        Ok(at) if at < code.bytecode.len() => Ok(()),
+        _ => Err(VerifyError::JumpOutOfRange(index, target)),
+    }
+}
+
+/// Whether `ty` can be the type of a value: it names its classes and type variables
+/// and isn't left over from a failed check.
+fn well_formed(ty: &Type) -> bool {
+    let name = |name: &str| {
+        name.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    };
+    match ty {
+        Type::Null | Type::Primitive(_) => true,
+        Type::Abra(class) | Type::Var(class) => name(class),
+        Type::Generic(class, args) => {
+            !args.is_empty() && name(class) && args.iter().all(well_formed)
+        }
+        Type::Function(params, result) => params.iter().all(well_formed) && well_formed(result),
+        Type::Composite(composite) => match composite.as_ref() {
+            Composite::Array(element) | Composite::HeapValue(element) => well_formed(element),
+            Composite::Map(key, value) => well_formed(key) && well_formed(value),
+            Composite::Tuple(elements) => elements.iter().all(well_formed),
+        },
+        Type::Algebraic(algebraic) => match algebraic.as_ref() {
+            Algebraic::Or(left, right) => well_formed(left) && well_formed(right),
+        },
+        Type::Unknown => false,
+    }
+}
+
+/// The instructions that may run right after `index`, if it runs at all; an index
+/// past the end of the code means it runs off it.
+fn successors(code: &Code, index: usize) -> Vec<usize> {
+    let label = |label: &String| code.labels[label];
+    match &code.bytecode[index] {
+        ByteCode::RET(_) | ByteCode::EXIT | ByteCode::THROW => vec![],
+        ByteCode::JMPTO(target) => vec![label(target)],
+        ByteCode::JMPABS(target) => vec![*target as usize],
+        ByteCode::JMPREL(offset) => vec![(index as i64 + offset) as usize],
+        ByteCode::JITL(target) => vec![index + 1, label(target)],
+        ByteCode::JITA(target) => vec![index + 1, *target as usize],
+        ByteCode::JITR(offset) => vec![index + 1, (index as i64 + offset) as usize],
+        ByteCode::PUSHEH(handler) => vec![index + 1, label(handler)],
+        _ => vec![index + 1],
+    }
+}
+
+/// Whether the function starting at `entry` returns a value. One that never returns
+/// counts as returning one, as nothing after a call to it runs.
+fn returns_value(code: &Code, entry: usize) -> bool {
+    if entry >= code.bytecode.len() {
+        return true;
+    }
+    let mut seen = HashSet::from([entry]);
+    let mut pending = vec![entry];
+    while let Some(index) = pending.pop() {
+        if let ByteCode::RET(value) = code.bytecode[index] {
+            return value;
+        }
+        for next in successors(code, index) {
+            if next < code.bytecode.len() && seen.insert(next) {
+                pending.push(next);
+            }
+        }
+    }
+    true
+}
+
+struct Stack<'a> {
+    code: &'a Code,
+    inbuilt_functions: InbuiltFuncMap,
+    /// Whether the function starting at each index returns a value.
+    returns: HashMap<usize, bool>,
+    /// Whether any closure returns a value.
+    closures_return: bool,
+}
+
+impl Stack<'_> {
+    /// Follows every path from `entries`, each a function start, with an empty stack.
+    fn check(&self, entries: &[usize]) -> Result<(), VerifyError> {
+        let code = self.code;
+        let mut depths: Vec<Option<usize>> = vec![None; code.bytecode.len()];
+        let mut pending = Vec::new();
+        for entry in entries {
+            if *entry >= code.bytecode.len() {
+                continue;
+            }
+            depths[*entry] = Some(0);
+            pending.push(*entry);
+        }
+        let mut past_end = None;
+        while let Some(index) = pending.pop() {
+            let depth = depths[index].expect("pending instructions are reached");
+            let (pops, pushes) = self.effect(&code.bytecode[index]);
+            let after = depth.saturating_sub(pops) + pushes;
+            for next in successors(code, index) {
+                // A `try`'s handler starts with the stack as it was, and the error.
+                let depth = match &code.bytecode[index] {
+                    ByteCode::PUSHEH(handler) if code.labels[handler] == next => depth + 1,
+                    _ => after,
+                };
+                if next >= code.bytecode.len() {
+                    past_end = Some(past_end.map_or(index, |end: usize| end.min(index)));
+                    continue;
+                }
+                if depths[next].is_none_or(|known| depth < known) {
+                    depths[next] = Some(depth);
+                    pending.push(next);
+                }
+            }
+        }
+        for (index, depth) in depths.iter().enumerate() {
+            if past_end == Some(index) {
+                return Err(VerifyError::RunsPastEnd(index));
+            }
+            let Some(depth) = *depth else {
+                continue;
+            };
+            let (pops, _) = self.effect(&code.bytecode[index]);
+            if pops > depth {
+                return Err(VerifyError::StackUnderflow(index, pops, depth));
+            }
+        }
+        Ok(())
+    }
+
+    /// How many values `byte` pops off the stack and then pushes.
+    fn effect(&self, byte: &ByteCode) -> (usize, usize) {
+        let code = self.code;
+        let returns = |label: &str| {
+            let entry = code.labels.get(label);
+            entry.is_some_and(|entry| self.returns.get(entry) == Some(&true)) as usize
+        };
+        // Which method a call runs is only known then; any that returns a value may.
+        let method_returns = |method: &str| {
+            let suffix = format!("::{}", method);
+            code.functions
+                .iter()
+                .any(|func| func.ends_with(&suffix) && returns(func) == 1) as usize
+        };
+        match byte {
+            ByteCode::PUSH(_)
+            | ByteCode::LOADSLOT(_)
+            | ByteCode::GETVARLOCAL(_)
+            | ByteCode::GETVARGLOBAL(_) => (0, 1),
+            ByteCode::POP
+            | ByteCode::STORESLOT(_)
+            | ByteCode::SAVEVARLOCAL(_)
+            | ByteCode::SAVEVARGLOBAL(_)
+            | ByteCode::DEFVAR(_, _)
+            | ByteCode::THROW
+            | ByteCode::JITL(_)
+            | ByteCode::JITA(_)
+            | ByteCode::JITR(_) => (1, 0),
+            ByteCode::DUP => (1, 2),
+            ByteCode::ADD
+            | ByteCode::SUB
+            | ByteCode::MULT
+            | ByteCode::DIV
+            | ByteCode::MOD
+            | ByteCode::EQUALS
+            | ByteCode::LESSER
+            | ByteCode::GREATER
+            | ByteCode::EQLESS
+            | ByteCode::EQGREAT
+            | ByteCode::AND
+            | ByteCode::OR
+            | ByteCode::XOR
+            | ByteCode::BAND
+            | ByteCode::BOR
+            | ByteCode::BXOR
+            | ByteCode::SHL
+            | ByteCode::SHR
+            | ByteCode::GETFROMREF => (2, 1),
+            ByteCode::NEGATE
+            | ByteCode::BNOT
+            | ByteCode::NOT
+            | ByteCode::CAST(_)
+            | ByteCode::ISTYPE(_)
+            | ByteCode::ISVARIANT(_) => (1, 1),
+            ByteCode::SLICE => (3, 1),
+            ByteCode::SAVETOREF => (3, 0),
+            ByteCode::VARIANT(_, argc) | ByteCode::INSTANCE(_, argc) => (*argc, 1),
+            ByteCode::CLOSURE(_, _, captures) => (captures.len(), 1),
+            ByteCode::CALL(func, argc) => {
+                let pushes = match self.inbuilt_functions.get(func) {
+                    Some((signature, _)) => (*signature.return_type() != Type::Null) as usize,
+                    None => returns(func),
+                };
+                (*argc as usize, pushes)
+            }
+            ByteCode::CALLFN(func, argc) => (*argc as usize, returns(&code.functions[*func])),
+            ByteCode::CALLVIRT(method, argc) => (*argc as usize + 1, method_returns(method)),
+            ByteCode::CALLSUPER(class, method, argc) => {
+                let pushes = match code.labels.contains_key(&format!("{}::{}", class, method)) {
+                    true => returns(&format!("{}::{}", class, method)),
+                    false => method_returns(method),
+                };
+                (*argc as usize, pushes)
+            }
+            ByteCode::CALLINDIRECT(argc) => (*argc as usize + 1, self.closures_return as usize),
+            ByteCode::RET(value) => (*value as usize, 0),
+            ByteCode::BINARY(_, first, second, result) => {
+                let pops = [first, second]
+                    .into_iter()
+                    .filter(|operand| **operand == Operand::Stack)
+                    .count();
+                (pops, (*result == Operand::Stack) as usize)
+            }
+            ByteCode::JMPABS(_)
+            | ByteCode::JMPREL(_)
+            | ByteCode::JMPTO(_)
+            | ByteCode::DROPSLOT(_)
+            | ByteCode::DEFARG(_, _)
+            | ByteCode::DROPVAR(_)
+            | ByteCode::PUSHEH(_)
+            | ByteCode::POPEH
+            | ByteCode::EXIT => (0, 0),
+        }
+    }
+}
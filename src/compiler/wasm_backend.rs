@@ -0,0 +1,804 @@
+//! WebAssembly output, for `abra compile --target wasm`.
+//!
+//! Lowers the compiled `Code` of a program to a module exporting its entry as
+//! `_start`, which returns the exit code. Only programs working on `int`s, `bool`s
+//! and string literals in locals can be lowered: arithmetic, comparisons, jumps,
+//! calls and builtins, no objects, arrays, globals or exceptions.
+//!
+//! Builtins become functions the host provides in the `abra` import module, named
+//! after the builtin, with the types of the arguments appended for the ones that
+//! take any type: `print(1)` calls `abra.print_int`. Every value is an `i64`; a
+//! string literal is its offset in the exported `memory` in the upper half and its
+//! length in bytes in the lower half.
+//!
+//! Wasm only has structured control flow, so each function is a loop around a
+//! `br_table` over its basic blocks, and the operand stack lives in locals, one per
+//! depth. `int` arithmetic that overflows and division by zero trap, where the VM
+//! stops with a runtime error.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    compiler::{
+        analysis::{self, is_conditional, jump_target, Kind, Shape, Signatures},
+        typecheck::Type,
+        BinaryOp, ByteCode, Code, Operand,
+    },
+    runtime::{
+        inbuilt::{generate_inbuilt_function_hashmap, InbuiltFuncMap},
+        value::StaticValue,
+    },
+};
+
+/// Import module the builtins are looked up in.
+const HOST_MODULE: &str = "abra";
+const PAGE_SIZE: usize = 65536;
+
+/// A builtin called with arguments of particular kinds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Import {
+    field: String,
+    params: usize,
+    returns: Option<Kind>,
+}
+
+/// The import for a call to the builtin `name` with arguments of kinds `args`.
+fn builtin(builtins: &InbuiltFuncMap, name: &str, args: &[Kind]) -> Result<Import, String> {
+    let (signature, _) = builtins
+        .get(name)
+        .ok_or_else(|| format!("'{}' is not a builtin", name))?;
+    if signature.parameters().len() != args.len() {
+        return Err(format!("'{}' called with {} arguments", name, args.len()));
+    }
+    let mut field = name.to_string();
+    let mut bound = HashMap::new();
+    for (param, kind) in signature.parameters().iter().zip(args) {
+        match param {
+            Type::Var(var) => {
+                field = format!("{}_{}", field, kind.name());
+                bound.insert(var, *kind);
+            }
+            ty => {
+                Kind::of_type(ty)
+                    .and_then(|expected| kind.join(expected))
+                    .ok_or_else(|| format!("'{}' takes a {}", name, ty))?;
+            }
+        }
+    }
+    let returns = match signature.return_type() {
+        Type::Null => None,
+        Type::Var(var) => Some(*bound.get(var).unwrap_or(&Kind::Unknown)),
+        ty => Some(Kind::of_type(ty).ok_or_else(|| format!("'{}' returns a {}", name, ty))?),
+    };
+    Ok(Import {
+        field,
+        params: args.len(),
+        returns,
+    })
+}
+
+/// The program as the analysis sees it, with the builtins it may call.
+#[derive(Clone, Copy)]
+struct Lowering<'a> {
+    code: &'a Code,
+    builtins: &'a InbuiltFuncMap,
+}
+
+impl analysis::Program for Lowering<'_> {
+    fn bytecode(&self) -> &[ByteCode] {
+        &self.code.bytecode
+    }
+
+    fn constant(&self, index: usize) -> Option<Kind> {
+        match self.code.constants.get(index)? {
+            StaticValue::Integer(_) => Some(Kind::Int),
+            StaticValue::Bool(_) => Some(Kind::Bool),
+            StaticValue::String(_) => Some(Kind::Str),
+            _ => None,
+        }
+    }
+
+    fn function(&self, index: usize) -> Option<usize> {
+        self.label(self.code.functions.get(index)?)
+    }
+
+    fn label(&self, name: &str) -> Option<usize> {
+        self.code.labels.get(name).copied()
+    }
+
+    fn builtin(&self, name: &str, args: &[Kind]) -> Result<Option<Kind>, String> {
+        builtin(self.builtins, name, args).map(|import| import.returns)
+    }
+}
+
+/// A function of the program and the kinds of what it works on.
+struct Function {
+    name: String,
+    shape: Shape,
+}
+
+/// Instructions starting a basic block, in order.
+fn leaders(program: &Lowering, shape: &Shape) -> Result<Vec<usize>, String> {
+    let mut leaders = BTreeSet::from([shape.body]);
+    for &pc in &shape.reachable {
+        let byte = &program.code.bytecode[pc];
+        if let Some(target) = jump_target(program, pc, byte) {
+            leaders.insert(target?);
+            if is_conditional(byte) {
+                leaders.insert(pc + 1);
+            }
+        }
+    }
+    Ok(leaders.into_iter().collect())
+}
+
+/// Lowers the program in `code` to the bytes of a WebAssembly module.
+pub fn compile_to_wasm(code: &Code) -> Result<Vec<u8>> {
+    let builtins = generate_inbuilt_function_hashmap();
+    let entry = *code
+        .labels
+        .get("_start")
+        .ok_or_else(|| anyhow!("The program has no entry point"))?;
+    let program = Lowering {
+        code,
+        builtins: &builtins,
+    };
+    let mut functions = vec![scan(&program, "_start", entry)?];
+    for name in &code.functions {
+        let address = *code
+            .labels
+            .get(name)
+            .ok_or_else(|| anyhow!("Function '{}' has no code", name))?;
+        functions.push(scan(&program, name, address)?);
+    }
+    let mut shapes: Vec<&mut Shape> = functions.iter_mut().map(|f| &mut f.shape).collect();
+    analysis::infer(&program, &mut shapes, &Signatures::new())
+        .map_err(|(index, reason)| unsupported(&functions[index].name, reason))?;
+
+    let mut imports: Vec<Import> = Vec::new();
+    for function in &functions {
+        for &pc in &function.shape.reachable {
+            if let ByteCode::CALL(name, argc) = &code.bytecode[pc] {
+                let args = function.shape.arguments(pc, *argc);
+                if args.contains(&Kind::Unknown) {
+                    let reason = format!("the type of an argument to '{}' isn't known", name);
+                    return Err(unsupported(&function.name, reason));
+                }
+                let import = builtin(&builtins, name, args)
+                    .map_err(|reason| unsupported(&function.name, reason))?;
+                if !imports.contains(&import) {
+                    imports.push(import);
+                }
+            }
+        }
+    }
+
+    let strings = Strings::lay_out(&code.constants);
+    let module = Module {
+        code,
+        builtins: &builtins,
+        imports: &imports,
+        functions: &functions,
+        indices: functions
+            .iter()
+            .enumerate()
+            .map(|(index, function)| (function.shape.address, (imports.len() + index) as u32))
+            .collect(),
+        strings: &strings,
+    };
+    module
+        .encode()
+        .map_err(|(name, reason)| unsupported(&name, reason))
+}
+
+fn scan(program: &Lowering, name: &str, address: usize) -> Result<Function> {
+    let shape = Shape::scan(program, address).map_err(|reason| unsupported(name, reason))?;
+    Ok(Function {
+        name: name.to_string(),
+        shape,
+    })
+}
+
+fn unsupported(function: &str, reason: String) -> anyhow::Error {
+    anyhow!("Can't compile '{}' to WebAssembly: {}", function, reason)
+}
+
+/// Where the string constants go in linear memory.
+struct Strings {
+    data: Vec<u8>,
+    /// Offset and length by constant index.
+    spans: HashMap<usize, (usize, usize)>,
+}
+
+impl Strings {
+    fn lay_out(constants: &[StaticValue]) -> Strings {
+        let mut data = Vec::new();
+        let mut spans = HashMap::new();
+        for (index, constant) in constants.iter().enumerate() {
+            if let StaticValue::String(string) = constant {
+                spans.insert(index, (data.len(), string.len()));
+                data.extend_from_slice(string.as_bytes());
+            }
+        }
+        Strings { data, spans }
+    }
+
+    fn packed(&self, constant: usize) -> i64 {
+        let (offset, len) = self.spans[&constant];
+        ((offset as i64) << 32) | len as i64
+    }
+}
+
+mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_TABLE: u8 = 0x0e;
+    pub const RETURN: u8 = 0x0f;
+    pub const CALL: u8 = 0x10;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I64_CONST: u8 = 0x42;
+    pub const I64_EQZ: u8 = 0x50;
+    pub const I64_EQ: u8 = 0x51;
+    pub const I64_NE: u8 = 0x52;
+    pub const I64_LT_S: u8 = 0x53;
+    pub const I64_GT_S: u8 = 0x55;
+    pub const I64_LE_S: u8 = 0x57;
+    pub const I64_GE_S: u8 = 0x59;
+    pub const I64_ADD: u8 = 0x7c;
+    pub const I64_SUB: u8 = 0x7d;
+    pub const I64_MUL: u8 = 0x7e;
+    pub const I64_DIV_S: u8 = 0x7f;
+    pub const I64_REM_S: u8 = 0x81;
+    pub const I64_AND: u8 = 0x83;
+    pub const I64_OR: u8 = 0x84;
+    pub const I64_XOR: u8 = 0x85;
+    pub const I32_WRAP_I64: u8 = 0xa7;
+    pub const I64_EXTEND_I32_U: u8 = 0xad;
+    pub const EMPTY: u8 = 0x40;
+    pub const I32: u8 = 0x7f;
+    pub const I64: u8 = 0x7e;
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Appends the section `id` holding `count` entries, encoded in `entries`.
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: Vec<u8>) {
+    let mut contents = Vec::new();
+    uleb(&mut contents, count as u64);
+    contents.extend(entries);
+    out.push(id);
+    uleb(out, contents.len() as u64);
+    out.extend(contents);
+}
+
+/// A function type: how many `i64` parameters, and whether it returns an `i64`.
+type FuncType = (usize, bool);
+
+struct Module<'a> {
+    code: &'a Code,
+    builtins: &'a InbuiltFuncMap,
+    imports: &'a [Import],
+    functions: &'a [Function],
+    /// Wasm function index by entry address.
+    indices: HashMap<usize, u32>,
+    strings: &'a Strings,
+}
+
+impl Module<'_> {
+    fn program(&self) -> Lowering<'_> {
+        Lowering {
+            code: self.code,
+            builtins: self.builtins,
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, (String, String)> {
+        let mut types: Vec<FuncType> = Vec::new();
+        let mut type_of = |ty: FuncType| match types.iter().position(|other| *other == ty) {
+            Some(index) => index as u32,
+            None => {
+                types.push(ty);
+                types.len() as u32 - 1
+            }
+        };
+
+        let mut imports = Vec::new();
+        for import in self.imports {
+            name(&mut imports, HOST_MODULE);
+            name(&mut imports, &import.field);
+            imports.push(0x00);
+            uleb(
+                &mut imports,
+                type_of((import.params, import.returns.is_some())) as u64,
+            );
+        }
+        let mut declarations = Vec::new();
+        let mut bodies = Vec::new();
+        for function in self.functions {
+            let ty = type_of((function.shape.params.len(), function.shape.returns_value));
+            uleb(&mut declarations, ty as u64);
+            let body = self
+                .body(function)
+                .map_err(|reason| (function.name.clone(), reason))?;
+            uleb(&mut bodies, body.len() as u64);
+            bodies.extend(body);
+        }
+
+        let mut module = b"\0asm".to_vec();
+        module.extend(1u32.to_le_bytes());
+        let mut entries = Vec::new();
+        for (params, returns) in &types {
+            entries.push(0x60);
+            uleb(&mut entries, *params as u64);
+            entries.extend(std::iter::repeat_n(op::I64, *params));
+            uleb(&mut entries, *returns as u64);
+            if *returns {
+                entries.push(op::I64);
+            }
+        }
+        section(&mut module, 1, types.len(), entries);
+        section(&mut module, 2, self.imports.len(), imports);
+        section(&mut module, 3, self.functions.len(), declarations);
+
+        let pages = self.strings.data.len().div_ceil(PAGE_SIZE).max(1);
+        let mut memory = vec![0x00];
+        uleb(&mut memory, pages as u64);
+        section(&mut module, 5, 1, memory);
+
+        let mut exports = Vec::new();
+        name(&mut exports, "_start");
+        exports.push(0x00);
+        uleb(&mut exports, self.imports.len() as u64);
+        name(&mut exports, "memory");
+        exports.push(0x02);
+        uleb(&mut exports, 0);
+        section(&mut module, 7, 2, exports);
+
+        section(&mut module, 10, self.functions.len(), bodies);
+
+        let mut data = vec![0x00, op::I32_CONST, 0x00, op::END];
+        uleb(&mut data, self.strings.data.len() as u64);
+        data.extend_from_slice(&self.strings.data);
+        section(&mut module, 11, 1, data);
+        Ok(module)
+    }
+
+    /// The locals and instructions of `function`.
+    fn body(&self, function: &Function) -> Result<Vec<u8>, String> {
+        let code = self.code;
+        let leaders = leaders(&self.program(), &function.shape)?;
+        let blocks: HashMap<usize, usize> = leaders
+            .iter()
+            .enumerate()
+            .map(|(index, pc)| (*pc, index))
+            .collect();
+        let depth = function
+            .shape
+            .stacks
+            .values()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let locals = Locals::new(function, depth);
+
+        let mut out = Vec::new();
+        // The slots that aren't parameters, the stack and the scratch local, then the
+        // block to run.
+        uleb(&mut out, 2);
+        uleb(
+            &mut out,
+            (locals.slots.len() - locals.params + depth + 1) as u64,
+        );
+        out.push(op::I64);
+        uleb(&mut out, 1);
+        out.push(op::I32);
+
+        out.push(op::I32_CONST);
+        sleb(&mut out, blocks[&function.shape.body] as i64);
+        out.extend([op::LOCAL_SET]);
+        uleb(&mut out, locals.block as u64);
+        out.extend([op::LOOP, op::EMPTY]);
+        for _ in &leaders {
+            out.extend([op::BLOCK, op::EMPTY]);
+        }
+        out.push(op::LOCAL_GET);
+        uleb(&mut out, locals.block as u64);
+        out.push(op::BR_TABLE);
+        uleb(&mut out, leaders.len() as u64);
+        for index in 0..leaders.len() {
+            uleb(&mut out, index as u64);
+        }
+        uleb(&mut out, 0);
+
+        for (index, leader) in leaders.iter().enumerate() {
+            out.push(op::END);
+            let mut emitter = Emitter {
+                module: self,
+                function,
+                locals: &locals,
+                blocks: &blocks,
+                // Blocks left to close before the loop is reached.
+                nesting: (leaders.len() - 1 - index) as u64,
+                out: &mut out,
+            };
+            let mut pc = *leader;
+            loop {
+                let byte = &code.bytecode[pc];
+                if !emitter.instruction(pc, byte)? {
+                    break;
+                }
+                pc += 1;
+                if blocks.contains_key(&pc) || !function.shape.reachable.contains(&pc) {
+                    break;
+                }
+            }
+        }
+        out.extend([op::END, op::UNREACHABLE, op::END]);
+        Ok(out)
+    }
+}
+
+/// Wasm local indices of a function's slots, operand stack and current block.
+struct Locals {
+    /// Parameters come first, in their order; the other slots follow.
+    slots: HashMap<u16, u32>,
+    params: usize,
+    /// Local of the bottom of the stack.
+    stack: u32,
+    /// Holds the result of an arithmetic operation while it is checked for overflow.
+    scratch: u32,
+    block: u32,
+}
+
+impl Locals {
+    fn new(function: &Function, depth: usize) -> Locals {
+        let mut slots: HashMap<u16, u32> = function
+            .shape
+            .params
+            .iter()
+            .enumerate()
+            .map(|(index, (slot, _))| (*slot, index as u32))
+            .collect();
+        let params = slots.len();
+        for slot in function.shape.slots.keys() {
+            let next = slots.len() as u32;
+            slots.entry(*slot).or_insert(next);
+        }
+        let stack = slots.len() as u32;
+        Locals {
+            scratch: stack + depth as u32,
+            block: stack + depth as u32 + 1,
+            slots,
+            params,
+            stack,
+        }
+    }
+}
+
+/// Where an operand of a `BINARY` is read from.
+#[derive(Clone, Copy)]
+enum Source {
+    Local(u32),
+    Const(usize),
+}
+
+/// Writes the instructions of one basic block.
+struct Emitter<'a> {
+    module: &'a Module<'a>,
+    function: &'a Function,
+    locals: &'a Locals,
+    blocks: &'a HashMap<usize, usize>,
+    nesting: u64,
+    out: &'a mut Vec<u8>,
+}
+
+impl Emitter<'_> {
+    fn get(&mut self, local: u32) {
+        self.out.push(op::LOCAL_GET);
+        uleb(self.out, local as u64);
+    }
+
+    fn set(&mut self, local: u32) {
+        self.out.push(op::LOCAL_SET);
+        uleb(self.out, local as u64);
+    }
+
+    fn slot(&self, slot: u16) -> u32 {
+        self.locals.slots[&slot]
+    }
+
+    /// Local of the stack entry at `depth`.
+    fn stack(&self, depth: usize) -> u32 {
+        self.locals.stack + depth as u32
+    }
+
+    fn constant(&mut self, constant: usize) -> Result<(), String> {
+        let value = match &self.module.code.constants[constant] {
+            StaticValue::Integer(value) => *value,
+            StaticValue::Bool(value) => *value as i64,
+            StaticValue::String(_) => self.module.strings.packed(constant),
+            other => return Err(format!("the constant {} is not supported", other)),
+        };
+        self.out.push(op::I64_CONST);
+        sleb(self.out, value);
+        Ok(())
+    }
+
+    /// Continues at the block starting at `target`, from `extra` levels inside the block.
+    fn branch(&mut self, target: usize, extra: u64) {
+        self.out.push(op::I32_CONST);
+        sleb(self.out, self.blocks[&target] as i64);
+        self.set(self.locals.block);
+        self.out.push(op::BR);
+        uleb(self.out, self.nesting + extra);
+    }
+
+    /// Writes `byte`, returning whether control can go on to the next instruction.
+    fn instruction(&mut self, pc: usize, byte: &ByteCode) -> Result<bool, String> {
+        let code = self.module.code;
+        let depth = self.function.shape.stacks[&pc].len();
+        match byte {
+            ByteCode::PUSH(constant) => {
+                self.constant(*constant)?;
+                self.set(self.stack(depth));
+            }
+            ByteCode::POP | ByteCode::DROPSLOT(_) => {}
+            ByteCode::DUP => {
+                self.get(self.stack(depth - 1));
+                self.set(self.stack(depth));
+            }
+            ByteCode::LOADSLOT(slot) => {
+                self.get(self.slot(*slot));
+                self.set(self.stack(depth));
+            }
+            ByteCode::STORESLOT(slot) => {
+                self.get(self.stack(depth - 1));
+                self.set(self.slot(*slot));
+            }
+            ByteCode::EQUALS => {
+                self.get(self.stack(depth - 1));
+                self.get(self.stack(depth - 2));
+                self.out.extend([op::I64_EQ, op::I64_EXTEND_I32_U]);
+                self.set(self.stack(depth - 2));
+            }
+            ByteCode::NEGATE | ByteCode::NOT => {
+                self.get(self.stack(depth - 1));
+                self.out.extend([op::I64_EQZ, op::I64_EXTEND_I32_U]);
+                self.set(self.stack(depth - 1));
+            }
+            ByteCode::AND | ByteCode::OR | ByteCode::XOR => {
+                self.get(self.stack(depth - 1));
+                self.get(self.stack(depth - 2));
+                self.out.push(match byte {
+                    ByteCode::AND => op::I64_AND,
+                    ByteCode::OR => op::I64_OR,
+                    _ => op::I64_XOR,
+                });
+                self.set(self.stack(depth - 2));
+            }
+            ByteCode::BINARY(op, first, second, result) => {
+                self.binary(depth, *op, *first, *second, *result)?
+            }
+            ByteCode::CALLFN(index, argc) => {
+                let address = code.labels[&code.functions[*index]];
+                let callee = self.module.indices[&address];
+                let returns = self.module.functions[callee as usize - self.module.imports.len()]
+                    .shape
+                    .returns_value;
+                self.call(depth, callee, *argc, returns);
+            }
+            ByteCode::CALL(name, argc) => {
+                let import = builtin(
+                    self.module.builtins,
+                    name,
+                    self.function.shape.arguments(pc, *argc),
+                )?;
+                let index = self
+                    .module
+                    .imports
+                    .iter()
+                    .position(|other| *other == import);
+                self.call(
+                    depth,
+                    index.unwrap() as u32,
+                    *argc,
+                    import.returns.is_some(),
+                );
+            }
+            ByteCode::RET(value) => {
+                if *value {
+                    self.get(self.stack(depth - 1));
+                }
+                self.out.push(op::RETURN);
+                return Ok(false);
+            }
+            ByteCode::EXIT => {
+                match depth {
+                    0 => {
+                        self.out.push(op::I64_CONST);
+                        sleb(self.out, 0);
+                    }
+                    depth => self.get(self.stack(depth - 1)),
+                }
+                self.out.push(op::RETURN);
+                return Ok(false);
+            }
+            byte => {
+                if let Some(target) = jump_target(&self.module.program(), pc, byte) {
+                    let target = target?;
+                    if !is_conditional(byte) {
+                        self.branch(target, 0);
+                        return Ok(false);
+                    }
+                    self.get(self.stack(depth - 1));
+                    self.out.extend([op::I32_WRAP_I64, op::IF, op::EMPTY]);
+                    self.branch(target, 1);
+                    self.out.push(op::END);
+                    return Ok(true);
+                }
+                let op =
+                    BinaryOp::of(byte).ok_or_else(|| format!("{:?} is not supported", byte))?;
+                self.binary(depth, op, Operand::Stack, Operand::Stack, Operand::Stack)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn call(&mut self, depth: usize, callee: u32, argc: u64, returns: bool) {
+        let base = depth - argc as usize;
+        for depth in base..depth {
+            self.get(self.stack(depth));
+        }
+        self.out.push(op::CALL);
+        uleb(self.out, callee as u64);
+        if returns {
+            self.set(self.stack(base));
+        }
+    }
+
+    /// Writes `op` the way the VM runs it: `first` is the operand the stack form pops
+    /// first, the left-hand side, so `SUB` computes `first - second`.
+    fn binary(
+        &mut self,
+        mut depth: usize,
+        op: BinaryOp,
+        first: Operand,
+        second: Operand,
+        result: Operand,
+    ) -> Result<(), String> {
+        let mut source = |operand: Operand| match operand {
+            Operand::Stack => {
+                depth -= 1;
+                Source::Local(self.stack(depth))
+            }
+            Operand::Slot(slot) => Source::Local(self.slot(slot)),
+            Operand::Const(constant) => Source::Const(constant),
+        };
+        let (first, second) = (source(first), source(second));
+        self.source(first)?;
+        self.source(second)?;
+        let (code, compares) = match op {
+            BinaryOp::Add => (op::I64_ADD, false),
+            BinaryOp::Sub => (op::I64_SUB, false),
+            BinaryOp::Mult => (op::I64_MUL, false),
+            BinaryOp::Div => (op::I64_DIV_S, false),
+            BinaryOp::Mod => (op::I64_REM_S, false),
+            BinaryOp::Lesser => (op::I64_LT_S, true),
+            BinaryOp::Greater => (op::I64_GT_S, true),
+            BinaryOp::EqLess => (op::I64_LE_S, true),
+            BinaryOp::EqGreat => (op::I64_GE_S, true),
+        };
+        self.out.push(code);
+        if compares {
+            self.out.push(op::I64_EXTEND_I32_U);
+        }
+        if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mult) {
+            // The result can alias an operand, so it waits in the scratch local.
+            self.set(self.locals.scratch);
+            self.overflow_check(op, first, second)?;
+            self.get(self.locals.scratch);
+        }
+        match result {
+            Operand::Stack => self.set(self.stack(depth)),
+            Operand::Slot(slot) => self.set(self.slot(slot)),
+            Operand::Const(_) => return Err("a write to a constant".into()),
+        }
+        Ok(())
+    }
+
+    fn source(&mut self, source: Source) -> Result<(), String> {
+        match source {
+            Source::Local(local) => self.get(local),
+            Source::Const(constant) => self.constant(constant)?,
+        }
+        Ok(())
+    }
+
+    /// Traps if the wrapped result of `first op second`, in the scratch local,
+    /// overflowed. Division needs no check: `i64.div_s` traps by itself.
+    fn overflow_check(
+        &mut self,
+        op: BinaryOp,
+        first: Source,
+        second: Source,
+    ) -> Result<(), String> {
+        let result = Source::Local(self.locals.scratch);
+        match op {
+            // The sign of a sum is wrong when both operands have the other sign.
+            BinaryOp::Add => {
+                self.xor(first, result)?;
+                self.xor(second, result)?;
+            }
+            // And a difference's when the operands differ in sign and the first's differs
+            // from it.
+            BinaryOp::Sub => {
+                self.xor(first, second)?;
+                self.xor(first, result)?;
+            }
+            // A product overflowed when dividing it by a non-zero `first` doesn't give
+            // back `second`; `int::MIN * -1` traps in the division itself.
+            _ => {
+                self.source(first)?;
+                self.out
+                    .extend([op::I64_CONST, 0, op::I64_NE, op::IF, op::EMPTY]);
+                self.source(result)?;
+                self.source(first)?;
+                self.out.push(op::I64_DIV_S);
+                self.source(second)?;
+                self.out
+                    .extend([op::I64_NE, op::IF, op::EMPTY, op::UNREACHABLE, op::END]);
+                self.out.push(op::END);
+                return Ok(());
+            }
+        }
+        self.out
+            .extend([op::I64_AND, op::I64_CONST, 0, op::I64_LT_S]);
+        self.out
+            .extend([op::IF, op::EMPTY, op::UNREACHABLE, op::END]);
+        Ok(())
+    }
+
+    fn xor(&mut self, a: Source, b: Source) -> Result<(), String> {
+        self.source(a)?;
+        self.source(b)?;
+        self.out.push(op::I64_XOR);
+        Ok(())
+    }
+}
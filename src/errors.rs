@@ -1 +1,234 @@
+//! Errors of every phase, for callers that need to tell them apart.
+//!
+//! Each error has a stable code: `E01xx` from the tokenizer, `E02xx` from the
+//! parser, `E03xx` from the typechecker, `E04xx` from loading and compiling,
+//! `E05xx` from running and `E06xx` from misusing the embedding API. Errors found
+//! in the source carry the [`Span`] they point at and display as `line:col: message`;
+//! the CLI renders them with the offending line.
 
+use thiserror::Error;
+
+use crate::{frontend::span::Span, runtime::debug::RuntimeError};
+
+/// Declares a phase's errors, each a message about a place in the source.
+macro_rules! located_errors {
+    ($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $code:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Error)]
+        pub enum $name {
+            $($(#[$variant_meta])* #[error("{0}: {1}")] $variant(Span, String),)*
+        }
+
+        impl $name {
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $($name::$variant(..) => $code,)*
+                }
+            }
+
+            pub fn span(&self) -> &Span {
+                match self {
+                    $($name::$variant(span, _))|* => span,
+                }
+            }
+
+            // Only the typechecker places errors after creating them.
+            #[allow(dead_code)]
+            pub(crate) fn span_mut(&mut self) -> &mut Span {
+                match self {
+                    $($name::$variant(span, _))|* => span,
+                }
+            }
+
+            /// The message without its location.
+            pub fn message(&self) -> &str {
+                match self {
+                    $($name::$variant(_, message))|* => message,
+                }
+            }
+        }
+    };
+}
+
+located_errors! {
+    /// Source text that isn't made of valid tokens.
+    LexError {
+        UnexpectedCharacter = "E0101",
+        UnterminatedLiteral = "E0102",
+        InvalidEscape = "E0103",
+        InvalidNumber = "E0104",
+        InvalidCharLiteral = "E0105",
+        Indentation = "E0106",
+    }
+}
+
+located_errors! {
+    /// Tokens that don't form a program.
+    ParseError {
+        UnexpectedToken = "E0201",
+        UnexpectedEof = "E0202",
+        EmptyBlock = "E0203",
+        InvalidRange = "E0204",
+        /// Constructs the grammar accepts but the language doesn't support there.
+        InvalidSyntax = "E0205",
+    }
+}
+
+located_errors! {
+    /// A program that parses but doesn't typecheck.
+    TypeError {
+        Mismatch = "E0301",
+        Undefined = "E0302",
+        Duplicate = "E0303",
+        Arity = "E0304",
+        /// An operator, call or member access the operand's type doesn't support.
+        InvalidOperation = "E0305",
+        /// A statement where it isn't allowed, like `return` outside a function.
+        Misplaced = "E0306",
+        /// A warning, reported as an error because warnings are treated as errors.
+        DeniedWarning = "E0307",
+        /// A `const` assigned to, or given a value that isn't known while compiling or
+        /// can't be computed.
+        Constant = "E0308",
+        /// A variable declared without a value, read where a path may not assign it, or
+        /// a field with nothing to start out as.
+        Unassigned = "E0309",
+    }
+}
+
+/// Errors from putting the files of a program together and compiling them.
+#[derive(Debug, Clone, Error)]
+pub enum CompileError {
+    #[error("No 'main' function defined")]
+    MissingMain,
+    /// A name defined in more than one file.
+    #[error("{0}: {1}")]
+    DuplicateDefinition(Span, String),
+    /// An import that can't be loaded, or a `module::name` that doesn't resolve.
+    #[error("{0}: {1}")]
+    Import(Span, String),
+    /// A program the typechecker accepted but the compiler can't translate.
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl CompileError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::MissingMain => "E0401",
+            CompileError::DuplicateDefinition(..) => "E0402",
+            CompileError::Import(..) => "E0403",
+            CompileError::Internal(_) => "E0404",
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CompileError::DuplicateDefinition(span, _) | CompileError::Import(span, _) => {
+                Some(span)
+            }
+            CompileError::MissingMain | CompileError::Internal(_) => None,
+        }
+    }
+
+    /// The message without its location.
+    pub fn message(&self) -> String {
+        match self {
+            CompileError::DuplicateDefinition(_, message) | CompileError::Import(_, message) => {
+                message.clone()
+            }
+            other => other.to_string(),
+        }
+    }
+}
+
+/// Calls into a [`Vm`](crate::Vm) the program can't serve.
+#[derive(Debug, Clone, Error)]
+pub enum EmbedError {
+    #[error("Function '{0}' is not defined")]
+    UnknownFunction(String),
+    #[error("'{0}' is already defined")]
+    DuplicateNative(String),
+    #[error("Signature of native function '{0}' must be a function type, found '{1}'")]
+    NotAFunctionType(String, String),
+}
+
+impl EmbedError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EmbedError::UnknownFunction(_) => "E0601",
+            EmbedError::DuplicateNative(_) => "E0602",
+            EmbedError::NotAFunctionType(..) => "E0603",
+        }
+    }
+}
+
+/// Any error the library returns.
+#[derive(Debug, Clone, Error)]
+pub enum AbraError {
+    #[error(transparent)]
+    Lex(#[from] LexError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// Every type error in the program, in the order they were found.
+    #[error("{}", lines(.0))]
+    Type(Vec<TypeError>),
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+    #[error(transparent)]
+    Embed(#[from] EmbedError),
+}
+
+impl AbraError {
+    /// Code of the error, or of the first one for type errors.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AbraError::Lex(e) => e.code(),
+            AbraError::Parse(e) => e.code(),
+            AbraError::Type(errors) => errors.first().map_or("E0300", TypeError::code),
+            AbraError::Compile(e) => e.code(),
+            AbraError::Runtime(e) => e.code(),
+            AbraError::Embed(e) => e.code(),
+        }
+    }
+
+    /// Recovers the typed error a phase carried through `anyhow`. Anything else is
+    /// a compiler bug as far as the caller can tell.
+    pub(crate) fn recover(error: anyhow::Error) -> AbraError {
+        let error = match error.downcast::<AbraError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<LexError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<ParseError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<TypeError>() {
+            Ok(error) => return AbraError::Type(vec![error]),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<CompileError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<RuntimeError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        match error.downcast::<EmbedError>() {
+            Ok(error) => error.into(),
+            Err(error) => CompileError::Internal(format!("{:#}", error)).into(),
+        }
+    }
+}
+
+fn lines(errors: &[TypeError]) -> String {
+    let lines: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    lines.join("\n")
+}
@@ -1,8 +1,11 @@
 use std::fmt::Display;
 
 use crate::{
-    compiler::typecheck::Type,
-    frontend::tokenizer::{Token, TokenLiteral},
+    compiler::typecheck::{Composite, Primitives, Type},
+    frontend::{
+        span::{Located, Span},
+        tokenizer::{Token, TokenLiteral},
+    },
     runtime::value::StaticValue,
 };
 
@@ -10,21 +13,81 @@ use crate::{
 pub enum Item {
     Class(Class),
     Function(Function),
+    Enum(Enum),
+    Interface(Interface),
+    /// `import "path"`: the items of another file, also reachable as `module::name`.
+    Import(Located<String>),
+    Global(Global),
 }
+impl Item {
+    pub fn span(&self) -> &Span {
+        match self {
+            Item::Class(class) => &class.span,
+            Item::Function(func) => &func.span,
+            Item::Enum(enumeration) => &enumeration.span,
+            Item::Interface(interface) => &interface.span,
+            Item::Import(path) => &path.span,
+            Item::Global(global) => &global.span,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Class {
     pub name: String,
-    pub variables: Vec<(String, Type, StaticValue)>,
+    /// Names of its type parameters, `T` in `class Box<T>`.
+    pub type_params: Vec<String>,
+    /// The class this one extends, whose fields and methods it inherits.
+    pub parent: Option<String>,
+    /// Interfaces named in its `implements` clause.
+    pub interfaces: Vec<String>,
+    /// Fields with their type and the expression, if any, they start out as.
+    pub variables: Vec<(String, Type, Option<Expression>)>,
     pub functions: Vec<Function>,
+    pub span: Span,
+}
+/// `enum Name: A | B(int, string)`, a type whose values are one of the variants,
+/// each carrying values of its payload types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<(String, Vec<Type>)>,
+    pub span: Span,
+}
+/// `interface Name: func sig`, or an indented block of signatures: the methods a
+/// class that `implements Name` must define.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    /// Name, parameters and return type of each method.
+    pub methods: Vec<(String, Vec<Parameter>, Type)>,
+    pub span: Span,
+}
+/// `let name: type = value` or `const NAME: type = value` outside any function.
+/// Variables are set before `main` runs, in the order they are written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+    pub value: Expression,
+    /// Declared with `const`: worked out while compiling and never reassigned.
+    pub constant: bool,
+    pub span: Span,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
+    /// Names of its type parameters, `T` in `func head<T>(xs: [T]) -> T`.
+    pub type_params: Vec<String>,
     pub params: Vec<Parameter>,
     pub return_type: Type,
-    pub body: Vec<Statement>,
+    pub body: Block,
+    pub span: Span,
 }
 
+/// Statements of a body, each with the span it was parsed from.
+pub type Block = Vec<Located<Statement>>;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinOpCode {
     ADD,
@@ -41,21 +104,34 @@ pub enum BinOpCode {
     GE,
     EQ,
     NE,
+    /// `&`, `|` and `^` on the bits of integers.
+    BAND,
+    BOR,
+    BXOR,
+    /// `<<` and `>>`: an integer shifted by a number of bits; `>>` keeps the sign.
+    SHL,
+    SHR,
 }
 
 impl From<Token> for BinOpCode {
     fn from(value: Token) -> Self {
         match value {
-            Token::Plus => BinOpCode::ADD,
-            Token::Minus => BinOpCode::SUB,
-            Token::Star => BinOpCode::MULT,
-            Token::Slash => BinOpCode::DIV,
+            Token::Plus | Token::PlusEquals => BinOpCode::ADD,
+            Token::Minus | Token::MinusEquals => BinOpCode::SUB,
+            Token::Star | Token::StarEquals => BinOpCode::MULT,
+            Token::Slash | Token::SlashEquals => BinOpCode::DIV,
+            Token::Percent | Token::PercentEquals => BinOpCode::MOD,
+            Token::AndAnd => BinOpCode::AND,
+            Token::OrOr => BinOpCode::OR,
             Token::Lesser => BinOpCode::LT,
             Token::EqualsLesser => BinOpCode::LE,
             Token::Greater => BinOpCode::GT,
             Token::EqualsGreater => BinOpCode::GE,
             Token::EqualsEquals => BinOpCode::EQ,
             Token::BangEq => BinOpCode::NE,
+            Token::And => BinOpCode::BAND,
+            Token::Or => BinOpCode::BOR,
+            Token::Caret => BinOpCode::BXOR,
             _ => panic!(),
         }
     }
@@ -70,9 +146,13 @@ impl<T: Into<String>> From<T> for BinOpCode {
             "*" => BinOpCode::MULT,
             "/" => BinOpCode::DIV,
             "%" => BinOpCode::MOD,
-            "&" => BinOpCode::AND,
-            "|" => BinOpCode::OR,
-            "^" => BinOpCode::XOR,
+            "&&" | "and" => BinOpCode::AND,
+            "||" | "or" => BinOpCode::OR,
+            "&" => BinOpCode::BAND,
+            "|" => BinOpCode::BOR,
+            "^" => BinOpCode::BXOR,
+            "<<" => BinOpCode::SHL,
+            ">>" => BinOpCode::SHR,
             "<" => BinOpCode::LT,
             "<=" => BinOpCode::LE,
             ">" => BinOpCode::GT,
@@ -92,8 +172,8 @@ impl Display for BinOpCode {
             BinOpCode::MULT => write!(f, "*"),
             BinOpCode::DIV => write!(f, "/"),
             BinOpCode::MOD => write!(f, "%"),
-            BinOpCode::AND => write!(f, "&"),
-            BinOpCode::OR => write!(f, "|"),
+            BinOpCode::AND => write!(f, "and"),
+            BinOpCode::OR => write!(f, "or"),
             BinOpCode::XOR => write!(f, "^"),
             BinOpCode::LT => write!(f, "<"),
             BinOpCode::LE => write!(f, "<="),
@@ -101,6 +181,11 @@ impl Display for BinOpCode {
             BinOpCode::GE => write!(f, ">="),
             BinOpCode::EQ => write!(f, "=="),
             BinOpCode::NE => write!(f, "!="),
+            BinOpCode::BAND => write!(f, "&"),
+            BinOpCode::BOR => write!(f, "|"),
+            BinOpCode::BXOR => write!(f, "^"),
+            BinOpCode::SHL => write!(f, "<<"),
+            BinOpCode::SHR => write!(f, ">>"),
         }
     }
 }
@@ -109,6 +194,8 @@ impl Display for BinOpCode {
 pub enum UnaryOpCode {
     NEG,
     NOT,
+    /// `~`: an integer with its bits flipped.
+    BNOT,
 }
 
 impl Display for UnaryOpCode {
@@ -116,6 +203,7 @@ impl Display for UnaryOpCode {
         match self {
             UnaryOpCode::NEG => write!(f, "-"),
             UnaryOpCode::NOT => write!(f, "!"),
+            UnaryOpCode::BNOT => write!(f, "~"),
         }
     }
 }
@@ -127,9 +215,69 @@ pub enum Expression {
     Binary(BinOpCode, Box<Expression>, Box<Expression>),
     Grouping(Box<Expression>),
     Call(String, Vec<Expression>),
+    /// Call of a function value, e.g. the result of another call.
+    CallIndirect(Box<Expression>, Vec<Expression>),
+    /// `fn(params) -> type: body`, a function value closing over the variables in scope.
+    Lambda(Vec<Parameter>, Type, Box<Expression>),
+    MethodCall(Box<Expression>, String, Vec<Expression>),
+    /// `object.member`: the member's name and the object.
     Get(String, Box<Expression>),
+    /// `collection[index]` on an array or map.
+    Index(Box<Expression>, Box<Expression>),
+    /// `(a, b)`: a tuple of two or more values.
+    TupleLiteral(Vec<Expression>),
+    /// `tuple.0`: one of a tuple's values, by position.
+    TupleIndex(Box<Expression>, usize),
+    /// `collection[start:end]` on an array or string; either bound may be left out.
+    Slice(
+        Box<Expression>,
+        Option<Box<Expression>>,
+        Option<Box<Expression>>,
+    ),
     Instance(Type, Vec<Expression>),
+    /// `start -> end`: an array of the integers from start up to end, or of the chars
+    /// from start through end.
+    Range(StaticValue, StaticValue),
+    /// `[a, b, c]`, typed by its elements.
+    ArrayLiteral(Vec<Expression>),
+    /// `{ key: value, ... }`, typed by its keys and values.
+    MapLiteral(Vec<(Expression, Expression)>),
+    /// `super.method(args)`: the parent class's version of a method, on the same object.
+    SuperCall(String, Vec<Expression>),
+    /// `value ?? fallback`: the value unless it is null, else the fallback.
+    Coalesce(Box<Expression>, Box<Expression>),
+    /// `name: value` among a call's arguments: the argument of the parameter so named.
+    NamedArgument(String, Box<Expression>),
+    /// `condition ? then : otherwise`: one of the two, only that one evaluated.
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// `object?.member` or `object?.method(args)`: the wrapped `Get` or `MethodCall`,
+    /// or null without evaluating it when the object is null.
+    SafeAccess(Box<Expression>),
+    /// `value is Type`: whether the value currently holds that type.
+    Is(Box<Expression>, Type),
 }
+impl Expression {
+    /// The array a `Range` stands for, written out as `new [type](elements)`.
+    pub fn range_elements(start: &StaticValue, end: &StaticValue) -> Expression {
+        let (element_type, elements): (_, Vec<StaticValue>) = match (start, end) {
+            (StaticValue::Char(s), StaticValue::Char(e)) => {
+                (Primitives::Char, (*s..=*e).map(StaticValue::Char).collect())
+            }
+            (StaticValue::Integer(s), StaticValue::Integer(e)) => (
+                Primitives::Integer,
+                (*s..*e).map(StaticValue::Integer).collect(),
+            ),
+            _ => unreachable!("the parser only builds ranges of integers or chars"),
+        };
+        let elements = elements
+            .into_iter()
+            .map(|value| Expression::Literal(TokenLiteral::Value(value)))
+            .collect();
+        let array = Type::Composite(Box::new(Composite::Array(Type::Primitive(element_type))));
+        Expression::Instance(array, elements)
+    }
+}
+
 //Generate Display trait implementation for Expression enum
 impl Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -148,7 +296,54 @@ impl Display for Expression {
                 }
                 write!(f, ")")
             }
+            Expression::CallIndirect(callee, args) => {
+                write!(f, "{}(", callee)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}", arg)?;
+                    if i < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Expression::Lambda(params, ret, body) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    write!(f, "{}: {}", param.name, param.ty)?;
+                    if i < params.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ") -> {}: {}", ret, body)
+            }
+            Expression::MethodCall(receiver, method, args) => {
+                write!(f, "{}.{}(", receiver, method)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}", arg)?;
+                    if i < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
             Expression::Get(literal, expr) => write!(f, "{}.{}", expr, literal),
+            Expression::Index(collection, index) => write!(f, "{}[{}]", collection, index),
+            Expression::TupleLiteral(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+            Expression::TupleIndex(tuple, index) => write!(f, "{}.{}", tuple, index),
+            Expression::Slice(collection, start, end) => {
+                write!(f, "{}[", collection)?;
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, ":")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                write!(f, "]")
+            }
             Expression::Instance(t, expressionss) => {
                 write!(f, "new {} {{", t)?;
                 for (i, expr) in expressionss.iter().enumerate() {
@@ -159,24 +354,81 @@ impl Display for Expression {
                 }
                 write!(f, "}}")
             }
+            Expression::SuperCall(method, args) => {
+                write!(f, "super.{}(", method)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}", arg)?;
+                    if i < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Expression::Coalesce(value, fallback) => write!(f, "({} ?? {})", value, fallback),
+            Expression::Conditional(condition, then, otherwise) => {
+                write!(f, "({} ? {} : {})", condition, then, otherwise)
+            }
+            Expression::NamedArgument(name, value) => write!(f, "{}: {}", name, value),
+            Expression::SafeAccess(access) => match &**access {
+                Expression::Get(member, object) => write!(f, "{}?.{}", object, member),
+                Expression::MethodCall(receiver, method, args) => {
+                    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                    write!(f, "{}?.{}({})", receiver, method, args.join(", "))
+                }
+                other => write!(f, "{}", other),
+            },
+            Expression::Is(value, ty) => write!(f, "({} is {})", value, ty),
+            Expression::Range(start, end) => {
+                write_pattern_literal(f, start)?;
+                write!(f, " -> ")?;
+                write_pattern_literal(f, end)
+            }
+            Expression::ArrayLiteral(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Expression::MapLiteral(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    Declare(String, Type, Expression),
-    Set(Option<Expression>,String, Expression),
+    /// `let name: type = value`; without a value, every path has to assign one
+    /// before the variable is read.
+    Declare(String, Type, Option<Expression>),
+    /// `let (a, b) = tuple`: one new variable per value; `_` skips one.
+    Destructure(Vec<String>, Expression),
+    /// `name = value`, or `object.name = value` when there is an object.
+    Set(Option<Expression>, String, Expression),
+    /// `collection[index] = value` on an array or map.
+    SetIndex(Expression, Expression, Expression),
+    /// `target op= value`, where the target is a variable, `object.member` or
+    /// `collection[index]`.
+    Compound(Expression, BinOpCode, Expression),
     Expression(Expression),
     Print(Expression),
     Return(Option<Expression>),
-    If(Expression, Vec<Statement>, Option<Vec<Statement>>),
-    For(
-        Box<Statement>,
-        Expression,
-        Box<Statement>,
-        Option<Vec<Statement>>,
-    ),
+    If(Expression, Block, Option<Block>),
+    For(Box<Statement>, Expression, Box<Statement>, Option<Block>),
+    While(Expression, Block),
+    Break,
+    Continue,
+    /// `throw message`: unwinds to the innermost `try`, or stops the program.
+    Throw(Expression),
+    /// `try` body, the variable the caught message is bound to, and the `catch` body.
+    Try(Block, String, Block),
+    /// `match value` and its arms; the first arm whose pattern fits runs.
+    Match(Expression, Vec<(Pattern, Block)>),
+    /// `func` inside a function: a local of its name holding a closure over the
+    /// enclosing variables it reads.
+    Function(Function),
     Null,
 }
 
@@ -184,15 +436,25 @@ pub enum Statement {
 impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::Declare(name, type_data, expr) => {
+            Statement::Declare(name, type_data, Some(expr)) => {
                 write!(f, "let {}: {} = {}", name, type_data, expr)
             }
-            Statement::Set(on,name, expr) => {
+            Statement::Declare(name, type_data, None) => write!(f, "let {}: {}", name, type_data),
+            Statement::Destructure(names, expr) => {
+                write!(f, "let ({}) = {}", names.join(", "), expr)
+            }
+            Statement::Set(on, name, expr) => {
                 if let Some(on) = on {
                     write!(f, "{}.", on)?;
-                }   
+                }
                 write!(f, "{} = {}", name, expr)
-            },
+            }
+            Statement::SetIndex(collection, index, expr) => {
+                write!(f, "{}[{}] = {}", collection, index, expr)
+            }
+            Statement::Compound(target, op, expr) => {
+                write!(f, "{} {}= {}", target, op, expr)
+            }
             Statement::Expression(expr) => write!(f, "{}", expr),
             Statement::Print(expr) => write!(f, "print {}", expr),
             Statement::Return(op_expr) => {
@@ -203,15 +465,15 @@ impl Display for Statement {
                 Ok(())
             }
             Statement::If(expr, block, els) => {
-                write!(f, "if {} {{\n", expr)?;
+                writeln!(f, "if {} {{", expr)?;
                 for stmt in block {
-                    write!(f, "{}\n", stmt)?;
+                    writeln!(f, "{}", stmt)?;
                 }
                 write!(f, "}}")?;
                 if let Some(else_block) = els {
-                    write!(f, " else {{\n")?;
+                    writeln!(f, " else {{")?;
                     for stmt in else_block {
-                        write!(f, "{}\n", stmt)?;
+                        writeln!(f, "{}", stmt)?;
                     }
                     write!(f, "}}")?;
                 }
@@ -220,21 +482,181 @@ impl Display for Statement {
             Statement::For(stmt, expr, stmt2, body) => {
                 write!(f, "for {} {} {}", stmt, expr, stmt2)?;
                 if let Some(body) = body {
-                    write!(f, " {{\n")?;
+                    writeln!(f, " {{")?;
                     for stmt in body {
-                        write!(f, "{}\n", stmt)?;
+                        writeln!(f, "{}", stmt)?;
                     }
                     write!(f, "}}")?;
                 }
                 Ok(())
             }
+            Statement::While(expr, body) => {
+                writeln!(f, "while {} {{", expr)?;
+                for stmt in body {
+                    writeln!(f, "{}", stmt)?;
+                }
+                write!(f, "}}")
+            }
+            Statement::Break => write!(f, "break"),
+            Statement::Continue => write!(f, "continue"),
+            Statement::Throw(expr) => write!(f, "throw {}", expr),
+            Statement::Try(body, name, handler) => {
+                writeln!(f, "try {{")?;
+                for stmt in body {
+                    writeln!(f, "{}", stmt)?;
+                }
+                writeln!(f, "}} catch {} {{", name)?;
+                for stmt in handler {
+                    writeln!(f, "{}", stmt)?;
+                }
+                write!(f, "}}")
+            }
+            Statement::Match(subject, arms) => {
+                writeln!(f, "match {} {{", subject)?;
+                for (pattern, body) in arms {
+                    writeln!(f, "{} {{", pattern)?;
+                    for stmt in body {
+                        writeln!(f, "{}", stmt)?;
+                    }
+                    writeln!(f, "}}")?;
+                }
+                write!(f, "}}")
+            }
+            Statement::Function(func) => {
+                let params: Vec<String> = func
+                    .params
+                    .iter()
+                    .map(|param| format!("{}: {}", param.name, param.ty))
+                    .collect();
+                writeln!(
+                    f,
+                    "func {}({}) -> {} {{",
+                    func.name,
+                    params.join(", "),
+                    func.return_type
+                )?;
+                for stmt in func.body.iter() {
+                    writeln!(f, "{}", stmt)?;
+                }
+                write!(f, "}}")
+            }
             Statement::Null => write!(f, ""),
         }
     }
 }
 
+/// What a `match` arm tests the value against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// A value equal to the literal.
+    Literal(StaticValue),
+    /// `start -> end`: a value the range expression `start -> end` contains, so the
+    /// end is excluded for integers and included for chars.
+    Range(StaticValue, StaticValue),
+    /// `is Type`: a value of that type.
+    Type(Type),
+    /// `Enum::Variant(a, b)`: a value of that variant, its payload bound to the names;
+    /// `_` skips a value.
+    Variant(String, Vec<String>),
+    /// `_`: any value.
+    Wildcard,
+}
+
+/// A pattern's or range's literal as it is written in the source.
+fn write_pattern_literal(f: &mut std::fmt::Formatter<'_>, value: &StaticValue) -> std::fmt::Result {
+    match value {
+        StaticValue::Char(c) => write!(f, "'{}'", c),
+        StaticValue::String(s) => write!(f, "{:?}", s),
+        other => write!(f, "{}", other),
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Literal(value) => write_pattern_literal(f, value),
+            Pattern::Range(start, end) => {
+                write_pattern_literal(f, start)?;
+                write!(f, " -> ")?;
+                write_pattern_literal(f, end)
+            }
+            Pattern::Type(ty) => write!(f, "is {}", ty),
+            Pattern::Variant(name, bindings) if bindings.is_empty() => write!(f, "{}", name),
+            Pattern::Variant(name, bindings) => write!(f, "{}({})", name, bindings.join(", ")),
+            Pattern::Wildcard => write!(f, "_"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Parameter {
     pub name: String,
     pub ty: Type,
+    /// `name: Type = default`: the value a call that leaves the argument out passes.
+    pub default: Option<Expression>,
+}
+
+impl Parameter {
+    pub fn new<S: Into<String>>(name: S, ty: Type) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            default: None,
+        }
+    }
+}
+
+/// The argument each of `params` gets from a call's `args`: the positional ones in
+/// order, then the `name: value` ones by name. `None` leaves a parameter to its
+/// default. The error says what doesn't match, to follow the function's name.
+pub fn bind_arguments<'a>(
+    params: &[Parameter],
+    args: &'a [Expression],
+) -> Result<Vec<Option<&'a Expression>>, String> {
+    let mut bound: Vec<Option<&Expression>> = vec![None; params.len()];
+    let mut named = false;
+    for (position, arg) in args.iter().enumerate() {
+        let index = match arg {
+            Expression::NamedArgument(name, _) => {
+                named = true;
+                params
+                    .iter()
+                    .position(|param| param.name == *name)
+                    .ok_or_else(|| format!("has no parameter named '{}'", name))?
+            }
+            _ if named => return Err("got a positional argument after a named one".into()),
+            _ if position >= params.len() => break,
+            _ => position,
+        };
+        if bound[index].is_some() {
+            return Err(format!("got parameter '{}' twice", params[index].name));
+        }
+        bound[index] = Some(match arg {
+            Expression::NamedArgument(_, value) => value,
+            positional => positional,
+        });
+    }
+    let required = params
+        .iter()
+        .filter(|param| param.default.is_none())
+        .count();
+    let missing = params
+        .iter()
+        .zip(bound.iter())
+        .find(|(param, arg)| param.default.is_none() && arg.is_none());
+    if args.len() > params.len() || (missing.is_some() && !named) {
+        let expected = match required == params.len() {
+            true => params.len().to_string(),
+            false => format!("{} to {}", required, params.len()),
+        };
+        return Err(format!(
+            "expected {} arguments, but got {}",
+            expected,
+            args.len()
+        ));
+    }
+    if let Some((param, _)) = missing {
+        return Err(format!("is missing an argument for '{}'", param.name));
+    }
+    Ok(bound)
 }
@@ -0,0 +1,694 @@
+//! `abra fmt`: prints a program back out in one canonical layout.
+//!
+//! The program is parsed and each item and statement printed from the AST, so the
+//! layout comes out the same however the source was spaced: four spaces per level,
+//! one space around binary operators and after commas, a blank line between
+//! functions and methods. Comments aren't part of the AST; they are put back from
+//! the source, each before the statement that followed it or at the end of the
+//! line it was on. Single blank lines between statements are kept.
+
+use crate::{
+    compiler::typecheck::{Algebraic, Composite, Primitives, Type},
+    errors::AbraError,
+    frontend::{
+        ast::{
+            Block, Class, Enum, Expression, Function, Global, Interface, Item, Parameter, Pattern,
+            Statement, UnaryOpCode,
+        },
+        parser::Parser,
+        tokenizer::{TokenLiteral, Tokenizer},
+    },
+    runtime::value::StaticValue,
+};
+
+const INDENT: &str = "    ";
+
+/// `source` in the canonical layout, or the error that stops it from parsing.
+pub fn format_source(source: &str) -> Result<String, AbraError> {
+    let items = Parser::new(Tokenizer::new(source))
+        .with_source(source)
+        .parse_program()?;
+    let mut printer = Printer::new(source);
+    for item in items.iter() {
+        printer.item(item);
+    }
+    printer.finish();
+    Ok(printer.out)
+}
+
+/// A `//` comment of the source.
+struct Comment {
+    /// 1-based line it is on.
+    line: usize,
+    /// Indentation level of the line, for a comment on a line of its own.
+    level: usize,
+    /// Whether code precedes it on its line.
+    trailing: bool,
+    text: String,
+}
+
+struct Printer<'s> {
+    out: String,
+    lines: Vec<&'s str>,
+    comments: Vec<Comment>,
+    /// Index of the first comment not printed yet.
+    next_comment: usize,
+    /// Last source line printed from.
+    last_line: usize,
+    /// Whether the next line printed is to be preceded by a blank one.
+    blank: bool,
+    /// Whether the last line printed opened a block, which never starts with a blank.
+    block_start: bool,
+}
+
+impl<'s> Printer<'s> {
+    fn new(source: &'s str) -> Self {
+        let lines: Vec<&str> = source.lines().collect();
+        let comments = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let start = comment_start(line)?;
+                let code = &line[..start];
+                Some(Comment {
+                    line: index + 1,
+                    level: indent_level(line),
+                    trailing: !code.trim().is_empty(),
+                    text: line[start..].trim_end().to_string(),
+                })
+            })
+            .collect();
+        Printer {
+            out: String::new(),
+            lines,
+            comments,
+            next_comment: 0,
+            last_line: 0,
+            blank: false,
+            block_start: false,
+        }
+    }
+
+    fn finish(&mut self) {
+        while self.next_comment < self.comments.len() {
+            let comment = &self.comments[self.next_comment];
+            let (line, text) = (comment.line, comment.text.clone());
+            self.next_comment += 1;
+            self.write(0, &text, Some(line));
+        }
+    }
+
+    /// Prints `text` at `depth`, with the comments before source line `line` ahead of
+    /// it and the one at the end of that line after it.
+    fn line(&mut self, depth: usize, text: &str, line: Option<usize>) {
+        let Some(line) = line else {
+            self.write(depth, text, None);
+            return;
+        };
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line >= line {
+                break;
+            }
+            let (comment_line, comment_text) = (comment.line, comment.text.clone());
+            self.next_comment += 1;
+            self.write(depth, &comment_text, Some(comment_line));
+        }
+        let mut text = text.to_string();
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line == line && comment.trailing {
+                text = format!("{}  {}", text, comment.text);
+                self.next_comment += 1;
+            }
+        }
+        self.write(depth, &text, Some(line));
+    }
+
+    fn write(&mut self, depth: usize, text: &str, line: Option<usize>) {
+        if let Some(line) = line {
+            if line > 1 && self.is_blank(line - 1) {
+                self.blank = true;
+            }
+            self.last_line = self.last_line.max(line);
+        }
+        if self.blank && !self.block_start && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.blank = false;
+        self.block_start = false;
+        self.out.push_str(&INDENT.repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn is_blank(&self, line: usize) -> bool {
+        self.lines
+            .get(line - 1)
+            .is_some_and(|text| text.trim().is_empty())
+    }
+
+    fn is_code(&self, line: usize) -> bool {
+        self.lines.get(line - 1).is_some_and(|text| {
+            let text = text.trim();
+            !text.is_empty() && !text.starts_with("//")
+        })
+    }
+
+    /// Prints the comments on their own lines right after the block just printed that
+    /// are indented into it.
+    fn close_block(&mut self, depth: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.trailing
+                || comment.level < depth
+                || (self.last_line + 1..comment.line).any(|line| self.is_code(line))
+            {
+                break;
+            }
+            let (line, text) = (comment.line, comment.text.clone());
+            self.next_comment += 1;
+            self.write(depth, &text, Some(line));
+        }
+    }
+
+    /// Last line with code before `line`, the header of the block starting there.
+    fn header_before(&self, line: usize) -> Option<usize> {
+        (1..line).rev().find(|line| self.is_code(*line))
+    }
+
+    /// Whether `block` is a single simple statement written after its header on the
+    /// same line, as in `if done: return`.
+    fn is_inline(&self, block: &Block) -> bool {
+        let [statement] = &block[..] else {
+            return false;
+        };
+        let Some(text) = self.lines.get(statement.span.line - 1) else {
+            return false;
+        };
+        let before: String = text.chars().take(statement.span.col - 1).collect();
+        simple_statement(&statement.node).is_some() && !before.trim().is_empty()
+    }
+
+    /// `header` followed by `block`, either on the same line after a `:` or indented
+    /// below it.
+    fn block(&mut self, depth: usize, header: &str, line: Option<usize>, block: &Block) {
+        if self.is_inline(block) {
+            let statement = &block[0];
+            let text = simple_statement(&statement.node).expect("inline statements are simple");
+            self.line(
+                depth,
+                &format!("{}: {}", header, text),
+                line.or(Some(statement.span.line)),
+            );
+            return;
+        }
+        let line = line.or_else(|| block.first().and_then(|s| self.header_before(s.span.line)));
+        self.line(depth, header, line);
+        self.block_start = true;
+        self.statements(depth + 1, block);
+    }
+
+    fn statements(&mut self, depth: usize, block: &Block) {
+        for statement in block.iter() {
+            self.statement(depth, &statement.node, statement.span.line);
+        }
+        self.close_block(depth);
+    }
+
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::Function(function) => {
+                self.blank = true;
+                self.function(0, function);
+                self.blank = true;
+            }
+            Item::Class(class) => {
+                self.blank = true;
+                self.class(class);
+                self.blank = true;
+            }
+            Item::Interface(interface) => self.interface(interface),
+            Item::Enum(enumeration) => {
+                self.line(0, &enum_text(enumeration), Some(enumeration.span.line))
+            }
+            Item::Global(global) => self.line(0, &global_text(global), Some(global.span.line)),
+            Item::Import(path) => self.line(
+                0,
+                &format!("import {}", string_literal(&path.node)),
+                Some(path.span.line),
+            ),
+        }
+    }
+
+    fn function(&mut self, depth: usize, function: &Function) {
+        let header = format!(
+            "func {}{}({}) -> {}",
+            function.name,
+            type_params(&function.type_params),
+            parameters(&function.params),
+            type_text(&function.return_type)
+        );
+        self.block(depth, &header, Some(function.span.line), &function.body);
+    }
+
+    fn class(&mut self, class: &Class) {
+        let mut header = format!("class {}{}", class.name, type_params(&class.type_params));
+        if let Some(parent) = &class.parent {
+            header.push_str(&format!(": {}", parent));
+        }
+        if !class.interfaces.is_empty() {
+            header.push_str(&format!(" implements {}", class.interfaces.join(", ")));
+        }
+        self.line(0, &header, Some(class.span.line));
+        self.block_start = true;
+        for (name, ty, value) in class.variables.iter() {
+            let mut text = format!("let {}: {}", name, type_text(ty));
+            if let Some(value) = value {
+                text.push_str(&format!(" = {}", expression(value)));
+            }
+            let line = self.member_line(class.span.line, &format!("let {}", name));
+            self.line(1, &text, line);
+        }
+        for function in class.functions.iter() {
+            self.blank = true;
+            self.function(1, function);
+        }
+        self.close_block(1);
+    }
+
+    fn interface(&mut self, interface: &Interface) {
+        let header = format!("interface {}", interface.name);
+        let line = interface.span.line;
+        let one_line = self
+            .lines
+            .get(line - 1)
+            .is_some_and(|text| text.contains("func"));
+        match &interface.methods[..] {
+            [method] if one_line => {
+                let text = format!("{}: {}", header, signature(method));
+                self.line(0, &text, Some(line));
+            }
+            methods => {
+                self.blank = true;
+                self.line(0, &header, Some(line));
+                self.block_start = true;
+                for method in methods {
+                    let method_line = self.member_line(line, &format!("func {}", method.0));
+                    self.line(1, &signature(method), method_line);
+                }
+                self.close_block(1);
+                self.blank = true;
+            }
+        }
+    }
+
+    /// First line after `after` that starts with `start` followed by a separator, where
+    /// a class field or interface method, which have no span, is declared.
+    fn member_line(&self, after: usize, start: &str) -> Option<usize> {
+        (after + 1..=self.lines.len()).find(|line| {
+            let text = self.lines[line - 1].trim_start();
+            text.strip_prefix(start)
+                .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+        })
+    }
+
+    fn statement(&mut self, depth: usize, statement: &Statement, line: usize) {
+        if let Some(text) = simple_statement(statement) {
+            self.line(depth, &text, Some(line));
+            return;
+        }
+        match statement {
+            Statement::If(condition, then, otherwise) => {
+                self.block(
+                    depth,
+                    &format!("if {}", expression(condition)),
+                    Some(line),
+                    then,
+                );
+                let mut otherwise = otherwise.as_ref();
+                while let Some(block) = otherwise {
+                    match &block[..] {
+                        // `else if`, which parses to an `if` alone in the `else` block.
+                        [nested] if matches!(nested.node, Statement::If(..)) => {
+                            let Statement::If(condition, then, next) = &nested.node else {
+                                unreachable!()
+                            };
+                            let header = format!("else if {}", expression(condition));
+                            self.block(depth, &header, Some(nested.span.line), then);
+                            otherwise = next.as_ref();
+                        }
+                        _ => {
+                            self.block(depth, "else", None, block);
+                            otherwise = None;
+                        }
+                    }
+                }
+            }
+            Statement::For(init, condition, step, body) => {
+                let header = format!(
+                    "for {}, {}, {}",
+                    simple_statement(init).unwrap_or_default(),
+                    expression(condition),
+                    simple_statement(step).unwrap_or_default()
+                );
+                match body {
+                    Some(body) => self.block(depth, &header, Some(line), body),
+                    None => self.line(depth, &header, Some(line)),
+                }
+            }
+            Statement::While(condition, body) => self.block(
+                depth,
+                &format!("while {}", expression(condition)),
+                Some(line),
+                body,
+            ),
+            Statement::Try(body, name, handler) => {
+                self.block(depth, "try", Some(line), body);
+                self.block(depth, &format!("catch {}", name), None, handler);
+            }
+            Statement::Match(subject, arms) => {
+                self.line(depth, &format!("match {}", expression(subject)), Some(line));
+                self.block_start = true;
+                for (pattern, body) in arms {
+                    self.block(depth + 1, &pattern_text(pattern), None, body);
+                }
+                self.close_block(depth + 1);
+            }
+            Statement::Function(function) => self.function(depth, function),
+            _ => unreachable!("every other statement is simple"),
+        }
+    }
+}
+
+/// A statement that fits on one line as text, or `None` for one with a block.
+fn simple_statement(statement: &Statement) -> Option<String> {
+    Some(match statement {
+        Statement::Declare(name, ty, Some(value)) => {
+            format!("let {}: {} = {}", name, type_text(ty), expression(value))
+        }
+        Statement::Declare(name, ty, None) => format!("let {}: {}", name, type_text(ty)),
+        Statement::Destructure(names, value) => {
+            format!("let ({}) = {}", names.join(", "), expression(value))
+        }
+        Statement::Set(Some(object), name, value) => {
+            format!("{}.{} = {}", expression(object), name, expression(value))
+        }
+        Statement::Set(None, name, value) => format!("{} = {}", name, expression(value)),
+        Statement::SetIndex(collection, index, value) => format!(
+            "{}[{}] = {}",
+            expression(collection),
+            expression(index),
+            expression(value)
+        ),
+        Statement::Compound(target, op, value) => {
+            format!("{} {}= {}", expression(target), op, expression(value))
+        }
+        Statement::Expression(value) => expression(value),
+        // `print(x)` keeps reading like the call it compiles to.
+        Statement::Print(value @ Expression::Grouping(_)) => format!("print{}", expression(value)),
+        Statement::Print(value) => format!("print {}", expression(value)),
+        Statement::Return(None) => "return".to_string(),
+        Statement::Return(Some(value)) => format!("return {}", expression(value)),
+        Statement::Break => "break".to_string(),
+        Statement::Continue => "continue".to_string(),
+        Statement::Throw(value) => format!("throw {}", expression(value)),
+        Statement::Null => String::new(),
+        Statement::If(..)
+        | Statement::For(..)
+        | Statement::While(..)
+        | Statement::Try(..)
+        | Statement::Match(..)
+        | Statement::Function(..) => return None,
+    })
+}
+
+/// `expr` as source. Parentheses are printed where the source had them, which the AST
+/// keeps as `Grouping`, so the text parses back to the same tree. Diagnostics quote
+/// expressions with it too.
+pub(crate) fn expression(expr: &Expression) -> String {
+    match expr {
+        Expression::Literal(TokenLiteral::Identifier(name)) => name.clone(),
+        Expression::Literal(TokenLiteral::Value(value)) => literal(value),
+        Expression::Unary(UnaryOpCode::NOT, operand) => format!("not {}", expression(operand)),
+        Expression::Unary(op, operand) => format!("{}{}", op, expression(operand)),
+        Expression::Binary(op, lhs, rhs) => {
+            format!("{} {} {}", expression(lhs), op, expression(rhs))
+        }
+        Expression::Grouping(inner) => format!("({})", expression(inner)),
+        Expression::Call(name, args) => format!("{}({})", name, list(args)),
+        Expression::CallIndirect(callee, args) => {
+            format!("{}({})", expression(callee), list(args))
+        }
+        Expression::Lambda(params, ret, body) => format!(
+            "fn({}) -> {}: {}",
+            parameters(params),
+            type_text(ret),
+            expression(body)
+        ),
+        Expression::MethodCall(receiver, method, args) => {
+            format!("{}.{}({})", expression(receiver), method, list(args))
+        }
+        Expression::Get(member, object) => format!("{}.{}", expression(object), member),
+        Expression::Index(collection, index) => {
+            format!("{}[{}]", expression(collection), expression(index))
+        }
+        Expression::TupleLiteral(elements) => format!("({})", list(elements)),
+        Expression::TupleIndex(tuple, index) => format!("{}.{}", expression(tuple), index),
+        Expression::Slice(collection, start, end) => {
+            let bound = |bound: &Option<Box<Expression>>| {
+                bound.as_deref().map(expression).unwrap_or_default()
+            };
+            format!(
+                "{}[{}:{}]",
+                expression(collection),
+                bound(start),
+                bound(end)
+            )
+        }
+        Expression::Instance(ty, args) => format!("new {}({})", type_text(ty), list(args)),
+        Expression::Range(start, end) => format!("{} -> {}", literal(start), literal(end)),
+        Expression::ArrayLiteral(elements) => format!("[{}]", list(elements)),
+        Expression::MapLiteral(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", expression(key), expression(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Expression::SuperCall(method, args) => format!("super.{}({})", method, list(args)),
+        Expression::Coalesce(value, fallback) => {
+            format!("{} ?? {}", expression(value), expression(fallback))
+        }
+        Expression::NamedArgument(name, value) => format!("{}: {}", name, expression(value)),
+        Expression::Conditional(condition, then, otherwise) => format!(
+            "{} ? {} : {}",
+            expression(condition),
+            expression(then),
+            expression(otherwise)
+        ),
+        Expression::SafeAccess(access) => match &**access {
+            Expression::Get(member, object) => format!("{}?.{}", expression(object), member),
+            Expression::MethodCall(receiver, method, args) => {
+                format!("{}?.{}({})", expression(receiver), method, list(args))
+            }
+            other => expression(other),
+        },
+        Expression::Is(value, ty) => format!("{} is {}", expression(value), type_text(ty)),
+    }
+}
+
+fn list(expressions: &[Expression]) -> String {
+    let expressions: Vec<String> = expressions.iter().map(expression).collect();
+    expressions.join(", ")
+}
+
+/// `value` as a literal the tokenizer reads back as the same value.
+fn literal(value: &StaticValue) -> String {
+    match value {
+        StaticValue::Null => "null".to_string(),
+        StaticValue::Integer(i) => i.to_string(),
+        StaticValue::BigInt(i) => format!("{}n", i),
+        StaticValue::Float(f) => {
+            let text = f.to_string();
+            if text.contains('.') {
+                text
+            } else {
+                format!("{}.0", text)
+            }
+        }
+        StaticValue::Char(c) => match c {
+            '\'' => "'\\''".to_string(),
+            other => format!("'{}'", escape(*other)),
+        },
+        StaticValue::Bool(b) => b.to_string(),
+        StaticValue::String(s) => string_literal(s),
+    }
+}
+
+fn string_literal(s: &str) -> String {
+    let mut text = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => text.push_str("\\\""),
+            other => text.push_str(&escape(other)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+/// `c` inside a string or char literal, with the escapes both of them know.
+fn escape(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\\' => "\\\\".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// `ty` as it is written in the source.
+fn type_text(ty: &Type) -> String {
+    match ty {
+        Type::Primitive(primitive) => match primitive {
+            Primitives::Integer => "int",
+            Primitives::BigInt => "bigint",
+            Primitives::Float => "float",
+            Primitives::Char => "char",
+            Primitives::Bool => "bool",
+            Primitives::String => "string",
+        }
+        .to_string(),
+        Type::Composite(composite) => match &**composite {
+            Composite::Array(element) => format!("[{}]", type_text(element)),
+            Composite::Map(key, value) => format!("<{} -> {}>", type_text(key), type_text(value)),
+            Composite::HeapValue(inner) => format!("Box<{}>", type_text(inner)),
+            Composite::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(type_text).collect();
+                format!("({})", types.join(", "))
+            }
+        },
+        Type::Abra(name) | Type::Var(name) => name.clone(),
+        Type::Null => "null".to_string(),
+        Type::Unknown => "unknown".to_string(),
+        Type::Generic(name, args) => {
+            let args: Vec<String> = args.iter().map(type_text).collect();
+            format!("{}<{}>", name, args.join(", "))
+        }
+        Type::Function(params, ret) => {
+            let params: Vec<String> = params.iter().map(type_text).collect();
+            format!("fn({}) -> {}", params.join(", "), type_text(ret))
+        }
+        Type::Algebraic(algebraic) => {
+            let Algebraic::Or(first, second) = &**algebraic;
+            // Anything ending in a type would take the `?` or `| ...` as part of it.
+            let first = match first {
+                Type::Function(..) | Type::Algebraic(..) => format!("({})", type_text(first)),
+                other => type_text(other),
+            };
+            match second {
+                Type::Null => format!("{}?", first),
+                other => format!("{} | {}", first, type_text(other)),
+            }
+        }
+    }
+}
+
+fn type_params(params: &[String]) -> String {
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", params.join(", "))
+    }
+}
+
+fn parameters(params: &[Parameter]) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| match &param.default {
+            Some(default) => format!(
+                "{}: {} = {}",
+                param.name,
+                type_text(&param.ty),
+                expression(default)
+            ),
+            None => format!("{}: {}", param.name, type_text(&param.ty)),
+        })
+        .collect();
+    params.join(", ")
+}
+
+fn signature((name, params, ret): &(String, Vec<Parameter>, Type)) -> String {
+    format!(
+        "func {}({}) -> {}",
+        name,
+        parameters(params),
+        type_text(ret)
+    )
+}
+
+fn enum_text(enumeration: &Enum) -> String {
+    let variants: Vec<String> = enumeration
+        .variants
+        .iter()
+        .map(|(name, payload)| {
+            if payload.is_empty() {
+                name.clone()
+            } else {
+                let payload: Vec<String> = payload.iter().map(type_text).collect();
+                format!("{}({})", name, payload.join(", "))
+            }
+        })
+        .collect();
+    format!("enum {}: {}", enumeration.name, variants.join(" | "))
+}
+
+fn global_text(global: &Global) -> String {
+    let keyword = if global.constant { "const" } else { "let" };
+    format!(
+        "{} {}: {} = {}",
+        keyword,
+        global.name,
+        type_text(&global.ty),
+        expression(&global.value)
+    )
+}
+
+fn pattern_text(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Literal(value) => literal(value),
+        Pattern::Range(start, end) => format!("{} -> {}", literal(start), literal(end)),
+        Pattern::Type(ty) => format!("is {}", type_text(ty)),
+        Pattern::Variant(..) | Pattern::Wildcard => pattern.to_string(),
+    }
+}
+
+/// Byte offset of the `//` starting a comment in `line`, skipping string and char
+/// literals.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if chars.peek().is_some_and(|(_, next)| *next == '/') => {
+                return Some(index)
+            }
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+/// Indentation level of `line`: a tab or four spaces per level.
+fn indent_level(line: &str) -> usize {
+    let spaces: usize = line
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    spaces / 4
+}
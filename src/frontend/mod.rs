@@ -1,7 +1,9 @@
 //! Frontend components: Tokenizer, AST, Parser.
 
 pub mod ast;
+pub mod format;
 pub mod parser;
+pub mod span;
 pub mod tokenizer;
 // Potentially re-export key types
 // pub use ast::{Expression, Statement, Item};
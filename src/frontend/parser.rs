@@ -1,16 +1,18 @@
-use std::{
-    collections::{HashMap, VecDeque},
-    iter::Peekable,
-};
+use std::{collections::VecDeque, iter::Peekable, ops::Range};
 
 use crate::{
     compiler::typecheck::*,
+    errors::{AbraError, LexError, ParseError},
     frontend::{ast::Class, tokenizer::TokenName},
     runtime::value::StaticValue,
 };
 
 use super::{
-    ast::{BinOpCode, Expression, Function, Item, Parameter, Statement, UnaryOpCode},
+    ast::{
+        BinOpCode, Block, Enum, Expression, Function, Global, Interface, Item, Parameter, Pattern,
+        Statement, UnaryOpCode,
+    },
+    span::{LineIndex, Located, Span},
     tokenizer::{Token, TokenLiteral},
 };
 
@@ -20,9 +22,14 @@ type LexerItem = Result<(usize, Token, usize), anyhow::Error>;
 pub struct Parser<L: Iterator<Item = LexerItem>> {
     lexer: Peekable<L>,
     buffer: VecDeque<(usize, Token, usize)>,
-
-    classes: Vec<Class>,
-    functions: Vec<Function>,
+    /// Resolves token offsets into lines and columns for spans and errors.
+    line_index: LineIndex,
+    /// End offset of the most recently consumed token.
+    last_end: usize,
+
+    /// Type parameters of the class and function being parsed, which types name
+    /// as `Type::Var`.
+    type_params: Vec<String>,
 }
 
 impl<L: Iterator<Item = LexerItem>> Parser<L> {
@@ -30,9 +37,55 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         Parser {
             lexer: lexer.peekable(),
             buffer: VecDeque::with_capacity(2), // Lookahead buffer
-            classes: vec![],
-            functions: vec![],
+            line_index: LineIndex::default(),
+            last_end: 0,
+            type_params: vec![],
+        }
+    }
+
+    /// Lets spans and errors carry real line and column numbers for `source`.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.line_index = LineIndex::new(source);
+        self
+    }
+
+    /// Names the file spans and errors point into, for programs made of several files.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.line_index = std::mem::take(&mut self.line_index).in_file(file);
+        self
+    }
+
+    fn error_at<S: Into<String>>(
+        &self,
+        range: Range<usize>,
+        kind: fn(Span, String) -> ParseError,
+        message: S,
+    ) -> Error {
+        Error::new(kind(self.line_index.span(range), message.into()))
+    }
+
+    /// An error for input that ended where `message` expected more.
+    fn eof_error<S: Into<String>>(&self, message: S) -> Error {
+        let end = self.last_end;
+        self.error_at(end..end, ParseError::UnexpectedEof, message)
+    }
+
+    /// Byte range of the next token, or the end of the last one at EOF.
+    fn next_range(&mut self) -> Result<Range<usize>> {
+        let last_end = self.last_end;
+        Ok(self
+            .peek_nth(0)?
+            .map_or(last_end..last_end, |(start, _, end)| *start..*end))
+    }
+
+    /// Pins errors raised without a location to the token the parser stopped at.
+    fn locate_error(&mut self, error: Error) -> AbraError {
+        if error.is::<LexError>() || error.is::<ParseError>() {
+            return AbraError::recover(error);
         }
+        let range = self.next_range().unwrap_or(self.last_end..self.last_end);
+        let span = self.line_index.span(range);
+        ParseError::InvalidSyntax(span, error.to_string()).into()
     }
 
     // --- Token Handling Helpers ---
@@ -59,7 +112,11 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
 
     fn consume(&mut self) -> Result<Option<(usize, Token, usize)>> {
         self.ensure_buffered(1)?;
-        Ok(self.buffer.pop_front())
+        let token = self.buffer.pop_front();
+        if let Some((_, _, end)) = &token {
+            self.last_end = *end;
+        }
+        Ok(token)
     }
 
     fn expect(&mut self, expected: Token) -> Result<(usize, Token, usize)> {
@@ -68,16 +125,19 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             if std::mem::discriminant(token) == std::mem::discriminant(&expected) {
                 Ok(self.consume()?.unwrap()) // Safe unwrap due to peek
             } else {
-                bail!(
-                    "Expected token {} but found {:?} at {}..{}",
+                let message = format!(
+                    "Expected token {} but found {:?}",
                     expected.variant_name(),
-                    token.clone(),
-                    *start,
-                    *end
-                )
+                    token
+                );
+                let range = *start..*end;
+                Err(self.error_at(range, ParseError::UnexpectedToken, message))
             }
         } else {
-            bail!("Expected token {} but found EOF", expected.variant_name())
+            Err(self.eof_error(format!(
+                "Expected token {} but found EOF",
+                expected.variant_name()
+            )))
         }
     }
 
@@ -85,22 +145,21 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         self.ensure_buffered(1)?;
         if let Some((_start, token, _end)) = self.buffer.front() {
             if matches!(token, Token::Literal(TokenLiteral::Identifier(_))) {
-                match self.buffer.pop_front().unwrap() {
+                match self.consume()?.unwrap() {
                     // Safe unwrap
                     (s, Token::Literal(TokenLiteral::Identifier(name)), e) => Ok((name, s, e)),
                     _ => unreachable!(),
                 }
             } else {
-                let (start, consumed_token, end) = self.buffer.pop_front().unwrap();
-                bail!(
-                    "Expected Identifier but found {:?} at {}..{}",
-                    consumed_token,
-                    start,
-                    end
-                )
+                let (start, consumed_token, end) = self.consume()?.unwrap();
+                Err(self.error_at(
+                    start..end,
+                    ParseError::UnexpectedToken,
+                    format!("Expected Identifier but found {:?}", consumed_token),
+                ))
             }
         } else {
-            bail!("Expected Identifier but found EOF")
+            Err(self.eof_error("Expected Identifier but found EOF"))
         }
     }
 
@@ -113,7 +172,11 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
 
     // --- Main Parsing Methods ---
 
-    pub fn parse_program(&mut self) -> Result<Vec<Item>> {
+    pub fn parse_program(&mut self) -> Result<Vec<Item>, AbraError> {
+        self.parse_items().map_err(|e| self.locate_error(e))
+    }
+
+    fn parse_items(&mut self) -> Result<Vec<Item>> {
         let mut items = Vec::new();
         self.consume_eols()?; // Consume leading EOLs
         while self.peek_nth_token(0)? != Some(&Token::EndOfFile) {
@@ -124,25 +187,92 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         Ok(items)
     }
 
+    /// Parses loose statements outside of any function, as typed into the REPL.
+    pub fn parse_statements(&mut self) -> Result<Block, AbraError> {
+        self.parse_loose_statements()
+            .map_err(|e| self.locate_error(e))
+    }
+
+    fn parse_loose_statements(&mut self) -> Result<Block> {
+        let mut stmts = Vec::new();
+        self.consume_eols()?; // Consume leading EOLs
+        while self.peek_nth_token(0)? != Some(&Token::EndOfFile) {
+            stmts.push(self.parse_located_statement()?);
+            self.consume_eols()?; // Consume EOLs between statements
+        }
+        self.expect(Token::EndOfFile)?;
+        Ok(stmts)
+    }
+
     fn parse_top_level_item(&mut self) -> Result<Item> {
         match self.peek_nth_token(0)? {
             Some(Token::Func) => self.parse_function().map(Item::Function),
             Some(Token::Class) => self.parse_class().map(Item::Class),
+            Some(Token::Enum) => self.parse_enum().map(Item::Enum),
+            Some(Token::Interface) => self.parse_interface().map(Item::Interface),
+            Some(Token::Import) => self.parse_import().map(Item::Import),
+            Some(Token::Let | Token::Const) => self.parse_global().map(Item::Global),
             // Add other top-level items (struct, enum, etc.)
             Some(_) => {
                 let (start, unexpected_token, end) = self.consume()?.unwrap();
-                bail!(
-                    "Expected top-level item (like 'func') but found {:?} at {}..{}",
-                    unexpected_token,
-                    start,
-                    end
-                )
+                let message = format!(
+                    "Expected top-level item (like 'func') but found {:?}",
+                    unexpected_token
+                );
+                Err(self.error_at(start..end, ParseError::UnexpectedToken, message))
+            }
+            None => Err(self.eof_error("Expected top-level item but found EOF")),
+        }
+    }
+
+    /// `import "path"`; the path is resolved against the importing file later.
+    fn parse_import(&mut self) -> Result<Located<String>> {
+        let (start, _, _) = self.expect(Token::Import)?;
+        let (path_start, token, end) = self
+            .consume()?
+            .ok_or_else(|| self.eof_error("Expected module path but found EOF"))?;
+        match token {
+            Token::Literal(TokenLiteral::Value(StaticValue::String(path))) => {
+                Ok(Located::new(path, self.line_index.span(start..end)))
             }
-            None => bail!("Expected top-level item but found EOF"),
+            other => Err(self.error_at(
+                path_start..end,
+                ParseError::UnexpectedToken,
+                format!("Expected module path string but found {:?}", other),
+            )),
         }
     }
 
-    fn parse_class_variable_declaration(&mut self) -> Result<(String, Type, StaticValue)> {
+    /// `let name: type = value` or `const NAME: type = value` at the top level.
+    fn parse_global(&mut self) -> Result<Global> {
+        let (start, keyword, _) = self.consume()?.unwrap();
+        let (name, _, _) = self.expect_identifier()?;
+        self.expect(Token::Colon)?;
+        let ty = self.parse_type()?;
+        self.expect(Token::Equals)?;
+        let value = self.parse_expression()?;
+        let span = self.line_index.span(start..self.last_end);
+        self.expect(Token::EndLine)?;
+        Ok(Global {
+            name,
+            ty,
+            value,
+            constant: keyword == Token::Const,
+            span,
+        })
+    }
+
+    /// Extends `name` with any `::name` parts that follow, as in `module::item`.
+    fn parse_qualified(&mut self, mut name: String) -> Result<String> {
+        while self.peek_nth_token(0)? == Some(&Token::DColon) {
+            self.consume()?;
+            let (part, _, _) = self.expect_identifier()?;
+            name = format!("{}::{}", name, part);
+        }
+        Ok(name)
+    }
+
+    fn parse_class_variable_declaration(&mut self) -> Result<(String, Type, Option<Expression>)> {
         self.expect(Token::Let)?;
         let (name, _, _) = self.expect_identifier()?;
         self.expect(Token::Colon)?;
@@ -150,21 +280,40 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
 
         let initial_value = if self.peek_nth_token(0)? == Some(&Token::Equals) {
             self.consume()?; // Consume '='
-            let expr = self.parse_expression()?;
-            match expr {
-                Expression::Literal(TokenLiteral::Value(sv)) => sv,
-                _ => bail!("Class variable initializers must be static literal values."),
-            }
+            Some(self.parse_expression()?)
         } else {
-            StaticValue::Null
+            None
         };
         self.expect(Token::EndLine)?;
         Ok((name, ty, initial_value))
     }
 
     fn parse_class(&mut self) -> Result<Class> {
-        self.expect(Token::Class)?;
+        let (start, _, _) = self.expect(Token::Class)?;
         let (name, _, _) = self.expect_identifier()?;
+        let type_params = self.parse_type_params()?;
+        let enclosing_params = self.type_params.len();
+        self.type_params.extend(type_params.iter().cloned());
+        let parent = if self.peek_nth_token(0)? == Some(&Token::Colon) {
+            self.consume()?; // Consume ':'
+            let (parent, _, _) = self.expect_identifier()?;
+            Some(self.parse_qualified(parent)?)
+        } else {
+            None
+        };
+        let mut interfaces = Vec::new();
+        if self.peek_nth_token(0)? == Some(&Token::Implements) {
+            self.consume()?; // Consume 'implements'
+            loop {
+                let (interface, _, _) = self.expect_identifier()?;
+                interfaces.push(self.parse_qualified(interface)?);
+                if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                    break;
+                }
+                self.consume()?; // Consume ','
+            }
+        }
+        let span = self.line_index.span(start..self.last_end);
 
         self.consume_eols()?;
         self.expect(Token::Indent)?;
@@ -180,10 +329,10 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                     break;
                 }
                 Some(Token::EndOfFile) => {
-                    bail!(
+                    return Err(self.eof_error(format!(
                         "Unexpected EOF while parsing class body for '{}'. Expected Dedent.",
                         name
-                    );
+                    )));
                 }
                 Some(Token::Func) => {
                     functions.push(self.parse_function()?);
@@ -193,44 +342,180 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                     variables.push(self.parse_class_variable_declaration()?);
                     self.consume_eols()?;
                 }
-                Some(tok) => {
+                Some(_) => {
                     let (start, unexpected_token, end) = self.consume()?.unwrap();
-                    bail!(
-                        "Expected 'func', 'let', or Dedent in class '{}' body, but found {:?} at {}..{}",
-                        name, unexpected_token, start, end
+                    let message = format!(
+                        "Expected 'func', 'let', or Dedent in class '{}' body, but found {:?}",
+                        name, unexpected_token
                     );
+                    return Err(self.error_at(start..end, ParseError::UnexpectedToken, message));
                 }
                 None => {
-                    bail!("Unexpected end of input while parsing class body for '{}'. Expected Dedent.", name);
+                    return Err(self.eof_error(format!(
+                        "Unexpected end of input while parsing class body for '{}'. Expected Dedent.",
+                        name
+                    )));
                 }
             }
         }
+        self.type_params.truncate(enclosing_params);
         Ok(Class {
             name,
+            type_params,
+            parent,
+            interfaces,
             variables,
             functions,
+            span,
         })
     }
 
+    /// `enum Name: Variant | Variant(Type, ...) | ...` on one line.
+    fn parse_enum(&mut self) -> Result<Enum> {
+        let (start, _, _) = self.expect(Token::Enum)?;
+        let (name, _, _) = self.expect_identifier()?;
+        self.expect(Token::Colon)?;
+        let mut variants = Vec::new();
+        loop {
+            let (variant, _, _) = self.expect_identifier()?;
+            let mut payload = Vec::new();
+            if self.peek_nth_token(0)? == Some(&Token::LParen) {
+                self.consume()?; // Consume '('
+                loop {
+                    payload.push(self.parse_type()?);
+                    if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                        break;
+                    }
+                    self.consume()?; // Consume comma
+                }
+                self.expect(Token::RParen)?;
+            }
+            variants.push((variant, payload));
+            if self.peek_nth_token(0)? != Some(&Token::Or) {
+                break;
+            }
+            self.consume()?; // Consume '|'
+        }
+        let span = self.line_index.span(start..self.last_end);
+        self.expect(Token::EndLine)?;
+        Ok(Enum {
+            name,
+            variants,
+            span,
+        })
+    }
+
+    /// `interface Name: func sig` on one line, or `interface Name` followed by an
+    /// indented block of signatures.
+    fn parse_interface(&mut self) -> Result<Interface> {
+        let (start, _, _) = self.expect(Token::Interface)?;
+        let (name, _, _) = self.expect_identifier()?;
+        let mut methods = Vec::new();
+        if self.peek_nth_token(0)? == Some(&Token::Colon) {
+            self.consume()?; // Consume ':'
+            methods.push(self.parse_method_signature()?);
+            let span = self.line_index.span(start..self.last_end);
+            self.expect(Token::EndLine)?;
+            return Ok(Interface {
+                name,
+                methods,
+                span,
+            });
+        }
+        let span = self.line_index.span(start..self.last_end);
+        self.consume_eols()?;
+        self.expect(Token::Indent)?;
+        loop {
+            self.consume_eols()?;
+            match self.peek_nth_token(0)? {
+                Some(Token::Dedent) => {
+                    self.consume()?; // Consume Dedent
+                    break;
+                }
+                Some(Token::Func) => {
+                    methods.push(self.parse_method_signature()?);
+                    self.expect(Token::EndLine)?;
+                }
+                Some(_) => {
+                    let (start, unexpected_token, end) = self.consume()?.unwrap();
+                    let message = format!(
+                        "Expected 'func' or Dedent in interface '{}' body, but found {:?}",
+                        name, unexpected_token
+                    );
+                    return Err(self.error_at(start..end, ParseError::UnexpectedToken, message));
+                }
+                None => {
+                    return Err(self.eof_error(format!(
+                        "Unexpected end of input while parsing interface '{}'. Expected Dedent.",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(Interface {
+            name,
+            methods,
+            span,
+        })
+    }
+
+    /// `func name(params) -> type`, without a body.
+    fn parse_method_signature(&mut self) -> Result<(String, Vec<Parameter>, Type)> {
+        self.expect(Token::Func)?;
+        let (name, _, _) = self.expect_identifier()?;
+        self.expect(Token::LParen)?;
+        let params = self.parse_param_list()?;
+        self.expect(Token::RParen)?;
+        self.expect(Token::RArrow)?;
+        let return_type = self.parse_type()?;
+        Ok((name, params, return_type))
+    }
+
     // --- Item/Structure Parsers ---
 
     fn parse_function(&mut self) -> Result<Function> {
-        self.expect(Token::Func)?;
+        let (start, _, _) = self.expect(Token::Func)?;
         let (name, _, _) = self.expect_identifier()?;
+        let type_params = self.parse_type_params()?;
+        let enclosing_params = self.type_params.len();
+        self.type_params.extend(type_params.iter().cloned());
         self.expect(Token::LParen)?;
         let params = self.parse_param_list()?;
         self.expect(Token::RParen)?;
         self.expect(Token::RArrow)?;
         let return_type = self.parse_type()?;
+        let span = self.line_index.span(start..self.last_end);
         let body = self.parse_statement_block()?; // Calls modified block parser
+        self.type_params.truncate(enclosing_params);
         Ok(Function {
             name,
+            type_params,
             params,
             return_type,
             body,
+            span,
         })
     }
 
+    /// `<T, U>` after the name of a generic class or function; empty if there is none.
+    fn parse_type_params(&mut self) -> Result<Vec<String>> {
+        let mut params = Vec::new();
+        if self.peek_nth_token(0)? != Some(&Token::Lesser) {
+            return Ok(params);
+        }
+        self.consume()?; // Consume '<'
+        loop {
+            let (param, _, _) = self.expect_identifier()?;
+            params.push(param);
+            if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                break;
+            }
+            self.consume()?; // Consume ','
+        }
+        self.expect(Token::Greater)?;
+        Ok(params)
+    }
+
     fn parse_param_list(&mut self) -> Result<Vec<Parameter>> {
         let mut params = Vec::new();
         if self.peek_nth_token(0)? != Some(&Token::RParen) {
@@ -249,19 +534,27 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         let (name, _, _) = self.expect_identifier()?;
         self.expect(Token::Colon)?;
         let ty = self.parse_type()?;
-        Ok(Parameter { name, ty })
+        let default = if self.peek_nth_token(0)? == Some(&Token::Equals) {
+            self.consume()?; // Consume '='
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(Parameter { name, ty, default })
     }
 
     fn parse_type(&mut self) -> Result<Type> {
         let (start, token, end) = self
             .consume()?
-            .ok_or_else(|| anyhow!("Expected type but found EOF"))?;
+            .ok_or_else(|| self.eof_error("Expected type but found EOF"))?;
         let ret = match token {
             Token::Int => Ok(Type::Primitive(Primitives::Integer)),
+            Token::BigInt => Ok(Type::Primitive(Primitives::BigInt)),
             Token::Float => Ok(Type::Primitive(Primitives::Float)),
             Token::Bool => Ok(Type::Primitive(Primitives::Bool)),
             Token::Char => Ok(Type::Primitive(Primitives::Char)),
             Token::String => Ok(Type::Primitive(Primitives::String)),
+            Token::Literal(TokenLiteral::Value(StaticValue::Null)) => Ok(Type::Null),
             Token::LBracket => {
                 let inner_type = self.parse_type()?;
                 self.expect(Token::RBracket)?;
@@ -274,12 +567,70 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 self.expect(Token::Greater)?;
                 Ok(Type::Composite(Box::new(Composite::Map(key_type, value_type))))
             },
+            Token::Literal(TokenLiteral::Identifier(name)) if self.type_params.contains(&name) => {
+                Ok(Type::Var(name))
+            }
             Token::Literal(TokenLiteral::Identifier(name)) => {
                 // The parser creates a basic AbraType. The type checker will resolve/populate it later.
-                Ok(Type::Abra(name.clone()))
+                let name = self.parse_qualified(name)?;
+                if self.peek_nth_token(0)? != Some(&Token::Lesser) {
+                    Ok(Type::Abra(name))
+                } else {
+                    self.consume()?; // Consume '<'
+                    let mut args = Vec::new();
+                    loop {
+                        args.push(self.parse_type()?);
+                        if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.consume()?; // Consume ','
+                    }
+                    self.expect(Token::Greater)?;
+                    Ok(Type::Generic(name, args))
+                }
+            }
+            Token::LParen => { // (Type, ...) is a tuple; (Type) is just Type
+                let mut types = vec![self.parse_type()?];
+                while self.peek_nth_token(0)? == Some(&Token::Comma) {
+                    self.consume()?;
+                    types.push(self.parse_type()?);
+                }
+                self.expect(Token::RParen)?;
+                if types.len() == 1 {
+                    Ok(types.pop().unwrap())
+                } else {
+                    Ok(Type::Composite(Box::new(Composite::Tuple(types))))
+                }
             }
-            other => bail!("Expected type (int, float, bool, char, string, [Type], <Type -> Type>, Identifier) but found {:?} at {}..{}", other, start, end),
+            Token::Fn => { // fn(Type, ...) -> Type
+                self.expect(Token::LParen)?;
+                let mut params = Vec::new();
+                if self.peek_nth_token(0)? != Some(&Token::RParen) {
+                    loop {
+                        params.push(self.parse_type()?);
+                        if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.consume()?;
+                    }
+                }
+                self.expect(Token::RParen)?;
+                self.expect(Token::RArrow)?;
+                Ok(Type::function(params, self.parse_type()?))
+            }
+            other => Err(self.error_at(start..end, ParseError::UnexpectedToken, format!("Expected type (int, float, bool, char, string, [Type], <Type -> Type>, Identifier) but found {:?}", other))),
         }?;
+        // `T?` is an optional: `T | null`. The `?` has to touch the type, so the one of
+        // `x is T ? a : b` is left to the conditional.
+        let last_end = self.last_end;
+        let optional =
+            matches!(self.peek_nth(0)?, Some((start, Token::Question, _)) if *start == last_end);
+        let ret = if optional {
+            self.consume()?;
+            Type::or(ret, Type::Null)
+        } else {
+            ret
+        };
         if self.peek_nth_token(0)? == Some(&Token::Or) {
             self.consume()?;
             return Ok(Type::or(ret, self.parse_type()?));
@@ -289,12 +640,21 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
 
     // --- Statement Parsing ---
 
-    fn parse_statement_block(&mut self) -> Result<Vec<Statement>> {
+    fn parse_located_statement(&mut self) -> Result<Located<Statement>> {
+        let start = self.next_range()?.start;
+        let stmt = self.parse_statement_rule()?;
+        Ok(Located::new(
+            stmt,
+            self.line_index.span(start..self.last_end),
+        ))
+    }
+
+    fn parse_statement_block(&mut self) -> Result<Block> {
         // Case 1: Single statement block (e.g., if x: print y)
         if self.peek_nth_token(0)? == Some(&Token::Colon) {
             self.consume()?; // Consume ':'
                              // Expect a single statement rule, which MUST handle its own EOL
-            let stmt = self.parse_statement_rule()?;
+            let stmt = self.parse_located_statement()?;
             Ok(vec![stmt])
         }
         // Case 2: Indented block
@@ -306,17 +666,15 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 && self.peek_nth_token(0)? != Some(&Token::EndOfFile)
             {
                 // Each statement rule handles its own EOL
-                stmts.push(self.parse_statement_rule()?);
+                stmts.push(self.parse_located_statement()?);
                 // Allow blank lines within the block
                 self.consume_eols()?;
             }
 
             if stmts.is_empty() {
-                let loc = match self.peek_nth(0)? {
-                    Some((s, _, e)) => (*s, *e),
-                    None => (0, 0),
-                };
-                bail!("Indented block cannot be empty at {}..{}", loc.0, loc.1);
+                let range = self.next_range()?;
+                let message = "Indented block cannot be empty";
+                return Err(self.error_at(range, ParseError::EmptyBlock, message));
             }
 
             self.expect(Token::Dedent)?;
@@ -326,9 +684,10 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
     }
 
     fn parse_statement_rule(&mut self) -> Result<Statement> {
-        let first_token_peek = self
-            .peek_nth_token(0)?
-            .ok_or_else(|| anyhow!("Expected statement but found EOF"))?;
+        let first_token_peek = match self.peek_nth_token(0)? {
+            Some(token) => token,
+            None => return Err(self.eof_error("Expected statement but found EOF")),
+        };
 
         // Dispatch based on the first token
         let statement = match first_token_peek {
@@ -337,8 +696,12 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             Token::Print => self.parse_print_statement(),
             Token::If => self.parse_if_statement(),
             Token::For => self.parse_for_statement(),
-            // Add While, Loop, etc. here
-            // Token::While => self.parse_while_statement(),
+            Token::While => self.parse_while_statement(),
+            Token::Break | Token::Continue => self.parse_loop_control_statement(),
+            Token::Throw => self.parse_throw_statement(),
+            Token::Try => self.parse_try_statement(),
+            Token::Match => self.parse_match_statement(),
+            Token::Func => self.parse_function().map(Statement::Function),
             Token::Literal(TokenLiteral::Identifier(_)) => {
                 self.parse_statement_starting_with_literal()
             }
@@ -349,12 +712,11 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             // Unexpected token
             _ => {
                 let (start, token, end) = self.consume()?.unwrap(); // Consume to advance
-                bail!(
-                    "Expected statement start (Let, If, Identifier, etc.) but found {:?} at {}..{}",
-                    token,
-                    start,
-                    end
-                )
+                let message = format!(
+                    "Expected statement start (Let, If, Identifier, etc.) but found {:?}",
+                    token
+                );
+                Err(self.error_at(start..end, ParseError::UnexpectedToken, message))
             }
         };
 
@@ -364,30 +726,97 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
     }
 
     fn parse_statement_starting_with_literal(&mut self) -> Result<Statement> {
-        // Lookahead for assignment
+        let statement = self.parse_assignment_or_expression()?;
+        self.expect(Token::EndLine)?; // Expect EOL
+        Ok(statement)
+    }
 
-        let is_assign = self.peek_nth_token(1)? == Some(&Token::Equals);
-        // Add lookahead for compound assignment if needed
-        // let is_compound_assign = matches!(self.peek_nth_token(1)?, Some(Token::PlusEquals | ...));
+    /// `name = expr`, `object.member = expr`, `collection[index] = expr`, one of
+    /// those targets followed by `+=`, `-=`, `*=`, `/=` or `%=`, or a plain expression
+    /// (NO EOL).
+    fn parse_assignment_or_expression(&mut self) -> Result<Statement> {
+        // Lookahead for assignment
+        if self.peek_nth_token(1)? == Some(&Token::Equals) {
+            let (n, _, _) = self.expect_identifier()?;
+            self.expect(Token::Equals)?;
+            let e = self.parse_expression()?;
+            return Ok(Statement::Set(None, n, e));
+        }
+        let target = self.parse_expression()?;
+        match self.peek_nth_token(0)? {
+            Some(Token::Equals) => {}
+            Some(
+                Token::PlusEquals
+                | Token::MinusEquals
+                | Token::StarEquals
+                | Token::SlashEquals
+                | Token::PercentEquals,
+            ) => return self.parse_compound_assignment(target),
+            _ => return Ok(Statement::Expression(target)),
+        }
+        let (start, _, end) = self.expect(Token::Equals)?;
+        match target {
+            Expression::Get(member, object) => {
+                let e = self.parse_expression()?;
+                Ok(Statement::Set(Some(*object), member, e))
+            }
+            Expression::Index(collection, index) => {
+                let e = self.parse_expression()?;
+                Ok(Statement::SetIndex(*collection, *index, e))
+            }
+            other => {
+                let message = format!("Cannot assign to '{}'", other);
+                Err(self.error_at(start..end, ParseError::InvalidSyntax, message))
+            }
+        }
+    }
 
-        if is_assign
-        /* || is_compound_assign */
-        {
-            self.parse_set_statement() // Or parse_compound_assignment()
-        } else {
-            // If not assignment, it's an expression statement (e.g., function call)
-            self.parse_expression_statement()
+    fn parse_compound_assignment(&mut self, target: Expression) -> Result<Statement> {
+        let (start, token, end) = self.consume()?.unwrap(); // Safe unwrap: the caller peeked it
+        match target {
+            Expression::Literal(TokenLiteral::Identifier(_))
+            | Expression::Get(..)
+            | Expression::Index(..) => {
+                let e = self.parse_expression()?;
+                Ok(Statement::Compound(target, BinOpCode::from(token), e))
+            }
+            other => {
+                let message = format!("Cannot assign to '{}'", other);
+                Err(self.error_at(start..end, ParseError::InvalidSyntax, message))
+            }
         }
     }
     // --- Specific Statement Parsers (Each handles its own EOL) ---
 
     fn parse_let_statement(&mut self) -> Result<Statement> {
         self.expect(Token::Let)?;
+        if self.peek_nth_token(0)? == Some(&Token::LParen) {
+            // Destructuring: let (a, b) = tuple
+            self.consume()?; // Consume '('
+            let mut names = Vec::new();
+            loop {
+                names.push(self.expect_identifier()?.0);
+                if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                    break;
+                }
+                self.consume()?; // Consume ','
+            }
+            self.expect(Token::RParen)?;
+            self.expect(Token::Equals)?;
+            let e = self.parse_expression()?;
+            self.expect(Token::EndLine)?; // Expect EOL
+            return Ok(Statement::Destructure(names, e));
+        }
         let (n, _, _) = self.expect_identifier()?;
         self.expect(Token::Colon)?;
         let t = self.parse_type()?;
-        self.expect(Token::Equals)?;
-        let e = self.parse_expression()?;
+        // A variable without a value gets one assigned later.
+        let e = if self.peek_nth_token(0)? == Some(&Token::Equals) {
+            self.consume()?; // Consume '='
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
         self.expect(Token::EndLine)?; // Expect EOL
         Ok(Statement::Declare(n, t, e))
     }
@@ -410,15 +839,6 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         Ok(Statement::Print(e))
     }
 
-    fn parse_set_statement(&mut self) -> Result<Statement> {
-        let (n, _, _) = self.expect_identifier()?;
-        self.expect(Token::Equals)?;
-        let e = self.parse_expression()?;
-        self.expect(Token::EndLine)?; // Expect EOL
-        Ok(Statement::Set(n, e))
-    }
-    // Add parse_compound_assignment if needed
-
     fn parse_expression_statement(&mut self) -> Result<Statement> {
         let e = self.parse_expression()?;
         self.expect(Token::EndLine)?; // Expect EOL
@@ -435,7 +855,7 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                              // Check for 'else if' vs 'else:'/'else <block>'
             if self.peek_nth_token(0)? == Some(&Token::If) {
                 // Parse 'else if' as a nested If statement wrapped in a block
-                let nested_if = self.parse_if_statement()?;
+                let nested_if = self.parse_located_statement()?;
                 Some(vec![nested_if])
             } else {
                 // Parse 'else:' or 'else <indented block>'
@@ -467,11 +887,132 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         ))
     }
 
+    fn parse_loop_control_statement(&mut self) -> Result<Statement> {
+        let (_, token, _) = self.consume()?.unwrap();
+        self.expect(Token::EndLine)?; // Expect EOL
+        Ok(match token {
+            Token::Break => Statement::Break,
+            _ => Statement::Continue,
+        })
+    }
+
+    fn parse_while_statement(&mut self) -> Result<Statement> {
+        self.expect(Token::While)?;
+        let cond = self.parse_expression()?;
+        let body = self.parse_statement_block()?; // Handles its own block end
+
+        // No EOL expected here after block
+        Ok(Statement::While(cond, body))
+    }
+
+    fn parse_throw_statement(&mut self) -> Result<Statement> {
+        self.expect(Token::Throw)?;
+        let e = self.parse_expression()?;
+        self.expect(Token::EndLine)?; // Expect EOL
+        Ok(Statement::Throw(e))
+    }
+
+    fn parse_try_statement(&mut self) -> Result<Statement> {
+        self.expect(Token::Try)?;
+        let body = self.parse_statement_block()?; // Handles its own block end
+        self.consume_eols()?;
+        self.expect(Token::Catch)?;
+        let (name, _, _) = self.expect_identifier()?;
+        let handler = self.parse_statement_block()?;
+
+        // No EOL expected here after block
+        Ok(Statement::Try(body, name, handler))
+    }
+
+    fn parse_match_statement(&mut self) -> Result<Statement> {
+        self.expect(Token::Match)?;
+        let subject = self.parse_expression()?;
+        self.consume_eols()?;
+        self.expect(Token::Indent)?;
+        let mut arms = Vec::new();
+        while self.peek_nth_token(0)? != Some(&Token::Dedent)
+            && self.peek_nth_token(0)? != Some(&Token::EndOfFile)
+        {
+            let pattern = self.parse_pattern()?;
+            let body = self.parse_statement_block()?; // Handles its own block end
+            arms.push((pattern, body));
+            self.consume_eols()?;
+        }
+
+        if arms.is_empty() {
+            let range = self.next_range()?;
+            let message = "Match needs at least one arm";
+            return Err(self.error_at(range, ParseError::EmptyBlock, message));
+        }
+
+        self.expect(Token::Dedent)?;
+        // No EOL expected here after the arms
+        Ok(Statement::Match(subject, arms))
+    }
+
+    // Pattern of a match arm: literal, literal -> literal, 'is' Type, Enum::Variant(names) or '_'
+    fn parse_pattern(&mut self) -> Result<Pattern> {
+        let (start, token, end) = self
+            .consume()?
+            .ok_or_else(|| self.eof_error("Expected pattern but found EOF"))?;
+        match token {
+            Token::Is => Ok(Pattern::Type(self.parse_type()?)),
+            Token::Literal(TokenLiteral::Identifier(name)) if name == "_" => Ok(Pattern::Wildcard),
+            Token::Literal(TokenLiteral::Identifier(name)) => {
+                let name = self.parse_qualified(name)?;
+                let mut bindings = Vec::new();
+                if self.peek_nth_token(0)? == Some(&Token::LParen) {
+                    self.consume()?; // Consume '('
+                    loop {
+                        bindings.push(self.expect_identifier()?.0);
+                        if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.consume()?; // Consume comma
+                    }
+                    self.expect(Token::RParen)?;
+                }
+                Ok(Pattern::Variant(name, bindings))
+            }
+            Token::Literal(TokenLiteral::Value(value)) => {
+                if self.peek_nth_token(0)? != Some(&Token::RArrow) {
+                    return Ok(Pattern::Literal(value));
+                }
+                self.consume()?; // Consume '->'
+                let (end_start, end_token, end_end) = self
+                    .consume()?
+                    .ok_or_else(|| self.eof_error("Expected end of range pattern after '->' but found EOF"))?;
+                let end_value = match end_token {
+                    Token::Literal(TokenLiteral::Value(v)) => v,
+                    o => {
+                        let message = format!("Expected literal value for end of range but found {:?}", o);
+                        return Err(self.error_at(end_start..end_end, ParseError::InvalidRange, message));
+                    }
+                };
+                let empty = match (&value, &end_value) {
+                    (StaticValue::Integer(s), StaticValue::Integer(e)) => s >= e,
+                    (StaticValue::Char(s), StaticValue::Char(e)) => s > e,
+                    (l, r) => {
+                        let message = format!("Cannot match a range between {:?} and {:?}", l, r);
+                        return Err(self.error_at(start..end_end, ParseError::InvalidRange, message));
+                    }
+                };
+                if empty {
+                    let message = format!("Range pattern {} -> {} matches nothing", value, end_value);
+                    return Err(self.error_at(start..end_end, ParseError::InvalidRange, message));
+                }
+                Ok(Pattern::Range(value, end_value))
+            }
+            other => Err(self.error_at(start..end, ParseError::UnexpectedToken, format!("Expected pattern (literal, range, 'is Type', enum variant or '_') but found {:?}", other))),
+        }
+    }
+
     // Helper for 'for' loop initializer (No EOL consumed)
     fn parse_for_init(&mut self) -> Result<Statement> {
-        let first_token_peek = self
-            .peek_nth_token(0)?
-            .ok_or_else(|| anyhow!("Expected for loop initializer but found EOF"))?;
+        let first_token_peek = match self.peek_nth_token(0)? {
+            Some(token) => token,
+            None => return Err(self.eof_error("Expected for loop initializer but found EOF")),
+        };
         match first_token_peek {
             Token::Let => {
                 // let var: type = expr
@@ -481,22 +1022,11 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 let t = self.parse_type()?;
                 self.expect(Token::Equals)?;
                 let e = self.parse_expression()?;
-                Ok(Statement::Declare(n, t, e)) // NO EOL
+                Ok(Statement::Declare(n, t, Some(e))) // NO EOL
             }
             Token::Literal(TokenLiteral::Identifier(_)) => {
-                // var = expr | expr
-                let is_assign = self.peek_nth_token(1)? == Some(&Token::Equals);
-                if is_assign {
-                    // Assignment: var = expr
-                    let (n, _, _) = self.expect_identifier()?;
-                    self.expect(Token::Equals)?;
-                    let e = self.parse_expression()?;
-                    Ok(Statement::Set(n, e)) // NO EOL
-                } else {
-                    // Just an expression (e.g., func_call())
-                    let e = self.parse_expression()?;
-                    Ok(Statement::Expression(e)) // NO EOL
-                }
+                // var = expr | obj.member = expr | arr[i] = expr | expr
+                self.parse_assignment_or_expression() // NO EOL
             }
             _ if Self::is_start_of_expression(first_token_peek) => {
                 // Other expression
@@ -505,38 +1035,27 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             }
             _ => {
                 let (s, t, e) = self.peek_nth(0)?.unwrap();
-                bail!("Expected for loop initializer (Let, Assignment, or Expression) but found {:?} at {}..{}", t.clone(), *s, *e)
+                let message = format!(
+                    "Expected for loop initializer (Let, Assignment, or Expression) but found {:?}",
+                    t
+                );
+                let range = *s..*e;
+                Err(self.error_at(range, ParseError::UnexpectedToken, message))
             }
         }
     }
 
     // Helper for 'for' loop incrementor (No EOL consumed) - CORRECTED version
     fn parse_for_incr(&mut self) -> Result<Statement> {
-        let first_token_peek = self
-            .peek_nth_token(0)?
-            .ok_or_else(|| anyhow!("Expected for loop incrementor but found EOF"))?;
+        let first_token_peek = match self.peek_nth_token(0)? {
+            Some(token) => token,
+            None => return Err(self.eof_error("Expected for loop incrementor but found EOF")),
+        };
 
         match first_token_peek {
             Token::Literal(TokenLiteral::Identifier(_)) => {
-                // var = expr | expr
-                let is_assign = self.peek_nth_token(1)? == Some(&Token::Equals);
-                // Add compound assign check if needed
-                // let is_compound_assign = matches!(self.peek_nth_token(1)?, Some(Token::PlusEquals | ...));
-
-                if is_assign
-                /* || is_compound_assign */
-                {
-                    // Assignment: var = expr
-                    let (n, _, _) = self.expect_identifier()?;
-                    self.expect(Token::Equals)?; // Or expect compound token
-                    let e = self.parse_expression()?;
-                    Ok(Statement::Set(n, e)) // NO EOL
-                                             // Handle compound assignment Statement creation if needed
-                } else {
-                    // Just an expression
-                    let e = self.parse_expression()?;
-                    Ok(Statement::Expression(e)) // NO EOL
-                }
+                // var = expr | obj.member = expr | arr[i] = expr | expr
+                self.parse_assignment_or_expression() // NO EOL
             }
             _ if Self::is_start_of_expression(first_token_peek) => {
                 // Other expression
@@ -545,7 +1064,12 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             }
             _ => {
                 let (s, t, e) = self.peek_nth(0)?.unwrap();
-                bail!("Expected for loop incrementor (Assignment or Expression) but found {:?} at {}..{}", t.clone(), *s, *e)
+                let message = format!(
+                    "Expected for loop incrementor (Assignment or Expression) but found {:?}",
+                    t
+                );
+                let range = *s..*e;
+                Err(self.error_at(range, ParseError::UnexpectedToken, message))
             }
         }
     }
@@ -559,14 +1083,16 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 | Token::LParen
                 | Token::Minus
                 | Token::Bang
+                | Token::Tilde
                 | Token::New
+                | Token::Fn
                 | Token::LBracket /* Array lits? */
         )
         // Add others like '{' for object literals if needed
     }
 
     fn parse_expression(&mut self) -> Result<Expression> {
-        self.parse_equality() // Start with lowest precedence binary op handled
+        self.parse_conditional() // Start with lowest precedence binary op handled
     }
 
     // Generic binary operator parsing (uses TryFrom for BinOpCode)
@@ -581,12 +1107,9 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 .iter()
                 .any(|op| std::mem::discriminant(op) == std::mem::discriminant(peeked_token))
             {
-                let (op_start, op_token, op_end) = self.consume()?.unwrap();
-
-                // Use TryFrom for conversion
-                let bin_op = BinOpCode::try_from(op_token.clone()).map_err(|e| {
-                    anyhow!("Internal parser error at {}..{}: {}", op_start, op_end, e)
-                })?;
+                let (_, op_token, _) = self.consume()?.unwrap();
+                // `ops` only holds operator tokens, which all convert.
+                let bin_op = BinOpCode::from(op_token);
 
                 let right = parse_operand(self)?;
                 left = Expression::Binary(bin_op, Box::new(left), Box::new(right));
@@ -598,15 +1121,51 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
     }
 
     // Operator Precedence Levels
+    fn parse_conditional(&mut self) -> Result<Expression> {
+        let condition = self.parse_coalesce()?;
+        if self.peek_nth_token(0)? != Some(&Token::Question) {
+            return Ok(condition);
+        }
+        self.consume()?; // Consume '?'
+        let then = self.parse_conditional()?;
+        self.expect(Token::Colon)?;
+        let otherwise = self.parse_conditional()?; // Right associative: a ? b : c ? d : e
+        Ok(Expression::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+    fn parse_coalesce(&mut self) -> Result<Expression> {
+        let value = self.parse_or()?;
+        if self.peek_nth_token(0)? != Some(&Token::QuestionQuestion) {
+            return Ok(value);
+        }
+        self.consume()?; // Consume '??'
+        let fallback = self.parse_coalesce()?; // Right associative: a ?? b ?? c
+        Ok(Expression::Coalesce(Box::new(value), Box::new(fallback)))
+    }
+    fn parse_or(&mut self) -> Result<Expression> {
+        self.parse_binary(Self::parse_and, &[Token::OrOr])
+    }
+    fn parse_and(&mut self) -> Result<Expression> {
+        self.parse_binary(Self::parse_equality, &[Token::AndAnd])
+    }
     fn parse_equality(&mut self) -> Result<Expression> {
-        self.parse_binary(
-            Self::parse_comparison,
-            &[Token::EqualsEquals, Token::BangEq],
-        )
+        self.parse_binary(Self::parse_type_test, &[Token::EqualsEquals, Token::BangEq])
+    }
+    fn parse_type_test(&mut self) -> Result<Expression> {
+        let value = self.parse_comparison()?;
+        if self.peek_nth_token(0)? != Some(&Token::Is) {
+            return Ok(value);
+        }
+        self.consume()?; // Consume 'is'
+        let ty = self.parse_type()?;
+        Ok(Expression::Is(Box::new(value), ty))
     }
     fn parse_comparison(&mut self) -> Result<Expression> {
         self.parse_binary(
-            Self::parse_term,
+            Self::parse_bitor,
             &[
                 Token::Lesser,
                 Token::Greater,
@@ -615,13 +1174,49 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             ],
         )
     }
+    fn parse_bitor(&mut self) -> Result<Expression> {
+        self.parse_binary(Self::parse_bitxor, &[Token::Or])
+    }
+    fn parse_bitxor(&mut self) -> Result<Expression> {
+        self.parse_binary(Self::parse_bitand, &[Token::Caret])
+    }
+    fn parse_bitand(&mut self) -> Result<Expression> {
+        self.parse_binary(Self::parse_shift, &[Token::And])
+    }
+    fn parse_shift(&mut self) -> Result<Expression> {
+        let mut left = self.parse_term()?;
+        while let Some(op) = self.peek_shift()? {
+            self.consume()?;
+            self.consume()?;
+            let right = self.parse_term()?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    /// `<<` or `>>` next: two angle brackets with nothing between them. The tokenizer
+    /// keeps them apart so `>>` can still close two type arguments.
+    fn peek_shift(&mut self) -> Result<Option<BinOpCode>> {
+        let Some(&(_, ref first, first_end)) = self.peek_nth(0)? else {
+            return Ok(None);
+        };
+        let op = match first {
+            Token::Lesser => BinOpCode::SHL,
+            Token::Greater => BinOpCode::SHR,
+            _ => return Ok(None),
+        };
+        let first = first.clone();
+        Ok(match self.peek_nth(1)? {
+            Some((start, second, _)) if *start == first_end && *second == first => Some(op),
+            _ => None,
+        })
+    }
     fn parse_term(&mut self) -> Result<Expression> {
         self.parse_binary(Self::parse_factor, &[Token::Plus, Token::Minus])
     }
     fn parse_factor(&mut self) -> Result<Expression> {
         self.parse_binary(
             Self::parse_unary,
-            &[Token::Star, Token::Slash /* Add Token::Percent? */],
+            &[Token::Star, Token::Slash, Token::Percent],
         )
     }
 
@@ -631,6 +1226,7 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
             let unary_op = match op_token {
                 Token::Minus => Some(UnaryOpCode::NEG),
                 Token::Bang => Some(UnaryOpCode::NOT),
+                Token::Tilde => Some(UnaryOpCode::BNOT),
                 _ => None,
             };
             if let Some(op) = unary_op {
@@ -648,35 +1244,82 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         loop {
             match self.peek_nth_token(0)? {
                 Some(Token::LBracket) => {
-                    // Array/Map Access: expr[index]
+                    // Array/Map Access: expr[index], or Slice: expr[start:end]
                     self.consume()?; // Consume '['
-                    let index_expr = self.parse_expression()?;
-                    self.expect(Token::RBracket)?;
-                    // TODO: Generalize access beyond just identifiers if needed
-                    match expr {
-                        Expression::Literal(TokenLiteral::Identifier(name)) => {
-                            expr = Expression::Get(name, Box::new(index_expr))
-                        }
-                        _ => bail!(
-                            "Cannot apply index operator `[]` to this expression type: {}",
-                            expr
-                        ),
+                    let start = match self.peek_nth_token(0)? {
+                        Some(Token::Colon) => None,
+                        _ => Some(Box::new(self.parse_expression()?)),
+                    };
+                    if self.peek_nth_token(0)? != Some(&Token::Colon) {
+                        self.expect(Token::RBracket)?;
+                        let index_expr = start.expect("an index without ':' is parsed");
+                        expr = Expression::Index(Box::new(expr), index_expr);
+                        continue;
                     }
+                    self.consume()?; // Consume ':'
+                    let end = match self.peek_nth_token(0)? {
+                        Some(Token::RBracket) => None,
+                        _ => Some(Box::new(self.parse_expression()?)),
+                    };
+                    self.expect(Token::RBracket)?;
+                    expr = Expression::Slice(Box::new(expr), start, end);
                 }
                 Some(Token::LParen) => {
                     // Function Call: expr(args)
                     self.consume()?; // Consume '('
                     let args = self.parse_call_args()?;
                     self.expect(Token::RParen)?;
-                    // TODO: Generalize calls beyond just identifiers if needed (e.g., (get_func())() )
+                    // Named calls are resolved by name; anything else must evaluate to a function.
                     match expr {
                         Expression::Literal(TokenLiteral::Identifier(name)) => {
                             expr = Expression::Call(name, args)
                         }
-                        _ => bail!("Cannot call this expression type like a function: {}", expr),
+                        callee => expr = Expression::CallIndirect(Box::new(callee), args),
+                    }
+                }
+                Some(Token::Dot) => {
+                    // Method Call: expr.method(args), or Member Access: expr.member
+                    self.consume()?; // Consume '.'
+                    if let Some(Token::Literal(TokenLiteral::Value(StaticValue::Integer(_)))) =
+                        self.peek_nth_token(0)?
+                    {
+                        // Tuple Access: expr.0
+                        let (start, token, end) = self.consume()?.unwrap();
+                        let index = match token {
+                            Token::Literal(TokenLiteral::Value(StaticValue::Integer(i))) => i,
+                            _ => unreachable!("peeked an integer"),
+                        };
+                        let index = usize::try_from(index).map_err(|_| {
+                            let message = format!("Invalid tuple position {}", index);
+                            self.error_at(start..end, ParseError::InvalidSyntax, message)
+                        })?;
+                        expr = Expression::TupleIndex(Box::new(expr), index);
+                        continue;
+                    }
+                    let (member, _, _) = self.expect_identifier()?;
+                    if self.peek_nth_token(0)? != Some(&Token::LParen) {
+                        expr = Expression::Get(member, Box::new(expr));
+                        continue;
                     }
+                    self.consume()?; // Consume '('
+                    let args = self.parse_call_args()?;
+                    self.expect(Token::RParen)?;
+                    expr = Expression::MethodCall(Box::new(expr), member, args);
+                }
+                Some(Token::QuestionDot) => {
+                    // Safe Access: expr?.member or expr?.method(args), null when expr is
+                    self.consume()?; // Consume '?.'
+                    let (member, _, _) = self.expect_identifier()?;
+                    let access = if self.peek_nth_token(0)? != Some(&Token::LParen) {
+                        Expression::Get(member, Box::new(expr))
+                    } else {
+                        self.consume()?; // Consume '('
+                        let args = self.parse_call_args()?;
+                        self.expect(Token::RParen)?;
+                        Expression::MethodCall(Box::new(expr), member, args)
+                    };
+                    expr = Expression::SafeAccess(Box::new(access));
                 }
-                // Add Token::Dot for member access if needed
                 _ => break, // No more postfix operators
             }
         }
@@ -688,7 +1331,19 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
         let mut args = Vec::new();
         if self.peek_nth_token(0)? != Some(&Token::RParen) {
             loop {
-                args.push(self.parse_expression()?);
+                let named = matches!(
+                    self.peek_nth_token(0)?,
+                    Some(Token::Literal(TokenLiteral::Identifier(_)))
+                ) && self.peek_nth_token(1)? == Some(&Token::Colon);
+                if named {
+                    // Named Argument: name: value
+                    let (name, _, _) = self.expect_identifier()?;
+                    self.consume()?; // Consume ':'
+                    let value = self.parse_expression()?;
+                    args.push(Expression::NamedArgument(name, Box::new(value)));
+                } else {
+                    args.push(self.parse_expression()?);
+                }
                 if self.peek_nth_token(0)? != Some(&Token::Comma) {
                     break;
                 }
@@ -702,7 +1357,7 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
     fn parse_primary(&mut self) -> Result<Expression> {
         let (start, token, end) = self
             .consume()?
-            .ok_or_else(|| anyhow!("Expected primary expression but found EOF"))?;
+            .ok_or_else(|| self.eof_error("Expected primary expression but found EOF"))?;
         match token {
             Token::Literal(lit @ TokenLiteral::Value(_)) => {
                  // Check for Range Expression: literal -> literal
@@ -712,15 +1367,35 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                      Ok(Expression::Literal(lit)) // Simple literal
                  }
             }
-            Token::Literal(lit @ TokenLiteral::Identifier(_)) => {
+            Token::Literal(TokenLiteral::Identifier(name)) => {
                 // Identifier is initially parsed as a literal.
                 // Postfix parsing will handle if it's used in a call or access.
-                Ok(Expression::Literal(lit))
+                let name = self.parse_qualified(name)?;
+                Ok(Expression::Literal(TokenLiteral::Identifier(name)))
             }
-            Token::LParen => { // Grouping: ( expr )
+            Token::LParen => { // Grouping: ( expr ), or Tuple: ( expr, expr, ... )
                 let expr = self.parse_expression()?;
+                if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                    self.expect(Token::RParen)?;
+                    return Ok(Expression::Grouping(Box::new(expr)));
+                }
+                let mut elements = vec![expr];
+                while self.peek_nth_token(0)? == Some(&Token::Comma) {
+                    self.consume()?; // Consume ','
+                    elements.push(self.parse_expression()?);
+                }
                 self.expect(Token::RParen)?;
-                Ok(Expression::Grouping(Box::new(expr)))
+                Ok(Expression::TupleLiteral(elements))
+            }
+            Token::Fn => { // Lambda: fn(params) -> Type: body
+                self.expect(Token::LParen)?;
+                let params = self.parse_param_list()?;
+                self.expect(Token::RParen)?;
+                self.expect(Token::RArrow)?;
+                let return_type = self.parse_type()?;
+                self.expect(Token::Colon)?;
+                let body = self.parse_expression()?;
+                Ok(Expression::Lambda(params, return_type, Box::new(body)))
             }
             Token::New => { // Instance Creation: new Type(args)
                 let ty = self.parse_type()?;
@@ -729,13 +1404,45 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 self.expect(Token::RParen)?;
                 Ok(Expression::Instance(ty, args))
             }
-             // Add Token::LBracket for array literals if needed
-             // Add Token::LBrace for object/struct literals if needed
-            other => bail!("Expected primary expression (Literal, Identifier, '(', 'new') but found {:?} at {}..{}", other, start, end),
+            Token::Super => { // Parent method call: super.method(args)
+                self.expect(Token::Dot)?;
+                let (method, _, _) = self.expect_identifier()?;
+                self.expect(Token::LParen)?;
+                let args = self.parse_call_args()?;
+                self.expect(Token::RParen)?;
+                Ok(Expression::SuperCall(method, args))
+            }
+            Token::LBracket => { // Array Literal: [a, b, c]
+                let mut elements = Vec::new();
+                while self.peek_nth_token(0)? != Some(&Token::RBracket) {
+                    elements.push(self.parse_expression()?);
+                    if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                        break;
+                    }
+                    self.consume()?; // Consume ','
+                }
+                self.expect(Token::RBracket)?;
+                Ok(Expression::ArrayLiteral(elements))
+            }
+            Token::LBrace => { // Map Literal: { key: value, ... }
+                let mut entries = Vec::new();
+                while self.peek_nth_token(0)? != Some(&Token::RBrace) {
+                    let key = self.parse_expression()?;
+                    self.expect(Token::Colon)?;
+                    entries.push((key, self.parse_expression()?));
+                    if self.peek_nth_token(0)? != Some(&Token::Comma) {
+                        break;
+                    }
+                    self.consume()?; // Consume ','
+                }
+                self.expect(Token::RBrace)?;
+                Ok(Expression::MapLiteral(entries))
+            }
+            other => Err(self.error_at(start..end, ParseError::UnexpectedToken, format!("Expected primary expression (Literal, Identifier, '(', '[', '{{', 'new', 'fn', 'super') but found {:?}", other))),
         }
     }
 
-    // Range Expression: literal -> literal (creates an array instance)
+    // Range Expression: literal -> literal (an array of the values between them)
     fn parse_range_expression(
         &mut self,
         start_lit: TokenLiteral,
@@ -744,17 +1451,15 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
     ) -> Result<Expression> {
         self.expect(Token::RArrow)?; // Consume '->'
 
-        let (end_start, end_token, end_end) = self
-            .consume()?
-            .ok_or_else(|| anyhow!("Expected end of range expression after '->' but found EOF"))?;
+        let (end_start, end_token, end_end) = self.consume()?.ok_or_else(|| {
+            self.eof_error("Expected end of range expression after '->' but found EOF")
+        })?;
         let end_lit = match end_token {
             Token::Literal(l @ TokenLiteral::Value(_)) => l,
-            o => bail!(
-                "Expected literal value for end of range but found {:?} at {}..{}",
-                o,
-                end_start,
-                end_end
-            ),
+            o => {
+                let message = format!("Expected literal value for end of range but found {:?}", o);
+                return Err(self.error_at(end_start..end_end, ParseError::InvalidRange, message));
+            }
         };
 
         match (start_lit, end_lit) {
@@ -763,47 +1468,36 @@ impl<L: Iterator<Item = LexerItem>> Parser<L> {
                 TokenLiteral::Value(StaticValue::Integer(e)),
             ) => {
                 if s >= e {
-                    bail!(
-                        "Range start {} must be less than end {} at {}..{}",
-                        s,
-                        e,
-                        start_loc,
-                        end_end
-                    );
+                    let message = format!("Range start {} must be less than end {}", s, e);
+                    let range = start_loc..end_end;
+                    return Err(self.error_at(range, ParseError::InvalidRange, message));
                 }
-                let elements = (s..e)
-                    .map(|v| Expression::Literal(TokenLiteral::Value(StaticValue::Integer(v))))
-                    .collect();
-                let int_primitive = Type::Primitive(Primitives::Integer);
-                let array_of_int = Type::Composite(Box::new(Composite::Array(int_primitive)));
-                Ok(Expression::Instance(array_of_int, elements))
+                Ok(Expression::Range(
+                    StaticValue::Integer(s),
+                    StaticValue::Integer(e),
+                ))
             }
             (
                 TokenLiteral::Value(StaticValue::Char(s)),
                 TokenLiteral::Value(StaticValue::Char(e)),
             ) => {
                 if s > e {
-                    bail!(
-                        "Range start '{}' must be less than or equal to end '{}' at {}..{}",
-                        s,
-                        e,
-                        start_loc,
-                        end_end
+                    let message = format!(
+                        "Range start '{}' must be less than or equal to end '{}'",
+                        s, e
                     );
+                    let range = start_loc..end_end;
+                    return Err(self.error_at(range, ParseError::InvalidRange, message));
                 }
-                let elements = (s..=e)
-                    .map(|v| Expression::Literal(TokenLiteral::Value(StaticValue::Char(v))))
-                    .collect();
-                let char_primitive = Type::Primitive(Primitives::Char);
-                let array_of_char = Type::Composite(Box::new(Composite::Array(char_primitive)));
-                Ok(Expression::Instance(array_of_char, elements))
-            }
-            (l, r) => bail!(
-                "Cannot create a range between {:?} and {:?} starting near {}",
-                l,
-                r,
-                start_loc
-            ),
+                Ok(Expression::Range(
+                    StaticValue::Char(s),
+                    StaticValue::Char(e),
+                ))
+            }
+            (l, r) => {
+                let message = format!("Cannot create a range between {:?} and {:?}", l, r);
+                Err(self.error_at(start_loc..end_end, ParseError::InvalidRange, message))
+            }
         }
     }
 } // end impl Parser
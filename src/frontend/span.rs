@@ -0,0 +1,172 @@
+//! Source locations for diagnostics.
+//!
+//! The tokenizer works in byte offsets; a [`LineIndex`] turns those into the
+//! 1-based line and column a person looks for in their editor.
+
+use std::{fmt::Display, ops::Range, sync::Arc};
+
+use crate::compiler::lint::Lint;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// File the span points into, when the program spans several files.
+    pub file: Option<Arc<str>>,
+    /// 1-based line of the first byte.
+    pub line: usize,
+    /// 1-based column, in characters, of the first byte.
+    pub col: usize,
+    pub byte_range: Range<usize>,
+}
+
+/// `line:col`, the prefix error messages start with.
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Byte offsets of every line start, to resolve offsets into lines and columns.
+#[derive(Debug, Clone, Default)]
+pub struct LineIndex {
+    source: String,
+    line_starts: Vec<usize>,
+    file: Option<Arc<str>>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(idx, _)| idx + 1));
+        LineIndex {
+            source: source.to_string(),
+            line_starts,
+            file: None,
+        }
+    }
+
+    /// Tags every span made from this index with `file`.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Without a source every offset is reported on line 1.
+    pub fn span(&self, byte_range: Range<usize>) -> Span {
+        let line = self
+            .line_starts
+            .partition_point(|start| *start <= byte_range.start)
+            .max(1);
+        let line_start = self.line_starts.get(line - 1).copied().unwrap_or(0);
+        let col = match self.source.get(line_start..byte_range.start) {
+            Some(prefix) => prefix.chars().count() + 1,
+            None => byte_range.start - line_start + 1,
+        };
+        Span {
+            file: self.file.clone(),
+            line,
+            col,
+            byte_range,
+        }
+    }
+
+    /// Text of the 1-based `line`, without its line break.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = match self.line_starts.get(line.wrapping_sub(1)) {
+            Some(start) => *start,
+            None => return "",
+        };
+        let rest = self.source.get(start..).unwrap_or("");
+        rest.split('\n').next().unwrap_or("").trim_end_matches('\r')
+    }
+
+    /// `message` with the file, line and column of `span` and the offending line
+    /// underlined, in the style of rustc:
+    ///
+    /// ```text
+    /// error: Variable y not found
+    ///  --> main.abra:3:12
+    ///   |
+    /// 3 |     return y
+    ///   |            ^
+    /// ```
+    pub fn render(&self, severity: &str, message: &str, file: &str, span: &Span) -> String {
+        let text = self.line_text(span.line);
+        let gutter = " ".repeat(span.line.to_string().len());
+        let width = text.chars().count().saturating_sub(span.col - 1);
+        let carets = span.byte_range.len().min(width).max(1);
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            severity,
+            message,
+            gutter,
+            file,
+            span.line,
+            span.col,
+            gutter,
+            span.line,
+            text,
+            gutter,
+            " ".repeat(span.col - 1),
+            "^".repeat(carets)
+        )
+    }
+}
+
+/// A typechecker warning tied to the place in the source that caused it.
+#[derive(Debug, Clone)]
+pub struct SourceError {
+    pub span: Span,
+    pub message: String,
+    /// The lint the warning belongs to, which decides whether it is reported.
+    pub lint: Option<Lint>,
+}
+
+impl SourceError {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Self {
+        SourceError {
+            span,
+            message: message.into(),
+            lint: None,
+        }
+    }
+
+    pub fn with_lint(mut self, lint: Lint) -> Self {
+        self.lint = Some(lint);
+        self
+    }
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.col, self.message)
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// An AST node together with where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Located<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Located { node, span }
+    }
+}
+
+impl<T> std::ops::Deref for Located<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T: Display> Display for Located<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
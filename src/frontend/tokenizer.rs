@@ -1,7 +1,11 @@
 use std::{fmt::Display, iter::Peekable, str::CharIndices};
 
-use crate::runtime::value::StaticValue;
-use anyhow::{anyhow, Result};
+use crate::{
+    errors::LexError,
+    frontend::span::{LineIndex, Span},
+    runtime::{bigint::BigInt, value::StaticValue},
+};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, PartialOrd)]
@@ -9,10 +13,13 @@ pub enum Token {
     DColon,
     Colon,
     Comma,
+    Dot,
     LParen,
     RParen,
     LBracket,
     RBracket,
+    LBrace,
+    RBrace,
     Plus,
     PlusEquals,
     Minus,
@@ -21,6 +28,8 @@ pub enum Token {
     SlashEquals,
     Star,
     StarEquals,
+    Percent,
+    PercentEquals,
     Equals,
     EqualsEquals,
     Greater,
@@ -38,7 +47,9 @@ pub enum Token {
     Literal(TokenLiteral),
 
     Func,
+    Fn,
     Int,
+    BigInt,
     Float,
     Char,
     Bool,
@@ -49,18 +60,37 @@ pub enum Token {
     Else,
     For,
     While,
+    Break,
+    Continue,
+    Try,
+    Catch,
+    Throw,
     Do,
     Let,
+    Const,
 
     Loop,
     New,
     EndOfFile,
     Class,
+    Import,
+    Match,
+    Is,
+    Enum,
+    Super,
+    Interface,
+    Implements,
 
     And,
     AndAnd,
     Or,
     OrOr,
+    Caret,
+    Tilde,
+
+    Question,
+    QuestionQuestion,
+    QuestionDot,
 }
 
 impl Display for Token {
@@ -101,10 +131,13 @@ impl TokenName for Token {
             Token::DColon => "::",
             Token::Colon => ":",
             Token::Comma => ",",
+            Token::Dot => ".",
             Token::LParen => "(",
             Token::RParen => ")",
             Token::LBracket => "[",
             Token::RBracket => "]",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
             Token::Plus => "+",
             Token::PlusEquals => "+=",
             Token::Minus => "-",
@@ -113,6 +146,8 @@ impl TokenName for Token {
             Token::SlashEquals => "/=",
             Token::Star => "*",
             Token::StarEquals => "*=",
+            Token::Percent => "%",
+            Token::PercentEquals => "%=",
             Token::Equals => "=",
             Token::EqualsEquals => "==",
             Token::Greater => ">",
@@ -128,13 +163,17 @@ impl TokenName for Token {
             Token::BangEq => "!=",
             Token::Literal(TokenLiteral::Identifier(_)) => "Identifier",
             Token::Literal(TokenLiteral::Value(StaticValue::Integer(_))) => "IntegerLiteral",
+            Token::Literal(TokenLiteral::Value(StaticValue::BigInt(_))) => "BigIntLiteral",
             Token::Literal(TokenLiteral::Value(StaticValue::Float(_))) => "FloatLiteral",
             Token::Literal(TokenLiteral::Value(StaticValue::Char(_))) => "CharLiteral",
             Token::Literal(TokenLiteral::Value(StaticValue::Bool(_))) => "BoolLiteral",
             Token::Literal(TokenLiteral::Value(StaticValue::String(_))) => "StringLiteral",
             Token::Literal(TokenLiteral::Value(StaticValue::Null)) => "NullLiteral",
             Token::Func => "func",
+            Token::Fn => "fn",
+            Token::Import => "import",
             Token::Int => "int",
+            Token::BigInt => "bigint",
             Token::Float => "float",
             Token::Char => "char",
             Token::Bool => "bool",
@@ -145,16 +184,33 @@ impl TokenName for Token {
             Token::Else => "else",
             Token::For => "for",
             Token::While => "while",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Try => "try",
+            Token::Catch => "catch",
+            Token::Throw => "throw",
             Token::Do => "do",
             Token::Loop => "loop",
             Token::New => "new",
             Token::EndOfFile => "EndOfFile",
             Token::Let => "let",
+            Token::Const => "const",
             Token::Class => "class",
+            Token::Match => "match",
+            Token::Is => "is",
+            Token::Enum => "enum",
+            Token::Super => "super",
+            Token::Interface => "interface",
+            Token::Implements => "implements",
             Token::And => "&",
             Token::AndAnd => "&&",
             Token::Or => "|",
             Token::OrOr => "||",
+            Token::Caret => "^",
+            Token::Tilde => "~",
+            Token::Question => "?",
+            Token::QuestionQuestion => "??",
+            Token::QuestionDot => "?.",
         }
     }
 }
@@ -167,6 +223,7 @@ pub struct Tokenizer<'i> {
     needs_indent_check: bool,
     pending_dedents: usize,
     current_token_start_pos: usize,
+    line_index: LineIndex,
 }
 
 const SPACES_PER_INDENT: usize = 4;
@@ -188,22 +245,32 @@ impl<'a> Tokenizer<'a> {
             needs_indent_check: true,
             pending_dedents: 0,
             current_token_start_pos: 0,
+            line_index: LineIndex::new(input),
         }
     }
 
+    /// A `kind` of error pointing at the character at byte offset `at`.
+    fn error_at<S: Into<String>>(
+        &self,
+        at: usize,
+        kind: fn(Span, String) -> LexError,
+        message: S,
+    ) -> anyhow::Error {
+        let end = self.input[at..]
+            .chars()
+            .next()
+            .map_or(at, |c| at + c.len_utf8());
+        anyhow::Error::new(kind(self.line_index.span(at..end), message.into()))
+    }
+
     fn consume_while<F>(&mut self, start_index: usize, condition: F) -> (usize, &'a str)
     where
         F: Fn(char) -> bool,
     {
-        let mut current_idx = start_index;
-        let mut end_idx = start_index;
-
-        if let Some(&(idx, _)) = self.characters.peek() {
-            current_idx = idx;
-            end_idx = idx;
-        } else {
-            return (start_index, &self.input[start_index..start_index]);
-        }
+        let mut end_idx = match self.characters.peek() {
+            Some(&(idx, _)) => idx,
+            None => return (start_index, &self.input[start_index..start_index]),
+        };
 
         while let Some(&(idx, ch)) = self.characters.peek() {
             if condition(ch) {
@@ -228,24 +295,43 @@ impl<'a> Tokenizer<'a> {
 
         let token = match full_id.as_str() {
             "func" => Token::Func,
+            "fn" => Token::Fn,
+            "import" => Token::Import,
             "int" => Token::Int,
+            "bigint" => Token::BigInt,
             "float" => Token::Float,
             "char" => Token::Char,
             "bool" => Token::Bool,
             "string" => Token::String,
-            //"print" => Token::Print,
+            "print" => Token::Print,
             "return" => Token::Return,
             "if" => Token::If,
             "else" => Token::Else,
             "for" => Token::For,
             "while" => Token::While,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "try" => Token::Try,
+            "catch" => Token::Catch,
+            "throw" => Token::Throw,
             "do" => Token::Do,
             "loop" => Token::Loop,
             "new" => Token::New,
             "true" => Token::Literal(TokenLiteral::Value(StaticValue::Bool(true))),
             "false" => Token::Literal(TokenLiteral::Value(StaticValue::Bool(false))),
+            "null" => Token::Literal(TokenLiteral::Value(StaticValue::Null)),
             "let" => Token::Let,
+            "const" => Token::Const,
+            "and" => Token::AndAnd,
+            "or" => Token::OrOr,
+            "not" => Token::Bang,
             "class" => Token::Class,
+            "match" => Token::Match,
+            "is" => Token::Is,
+            "enum" => Token::Enum,
+            "super" => Token::Super,
+            "interface" => Token::Interface,
+            "implements" => Token::Implements,
             _ => Token::Literal(TokenLiteral::Identifier(full_id)),
         };
 
@@ -272,13 +358,12 @@ impl<'a> Tokenizer<'a> {
             }
         }
 
-        if let Some(&(idx_dot, '.')) = self.characters.peek() {
+        // After a dot the number is a tuple position, so `pair.0.1` is two of them.
+        let after_dot = self.input[..start_index].ends_with('.');
+        if let (Some(&(idx_dot, '.')), false) = (self.characters.peek(), after_dot) {
             let mut ahead_peek = self.characters.clone();
             ahead_peek.next();
-            if ahead_peek
-                .peek()
-                .map_or(false, |&(_, c)| c.is_ascii_digit())
-            {
+            if ahead_peek.peek().is_some_and(|&(_, c)| c.is_ascii_digit()) {
                 is_float = true;
                 self.characters.next();
                 end_index = idx_dot + '.'.len_utf8();
@@ -296,6 +381,28 @@ impl<'a> Tokenizer<'a> {
             }
         }
 
+        // An `n` suffix makes an arbitrary-precision literal: `123n`.
+        if !is_float {
+            if let Some(&(idx_n, 'n')) = self.characters.peek() {
+                let mut ahead_peek = self.characters.clone();
+                ahead_peek.next();
+                if !ahead_peek
+                    .peek()
+                    .is_some_and(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.characters.next();
+                    let value = num_str_buf.parse::<BigInt>().map_err(|e| {
+                        self.error_at(start_index, LexError::InvalidNumber, e.to_string())
+                    })?;
+                    return Ok((
+                        start_index,
+                        Token::Literal(TokenLiteral::Value(StaticValue::BigInt(value))),
+                        idx_n + 'n'.len_utf8(),
+                    ));
+                }
+            }
+        }
+
         let number_str = num_str_buf.as_str();
 
         if is_float {
@@ -305,11 +412,10 @@ impl<'a> Tokenizer<'a> {
                     Token::Literal(TokenLiteral::Value(f.into())),
                     end_index,
                 )),
-                Err(e) => Err(anyhow!(
-                    "Invalid float literal '{}' at index {}: {}",
-                    number_str,
+                Err(e) => Err(self.error_at(
                     start_index,
-                    e
+                    LexError::InvalidNumber,
+                    format!("Invalid float literal '{}': {}", number_str, e),
                 )),
             }
         } else {
@@ -319,11 +425,10 @@ impl<'a> Tokenizer<'a> {
                     Token::Literal(TokenLiteral::Value(StaticValue::Integer(i))),
                     end_index,
                 )),
-                Err(e) => Err(anyhow!(
-                    "Invalid integer literal '{}' at index {}: {}",
-                    number_str,
+                Err(e) => Err(self.error_at(
                     start_index,
-                    e
+                    LexError::InvalidNumber,
+                    format!("Invalid integer literal '{}': {}", number_str, e),
                 )),
             }
         }
@@ -331,8 +436,6 @@ impl<'a> Tokenizer<'a> {
 
     fn consume_string(&mut self, start_index: usize) -> Result<(usize, Token, usize)> {
         let mut content = String::new();
-        let mut current_idx = start_index + '"'.len_utf8();
-
         loop {
             match self.characters.next() {
                 Some((idx, '"')) => {
@@ -343,44 +446,42 @@ impl<'a> Tokenizer<'a> {
                         end_index,
                     ));
                 }
-                Some((idx, '\\')) => {
-                    current_idx = idx + '\\'.len_utf8();
-                    match self.characters.next() {
-                        Some((idx_esc, 'n')) => {
-                            content.push('\n');
-                            current_idx = idx_esc + 'n'.len_utf8();
-                        }
-                        Some((idx_esc, 't')) => {
-                            content.push('\t');
-                            current_idx = idx_esc + 't'.len_utf8();
-                        }
-                        Some((idx_esc, '\\')) => {
-                            content.push('\\');
-                            current_idx = idx_esc + '\\'.len_utf8();
-                        }
-                        Some((idx_esc, '"')) => {
-                            content.push('"');
-                            current_idx = idx_esc + '"'.len_utf8();
-                        }
-                        Some((idx_esc, other)) => {
-                            return Err(anyhow!("Invalid escape sequence '\\{}' in string literal starting at index {}", other, idx));
-                        }
-                        None => {
-                            return Err(anyhow!(
-                                "Unterminated string literal starting at index {}",
-                                start_index
-                            ));
-                        }
+                Some((_, '\\')) => match self.characters.next() {
+                    Some((_, 'n')) => {
+                        content.push('\n');
                     }
-                }
-                Some((idx, ch)) => {
+                    Some((_, 't')) => {
+                        content.push('\t');
+                    }
+                    Some((_, '\\')) => {
+                        content.push('\\');
+                    }
+                    Some((_, '"')) => {
+                        content.push('"');
+                    }
+                    Some((idx_esc, other)) => {
+                        return Err(self.error_at(
+                            idx_esc,
+                            LexError::InvalidEscape,
+                            format!("Invalid escape sequence '\\{}' in string literal", other),
+                        ));
+                    }
+                    None => {
+                        return Err(self.error_at(
+                            start_index,
+                            LexError::UnterminatedLiteral,
+                            "Unterminated string literal",
+                        ));
+                    }
+                },
+                Some((_, ch)) => {
                     content.push(ch);
-                    current_idx = idx + ch.len_utf8();
                 }
                 None => {
-                    return Err(anyhow!(
-                        "Unterminated string literal starting at index {}",
-                        start_index
+                    return Err(self.error_at(
+                        start_index,
+                        LexError::UnterminatedLiteral,
+                        "Unterminated string literal",
                     ));
                 }
             }
@@ -389,51 +490,51 @@ impl<'a> Tokenizer<'a> {
 
     fn consume_char(&mut self, start_index: usize) -> Result<(usize, Token, usize)> {
         let char_val: char;
-        let pos_after_char: usize;
 
         match self.characters.next() {
-            Some((idx, '\\')) => match self.characters.next() {
-                Some((idx_esc, 'n')) => {
+            Some((_, '\\')) => match self.characters.next() {
+                Some((_, 'n')) => {
                     char_val = '\n';
-                    pos_after_char = idx_esc + 'n'.len_utf8();
                 }
-                Some((idx_esc, 't')) => {
+                Some((_, 't')) => {
                     char_val = '\t';
-                    pos_after_char = idx_esc + 't'.len_utf8();
                 }
-                Some((idx_esc, '\\')) => {
+                Some((_, '\\')) => {
                     char_val = '\\';
-                    pos_after_char = idx_esc + '\\'.len_utf8();
                 }
-                Some((idx_esc, '\'')) => {
+                Some((_, '\'')) => {
                     char_val = '\'';
-                    pos_after_char = idx_esc + '\''.len_utf8();
                 }
                 Some((idx_esc, other)) => {
-                    return Err(anyhow!(
-                        "Invalid escape sequence '\\{}' in char literal at index {}",
-                        other,
-                        idx
+                    return Err(self.error_at(
+                        idx_esc,
+                        LexError::InvalidEscape,
+                        format!("Invalid escape sequence '\\{}' in char literal", other),
                     ));
                 }
                 None => {
-                    return Err(anyhow!(
-                        "Unterminated char literal (EOF after escape) starting at index {}",
-                        start_index
+                    return Err(self.error_at(
+                        start_index,
+                        LexError::UnterminatedLiteral,
+                        "Unterminated char literal (EOF after escape)",
                     ));
                 }
             },
-            Some((idx, '\'')) => {
-                return Err(anyhow!("Empty char literal at index {}", start_index));
+            Some((_, '\'')) => {
+                return Err(self.error_at(
+                    start_index,
+                    LexError::InvalidCharLiteral,
+                    "Empty char literal",
+                ));
             }
-            Some((idx, ch)) => {
+            Some((_, ch)) => {
                 char_val = ch;
-                pos_after_char = idx + ch.len_utf8();
             }
             None => {
-                return Err(anyhow!(
-                    "Unterminated char literal (EOF after opening quote) starting at index {}",
-                    start_index
+                return Err(self.error_at(
+                    start_index,
+                    LexError::UnterminatedLiteral,
+                    "Unterminated char literal (EOF after opening quote)",
                 ));
             }
         }
@@ -447,14 +548,15 @@ impl<'a> Tokenizer<'a> {
                     end_index,
                 ))
             }
-            Some((idx_bad, other)) => Err(anyhow!(
-                "Expected closing ' for char literal at index {}, found '{}'",
-                pos_after_char,
-                other
+            Some((idx_bad, other)) => Err(self.error_at(
+                idx_bad,
+                LexError::InvalidCharLiteral,
+                format!("Expected closing ' for char literal, found '{}'", other),
             )),
-            None => Err(anyhow!(
-                "Unterminated char literal (EOF before closing quote) starting at index {}",
-                start_index
+            None => Err(self.error_at(
+                start_index,
+                LexError::UnterminatedLiteral,
+                "Unterminated char literal (EOF before closing quote)",
             )),
         }
     }
@@ -488,9 +590,9 @@ impl<'a> Tokenizer<'a> {
                             }
                         }
                         IndentStyle::Tabs => {
-                            let err_pos = idx;
-                            return Err(anyhow!(
-                                "Mixed indentation: Found space at index {} after using tabs for indentation on this line.", err_pos
+                            return Err(self.error_at(
+                                idx, LexError::Indentation,
+                                "Mixed indentation: Found space after using tabs for indentation on this line.",
                             ));
                         }
                     }
@@ -504,9 +606,9 @@ impl<'a> Tokenizer<'a> {
                             self.characters.next();
                         }
                         IndentStyle::Spaces => {
-                            let err_pos = idx;
-                            return Err(anyhow!(
-                                "Mixed indentation: Found tab at index {} after using spaces for indentation on this line.", err_pos
+                            return Err(self.error_at(
+                                idx, LexError::Indentation,
+                                "Mixed indentation: Found tab after using spaces for indentation on this line.",
                             ));
                         }
                         IndentStyle::Tabs => {
@@ -522,9 +624,13 @@ impl<'a> Tokenizer<'a> {
         }
 
         if style == IndentStyle::Spaces && space_count != 0 {
-            return Err(anyhow!(
-                "Inconsistent indentation: Found {} spaces at index {} which is not a multiple of {}.",
-                space_count, pos_after_indent - space_count, SPACES_PER_INDENT
+            return Err(self.error_at(
+                pos_after_indent - space_count,
+                LexError::Indentation,
+                format!(
+                    "Inconsistent indentation: Found {} spaces which is not a multiple of {}.",
+                    space_count, SPACES_PER_INDENT
+                ),
             ));
         }
 
@@ -566,13 +672,14 @@ impl<'i> Iterator for Tokenizer<'i> {
                     Some(&(_, '/')) => {
                         let mut ahead_peek = self.characters.clone();
                         ahead_peek.next();
-                        ahead_peek.peek().map_or(false, |&(_, c)| c == '/')
+                        ahead_peek.peek().is_some_and(|&(_, c)| c == '/')
                     }
                     _ => false,
                 };
 
                 if is_blank_or_comment {
                     if let Some(&(_, '\n')) = self.characters.peek() {
+                        self.characters.next();
                         self.needs_indent_check = true;
                     }
                     continue;
@@ -585,9 +692,12 @@ impl<'i> Iterator for Tokenizer<'i> {
                         self.indent_stack.push(current_level);
                         return Some(Ok((indent_start_pos, Token::Indent, indent_start_pos)));
                     } else {
-                        return Some(Err(anyhow!(
-                            "Invalid indentation: Indented to level {} from level {} at index {}. Can only indent one level at a time.",
-                            current_level, last_level, indent_start_pos
+                        return Some(Err(self.error_at(
+                            indent_start_pos, LexError::Indentation,
+                            format!(
+                                "Invalid indentation: Indented to level {} from level {}. Can only indent one level at a time.",
+                                current_level, last_level
+                            ),
                         )));
                     }
                 } else if current_level < last_level {
@@ -597,9 +707,12 @@ impl<'i> Iterator for Tokenizer<'i> {
                     }
 
                     if *self.indent_stack.last().unwrap() != current_level {
-                        return Some(Err(anyhow!(
-                            "Inconsistent indentation: Dedented to level {} at index {}, which does not match any previous indentation level. Known levels: {:?}",
-                            current_level, indent_start_pos, self.indent_stack
+                        return Some(Err(self.error_at(
+                            indent_start_pos, LexError::Indentation,
+                            format!(
+                                "Inconsistent indentation: Dedented to level {}, which does not match any previous indentation level. Known levels: {:?}",
+                                current_level, self.indent_stack
+                            ),
                         )));
                     }
 
@@ -655,7 +768,12 @@ impl<'i> Iterator for Tokenizer<'i> {
                         ')' => Ok((start_index, Token::RParen, end_index)),
                         '[' => Ok((start_index, Token::LBracket, end_index)),
                         ']' => Ok((start_index, Token::RBracket, end_index)),
+                        '{' => Ok((start_index, Token::LBrace, end_index)),
+                        '}' => Ok((start_index, Token::RBrace, end_index)),
                         ',' => Ok((start_index, Token::Comma, end_index)),
+                        '.' => Ok((start_index, Token::Dot, end_index)),
+                        '^' => Ok((start_index, Token::Caret, end_index)),
+                        '~' => Ok((start_index, Token::Tilde, end_index)),
                         ':' => {
                             if self
                                 .characters
@@ -800,6 +918,56 @@ impl<'i> Iterator for Tokenizer<'i> {
                                 Ok((start_index, Token::Bang, end_index))
                             }
                         }
+                        '%' => {
+                            if self
+                                .characters
+                                .peek()
+                                .map(|&(_, c)| c == '=')
+                                .unwrap_or(false)
+                            {
+                                self.characters.next();
+                                Ok((start_index, Token::PercentEquals, start_index + 2))
+                            } else {
+                                Ok((start_index, Token::Percent, end_index))
+                            }
+                        }
+                        '&' => {
+                            if self
+                                .characters
+                                .peek()
+                                .map(|&(_, c)| c == '&')
+                                .unwrap_or(false)
+                            {
+                                self.characters.next();
+                                Ok((start_index, Token::AndAnd, start_index + 2))
+                            } else {
+                                Ok((start_index, Token::And, end_index))
+                            }
+                        }
+                        '|' => {
+                            if self
+                                .characters
+                                .peek()
+                                .map(|&(_, c)| c == '|')
+                                .unwrap_or(false)
+                            {
+                                self.characters.next();
+                                Ok((start_index, Token::OrOr, start_index + 2))
+                            } else {
+                                Ok((start_index, Token::Or, end_index))
+                            }
+                        }
+                        '?' => match self.characters.peek().map(|&(_, c)| c) {
+                            Some('?') => {
+                                self.characters.next();
+                                Ok((start_index, Token::QuestionQuestion, start_index + 2))
+                            }
+                            Some('.') => {
+                                self.characters.next();
+                                Ok((start_index, Token::QuestionDot, start_index + 2))
+                            }
+                            _ => Ok((start_index, Token::Question, end_index)),
+                        },
 
                         '"' => self.consume_string(start_index),
                         '\'' => self.consume_char(start_index),
@@ -809,10 +977,10 @@ impl<'i> Iterator for Tokenizer<'i> {
                         c if c.is_ascii_alphabetic() || c == '_' => {
                             Ok(self.consume_identifier(start_index, c))
                         }
-                        _ => Err(anyhow!(
-                            "Unexpected character '{}' at index {}",
-                            current_char,
-                            start_index
+                        _ => Err(self.error_at(
+                            start_index,
+                            LexError::UnexpectedCharacter,
+                            format!("Unexpected character '{}'", current_char),
                         )),
                     };
                     return Some(result);
@@ -6,13 +6,48 @@
 // Declare top-level modules
 pub mod cli;
 pub mod compiler;
+pub mod errors;
 pub mod frontend;
 pub mod optimizer;
+pub mod playground;
 pub mod runtime;
 #[cfg(test)]
 pub mod test;
 
-// Re-export key components for easier use if this were a library
-// pub use compiler::compile_from_source;
-// pub use runtime::execute_bytecode;
-// ... etc.
+// Cranelift only generates code for the machine it runs on.
+#[cfg(all(feature = "jit", target_arch = "wasm32"))]
+compile_error!("the `jit` feature needs a native target");
+
+// The surface other crates embed Abra through
+pub use compiler::typecheck::{FunctionSignature, SignatureProvider, Type};
+pub use compiler::Code;
+pub use errors::AbraError;
+pub use runtime::{
+    debug::RuntimeError,
+    embed::Vm,
+    inbuilt::Natives,
+    sandbox::{Capability, VmConfig},
+    value::Value,
+};
+
+/// Compiles a program held in memory. Errors point into the file `<source>`.
+pub fn compile_source(source: &str) -> Result<Code, AbraError> {
+    cli::compile_named(
+        source,
+        cli::SOURCE_FILE,
+        0,
+        &Vec::new(),
+        &Default::default(),
+    )
+    .map(|(code, _)| code)
+}
+
+/// Compiles a program that may call the host functions in `natives`, which are then
+/// registered with the [`Vm`] that runs it.
+pub fn compile_source_with(
+    source: &str,
+    natives: &dyn SignatureProvider,
+) -> Result<Code, AbraError> {
+    cli::compile_named(source, cli::SOURCE_FILE, 0, natives, &Default::default())
+        .map(|(code, _)| code)
+}
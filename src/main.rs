@@ -2,8 +2,11 @@
 use abra_lang::cli;
 fn main() {
     if let Err(e) = cli::run_app() {
-        eprintln!("Error: {}", e);
-        // Consider adding more context to the error if possible
+        // Compile errors come with their diagnostics rendered already.
+        match e.downcast_ref::<cli::Rendered>() {
+            Some(rendered) => eprintln!("{}", rendered),
+            None => eprintln!("Error: {}", e),
+        }
         std::process::exit(1);
     }
 }
@@ -0,0 +1,209 @@
+//! Peephole passes over compiled bytecode.
+//!
+//! Every pass works on a window of adjacent instructions and only rewrites it
+//! when nothing can jump into the middle of it. Instructions are deleted by
+//! marking them, then labels and numeric jumps are remapped in one go.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    compiler::{ByteCode, Code, Symbol},
+    runtime::value::StaticValue,
+};
+
+/// Runs the peephole passes until none of them finds anything left to do.
+pub fn optimize_bytecode(code: &mut Code) {
+    // Hand-written code can jump outside the program; leave that alone.
+    if numeric_jump_targets(&code.bytecode).is_none() {
+        return;
+    }
+    loop {
+        let threaded = thread_jumps(code);
+        let removed = rewrite(code);
+        if !threaded && !removed {
+            break;
+        }
+    }
+}
+
+/// Indices reachable by `JMPABS`/`JMPREL`/`JITA`/`JITR`, or `None` if one of them
+/// points outside the bytecode.
+pub(super) fn numeric_jump_targets(bytecode: &[ByteCode]) -> Option<Vec<usize>> {
+    let mut targets = Vec::new();
+    for (index, byte) in bytecode.iter().enumerate() {
+        let target = match byte {
+            ByteCode::JMPABS(target) | ByteCode::JITA(target) => *target,
+            ByteCode::JMPREL(offset) | ByteCode::JITR(offset) => index as i64 + offset,
+            _ => continue,
+        };
+        if target < 0 || target as usize > bytecode.len() {
+            return None;
+        }
+        targets.push(target as usize);
+    }
+    Some(targets)
+}
+
+/// Points `JMPTO`/`JITL` at the end of a chain of `JMPTO`s directly.
+fn thread_jumps(code: &mut Code) -> bool {
+    let mut changed = false;
+    for index in 0..code.bytecode.len() {
+        let label = match &code.bytecode[index] {
+            ByteCode::JMPTO(label) | ByteCode::JITL(label) => label.clone(),
+            _ => continue,
+        };
+        let mut seen = HashSet::from([label.clone()]);
+        let mut last = label.clone();
+        while let Some(ByteCode::JMPTO(next)) =
+            code.labels.get(&last).and_then(|at| code.bytecode.get(*at))
+        {
+            // A jump that loops back on itself has no end to thread to.
+            if !seen.insert(next.clone()) {
+                break;
+            }
+            last = next.clone();
+        }
+        if last != label {
+            match &mut code.bytecode[index] {
+                ByteCode::JMPTO(label) | ByteCode::JITL(label) => *label = last,
+                _ => unreachable!(),
+            }
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Deletes `PUSH`/`POP` pairs and unreachable code and folds constant arithmetic.
+fn rewrite(code: &mut Code) -> bool {
+    let mut targets: HashSet<usize> = code.labels.values().copied().collect();
+    targets.extend(numeric_jump_targets(&code.bytecode).unwrap_or_default());
+    let bytecode = &mut code.bytecode;
+    let constants = &mut code.constants;
+    let mut keep = vec![true; bytecode.len()];
+    let mut index = 0;
+    while index < bytecode.len() {
+        // Later instructions of a window must not be jump targets.
+        let inner_free = |len: usize| (index + 1..index + len).all(|i| !targets.contains(&i));
+        match &bytecode[index..] {
+            [ByteCode::PUSH(_), ByteCode::POP, ..] if inner_free(2) => {
+                keep[index] = false;
+                keep[index + 1] = false;
+                index += 2;
+            }
+            [ByteCode::PUSH(lower), ByteCode::PUSH(upper), op, ..] if inner_free(3) => {
+                match fold(op, &constants[*lower], &constants[*upper]) {
+                    Some(value) => {
+                        bytecode[index] = ByteCode::PUSH(constant_index(constants, value));
+                        keep[index + 1] = false;
+                        keep[index + 2] = false;
+                        index += 3;
+                    }
+                    None => index += 1,
+                }
+            }
+            [ByteCode::RET(_)
+            | ByteCode::EXIT
+            | ByteCode::THROW
+            | ByteCode::JMPTO(_)
+            | ByteCode::JMPABS(_), ..] => {
+                index += 1;
+                while index < bytecode.len() && !targets.contains(&index) {
+                    keep[index] = false;
+                    index += 1;
+                }
+            }
+            _ => index += 1,
+        }
+    }
+    if keep.iter().all(|kept| *kept) {
+        return false;
+    }
+    compact(code, &keep);
+    true
+}
+
+/// The constant `ADD`/`SUB`/`MULT` leaves on the stack after `PUSH lower; PUSH upper`,
+/// or `None` when it isn't known at compile time or would overflow.
+fn fold(op: &ByteCode, lower: &StaticValue, upper: &StaticValue) -> Option<StaticValue> {
+    // `upper` is the left-hand side, which the VM pops first: `SUB` is `upper - lower`.
+    match (upper, lower) {
+        (StaticValue::Integer(a), StaticValue::Integer(b)) => match op {
+            ByteCode::ADD => a.checked_add(*b),
+            ByteCode::SUB => a.checked_sub(*b),
+            ByteCode::MULT => a.checked_mul(*b),
+            _ => None,
+        }
+        .map(StaticValue::Integer),
+        (StaticValue::Float(a), StaticValue::Float(b)) => match op {
+            ByteCode::ADD => Some(*a + *b),
+            ByteCode::SUB => Some(*a - *b),
+            ByteCode::MULT => Some(*a * *b),
+            _ => None,
+        }
+        .map(StaticValue::Float),
+        _ => None,
+    }
+}
+
+/// Index of `value` in the constant pool, adding it if it isn't there yet.
+fn constant_index(constants: &mut Vec<StaticValue>, value: StaticValue) -> usize {
+    match constants.iter().position(|constant| *constant == value) {
+        Some(index) => index,
+        None => {
+            constants.push(value);
+            constants.len() - 1
+        }
+    }
+}
+
+/// Drops the instructions not marked in `keep`. A label on a dropped instruction
+/// moves to the next one kept.
+pub(super) fn compact(code: &mut Code, keep: &[bool]) {
+    // new_index[old] is the number of instructions kept before `old`.
+    let mut new_index = Vec::with_capacity(keep.len() + 1);
+    let mut kept = 0;
+    for keep in keep.iter() {
+        new_index.push(kept);
+        kept += *keep as usize;
+    }
+    new_index.push(kept);
+
+    if code.locations.len() == keep.len() {
+        let locations = std::mem::take(&mut code.locations);
+        code.locations = locations
+            .into_iter()
+            .zip(keep.iter())
+            .filter_map(|(location, keep)| keep.then_some(location))
+            .collect();
+    }
+
+    let old = std::mem::take(&mut code.bytecode);
+    for (index, mut byte) in old.into_iter().enumerate() {
+        if !keep[index] {
+            continue;
+        }
+        match &mut byte {
+            ByteCode::JMPABS(target) | ByteCode::JITA(target) => {
+                *target = new_index[*target as usize] as i64;
+            }
+            ByteCode::JMPREL(offset) | ByteCode::JITR(offset) => {
+                let target = new_index[(index as i64 + *offset) as usize] as i64;
+                *offset = target - new_index[index] as i64;
+            }
+            _ => {}
+        }
+        code.bytecode.push(byte);
+    }
+    let labels: HashMap<String, usize> = code
+        .labels
+        .drain()
+        .map(|(name, index)| (name, new_index[index.min(keep.len())]))
+        .collect();
+    code.labels = labels;
+    for (_, symbol) in code.symbols.iter_mut() {
+        if let Symbol::Function((Some(start), _)) = symbol {
+            *start = new_index[(*start).min(keep.len())];
+        }
+    }
+}
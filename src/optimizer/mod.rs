@@ -16,12 +16,8 @@ mod ast_optimizer {
 }
 
 // --- Bytecode Optimizer Module ---
-mod bytecode_optimizer {
-    // Use items from optimizer::mod.rs
-    // Import specific bytecodes relative to crate root
-    // If using helpers like try_evaluate_binary_op
-    // If using helpers like try_evaluate_binary_op
+mod bytecode_optimizer;
+mod registers;
 
-    // ... content of bytecode_optimizer module ...
-    // Ensure internal `use` statements reference correctly
-}
+pub use bytecode_optimizer::optimize_bytecode;
+pub use registers::lower_to_registers;
@@ -0,0 +1,139 @@
+//! The register backend: rewrites the stack machine's arithmetic into `BINARY`
+//! instructions that name their operands.
+//!
+//! Locals already live in numbered frame slots, so they serve as the registers.
+//! An operand that was pushed by `LOADSLOT` or `PUSH` right before the instruction
+//! using it is read from its slot or the constant pool instead, and a result that
+//! goes straight into a `STORESLOT` is written there. Whatever can't be named
+//! stays on the stack, so the two forms mix freely. Like the peephole passes, a
+//! window is only rewritten when nothing can jump into the middle of it.
+
+use std::collections::HashSet;
+
+use crate::compiler::{BinaryOp, ByteCode, Code, Operand};
+
+use super::bytecode_optimizer::{compact, numeric_jump_targets};
+
+/// Rewrites `code` into register form until there is nothing left to fold.
+pub fn lower_to_registers(code: &mut Code) {
+    // Hand-written code can jump outside the program; leave that alone.
+    if numeric_jump_targets(&code.bytecode).is_none() {
+        return;
+    }
+    while rewrite(code) {}
+}
+
+/// The operand an instruction pushes without popping anything.
+fn operand(byte: &ByteCode) -> Option<Operand> {
+    match byte {
+        ByteCode::LOADSLOT(slot) => Some(Operand::Slot(*slot)),
+        ByteCode::PUSH(constant) => Some(Operand::Const(*constant)),
+        _ => None,
+    }
+}
+
+/// Whether `byte` only pushes its result, so what it reads can't be disturbed by
+/// moving a read of a slot or constant after it.
+fn pushes_only(byte: &ByteCode) -> bool {
+    matches!(
+        byte,
+        ByteCode::BINARY(_, first, second, Operand::Stack)
+            if *first != Operand::Stack && *second != Operand::Stack
+    )
+}
+
+/// `byte` as a `BINARY` leaving its result on the stack, if it is one or can be one.
+fn binary(byte: &ByteCode) -> Option<(BinaryOp, Operand, Operand)> {
+    match byte {
+        ByteCode::BINARY(op, first, second, Operand::Stack) => Some((*op, *first, *second)),
+        byte => BinaryOp::of(byte).map(|op| (op, Operand::Stack, Operand::Stack)),
+    }
+}
+
+/// The instructions replacing the start of `window` and how many they replace,
+/// looking at no more than `len` instructions.
+fn fold(window: &[ByteCode], len: usize) -> Option<(Vec<ByteCode>, usize)> {
+    let window = &window[..len.min(window.len())];
+    fold_operands(window).or_else(|| fold_result(window))
+}
+
+/// `PUSH`/`LOADSLOT` operands read by the instruction right after them.
+fn fold_operands(window: &[ByteCode]) -> Option<(Vec<ByteCode>, usize)> {
+    // The stack form pops the operand pushed last first.
+    match window {
+        [lower, upper, op, ..] if operand(upper).is_some() => Some((
+            vec![ByteCode::BINARY(
+                BinaryOp::of(op)?,
+                operand(upper)?,
+                operand(lower)?,
+                Operand::Stack,
+            )],
+            3,
+        )),
+        [lower, upper, op, ..] if pushes_only(upper) => Some((
+            vec![
+                upper.clone(),
+                ByteCode::BINARY(
+                    BinaryOp::of(op)?,
+                    Operand::Stack,
+                    operand(lower)?,
+                    Operand::Stack,
+                ),
+            ],
+            3,
+        )),
+        [pushed, op, ..] => Some((
+            vec![ByteCode::BINARY(
+                BinaryOp::of(op)?,
+                operand(pushed)?,
+                Operand::Stack,
+                Operand::Stack,
+            )],
+            2,
+        )),
+        _ => None,
+    }
+}
+
+/// A result stored into a slot right after it is computed.
+fn fold_result(window: &[ByteCode]) -> Option<(Vec<ByteCode>, usize)> {
+    match window {
+        [pushed, ByteCode::STORESLOT(slot), ..] => {
+            let (op, first, second) = binary(pushed)?;
+            Some((
+                vec![ByteCode::BINARY(op, first, second, Operand::Slot(*slot))],
+                2,
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn rewrite(code: &mut Code) -> bool {
+    let mut targets: HashSet<usize> = code.labels.values().copied().collect();
+    targets.extend(numeric_jump_targets(&code.bytecode).unwrap_or_default());
+    let bytecode = &mut code.bytecode;
+    let mut keep = vec![true; bytecode.len()];
+    let mut index = 0;
+    while index < bytecode.len() {
+        // Later instructions of a window must not be jump targets.
+        let free = (1..3)
+            .take_while(|offset| !targets.contains(&(index + offset)))
+            .count()
+            + 1;
+        let Some((replacement, len)) = fold(&bytecode[index..], free) else {
+            index += 1;
+            continue;
+        };
+        for (offset, byte) in replacement.iter().enumerate() {
+            bytecode[index + offset] = byte.clone();
+        }
+        keep[index + replacement.len()..index + len].fill(false);
+        index += len;
+    }
+    if keep.iter().all(|kept| *kept) {
+        return false;
+    }
+    compact(code, &keep);
+    true
+}
@@ -0,0 +1,57 @@
+//! What the browser playground calls. Built for the web with
+//! `cargo build --lib --target wasm32-unknown-unknown --features playground` and
+//! bound with `wasm-bindgen`; without the feature it is plain Rust, for tests.
+
+#[cfg(feature = "playground")]
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    cli::{compile_named, render_error, render_message, SOURCE_FILE},
+    runtime::{
+        platform::{InMemory, SharedBuffer},
+        vm::ByteCodeMachine,
+    },
+};
+
+/// Compiles and runs `source`, returning what it printed.
+#[cfg_attr(feature = "playground", wasm_bindgen)]
+pub fn compile_and_run(source: &str) -> String {
+    compile_and_run_with_input(source, "")
+}
+
+/// Like [`compile_and_run`], with `input` as the program's stdin. Warnings come
+/// before the output and an error after it, rendered the way the CLI shows them.
+#[cfg_attr(feature = "playground", wasm_bindgen)]
+pub fn compile_and_run_with_input(source: &str, input: &str) -> String {
+    let mut output = String::new();
+    let code = match compile_named(source, SOURCE_FILE, 0, &Vec::new(), &Default::default()) {
+        Ok((code, messages)) => {
+            for message in messages.iter() {
+                output.push_str(&render_message(message, SOURCE_FILE, source));
+                output.push('\n');
+            }
+            code
+        }
+        Err(e) => return render_error(&e, SOURCE_FILE, source),
+    };
+    let buffer = SharedBuffer::default();
+    let mut machine = ByteCodeMachine::new(code);
+    machine.set_platform(Box::new(InMemory::new(input, Box::new(buffer.clone()))));
+    machine.run();
+    output.push_str(&buffer.contents());
+    if let Some(error) = machine.runtime_error() {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(error.to_string().trim_end());
+    }
+    output
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "playground"))]
+#[wasm_bindgen]
+extern "C" {
+    /// The browser's clock, as `std::time` has none on wasm.
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    pub(crate) fn date_now() -> f64;
+}
@@ -0,0 +1,332 @@
+//! Arbitrary-precision integers backing the `bigint` primitive.
+//!
+//! Sign-magnitude representation with little-endian base 10^9 limbs, which
+//! keeps parsing and printing trivial. Zero is always an empty, non-negative
+//! magnitude so the derived `Eq`/`Hash` agree with numeric equality.
+
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+const BASE: u64 = 1_000_000_000;
+const BASE_DIGITS: usize = 9;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt::default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    fn from_parts(negative: bool, mut limbs: Vec<u32>) -> Self {
+        trim(&mut limbs);
+        let negative = negative && !limbs.is_empty();
+        BigInt { negative, limbs }
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_parts(false, self.limbs.clone())
+    }
+
+    /// Converts back to an `i64` if the value fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        let mut magnitude: i128 = 0;
+        for limb in self.limbs.iter().rev() {
+            magnitude = magnitude.checked_mul(BASE as i128)? + *limb as i128;
+            if magnitude > i64::MAX as i128 + 1 {
+                return None;
+            }
+        }
+        let value = if self.negative { -magnitude } else { magnitude };
+        i64::try_from(value).ok()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, limb| acc * BASE as f64 + *limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Division truncating toward zero, like `i64`. `None` on division by zero.
+    pub fn checked_div(&self, rhs: &BigInt) -> Option<BigInt> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (quotient, _) = divrem_magnitude(&self.limbs, &rhs.limbs);
+        Some(BigInt::from_parts(self.negative != rhs.negative, quotient))
+    }
+
+    /// Remainder with the sign of the dividend, like `i64`. `None` on division by zero.
+    pub fn checked_rem(&self, rhs: &BigInt) -> Option<BigInt> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (_, remainder) = divrem_magnitude(&self.limbs, &rhs.limbs);
+        Some(BigInt::from_parts(self.negative, remainder))
+    }
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// `a - b`, requires `|a| >= |b|`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, digit) in a.iter().enumerate() {
+        let mut diff = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += BASE as i64;
+            borrow = 1;
+        }
+        result.push(diff as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut result = vec![0u64; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let current = result[i + j] + *x as u64 * *y as u64 + carry;
+            result[i + j] = current % BASE;
+            carry = current / BASE;
+        }
+        let mut k = i + b.len();
+        while carry > 0 {
+            let current = result[k] + carry;
+            result[k] = current % BASE;
+            carry = current / BASE;
+            k += 1;
+        }
+    }
+    let mut result: Vec<u32> = result.into_iter().map(|limb| limb as u32).collect();
+    trim(&mut result);
+    result
+}
+
+fn mul_small(a: &[u32], m: u64) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for limb in a {
+        let current = *limb as u64 * m + carry;
+        result.push((current % BASE) as u32);
+        carry = current / BASE;
+    }
+    while carry > 0 {
+        result.push((carry % BASE) as u32);
+        carry /= BASE;
+    }
+    trim(&mut result);
+    result
+}
+
+/// Schoolbook long division, one base-10^9 digit at a time. `b` must be non-zero.
+fn divrem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for i in (0..a.len()).rev() {
+        remainder.insert(0, a[i]);
+        trim(&mut remainder);
+        // Largest digit q with b * q <= remainder.
+        let (mut lo, mut hi) = (0u64, BASE - 1);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if cmp_magnitude(&mul_small(b, mid), &remainder) != Ordering::Greater {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        quotient[i] = lo as u32;
+        remainder = sub_magnitude(&remainder, &mul_small(b, lo));
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let mut magnitude = (value as i128).unsigned_abs();
+        let mut limbs = Vec::new();
+        while magnitude > 0 {
+            limbs.push((magnitude % BASE as u128) as u32);
+            magnitude /= BASE as u128;
+        }
+        BigInt::from_parts(value < 0, limbs)
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(anyhow!("Invalid bigint literal '{}'", s));
+        }
+        let bytes = digits.as_bytes();
+        let mut limbs = Vec::with_capacity(bytes.len() / BASE_DIGITS + 1);
+        let mut end = bytes.len();
+        while end > 0 {
+            let start = end.saturating_sub(BASE_DIGITS);
+            // Safe: the slice is all ASCII digits and at most 9 of them.
+            limbs.push(digits[start..end].parse::<u32>().unwrap());
+            end = start;
+        }
+        Ok(BigInt::from_parts(negative, limbs))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut limbs = self.limbs.iter().rev();
+        write!(f, "{}", limbs.next().unwrap())?;
+        for limb in limbs {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.limbs, &other.limbs),
+            (true, true) => cmp_magnitude(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> Self::Output {
+        BigInt::from_parts(!self.negative, self.limbs)
+    }
+}
+
+impl Add for BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        if self.negative == rhs.negative {
+            return BigInt::from_parts(self.negative, add_magnitude(&self.limbs, &rhs.limbs));
+        }
+        match cmp_magnitude(&self.limbs, &rhs.limbs) {
+            Ordering::Less => {
+                BigInt::from_parts(rhs.negative, sub_magnitude(&rhs.limbs, &self.limbs))
+            }
+            _ => BigInt::from_parts(self.negative, sub_magnitude(&self.limbs, &rhs.limbs)),
+        }
+    }
+}
+
+impl Sub for BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + (-rhs)
+    }
+}
+
+impl Mul for BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        BigInt::from_parts(
+            self.negative != rhs.negative,
+            mul_magnitude(&self.limbs, &rhs.limbs),
+        )
+    }
+}
+
+impl Div for BigInt {
+    type Output = BigInt;
+
+    /// Panics on division by zero, exactly like `i64`.
+    fn div(self, rhs: Self) -> Self::Output {
+        self.checked_div(&rhs).expect("attempt to divide by zero")
+    }
+}
+
+impl Rem for BigInt {
+    type Output = BigInt;
+
+    /// Panics on division by zero, exactly like `i64`.
+    fn rem(self, rhs: Self) -> Self::Output {
+        self.checked_rem(&rhs)
+            .expect("attempt to calculate the remainder with a divisor of zero")
+    }
+}
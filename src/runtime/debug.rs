@@ -1 +1,161 @@
+//! Diagnostics for runtime failures.
 
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use crate::{compiler::ByteCode, runtime::value::Value};
+
+/// How many values from the top of the operand stack a report shows.
+pub const REPORT_STACK_DEPTH: usize = 5;
+/// How many of the innermost frames a report prints; runaway recursion has thousands.
+pub const REPORT_FRAME_DEPTH: usize = 16;
+
+/// A place in the program's source, as `file:line:col`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// One active call, innermost first in [`RuntimeError::frames`].
+#[derive(Debug, Clone)]
+pub struct FrameReport {
+    pub function: String,
+    /// Instruction the frame was executing: the failing one for the innermost
+    /// frame, the call site for the others.
+    pub bytecode_index: usize,
+    pub instruction: Option<ByteCode>,
+    /// Statement that instruction was compiled from, when the code has line information.
+    pub source: Option<SourcePosition>,
+}
+
+/// What an uncaught error started as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// A `throw` no `try` caught.
+    Thrown,
+    /// An error raised by the VM itself, e.g. an index out of range.
+    Fault,
+    /// A failed `assert`, `assert_eq` or `fail`.
+    Assertion,
+    /// Running past the timeout or instruction budget of the VM's
+    /// [`VmConfig`](crate::runtime::sandbox::VmConfig).
+    ResourceLimitExceeded,
+}
+
+/// The error a `throw` raises while it unwinds, until it is caught or ends the program.
+#[derive(Debug, Clone, Error)]
+#[error("{0}")]
+pub(crate) struct Thrown(pub String);
+
+/// The error `exit(code)` raises to stop the program; no `try` catches it.
+#[derive(Debug, Clone, Error)]
+#[error("Program exited with code {0}")]
+pub(crate) struct Exit(pub i64);
+
+/// The error `assert`, `assert_eq` and `fail` raise; like `exit`, no `try` catches it,
+/// so a test stops at its first failure.
+#[derive(Debug, Clone, Error)]
+#[error("{0}")]
+pub(crate) struct AssertionFailed(pub String);
+
+/// The error that stops a program running past a limit of its VM; like `exit`, no
+/// `try` catches it.
+#[derive(Debug, Clone, Error)]
+#[error("Resource limit exceeded: {0}")]
+pub(crate) struct ResourceLimitExceeded(pub String);
+
+/// Everything known about an uncaught VM error at the point it happened.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub frames: Vec<FrameReport>,
+    /// Top of the operand stack, topmost first, as the report shows it; the values
+    /// themselves may not outlive the VM.
+    pub stack_top: Vec<String>,
+    /// Source line of the statement that failed, when its file could be read.
+    pub excerpt: Option<String>,
+}
+
+/// `value` as a report's stack listing shows it; strings are quoted.
+pub fn stack_entry(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        other => other.get_string_representation(),
+    }
+}
+
+impl RuntimeError {
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            RuntimeErrorKind::Thrown => "E0501",
+            RuntimeErrorKind::Fault => "E0502",
+            RuntimeErrorKind::Assertion => "E0503",
+            RuntimeErrorKind::ResourceLimitExceeded => "E0504",
+        }
+    }
+
+    /// Index of the instruction that failed.
+    pub fn bytecode_index(&self) -> Option<usize> {
+        self.frames.first().map(|frame| frame.bytecode_index)
+    }
+
+    /// Source position of the statement that failed.
+    pub fn source(&self) -> Option<&SourcePosition> {
+        self.frames.first().and_then(|frame| frame.source.as_ref())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "runtime error: {}", self.message)?;
+        if let (Some(source), Some(text)) = (self.source(), &self.excerpt) {
+            let gutter = " ".repeat(source.line.to_string().len());
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", source.line, text)?;
+            writeln!(
+                f,
+                "{} | {}^",
+                gutter,
+                " ".repeat(source.col.saturating_sub(1))
+            )?;
+        }
+        for frame in self.frames.iter().take(REPORT_FRAME_DEPTH) {
+            write!(f, "  at {}", frame.function)?;
+            if let Some(source) = &frame.source {
+                write!(f, " ({})", source)?;
+            }
+            write!(f, " [bytecode {}]", frame.bytecode_index)?;
+            match &frame.instruction {
+                Some(instruction) => writeln!(f, " {:?}", instruction)?,
+                None => writeln!(f)?,
+            }
+        }
+        if self.frames.len() > REPORT_FRAME_DEPTH {
+            writeln!(
+                f,
+                "  ... {} more frames",
+                self.frames.len() - REPORT_FRAME_DEPTH
+            )?;
+        }
+        if self.stack_top.is_empty() {
+            return writeln!(f, "stack: <empty>");
+        }
+        writeln!(f, "stack (top first):")?;
+        for (depth, value) in self.stack_top.iter().enumerate() {
+            writeln!(f, "  {}: {}", depth, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+//! Source-level stepping and line and function breakpoints on a [`ByteCodeMachine`],
+//! for `abra debug` and debug adapters.
+//!
+//! A statement starts wherever execution reaches an instruction compiled from a
+//! different statement than the one it just ran, so stepping and breakpoints work in
+//! lines while the machine still runs one instruction at a time.
+//!
+//! A breakpoint's condition is compiled, along with the program, into a function
+//! taking the variables it reads, typed as they are in scope at the breakpoint. It is
+//! called on a machine of its own with their values from the innermost call, so the
+//! functions it calls don't see the program's globals.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    compiler::{
+        modules::load_program,
+        typecheck::{TypeChecker, BOOL_TYPE},
+        ByteCode, Code, Compiler, SourceLocation,
+    },
+    frontend::{
+        ast::{Function, Item, Parameter, Statement},
+        parser::Parser,
+        span::{Located, Span},
+        tokenizer::{Token, TokenLiteral, Tokenizer},
+    },
+    runtime::{debug::stack_entry, value::Value, vm::ByteCodeMachine},
+};
+
+/// Name of the function a condition is compiled into.
+const CONDITION_FUNCTION: &str = "$condition";
+
+/// How far [`Debugger::resume`] runs before stopping again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Until a breakpoint.
+    Continue,
+    /// To the next statement, in whichever function it is.
+    StepIn,
+    /// To the next statement of this function or a caller.
+    StepOver,
+    /// To the next statement of a caller.
+    StepOut,
+}
+
+/// Why [`Debugger::resume`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint,
+    Step,
+    /// The program ended with this exit code; after an error it is 1 and
+    /// [`ByteCodeMachine::runtime_error`] says what went wrong.
+    Exited(usize),
+}
+
+pub struct Debugger {
+    machine: ByteCodeMachine,
+    /// `Code::locations` and `Code::files`.
+    locations: Vec<Option<SourceLocation>>,
+    /// Locations of function prologues, which aren't statements to stop at.
+    headers: HashSet<SourceLocation>,
+    files: Vec<String>,
+    /// Lines to stop at, by index into `files`.
+    breakpoints: HashMap<usize, HashSet<usize>>,
+    /// Address of each function and method, by name as in `main` or `Counter::bump`.
+    functions: HashMap<String, usize>,
+    /// Addresses of the functions to stop in.
+    function_breakpoints: HashSet<usize>,
+    /// Address of the function in `function_breakpoints` a call just started, to stop
+    /// at its first statement.
+    entered: Option<usize>,
+    /// Conditions of line breakpoints, by index into `files` and line.
+    line_conditions: HashMap<(usize, usize), Condition>,
+    /// Conditions of function breakpoints, by address.
+    function_conditions: HashMap<usize, Condition>,
+    /// Why the condition of the breakpoint last stopped at couldn't be evaluated.
+    condition_error: Option<String>,
+    /// Statement of the last instruction run that had one.
+    previous: Option<SourceLocation>,
+}
+
+impl Debugger {
+    /// Loads `code` to run under the debugger; it needs line information to stop
+    /// anywhere.
+    pub fn new(code: Code) -> Self {
+        let locations = code.locations.clone();
+        let files = code.files.clone();
+        // Binding the arguments is attributed to the function's own span.
+        let headers = code
+            .bytecode
+            .iter()
+            .zip(locations.iter())
+            .filter(|(instruction, _)| matches!(instruction, ByteCode::DEFARG(..)))
+            .filter_map(|(_, location)| *location)
+            .collect();
+        let functions = code
+            .functions
+            .iter()
+            .filter_map(|name| Some((name.clone(), *code.labels.get(name)?)))
+            .collect();
+        Debugger {
+            machine: ByteCodeMachine::new(code),
+            locations,
+            headers,
+            files,
+            breakpoints: HashMap::new(),
+            functions,
+            function_breakpoints: HashSet::new(),
+            entered: None,
+            line_conditions: HashMap::new(),
+            function_conditions: HashMap::new(),
+            condition_error: None,
+            previous: None,
+        }
+    }
+
+    pub fn machine(&self) -> &ByteCodeMachine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut ByteCodeMachine {
+        &mut self.machine
+    }
+
+    /// Replaces the breakpoints in `file` with ones at `lines`. Each moves down to the
+    /// next line with code on it; the line it ends up at is returned, or `None` when
+    /// no code follows. Lines that keep a breakpoint keep its condition.
+    pub fn set_breakpoints(&mut self, file: &str, lines: &[usize]) -> Vec<Option<usize>> {
+        let Some(index) = self.file_index(file) else {
+            return vec![None; lines.len()];
+        };
+        let mut with_code: Vec<usize> = (0..self.locations.len())
+            .filter_map(|instruction| self.location(instruction))
+            .filter(|location| location.file == index)
+            .map(|location| location.line)
+            .collect();
+        with_code.sort_unstable();
+        with_code.dedup();
+        let placed: Vec<Option<usize>> = lines
+            .iter()
+            .map(|line| {
+                let next = with_code.partition_point(|with_code| with_code < line);
+                with_code.get(next).copied()
+            })
+            .collect();
+        let lines: HashSet<usize> = placed.iter().flatten().copied().collect();
+        self.line_conditions
+            .retain(|(file, line), _| *file != index || lines.contains(line));
+        self.breakpoints.insert(index, lines);
+        placed
+    }
+
+    /// Makes the breakpoint at `line` of `file` stop only where `condition`, a `bool`
+    /// expression over the variables in scope there, holds; `None` has it stop every
+    /// time. Fails if the condition doesn't compile.
+    pub fn set_line_condition(
+        &mut self,
+        file: &str,
+        line: usize,
+        condition: Option<&str>,
+    ) -> Result<(), String> {
+        let index = self
+            .file_index(file)
+            .ok_or_else(|| format!("No file '{}' in the program", file))?;
+        let Some(condition) = condition else {
+            self.line_conditions.remove(&(index, line));
+            return Ok(());
+        };
+        let at = (0..self.locations.len())
+            .filter_map(|instruction| self.location(instruction))
+            .filter(|location| location.file == index && location.line == line)
+            .min_by_key(|location| location.col)
+            .ok_or_else(|| format!("No code on line {}", line))?;
+        let condition = self.compile_condition(at, condition)?;
+        self.line_conditions.insert((index, line), condition);
+        Ok(())
+    }
+
+    /// Replaces the function breakpoints with ones stopping at the first statement of
+    /// each of `names`. Where each stops is returned, or `None` for a name that isn't a
+    /// function of the program.
+    pub fn set_function_breakpoints(&mut self, names: &[String]) -> Vec<Option<SourceLocation>> {
+        self.function_breakpoints = names
+            .iter()
+            .filter_map(|name| self.functions.get(name).copied())
+            .collect();
+        let addresses = &self.function_breakpoints;
+        self.function_conditions
+            .retain(|address, _| addresses.contains(address));
+        names
+            .iter()
+            .map(|name| self.function_location(name))
+            .collect()
+    }
+
+    /// Makes the function breakpoint on `name` stop only where `condition` holds, as
+    /// [`Debugger::set_line_condition`] does for lines.
+    pub fn set_function_condition(
+        &mut self,
+        name: &str,
+        condition: Option<&str>,
+    ) -> Result<(), String> {
+        let address = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| format!("No function '{}' in the program", name))?;
+        let Some(condition) = condition else {
+            self.function_conditions.remove(&address);
+            return Ok(());
+        };
+        let at = self
+            .function_location(name)
+            .ok_or_else(|| format!("No code in '{}'", name))?;
+        let condition = self.compile_condition(at, condition)?;
+        self.function_conditions.insert(address, condition);
+        Ok(())
+    }
+
+    /// Why the condition of the breakpoint just stopped at couldn't be evaluated;
+    /// such a breakpoint stops as if the condition held.
+    pub fn take_condition_error(&mut self) -> Option<String> {
+        self.condition_error.take()
+    }
+
+    /// Compiles `expression` to be evaluated at the statement `at`.
+    fn compile_condition(&self, at: SourceLocation, expression: &str) -> Result<Condition, String> {
+        let entry = self
+            .files
+            .first()
+            .ok_or("The program has no line information")?;
+        let source = std::fs::read_to_string(entry)
+            .map_err(|e| format!("Failed to read '{}': {}", entry, e))?;
+        let program = load_program(entry, &source).map_err(|e| e.to_string())?;
+        let file = &self.files[at.file];
+        let offset = program
+            .sources
+            .iter()
+            .find(|(name, _)| name == file)
+            .map_or(0, |(_, text)| byte_offset(text, at.line, at.col));
+        let mut checker = TypeChecker::new(&program.items).record_scopes();
+        checker.check();
+        let scope = checker.scope_at(file, offset).cloned().unwrap_or_default();
+
+        // Statements end with a line.
+        let expression = &format!("{}\n", expression.trim());
+        let mut parser = Parser::new(Tokenizer::new(expression)).with_source(expression);
+        let body = parser.parse_statements().map_err(|e| e.to_string())?;
+        let [Located {
+            node: Statement::Expression(condition),
+            span,
+        }] = body.as_slice()
+        else {
+            return Err("A condition is a single expression".to_string());
+        };
+        let mut variables: Vec<String> = Vec::new();
+        for token in Tokenizer::new(expression).flatten() {
+            if let (_, Token::Literal(TokenLiteral::Identifier(name)), _) = token {
+                if scope.contains_key(&name) && !variables.contains(&name) {
+                    variables.push(name);
+                }
+            }
+        }
+        let function = Function {
+            name: CONDITION_FUNCTION.to_string(),
+            type_params: Vec::new(),
+            params: variables
+                .iter()
+                .map(|name| Parameter::new(name.clone(), scope[name].0.clone()))
+                .collect(),
+            return_type: BOOL_TYPE,
+            body: vec![Located::new(
+                Statement::Return(Some(condition.clone())),
+                span.clone(),
+            )],
+            span: Span::default(),
+        };
+        let mut items = program.items;
+        items.push(Item::Function(function));
+        let mut compiler = Compiler::new();
+        compiler.set_library(true);
+        compiler
+            .compilation_pipepline(items)
+            .map_err(|e| e.to_string())?;
+        Ok(Condition {
+            machine: ByteCodeMachine::new(compiler.into()),
+            variables,
+        })
+    }
+
+    /// First statement of the function or method `name`.
+    pub fn function_location(&self, name: &str) -> Option<SourceLocation> {
+        let address = *self.functions.get(name)?;
+        (address..self.locations.len()).find_map(|instruction| self.location(instruction))
+    }
+
+    /// Files the program was compiled from, which `SourceLocation::file` indexes.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Index into [`Debugger::files`] of `file`, given as compiled or by another path
+    /// to it.
+    pub fn file_index(&self, file: &str) -> Option<usize> {
+        let canonical = std::fs::canonicalize(file).ok();
+        self.files.iter().position(|known| {
+            known == file || canonical.is_some() && std::fs::canonicalize(known).ok() == canonical
+        })
+    }
+
+    /// Runs until the statement `mode` asks for, a breakpoint or the end of the program.
+    pub fn resume(&mut self, mode: Resume) -> Stop {
+        let depth = self.machine.call_depth();
+        loop {
+            if let Some(location) = self.location(self.machine.pc()) {
+                self.previous = Some(location);
+            }
+            if let Some(exit_code) = self.machine.run_instruction() {
+                return Stop::Exited(exit_code);
+            }
+            if self.function_breakpoints.contains(&self.machine.pc()) {
+                self.entered = Some(self.machine.pc());
+            }
+            let Some(here) = self.location(self.machine.pc()) else {
+                continue;
+            };
+            if self.previous == Some(here) {
+                continue;
+            }
+            let on_line = self
+                .breakpoints
+                .get(&here.file)
+                .is_some_and(|lines| lines.contains(&here.line))
+                && condition_met(
+                    &self.machine,
+                    self.line_conditions.get_mut(&(here.file, here.line)),
+                    &mut self.condition_error,
+                );
+            let on_entry = self.entered.take().is_some_and(|address| {
+                condition_met(
+                    &self.machine,
+                    self.function_conditions.get_mut(&address),
+                    &mut self.condition_error,
+                )
+            });
+            if on_line || on_entry {
+                return Stop::Breakpoint;
+            }
+            let now = self.machine.call_depth();
+            match mode {
+                Resume::StepIn => return Stop::Step,
+                Resume::StepOver if now <= depth => return Stop::Step,
+                Resume::StepOut if now < depth => return Stop::Step,
+                _ => {}
+            }
+        }
+    }
+
+    /// Statement the program is stopped at.
+    pub fn current_location(&self) -> Option<SourceLocation> {
+        self.location(self.machine.pc())
+    }
+
+    /// Statement instruction `index` was compiled from.
+    fn location(&self, index: usize) -> Option<SourceLocation> {
+        let location = self.locations.get(index).copied().flatten()?;
+        (!self.headers.contains(&location)).then_some(location)
+    }
+}
+
+/// A breakpoint's condition, compiled into a function of the variables it reads.
+struct Condition {
+    /// The program along with the function, to call it on.
+    machine: ByteCodeMachine,
+    /// Variables the function takes, in order.
+    variables: Vec<String>,
+}
+
+/// Whether the breakpoint with `condition`, if it has one, stops in `machine`'s
+/// innermost call. A condition that fails stops it too, with the error in `error`.
+fn condition_met(
+    machine: &ByteCodeMachine,
+    condition: Option<&mut Condition>,
+    error: &mut Option<String>,
+) -> bool {
+    let Some(condition) = condition else {
+        return true;
+    };
+    let locals = machine.frame_locals(0);
+    let globals = machine.globals();
+    let args: Result<Vec<Value>, String> = condition
+        .variables
+        .iter()
+        .map(|name| {
+            locals
+                .iter()
+                .chain(globals.iter())
+                .find(|(variable, _)| variable == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| format!("'{}' has no value here", name))
+        })
+        .collect();
+    let outcome = match args.map(|args| condition.machine.call_function(CONDITION_FUNCTION, args)) {
+        Ok(Ok(Value::Bool(holds))) => Ok(holds),
+        Ok(Ok(other)) => Err(format!("Expected a bool, got {}", stack_entry(&other))),
+        // Without the backtrace, which only shows the condition's own function.
+        Ok(Err(e)) => Err(e.to_string().lines().next().unwrap_or_default().to_string()),
+        Err(e) => Err(e),
+    };
+    outcome.unwrap_or_else(|e| {
+        *error = Some(e);
+        true
+    })
+}
+
+/// Byte offset of 1-based `line` and `col` in `text`.
+fn byte_offset(text: &str, line: usize, col: usize) -> usize {
+    let start: usize = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    start + col.saturating_sub(1)
+}
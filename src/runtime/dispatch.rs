@@ -0,0 +1,59 @@
+//! Table dispatch for the VM, behind the `table-dispatch` feature.
+//!
+//! An experiment against the `match` in `ByteCodeMachine::next`: at load time
+//! each of the hot instructions is decoded into a handler function and a plain
+//! `usize` operand, so running it is one indirect call with no enum matching or
+//! operand conversion. Instructions without a handler decode to `None` and fall
+//! back to the `match`. Compare the two with
+//! `cargo bench --features bench` and `cargo bench --features bench,table-dispatch`.
+
+use std::rc::Rc;
+
+use crate::compiler::{BinaryOp, ByteCode};
+
+use super::vm::ByteCodeMachine;
+
+/// Runs one decoded instruction, same contract as `ByteCodeMachine::next`.
+pub(crate) type Handler = fn(&mut ByteCodeMachine, usize) -> anyhow::Result<bool>;
+
+/// A handler and its operand: a constant, a slot or a jump target.
+pub(crate) type Decoded = (Handler, usize);
+
+/// Decodes the program, one entry per instruction.
+pub(crate) fn decode(bytecode: &[ByteCode]) -> Rc<[Option<Decoded>]> {
+    bytecode
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| decode_one(index, byte))
+        .collect()
+}
+
+fn decode_one(index: usize, byte: &ByteCode) -> Option<Decoded> {
+    let decoded: Decoded = match byte {
+        ByteCode::PUSH(constant) => (ByteCodeMachine::push_constant, *constant),
+        ByteCode::ADD => (|vm, _| vm.stack_binary(BinaryOp::Add), 0),
+        ByteCode::SUB => (|vm, _| vm.stack_binary(BinaryOp::Sub), 0),
+        ByteCode::MULT => (|vm, _| vm.stack_binary(BinaryOp::Mult), 0),
+        ByteCode::DIV => (|vm, _| vm.stack_binary(BinaryOp::Div), 0),
+        ByteCode::MOD => (|vm, _| vm.stack_binary(BinaryOp::Mod), 0),
+        ByteCode::NEGATE => (|vm, _| vm.negate(), 0),
+        ByteCode::NOT => (|vm, _| vm.not(), 0),
+        ByteCode::BAND => (|vm, _| vm.bitwise(|a, b| Ok(a & b)), 0),
+        ByteCode::BOR => (|vm, _| vm.bitwise(|a, b| Ok(a | b)), 0),
+        ByteCode::BXOR => (|vm, _| vm.bitwise(|a, b| Ok(a ^ b)), 0),
+        ByteCode::BNOT => (|vm, _| vm.bitwise_not(), 0),
+        ByteCode::EQGREAT => (|vm, _| vm.stack_binary(BinaryOp::EqGreat), 0),
+        ByteCode::EQLESS => (|vm, _| vm.stack_binary(BinaryOp::EqLess), 0),
+        ByteCode::GREATER => (|vm, _| vm.stack_binary(BinaryOp::Greater), 0),
+        ByteCode::LESSER => (|vm, _| vm.stack_binary(BinaryOp::Lesser), 0),
+        ByteCode::JMPABS(target) => (ByteCodeMachine::jump, *target as usize),
+        ByteCode::JMPREL(offset) => (ByteCodeMachine::jump, (index as i64 + offset) as usize),
+        ByteCode::JITA(target) => (ByteCodeMachine::jump_if, *target as usize),
+        ByteCode::JITR(offset) => (ByteCodeMachine::jump_if, (index as i64 + offset) as usize),
+        ByteCode::LOADSLOT(slot) => (ByteCodeMachine::load_slot, *slot as usize),
+        ByteCode::STORESLOT(slot) => (ByteCodeMachine::store_slot, *slot as usize),
+        ByteCode::DROPSLOT(slot) => (ByteCodeMachine::drop_slot, *slot as usize),
+        _ => return None,
+    };
+    Some(decoded)
+}
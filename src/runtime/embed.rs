@@ -0,0 +1,85 @@
+//! The VM as other crates embed it.
+//!
+//! ```
+//! let source = "func double(x: int) -> int\n    return x * 2\n\nfunc main() -> int\n    return 0\n";
+//! let code = abra_lang::compile_source(source)?;
+//! let mut vm = abra_lang::Vm::new(code);
+//! let doubled: i64 = vm.call_function("double", vec![21.into()])?.try_into()?;
+//! assert_eq!(doubled, 42);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    compiler::{typecheck::Type, Code},
+    errors::{AbraError, EmbedError},
+    runtime::{inbuilt::Natives, sandbox::VmConfig, value::Value, vm::ByteCodeMachine},
+};
+
+/// A compiled program loaded into a virtual machine.
+///
+/// Errors from running it are [`AbraError::Runtime`]s carrying the failing line and
+/// call stack. Objects the host holds on to aren't
+/// garbage collection roots: keep them reachable from the program, or don't use them
+/// after the program has run again.
+pub struct Vm {
+    machine: ByteCodeMachine,
+}
+
+impl Vm {
+    pub fn new(code: Code) -> Self {
+        Vm {
+            machine: ByteCodeMachine::new(code),
+        }
+    }
+
+    /// Runs the program from `main` and returns its exit code.
+    pub fn run(&mut self) -> Result<usize, AbraError> {
+        let exit_code = self.machine.run();
+        match self.machine.runtime_error() {
+            Some(error) => Err(error.clone().into()),
+            None => Ok(exit_code),
+        }
+    }
+
+    /// Calls a top-level function of the program and returns its result (`null` for
+    /// functions without one). Works before, after or without `run`.
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, AbraError> {
+        self.machine.call_function(name, args)
+    }
+
+    /// Restricts what the program may do, e.g. to [`VmConfig::sandboxed`] for a script
+    /// the host doesn't trust.
+    pub fn set_config(&mut self, config: VmConfig) {
+        self.machine.set_config(config);
+    }
+
+    /// Makes the host function `body` callable from the program as `name`; `signature`
+    /// is its function type. The program must have been compiled against it, e.g. with
+    /// [`compile_source_with`](crate::compile_source_with).
+    pub fn register_native<F>(
+        &mut self,
+        name: &str,
+        signature: Type,
+        body: F,
+    ) -> Result<(), EmbedError>
+    where
+        F: Fn(&mut ByteCodeMachine, Vec<Value>) -> anyhow::Result<Value> + 'static,
+    {
+        self.machine.register_native(name, signature, body)
+    }
+
+    /// Registers every host function the program was compiled against.
+    pub fn register_natives(&mut self, natives: &Natives) -> Result<(), EmbedError> {
+        self.machine.register_natives(natives)
+    }
+
+    /// An array the program sees as `[element_type]`.
+    pub fn array(&mut self, element_type: Type, values: Vec<Value>) -> Value {
+        Value::Ref(self.machine.instance(Type::array(element_type), values))
+    }
+
+    /// The machine itself, e.g. to redirect the program's output.
+    pub fn machine(&mut self) -> &mut ByteCodeMachine {
+        &mut self.machine
+    }
+}
@@ -0,0 +1,132 @@
+//! Tracing garbage collection for `Ref` objects.
+//!
+//! Objects are still reference counted, which frees most of them as soon as the
+//! last reference goes away, but a cycle (an object pointing at itself, two
+//! objects pointing at each other) keeps its own count up forever. The heap keeps
+//! a weak handle to every object it allocates; a collection marks everything
+//! reachable from the VM's roots and clears the rest, which breaks the cycles so
+//! reference counting can free them.
+//!
+//! The heap also keeps an estimate of the bytes its live objects take, see
+//! [`RefHeader::size`], for the VM to hold the program to a memory limit.
+
+use std::collections::HashSet;
+
+use super::{
+    object::{Ref, RefHeader, WeakRef},
+    value::Value,
+};
+
+/// Allocations between automatic collections while few objects are alive.
+pub const DEFAULT_GC_THRESHOLD: usize = 10_000;
+
+pub struct Heap {
+    objects: Vec<WeakRef>,
+    /// Allocations since the last collection.
+    allocations: usize,
+    threshold: usize,
+    min_threshold: usize,
+    /// Estimated bytes of the objects alive at the last measurement, plus those
+    /// allocated since.
+    bytes: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            objects: Vec::new(),
+            allocations: 0,
+            threshold: DEFAULT_GC_THRESHOLD,
+            min_threshold: DEFAULT_GC_THRESHOLD,
+            bytes: 0,
+        }
+    }
+}
+
+impl Heap {
+    pub fn allocate(&mut self, header: RefHeader) -> Ref {
+        self.bytes += header.size();
+        let rf = Ref::instance_with(std::rc::Rc::new(std::sync::Mutex::new(header)));
+        self.objects.push(rf.downgrade());
+        self.allocations += 1;
+        rf
+    }
+
+    /// Whether enough has been allocated since the last collection to run another.
+    pub fn wants_collection(&self) -> bool {
+        self.allocations >= self.threshold
+    }
+
+    /// Collects after `allocations` allocations, or more once many objects stay alive.
+    pub fn set_threshold(&mut self, allocations: usize) {
+        self.min_threshold = allocations.max(1);
+        self.threshold = self.min_threshold;
+    }
+
+    /// Objects allocated here that haven't been freed yet.
+    pub fn live_objects(&self) -> usize {
+        self.objects
+            .iter()
+            .filter(|object| object.upgrade().is_some())
+            .count()
+    }
+
+    /// Estimated bytes taken by the live objects. Arrays and maps growing in place
+    /// only show up after the next [`Heap::measure`].
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Objects allocated here, counting freed ones until the next collection or
+    /// measurement forgets them.
+    pub fn objects(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Forgets the freed objects and sizes the live ones again; returns their bytes.
+    pub fn measure(&mut self) -> usize {
+        let mut bytes = 0;
+        self.objects.retain(|object| match object.upgrade() {
+            Some(rf) => {
+                bytes += rf.size();
+                true
+            }
+            None => false,
+        });
+        self.bytes = bytes;
+        bytes
+    }
+
+    /// Clears every object not reachable from `roots` and returns how many there were.
+    pub fn collect<'a>(&mut self, roots: impl IntoIterator<Item = &'a Value>) -> usize {
+        let mut marked = HashSet::new();
+        let mut pending: Vec<Value> = roots.into_iter().cloned().collect();
+        while let Some(value) = pending.pop() {
+            if let Value::Ref(rf) = value {
+                if marked.insert(rf.get_uuid()) {
+                    pending.extend(rf.children());
+                }
+            }
+        }
+
+        // Hold on to all garbage first: clearing one object may drop the last
+        // reference to another before the sweep gets to it.
+        let mut garbage = Vec::new();
+        self.objects.retain(|object| match object.upgrade() {
+            Some(rf) if marked.contains(&rf.get_uuid()) => true,
+            Some(rf) => {
+                garbage.push(rf);
+                false
+            }
+            None => false,
+        });
+        for rf in garbage.iter() {
+            rf.delete();
+        }
+        self.allocations = 0;
+        // Survivors are likely to survive again; don't rescan them too often.
+        self.threshold = self.min_threshold.max(self.objects.len() * 2);
+        self.measure();
+        garbage.len()
+    }
+}
@@ -1,15 +1,101 @@
 use crate::{
     compiler::typecheck::{
-        Composite, FunctionSignature, Primitives, Type, FLOAT_TYPE, INTEGER_TYPE, STRING_TYPE,
+        Composite, FunctionSignature, SignatureProvider, Type, BOOL_TYPE, CHAR_TYPE, FLOAT_TYPE,
+        INTEGER_TYPE, STRING_TYPE,
+    },
+    errors::EmbedError,
+    runtime::{
+        debug::{AssertionFailed, Exit},
+        json,
+        object::array_size,
+        random::{int_in_range, unit_float},
+        time::format_time,
+        value::{StaticValue, Value},
+        vm::ByteCodeMachine,
     },
-    runtime::{value::Value, vm::ByteCodeMachine},
 };
 use anyhow::*;
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{Seek, SeekFrom, Write},
+    rc::Rc,
+};
 
 pub type InbuiltFuncBody = Rc<dyn Fn(&mut ByteCodeMachine, u64) -> anyhow::Result<()>>;
 pub type CompleteInbuiltFuncBody = (FunctionSignature, InbuiltFuncBody);
 pub type InbuiltFuncMap = HashMap<String, CompleteInbuiltFuncBody>;
+/// A function a host embedding the VM provides: gets the arguments in call order
+/// and returns the result, which is discarded when the signature returns nothing.
+pub type NativeFn = Rc<dyn Fn(&mut ByteCodeMachine, Vec<Value>) -> anyhow::Result<Value>>;
+
+/// Host functions to register with every VM that runs a program, and to compile
+/// that program against.
+#[derive(Clone, Default)]
+pub struct Natives(Vec<(FunctionSignature, NativeFn)>);
+
+impl Natives {
+    /// Adds `name` with the parameter and return types of the function type `signature`.
+    pub fn register<F>(
+        &mut self,
+        name: &str,
+        signature: Type,
+        body: F,
+    ) -> Result<&mut Self, EmbedError>
+    where
+        F: Fn(&mut ByteCodeMachine, Vec<Value>) -> anyhow::Result<Value> + 'static,
+    {
+        let signature = native_signature(name, signature)?;
+        if self.0.iter().any(|(other, _)| other.name() == name) {
+            return Err(EmbedError::DuplicateNative(name.to_string()));
+        }
+        self.0.push((signature, Rc::new(body)));
+        Result::Ok(self)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(FunctionSignature, NativeFn)> {
+        self.0.iter()
+    }
+}
+
+impl SignatureProvider for Natives {
+    fn signatures(&self) -> Vec<FunctionSignature> {
+        self.0
+            .iter()
+            .map(|(signature, _)| signature.clone())
+            .collect()
+    }
+}
+
+/// The signature of a native function `name` of type `signature`.
+pub fn native_signature(name: &str, signature: Type) -> Result<FunctionSignature, EmbedError> {
+    match signature {
+        Type::Function(params, return_type) => {
+            Result::Ok(FunctionSignature::new(name.into(), params, *return_type))
+        }
+        other => Err(EmbedError::NotAFunctionType(
+            name.to_string(),
+            other.to_string(),
+        )),
+    }
+}
+
+/// Adapts a native function to the calling convention of the builtins.
+pub fn native_body(signature: &FunctionSignature, body: NativeFn) -> InbuiltFuncBody {
+    let name = signature.name().to_string();
+    let arity = signature.parameters().len() as u64;
+    let returns = *signature.return_type() != Type::Null;
+    Rc::new(move |state, argc| {
+        check_argc(&name, argc, arity)?;
+        let args = pop_args(state, argc)?;
+        let result = body(state, args)?;
+        if returns {
+            state.push_to_stack(&result)?;
+        }
+        Ok(())
+    })
+}
 
 struct FuncStore(InbuiltFuncMap);
 
@@ -31,76 +117,1074 @@ impl FuncStore {
         self
     }
 
+    /// Like `func_gen`, for math functions generic over the type variable `N`, which
+    /// only accepts numbers.
+    fn bounded_func_gen<T: Into<String>>(
+        mut self,
+        name: T,
+        params: Vec<Type>,
+        return_type: Type,
+        functor: fn(&mut ByteCodeMachine, u64) -> anyhow::Result<()>,
+    ) -> FuncStore {
+        let name = name.into();
+        let f = FunctionSignature::new(name.clone(), params, return_type)
+            .with_bound("N", number_type());
+        self.0.insert(name, (f, Rc::new(functor)));
+        self
+    }
+
+    /// Like `func_gen`, for functions that order the values of the element type `T`,
+    /// which only accepts types `<` can compare.
+    fn ordered_func_gen<T: Into<String>>(
+        mut self,
+        name: T,
+        params: Vec<Type>,
+        return_type: Type,
+        functor: fn(&mut ByteCodeMachine, u64) -> anyhow::Result<()>,
+    ) -> FuncStore {
+        let name = name.into();
+        let f = FunctionSignature::new(name.clone(), params, return_type).with_ordered("T");
+        self.0.insert(name, (f, Rc::new(functor)));
+        self
+    }
+
     fn finalize(self) -> InbuiltFuncMap {
         self.0
     }
 }
 
+fn check_argc(name: &str, argc: u64, expected: u64) -> Result<()> {
+    if argc != expected {
+        bail!(
+            "Wrong amount of arguments for {}! expected {}, got {}",
+            name,
+            expected,
+            argc
+        );
+    }
+    Ok(())
+}
+
+/// An empty vector with room for `len` values, or `None` where there is no memory
+/// for them, rather than the process aborting.
+fn reserved(len: usize) -> Option<Vec<Value>> {
+    let mut values = Vec::new();
+    values.try_reserve_exact(len).ok()?;
+    Some(values)
+}
+
+/// Pops `argc` arguments and returns them in call order.
+fn pop_args(state: &mut ByteCodeMachine, argc: u64) -> Result<Vec<Value>> {
+    let mut args = Vec::with_capacity(argc as usize);
+    for _ in 0..argc {
+        args.push(state.pop_from_stack()?);
+    }
+    args.reverse();
+    Ok(args)
+}
+
+/// The next line of stdin for the builtin `name`; running out of input is an error.
+fn input_line(state: &mut ByteCodeMachine, name: &str) -> Result<String> {
+    state
+        .stdin_line()?
+        .ok_or_else(|| anyhow!("{}() reached the end of stdin", name))
+}
+
+/// Values the predefined names stand for, e.g. `PI`; a variable of the same name
+/// hides them.
+pub fn inbuilt_constant(name: &str) -> Option<StaticValue> {
+    match name {
+        "PI" => Some(StaticValue::Float(std::f64::consts::PI.into())),
+        "E" => Some(StaticValue::Float(std::f64::consts::E.into())),
+        _ => None,
+    }
+}
+
+/// Parameter type of the math builtins, which take integers and floats alike.
+fn number_type() -> Type {
+    Type::or(INTEGER_TYPE, FLOAT_TYPE)
+}
+
+fn float_value(name: &str, value: Value) -> Result<f64> {
+    match value {
+        Value::Integer(i) => Ok(i as f64),
+        Value::Float(f) => Ok(f.into_inner()),
+        other => bail!("{}() expects a number, got {}", name, other),
+    }
+}
+
+/// Applies `f` to the number argument of the builtin `name`, giving a float.
+fn float_function(
+    state: &mut ByteCodeMachine,
+    argc: u64,
+    name: &str,
+    f: fn(f64) -> f64,
+) -> Result<()> {
+    check_argc(name, argc, 1)?;
+    let x = float_value(name, state.pop_from_stack()?)?;
+    state.push_to_stack(&f(x).into())?;
+    Ok(())
+}
+
+/// Rounds the number argument of the builtin `name` with `f`, giving an integer.
+fn integer_function(
+    state: &mut ByteCodeMachine,
+    argc: u64,
+    name: &str,
+    f: fn(f64) -> f64,
+) -> Result<()> {
+    check_argc(name, argc, 1)?;
+    let rounded = match state.pop_from_stack()? {
+        Value::Integer(i) => i,
+        value => {
+            let rounded = f(float_value(name, value)?);
+            if !rounded.is_finite() || rounded.abs() >= i64::MAX as f64 {
+                bail!("{}() result {} doesn't fit in an integer", name, rounded);
+            }
+            rounded as i64
+        }
+    };
+    state.push_to_stack(&Value::Integer(rounded))?;
+    Ok(())
+}
+
+/// The argument of `min` (`wanted` less) or `max` (`wanted` greater); the first
+/// one when they're equal.
+fn extremum(state: &mut ByteCodeMachine, argc: u64, name: &str, wanted: Ordering) -> Result<()> {
+    check_argc(name, argc, 2)?;
+    let mut args = pop_args(state, argc)?.into_iter();
+    let (a, b) = (args.next().unwrap(), args.next().unwrap());
+    let ordering = state
+        .compare_values(&b, &a)?
+        .ok_or_else(|| anyhow!("{}() cannot compare {} and {}", name, a, b))?;
+    state.push_to_stack(if ordering == wanted { &b } else { &a })?;
+    Ok(())
+}
+
+/// Byte buffers are plain `[int]` arrays holding values in `0..=255`.
+fn byte_array_type() -> Type {
+    Type::Composite(Box::new(Composite::Array(INTEGER_TYPE)))
+}
+
+fn bytes_from_value(value: &Value) -> Result<Vec<u8>> {
+    value
+        .expect_ref()?
+        .array_values()?
+        .iter()
+        .map(|v| {
+            let byte = v.expect_int()?;
+            u8::try_from(byte).map_err(|_| anyhow!("byte value {} out of range 0..=255", byte))
+        })
+        .collect()
+}
+
+/// Element type of the array builtins, bound per call from the array argument.
+fn element_type() -> Type {
+    Type::var("T")
+}
+
+/// `value` as `print` shows it, but with strings in quotes, so `1` and `"1"` differ.
+fn quoted(state: &mut ByteCodeMachine, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(format!("{:?}", s)),
+        other => state.display_value(other),
+    }
+}
+
+fn element_array_type() -> Type {
+    Type::array(element_type())
+}
+
+/// `index` as a position in an array of length `len`; `len` itself is allowed when
+/// `inclusive`, for inserting at the end.
+fn array_position(name: &str, index: i64, len: usize, inclusive: bool) -> Result<usize> {
+    let end = if inclusive { len + 1 } else { len };
+    match usize::try_from(index) {
+        Result::Ok(position) if position < end => Ok(position),
+        _ => bail!(
+            "{} index {} out of range for array of length {}",
+            name,
+            index,
+            len
+        ),
+    }
+}
+
+/// Stable merge sort through the VM, since comparisons may run user `compare` methods.
+fn sort_values(state: &mut ByteCodeMachine, values: Vec<Value>) -> Result<Vec<Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let mut left = values;
+    let right = left.split_off(left.len() / 2);
+    let left = sort_values(state, left)?;
+    let right = sort_values(state, right)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let ordering = state
+            .compare_values(r, l)?
+            .ok_or_else(|| anyhow!("Cannot compare {} and {}", r, l))?;
+        if ordering.is_lt() {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
 pub fn generate_inbuilt_function_hashmap() -> InbuiltFuncMap {
     FuncStore::new()
-        .func_gen("print", vec![Type::Null], Type::Null, |state, argc| {
-            if argc != 1 {
-                return Err(anyhow!("Wrong amount of of arguments for print!"));
-            }
+        .func_gen("print", vec![element_type()], Type::Null, |state, argc| {
+            check_argc("print", argc, 1)?;
             let arg0 = state.pop_from_stack()?;
-            print!("{}", arg0);
+            let text = state.display_value(&arg0)?;
+            write!(state.stdout(), "{}", text)?;
             Ok(())
         })
         .func_gen(
-            "sqrt",
-            vec![Type::or(FLOAT_TYPE, INTEGER_TYPE)],
+            "println",
+            vec![element_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("println", argc, 1)?;
+                let arg0 = state.pop_from_stack()?;
+                let text = state.display_value(&arg0)?;
+                writeln!(state.stdout(), "{}", text)?;
+                Ok(())
+            },
+        )
+        .func_gen("sqrt", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "sqrt", f64::sqrt)
+        })
+        .func_gen("exp", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "exp", f64::exp)
+        })
+        .func_gen("sin", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "sin", f64::sin)
+        })
+        .func_gen("cos", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "cos", f64::cos)
+        })
+        .func_gen("tan", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "tan", f64::tan)
+        })
+        .func_gen("log", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "log", f64::ln)
+        })
+        .func_gen("log10", vec![number_type()], FLOAT_TYPE, |state, argc| {
+            float_function(state, argc, "log10", f64::log10)
+        })
+        .func_gen(
+            "pow",
+            vec![number_type(), number_type()],
             FLOAT_TYPE,
             |state, argc| {
-                if argc != 1 {
-                    return Err(anyhow!("Wrong amount of of arguments for print!"));
+                check_argc("pow", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let base = float_value("pow", args.next().unwrap())?;
+                let exponent = float_value("pow", args.next().unwrap())?;
+                state.push_to_stack(&base.powf(exponent).into())?;
+                Ok(())
+            },
+        )
+        .func_gen("floor", vec![number_type()], INTEGER_TYPE, |state, argc| {
+            integer_function(state, argc, "floor", f64::floor)
+        })
+        .func_gen("ceil", vec![number_type()], INTEGER_TYPE, |state, argc| {
+            integer_function(state, argc, "ceil", f64::ceil)
+        })
+        .func_gen("round", vec![number_type()], INTEGER_TYPE, |state, argc| {
+            integer_function(state, argc, "round", f64::round)
+        })
+        .bounded_func_gen(
+            "abs",
+            vec![Type::var("N")],
+            Type::var("N"),
+            |state, argc| {
+                check_argc("abs", argc, 1)?;
+                let result = match state.pop_from_stack()? {
+                    Value::Integer(i) => Value::Integer(
+                        i.checked_abs()
+                            .ok_or_else(|| anyhow!("abs() of {} overflows", i))?,
+                    ),
+                    Value::Float(f) => f.abs().into(),
+                    other => bail!("abs() expects a number, got {}", other),
+                };
+                state.push_to_stack(&result)?;
+                Ok(())
+            },
+        )
+        .bounded_func_gen(
+            "min",
+            vec![Type::var("N"), Type::var("N")],
+            Type::var("N"),
+            |state, argc| extremum(state, argc, "min", Ordering::Less),
+        )
+        .bounded_func_gen(
+            "max",
+            vec![Type::var("N"), Type::var("N")],
+            Type::var("N"),
+            |state, argc| extremum(state, argc, "max", Ordering::Greater),
+        )
+        .func_gen("random", vec![], FLOAT_TYPE, |state, argc| {
+            check_argc("random", argc, 0)?;
+            let bits = state.random_bits()?;
+            state.push_to_stack(&unit_float(bits).into())?;
+            Ok(())
+        })
+        .func_gen(
+            "random_int",
+            vec![INTEGER_TYPE, INTEGER_TYPE],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("random_int", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let lo = args.next().unwrap().expect_int()?;
+                let hi = args.next().unwrap().expect_int()?;
+                if lo > hi {
+                    bail!("random_int() range {}..{} is empty", lo, hi);
                 }
-                let arg0 = state.pop_from_stack()?;
-                match arg0 {
-                    Value::Null => bail!("Wrong type of argument provided: Null"),
-                    Value::Integer(i) => {
-                        state.push_to_stack(&Value::Integer(f64::sqrt(i as f64) as i64))?;
-                    }
-                    Value::Float(f) => {
-                        state.push_to_stack(&f.sqrt().into())?;
-                    }
-                    Value::Char(_) => bail!("Wrong type of argument provided: Char"),
-                    Value::Bool(_) => bail!("Wrong type of argument provided: Bool"),
-                    Value::String(_) => bail!("Wrong type of argument provided: String"),
-                    Value::Ref(_) => bail!("Wrong type of argument provided: Ref"),
+                let bits = state.random_bits()?;
+                state.push_to_stack(&Value::Integer(int_in_range(bits, lo, hi)))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "random_seed",
+            vec![INTEGER_TYPE],
+            Type::Null,
+            |state, argc| {
+                check_argc("random_seed", argc, 1)?;
+                let seed = state.pop_from_stack()?.expect_int()?;
+                state.set_random_seed(seed as u64);
+                Ok(())
+            },
+        )
+        .func_gen("now_millis", vec![], INTEGER_TYPE, |state, argc| {
+            check_argc("now_millis", argc, 0)?;
+            let millis = state.now_millis()?;
+            state.push_to_stack(&Value::Integer(millis))?;
+            Ok(())
+        })
+        .func_gen("clock", vec![], FLOAT_TYPE, |state, argc| {
+            check_argc("clock", argc, 0)?;
+            let seconds = state.clock()?;
+            state.push_to_stack(&seconds.into())?;
+            Ok(())
+        })
+        .func_gen("sleep", vec![INTEGER_TYPE], Type::Null, |state, argc| {
+            check_argc("sleep", argc, 1)?;
+            let millis = state.pop_from_stack()?.expect_int()?;
+            if millis < 0 {
+                bail!("sleep() got a negative duration: {}", millis);
+            }
+            state.sleep(millis as u64)
+        })
+        .func_gen(
+            "format_time",
+            vec![INTEGER_TYPE, STRING_TYPE],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("format_time", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let millis = args.next().unwrap().expect_int()?;
+                let fmt: String = args.next().unwrap().try_into()?;
+                state.push_to_stack(&Value::String(format_time(millis, &fmt)?.into()))?;
+                Ok(())
+            },
+        )
+        .func_gen("input", vec![], STRING_TYPE, |state, argc| {
+            check_argc("input", argc, 0)?;
+            let line = input_line(state, "input")?;
+            state.push_to_stack(&Value::String(line.into()))?;
+            Ok(())
+        })
+        .func_gen("input_int", vec![], INTEGER_TYPE, |state, argc| {
+            check_argc("input_int", argc, 0)?;
+            let line = input_line(state, "input_int")?;
+            let value: i64 = line
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("input_int() expected an integer, got '{}'", line))?;
+            state.push_to_stack(&Value::Integer(value))?;
+            Ok(())
+        })
+        .func_gen("input_float", vec![], FLOAT_TYPE, |state, argc| {
+            check_argc("input_float", argc, 0)?;
+            let line = input_line(state, "input_float")?;
+            let value: f64 = line
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("input_float() expected a number, got '{}'", line))?;
+            state.push_to_stack(&value.into())?;
+            Ok(())
+        })
+        .func_gen(
+            "substring",
+            vec![STRING_TYPE, INTEGER_TYPE, INTEGER_TYPE],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("substring", argc, 3)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let start = args.next().unwrap().expect_int()?;
+                let len = args.next().unwrap().expect_int()?;
+                let char_count = s.chars().count() as i64;
+                if start < 0 || start > char_count {
+                    bail!(
+                        "substring start {} out of range for string of length {}",
+                        start,
+                        char_count
+                    );
+                }
+                if len < 0 {
+                    bail!("substring length must not be negative, got {}", len);
                 }
+                let sub: String = s.chars().skip(start as usize).take(len as usize).collect();
+                state.push_to_stack(&Value::String(sub.into()))?;
                 Ok(())
             },
         )
         .func_gen(
-            "exp",
-            vec![Type::or(FLOAT_TYPE, INTEGER_TYPE)],
-            FLOAT_TYPE,
+            "find",
+            vec![STRING_TYPE, STRING_TYPE],
+            Type::or(INTEGER_TYPE, Type::Null),
             |state, argc| {
-                if argc != 1 {
-                    return Err(anyhow!("Wrong amount of of arguments for print!"));
+                check_argc("find", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let needle: String = args.next().unwrap().try_into()?;
+                // Report the position in characters, matching `substring`.
+                let found = s
+                    .find(&needle)
+                    .map(|byte_index| Value::Integer(s[..byte_index].chars().count() as i64))
+                    .unwrap_or(Value::Null);
+                state.push_to_stack(&found)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "count",
+            vec![STRING_TYPE, STRING_TYPE],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("count", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let needle: String = args.next().unwrap().try_into()?;
+                if needle.is_empty() {
+                    bail!("count() needle must not be empty");
                 }
-                let arg0 = state.pop_from_stack()?;
-                match arg0 {
-                    Value::Null => bail!("Wrong type of argument provided: Null"),
-                    Value::Integer(i) => {
-                        state.push_to_stack(&Value::Integer(f64::exp(i as f64) as i64))?;
-                    }
-                    Value::Float(f) => {
-                        state.push_to_stack(&f.exp().into())?;
-                    }
-                    Value::Char(_) => bail!("Wrong type of argument provided: Char"),
-                    Value::Bool(_) => bail!("Wrong type of argument provided: Bool"),
-                    Value::String(_) => bail!("Wrong type of argument provided: String"),
-                    Value::Ref(_) => bail!("Wrong type of argument provided: Ref"),
+                state.push_to_stack(&Value::Integer(s.matches(&needle).count() as i64))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "len",
+            vec![Type::or(STRING_TYPE, element_array_type())],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("len", argc, 1)?;
+                let len = match state.pop_from_stack()? {
+                    Value::String(s) => s.chars().count(),
+                    Value::Ref(array) => array.array_values()?.len(),
+                    other => bail!("len() expects a string or an array, got {}", other),
+                };
+                state.push_to_stack(&Value::Integer(len as i64))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "substr",
+            vec![STRING_TYPE, INTEGER_TYPE, INTEGER_TYPE],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("substr", argc, 3)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let start = args.next().unwrap().expect_int()?;
+                let end = args.next().unwrap().expect_int()?;
+                // Unlike `substring`, takes the character range `start..end`.
+                let char_count = s.chars().count() as i64;
+                if start < 0 || start > end || end > char_count {
+                    bail!(
+                        "substr range {}..{} out of range for string of length {}",
+                        start,
+                        end,
+                        char_count
+                    );
                 }
+                let sub: String = s
+                    .chars()
+                    .skip(start as usize)
+                    .take((end - start) as usize)
+                    .collect();
+                state.push_to_stack(&Value::String(sub.into()))?;
                 Ok(())
             },
         )
-        .func_gen("input", vec![], STRING_TYPE, |_state, argc| {
-            if argc != 0 {
-                return Err(anyhow!("Wrong amount of of arguments for print!"));
+        .func_gen(
+            "split",
+            vec![STRING_TYPE, STRING_TYPE],
+            Type::array(STRING_TYPE),
+            |state, argc| {
+                check_argc("split", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let separator: String = args.next().unwrap().try_into()?;
+                if separator.is_empty() {
+                    bail!("split() separator must not be empty");
+                }
+                let parts = s.split(&separator).map(Value::from).collect();
+                let array = state.instance(Type::array(STRING_TYPE), parts);
+                state.push_to_stack(&Value::Ref(array))?;
+                Ok(())
+            },
+        )
+        .func_gen("exit", vec![INTEGER_TYPE], Type::Null, |state, argc| {
+            check_argc("exit", argc, 1)?;
+            let code = state.pop_from_stack()?.expect_int()?;
+            Err(anyhow::Error::new(Exit(code)))
+        })
+        .func_gen("assert", vec![BOOL_TYPE], Type::Null, |state, argc| {
+            check_argc("assert", argc, 1)?;
+            if !state.pop_from_stack()?.expect_bool()? {
+                return Err(anyhow::Error::new(AssertionFailed(
+                    "assertion failed".to_string(),
+                )));
             }
             Ok(())
         })
+        .func_gen(
+            "assert_eq",
+            vec![element_type(), element_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("assert_eq", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let (left, right) = (args.next().unwrap(), args.next().unwrap());
+                if left != right {
+                    let message = format!(
+                        "assertion failed: {} != {}",
+                        quoted(state, &left)?,
+                        quoted(state, &right)?
+                    );
+                    return Err(anyhow::Error::new(AssertionFailed(message)));
+                }
+                Ok(())
+            },
+        )
+        .func_gen("fail", vec![STRING_TYPE], Type::Null, |state, argc| {
+            check_argc("fail", argc, 1)?;
+            let message: String = state.pop_from_stack()?.try_into()?;
+            Err(anyhow::Error::new(AssertionFailed(message)))
+        })
+        .func_gen("args", vec![], Type::array(STRING_TYPE), |state, argc| {
+            check_argc("args", argc, 0)?;
+            let args = state
+                .args()
+                .iter()
+                .map(|arg| Value::from(arg.as_str()))
+                .collect();
+            let array = state.instance(Type::array(STRING_TYPE), args);
+            state.push_to_stack(&Value::Ref(array))?;
+            Ok(())
+        })
+        .func_gen("to_upper", vec![STRING_TYPE], STRING_TYPE, |state, argc| {
+            check_argc("to_upper", argc, 1)?;
+            let s: String = state.pop_from_stack()?.try_into()?;
+            state.push_to_stack(&Value::String(s.to_uppercase().into()))?;
+            Ok(())
+        })
+        .func_gen("to_lower", vec![STRING_TYPE], STRING_TYPE, |state, argc| {
+            check_argc("to_lower", argc, 1)?;
+            let s: String = state.pop_from_stack()?.try_into()?;
+            state.push_to_stack(&Value::String(s.to_lowercase().into()))?;
+            Ok(())
+        })
+        .func_gen("trim", vec![STRING_TYPE], STRING_TYPE, |state, argc| {
+            check_argc("trim", argc, 1)?;
+            let s: String = state.pop_from_stack()?.try_into()?;
+            state.push_to_stack(&Value::from(s.trim()))?;
+            Ok(())
+        })
+        .func_gen(
+            "contains",
+            vec![STRING_TYPE, STRING_TYPE],
+            BOOL_TYPE,
+            |state, argc| {
+                check_argc("contains", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let needle: String = args.next().unwrap().try_into()?;
+                state.push_to_stack(&Value::Bool(s.contains(&needle)))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "replace",
+            vec![STRING_TYPE, STRING_TYPE, STRING_TYPE],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("replace", argc, 3)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let from: String = args.next().unwrap().try_into()?;
+                let to: String = args.next().unwrap().try_into()?;
+                if from.is_empty() {
+                    bail!("replace() pattern must not be empty");
+                }
+                state.push_to_stack(&Value::String(s.replace(&from, &to).into()))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "char_at",
+            vec![STRING_TYPE, INTEGER_TYPE],
+            CHAR_TYPE,
+            |state, argc| {
+                check_argc("char_at", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let index = args.next().unwrap().expect_int()?;
+                let c = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| s.chars().nth(index))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "char_at index {} out of range for string of length {}",
+                            index,
+                            s.chars().count()
+                        )
+                    })?;
+                state.push_to_stack(&Value::Char(c))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "open",
+            vec![STRING_TYPE, STRING_TYPE],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("open", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let path: String = args.next().unwrap().try_into()?;
+                let mode: String = args.next().unwrap().try_into()?;
+                let mut options = OpenOptions::new();
+                match mode.as_str() {
+                    "r" => options.read(true),
+                    "w" => options.write(true).create(true).truncate(true),
+                    "a" => options.append(true).create(true),
+                    "rw" => options.read(true).write(true).create(true),
+                    _ => bail!("Unknown file mode '{}', expected r, w, a or rw", mode),
+                };
+                let file = options
+                    .open(&path)
+                    .map_err(|e| anyhow!("Failed to open '{}': {}", path, e))?;
+                let handle = state.open_file(file);
+                state.push_to_stack(&Value::Integer(handle))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "read_n",
+            vec![INTEGER_TYPE, INTEGER_TYPE],
+            byte_array_type(),
+            |state, argc| {
+                check_argc("read_n", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let handle = args.next().unwrap().expect_int()?;
+                let n = args.next().unwrap().expect_int()?;
+                if n < 0 {
+                    bail!("read_n count must not be negative, got {}", n);
+                }
+                let values = state
+                    .read_file(handle, n as u64)?
+                    .into_iter()
+                    .map(|b| Value::Integer(b as i64))
+                    .collect();
+                let array = state.instance(byte_array_type(), values);
+                state.push_to_stack(&Value::Ref(array))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "write",
+            vec![INTEGER_TYPE, byte_array_type()],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("write", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let handle = args.next().unwrap().expect_int()?;
+                let bytes = bytes_from_value(&args.next().unwrap())?;
+                state.file(handle)?.write_all(&bytes)?;
+                state.push_to_stack(&Value::Integer(bytes.len() as i64))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "seek",
+            vec![INTEGER_TYPE, INTEGER_TYPE],
+            INTEGER_TYPE,
+            |state, argc| {
+                check_argc("seek", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let handle = args.next().unwrap().expect_int()?;
+                let position = args.next().unwrap().expect_int()?;
+                if position < 0 {
+                    bail!("seek position must not be negative, got {}", position);
+                }
+                let new_position = state.file(handle)?.seek(SeekFrom::Start(position as u64))?;
+                state.push_to_stack(&Value::Integer(new_position as i64))?;
+                Ok(())
+            },
+        )
+        .func_gen("close", vec![INTEGER_TYPE], Type::Null, |state, argc| {
+            check_argc("close", argc, 1)?;
+            let handle = state.pop_from_stack()?.expect_int()?;
+            state.close_file(handle)?;
+            Ok(())
+        })
+        .func_gen(
+            "read_file",
+            vec![STRING_TYPE],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("read_file", argc, 1)?;
+                let path: String = state.pop_from_stack()?.try_into()?;
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read '{}': {}", path, e))?;
+                state.push_to_stack(&Value::String(contents.into()))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "write_file",
+            vec![STRING_TYPE, STRING_TYPE],
+            Type::Null,
+            |state, argc| {
+                check_argc("write_file", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let path: String = args.next().unwrap().try_into()?;
+                let contents: String = args.next().unwrap().try_into()?;
+                std::fs::write(&path, contents)
+                    .map_err(|e| anyhow!("Failed to write '{}': {}", path, e))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "append_file",
+            vec![STRING_TYPE, STRING_TYPE],
+            Type::Null,
+            |state, argc| {
+                check_argc("append_file", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let path: String = args.next().unwrap().try_into()?;
+                let contents: String = args.next().unwrap().try_into()?;
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(contents.as_bytes()))
+                    .map_err(|e| anyhow!("Failed to append to '{}': {}", path, e))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "file_exists",
+            vec![STRING_TYPE],
+            BOOL_TYPE,
+            |state, argc| {
+                check_argc("file_exists", argc, 1)?;
+                let path: String = state.pop_from_stack()?.try_into()?;
+                state.push_to_stack(&Value::Bool(std::path::Path::new(&path).exists()))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "delete_file",
+            vec![STRING_TYPE],
+            Type::Null,
+            |state, argc| {
+                check_argc("delete_file", argc, 1)?;
+                let path: String = state.pop_from_stack()?.try_into()?;
+                std::fs::remove_file(&path)
+                    .map_err(|e| anyhow!("Failed to delete '{}': {}", path, e))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "list_dir",
+            vec![STRING_TYPE],
+            Type::array(STRING_TYPE),
+            |state, argc| {
+                check_argc("list_dir", argc, 1)?;
+                let path: String = state.pop_from_stack()?.try_into()?;
+                let mut names = std::fs::read_dir(&path)
+                    .and_then(|entries| {
+                        entries
+                            .map(|entry| {
+                                Result::Ok(entry?.file_name().to_string_lossy().into_owned())
+                            })
+                            .collect::<std::io::Result<Vec<String>>>()
+                    })
+                    .map_err(|e| anyhow!("Failed to list '{}': {}", path, e))?;
+                // Directory order is up to the file system.
+                names.sort();
+                let names = names.into_iter().map(Value::from).collect();
+                let array = state.instance(Type::array(STRING_TYPE), names);
+                state.push_to_stack(&Value::Ref(array))?;
+                Ok(())
+            },
+        )
+        .func_gen("is_upper", vec![CHAR_TYPE], BOOL_TYPE, |state, argc| {
+            check_argc("is_upper", argc, 1)?;
+            let c = state.pop_from_stack()?.expect_char()?;
+            state.push_to_stack(&Value::Bool(c.is_uppercase()))?;
+            Ok(())
+        })
+        .func_gen("is_lower", vec![CHAR_TYPE], BOOL_TYPE, |state, argc| {
+            check_argc("is_lower", argc, 1)?;
+            let c = state.pop_from_stack()?.expect_char()?;
+            state.push_to_stack(&Value::Bool(c.is_lowercase()))?;
+            Ok(())
+        })
+        .func_gen(
+            "to_digit",
+            vec![CHAR_TYPE],
+            Type::or(INTEGER_TYPE, Type::Null),
+            |state, argc| {
+                check_argc("to_digit", argc, 1)?;
+                let c = state.pop_from_stack()?.expect_char()?;
+                let digit = c
+                    .to_digit(10)
+                    .map(|d| Value::Integer(d as i64))
+                    .unwrap_or(Value::Null);
+                state.push_to_stack(&digit)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "parse_int",
+            vec![STRING_TYPE, INTEGER_TYPE],
+            Type::or(INTEGER_TYPE, Type::Null),
+            |state, argc| {
+                check_argc("parse_int", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let s: String = args.next().unwrap().try_into()?;
+                let base = args.next().unwrap().expect_int()?;
+                // A bad base is a programming error, not a parse failure.
+                if !(2..=36).contains(&base) {
+                    bail!("parse_int base must be between 2 and 36, got {}", base);
+                }
+                let parsed = i64::from_str_radix(&s, base as u32)
+                    .map(Value::Integer)
+                    .unwrap_or(Value::Null);
+                state.push_to_stack(&parsed)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "parse_float",
+            vec![STRING_TYPE],
+            Type::or(FLOAT_TYPE, Type::Null),
+            |state, argc| {
+                check_argc("parse_float", argc, 1)?;
+                let s: String = state.pop_from_stack()?.try_into()?;
+                let parsed = s.parse::<f64>().map(Value::from).unwrap_or(Value::Null);
+                state.push_to_stack(&parsed)?;
+                Ok(())
+            },
+        )
+        .ordered_func_gen(
+            "sort",
+            vec![element_array_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("sort", argc, 1)?;
+                let array = state.pop_from_stack()?.expect_ref()?;
+                let sorted = sort_values(state, array.array_values()?)?;
+                array.set_array_values(sorted)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "push",
+            vec![element_array_type(), element_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("push", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let array = args.next().unwrap().expect_ref()?;
+                let mut values = array.array_values()?;
+                values.push(args.next().unwrap());
+                array.set_array_values(values)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "pop",
+            vec![element_array_type()],
+            element_type(),
+            |state, argc| {
+                check_argc("pop", argc, 1)?;
+                let array = state.pop_from_stack()?.expect_ref()?;
+                let mut values = array.array_values()?;
+                let last = values
+                    .pop()
+                    .ok_or_else(|| anyhow!("pop() called on an empty array"))?;
+                array.set_array_values(values)?;
+                state.push_to_stack(&last)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "insert",
+            vec![element_array_type(), INTEGER_TYPE, element_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("insert", argc, 3)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let array = args.next().unwrap().expect_ref()?;
+                let index = args.next().unwrap().expect_int()?;
+                let mut values = array.array_values()?;
+                let position = array_position("insert", index, values.len(), true)?;
+                values.insert(position, args.next().unwrap());
+                array.set_array_values(values)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "remove_at",
+            vec![element_array_type(), INTEGER_TYPE],
+            element_type(),
+            |state, argc| {
+                check_argc("remove_at", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let array = args.next().unwrap().expect_ref()?;
+                let index = args.next().unwrap().expect_int()?;
+                let mut values = array.array_values()?;
+                let position = array_position("remove_at", index, values.len(), false)?;
+                let removed = values.remove(position);
+                array.set_array_values(values)?;
+                state.push_to_stack(&removed)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "index_of",
+            vec![element_array_type(), element_type()],
+            Type::or(INTEGER_TYPE, Type::Null),
+            |state, argc| {
+                check_argc("index_of", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let values = args.next().unwrap().expect_ref()?.array_values()?;
+                let needle = args.next().unwrap();
+                let found = values
+                    .iter()
+                    .position(|value| *value == needle)
+                    .map(|index| Value::Integer(index as i64))
+                    .unwrap_or(Value::Null);
+                state.push_to_stack(&found)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "reverse",
+            vec![element_array_type()],
+            Type::Null,
+            |state, argc| {
+                check_argc("reverse", argc, 1)?;
+                let array = state.pop_from_stack()?.expect_ref()?;
+                let mut values = array.array_values()?;
+                values.reverse();
+                array.set_array_values(values)?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "new_matrix",
+            vec![INTEGER_TYPE, INTEGER_TYPE, element_type()],
+            Type::array(element_array_type()),
+            |state, argc| {
+                // Each row is its own array; the cells all start as the same value.
+                check_argc("new_matrix", argc, 3)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let rows = args.next().unwrap().expect_int()?;
+                let cols = args.next().unwrap().expect_int()?;
+                let init = args.next().unwrap();
+                let (rows, cols) = match (usize::try_from(rows), usize::try_from(cols)) {
+                    (Result::Ok(rows), Result::Ok(cols)) => (rows, cols),
+                    _ => bail!("new_matrix size {}x{} must not be negative", rows, cols),
+                };
+                let too_large = || anyhow!("new_matrix size {}x{} is too large", rows, cols);
+                let bytes = array_size(cols, &init)
+                    .and_then(|row| row.checked_mul(rows))
+                    .and_then(|cells| cells.checked_add(array_size(rows, &Value::Null)?))
+                    .ok_or_else(too_large)?;
+                state.reserve_heap(bytes, rows + 1)?;
+                let row_type = Type::array(init.get_type());
+                let mut matrix = reserved(rows).ok_or_else(too_large)?;
+                for _ in 0..rows {
+                    let mut row = reserved(cols).ok_or_else(too_large)?;
+                    row.resize(cols, init.clone());
+                    matrix.push(Value::Ref(state.instance(row_type.clone(), row)));
+                }
+                let matrix = state.instance(Type::array(row_type), matrix);
+                state.push_to_stack(&Value::Ref(matrix))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "to_json",
+            vec![element_type()],
+            STRING_TYPE,
+            |state, argc| {
+                check_argc("to_json", argc, 1)?;
+                let value = state.pop_from_stack()?;
+                let text = serde_json::to_string(&json::to_json(&value)?)?;
+                state.push_to_stack(&Value::String(text.into()))?;
+                Ok(())
+            },
+        )
+        .func_gen(
+            "from_json",
+            vec![STRING_TYPE, STRING_TYPE],
+            element_type(),
+            |state, argc| {
+                check_argc("from_json", argc, 2)?;
+                let mut args = pop_args(state, argc)?.into_iter();
+                let text: String = args.next().unwrap().try_into()?;
+                let class: String = args.next().unwrap().try_into()?;
+                let parsed: serde_json::Value =
+                    serde_json::from_str(&text).map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+                let value = json::from_json(state, &parsed, &Type::abra(class))?;
+                state.push_to_stack(&value)?;
+                Ok(())
+            },
+        )
+        .func_gen("gc_collect", vec![], INTEGER_TYPE, |state, argc| {
+            check_argc("gc_collect", argc, 0)?;
+            let freed = state.collect_garbage();
+            state.push_to_stack(&Value::Integer(freed as i64))?;
+            Ok(())
+        })
+        .func_gen("mem_used", vec![], INTEGER_TYPE, |state, argc| {
+            check_argc("mem_used", argc, 0)?;
+            let bytes = state.heap_bytes();
+            state.push_to_stack(&Value::Integer(bytes as i64))?;
+            Ok(())
+        })
+        .func_gen("obj_count", vec![], INTEGER_TYPE, |state, argc| {
+            check_argc("obj_count", argc, 0)?;
+            let objects = state.live_objects();
+            state.push_to_stack(&Value::Integer(objects as i64))?;
+            Ok(())
+        })
         .finalize()
 }
@@ -0,0 +1,624 @@
+//! Native code for hot functions, behind the `jit` feature.
+//!
+//! The VM counts the calls to each function; once one is hot, it and every
+//! function it calls are compiled with Cranelift, if all of them only work on
+//! `int`s and `bool`s in locals: arithmetic, comparisons, jumps and calls, no
+//! objects, strings, globals or builtins. Anything else stays interpreted.
+//!
+//! Compiled code has no side effects, so whenever it meets something it can't
+//! handle the way the interpreter does (an overflow, a division by zero, deep
+//! recursion) it gives up on the whole call, and the VM runs that call again in
+//! the interpreter, which then behaves exactly as it would have without the JIT.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::anyhow;
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types, AbiParam, Block, InstBuilder, MemFlags, StackSlotData,
+        StackSlotKind, Value as IrValue,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module};
+
+use crate::compiler::{
+    analysis::{self, successors, Kind, Shape, Signatures},
+    typecheck::Type,
+    BinaryOp, ByteCode, Operand,
+};
+
+use super::value::Value;
+
+/// Calls after which a function is compiled, unless the VM is told otherwise.
+pub const DEFAULT_HOT_CALLS: u32 = 100;
+/// Nested native calls before compiled code hands the call back to the interpreter,
+/// which has its own limit and reports a stack overflow properly.
+const MAX_NATIVE_DEPTH: i64 = 1000;
+
+/// `fn(arguments, result, depth left) -> gave up`.
+type Entry = extern "C" fn(*const i64, *mut i64, i64) -> u8;
+
+/// The parts of a loaded program the JIT reads.
+pub(crate) struct Program<'a> {
+    pub bytecode: &'a [ByteCode],
+    pub constants: &'a [Value],
+    pub function_table: &'a [(String, Option<usize>)],
+}
+
+impl analysis::Program for Program<'_> {
+    fn bytecode(&self) -> &[ByteCode] {
+        self.bytecode
+    }
+
+    fn constant(&self, index: usize) -> Option<Kind> {
+        match self.constants.get(index)? {
+            Value::Integer(_) => Some(Kind::Int),
+            Value::Bool(_) => Some(Kind::Bool),
+            _ => None,
+        }
+    }
+
+    fn function(&self, index: usize) -> Option<usize> {
+        self.function_table.get(index)?.1
+    }
+
+    fn parameter(&self, ty: &Type) -> Option<Kind> {
+        Kind::of_type(ty).filter(|kind| *kind != Kind::Str)
+    }
+
+    /// Compiled code has no builtins, labels or exits: the VM resolves labels to
+    /// addresses when it loads a program.
+    fn supports(&self, byte: &ByteCode) -> bool {
+        !matches!(
+            byte,
+            ByteCode::CALL(..) | ByteCode::EXIT | ByteCode::JMPTO(_) | ByteCode::JITL(_)
+        )
+    }
+}
+
+/// A function compiled to native code.
+pub(crate) struct NativeFunction {
+    entry: Entry,
+    params: Vec<Kind>,
+    /// `None` for functions that return nothing.
+    returns: Option<Kind>,
+}
+
+impl NativeFunction {
+    pub(crate) fn returns_value(&self) -> bool {
+        self.returns.is_some()
+    }
+
+    /// Runs the function on `args`; `None` if it gave up and the call has to be
+    /// interpreted instead. A function that returns nothing gives `Value::Null`.
+    pub(crate) fn call(&self, args: &[Value]) -> Option<Value> {
+        if args.len() != self.params.len() {
+            return None;
+        }
+        let mut raw = Vec::with_capacity(args.len());
+        for (arg, kind) in args.iter().zip(self.params.iter()) {
+            raw.push(match (arg, kind) {
+                (Value::Integer(value), Kind::Int) => *value,
+                (Value::Bool(value), Kind::Bool) => *value as i64,
+                _ => return None,
+            });
+        }
+        let mut result = 0;
+        if (self.entry)(raw.as_ptr(), &mut result, MAX_NATIVE_DEPTH) != 0 {
+            return None;
+        }
+        Some(match self.returns {
+            Some(Kind::Bool) => Value::Bool(result != 0),
+            Some(_) => Value::Integer(result),
+            None => Value::Null,
+        })
+    }
+}
+
+/// The instructions that start a basic block: the body, jump targets and whatever
+/// follows a jump or a return.
+fn leaders(program: &Program, shape: &Shape) -> BTreeSet<usize> {
+    let mut leaders = BTreeSet::from([shape.body]);
+    for pc in shape.reachable.iter().copied() {
+        let byte = &program.bytecode[pc];
+        if let ByteCode::JMPABS(_)
+        | ByteCode::JMPREL(_)
+        | ByteCode::JITA(_)
+        | ByteCode::JITR(_)
+        | ByteCode::RET(_) = byte
+        {
+            leaders.extend(successors(program, pc, byte).unwrap_or_default());
+            leaders.insert(pc + 1);
+        }
+    }
+    leaders.retain(|pc| shape.reachable.contains(pc));
+    leaders
+}
+
+/// Entry addresses of the functions `shape` calls.
+fn callees(program: &Program, shape: &Shape) -> Vec<usize> {
+    let call = |pc: &usize| match program.bytecode[*pc] {
+        ByteCode::CALLFN(index, _) => program.function_table[index].1,
+        _ => None,
+    };
+    shape.reachable.iter().filter_map(call).collect()
+}
+
+/// Compiled code for one VM, and the call counts that decide what to compile.
+pub(crate) struct Jit {
+    module: JITModule,
+    hot_calls: u32,
+    calls: HashMap<usize, u32>,
+    /// By entry address; `None` for functions that can't be compiled.
+    functions: HashMap<usize, Option<Compiled>>,
+}
+
+struct Compiled {
+    id: FuncId,
+    native: NativeFunction,
+}
+
+impl Jit {
+    /// A JIT for the machine this runs on, compiling functions after `hot_calls` calls.
+    pub(crate) fn new(hot_calls: u32) -> anyhow::Result<Jit> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false")?;
+        flags.set("is_pic", "false")?;
+        flags.set("opt_level", "speed")?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| anyhow!("No JIT for this machine: {}", e))?
+            .finish(settings::Flags::new(flags))?;
+        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        Ok(Jit {
+            module: JITModule::new(builder),
+            hot_calls,
+            calls: HashMap::new(),
+            functions: HashMap::new(),
+        })
+    }
+
+    /// Counts a call to the function at `address` and returns its native code once
+    /// it is hot and compiled.
+    pub(crate) fn hot_function(
+        &mut self,
+        address: usize,
+        program: &Program,
+    ) -> Option<&NativeFunction> {
+        if !self.functions.contains_key(&address) {
+            let calls = self.calls.entry(address).or_insert(0);
+            *calls += 1;
+            if *calls < self.hot_calls {
+                return None;
+            }
+            self.compile(address, program);
+        }
+        self.functions[&address]
+            .as_ref()
+            .map(|compiled| &compiled.native)
+    }
+
+    pub(crate) fn hot_calls(&self) -> u32 {
+        self.hot_calls
+    }
+
+    /// Number of functions running as native code.
+    pub(crate) fn compiled_count(&self) -> usize {
+        self.functions.values().flatten().count()
+    }
+
+    /// Compiles the function at `address` with the functions it calls that aren't
+    /// compiled yet, or marks them all as interpreted-only.
+    fn compile(&mut self, address: usize, program: &Program) {
+        let mut shapes = BTreeMap::new();
+        let mut pending = vec![address];
+        let mut failed = false;
+        while let Some(address) = pending.pop() {
+            if shapes.contains_key(&address) || self.functions.contains_key(&address) {
+                failed |= matches!(self.functions.get(&address), Some(None));
+                continue;
+            }
+            match Shape::scan(program, address) {
+                Ok(shape) => {
+                    pending.extend(callees(program, &shape));
+                    shapes.insert(address, shape);
+                }
+                Err(_) => failed = true,
+            }
+        }
+        let defined = !failed && self.infer(&mut shapes, program).is_some();
+        let compiled = defined && self.define(&shapes, program).is_some();
+        for address in shapes.keys() {
+            if !compiled {
+                self.functions.insert(*address, None);
+            }
+        }
+        self.functions.entry(address).or_insert(None);
+    }
+
+    fn infer(&self, shapes: &mut BTreeMap<usize, Shape>, program: &Program) -> Option<()> {
+        let mut compiled = Signatures::new();
+        for (address, compiled_function) in self.functions.iter() {
+            if let Some(Compiled { native, .. }) = compiled_function {
+                compiled.insert(*address, (native.params.clone(), native.returns));
+            }
+        }
+        let mut analyzed: Vec<&mut Shape> = shapes.values_mut().collect();
+        analysis::infer(program, &mut analyzed, &compiled).ok()?;
+        // A result nothing pins down, e.g. of a function that only ever recurses.
+        let unknown = |shape: &Shape| shape.returns_value && shape.returns == Kind::Unknown;
+        (!shapes.values().any(unknown)).then_some(())
+    }
+
+    fn define(&mut self, shapes: &BTreeMap<usize, Shape>, program: &Program) -> Option<()> {
+        let mut signature = self.module.make_signature();
+        let pointer = self.module.target_config().pointer_type();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I64));
+        signature.returns.push(AbiParam::new(types::I8));
+
+        // What calls need to know about every function they may call.
+        let mut ids = HashMap::new();
+        let mut returns_value = HashMap::new();
+        for (address, shape) in shapes.iter() {
+            let name = format!("abra_{}", address);
+            let id = self
+                .module
+                .declare_function(&name, Linkage::Local, &signature)
+                .ok()?;
+            ids.insert(*address, id);
+            returns_value.insert(*address, shape.returns_value);
+        }
+        for (address, compiled) in self.functions.iter() {
+            if let Some(compiled) = compiled {
+                ids.insert(*address, compiled.id);
+                returns_value.insert(*address, compiled.native.returns_value());
+            }
+        }
+
+        let mut context = self.module.make_context();
+        let mut builder_context = FunctionBuilderContext::new();
+        for (address, shape) in shapes.iter() {
+            context.func.signature = signature.clone();
+            let translator = Translator {
+                builder: FunctionBuilder::new(&mut context.func, &mut builder_context),
+                module: &mut self.module,
+                program,
+                shape,
+                ids: &ids,
+                returns_value: &returns_value,
+                stack_base: 0,
+                depth: 0,
+            };
+            translator.translate()?;
+            self.module
+                .define_function(ids[address], &mut context)
+                .ok()?;
+            self.module.clear_context(&mut context);
+        }
+        self.module.finalize_definitions().ok()?;
+
+        for (address, shape) in shapes.iter() {
+            let code = self.module.get_finalized_function(ids[address]);
+            // SAFETY: the function was compiled with the signature `Entry` describes.
+            let entry = unsafe { std::mem::transmute::<*const u8, Entry>(code) };
+            let (params, returns) = shape.signature();
+            let native = NativeFunction {
+                entry,
+                params,
+                returns,
+            };
+            let compiled = Compiled {
+                id: ids[address],
+                native,
+            };
+            self.functions.insert(*address, Some(compiled));
+        }
+        Some(())
+    }
+}
+
+/// Turns one function's bytecode into Cranelift IR. Slots and operand stack
+/// entries become variables, so jumps carrying values on the stack need no
+/// special handling.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    program: &'a Program<'a>,
+    shape: &'a Shape,
+    ids: &'a HashMap<usize, FuncId>,
+    /// Whether each function that may be called leaves a result.
+    returns_value: &'a HashMap<usize, bool>,
+    /// First variable of the operand stack; the ones before are slots.
+    stack_base: u32,
+    /// Operand stack depth at the instruction being translated.
+    depth: u32,
+}
+
+impl Translator<'_> {
+    fn translate(mut self) -> Option<()> {
+        let shape = self.shape;
+        let slots = shape
+            .slots
+            .keys()
+            .map(|slot| *slot as u32 + 1)
+            .max()
+            .unwrap_or(0);
+        let depth = shape.stacks.values().map(Vec::len).max().unwrap_or(0) as u32;
+        self.stack_base = slots;
+
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_vec();
+        let (args, result, depth_left) = (params[0], params[1], params[2]);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        for index in 0..slots + depth {
+            let var = Variable::from_u32(index);
+            self.builder.declare_var(var, types::I64);
+            self.builder.def_var(var, zero);
+        }
+        for (index, (slot, _)) in shape.params.iter().enumerate() {
+            let arg =
+                self.builder
+                    .ins()
+                    .load(types::I64, MemFlags::trusted(), args, index as i32 * 8);
+            self.builder.def_var(Variable::from_u32(*slot as u32), arg);
+        }
+
+        let blocks: HashMap<usize, _> = leaders(self.program, shape)
+            .into_iter()
+            .map(|pc| (pc, self.builder.create_block()))
+            .collect();
+        let give_up = self.builder.create_block();
+        let exhausted = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::SignedLessThanOrEqual, depth_left, 0);
+        self.builder
+            .ins()
+            .brif(exhausted, give_up, &[], blocks[&shape.body], &[]);
+
+        let mut open = false;
+        for pc in shape.reachable.iter().copied() {
+            if let Some(block) = blocks.get(&pc) {
+                if open {
+                    self.builder.ins().jump(*block, &[]);
+                }
+                self.builder.switch_to_block(*block);
+            }
+            self.depth = shape.stacks.get(&pc)?.len() as u32;
+            open = self.instruction(pc, &blocks, give_up, result, depth_left)?;
+        }
+
+        self.builder.switch_to_block(give_up);
+        let gave_up = self.builder.ins().iconst(types::I8, 1);
+        self.builder.ins().return_(&[gave_up]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Some(())
+    }
+
+    /// Translates the instruction at `pc`; returns whether control falls through.
+    fn instruction(
+        &mut self,
+        pc: usize,
+        blocks: &HashMap<usize, Block>,
+        give_up: Block,
+        result: IrValue,
+        depth_left: IrValue,
+    ) -> Option<bool> {
+        let byte = &self.program.bytecode[pc];
+        match byte {
+            ByteCode::PUSH(constant) => {
+                let value = self.constant(*constant)?;
+                self.push(value);
+            }
+            ByteCode::POP => {
+                self.pop();
+            }
+            ByteCode::DUP => {
+                let value = self.pop();
+                self.push(value);
+                self.push(value);
+            }
+            ByteCode::LOADSLOT(slot) => {
+                let value = self.builder.use_var(Variable::from_u32(*slot as u32));
+                self.push(value);
+            }
+            ByteCode::STORESLOT(slot) => {
+                let value = self.pop();
+                self.builder
+                    .def_var(Variable::from_u32(*slot as u32), value);
+            }
+            ByteCode::DROPSLOT(_) => {}
+            ByteCode::EQUALS => {
+                let (a, b) = (self.pop(), self.pop());
+                let equal = self.builder.ins().icmp(IntCC::Equal, a, b);
+                let equal = self.builder.ins().uextend(types::I64, equal);
+                self.push(equal);
+            }
+            ByteCode::NEGATE | ByteCode::NOT => {
+                let value = self.pop();
+                let negated = self.builder.ins().bxor_imm(value, 1);
+                self.push(negated);
+            }
+            ByteCode::AND | ByteCode::OR | ByteCode::XOR => {
+                let (a, b) = (self.pop(), self.pop());
+                let value = match byte {
+                    ByteCode::AND => self.builder.ins().band(a, b),
+                    ByteCode::OR => self.builder.ins().bor(a, b),
+                    _ => self.builder.ins().bxor(a, b),
+                };
+                self.push(value);
+            }
+            ByteCode::BINARY(op, first, second, target) => {
+                let first = self.operand(*first)?;
+                let second = self.operand(*second)?;
+                let value = self.binary(*op, first, second, give_up);
+                match target {
+                    Operand::Stack => self.push(value),
+                    Operand::Slot(slot) => self
+                        .builder
+                        .def_var(Variable::from_u32(*slot as u32), value),
+                    Operand::Const(_) => return None,
+                }
+            }
+            ByteCode::JMPABS(_) | ByteCode::JMPREL(_) => {
+                let target = successors(self.program, pc, byte).ok()?[0];
+                self.builder.ins().jump(blocks[&target], &[]);
+                return Some(false);
+            }
+            ByteCode::JITA(_) | ByteCode::JITR(_) => {
+                let targets = successors(self.program, pc, byte).ok()?;
+                let condition = self.pop();
+                self.builder.ins().brif(
+                    condition,
+                    blocks[&targets[0]],
+                    &[],
+                    blocks[&targets[1]],
+                    &[],
+                );
+                return Some(false);
+            }
+            ByteCode::CALLFN(index, argc) => {
+                let address = self.program.function_table[*index].1?;
+                self.call(address, *argc as usize, give_up, depth_left)?;
+            }
+            ByteCode::RET(value) => {
+                if *value {
+                    let value = self.pop();
+                    self.builder
+                        .ins()
+                        .store(MemFlags::trusted(), value, result, 0);
+                }
+                let done = self.builder.ins().iconst(types::I8, 0);
+                self.builder.ins().return_(&[done]);
+                return Some(false);
+            }
+            byte => {
+                let first = self.pop();
+                let second = self.pop();
+                let value = self.binary(BinaryOp::of(byte)?, first, second, give_up);
+                self.push(value);
+            }
+        }
+        Some(true)
+    }
+
+    fn push(&mut self, value: IrValue) {
+        let var = Variable::from_u32(self.stack_base + self.depth);
+        self.builder.def_var(var, value);
+        self.depth += 1;
+    }
+
+    fn pop(&mut self) -> IrValue {
+        self.depth -= 1;
+        self.builder
+            .use_var(Variable::from_u32(self.stack_base + self.depth))
+    }
+
+    fn constant(&mut self, constant: usize) -> Option<IrValue> {
+        let value = match self.program.constants.get(constant)? {
+            Value::Integer(value) => *value,
+            Value::Bool(value) => *value as i64,
+            _ => return None,
+        };
+        Some(self.builder.ins().iconst(types::I64, value))
+    }
+
+    fn operand(&mut self, operand: Operand) -> Option<IrValue> {
+        match operand {
+            Operand::Stack => Some(self.pop()),
+            Operand::Slot(slot) => Some(self.builder.use_var(Variable::from_u32(slot as u32))),
+            Operand::Const(constant) => self.constant(constant),
+        }
+    }
+
+    /// Continues in a new block if `condition` is false, gives up otherwise.
+    fn give_up_if(&mut self, condition: IrValue, give_up: Block) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, give_up, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// `op` on the operand popped first and the one below it, as `BINARY` has it.
+    fn binary(&mut self, op: BinaryOp, first: IrValue, second: IrValue, give_up: Block) -> IrValue {
+        let (value, overflow) = match op {
+            BinaryOp::Add => self.builder.ins().sadd_overflow(first, second),
+            BinaryOp::Sub => self.builder.ins().ssub_overflow(first, second),
+            BinaryOp::Mult => self.builder.ins().smul_overflow(first, second),
+            BinaryOp::Div | BinaryOp::Mod => {
+                // The interpreter fails on both of these; let it.
+                let by_zero = self.builder.ins().icmp_imm(IntCC::Equal, second, 0);
+                self.give_up_if(by_zero, give_up);
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, first, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, second, -1);
+                let overflow = self.builder.ins().band(min, minus_one);
+                self.give_up_if(overflow, give_up);
+                return match op {
+                    BinaryOp::Div => self.builder.ins().sdiv(first, second),
+                    _ => self.builder.ins().srem(first, second),
+                };
+            }
+            BinaryOp::Lesser | BinaryOp::Greater | BinaryOp::EqLess | BinaryOp::EqGreat => {
+                let cc = match op {
+                    BinaryOp::Lesser => IntCC::SignedLessThan,
+                    BinaryOp::Greater => IntCC::SignedGreaterThan,
+                    BinaryOp::EqLess => IntCC::SignedLessThanOrEqual,
+                    _ => IntCC::SignedGreaterThanOrEqual,
+                };
+                let flag = self.builder.ins().icmp(cc, first, second);
+                return self.builder.ins().uextend(types::I64, flag);
+            }
+        };
+        self.give_up_if(overflow, give_up);
+        value
+    }
+
+    fn call(
+        &mut self,
+        address: usize,
+        argc: usize,
+        give_up: Block,
+        depth_left: IrValue,
+    ) -> Option<()> {
+        let callee = self
+            .module
+            .declare_func_in_func(*self.ids.get(&address)?, self.builder.func);
+        let args = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            (argc.max(1) * 8) as u32,
+            3,
+        ));
+        let result = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            8,
+            3,
+        ));
+        for index in (0..argc).rev() {
+            let value = self.pop();
+            self.builder
+                .ins()
+                .stack_store(value, args, index as i32 * 8);
+        }
+        let pointer = self.module.target_config().pointer_type();
+        let args = self.builder.ins().stack_addr(pointer, args, 0);
+        let result_address = self.builder.ins().stack_addr(pointer, result, 0);
+        let depth_left = self.builder.ins().iadd_imm(depth_left, -1);
+        let call = self
+            .builder
+            .ins()
+            .call(callee, &[args, result_address, depth_left]);
+        let gave_up = self.builder.inst_results(call)[0];
+        self.give_up_if(gave_up, give_up);
+        if *self.returns_value.get(&address)? {
+            let value = self.builder.ins().stack_load(types::I64, result, 0);
+            self.push(value);
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Conversion between runtime values and JSON for `to_json`/`from_json`.
+//!
+//! Objects serialize as JSON objects with one entry per field. Decoding is
+//! driven by the target type, and objects are rebuilt from the class layouts
+//! the compiler embeds in `Code`, so every field is checked against its
+//! declared type.
+
+use anyhow::{anyhow, bail, Result};
+use ordered_float::OrderedFloat;
+use serde_json::{Map, Number, Value as Json};
+
+use crate::{
+    compiler::typecheck::{Algebraic, Composite, Primitives, Type},
+    runtime::{value::Value, vm::ByteCodeMachine},
+};
+
+pub fn to_json(value: &Value) -> Result<Json> {
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Integer(i) => Json::Number((*i).into()),
+        // JSON numbers lose precision past 2^53 in most readers.
+        Value::BigInt(i) => Json::String(i.to_string()),
+        Value::Float(f) => Json::Number(
+            Number::from_f64(**f).ok_or_else(|| anyhow!("Cannot encode {} as JSON", f))?,
+        ),
+        Value::Char(c) => Json::String(c.to_string()),
+        Value::String(s) => Json::String(s.to_string()),
+        Value::Ref(rf) => {
+            if let Ok(elements) = rf.array_values().or_else(|_| rf.tuple_values()) {
+                Json::Array(elements.iter().map(to_json).collect::<Result<_>>()?)
+            } else if let Ok(entries) = rf.map_entries() {
+                let mut object = Map::new();
+                for (key, value) in entries.iter() {
+                    object.insert(key.to_string(), to_json(value)?);
+                }
+                Json::Object(object)
+            } else if let Ok((_, fields)) = rf.object_fields() {
+                let mut object = Map::new();
+                for (name, value) in fields.iter() {
+                    object.insert(name.clone(), to_json(value)?);
+                }
+                Json::Object(object)
+            } else {
+                bail!("Cannot encode {} as JSON", rf)
+            }
+        }
+    })
+}
+
+/// Decodes `json` as a value of type `ty`, instantiating objects on `state`.
+pub fn from_json(state: &mut ByteCodeMachine, json: &Json, ty: &Type) -> Result<Value> {
+    let mismatch = || anyhow!("Expected {} in JSON but found {}", ty, json);
+    Ok(match (ty, json) {
+        // Reference-typed fields may be null, just like a freshly constructed object's.
+        (Type::Null | Type::Abra(_) | Type::Composite(_), Json::Null) => Value::Null,
+        (Type::Primitive(Primitives::Bool), Json::Bool(b)) => Value::Bool(*b),
+        (Type::Primitive(Primitives::Integer), Json::Number(n)) => {
+            Value::Integer(n.as_i64().ok_or_else(mismatch)?)
+        }
+        (Type::Primitive(Primitives::BigInt), Json::String(s)) => {
+            Value::BigInt(s.parse().map_err(|_| mismatch())?)
+        }
+        (Type::Primitive(Primitives::BigInt), Json::Number(n)) => {
+            Value::BigInt(n.to_string().parse().map_err(|_| mismatch())?)
+        }
+        (Type::Primitive(Primitives::Float), Json::Number(n)) => {
+            Value::Float(OrderedFloat(n.as_f64().ok_or_else(mismatch)?))
+        }
+        (Type::Primitive(Primitives::Char), Json::String(s)) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Value::Char(c),
+                _ => return Err(mismatch()),
+            }
+        }
+        (Type::Primitive(Primitives::String), Json::String(s)) => Value::String(s.as_str().into()),
+        (Type::Composite(composite), _) => match (&**composite, json) {
+            (Composite::Array(element_type), Json::Array(elements)) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements.iter() {
+                    values.push(from_json(state, element, element_type)?);
+                }
+                Value::Ref(state.instance(ty.clone(), values))
+            }
+            (Composite::Map(key_type, value_type), Json::Object(entries)) => {
+                let mut values = Vec::with_capacity(entries.len() * 2);
+                for (key, value) in entries.iter() {
+                    values.push(from_json(state, &Json::String(key.clone()), key_type)?);
+                    values.push(from_json(state, value, value_type)?);
+                }
+                Value::Ref(state.instance(ty.clone(), values))
+            }
+            (Composite::HeapValue(inner), _) => {
+                let value = from_json(state, json, inner)?;
+                Value::Ref(state.instance(ty.clone(), vec![value]))
+            }
+            (Composite::Tuple(types), Json::Array(elements)) if types.len() == elements.len() => {
+                let mut values = Vec::with_capacity(elements.len());
+                for (element, element_type) in elements.iter().zip(types) {
+                    values.push(from_json(state, element, element_type)?);
+                }
+                Value::Ref(state.instance(ty.clone(), values))
+            }
+            _ => return Err(mismatch()),
+        },
+        (Type::Algebraic(algebraic), _) => match &**algebraic {
+            Algebraic::Or(left, right) => match from_json(state, json, left) {
+                Ok(value) => value,
+                Err(_) => from_json(state, json, right)?,
+            },
+        },
+        (Type::Abra(class), Json::Object(entries)) => {
+            let def = state
+                .abra_type(class)
+                .ok_or_else(|| anyhow!("Unknown class '{}'", class))?
+                .clone();
+            if let Some(extra) = entries.keys().find(|key| !def.variables.contains_key(*key)) {
+                bail!("Class '{}' has no field '{}'", class, extra);
+            }
+            let object = state.instance(ty.clone(), Vec::new());
+            for (name, (field_type, _)) in def.variables.iter() {
+                let field = entries
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Missing field '{}' for class '{}'", name, class))?;
+                let value = from_json(state, field, field_type)
+                    .map_err(|e| anyhow!("In field '{}' of class '{}': {}", name, class, e))?;
+                object.modify(&Value::String(name.as_str().into()), value)?;
+            }
+            Value::Ref(object)
+        }
+        _ => return Err(mismatch()),
+    })
+}
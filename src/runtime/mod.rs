@@ -1,8 +1,24 @@
 //! Runtime components: VM, Value, Type, Object systems.
 
-mod debug;
+pub mod bigint;
+pub mod debug;
+pub mod debugger;
+#[cfg(feature = "table-dispatch")]
+pub(crate) mod dispatch;
+pub mod embed;
+pub mod heap;
 pub mod inbuilt;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod json;
 pub mod object;
+pub mod platform;
+pub mod profile;
+pub mod random;
+pub mod sandbox;
+pub mod session;
+pub mod time;
+pub mod trace;
 pub mod types;
 pub mod value;
 pub mod vm;
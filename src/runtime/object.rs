@@ -2,13 +2,14 @@ use std::{
     collections::HashMap,
     fmt::Display,
     hash::{Hash, Hasher},
-    rc::Rc,
+    ops::Range,
+    rc::{Rc, Weak},
     sync::{atomic::AtomicUsize, Mutex},
 };
 
 use crate::{
-    compiler::typecheck::{AbraTypeDefinition, Composite, Primitives, Type},
-    runtime::value::Value,
+    compiler::typecheck::{AbraTypeDefinition, Composite, Type},
+    runtime::{debug::stack_entry, value::Value},
 };
 
 use anyhow::{anyhow, Ok, Result};
@@ -30,9 +31,9 @@ impl PartialEq for Ref {
 impl Eq for Ref {}
 
 impl Ref {
-    pub fn call_virt<T: Into<String>>(&self, func_name: T, args_vec: Vec<Value>) -> Result<Value> {
-        let mut lock = self.towards.lock().unwrap();
-        lock.call_virt(func_name, args_vec)
+    pub fn method_label(&self, func_name: &str) -> Result<String> {
+        let lock = self.towards.lock().unwrap();
+        lock.method_label(func_name)
     }
 
     pub fn get_uuid(&self) -> usize {
@@ -55,11 +56,45 @@ impl Ref {
         Ref { towards }
     }
 
+    /// A handle that doesn't keep the object alive.
+    pub fn downgrade(&self) -> WeakRef {
+        WeakRef {
+            towards: Rc::downgrade(&self.towards),
+        }
+    }
+
+    /// Values this object holds on to, for the garbage collector to trace.
+    pub fn children(&self) -> Vec<Value> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Null => Vec::new(),
+            RefObject::BoxedValue(value, _) => vec![value.clone()],
+            RefObject::Array(_, arr) => arr.clone(),
+            RefObject::Map(_, _, map) => map
+                .iter()
+                .flat_map(|(k, v)| [k.clone(), v.clone()])
+                .collect(),
+            RefObject::Abra(object) => object.variables.values().cloned().collect(),
+            RefObject::Enum(_, payload) | RefObject::Tuple(payload) => payload.clone(),
+            RefObject::Closure(closure) => closure
+                .captured
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect(),
+        }
+    }
+
     pub fn get_type(&self) -> Type {
         let lock = self.towards.lock().unwrap();
         lock.get_type()
     }
 
+    /// See [`RefHeader::size`].
+    pub fn size(&self) -> usize {
+        let lock = self.towards.lock().unwrap();
+        lock.size()
+    }
+
     pub fn get(&self, at: &Value) -> anyhow::Result<Value> {
         let lock = self.towards.lock().unwrap();
         lock.get(at)
@@ -69,6 +104,156 @@ impl Ref {
         let mut lock = self.towards.lock().unwrap();
         lock.set(at, with)
     }
+
+    pub fn is_map(&self) -> bool {
+        let lock = self.towards.lock().unwrap();
+        matches!(lock.ref_object, RefObject::Map(..))
+    }
+
+    pub fn set_key_hash(&self, hash: i64) {
+        let mut lock = self.towards.lock().unwrap();
+        lock.key_hash = Some(hash);
+    }
+
+    /// Equality for `==` and map keys: arrays, maps and boxes compare by content;
+    /// objects by identity, unless both carry a `hash()` result, then by class, hash
+    /// and fields, so objects whose hashes collide stay different keys.
+    pub fn key_eq(&self, other: &Ref) -> bool {
+        if Rc::ptr_eq(&self.towards, &other.towards) {
+            return true;
+        }
+        let a = self.towards.lock().unwrap();
+        let b = other.towards.lock().unwrap();
+        match (&a.ref_object, &b.ref_object) {
+            (RefObject::Abra(x), RefObject::Abra(y)) => match (a.key_hash, b.key_hash) {
+                (Some(h1), Some(h2)) => {
+                    x.abra_type.name == y.abra_type.name && h1 == h2 && x.variables == y.variables
+                }
+                _ => a.uuid == b.uuid,
+            },
+            (x, y) => x == y,
+        }
+    }
+
+    /// Hashing consistent with [`Ref::key_eq`].
+    pub fn hash_key<H: Hasher>(&self, state: &mut H) {
+        let lock = self.towards.lock().unwrap();
+        match (&lock.ref_object, lock.key_hash) {
+            (RefObject::Abra(object), Some(hash)) => {
+                object.abra_type.name.hash(state);
+                hash.hash(state);
+            }
+            (RefObject::Abra(_), None) => lock.uuid.hash(state),
+            (object, _) => object.hash(state),
+        }
+    }
+
+    /// Class name and fields of an object reference, fields sorted by name.
+    pub fn object_fields(&self) -> anyhow::Result<(String, Vec<(String, Value)>)> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Abra(object) => {
+                let mut fields: Vec<(String, Value)> = object
+                    .variables
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                Ok((object.abra_type.name.clone(), fields))
+            }
+            _ => Err(anyhow!("expected object")),
+        }
+    }
+
+    /// `Enum::Variant` tag and payload of an enum value.
+    pub fn variant_parts(&self) -> anyhow::Result<(String, Vec<Value>)> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Enum(tag, payload) => Ok((tag.clone(), payload.clone())),
+            _ => Err(anyhow!("expected an enum value")),
+        }
+    }
+
+    /// The values of a tuple.
+    pub fn tuple_values(&self) -> anyhow::Result<Vec<Value>> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Tuple(values) => Ok(values.clone()),
+            _ => Err(anyhow!("expected a tuple")),
+        }
+    }
+
+    /// Label and captured variables of a function value.
+    pub fn closure_parts(&self) -> anyhow::Result<(String, Vec<(String, Value)>)> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Closure(closure) => Ok((closure.label.clone(), closure.captured.clone())),
+            _ => Err(anyhow!("expected a function")),
+        }
+    }
+
+    /// The value in a box.
+    pub fn boxed_value(&self) -> anyhow::Result<Value> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::BoxedValue(value, _) => Ok(value.clone()),
+            _ => Err(anyhow!("expected a box")),
+        }
+    }
+
+    /// The value a map reference holds for `key`, if it has it.
+    pub fn map_value(&self, key: &Value) -> anyhow::Result<Option<Value>> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Map(_, _, map) => Ok(map.get(key).cloned()),
+            _ => Err(anyhow!("expected map")),
+        }
+    }
+
+    /// Copies out the entries of a map reference.
+    pub fn map_entries(&self) -> anyhow::Result<Vec<(Value, Value)>> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Map(_, _, map) => {
+                Ok(map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            }
+            _ => Err(anyhow!("expected map")),
+        }
+    }
+
+    /// Copies out the elements of an array reference.
+    pub fn array_values(&self) -> anyhow::Result<Vec<Value>> {
+        let lock = self.towards.lock().unwrap();
+        match &lock.ref_object {
+            RefObject::Array(_, arr) => Ok(arr.clone()),
+            _ => Err(anyhow!("expected array")),
+        }
+    }
+
+    /// Replaces the elements of an array reference in place.
+    pub fn set_array_values(&self, values: Vec<Value>) -> anyhow::Result<()> {
+        let mut lock = self.towards.lock().unwrap();
+        match &mut lock.ref_object {
+            RefObject::Array(_, arr) => {
+                *arr = values;
+                Ok(())
+            }
+            _ => Err(anyhow!("expected array")),
+        }
+    }
+}
+
+/// A reference that doesn't keep its object alive, as held by the heap registry.
+#[derive(Debug, Clone)]
+pub struct WeakRef {
+    towards: Weak<Mutex<RefHeader>>,
+}
+
+impl WeakRef {
+    /// The object, unless every strong reference to it is gone.
+    pub fn upgrade(&self) -> Option<Ref> {
+        self.towards.upgrade().map(|towards| Ref { towards })
+    }
 }
 
 impl Display for Ref {
@@ -83,6 +268,8 @@ pub struct RefHeader {
     pub deleted: bool,
     pub uuid: usize,
     pub ref_object: RefObject,
+    /// Result of the object's `hash()` method, refreshed by the VM before map accesses.
+    pub key_hash: Option<i64>,
 }
 
 impl Hash for RefHeader {
@@ -93,72 +280,176 @@ impl Hash for RefHeader {
     }
 }
 
+/// A value's slot, and its text if it is a string.
+fn value_size(value: &Value) -> usize {
+    let text = match value {
+        Value::String(text) => text.len(),
+        _ => 0,
+    };
+    std::mem::size_of::<Value>() + text
+}
+
+/// What [`RefHeader::size`] estimates for an array of `len` copies of `element`,
+/// or `None` if that doesn't fit in a `usize`.
+pub(crate) fn array_size(len: usize, element: &Value) -> Option<usize> {
+    len.checked_mul(value_size(element))?
+        .checked_add(std::mem::size_of::<RefHeader>())
+}
+
+/// Source of `RefHeader::uuid`s, shared by every kind of reference.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn next_uuid() -> usize {
+    COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+/// `index` as a position among `len` elements, counting back from the end when negative.
+fn array_index(index: i64, len: usize) -> anyhow::Result<usize> {
+    let position = if index < 0 { index + len as i64 } else { index };
+    usize::try_from(position)
+        .ok()
+        .filter(|&position| position < len)
+        .ok_or_else(|| anyhow!("index {} out of range for array of length {}", index, len))
+}
+
+/// The positions from `start` up to `end` among `len` elements. Negative bounds count
+/// back from the end, missing ones are the ends, and out of range ones are clamped.
+pub(crate) fn slice_range(start: Option<i64>, end: Option<i64>, len: usize) -> Range<usize> {
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 { bound + len as i64 } else { bound };
+        bound.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    start..end.max(start)
+}
+
+/// `ty`, or when a literal left it unknown, the join of the values' types.
+fn known_or_joined<'a>(ty: Type, values: impl Iterator<Item = &'a Value>) -> Type {
+    if ty != Type::Unknown {
+        return ty;
+    }
+    values.map(Value::get_type).fold(Type::Unknown, Type::join)
+}
+
 impl RefHeader {
+    /// A function value running the code at `label` with `captured` as its first locals.
+    pub fn closure(label: String, ty: Type, captured: Vec<(String, Value)>) -> Self {
+        RefHeader {
+            deleted: false,
+            uuid: next_uuid(),
+            key_hash: None,
+            ref_object: RefObject::Closure(Closure {
+                label,
+                ty,
+                captured,
+            }),
+        }
+    }
+
+    /// A value of the enum variant `tag`, written `Enum::Variant`.
+    /// Estimated bytes the object takes: its header, a slot for each value it holds
+    /// and the text of the strings among them and of its field names. Objects it
+    /// refers to count on their own.
+    pub fn size(&self) -> usize {
+        let all = |values: &[Value]| values.iter().map(value_size).sum::<usize>();
+        let pair = |(key, value): (&Value, &Value)| value_size(key) + value_size(value);
+        let named = |(name, value): (&String, &Value)| name.len() + value_size(value);
+        let held = match &self.ref_object {
+            RefObject::Null => 0,
+            RefObject::BoxedValue(value, _) => value_size(value),
+            RefObject::Array(_, values) | RefObject::Tuple(values) => all(values),
+            RefObject::Map(_, _, map) => map.iter().map(pair).sum(),
+            RefObject::Abra(object) => object.variables.iter().map(named).sum(),
+            RefObject::Closure(closure) => {
+                let captured = closure.captured.iter().map(|(name, value)| (name, value));
+                closure.label.len() + captured.map(named).sum::<usize>()
+            }
+            RefObject::Enum(tag, payload) => tag.len() + all(payload),
+        };
+        std::mem::size_of::<RefHeader>() + held
+    }
+
+    pub fn variant(tag: String, payload: Vec<Value>) -> Self {
+        RefHeader {
+            deleted: false,
+            uuid: next_uuid(),
+            key_hash: None,
+            ref_object: RefObject::Enum(tag, payload),
+        }
+    }
+
     pub fn instance_with_initializer(
         typ: Type,
         args: Vec<Value>,
-        type_tree: &Vec<AbraTypeDefinition>,
+        type_tree: &[AbraTypeDefinition],
     ) -> Self {
-        static COUNTER: AtomicUsize = AtomicUsize::new(0);
         RefHeader {
             deleted: false,
-            uuid: COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            uuid: next_uuid(),
+            key_hash: None,
             ref_object: match typ.clone() {
-                Type::Primitive(p) => {
+                Type::Primitive(_) => {
                     // For primitives, we typically create a BoxedValue.
                     // The initial value depends on the arguments, usually the first one.
-                    let initial_val = args.get(0).cloned().unwrap_or_default();
+                    let initial_val = args.first().cloned().unwrap_or_default();
                     RefObject::BoxedValue(initial_val, typ)
                 }
                 Type::Composite(composite_box) => match *composite_box {
-                    Composite::Array(element_type) => RefObject::Array(element_type, args),
+                    Composite::Array(element_type) => {
+                        let element_type = known_or_joined(element_type, args.iter());
+                        RefObject::Array(element_type, args)
+                    }
                     Composite::Map(key_type, value_type) => {
+                        // Keys can be references, which `Ref::hash_key` hashes
+                        // by what they hold or by identity.
+                        #[allow(clippy::mutable_key_type)]
                         let mut map = HashMap::new();
-                        if args.len() % 2 != 0 {
+                        if !args.len().is_multiple_of(2) {
                             // Or handle error appropriately
-                            eprintln!("Warning: Odd number of arguments for map initialization. Ignoring last argument.");
+                            log::warn!("Odd number of arguments for map initialization. Ignoring last argument.");
                         }
                         for chunk in args.chunks_exact(2) {
                             map.insert(chunk[0].clone(), chunk[1].clone());
                         }
+                        let key_type = known_or_joined(key_type, args.iter().step_by(2));
+                        let value_type =
+                            known_or_joined(value_type, args.iter().skip(1).step_by(2));
                         RefObject::Map(key_type, value_type, map)
                     }
                     Composite::HeapValue(value_type) => {
-                        let initial_val = args.get(0).cloned().unwrap_or_default();
+                        let initial_val = args.first().cloned().unwrap_or_default();
                         RefObject::BoxedValue(initial_val, value_type)
                     }
+                    Composite::Tuple(_) => RefObject::Tuple(args),
                 },
                 Type::Algebraic(_) => {
                     // Cannot directly instantiate an algebraic type.
                     RefObject::Null // Or handle as an error
                 }
-                Type::Abra(abra_type_name) => {
+                Type::Abra(abra_type_name) | Type::Generic(abra_type_name, _) => {
                     match type_tree.iter().find(|def| def.name == abra_type_name) {
-                        Some(def) => RefObject::Abra(AbraObject::new(def.clone(), args)),
+                        Some(def) => RefObject::Abra(AbraObject::new(def.clone())),
                         None => panic!("Abra type definition not found: {}", abra_type_name), // Or return error
                     }
                 }
-                Type::Null => RefObject::Null,
+                Type::Null | Type::Var(_) | Type::Function(_, _) | Type::Unknown => RefObject::Null,
             },
         }
     }
 
-    pub fn call_virt<T: Into<String>>(
-        &mut self,
-        _func_name: T,
-        _args_vec: Vec<Value>,
-    ) -> Result<Value> {
+    /// The bytecode label implementing `func_name` for this receiver.
+    pub fn method_label(&self, func_name: &str) -> Result<String> {
         match &self.ref_object {
             RefObject::Null => Err(anyhow!("Cannot call a function on a Null Ref")),
             RefObject::BoxedValue(_, _) => Err(anyhow!("Cannot call a function on a Value Ref")),
             RefObject::Array(_, _) => Err(anyhow!("Cannot call a virtual function on a Array Ref")),
             RefObject::Map(_, _, _) => Err(anyhow!("Cannot call a virtual function on a Map Ref")),
+            RefObject::Closure(_) => Err(anyhow!("Cannot call a virtual function on a function")),
+            RefObject::Enum(..) => Err(anyhow!("Cannot call a virtual function on an enum value")),
+            RefObject::Tuple(_) => Err(anyhow!("Cannot call a virtual function on a tuple")),
             RefObject::Abra(abra_object) => {
-                // Placeholder for actual virtual call dispatch
-                Err(anyhow!(
-                    "Virtual call on AbraObject not yet implemented for function {}",
-                    _func_name.into()
-                ))
+                Ok(format!("{}::{}", abra_object.abra_type.name, func_name))
             }
         }
     }
@@ -172,18 +463,42 @@ impl RefHeader {
             RefObject::Null => panic!("Cannot get type of a Null/deleted RefObject"), // Or a specific "Unit" or "Void" type
             RefObject::BoxedValue(_, t) => t.clone(), // The stored type is already the new Type
             RefObject::Abra(abra_object) => Type::Abra(abra_object.abra_type.name.clone()),
+            RefObject::Closure(closure) => closure.ty.clone(),
+            RefObject::Enum(tag, _) => {
+                let name = tag.rsplit_once("::").map_or(tag.as_str(), |(name, _)| name);
+                Type::Abra(name.to_string())
+            }
+            RefObject::Tuple(values) => {
+                let types = values.iter().map(Value::get_type).collect();
+                Type::Composite(Box::new(Composite::Tuple(types)))
+            }
         }
     }
 
     pub fn get(&self, at: &Value) -> anyhow::Result<Value> {
         match &self.ref_object {
-            RefObject::Map(_, _, map) => Ok(map[&at].clone()),
+            RefObject::Map(_, _, map) => map
+                .get(at)
+                .cloned()
+                .ok_or_else(|| anyhow!("key {} not found in map", stack_entry(at))),
             RefObject::Null => Err(anyhow!("Cannot dereference null")),
-            RefObject::Array(_, arr) => {
+            RefObject::Array(_, arr) => Ok(arr[array_index(at.expect_int()?, arr.len())?].clone()),
+            RefObject::BoxedValue(value, _) => Ok(value.clone()),
+            RefObject::Closure(_) => Err(anyhow!("Cannot index into a function")),
+            RefObject::Enum(tag, payload) => {
                 let index = at.expect_int()?;
-                Ok(arr[index as usize].clone())
+                payload
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("{} has no value at {}", tag, index))
+            }
+            RefObject::Tuple(values) => {
+                let index = at.expect_int()?;
+                values
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("tuple of {} has no value at {}", values.len(), index))
             }
-            RefObject::BoxedValue(value, _) => Ok(value.clone()),
             RefObject::Abra(abra_object) => match at {
                 Value::String(var_name) => abra_object.get(var_name),
                 _ => Err(anyhow!(
@@ -196,22 +511,25 @@ impl RefHeader {
     pub fn set(&mut self, at: &Value, with: Value) -> anyhow::Result<()> {
         match &mut self.ref_object {
             RefObject::Map(_, _, map) => {
-                if map.contains_key(&at) {
-                    map.remove(&at);
+                if map.contains_key(at) {
+                    map.remove(at);
                 }
                 map.insert(at.to_owned(), with);
                 Ok(())
             }
             RefObject::Null => Err(anyhow!("Cannot dereference null")),
             RefObject::Array(_, arr) => {
-                let index = at.expect_int()?;
-                arr[index as usize] = with;
+                let index = array_index(at.expect_int()?, arr.len())?;
+                arr[index] = with;
                 Ok(())
             }
             RefObject::BoxedValue(value, _) => {
                 *value = with.clone();
                 Ok(())
             }
+            RefObject::Closure(_) => Err(anyhow!("Cannot index into a function")),
+            RefObject::Enum(..) => Err(anyhow!("Enum values cannot be changed")),
+            RefObject::Tuple(_) => Err(anyhow!("Tuples cannot be changed")),
             RefObject::Abra(abra_object) => match at {
                 Value::String(var_name) => abra_object.set(var_name, with),
                 _ => Err(anyhow!(
@@ -229,6 +547,11 @@ pub enum RefObject {
     Array(Type, Vec<Value>),
     Map(Type, Type, HashMap<Value, Value>),
     Abra(AbraObject),
+    Closure(Closure),
+    /// A value of an enum: its `Enum::Variant` tag and payload.
+    Enum(String, Vec<Value>),
+    /// A fixed-size group of values, which can't be changed once made.
+    Tuple(Vec<Value>),
 }
 
 //write a Hash trait implementation for RefObject
@@ -261,9 +584,23 @@ impl Hash for RefObject {
                 abra_object.abra_type.name.hash(state);
                 let mut sorted_vars: Vec<(&String, &Value)> =
                     abra_object.variables.iter().collect();
-                sorted_vars.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+                sorted_vars.sort_by_key(|(name, _)| *name);
                 sorted_vars.hash(state);
             }
+            RefObject::Closure(closure) => {
+                5.hash(state);
+                closure.label.hash(state);
+                closure.captured.hash(state);
+            }
+            RefObject::Enum(tag, payload) => {
+                6.hash(state);
+                tag.hash(state);
+                payload.hash(state);
+            }
+            RefObject::Tuple(values) => {
+                7.hash(state);
+                values.hash(state);
+            }
         }
     }
 }
@@ -296,7 +633,7 @@ impl Display for RefObject {
                     write!(f, "{}", v)?;
                     first = false;
                 }
-                write!(f, "")
+                write!(f, "]")
             }
             RefObject::BoxedValue(value, _) => {
                 // Use Display impl of the inner Value
@@ -305,10 +642,35 @@ impl Display for RefObject {
             RefObject::Abra(abra_object) => {
                 write!(f, "instance of {}", abra_object.abra_type.name)
             }
+            RefObject::Closure(closure) => write!(f, "<fn {}>", closure.label),
+            RefObject::Enum(tag, payload) if payload.is_empty() => write!(f, "{}", tag),
+            RefObject::Enum(tag, payload) => {
+                write!(f, "{}(", tag)?;
+                for (i, value) in payload.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+            RefObject::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "({})", values.join(", "))
+            }
         }
     }
 }
 
+/// A function value: the label of its code, its `fn(...) -> ...` type and the
+/// variables it captured when it was created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub label: String,
+    pub ty: Type,
+    pub captured: Vec<(String, Value)>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct AbraObject {
     abra_type: AbraTypeDefinition,
@@ -316,15 +678,20 @@ pub struct AbraObject {
 }
 
 impl AbraObject {
-    pub fn new(abra_type: AbraTypeDefinition, args: Vec<Value>) -> AbraObject {
-        // TODO: Handle constructor arguments (`args`) properly if/when constructors are implemented.
-        // For now, initialize based on type definition defaults.
-        let mut variables = HashMap::new();
-        for (name, (var_type, is_initialized)) in &abra_type.variables {
-            // If we had default values from AST or type system, we'd use them here.
-            // For now, just use Value::from(var_type) which gives default for primitives.
-            variables.insert(name.clone(), Value::from(var_type.clone()));
-        }
+    /// A blank instance: primitive fields hold their type's default and the rest
+    /// are null. The VM then runs the field initializers and `init` on it.
+    pub fn new(abra_type: AbraTypeDefinition) -> AbraObject {
+        let variables = abra_type
+            .variables
+            .iter()
+            .map(|(name, (var_type, _))| {
+                let default = match var_type {
+                    Type::Primitive(_) => Value::from(var_type.clone()),
+                    _ => Value::Null,
+                };
+                (name.clone(), default)
+            })
+            .collect();
         AbraObject {
             abra_type,
             variables,
@@ -0,0 +1,203 @@
+//! What a running program sees of the machine it runs on: where its output goes,
+//! where its input comes from and what time it is.
+//!
+//! The VM only reaches the outside world through a [`Platform`], so the same runtime
+//! works in a terminal ([`Native`]) and in a browser, where there is no stdin and
+//! the standard clock panics ([`InMemory`]). The session sits on top of it and
+//! records or replays what the platform returns.
+
+use std::{cell::RefCell, collections::VecDeque, io::Write, rc::Rc, time::Duration};
+
+use anyhow::Result;
+use thiserror::Error;
+
+pub trait Platform {
+    /// Where everything the program prints goes.
+    fn stdout(&mut self) -> &mut Box<dyn Write>;
+    /// The next line of input without its line ending, `None` at the end.
+    fn stdin_line(&mut self) -> Result<Option<String>>;
+    /// The next line of input like [`Platform::stdin_line`], failing with
+    /// [`InputTimedOut`] if none comes within `timeout`. Platforms whose input never
+    /// keeps a read waiting needn't do more than read it.
+    fn stdin_line_within(&mut self, _timeout: Duration) -> Result<Option<String>> {
+        self.stdin_line()
+    }
+    /// Milliseconds since the unix epoch.
+    fn now_millis(&mut self) -> Result<i64>;
+    /// Monotonic seconds since the platform was set up.
+    fn clock(&mut self) -> f64;
+    fn sleep(&mut self, millis: u64);
+}
+
+/// What [`Platform::stdin_line_within`] fails with when no line came in time.
+#[derive(Debug, Clone, Error)]
+#[error("No input came within the timeout")]
+pub struct InputTimedOut;
+
+/// The terminal: the process's stdin and stdout and the system clocks.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Native {
+    stdout: Box<dyn Write>,
+    started: std::time::Instant,
+    /// Lines read on a thread of their own, started by the first read with a timeout
+    /// since nothing interrupts a read of stdin. Every later read takes its line from
+    /// here, so none is lost; the thread reads ahead by a line.
+    stdin_lines: Option<std::sync::mpsc::Receiver<Result<Option<String>>>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for Native {
+    fn default() -> Self {
+        Native {
+            stdout: Box::new(std::io::stdout()),
+            started: std::time::Instant::now(),
+            stdin_lines: None,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin_line() -> Result<Option<String>> {
+    use std::io::BufRead;
+
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Platform for Native {
+    fn stdout(&mut self) -> &mut Box<dyn Write> {
+        &mut self.stdout
+    }
+
+    fn stdin_line(&mut self) -> Result<Option<String>> {
+        match &self.stdin_lines {
+            // The reader stops after the end of the input or an error.
+            Some(lines) => lines.recv().unwrap_or(Ok(None)),
+            None => read_stdin_line(),
+        }
+    }
+
+    fn stdin_line_within(&mut self, timeout: Duration) -> Result<Option<String>> {
+        use std::sync::mpsc::{channel, RecvTimeoutError};
+
+        let lines = self.stdin_lines.get_or_insert_with(|| {
+            let (sender, receiver) = channel();
+            std::thread::spawn(move || loop {
+                let line = read_stdin_line();
+                let last = !matches!(line, Ok(Some(_)));
+                if sender.send(line).is_err() || last {
+                    break;
+                }
+            });
+            receiver
+        });
+        match lines.recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => Err(InputTimedOut.into()),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+
+    fn now_millis(&mut self) -> Result<i64> {
+        Ok(unix_millis() as i64)
+    }
+
+    fn clock(&mut self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    fn sleep(&mut self, millis: u64) {
+        std::thread::sleep(std::time::Duration::from_millis(millis));
+    }
+}
+
+/// Output into a buffer and input from a string, for the playground and anything
+/// else that must not touch the terminal. Sleeping returns right away.
+pub struct InMemory {
+    stdout: Box<dyn Write>,
+    input: VecDeque<String>,
+    started: f64,
+}
+
+impl InMemory {
+    /// Reads `input` line by line and writes to `stdout`.
+    pub fn new(input: &str, stdout: Box<dyn Write>) -> Self {
+        InMemory {
+            stdout,
+            input: input.lines().map(String::from).collect(),
+            started: unix_millis(),
+        }
+    }
+}
+
+impl Platform for InMemory {
+    fn stdout(&mut self) -> &mut Box<dyn Write> {
+        &mut self.stdout
+    }
+
+    fn stdin_line(&mut self) -> Result<Option<String>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn now_millis(&mut self) -> Result<i64> {
+        Ok(unix_millis() as i64)
+    }
+
+    fn clock(&mut self) -> f64 {
+        (unix_millis() - self.started) / 1000.0
+    }
+
+    fn sleep(&mut self, _millis: u64) {}
+}
+
+/// The default platform of the target: the terminal, or nothing to read and
+/// nowhere to write on wasm.
+pub fn default_platform() -> Box<dyn Platform> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Box::new(Native::default());
+    #[cfg(target_arch = "wasm32")]
+    return Box::new(InMemory::new("", Box::new(std::io::sink())));
+}
+
+/// Milliseconds since the unix epoch, with a fraction where the clock has one.
+pub(crate) fn unix_millis() -> f64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |since_epoch| since_epoch.as_secs_f64() * 1000.0);
+    #[cfg(all(target_arch = "wasm32", feature = "playground"))]
+    return crate::playground::date_now();
+    // No clock to ask without the browser's.
+    #[cfg(all(target_arch = "wasm32", not(feature = "playground")))]
+    return 0.0;
+}
+
+/// A `Write` sink whose contents stay readable after the VM that owns it is dropped.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Everything written so far, invalid UTF-8 replaced.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! Profiling: how often each instruction runs and how long it takes, and how often
+//! each function is called and how long its calls last, as `abra run --profile`
+//! reports.
+//!
+//! The VM times every instruction while profiling is enabled, so a profiled
+//! program runs a good deal slower; the proportions are what the numbers are for.
+//! Calls are followed through the VM's stack frames: a frame appearing is a call,
+//! and the call lasts until the frame goes away, returned or unwound by an error.
+//! A call's time is that of the instructions run during it, which leaves out what
+//! the profiler itself takes.
+
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+use serde_json::json;
+
+use crate::compiler::ByteCode;
+
+/// Folded stack of the code that runs outside any function, such as the
+/// initializers of globals.
+const TOP_LEVEL: &str = "<start>";
+
+/// Records what a running program spends its time on.
+pub(crate) struct Profiler {
+    /// Time taken by all instructions recorded so far.
+    clock: Duration,
+    /// Times run and time taken by the instruction at each index.
+    instructions: Vec<(u64, Duration)>,
+    functions: HashMap<String, FunctionTotals>,
+    /// Time spent in each stack of calls, as `main;outer;inner`.
+    stacks: HashMap<String, Duration>,
+    /// The calls in progress, outermost first.
+    calls: Vec<Call>,
+    /// The folded stack of `calls`.
+    path: String,
+}
+
+#[derive(Default)]
+struct FunctionTotals {
+    calls: u64,
+    /// Time from entering to leaving its calls, not counting a recursive call twice.
+    total: Duration,
+    /// Time running its own instructions.
+    own: Duration,
+}
+
+struct Call {
+    name: String,
+    /// `Profiler::clock` when the call started.
+    entered: Duration,
+    /// Length of the folded stack before the call.
+    path_len: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            clock: Duration::ZERO,
+            instructions: Vec::new(),
+            functions: HashMap::new(),
+            stacks: HashMap::new(),
+            calls: Vec::new(),
+            path: String::new(),
+        }
+    }
+}
+
+impl Profiler {
+    /// Brings the calls in progress up to date with the VM's `depth` frames, the one
+    /// at each depth named by `frame`.
+    pub(crate) fn follow_frames<'a>(&mut self, depth: usize, frame: impl Fn(usize) -> &'a str) {
+        while self.calls.len() > depth {
+            self.leave();
+        }
+        // A call may end and another start at the same depth between two looks.
+        let innermost = self.calls.last().map(|call| call.name.as_str());
+        if depth > 0 && self.calls.len() == depth && innermost != Some(frame(depth - 1)) {
+            self.leave();
+        }
+        while self.calls.len() < depth {
+            self.enter(frame(self.calls.len()).to_string());
+        }
+    }
+
+    fn enter(&mut self, name: String) {
+        self.functions.entry(name.clone()).or_default().calls += 1;
+        let path_len = self.path.len();
+        if !self.path.is_empty() {
+            self.path.push(';');
+        }
+        self.path.push_str(&name);
+        self.calls.push(Call {
+            name,
+            entered: self.clock,
+            path_len,
+        });
+    }
+
+    fn leave(&mut self) {
+        let call = self.calls.pop().expect("a call is in progress");
+        self.path.truncate(call.path_len);
+        // An outer call of the same function already counts this time.
+        if !self.calls.iter().any(|outer| outer.name == call.name) {
+            let totals = self.functions.entry(call.name).or_default();
+            totals.total += self.clock - call.entered;
+        }
+    }
+
+    /// Adds the instruction at `index`, which took `elapsed`, to the innermost call.
+    pub(crate) fn record(&mut self, index: usize, elapsed: Duration) {
+        if self.instructions.len() <= index {
+            self.instructions.resize(index + 1, (0, Duration::ZERO));
+        }
+        let (count, time) = &mut self.instructions[index];
+        *count += 1;
+        *time += elapsed;
+        self.clock += elapsed;
+        if let Some(call) = self.calls.last() {
+            if let Some(totals) = self.functions.get_mut(&call.name) {
+                totals.own += elapsed;
+            }
+        }
+        let stack = if self.path.is_empty() {
+            TOP_LEVEL
+        } else {
+            &self.path
+        };
+        match self.stacks.get_mut(stack) {
+            Some(time) => *time += elapsed,
+            None => {
+                self.stacks.insert(stack.to_string(), elapsed);
+            }
+        }
+    }
+
+    /// What was recorded so far, with the instructions of `bytecode` grouped by opcode.
+    /// Calls still in progress count up to now.
+    pub(crate) fn profile(&self, bytecode: &[ByteCode]) -> Profile {
+        let mut opcodes: HashMap<String, OpcodeStats> = HashMap::new();
+        for (byte, (count, time)) in bytecode.iter().zip(self.instructions.iter()) {
+            if *count == 0 {
+                continue;
+            }
+            let name = opcode_name(byte);
+            let stats = opcodes.entry(name.clone()).or_insert(OpcodeStats {
+                opcode: name,
+                count: 0,
+                time: Duration::ZERO,
+            });
+            stats.count += count;
+            stats.time += *time;
+        }
+        let mut functions: Vec<FunctionStats> = self
+            .functions
+            .iter()
+            .map(|(name, totals)| {
+                let open = self.calls.iter().find(|call| call.name == *name);
+                FunctionStats {
+                    name: name.clone(),
+                    calls: totals.calls,
+                    total: totals.total
+                        + open.map_or(Duration::ZERO, |call| self.clock - call.entered),
+                    own: totals.own,
+                }
+            })
+            .collect();
+        functions.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+        let mut opcodes: Vec<OpcodeStats> = opcodes.into_values().collect();
+        opcodes.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.opcode.cmp(&b.opcode)));
+        let mut stacks: Vec<(String, Duration)> = self
+            .stacks
+            .iter()
+            .map(|(stack, time)| (stack.clone(), *time))
+            .collect();
+        stacks.sort();
+        Profile {
+            instructions: self.instructions.iter().map(|(count, _)| count).sum(),
+            time: self.clock,
+            functions,
+            opcodes,
+            stacks,
+        }
+    }
+}
+
+/// The name of an instruction without its operands, as in `JMPTO`.
+fn opcode_name(byte: &ByteCode) -> String {
+    let text = format!("{:?}", byte);
+    match text.find('(') {
+        Some(end) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+/// A profile of a run, see [`ByteCodeMachine::profile`](crate::runtime::vm::ByteCodeMachine::profile).
+/// Shown, it is a report of the functions and opcodes that took the most time.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Instructions run.
+    pub instructions: u64,
+    /// Time the instructions took.
+    pub time: Duration,
+    /// Every function called, the one whose calls took longest first.
+    pub functions: Vec<FunctionStats>,
+    /// Every opcode run, the one that took longest first.
+    pub opcodes: Vec<OpcodeStats>,
+    /// Time spent in each stack of calls, as `main;outer;inner`, in order.
+    pub stacks: Vec<(String, Duration)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionStats {
+    pub name: String,
+    pub calls: u64,
+    /// Time from entering to leaving its calls, callees included.
+    pub total: Duration,
+    /// Time running its own instructions.
+    pub own: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeStats {
+    pub opcode: String,
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Profile {
+    /// The profile as JSON, with times in microseconds.
+    pub fn to_json(&self) -> serde_json::Value {
+        let micros = |time: &Duration| time.as_secs_f64() * 1e6;
+        let functions: Vec<serde_json::Value> = self
+            .functions
+            .iter()
+            .map(|function| {
+                json!({
+                    "name": function.name,
+                    "calls": function.calls,
+                    "total_us": micros(&function.total),
+                    "self_us": micros(&function.own),
+                })
+            })
+            .collect();
+        let opcodes: Vec<serde_json::Value> = self
+            .opcodes
+            .iter()
+            .map(|opcode| {
+                json!({
+                    "opcode": opcode.opcode,
+                    "count": opcode.count,
+                    "time_us": micros(&opcode.time),
+                })
+            })
+            .collect();
+        json!({
+            "instructions": self.instructions,
+            "time_us": micros(&self.time),
+            "functions": functions,
+            "opcodes": opcodes,
+        })
+    }
+
+    /// The stacks of calls one per line, as `main;outer;inner 1200` with the time
+    /// in nanoseconds, for flame graph tools to read.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_nanos()))
+            .collect()
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let millis = |time: &Duration| format!("{:.3}ms", time.as_secs_f64() * 1e3);
+        writeln!(
+            f,
+            "Ran {} instructions in {}",
+            self.instructions,
+            millis(&self.time)
+        )?;
+        let width = self
+            .functions
+            .iter()
+            .map(|function| function.name.len())
+            .chain(self.opcodes.iter().map(|opcode| opcode.opcode.len()))
+            .max()
+            .unwrap_or(0)
+            .max("Function".len());
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<width$} {:>10} {:>12} {:>12}",
+            "Function", "Calls", "Total", "Self"
+        )?;
+        for function in self.functions.iter() {
+            writeln!(
+                f,
+                "{:<width$} {:>10} {:>12} {:>12}",
+                function.name,
+                function.calls,
+                millis(&function.total),
+                millis(&function.own)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<width$} {:>10} {:>12}", "Opcode", "Count", "Time")?;
+        for opcode in self.opcodes.iter() {
+            writeln!(
+                f,
+                "{:<width$} {:>10} {:>12}",
+                opcode.opcode,
+                opcode.count,
+                millis(&opcode.time)
+            )?;
+        }
+        Ok(())
+    }
+}
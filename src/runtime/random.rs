@@ -0,0 +1,40 @@
+//! The generator behind `random()` and `random_int()`.
+//!
+//! SplitMix64: tiny, fast and good enough for games and simulations, and it
+//! reproduces the same sequence for the same seed on every platform.
+
+/// Pseudo-random number generator state of one VM.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Seeded from the clock, for runs that don't ask for a fixed seed.
+    pub fn from_clock() -> Self {
+        Rng::new((super::platform::unix_millis() * 1e6) as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// A float in `[0, 1)` from 64 random bits.
+pub fn unit_float(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// An integer in `lo..=hi` from 64 random bits; `lo` must not exceed `hi`.
+pub fn int_in_range(bits: u64, lo: i64, hi: i64) -> i64 {
+    let span = (hi as i128 - lo as i128 + 1) as u128;
+    (lo as i128 + (bits as u128 % span) as i128) as i64
+}
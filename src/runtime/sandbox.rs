@@ -0,0 +1,110 @@
+//! What a program may do besides computing, for hosts running scripts they don't
+//! trust: which builtins reaching outside the VM it may call, how long it may run and
+//! how much memory its objects may take.
+//!
+//! A denied builtin fails like any other runtime error, and so does going past the
+//! memory limits, which a program can recover from by letting go of objects. Running
+//! past a time limit stops the program with
+//! [`RuntimeErrorKind::ResourceLimitExceeded`], which no `try` catches.
+//!
+//! [`RuntimeErrorKind::ResourceLimitExceeded`]: crate::runtime::debug::RuntimeErrorKind::ResourceLimitExceeded
+
+use std::{fmt::Display, time::Duration};
+
+/// A kind of access to the world outside the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Reading, writing and listing files.
+    FileIo,
+    /// The process: its arguments and exiting it.
+    Process,
+    /// The network. No builtin reaches it; a host's natives that do can check
+    /// [`ByteCodeMachine::allows`](crate::runtime::vm::ByteCodeMachine::allows).
+    Network,
+}
+
+impl Capability {
+    /// The capability the builtin `name` needs, if it needs one.
+    pub fn of_builtin(name: &str) -> Option<Capability> {
+        match name {
+            "open" | "read_n" | "write" | "seek" | "close" | "read_file" | "write_file"
+            | "append_file" | "file_exists" | "delete_file" | "list_dir" => {
+                Some(Capability::FileIo)
+            }
+            "args" | "exit" => Some(Capability::Process),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::FileIo => write!(f, "file access"),
+            Capability::Process => write!(f, "process access"),
+            Capability::Network => write!(f, "network access"),
+        }
+    }
+}
+
+/// What a [`ByteCodeMachine`](crate::runtime::vm::ByteCodeMachine) lets the program
+/// do. The default allows everything and sets no limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    pub allow_file_io: bool,
+    pub allow_process: bool,
+    pub allow_network: bool,
+    /// Wall-clock time the program may run for, from its first instruction.
+    pub timeout: Option<Duration>,
+    /// Instructions the program may run, counting those of every call into it.
+    pub max_instructions: Option<u64>,
+    /// Estimated bytes the live arrays, maps, objects and the strings they hold may
+    /// take, see [`RefHeader::size`](crate::runtime::object::RefHeader::size).
+    pub max_heap_bytes: Option<usize>,
+    /// Arrays, maps, objects and other references that may be alive at once.
+    pub max_objects: Option<usize>,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            allow_file_io: true,
+            allow_process: true,
+            allow_network: true,
+            timeout: None,
+            max_instructions: None,
+            max_heap_bytes: None,
+            max_objects: None,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Denies every capability; the limits are left for the host to pick.
+    pub fn sandboxed() -> Self {
+        VmConfig {
+            allow_file_io: false,
+            allow_process: false,
+            allow_network: false,
+            ..VmConfig::default()
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::FileIo => self.allow_file_io,
+            Capability::Process => self.allow_process,
+            Capability::Network => self.allow_network,
+        }
+    }
+
+    /// Whether the program's running time or instructions are limited.
+    pub fn is_limited(&self) -> bool {
+        self.timeout.is_some() || self.max_instructions.is_some()
+    }
+
+    /// Whether the memory the program's objects take is limited.
+    pub fn limits_heap(&self) -> bool {
+        self.max_heap_bytes.is_some() || self.max_objects.is_some()
+    }
+}
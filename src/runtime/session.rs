@@ -0,0 +1,219 @@
+//! Record/replay of the nondeterministic inputs a program consumes.
+//!
+//! Every builtin that reads from the outside world (stdin, the RNG, the wall
+//! clock) goes through a [`Session`]. In live mode the value comes from the
+//! [`Platform`], in record mode it is additionally appended to a log, and in
+//! replay mode the value is taken from a previously recorded log instead.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::platform::Platform;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SessionEvent {
+    Stdin(Option<String>),
+    Random(u64),
+    NowMillis(i64),
+    Clock(f64),
+}
+
+impl SessionEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            SessionEvent::Stdin(_) => "stdin",
+            SessionEvent::Random(_) => "random",
+            SessionEvent::NowMillis(_) => "now_millis",
+            SessionEvent::Clock(_) => "clock",
+        }
+    }
+}
+
+enum SessionMode {
+    Live,
+    Recording {
+        path: PathBuf,
+        events: Vec<SessionEvent>,
+    },
+    Replaying {
+        path: PathBuf,
+        events: VecDeque<SessionEvent>,
+    },
+}
+
+pub struct Session {
+    mode: SessionMode,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::live()
+    }
+}
+
+impl Session {
+    pub fn live() -> Self {
+        Session {
+            mode: SessionMode::Live,
+        }
+    }
+
+    pub fn record<P: Into<PathBuf>>(path: P) -> Self {
+        Session {
+            mode: SessionMode::Recording {
+                path: path.into(),
+                events: Vec::new(),
+            },
+        }
+    }
+
+    pub fn replay<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let file = File::open(&path)
+            .map_err(|e| anyhow!("Failed to open session log '{}': {}", path.display(), e))?;
+        let mut events = VecDeque::new();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: SessionEvent = serde_json::from_str(&line).map_err(|e| {
+                anyhow!(
+                    "Malformed event on line {} of session log '{}': {}",
+                    line_no + 1,
+                    path.display(),
+                    e
+                )
+            })?;
+            events.push_back(event);
+        }
+        Ok(Session {
+            mode: SessionMode::Replaying { path, events },
+        })
+    }
+
+    /// Reads one line from stdin without its trailing newline, `None` on EOF. Live
+    /// input that doesn't come within `timeout` fails with
+    /// [`InputTimedOut`](super::platform::InputTimedOut).
+    pub fn stdin_line(
+        &mut self,
+        platform: &mut dyn Platform,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>> {
+        self.capture(
+            "stdin",
+            || match timeout {
+                Some(timeout) => platform.stdin_line_within(timeout),
+                None => platform.stdin_line(),
+            },
+            SessionEvent::Stdin,
+            |e| match e {
+                SessionEvent::Stdin(line) => Some(line),
+                _ => None,
+            },
+        )
+    }
+
+    /// Raw random bits. `live` is the generator used when not replaying.
+    pub fn random_bits<F: FnOnce() -> u64>(&mut self, live: F) -> Result<u64> {
+        self.capture(
+            "random",
+            || Ok(live()),
+            SessionEvent::Random,
+            |e| match e {
+                SessionEvent::Random(bits) => Some(bits),
+                _ => None,
+            },
+        )
+    }
+
+    /// Milliseconds since the unix epoch.
+    pub fn now_millis(&mut self, platform: &mut dyn Platform) -> Result<i64> {
+        self.capture(
+            "now_millis",
+            || platform.now_millis(),
+            SessionEvent::NowMillis,
+            |e| match e {
+                SessionEvent::NowMillis(ms) => Some(ms),
+                _ => None,
+            },
+        )
+    }
+
+    /// Monotonic seconds since the platform was set up.
+    pub fn clock(&mut self, platform: &mut dyn Platform) -> Result<f64> {
+        self.capture(
+            "clock",
+            || Ok(platform.clock()),
+            SessionEvent::Clock,
+            |e| match e {
+                SessionEvent::Clock(secs) => Some(secs),
+                _ => None,
+            },
+        )
+    }
+
+    /// Writes the recorded log to disk. A no-op for live and replay sessions.
+    pub fn finish(&mut self) -> Result<()> {
+        if let SessionMode::Recording { path, events } = &self.mode {
+            let file = File::create(path)
+                .map_err(|e| anyhow!("Failed to create session log '{}': {}", path.display(), e))?;
+            let mut writer = BufWriter::new(file);
+            for event in events {
+                writeln!(writer, "{}", serde_json::to_string(event)?)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn capture<T, L, W, U>(
+        &mut self,
+        requested: &'static str,
+        live: L,
+        wrap: W,
+        unwrap: U,
+    ) -> Result<T>
+    where
+        T: Clone,
+        L: FnOnce() -> Result<T>,
+        W: Fn(T) -> SessionEvent,
+        U: Fn(SessionEvent) -> Option<T>,
+    {
+        match &mut self.mode {
+            SessionMode::Live => live(),
+            SessionMode::Recording { events, .. } => {
+                let value = live()?;
+                events.push(wrap(value.clone()));
+                Ok(value)
+            }
+            SessionMode::Replaying { path, events } => {
+                let event = events.pop_front().ok_or_else(|| {
+                    anyhow!(
+                        "Session log '{}' exhausted: program requested {} input",
+                        path.display(),
+                        requested
+                    )
+                })?;
+                let kind = event.kind();
+                match unwrap(event) {
+                    Some(value) => Ok(value),
+                    None => bail!(
+                        "Session log '{}' diverged: program requested {} input but log contains {}",
+                        path.display(),
+                        requested,
+                        kind
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Calendar conversion behind `format_time()`.
+//!
+//! Timestamps are unix milliseconds and always rendered in UTC, so the output
+//! doesn't depend on the machine's time zone.
+
+use anyhow::{bail, Result};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Year, month (1-12) and day (1-31) of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Renders `millis` with a strftime-like `fmt`. Supported: `%Y %m %d %H %M %S`,
+/// `%L` (milliseconds) and `%%`.
+pub fn format_time(millis: i64, fmt: &str) -> Result<String> {
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    let of_day = millis.rem_euclid(MILLIS_PER_DAY);
+    let (hour, minute) = (of_day / 3_600_000, of_day / 60_000 % 60);
+    let (second, milli) = (of_day / 1000 % 60, of_day % 1000);
+
+    let mut out = String::with_capacity(fmt.len() + 8);
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('L') => out.push_str(&format!("{:03}", milli)),
+            Some('%') => out.push('%'),
+            Some(other) => bail!("format_time() doesn't support '%{}'", other),
+            None => bail!("format_time() format ends with a lone '%'"),
+        }
+    }
+    Ok(out)
+}
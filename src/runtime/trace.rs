@@ -0,0 +1,172 @@
+//! Instruction traces behind `--trace`: a record of every instruction the VM runs,
+//! for finding where compiled or optimized code goes wrong, which `abra trace`
+//! steps through afterwards.
+//!
+//! A trace file holds one [`TraceEntry`] as JSON per line. It is written as the
+//! program runs, or, with a ring size, only the last entries are kept and written
+//! once it ends: usually all that is needed to see how a program failed, without
+//! a log of every instruction before.
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// One instruction that ran.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Instructions run before this one.
+    pub step: u64,
+    pub pc: usize,
+    /// The function running it, or `<start>` outside any.
+    pub function: String,
+    /// Calls in progress.
+    pub depth: usize,
+    /// The instruction with its operands.
+    pub instruction: String,
+    /// The value on top of the operand stack after it ran.
+    pub top: Option<String>,
+    /// The error it failed with, caught or not.
+    pub error: Option<String>,
+    /// The statement it was compiled from, as `file:line:col`.
+    pub source: Option<String>,
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} pc {} {}{} {}",
+            self.step,
+            self.pc,
+            "  ".repeat(self.depth),
+            self.function,
+            self.instruction
+        )?;
+        if let Some(top) = &self.top {
+            write!(f, " => {}", top)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " !! {}", error)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, " @ {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+enum Sink {
+    Stream(BufWriter<File>),
+    Ring {
+        capacity: usize,
+        entries: VecDeque<TraceEntry>,
+    },
+}
+
+/// Where the VM records the instructions it runs, see [`ByteCodeMachine::set_tracer`].
+///
+/// [`ByteCodeMachine::set_tracer`]: crate::runtime::vm::ByteCodeMachine::set_tracer
+pub struct Tracer {
+    path: PathBuf,
+    sink: Sink,
+    steps: u64,
+}
+
+impl Tracer {
+    /// Writes every instruction to the file at `path` as it runs.
+    pub fn stream<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)
+            .map_err(|e| anyhow!("Failed to create trace '{}': {}", path.display(), e))?;
+        Ok(Tracer {
+            path,
+            sink: Sink::Stream(BufWriter::new(file)),
+            steps: 0,
+        })
+    }
+
+    /// Keeps the last `capacity` instructions, written to `path` by [`Tracer::finish`].
+    pub fn ring<P: Into<PathBuf>>(path: P, capacity: usize) -> Self {
+        Tracer {
+            path: path.into(),
+            sink: Sink::Ring {
+                capacity,
+                // Grows as entries come, since `capacity` may be far more than a
+                // short program runs.
+                entries: VecDeque::new(),
+            },
+            steps: 0,
+        }
+    }
+
+    /// Instructions recorded so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Records `entry`, numbering it as the next step.
+    pub(crate) fn record(&mut self, mut entry: TraceEntry) -> Result<()> {
+        entry.step = self.steps;
+        self.steps += 1;
+        match &mut self.sink {
+            Sink::Stream(writer) => writeln!(writer, "{}", serde_json::to_string(&entry)?)?,
+            Sink::Ring { capacity, entries } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                if *capacity > 0 {
+                    entries.push_back(entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out what is still buffered.
+    pub fn finish(&mut self) -> Result<()> {
+        let write_error =
+            |e: std::io::Error| anyhow!("Failed to write trace '{}': {}", self.path.display(), e);
+        match &mut self.sink {
+            Sink::Stream(writer) => writer.flush().map_err(write_error),
+            Sink::Ring { entries, .. } => {
+                let file = File::create(&self.path).map_err(write_error)?;
+                let mut writer = BufWriter::new(file);
+                for entry in entries.iter() {
+                    writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+                }
+                writer.flush().map_err(write_error)
+            }
+        }
+    }
+}
+
+/// Reads a trace written by a [`Tracer`].
+pub fn read_trace<P: Into<PathBuf>>(path: P) -> Result<Vec<TraceEntry>> {
+    let path = path.into();
+    let file = File::open(&path)
+        .map_err(|e| anyhow!("Failed to open trace '{}': {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (line_no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            anyhow!(
+                "Malformed entry on line {} of trace '{}': {}",
+                line_no + 1,
+                path.display(),
+                e
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
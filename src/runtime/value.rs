@@ -2,28 +2,37 @@ use std::{
     fmt::Display,
     hash::{Hash, Hasher},
     ops::{Add, Div, Mul, Sub},
+    rc::Rc,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    compiler::typecheck::{BOOL_TYPE, CHAR_TYPE, FLOAT_TYPE, INTEGER_TYPE, STRING_TYPE},
-    runtime::object::Ref,
+    compiler::typecheck::BIGINT_TYPE,
+    runtime::{bigint::BigInt, object::Ref},
 };
 use anyhow::*;
 use ordered_float::OrderedFloat;
 
-use crate::compiler::typecheck::{Composite, Primitives, Type};
+use crate::compiler::typecheck::{Primitives, Type};
 
 macro_rules! value_implements {
-    ($t:ty,$t_func:ident) => {
+    ($t:ty,$t_func:ident $(, $extra:pat => $extra_result:expr)* $(,)?) => {
         impl $t for Value {
             type Output = Value;
 
             fn $t_func(self, rhs: Self) -> Self::Output {
                 //assert_eq!(mem::discriminant(&self), mem::discriminant(&rhs));
                 match (self, rhs) {
+                    $($extra => $extra_result,)*
                     (Value::Integer(a), Value::Integer(b)) => Value::Integer(a.$t_func(b)),
+                    (Value::BigInt(a), Value::BigInt(b)) => Value::BigInt(a.$t_func(b)),
+                    (Value::BigInt(a), Value::Integer(b)) => {
+                        Value::BigInt(a.$t_func(BigInt::from(b)))
+                    }
+                    (Value::Integer(a), Value::BigInt(b)) => {
+                        Value::BigInt(BigInt::from(a).$t_func(b))
+                    }
                     (Value::Float(a), Value::Float(b)) => Value::Float(a.$t_func(b)),
                     (Value::Char(a), Value::Char(b)) => {
                         Value::Char((a as u8).$t_func(b as u8) as char)
@@ -43,6 +52,13 @@ macro_rules! cast_to {
                 Value::Char(x) => Ok(*x as u8 as $type),
                 Value::Float(x) => Ok(**x as $type),
                 Value::Integer(x) => Ok(*x as $type),
+                Value::BigInt(x) => x.to_i64().map(|x| x as $type).ok_or_else(|| {
+                    anyhow!(
+                        "Bad cast! bigint {} does not fit in {}",
+                        x,
+                        stringify!($type)
+                    )
+                }),
                 Value::String(x) => {
                     let type_cast = x.parse();
                     if type_cast.is_err() {
@@ -60,11 +76,12 @@ macro_rules! cast_to {
     };
 }
 
-#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Deserialize, Serialize, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Deserialize, Serialize, Eq, Hash)]
 pub enum StaticValue {
     #[default]
     Null,
     Integer(i64),
+    BigInt(BigInt),
     Float(OrderedFloat<f64>),
     Char(char),
     Bool(bool),
@@ -78,6 +95,7 @@ impl Display for StaticValue {
             StaticValue::Null => write!(f, ""),
             StaticValue::Bool(x) => write!(f, "{}", x),
             StaticValue::Integer(x) => write!(f, "{}", x),
+            StaticValue::BigInt(x) => write!(f, "{}", x),
             StaticValue::Float(x) => write!(f, "{}", x),
             StaticValue::Char(x) => write!(f, "{}", x),
         }
@@ -90,6 +108,12 @@ impl From<i64> for StaticValue {
     }
 }
 
+impl From<BigInt> for StaticValue {
+    fn from(value: BigInt) -> Self {
+        StaticValue::BigInt(value)
+    }
+}
+
 impl From<f64> for StaticValue {
     fn from(value: f64) -> Self {
         StaticValue::Float(OrderedFloat(value))
@@ -169,14 +193,15 @@ impl TryInto<String> for StaticValue {
     }
 }
 
-impl Into<Value> for StaticValue {
-    fn into(self) -> Value {
-        match self {
-            StaticValue::String(string) => Value::String(string),
+impl From<StaticValue> for Value {
+    fn from(value: StaticValue) -> Value {
+        match value {
+            StaticValue::String(string) => Value::String(string.into()),
             StaticValue::Null => Value::Null,
             StaticValue::Bool(b) => Value::Bool(b),
             StaticValue::Char(c) => Value::Char(c),
             StaticValue::Integer(i) => Value::Integer(i),
+            StaticValue::BigInt(i) => Value::BigInt(i),
             StaticValue::Float(f) => Value::Float(f),
             //StaticValue::Object(_) => Value::Null,
         }
@@ -187,11 +212,12 @@ impl TryFrom<Value> for StaticValue {
     type Error = anyhow::Error;
     fn try_from(value: Value) -> Result<Self, Self::Error> {
         Ok(match value {
-            Value::String(s) => StaticValue::String(s),
+            Value::String(s) => StaticValue::String(s.to_string()),
             Value::Null => StaticValue::Null,
             Value::Bool(b) => StaticValue::Bool(b),
             Value::Char(c) => StaticValue::Char(c),
             Value::Integer(i) => StaticValue::Integer(i),
+            Value::BigInt(i) => StaticValue::BigInt(i),
             Value::Float(f) => StaticValue::Float(f),
             x => bail!("{x:?} cannot be converted to StaticValue"),
         })
@@ -203,10 +229,12 @@ pub enum Value {
     #[default]
     Null,
     Integer(i64),
+    BigInt(BigInt),
     Float(OrderedFloat<f64>),
     Char(char),
     Bool(bool),
-    String(String),
+    /// Shared, so copying a string onto the stack or into a variable doesn't copy its text.
+    String(Rc<str>),
     Ref(Ref),
 }
 
@@ -259,7 +287,7 @@ impl TryInto<String> for Value {
 
     fn try_into(self) -> Result<String, Self::Error> {
         match self {
-            Value::String(x) => Ok(x),
+            Value::String(x) => Ok(x.to_string()),
             x => bail!("{x:?} cannot be converted to String"),
         }
     }
@@ -276,12 +304,30 @@ impl TryInto<Ref> for Value {
     }
 }
 
+/// The elements of an array.
+impl TryInto<Vec<Value>> for Value {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<Value>, Self::Error> {
+        match self {
+            Value::Ref(x) if !x.is_null() => x.array_values(),
+            x => bail!("{x:?} cannot be converted to Vec"),
+        }
+    }
+}
+
 impl From<i64> for Value {
     fn from(value: i64) -> Self {
         Value::Integer(value)
     }
 }
 
+impl From<BigInt> for Value {
+    fn from(value: BigInt) -> Self {
+        Value::BigInt(value)
+    }
+}
+
 impl From<f64> for Value {
     fn from(value: f64) -> Self {
         Value::Float(OrderedFloat(value))
@@ -302,7 +348,7 @@ impl From<bool> for Value {
 
 impl From<String> for Value {
     fn from(value: String) -> Self {
-        Value::String(value)
+        Value::String(value.into())
     }
 }
 
@@ -312,6 +358,13 @@ impl<'a> From<&'a str> for Value {
     }
 }
 
+/// `None` is `null`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
 const FLOAT_PRECISION_HASH: u64 = 256;
 
 //impl Eq for Value {}
@@ -341,9 +394,13 @@ impl Hash for Value {
                 5.hash(state); // Discriminant for String
                 s.hash(state);
             }
+            Value::BigInt(i) => {
+                7.hash(state); // Discriminant for BigInt
+                i.hash(state);
+            }
             Value::Ref(r) => {
                 6.hash(state); // Discriminant for Ref
-                r.get_uuid().hash(state); // Hash the unique identifier of the reference
+                r.hash_key(state); // Content for composites, identity or hash() for objects
             }
         }
     }
@@ -355,21 +412,22 @@ impl From<Type> for Value {
             Type::Primitive(p) => match p {
                         Primitives::String => "".into(),
                         Primitives::Integer => 0.into(),
+                        Primitives::BigInt => BigInt::zero().into(),
                         Primitives::Float => 0.0.into(),
                         Primitives::Bool => false.into(),
                         Primitives::Char => '\0'.into(),
                     },
             Type::Composite(_) => panic!("Cannot create default Value from Composite type directly. Instantiate a Ref instead."),
-            Type::Abra(_) => panic!("Cannot create default Value from Abra type directly. Instantiate a Ref instead."),
+            Type::Abra(_) | Type::Generic(_, _) => panic!("Cannot create default Value from Abra type directly. Instantiate a Ref instead."),
             Type::Algebraic(_) => panic!("Cannot create default Value from Algebraic type directly. Instantiate a Ref instead."),
-            Type::Null => Value::Null,
+            Type::Null | Type::Var(_) | Type::Function(_, _) | Type::Unknown => Value::Null,
         }
     }
 }
 
-impl Into<Type> for Value {
-    fn into(self) -> Type {
-        self.get_type()
+impl From<Value> for Type {
+    fn from(value: Value) -> Type {
+        value.get_type()
     }
 }
 
@@ -381,6 +439,7 @@ impl Value {
             Value::Char(x) => format!("{}", x),
             Value::Float(x) => format!("{}", x),
             Value::Integer(x) => format!("{}", x),
+            Value::BigInt(x) => format!("{}", x),
             Value::String(x) => format!("{}", x),
             Value::Ref(x) => format!("Ref<{}>", x.get_uuid()),
         }
@@ -392,13 +451,18 @@ impl Value {
                 Primitives::Bool => Ok(Value::Bool(self.cast_to_bool()?)),
                 Primitives::Char => Ok(Value::Char(self.cast_to_int()? as u8 as char)),
                 Primitives::Integer => Ok(Value::Integer(self.cast_to_int()?)),
+                Primitives::BigInt => Ok(Value::BigInt(self.cast_to_bigint()?)),
                 Primitives::Float => Ok(self.cast_to_float()?.into()),
-                Primitives::String => Ok(Value::String(format!("{}", &self))),
+                Primitives::String => Ok(Value::String(format!("{}", &self).into())),
             },
             Type::Composite(_) => Err(anyhow!("Cannot cast to a composite type directly.")),
-            Type::Abra(_) => Err(anyhow!("Cannot cast to an Abra type directly.")),
+            Type::Abra(_) | Type::Generic(_, _) => {
+                Err(anyhow!("Cannot cast to an Abra type directly."))
+            }
             Type::Null => Err(anyhow!("Cannot cast to a null type directly.")),
             Type::Algebraic(_) => Err(anyhow!("Cannot cast to an algebraic type directly.")),
+            Type::Var(_) | Type::Unknown => Err(anyhow!("Cannot cast to a type variable.")),
+            Type::Function(_, _) => Err(anyhow!("Cannot cast to a function type.")),
         }
     }
 
@@ -408,6 +472,7 @@ impl Value {
             Value::Char(x) => Ok(*x as u8 as f64),
             Value::Float(x) => Ok(**x),
             Value::Integer(x) => Ok(*x as f64),
+            Value::BigInt(x) => Ok(x.to_f64()),
             Value::String(x) => {
                 let type_cast = x.parse();
                 if type_cast.is_err() {
@@ -430,6 +495,7 @@ impl Value {
             Value::Char(_) => Type::Primitive(Primitives::Char),
             Value::Float(_) => Type::Primitive(Primitives::Float),
             Value::Integer(_) => Type::Primitive(Primitives::Integer), // Corrected from old system's Type::Float
+            Value::BigInt(_) => BIGINT_TYPE,
             Value::String(_) => Type::Primitive(Primitives::String),
             Value::Ref(rf) => {
                 // This will call the updated Ref::get_type which returns the new compiler::typecheck::Type
@@ -438,18 +504,56 @@ impl Value {
         }
     }
 
+    /// Whether the value belongs to `ty`, as an `is` test sees it: null, including
+    /// a deleted reference, only belongs to null.
+    pub fn is_of_type(&self, ty: &Type) -> bool {
+        let is_null = match self {
+            Value::Null => true,
+            Value::Ref(rf) => rf.is_null(),
+            _ => false,
+        };
+        match ty {
+            Type::Algebraic(_) => ty.members().into_iter().any(|m| self.is_of_type(m)),
+            Type::Null => is_null,
+            _ => !is_null && self.get_type().is_subtype_of(ty),
+        }
+    }
+
     cast_to!(cast_to_int, i64);
+
+    pub fn cast_to_bigint(&self) -> anyhow::Result<BigInt> {
+        match self {
+            Value::BigInt(x) => Ok(x.clone()),
+            Value::String(x) => x.parse().map_err(|_| {
+                anyhow!(
+                    "Bad cast error! tried to coerce string: {} to type bigint",
+                    x
+                )
+            }),
+            _ => Ok(BigInt::from(self.cast_to_int()?)),
+        }
+    }
     //cast_to!(cast_to_float, OrderedFloat<f64>);
     // cast_to!(cast_to_char, char);
 
+    /// Whether this is an `int` or `bigint` 0, which nothing may be divided by.
+    pub fn is_zero_integer(&self) -> bool {
+        match self {
+            Value::Integer(x) => *x == 0,
+            Value::BigInt(x) => x.is_zero(),
+            _ => false,
+        }
+    }
+
     pub fn cast_to_bool(&self) -> anyhow::Result<bool> {
         match self {
             Value::Null => Err(anyhow!("Null not expected")),
             Value::Bool(x) => Ok(*x),
             Value::Integer(x) => Ok(*x != 0),
+            Value::BigInt(x) => Ok(!x.is_zero()),
             Value::Float(x) => Ok(*x == 0.),
             Value::Char(x) => Ok(*x as u8 == 0),
-            Value::String(string) => Ok(string.len() != 0),
+            Value::String(string) => Ok(!string.is_empty()),
             Value::Ref(rf) => Ok(rf.is_null()),
         }
     }
@@ -511,6 +615,7 @@ impl Display for Value {
             Value::Null => write!(f, ""),
             Value::Bool(x) => write!(f, "{}", x),
             Value::Integer(x) => write!(f, "{}", x),
+            Value::BigInt(x) => write!(f, "{}", x),
             Value::Float(x) => write!(f, "{}", x),
             Value::Char(x) => write!(f, "{}", x),
             Value::Ref(x) => write!(f, "{}", x),
@@ -518,7 +623,12 @@ impl Display for Value {
     }
 }
 
-value_implements!(Add, add);
+// A string on either side of `+` concatenates, with the other operand as it prints.
+value_implements!(Add, add,
+    (Value::String(a), b) => Value::String(format!("{}{}", a, b).into()),
+    (a, Value::String(b)) => Value::String(format!("{}{}", a, b).into()),
+);
+// `string * int` is `ByteCodeMachine::repeat_string`, which checks the length.
 value_implements!(Mul, mul);
 value_implements!(Sub, sub);
 value_implements!(Div, div);
@@ -526,16 +636,20 @@ value_implements!(Div, div);
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Value::Null, Value::Null) => true,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b) || a == b,
+            (Value::Ref(a), Value::Ref(b)) => a.key_eq(b),
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::BigInt(a), Value::Integer(b)) | (Value::Integer(b), Value::BigInt(a)) => {
+                *a == BigInt::from(*b)
+            }
             (Value::Float(a), Value::Float(b)) => a == b,
             (Value::Char(a), Value::Char(b)) => a == b,
             (_, _) => false,
         }
     }
-    fn ne(&self, other: &Self) -> bool {
-        !(self == other)
-    }
 }
 
 impl PartialOrd for Value {
@@ -556,6 +670,9 @@ impl PartialOrd for Value {
         match (self, other) {
             (Value::Bool(a), Value::Bool(b)) => a > b,
             (Value::Integer(a), Value::Integer(b)) => a > b,
+            (Value::BigInt(a), Value::BigInt(b)) => a > b,
+            (Value::BigInt(a), Value::Integer(b)) => *a > BigInt::from(*b),
+            (Value::Integer(a), Value::BigInt(b)) => BigInt::from(*a) > *b,
             (Value::Float(a), Value::Float(b)) => a > b,
             (Value::Char(a), Value::Char(b)) => a > b,
             (_, _) => false,
@@ -566,6 +683,9 @@ impl PartialOrd for Value {
         match (self, other) {
             (Value::Bool(a), Value::Bool(b)) => a < b,
             (Value::Integer(a), Value::Integer(b)) => a < b,
+            (Value::BigInt(a), Value::BigInt(b)) => a < b,
+            (Value::BigInt(a), Value::Integer(b)) => *a < BigInt::from(*b),
+            (Value::Integer(a), Value::BigInt(b)) => BigInt::from(*a) < *b,
             (Value::Float(a), Value::Float(b)) => a < b,
             (Value::Char(a), Value::Char(b)) => a < b,
             (_, _) => false,
@@ -573,10 +693,16 @@ impl PartialOrd for Value {
     }
 
     fn ge(&self, other: &Self) -> bool {
-        self >= other
+        matches!(
+            self.partial_cmp(other),
+            Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)
+        )
     }
 
     fn le(&self, other: &Self) -> bool {
-        self <= other
+        matches!(
+            self.partial_cmp(other),
+            Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)
+        )
     }
 }
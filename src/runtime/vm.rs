@@ -1,15 +1,48 @@
 use crate::{
     compiler::{
-        typecheck::{AbraTypeDefinition, Type},
-        ByteCode, Code,
+        compile::FIELD_INITIALIZER,
+        typecheck::{
+            register_class, AbraTypeDefinition, Composite, FunctionSignature, Primitives,
+            SignatureProvider, Type,
+        },
+        BinaryOp, ByteCode, Code, Operand, SourceLocation,
+    },
+    errors::{AbraError, EmbedError},
+    runtime::{
+        debug::{
+            stack_entry, AssertionFailed, Exit, FrameReport, ResourceLimitExceeded, RuntimeError,
+            RuntimeErrorKind, SourcePosition, Thrown, REPORT_STACK_DEPTH,
+        },
+        heap::Heap,
+        inbuilt::{
+            generate_inbuilt_function_hashmap, native_body, native_signature, InbuiltFuncMap,
+            NativeFn, Natives,
+        },
+        platform::{default_platform, InputTimedOut, Platform},
+        profile::{Profile, Profiler},
+        random::Rng,
+        sandbox::{Capability, VmConfig},
+        session::Session,
+        trace::{TraceEntry, Tracer},
     },
-    runtime::inbuilt::generate_inbuilt_function_hashmap,
 };
 use anyhow::*;
-use std::{collections::HashMap, io::BufRead, rc::Rc, sync::Mutex};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{Read, Write},
+    rc::Rc,
+    sync::mpsc,
+    time::Duration,
+};
 
+#[cfg(feature = "table-dispatch")]
+use super::dispatch;
+#[cfg(feature = "jit")]
+use super::jit;
 use super::{
-    object::{Ref, RefHeader},
+    object::{slice_range, Ref, RefHeader},
     // types::{ObjectType, Type}, // Old type system import
     value::Value,
 };
@@ -25,38 +58,111 @@ use super::{
     R7 - LONG-LASTING REGISTER 7
     R8 - LONG-LASTING REGISTER 8
     R9 - LONG-LASTING REGISTER 9
-    RSI - STACK INDEX
-    RBI - BYTECODE INDEX
-    RSP - STACK FRAME POINTER
 */
 pub struct ByteCodeMachine {
-    bytecode: Vec<ByteCode>,
+    bytecode: Rc<[ByteCode]>,
+    /// `bytecode` decoded for the handler table, see `dispatch`.
+    #[cfg(feature = "table-dispatch")]
+    decoded: Rc<[Option<dispatch::Decoded>]>,
+    /// `Code::constants` converted once at load time, indexed by `PUSH`.
+    constants: Rc<[Value]>,
     labels: HashMap<String, usize>,
+    /// `Code::functions` resolved to entry addresses once at load time, indexed by `CALLFN`.
+    function_table: Vec<(String, Option<usize>)>,
+    /// `Code::local_names`, for showing a frame's slots in debug mode.
+    local_names: HashMap<String, Vec<String>>,
+    /// `Code::locations` and `Code::files`, for placing runtime errors in the source.
+    source_locations: Vec<Option<SourceLocation>>,
+    source_files: Vec<String>,
 
-    registers: [Value; 16],
+    registers: [Value; 10],
+    /// Index of the next instruction to run; a jump sets it to its target.
+    pc: usize,
     global_variables: HashMap<String, Value>,
     stack_frames: Vec<StackFrame>,
-    stack: [Value; 1028],
-
-    debug_mode: bool,
-    debug_run: bool,
-    debug_show_stack: bool,
-    debug_show_bytecode: bool,
-    debug_breakpoints: Vec<usize>,
+    /// Active `try` blocks, innermost last.
+    handlers: Vec<ErrorHandler>,
+    /// Operand stack; its length is the stack pointer.
+    stack: Vec<Value>,
+    /// Operand stack slots a program may use before it stops with a stack overflow.
+    max_stack_size: usize,
+    /// Nested calls a program may make before it stops with a stack overflow.
+    max_call_depth: usize,
+    /// What `int` arithmetic does when the result doesn't fit.
+    int_overflow: IntOverflow,
+    /// Which builtins reaching outside the VM the program may call, and its limits.
+    config: VmConfig,
+    /// Instructions run since the config was set.
+    instructions_run: u64,
+    /// Platform clock at the first of them, which the timeout counts from.
+    started: Option<f64>,
+    /// Instruction count at which to size the live objects again for the memory
+    /// limits, as arrays and maps grow without allocating.
+    next_heap_measurement: u64,
+    /// Every object the program allocated, for the garbage collector.
+    heap: Heap,
+    /// Native code (e.g. `sort` calling `compare`) running bytecode; while it is,
+    /// values may live only in Rust locals, so automatic collection waits.
+    native_depth: usize,
+    /// Times each instruction and call once `enable_profiling` is called.
+    profiler: Option<Profiler>,
+    /// Records each instruction run, see `set_tracer`.
+    tracer: Option<Tracer>,
     // This should now use the new AbraTypeDefinition from compiler::typecheck
     abra_types: Vec<AbraTypeDefinition>,
-    inbuilt_functions: HashMap<
-        String,
-        (
-            crate::compiler::typecheck::FunctionSignature,
-            Rc<dyn Fn(&mut ByteCodeMachine, u64) -> anyhow::Result<()>>,
-        ),
-    >,
+    /// Builtins and the natives a host registered, by name.
+    inbuilt_functions: InbuiltFuncMap,
+    session: Session,
+    /// Generator behind the random builtins; seeded from the clock unless
+    /// `set_random_seed` or the program's `random_seed` says otherwise.
+    rng: Rng,
+    /// Command-line arguments passed to the program, returned by `args()`.
+    args: Vec<String>,
+    /// Where output goes and input and time come from.
+    platform: Box<dyn Platform>,
+    /// Open file handles; a program refers to a file by its index here.
+    files: Vec<Option<File>>,
+    runtime_error: Option<RuntimeError>,
+    /// Native code for hot functions, once `enable_jit` is called.
+    #[cfg(feature = "jit")]
+    jit: Option<jit::Jit>,
 }
 
+/// Frame name for code run outside any function by `execute_from`.
+const TOPLEVEL_FRAME: &str = "<toplevel>";
+/// What an `int` operation does when its result doesn't fit in 64 bits, see
+/// [`ByteCodeMachine::set_int_overflow`]. `bigint` never overflows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntOverflow {
+    /// Stops the program with a runtime error.
+    #[default]
+    Error,
+    /// Wraps around, as two's complement.
+    Wrap,
+    /// Stays at the largest or smallest `int`.
+    Saturate,
+}
+
+/// How far `==` follows values nested in each other, which only values that contain
+/// themselves go past.
+const MAX_EQUALITY_DEPTH: usize = 1000;
+/// Default for [`ByteCodeMachine::set_max_stack_size`].
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1 << 20;
+/// Default for [`ByteCodeMachine::set_max_call_depth`].
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+/// How many instructions run between two looks at the clock for the timeout.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+/// Fewest instructions between two measurements of the heap for the memory limits.
+const HEAP_MEASURE_INTERVAL: u64 = 1024;
+
 struct StackFrame {
     name: Option<String>,
     local_variables: HashMap<String, Value>,
+    /// Locals by slot, see `ByteCode::LOADSLOT`; `local_variables` holds the ones kept
+    /// by name, like the REPL's top-level locals.
+    slots: Vec<Value>,
+    /// Arguments of the call the prologue's `DEFARG`s haven't bound yet, first one first.
+    arguments: VecDeque<Value>,
     object: Option<Ref>,
     bytecode_return_index: i64,
     stack_return_index: i64,
@@ -69,8 +175,10 @@ impl StackFrame {
         name: Option<T>,
     ) -> Self {
         StackFrame {
-            name: name.map_or(None, |s| Some(s.into())),
+            name: name.map(Into::into),
             local_variables: HashMap::new(),
+            slots: Vec::new(),
+            arguments: VecDeque::new(),
             object: None,
             bytecode_return_index: bytecode_ret_index,
             stack_return_index: stack_ret_index,
@@ -78,262 +186,1531 @@ impl StackFrame {
     }
 }
 
+/// Where execution continues when an error happens inside a `try`.
+struct ErrorHandler {
+    catch_address: usize,
+    /// Frames active when the `try` was entered; deeper ones are dropped on a catch.
+    frame_depth: usize,
+    stack_len: usize,
+    /// Locals of the `try`'s frame at the time; ones its body declared are dropped.
+    locals: HashSet<String>,
+    /// Native calls active at the time; an error can't unwind across a native call.
+    native_depth: usize,
+}
+
+impl SignatureProvider for ByteCodeMachine {
+    fn signatures(&self) -> Vec<FunctionSignature> {
+        self.inbuilt_functions.signatures()
+    }
+}
+
+fn function_table(
+    functions: Vec<String>,
+    labels: &HashMap<String, usize>,
+) -> Vec<(String, Option<usize>)> {
+    functions
+        .into_iter()
+        .map(|name| {
+            let address = labels.get(&name).copied();
+            (name, address)
+        })
+        .collect()
+}
+
+/// The bits an int is shifted by, which must leave some of its 64 in place.
+fn shift_amount(bits: i64) -> anyhow::Result<u32> {
+    match u32::try_from(bits).ok() {
+        Some(bits) if bits < i64::BITS => Ok(bits),
+        _ => bail!("Shift by {} bits is out of range; shift by 0 to 63", bits),
+    }
+}
+
+/// Rewrites jumps and calls to labels into their absolute forms, so the dispatch loop
+/// doesn't look labels up. Calls to user code go through `function_table`, growing it
+/// for labels it doesn't list yet; inbuilt calls keep their name.
+fn link(
+    bytecode: Vec<ByteCode>,
+    labels: &HashMap<String, usize>,
+    function_table: &mut Vec<(String, Option<usize>)>,
+    inbuilt_functions: &InbuiltFuncMap,
+) -> Rc<[ByteCode]> {
+    bytecode
+        .into_iter()
+        .map(|byte| match byte {
+            ByteCode::JMPTO(label) if labels.contains_key(&label) => {
+                ByteCode::JMPABS(labels[&label] as i64)
+            }
+            ByteCode::JITL(label) if labels.contains_key(&label) => {
+                ByteCode::JITA(labels[&label] as i64)
+            }
+            ByteCode::CALL(func, argc)
+                if labels.contains_key(&func) && !inbuilt_functions.contains_key(&func) =>
+            {
+                let index = match function_table.iter().position(|(name, _)| *name == func) {
+                    Some(index) => index,
+                    None => {
+                        let address = labels.get(&func).copied();
+                        function_table.push((func, address));
+                        function_table.len() - 1
+                    }
+                };
+                ByteCode::CALLFN(index, argc)
+            }
+            byte => byte,
+        })
+        .collect()
+}
+
 impl ByteCodeMachine {
-    pub fn new(code: Code, debug_mode: bool) -> Self {
+    pub fn new(code: Code) -> Self {
+        let mut function_table = function_table(code.functions, &code.labels);
+        let inbuilt_functions = generate_inbuilt_function_hashmap();
+        let bytecode = link(
+            code.bytecode,
+            &code.labels,
+            &mut function_table,
+            &inbuilt_functions,
+        );
+        code.types.iter().for_each(register_class);
         let mut slf = ByteCodeMachine {
-            bytecode: code.bytecode,
-            registers: [const { Value::Null }; 16],
-            labels: code
-                .labels
-                .into_iter()
-                .map(|(k, v)| (k.into(), v))
-                .collect(),
+            #[cfg(feature = "table-dispatch")]
+            decoded: dispatch::decode(&bytecode),
+            bytecode,
+            constants: code.constants.into_iter().map(Into::into).collect(),
+            registers: [const { Value::Null }; 10],
+            pc: 0,
+            labels: code.labels,
+            function_table,
+            local_names: code.local_names,
+            source_locations: code.locations,
+            source_files: code.files,
             global_variables: HashMap::new(),
             stack_frames: Vec::new(),
-            stack: [const { Value::Null }; 1028],
-            debug_mode,
-            debug_run: false,
-            debug_show_bytecode: false,
-            debug_show_stack: false,
-            debug_breakpoints: Vec::new(),
-            abra_types: Vec::new(),
-            inbuilt_functions: generate_inbuilt_function_hashmap(),
+            handlers: Vec::new(),
+            stack: Vec::new(),
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            int_overflow: IntOverflow::default(),
+            config: VmConfig::default(),
+            instructions_run: 0,
+            started: None,
+            next_heap_measurement: 0,
+            heap: Heap::default(),
+            native_depth: 0,
+            profiler: None,
+            tracer: None,
+            abra_types: code.types,
+            inbuilt_functions,
+            session: Session::live(),
+            rng: Rng::from_clock(),
+            args: Vec::new(),
+            platform: default_platform(),
+            files: Vec::new(),
+            runtime_error: None,
+            #[cfg(feature = "jit")]
+            jit: None,
         };
-        let start_index = slf.labels["_start"];
-        slf.registers[11] = Value::Integer(start_index as i64);
-        slf.registers[10] = Value::Integer(0);
-        if debug_mode {
-            println!("DEBUG MODE <Q/q - quit> <R/r - run> <N/n - next> <B/b - set breakpoint> <S/s - shows first 10 values on stack> <C/c - shows bytecode>");
-        }
+        // Code built up incrementally (the REPL) has no entry point yet.
+        let start_index = slf.labels.get("_start").copied().unwrap_or(0);
+        slf.pc = start_index;
         slf
     }
 
-    fn instance(&mut self, typ: Type, values: Vec<Value>) -> Ref {
-        Ref::instance_with(Rc::new(Mutex::new(RefHeader::instance_with_initializer(
-            typ,
-            values,
-            &self.abra_types,
-        ))))
+    /// Routes all nondeterministic inputs through `session` (record/replay).
+    pub fn set_session(&mut self, session: Session) {
+        self.session = session;
+    }
+
+    /// Restarts the random builtins at `seed`, making their values repeatable.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Next 64 bits from the generator, recorded or replayed by the session.
+    pub fn random_bits(&mut self) -> Result<u64> {
+        let rng = &mut self.rng;
+        self.session.random_bits(|| rng.next_u64())
     }
 
-    fn delete(&mut self, reference: Value) -> anyhow::Result<()> {
-        let rf = reference.expect_ref()?;
-        rf.delete();
+    /// Makes `args` what the program's `args()` returns.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Compiles functions to native code once they've been called `hot_calls` times,
+    /// as far as they only compute with integers and booleans.
+    #[cfg(feature = "jit")]
+    pub fn enable_jit(&mut self, hot_calls: u32) -> anyhow::Result<()> {
+        self.jit = Some(jit::Jit::new(hot_calls)?);
         Ok(())
     }
 
-    fn debug_mode(&mut self) -> bool {
-        use std::io::{stdin, Read};
-        let mut stdin_handle = stdin().lock();
-        let mut byte = [0_u8];
-        if self.debug_show_bytecode {
-            println!("Bytecode:");
-            let index = self.registers[11].expect_int().unwrap() as usize;
-            let (low_range, high_range) = (
-                0.max(index as i64 - 5) as usize,
-                self.bytecode.len().min(index + 5),
-            );
-            for i in low_range..high_range {
-                print!(
-                    "{} | {}",
-                    i,
-                    serde_json::to_string(&self.bytecode[i]).unwrap()
-                );
-                if i == index {
-                    println!(" << CURRENT");
-                } else {
-                    println!("");
-                }
-            }
+    /// Number of functions the JIT has compiled so far.
+    #[cfg(feature = "jit")]
+    pub fn jit_compiled_count(&self) -> usize {
+        self.jit.as_ref().map_or(0, jit::Jit::compiled_count)
+    }
+
+    pub fn session(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    /// Runs the program against `platform` instead of the target's default.
+    pub fn set_platform(&mut self, platform: Box<dyn Platform>) {
+        self.platform = platform;
+    }
+
+    pub fn platform(&mut self) -> &mut dyn Platform {
+        self.platform.as_mut()
+    }
+
+    /// Redirects everything the program prints (e.g. to capture it in tests).
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        *self.platform.stdout() = stdout;
+    }
+
+    pub fn stdout(&mut self) -> &mut dyn Write {
+        self.platform.stdout().as_mut()
+    }
+
+    /// The next line of input, recorded or replayed by the session. Waiting for it
+    /// counts towards the timeout.
+    pub fn stdin_line(&mut self) -> Result<Option<String>> {
+        let timeout = self.time_left()?;
+        let line = self.session.stdin_line(self.platform.as_mut(), timeout);
+        match line {
+            Err(error) if error.is::<InputTimedOut>() => Err(self.timed_out()),
+            line => line,
         }
-        if self.debug_show_stack {
-            println!("Stack:");
-            let stack_index = self.registers[10].expect_int().unwrap();
-            let mut i = stack_index;
-            while i >= 0 && i + 10 >= stack_index {
-                if i == stack_index {
-                    println!("{} | {} << HEAD", i, &self.stack[i as usize]);
-                } else {
-                    println!("{} | {}", i, &self.stack[i as usize]);
-                }
+    }
 
-                i -= 1;
-            }
+    /// Milliseconds since the unix epoch, recorded or replayed by the session.
+    pub fn now_millis(&mut self) -> Result<i64> {
+        self.session.now_millis(self.platform.as_mut())
+    }
+
+    /// Seconds on the platform's monotonic clock, recorded or replayed by the session.
+    pub fn clock(&mut self) -> Result<f64> {
+        self.session.clock(self.platform.as_mut())
+    }
+
+    /// Registers an open file and returns the handle the program uses for it.
+    pub fn open_file(&mut self, file: File) -> i64 {
+        self.files.push(Some(file));
+        (self.files.len() - 1) as i64
+    }
+
+    pub fn file(&mut self, handle: i64) -> Result<&mut File> {
+        self.files
+            .get_mut(handle as usize)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| anyhow!("Invalid or closed file handle {}", handle))
+    }
+
+    /// Up to `n` bytes from the file, fewer only at its end. Reading counts towards the
+    /// timeout: a pipe or a device may keep a read waiting for as long as it likes, so
+    /// with a timeout such a read runs on a thread of its own, left behind if the
+    /// timeout passes first.
+    pub(crate) fn read_file(&mut self, handle: i64, n: u64) -> Result<Vec<u8>> {
+        let timeout = self.time_left()?;
+        let file = self.file(handle)?;
+        // The buffer grows with what is read, since `n` may be far larger than the file.
+        let mut buffer = Vec::new();
+        let Some(left) = timeout.filter(|_| !file.metadata().is_ok_and(|m| m.is_file())) else {
+            file.take(n).read_to_end(&mut buffer)?;
+            return Ok(buffer);
+        };
+        let mut file = file.try_clone()?;
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let read = (&mut file).take(n).read_to_end(&mut buffer);
+            let _ = sender.send(read.map(|_| buffer));
+        });
+        match receiver.recv_timeout(left) {
+            Result::Ok(read) => Ok(read?),
+            Err(_) => Err(self.timed_out()),
         }
-        loop {
-            if self.debug_run {
-                let index = self.registers[11].expect_int().unwrap() as usize;
-                if self.debug_breakpoints.contains(&index) {
-                    self.debug_run = false;
-                    continue;
-                }
+    }
+
+    /// Closes the file; the handle is never reused.
+    pub fn close_file(&mut self, handle: i64) -> Result<()> {
+        self.file(handle)?;
+        self.files[handle as usize] = None;
+        Ok(())
+    }
+
+    /// Makes the host function `body` callable as `name`, with the parameter and
+    /// return types of the function type `signature`. Programs calling it must be
+    /// compiled with its signature, e.g. from [`Natives`] or from this machine, which
+    /// is a [`SignatureProvider`] for everything registered so far.
+    pub fn register_native<F>(
+        &mut self,
+        name: &str,
+        signature: Type,
+        body: F,
+    ) -> Result<(), EmbedError>
+    where
+        F: Fn(&mut ByteCodeMachine, Vec<Value>) -> anyhow::Result<Value> + 'static,
+    {
+        let signature = native_signature(name, signature)?;
+        self.insert_native(signature, Rc::new(body))
+    }
+
+    /// Registers every function of `natives`, see [`register_native`](Self::register_native).
+    pub fn register_natives(&mut self, natives: &Natives) -> Result<(), EmbedError> {
+        for (signature, body) in natives.iter() {
+            self.insert_native(signature.clone(), body.clone())?;
+        }
+        Result::Ok(())
+    }
+
+    fn insert_native(
+        &mut self,
+        signature: FunctionSignature,
+        body: NativeFn,
+    ) -> Result<(), EmbedError> {
+        if self.inbuilt_functions.contains_key(signature.name()) {
+            return Err(EmbedError::DuplicateNative(signature.name().to_string()));
+        }
+        let wrapped = native_body(&signature, body);
+        self.inbuilt_functions
+            .insert(signature.name().to_string(), (signature, wrapped));
+        Result::Ok(())
+    }
+
+    pub fn abra_type(&self, name: &str) -> Option<&AbraTypeDefinition> {
+        self.abra_types.iter().find(|def| def.name == name)
+    }
+
+    /// `class` followed by the classes it inherits from, nearest first.
+    fn ancestry(&self, class: &str) -> Vec<String> {
+        let mut classes = vec![class.to_string()];
+        while let Some(parent) = self
+            .abra_type(classes.last().unwrap())
+            .and_then(|def| def.parent.clone())
+        {
+            if classes.contains(&parent) {
                 break;
             }
-            stdin_handle.read_exact(&mut byte).unwrap();
-            let character: char = byte[0] as char;
-            //println!("{}",character);
-            match character {
-                'c' | 'C' => {
-                    self.debug_show_bytecode = !self.debug_show_bytecode;
-                }
-                's' | 'S' => {
-                    self.debug_show_stack = !self.debug_show_stack;
-                }
-                'b' | 'B' => {
-                    let mut string = String::new();
-                    stdin_handle.read_line(&mut string).unwrap();
-                    let stop_on: usize = string.trim().parse().unwrap();
-                    //println!("{}",string);
-                    self.debug_breakpoints.push(stop_on);
-                    continue;
-                }
-                'r' | 'R' => {
-                    self.debug_run = true;
-                    break;
-                }
-                'n' | 'N' => break,
-                'q' | 'Q' => return true,
-                _ => {}
-            }
+            classes.push(parent);
+        }
+        classes
+    }
+
+    /// The label and address of `method` as `class` defines or inherits it.
+    fn resolve_method(&self, class: &str, method: &str) -> Option<(String, usize)> {
+        self.ancestry(class).into_iter().find_map(|class| {
+            let name = format!("{}::{}", class, method);
+            let address = *self.labels.get(&name)?;
+            Some((name, address))
+        })
+    }
+
+    pub(crate) fn instance(&mut self, typ: Type, values: Vec<Value>) -> Ref {
+        let header = RefHeader::instance_with_initializer(typ, values, &self.abra_types);
+        self.heap.allocate(header)
+    }
+
+    /// Frees the objects the program can no longer reach, including cycles, and
+    /// returns how many there were. Does nothing while native code is running
+    /// bytecode, since the values it holds aren't visible to the collector.
+    pub fn collect_garbage(&mut self) -> usize {
+        if self.native_depth > 0 {
+            return 0;
         }
-        false
+        let frames = self.stack_frames.iter().flat_map(|frame| {
+            let receiver = frame.object.clone().map(Value::Ref);
+            let values = frame.local_variables.values().chain(frame.slots.iter());
+            let values = values.chain(frame.arguments.iter());
+            values.cloned().chain(receiver)
+        });
+        let roots: Vec<Value> = self
+            .stack
+            .iter()
+            .chain(self.registers.iter())
+            .chain(self.global_variables.values())
+            .cloned()
+            .chain(frames)
+            .collect();
+        self.heap.collect(roots.iter())
+    }
+
+    /// Collects garbage automatically after this many allocations (more once many
+    /// objects stay alive).
+    pub fn set_gc_threshold(&mut self, allocations: usize) {
+        self.heap.set_threshold(allocations);
+    }
+
+    /// Objects allocated by the program that haven't been freed yet.
+    pub fn live_objects(&self) -> usize {
+        self.heap.live_objects()
+    }
+
+    /// Estimated bytes the program's live objects take, see [`RefHeader::size`].
+    pub fn heap_bytes(&mut self) -> usize {
+        self.heap.measure()
     }
 
+    /// Runs the program and returns its exit code: what `main` returned, the code it
+    /// passed to `exit`, or 1 after an error, see [`ByteCodeMachine::runtime_error`].
     pub fn run(&mut self) -> usize {
         loop {
-            if self.debug_mode {
-                let q = self.debug_mode();
-                if q {
-                    return 1;
+            if let Some((exit_code, returned)) = self.advance() {
+                if returned {
+                    log::info!("Program exited successfully.");
                 }
+                return exit_code;
             }
-            match self.next() {
-                Result::Ok(true) => {
-                    self.registers[11] = self.registers[11].clone() + Value::Integer(1);
-                    continue;
-                }
-                Result::Ok(false) => {
-                    println!("Program exited successfully.");
-                    return self.pop_from_stack().unwrap().expect_int().unwrap() as usize;
-                }
+        }
+    }
+
+    /// Runs one instruction, for a debugger stepping through the program. Returns the
+    /// exit code once the program has ended, as [`ByteCodeMachine::run`] would.
+    pub fn run_instruction(&mut self) -> Option<usize> {
+        self.advance().map(|(exit_code, _)| exit_code)
+    }
+
+    /// Runs one instruction; once the program has ended, gives its exit code and
+    /// whether `main` returned it.
+    fn advance(&mut self) -> Option<(usize, bool)> {
+        match self.step() {
+            Result::Ok(true) => None,
+            // What `main` returned; a `main` that returns nothing exits with 0.
+            Result::Ok(false) => match self.pop_from_stack() {
+                Result::Ok(Value::Integer(code)) => Some((code as usize, true)),
+                _ => Some((0, true)),
+            },
+            Err(e) if e.is::<Exit>() => Some((e.downcast_ref::<Exit>().unwrap().0 as usize, false)),
+            Err(e) => {
+                // Callers decide how to show it, see `runtime_error`.
+                self.runtime_error = Some(self.build_runtime_error(&e));
+                Some((1, false))
+            }
+        }
+    }
+
+    /// Index of the instruction that runs next.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Calls in progress, `main` included.
+    pub fn call_depth(&self) -> usize {
+        self.stack_frames.len()
+    }
+
+    /// The calls in progress, innermost first, each at the instruction it will run
+    /// next or its call site.
+    pub fn backtrace(&self) -> Vec<FrameReport> {
+        self.frame_reports(self.pc as i64)
+    }
+
+    /// Named locals of the frame `depth` calls out from the innermost one, then its
+    /// unnamed slots as `slot N`.
+    pub fn frame_locals(&self, depth: usize) -> Vec<(String, Value)> {
+        let Some(frame) = self.stack_frames.iter().rev().nth(depth) else {
+            return Vec::new();
+        };
+        let names = frame
+            .name
+            .as_ref()
+            .and_then(|name| self.local_names.get(name));
+        let mut locals: Vec<(String, Value)> = frame
+            .local_variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        locals.sort_by(|a, b| a.0.cmp(&b.0));
+        locals.extend(frame.slots.iter().enumerate().map(|(slot, value)| {
+            let name = names.and_then(|names| names.get(slot));
+            let name = name.cloned().unwrap_or_else(|| format!("slot {}", slot));
+            (name, value.clone())
+        }));
+        locals
+    }
+
+    /// Global variables by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<(String, Value)> = self
+            .global_variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// `R0` to `R9`.
+    pub fn registers(&self) -> &[Value] {
+        &self.registers
+    }
+
+    /// Swaps in a newer build of the same program, keeping variables, frames and
+    /// objects. `code` must only have appended to what was loaded before.
+    pub fn load(&mut self, code: Code) {
+        self.function_table = function_table(code.functions, &code.labels);
+        self.source_locations = code.locations;
+        self.source_files = code.files;
+        self.constants = code.constants.into_iter().map(Into::into).collect();
+        self.bytecode = link(
+            code.bytecode,
+            &code.labels,
+            &mut self.function_table,
+            &self.inbuilt_functions,
+        );
+        #[cfg(feature = "table-dispatch")]
+        {
+            self.decoded = dispatch::decode(&self.bytecode);
+        }
+        self.labels = code.labels;
+        self.local_names = code.local_names;
+        code.types.iter().for_each(register_class);
+        self.abra_types = code.types;
+        // Compiled code may have inlined calls to what was just replaced.
+        #[cfg(feature = "jit")]
+        if let Some(jit) = self.jit.take() {
+            self.jit = jit::Jit::new(jit.hot_calls()).ok();
+        }
+    }
+
+    /// Runs from `start` up to the next `EXIT` inside a top-level frame that outlives
+    /// the call, so locals defined by one snippet are visible to the next. Returns the
+    /// value the snippet left on the stack, if any; the stack is emptied either way.
+    pub fn execute_from(&mut self, start: usize) -> anyhow::Result<Option<Value>> {
+        if self.stack_frames.is_empty() {
+            self.stack_frames
+                .push(StackFrame::new(-1, 0, Some(TOPLEVEL_FRAME)));
+        }
+        self.pc = start;
+        let outcome = loop {
+            match self.step() {
+                Result::Ok(true) => {}
+                Result::Ok(false) => break Ok(()),
                 Err(e) => {
-                    println!("An error occureed!\n {}", e);
-                    for stack in &self.stack_frames {
-                        println!(
-                            "From <{}>",
-                            stack.name.as_ref().unwrap_or(&"unknown".into())
-                        );
-                    }
-                    return 1;
+                    let report = self.build_runtime_error(&e).to_string();
+                    break Err(anyhow!("{}", report.trim_end()));
                 }
             }
+        };
+        let value = match outcome {
+            Result::Ok(()) if !self.stack.is_empty() => Some(self.pop_from_stack()?),
+            _ => None,
+        };
+        // A failed snippet can leave calls half-way; only the top-level frame survives.
+        self.stack_frames.truncate(1);
+        self.handlers.clear();
+        self.stack.clear();
+        outcome.map(|_| value)
+    }
+
+    /// The error that stopped the last `run`, if any.
+    pub fn runtime_error(&self) -> Option<&RuntimeError> {
+        self.runtime_error.as_ref()
+    }
+
+    fn source_position(&self, index: usize) -> Option<SourcePosition> {
+        let location = self.source_locations.get(index).copied().flatten()?;
+        Some(SourcePosition {
+            file: self.source_files.get(location.file)?.clone(),
+            line: location.line,
+            col: location.col,
+        })
+    }
+
+    /// One report per active frame, innermost first, the innermost at `position`.
+    fn frame_reports(&self, mut position: i64) -> Vec<FrameReport> {
+        let mut frames = Vec::with_capacity(self.stack_frames.len());
+        // Each frame remembers its caller's call site, so walk outward from `position`.
+        for frame in self.stack_frames.iter().rev() {
+            frames.push(FrameReport {
+                function: frame.name.clone().unwrap_or_else(|| "<unknown>".into()),
+                bytecode_index: position.max(0) as usize,
+                instruction: usize::try_from(position)
+                    .ok()
+                    .and_then(|index| self.bytecode.get(index).cloned()),
+                source: usize::try_from(position)
+                    .ok()
+                    .and_then(|index| self.source_position(index)),
+            });
+            position = frame.bytecode_return_index;
         }
+        frames
+    }
+
+    fn build_runtime_error(&self, error: &anyhow::Error) -> RuntimeError {
+        // `pc` has already moved past the failing instruction.
+        let frames = self.frame_reports(self.pc as i64 - 1);
+        let stack_top = self
+            .stack
+            .iter()
+            .rev()
+            .take(REPORT_STACK_DEPTH)
+            .map(stack_entry)
+            .collect();
+        let kind = if error.is::<Thrown>() {
+            RuntimeErrorKind::Thrown
+        } else if error.is::<AssertionFailed>() {
+            RuntimeErrorKind::Assertion
+        } else if error.is::<ResourceLimitExceeded>() {
+            RuntimeErrorKind::ResourceLimitExceeded
+        } else {
+            RuntimeErrorKind::Fault
+        };
+        let excerpt = frames
+            .first()
+            .and_then(|frame| frame.source.as_ref())
+            .and_then(|source| self.source_line(source));
+        RuntimeError {
+            kind,
+            message: format!("{:#}", error),
+            frames,
+            stack_top,
+            excerpt,
+        }
+    }
+
+    /// Text of the line `source` points at, read from its file. Code compiled from
+    /// text that isn't on disk has none, and neither does a VM without file access.
+    fn source_line(&self, source: &SourcePosition) -> Option<String> {
+        if !self.config.allow_file_io {
+            return None;
+        }
+        let text = std::fs::read_to_string(&source.file).ok()?;
+        let line = text.lines().nth(source.line.checked_sub(1)?)?;
+        Some(line.to_string())
     }
 
     pub fn pop_from_stack(&mut self) -> anyhow::Result<Value> {
-        let stack_index = self.registers[10].expect_int()? as usize;
-        let ret = Ok(self.stack[stack_index - 1].clone());
-        self.registers[10] = self.registers[10].clone() - Value::Integer(1);
-        //println!("{}",self.registers[10]);
-        ret
+        self.stack.pop().ok_or_else(|| anyhow!("Stack underflow"))
     }
 
     pub fn push_to_stack(&mut self, value: &Value) -> anyhow::Result<()> {
-        let stack_index = self.registers[10].expect_int()? as usize;
-        self.stack[stack_index] = value.clone();
-        self.registers[10] = self.registers[10].clone() + Value::Integer(1);
-        //println!("{}",self.registers[10]);
+        if self.stack.len() >= self.max_stack_size {
+            bail!(
+                "Stack overflow: more than {} values on the stack",
+                self.max_stack_size
+            );
+        }
+        self.stack.push(value.clone());
         Ok(())
     }
 
-    fn unwind_stack(&mut self) -> anyhow::Result<()> {
-        let stack_frame = self.stack_frames.pop().ok_or(anyhow!(
-            "Attempted to access stack frames while none are allocated!"
-        ))?;
-        self.registers[11] = Value::Integer(stack_frame.bytecode_return_index);
-        let current_stack_index = self.registers[10].expect_int()?;
-        for x in stack_frame.stack_return_index..current_stack_index {
-            self.stack[x as usize] = Value::Null;
-        }
-        self.registers[10] = Value::Integer(stack_frame.stack_return_index);
+    /// Enters a call, refusing once `max_call_depth` calls are active.
+    fn push_frame(&mut self, frame: StackFrame) -> anyhow::Result<()> {
+        if self.stack_frames.len() >= self.max_call_depth {
+            bail!(
+                "Stack overflow: more than {} nested calls",
+                self.max_call_depth
+            );
+        }
+        self.stack_frames.push(frame);
         Ok(())
     }
 
-    fn clone_value(&mut self, val: &Value) -> Value {
-        val.clone()
+    /// Caps the operand stack; pushing past it is a "stack overflow" runtime error.
+    pub fn set_max_stack_size(&mut self, slots: usize) {
+        self.max_stack_size = slots;
     }
 
-    fn next(&mut self) -> anyhow::Result<bool> {
-        let index = self.registers[11].expect_int()? as usize;
-        let code = self.bytecode[index].clone();
-        match code {
-            ByteCode::PUSH(v) => {
-                self.push_to_stack(&v.into())?;
-                Ok(true)
+    /// Caps how deeply calls may nest, so runaway recursion ends in a "stack
+    /// overflow" runtime error rather than exhausting memory.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Picks what `int` arithmetic does when its result doesn't fit in 64 bits.
+    pub fn set_int_overflow(&mut self, mode: IntOverflow) {
+        self.int_overflow = mode;
+    }
+
+    /// Restricts what the program may do, see [`VmConfig`]. The limits count from the
+    /// next instruction on. Native code isn't counted, so with a limit the JIT is off.
+    pub fn set_config(&mut self, config: VmConfig) {
+        self.config = config;
+        self.instructions_run = 0;
+        self.started = None;
+        self.next_heap_measurement = 0;
+    }
+
+    /// Starts timing every instruction and call from here on, see [`Profile`]. The
+    /// program runs considerably slower while it is profiled.
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// What the program spent its time on since `enable_profiling` was called.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.profile(&self.bytecode))
+    }
+
+    /// Records every instruction from here on with `tracer`; once the program ends,
+    /// [`Tracer::finish`] writes what it still holds.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// The tracer given to `set_tracer`, unless writing to it failed.
+    pub fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// The trace of the instruction at `index`, about to run in the innermost frame.
+    fn trace_entry(&self, index: usize) -> TraceEntry {
+        let function = match self.stack_frames.last() {
+            Some(frame) => frame.name.as_deref().unwrap_or("<unknown>"),
+            None => "<start>",
+        };
+        TraceEntry {
+            step: 0,
+            pc: index,
+            function: function.to_string(),
+            depth: self.stack_frames.len(),
+            instruction: format!("{:?}", self.bytecode[index]),
+            top: None,
+            error: None,
+            source: self.source_position(index).map(|source| source.to_string()),
+        }
+    }
+
+    /// Records `entry` of the instruction that just ran, failing with `error` if
+    /// given. Tracing stops if the trace can't be written.
+    fn trace(&mut self, mut entry: TraceEntry, error: Option<&anyhow::Error>) {
+        entry.top = self.stack.last().map(stack_entry);
+        entry.error = error.map(|error| format!("{:#}", error));
+        let Some(tracer) = self.tracer.as_mut() else {
+            return;
+        };
+        if let Err(e) = tracer.record(entry) {
+            log::warn!("Stopped tracing: {:#}", e);
+            self.tracer = None;
+        }
+    }
+
+    /// Whether the config lets the program use `capability`; natives a host registers
+    /// can ask before doing so.
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.config.allows(capability)
+    }
+
+    /// Fails once the program has run past its instruction budget or timeout.
+    fn count_instruction(&mut self) -> anyhow::Result<()> {
+        self.instructions_run += 1;
+        if let Some(max) = self.config.max_instructions {
+            if self.instructions_run > max {
+                let message = format!("ran more than {} instructions", max);
+                return Err(ResourceLimitExceeded(message).into());
             }
-            ByteCode::POP => {
-                self.pop_from_stack()?;
-                Ok(true)
+        }
+        if self.instructions_run % TIMEOUT_CHECK_INTERVAL == 1 {
+            self.time_left()?;
+        }
+        Ok(())
+    }
+
+    /// Time the program has left before the config's timeout, `None` without one.
+    /// Fails once the timeout has passed.
+    fn time_left(&mut self) -> anyhow::Result<Option<Duration>> {
+        let Some(timeout) = self.config.timeout else {
+            return Ok(None);
+        };
+        let now = self.platform.clock();
+        let started = *self.started.get_or_insert(now);
+        let left = timeout.as_secs_f64() - (now - started);
+        if left <= 0.0 {
+            return Err(self.timed_out());
+        }
+        Ok(Some(Duration::from_secs_f64(left)))
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        let timeout = self.config.timeout.unwrap_or_default();
+        ResourceLimitExceeded(format!("ran for longer than {:?}", timeout)).into()
+    }
+
+    /// Waits `millis` for `sleep`, or only until the timeout and then fails.
+    pub(crate) fn sleep(&mut self, millis: u64) -> anyhow::Result<()> {
+        match self.time_left()? {
+            Some(left) if left < Duration::from_millis(millis) => {
+                self.platform.sleep(left.as_millis() as u64);
+                Err(self.timed_out())
             }
-            ByteCode::ADD => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&(a + b))?;
-                Ok(true)
+            _ => {
+                self.platform.sleep(millis);
+                Ok(())
             }
-            ByteCode::SUB => {
-                let b = self.pop_from_stack()?;
-                let a = self.pop_from_stack()?;
-                self.push_to_stack(&(a - b))?;
-                Ok(true)
+        }
+    }
+
+    /// Fails when the live objects take more memory than the config allows, even
+    /// after collecting the garbage among them.
+    fn check_heap(&mut self) -> anyhow::Result<()> {
+        if !self.config.limits_heap() {
+            return Ok(());
+        }
+        if self.instructions_run >= self.next_heap_measurement {
+            // Measuring looks at every value, so it waits longer the more there are.
+            let values = self.heap.measure() / std::mem::size_of::<Value>();
+            self.next_heap_measurement =
+                self.instructions_run + HEAP_MEASURE_INTERVAL.max(values as u64);
+        }
+        if !self.over_heap_limit() {
+            return Ok(());
+        }
+        if self.collect_garbage() == 0 {
+            self.heap.measure();
+        }
+        if let Some(max) = self.config.max_heap_bytes {
+            if self.heap.bytes() > max {
+                bail!("Out of memory: objects take more than {} bytes", max);
             }
-            ByteCode::MULT => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&(a * b))?;
-                Ok(true)
+        }
+        if let Some(max) = self.config.max_objects {
+            if self.heap.objects() > max {
+                bail!("Out of memory: more than {} objects are alive", max);
             }
-            ByteCode::DIV => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&(a / b))?;
-                Ok(true)
+        }
+        Ok(())
+    }
+
+    /// Fails, as going past the heap limits does, when `bytes` more in `objects` new
+    /// objects wouldn't fit in them. Builtins making large values ask before making
+    /// them. Garbage isn't collected here, since the builtin may hold values the
+    /// collector can't see, but what was freed already is forgotten.
+    pub(crate) fn reserve_heap(&mut self, bytes: usize, objects: usize) -> anyhow::Result<()> {
+        let fits = |vm: &Self| {
+            let fit = |max: Option<usize>, used: usize, more: usize| {
+                max.is_none_or(|max| used.saturating_add(more) <= max)
+            };
+            fit(vm.config.max_heap_bytes, vm.heap.bytes(), bytes)
+                && fit(vm.config.max_objects, vm.heap.objects(), objects)
+        };
+        if fits(self) {
+            return Ok(());
+        }
+        self.heap.measure();
+        if let Some(max) = self.config.max_heap_bytes {
+            if self.heap.bytes().saturating_add(bytes) > max {
+                bail!("Out of memory: objects would take more than {} bytes", max);
             }
-            ByteCode::JMPTO(label) => {
-                let new_stack_index = self.labels[&label] as i64 - 1;
-                self.registers[11] = Value::Integer(new_stack_index);
-                Ok(true)
+        }
+        if let Some(max) = self.config.max_objects {
+            if self.heap.objects().saturating_add(objects) > max {
+                bail!("Out of memory: more than {} objects would be alive", max);
             }
-            ByteCode::JMPABS(indx) => {
-                self.registers[11] = Value::Integer(indx - 1);
-                Ok(true)
+        }
+        Ok(())
+    }
+
+    fn over_heap_limit(&self) -> bool {
+        let over = |max: Option<usize>, used: usize| max.is_some_and(|max| used > max);
+        over(self.config.max_heap_bytes, self.heap.bytes())
+            || over(self.config.max_objects, self.heap.objects())
+    }
+
+    /// Fails when the config denies what the builtin `name` needs.
+    fn check_capability(&self, name: &str) -> anyhow::Result<()> {
+        match Capability::of_builtin(name) {
+            Some(capability) if !self.allows(capability) => {
+                bail!("'{}' is not allowed: this VM denies {}", name, capability)
             }
-            ByteCode::JMPREL(offset) => {
-                self.registers[11] = Value::Integer(index as i64 + offset - 1);
-                Ok(true)
+            _ => Ok(()),
+        }
+    }
+
+    fn unwind_stack(&mut self) -> anyhow::Result<()> {
+        let stack_frame = self
+            .stack_frames
+            .pop()
+            .ok_or_else(|| anyhow!("Attempted to access stack frames while none are allocated!"))?;
+        self.pc = (stack_frame.bytecode_return_index + 1) as usize;
+        self.stack
+            .truncate(stack_frame.stack_return_index.max(0) as usize);
+        // Returning from inside a `try` leaves it.
+        let depth = self.stack_frames.len();
+        self.handlers.retain(|handler| handler.frame_depth <= depth);
+        Ok(())
+    }
+
+    /// Runs one instruction, handing an error to the innermost `try` that can catch
+    /// it; while profiling, times it too.
+    fn step(&mut self) -> anyhow::Result<bool> {
+        let Some(profiler) = self.profiler.as_mut() else {
+            return self.step_unprofiled();
+        };
+        let frames = &self.stack_frames;
+        profiler.follow_frames(frames.len(), |depth| {
+            frames[depth].name.as_deref().unwrap_or("<unknown>")
+        });
+        let index = self.pc;
+        // The platform's clock, since `Instant` isn't there on every target.
+        let started = self.platform.clock();
+        let outcome = self.step_unprofiled();
+        let elapsed = Duration::from_secs_f64((self.platform.clock() - started).max(0.0));
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(index, elapsed);
+        }
+        outcome
+    }
+
+    /// [`step`](Self::step) without the profiler.
+    fn step_unprofiled(&mut self) -> anyhow::Result<bool> {
+        let index = self.pc;
+        let traced = self.tracer.is_some().then(|| self.trace_entry(index));
+        let outcome = self.next().and_then(|running| {
+            self.check_heap()?;
+            Ok(running)
+        });
+        if let Some(entry) = traced {
+            self.trace(entry, outcome.as_ref().err());
+        }
+        match outcome {
+            Err(error) => self.catch(error),
+            outcome => outcome,
+        }
+    }
+
+    /// Continues at the innermost handler's `catch` with the error message on the
+    /// stack, or gives the error back when no handler of this native call is active.
+    fn catch(&mut self, error: anyhow::Error) -> anyhow::Result<bool> {
+        match self.handlers.last() {
+            Some(handler)
+                if handler.native_depth == self.native_depth
+                    && !error.is::<Exit>()
+                    && !error.is::<AssertionFailed>()
+                    && !error.is::<ResourceLimitExceeded>() => {}
+            _ => return Err(error),
+        }
+        let handler = self.handlers.pop().unwrap();
+        self.stack_frames.truncate(handler.frame_depth);
+        if let Some(frame) = self.stack_frames.last_mut() {
+            frame
+                .local_variables
+                .retain(|name, _| handler.locals.contains(name));
+        }
+        self.stack.truncate(handler.stack_len);
+        self.push_to_stack(&Value::String(format!("{:#}", error).into()))?;
+        self.pc = handler.catch_address;
+        Ok(true)
+    }
+
+    /// Runs the class method `method` on `receiver` to completion and returns its
+    /// result, or `Ok(None)` when the receiver's class doesn't define the method.
+    pub fn invoke_method(
+        &mut self,
+        receiver: &Ref,
+        method: &str,
+        args: Vec<Value>,
+    ) -> anyhow::Result<Option<Value>> {
+        if receiver.is_null() {
+            return Ok(None);
+        }
+        let resolved = match receiver.get_type() {
+            Type::Abra(class) => self.resolve_method(&class, method),
+            _ => None,
+        };
+        let (name, address) = match resolved {
+            Some(resolved) => resolved,
+            None => return Ok(None),
+        };
+        self.run_call(address, &name, Some(receiver.clone()), args)
+            .map(Some)
+    }
+
+    /// Calls the top-level function `name` with `args` and runs it to completion, for
+    /// a host embedding the VM. An error comes back as a [`RuntimeError`] and leaves
+    /// the machine as it was before the call.
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, AbraError> {
+        let address = self
+            .function_table
+            .iter()
+            .find(|(function, _)| function == name)
+            .and_then(|(_, address)| *address)
+            .ok_or_else(|| EmbedError::UnknownFunction(name.to_string()))?;
+        let return_index = self.pc;
+        let stack_base = self.stack.len();
+        let depth = self.stack_frames.len();
+        let result = self.run_call(address, name, None, args);
+        if let Err(error) = &result {
+            let error = self.build_runtime_error(error);
+            self.stack_frames.truncate(depth);
+            self.stack.truncate(stack_base);
+            self.pc = return_index;
+            return Err(error.into());
+        }
+        result.map_err(AbraError::recover)
+    }
+
+    /// Runs the code at `address` in a new frame until it returns, as native code
+    /// would: a `try` outside the call doesn't catch errors from inside it.
+    fn run_call(
+        &mut self,
+        address: usize,
+        name: &str,
+        receiver: Option<Ref>,
+        args: Vec<Value>,
+    ) -> anyhow::Result<Value> {
+        let return_index = self.pc;
+        let stack_base = self.stack.len() as i64;
+        let depth = self.stack_frames.len();
+        // Reports place the call at the instruction running now, the one before `pc`.
+        let mut frame = StackFrame::new(return_index as i64 - 1, stack_base, Some(name));
+        frame.arguments = args.into();
+        frame.object = receiver;
+        self.push_frame(frame)?;
+        self.pc = address;
+        let handlers = self.handlers.len();
+        self.native_depth += 1;
+        let finished = self.run_until_depth(depth, name);
+        self.native_depth -= 1;
+        if finished.is_err() {
+            // Handlers entered during the call can no longer be reached.
+            self.handlers.truncate(handlers);
+        }
+        finished?;
+        self.pc = return_index;
+        if self.stack.len() as i64 > stack_base {
+            return self.pop_from_stack();
+        }
+        Ok(Value::Null)
+    }
+
+    /// Calls the method at `address` on `receiver` from the instruction at `index`.
+    fn enter_method(
+        &mut self,
+        index: usize,
+        receiver: Ref,
+        name: String,
+        address: usize,
+        argc: u64,
+    ) -> anyhow::Result<bool> {
+        let mut frame = self.call_frame(index, name, argc)?;
+        frame.object = Some(receiver);
+        self.push_frame(frame)?;
+        self.pc = address;
+        Ok(true)
+    }
+
+    /// A frame for a call from the instruction at `index`, taking the call's `argc`
+    /// arguments off the stack.
+    fn call_frame(&mut self, index: usize, name: String, argc: u64) -> anyhow::Result<StackFrame> {
+        let mut arguments = VecDeque::with_capacity(argc as usize);
+        for _ in 0..argc {
+            arguments.push_front(self.pop_from_stack()?);
+        }
+        let stack_base = self.stack.len() as i64;
+        let mut frame = StackFrame::new(index as i64, stack_base, Some(name));
+        frame.arguments = arguments;
+        Ok(frame)
+    }
+
+    /// Runs the call to the function at `address` as native code, if the JIT has it
+    /// compiled and it doesn't give up. Returns whether it did.
+    #[cfg(feature = "jit")]
+    fn call_native(&mut self, address: usize, argc: u64) -> anyhow::Result<bool> {
+        let Some(jit) = self.jit.as_mut().filter(|_| !self.config.is_limited()) else {
+            return Ok(false);
+        };
+        let program = jit::Program {
+            bytecode: &self.bytecode,
+            constants: &self.constants,
+            function_table: &self.function_table,
+        };
+        let Some(function) = jit.hot_function(address, &program) else {
+            return Ok(false);
+        };
+        let Some(base) = self.stack.len().checked_sub(argc as usize) else {
+            return Ok(false);
+        };
+        let returns_value = function.returns_value();
+        let Some(result) = function.call(&self.stack[base..]) else {
+            return Ok(false);
+        };
+        self.stack.truncate(base);
+        if returns_value {
+            self.push_to_stack(&result)?;
+        }
+        Ok(true)
+    }
+
+    fn run_until_depth(&mut self, depth: usize, name: &str) -> anyhow::Result<()> {
+        while self.stack_frames.len() > depth {
+            if !self.step()? {
+                bail!("Program exited inside '{}'", name);
             }
-            ByteCode::JITA(indx) => {
-                let boolean = self.pop_from_stack()?.expect_bool()?;
-                if boolean {
-                    self.registers[11] = Value::Integer(indx - 1);
-                }
-                Ok(true)
+        }
+        Ok(())
+    }
+
+    /// Orders two values, dispatching to the class's `compare(other) -> int` for objects.
+    pub fn compare_values(&mut self, a: &Value, b: &Value) -> anyhow::Result<Option<Ordering>> {
+        if let Value::Ref(rf) = a {
+            if let Some(result) = self.invoke_method(rf, "compare", vec![b.clone()])? {
+                return Ok(Some(result.expect_int()?.cmp(&0)));
             }
-            ByteCode::JITL(label) => {
-                let boolean = self.pop_from_stack()?.expect_bool()?;
-                if boolean {
-                    let new_stack_index = self.labels[&label] as i64 - 1;
-                    self.registers[11] = Value::Integer(new_stack_index);
-                }
-                Ok(true)
+        }
+        Ok(a.partial_cmp(b))
+    }
+
+    /// `a == b` as the program sees it: plain values and strings by content; arrays,
+    /// maps, tuples, boxes and enum values by what they hold; objects by identity,
+    /// unless their class has an `equals(other) -> bool` method.
+    pub fn values_equal(&mut self, a: &Value, b: &Value) -> anyhow::Result<bool> {
+        self.values_equal_within(a, b, MAX_EQUALITY_DEPTH)
+    }
+
+    fn values_equal_within(&mut self, a: &Value, b: &Value, depth: usize) -> anyhow::Result<bool> {
+        let (x, y) = match (a, b) {
+            (Value::Ref(x), Value::Ref(y)) => (x, y),
+            _ => return Ok(a == b),
+        };
+        if x == y {
+            return Ok(true);
+        }
+        if x.is_null() || y.is_null() {
+            return Ok(false);
+        }
+        if let Some(equal) = self.invoke_method(x, "equals", vec![b.clone()])? {
+            return equal.cast_to_bool();
+        }
+        if depth == 0 {
+            bail!("Values are nested too deeply to compare, or contain themselves");
+        }
+        let depth = depth - 1;
+        if let (Result::Ok((x_tag, xs)), Result::Ok((y_tag, ys))) =
+            (x.variant_parts(), y.variant_parts())
+        {
+            return Ok(x_tag == y_tag && self.all_equal(&xs, &ys, depth)?);
+        }
+        if let (Result::Ok(xs), Result::Ok(ys)) = (x.array_values(), y.array_values()) {
+            return self.all_equal(&xs, &ys, depth);
+        }
+        if let (Result::Ok(xs), Result::Ok(ys)) = (x.tuple_values(), y.tuple_values()) {
+            return self.all_equal(&xs, &ys, depth);
+        }
+        if let (Result::Ok(x), Result::Ok(y)) = (x.boxed_value(), y.boxed_value()) {
+            return self.values_equal_within(&x, &y, depth);
+        }
+        if let (Result::Ok(entries), Result::Ok(others)) = (x.map_entries(), y.map_entries()) {
+            if entries.len() != others.len() {
+                return Ok(false);
             }
-            ByteCode::JITR(offset) => {
-                let boolean = self.pop_from_stack()?.expect_bool()?;
-                if boolean {
-                    self.registers[11] = Value::Integer(index as i64 + offset - 1);
+            for (key, value) in entries.iter() {
+                let Some(other) = y.map_value(key)? else {
+                    return Ok(false);
+                };
+                if !self.values_equal_within(value, &other, depth)? {
+                    return Ok(false);
                 }
+            }
+            return Ok(true);
+        }
+        // Different objects, functions, or references of different kinds.
+        Ok(false)
+    }
+
+    fn all_equal(&mut self, xs: &[Value], ys: &[Value], depth: usize) -> anyhow::Result<bool> {
+        if xs.len() != ys.len() {
+            return Ok(false);
+        }
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            if !self.values_equal_within(x, y, depth)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Text for `value` as the program sees it: objects go through their class's
+    /// `to_string()` when it has one, and otherwise show their contents, down to
+    /// the objects nested in them. An object inside itself shows as `...`.
+    pub fn display_value(&mut self, value: &Value) -> anyhow::Result<String> {
+        self.display_nested(value, &mut Vec::new())
+    }
+
+    /// [`ByteCodeMachine::display_value`] for a value inside the objects in `outer`.
+    fn display_nested(&mut self, value: &Value, outer: &mut Vec<usize>) -> anyhow::Result<String> {
+        let rf = match value {
+            Value::Ref(rf) if !rf.is_null() => rf,
+            _ => return Ok(value.to_string()),
+        };
+        if let Some(text) = self.invoke_method(rf, "to_string", Vec::new())? {
+            return text.try_into();
+        }
+        let uuid = rf.get_uuid();
+        if outer.contains(&uuid) {
+            return Ok("...".into());
+        }
+        outer.push(uuid);
+        let text = self.display_contents(rf, outer);
+        outer.pop();
+        text
+    }
+
+    fn display_contents(&mut self, rf: &Ref, outer: &mut Vec<usize>) -> anyhow::Result<String> {
+        if let Result::Ok((tag, payload)) = rf.variant_parts() {
+            if payload.is_empty() {
+                return Ok(tag);
+            }
+            return Ok(format!("{}({})", tag, self.display_all(&payload, outer)?));
+        }
+        if let Result::Ok(elements) = rf.array_values() {
+            return Ok(format!("[{}]", self.display_all(&elements, outer)?));
+        }
+        if let Result::Ok(values) = rf.tuple_values() {
+            return Ok(format!("({})", self.display_all(&values, outer)?));
+        }
+        if let Result::Ok(value) = rf.boxed_value() {
+            return Ok(format!("Box({})", self.display_nested(&value, outer)?));
+        }
+        if let Result::Ok(entries) = rf.map_entries() {
+            let mut shown = Vec::with_capacity(entries.len());
+            for (key, value) in entries.iter() {
+                // A string key in quotes, so `{"a": 1}` doesn't read like an object.
+                let text = match key {
+                    Value::String(key) => format!("{:?}", key),
+                    key => self.display_nested(key, outer)?,
+                };
+                shown.push((key, text, self.display_nested(value, outer)?));
+            }
+            // In key order, so the same map always prints the same; keys that don't
+            // order, such as strings, go by their text.
+            shown.sort_by(|(a, a_text, _), (b, b_text, _)| {
+                a.partial_cmp(b).unwrap_or_else(|| a_text.cmp(b_text))
+            });
+            let parts: Vec<String> = shown
+                .into_iter()
+                .map(|(_, key, value)| format!("{}: {}", key, value))
+                .collect();
+            return Ok(format!("{{{}}}", parts.join(", ")));
+        }
+        if let Result::Ok((class, fields)) = rf.object_fields() {
+            let mut parts = Vec::with_capacity(fields.len());
+            for (name, value) in fields.iter() {
+                parts.push(format!("{}: {}", name, self.display_nested(value, outer)?));
+            }
+            return Ok(format!("{}({})", class, parts.join(", ")));
+        }
+        Ok(rf.to_string())
+    }
+
+    fn display_all(&mut self, values: &[Value], outer: &mut Vec<usize>) -> anyhow::Result<String> {
+        let mut parts = Vec::with_capacity(values.len());
+        for value in values.iter() {
+            parts.push(self.display_nested(value, outer)?);
+        }
+        Ok(parts.join(", "))
+    }
+
+    /// Refreshes the cached `hash()` of an object about to be used as a map key.
+    fn prepare_map_key(&mut self, key: &Value) -> anyhow::Result<()> {
+        if let Value::Ref(rf) = key {
+            if let Some(hash) = self.invoke_method(rf, "hash", Vec::new())? {
+                rf.set_key_hash(hash.expect_int()?);
+            }
+        }
+        Ok(())
+    }
+
+    // The instructions below are shared by `next` and, with the `table-dispatch`
+    // feature, the handlers in `dispatch`.
+
+    pub(crate) fn push_constant(&mut self, index: usize) -> anyhow::Result<bool> {
+        let constants = Rc::clone(&self.constants);
+        self.push_to_stack(&constants[index])?;
+        Ok(true)
+    }
+
+    /// Runs `op` on its operands, see `ByteCode::BINARY`; the stack forms of the
+    /// instructions pass `Operand::Stack` for all three.
+    pub(crate) fn binary(
+        &mut self,
+        op: BinaryOp,
+        first: Operand,
+        second: Operand,
+        result: Operand,
+    ) -> anyhow::Result<bool> {
+        let first = self.read_operand(first)?;
+        let second = self.read_operand(second)?;
+        let value = self.binary_value(op, first, second)?;
+        self.write_operand(result, value)?;
+        Ok(true)
+    }
+
+    /// `a op b`, where `a` is the left-hand side, which the stack forms pop first.
+    fn binary_value(&mut self, op: BinaryOp, a: Value, b: Value) -> anyhow::Result<Value> {
+        if let (Value::Integer(x), Value::Integer(y)) = (&a, &b) {
+            if let Some(value) = self.integer_arithmetic(op, *x, *y)? {
+                return Ok(Value::Integer(value));
+            }
+        }
+        if matches!(op, BinaryOp::Div | BinaryOp::Mod) && b.is_zero_integer() {
+            bail!("Division by zero");
+        }
+        let accept: fn(Ordering) -> bool = match op {
+            BinaryOp::Add => {
+                // Objects and arrays joined to a string show as they would print.
+                return Ok(match (&a, &b) {
+                    (Value::String(_), Value::Ref(_)) | (Value::Ref(_), Value::String(_)) => {
+                        Value::String((self.display_value(&a)? + &self.display_value(&b)?).into())
+                    }
+                    _ => a + b,
+                });
+            }
+            BinaryOp::Sub => return Ok(a - b),
+            BinaryOp::Mult => {
+                return match (&a, &b) {
+                    (Value::String(text), Value::Integer(count))
+                    | (Value::Integer(count), Value::String(text)) => {
+                        self.repeat_string(text, *count)
+                    }
+                    _ => Ok(a * b),
+                };
+            }
+            BinaryOp::Div => return Ok(a / b),
+            BinaryOp::Mod => {
+                return Ok(match (&a, &b) {
+                    (Value::BigInt(_), _) | (_, Value::BigInt(_)) => {
+                        Value::BigInt(a.cast_to_bigint()? % b.cast_to_bigint()?)
+                    }
+                    _ => Value::Integer(a.expect_int()? % b.expect_int()?),
+                });
+            }
+            BinaryOp::Lesser => Ordering::is_lt,
+            BinaryOp::Greater => Ordering::is_gt,
+            BinaryOp::EqLess => Ordering::is_le,
+            BinaryOp::EqGreat => Ordering::is_ge,
+        };
+        let ordering = self.compare_values(&a, &b)?;
+        Ok(Value::Bool(ordering.is_some_and(accept)))
+    }
+
+    /// `text` repeated `count` times, for `string * int`; a negative count gives the
+    /// empty string. Fails rather than aborting when the result wouldn't fit.
+    fn repeat_string(&mut self, text: &str, count: i64) -> anyhow::Result<Value> {
+        let count = if text.is_empty() {
+            0
+        } else {
+            usize::try_from(count).unwrap_or(0)
+        };
+        let too_long = || anyhow!("A string repeated {} times is too long", count);
+        let len = text.len().checked_mul(count).ok_or_else(too_long)?;
+        self.reserve_heap(len, 0)?;
+        let mut repeated = String::new();
+        repeated.try_reserve_exact(len).map_err(|_| too_long())?;
+        for _ in 0..count {
+            repeated.push_str(text);
+        }
+        Ok(Value::String(repeated.into()))
+    }
+
+    /// `x op y` for the arithmetic operators, overflowing the way
+    /// [`ByteCodeMachine::set_int_overflow`] has it; `None` for the comparisons.
+    fn integer_arithmetic(&self, op: BinaryOp, x: i64, y: i64) -> anyhow::Result<Option<i64>> {
+        let (checked, wrapped, saturated, symbol) = match op {
+            BinaryOp::Add => (
+                x.checked_add(y),
+                x.wrapping_add(y),
+                x.saturating_add(y),
+                "+",
+            ),
+            BinaryOp::Sub => (
+                x.checked_sub(y),
+                x.wrapping_sub(y),
+                x.saturating_sub(y),
+                "-",
+            ),
+            BinaryOp::Mult => (
+                x.checked_mul(y),
+                x.wrapping_mul(y),
+                x.saturating_mul(y),
+                "*",
+            ),
+            BinaryOp::Div | BinaryOp::Mod if y == 0 => bail!("Division by zero"),
+            BinaryOp::Div => (
+                x.checked_div(y),
+                x.wrapping_div(y),
+                x.saturating_div(y),
+                "/",
+            ),
+            // Only `int::MIN % -1` fails to compute, and its remainder is 0 anyway.
+            BinaryOp::Mod => return Ok(Some(x.wrapping_rem(y))),
+            _ => return Ok(None),
+        };
+        let value = match (checked, self.int_overflow) {
+            (Some(value), _) => value,
+            (None, IntOverflow::Wrap) => wrapped,
+            (None, IntOverflow::Saturate) => saturated,
+            (None, IntOverflow::Error) => bail!(
+                "Integer overflow: {} {} {} doesn't fit in an int; use bigint for larger numbers",
+                x,
+                symbol,
+                y
+            ),
+        };
+        Ok(Some(value))
+    }
+
+    /// `-value` for numbers; `NEGATE` of a `bool` flips it, as `NOT` does.
+    pub(crate) fn negate(&mut self) -> anyhow::Result<bool> {
+        let value = match self.pop_from_stack()? {
+            Value::Integer(x) => Value::Integer(match (x.checked_neg(), self.int_overflow) {
+                (Some(negated), _) => negated,
+                (None, IntOverflow::Wrap) => x.wrapping_neg(),
+                (None, IntOverflow::Saturate) => x.saturating_neg(),
+                (None, IntOverflow::Error) => bail!(
+                    "Integer overflow: -({}) doesn't fit in an int; use bigint for larger numbers",
+                    x
+                ),
+            }),
+            Value::BigInt(x) => Value::BigInt(-x),
+            Value::Float(x) => Value::Float(-x),
+            other => Value::Bool(!other.cast_to_bool()?),
+        };
+        self.push_to_stack(&value)?;
+        Ok(true)
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> anyhow::Result<Value> {
+        match operand {
+            Operand::Stack => self.pop_from_stack(),
+            Operand::Slot(slot) => self.slot(slot as usize),
+            Operand::Const(index) => Ok(self.constants[index].clone()),
+        }
+    }
+
+    fn write_operand(&mut self, operand: Operand, value: Value) -> anyhow::Result<()> {
+        match operand {
+            Operand::Stack => self.push_to_stack(&value),
+            Operand::Slot(slot) => self.set_slot(slot as usize, value),
+            Operand::Const(_) => Err(anyhow!("Attempted to write to a constant!")),
+        }
+    }
+
+    pub(crate) fn stack_binary(&mut self, op: BinaryOp) -> anyhow::Result<bool> {
+        self.binary(op, Operand::Stack, Operand::Stack, Operand::Stack)
+    }
+
+    pub(crate) fn not(&mut self) -> anyhow::Result<bool> {
+        let val = self.pop_from_stack()?;
+        self.push_to_stack(&Value::Bool(!val.cast_to_bool()?))?;
+        Ok(true)
+    }
+
+    /// Pops the left integer, then the right one, and pushes `op` of them.
+    pub(crate) fn bitwise(
+        &mut self,
+        op: fn(i64, i64) -> anyhow::Result<i64>,
+    ) -> anyhow::Result<bool> {
+        let a = self.pop_from_stack()?.expect_int()?;
+        let b = self.pop_from_stack()?.expect_int()?;
+        self.push_to_stack(&Value::Integer(op(a, b)?))?;
+        Ok(true)
+    }
+
+    pub(crate) fn bitwise_not(&mut self) -> anyhow::Result<bool> {
+        let value = self.pop_from_stack()?.expect_int()?;
+        self.push_to_stack(&Value::Integer(!value))?;
+        Ok(true)
+    }
+
+    pub(crate) fn jump(&mut self, target: usize) -> anyhow::Result<bool> {
+        self.pc = target;
+        Ok(true)
+    }
+
+    pub(crate) fn jump_if(&mut self, target: usize) -> anyhow::Result<bool> {
+        if self.pop_from_stack()?.expect_bool()? {
+            self.pc = target;
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn load_slot(&mut self, slot: usize) -> anyhow::Result<bool> {
+        let value = self.slot(slot)?;
+        self.push_to_stack(&value)?;
+        Ok(true)
+    }
+
+    pub(crate) fn store_slot(&mut self, slot: usize) -> anyhow::Result<bool> {
+        let value = self.pop_from_stack()?;
+        self.set_slot(slot, value)?;
+        Ok(true)
+    }
+
+    fn slot(&self, slot: usize) -> anyhow::Result<Value> {
+        Ok(self
+            .stack_frames
+            .last()
+            .ok_or_else(|| anyhow!("Attempted to access stack frames while none are allocated!"))?
+            .slots
+            .get(slot)
+            .ok_or_else(|| anyhow!("Attempted to access an undefined variable!"))?
+            .clone())
+    }
+
+    fn set_slot(&mut self, slot: usize, value: Value) -> anyhow::Result<()> {
+        let frame = self
+            .stack_frames
+            .last_mut()
+            .ok_or_else(|| anyhow!("Attempted to access stack frames while none are allocated!"))?;
+        if slot >= frame.slots.len() {
+            frame.slots.resize(slot + 1, Value::Null);
+        }
+        frame.slots[slot] = value;
+        Ok(())
+    }
+
+    pub(crate) fn drop_slot(&mut self, slot: usize) -> anyhow::Result<bool> {
+        let frame = self
+            .stack_frames
+            .last_mut()
+            .ok_or_else(|| anyhow!("Attempted to access stack frames while none are allocated!"))?;
+        if let Some(value) = frame.slots.get_mut(slot) {
+            *value = Value::Null;
+        }
+        Ok(true)
+    }
+
+    fn clone_value(&mut self, val: &Value) -> Value {
+        val.clone()
+    }
+
+    fn next(&mut self) -> anyhow::Result<bool> {
+        // Between instructions every live value is on the stack or in a frame.
+        if self.heap.wants_collection() {
+            self.collect_garbage();
+        }
+        let index = self.pc;
+        self.pc = index + 1;
+        self.count_instruction()?;
+        #[cfg(feature = "table-dispatch")]
+        if let Some((handler, operand)) = self.decoded[index] {
+            return handler(self, operand);
+        }
+        // A handle on the program, so the instruction is borrowed rather than cloned.
+        let bytecode = Rc::clone(&self.bytecode);
+        match &bytecode[index] {
+            ByteCode::PUSH(constant) => self.push_constant(*constant),
+            ByteCode::POP => {
+                self.pop_from_stack()?;
                 Ok(true)
             }
+            ByteCode::ADD => self.stack_binary(BinaryOp::Add),
+            ByteCode::SUB => self.stack_binary(BinaryOp::Sub),
+            ByteCode::MULT => self.stack_binary(BinaryOp::Mult),
+            ByteCode::DIV => self.stack_binary(BinaryOp::Div),
+            ByteCode::JMPTO(label) => self.jump(self.labels[label]),
+            ByteCode::JMPABS(indx) => self.jump(*indx as usize),
+            ByteCode::JMPREL(offset) => self.jump((index as i64 + offset) as usize),
+            ByteCode::JITA(indx) => self.jump_if(*indx as usize),
+            ByteCode::JITL(label) => self.jump_if(self.labels[label]),
+            ByteCode::JITR(offset) => self.jump_if((index as i64 + offset) as usize),
             ByteCode::AND => {
                 let a = self.pop_from_stack()?;
                 let b = self.pop_from_stack()?;
@@ -352,41 +1729,24 @@ impl ByteCodeMachine {
                 self.push_to_stack(&Value::Bool(a.cast_to_bool()? ^ b.cast_to_bool()?))?;
                 Ok(true)
             }
-            ByteCode::NEGATE => {
-                let a = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(!a.cast_to_bool()?))?;
-                Ok(true)
-            }
+            ByteCode::BAND => self.bitwise(|a, b| Ok(a & b)),
+            ByteCode::BOR => self.bitwise(|a, b| Ok(a | b)),
+            ByteCode::BXOR => self.bitwise(|a, b| Ok(a ^ b)),
+            ByteCode::SHL => self.bitwise(|a, b| Ok(a << shift_amount(b)?)),
+            ByteCode::SHR => self.bitwise(|a, b| Ok(a >> shift_amount(b)?)),
+            ByteCode::BNOT => self.bitwise_not(),
+            ByteCode::NEGATE => self.negate(),
             ByteCode::EQUALS => {
                 let a = self.pop_from_stack()?;
                 let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(a == b))?;
-                Ok(true)
-            }
-            ByteCode::EQGREAT => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(a >= b))?;
-                Ok(true)
-            }
-            ByteCode::EQLESS => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(a <= b))?;
-                Ok(true)
-            }
-            ByteCode::GREATER => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(a > b))?;
-                Ok(true)
-            }
-            ByteCode::LESSER => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(a < b))?;
+                let equal = self.values_equal(&a, &b)?;
+                self.push_to_stack(&Value::Bool(equal))?;
                 Ok(true)
             }
+            ByteCode::EQGREAT => self.stack_binary(BinaryOp::EqGreat),
+            ByteCode::EQLESS => self.stack_binary(BinaryOp::EqLess),
+            ByteCode::GREATER => self.stack_binary(BinaryOp::Greater),
+            ByteCode::LESSER => self.stack_binary(BinaryOp::Lesser),
             ByteCode::DUP => {
                 let a = self.pop_from_stack()?;
                 self.push_to_stack(&a)?;
@@ -395,11 +1755,11 @@ impl ByteCodeMachine {
             }
             ByteCode::SAVEVARGLOBAL(name) => {
                 let a = self.pop_from_stack()?;
-                if self.global_variables.contains_key(&name) {
+                if self.global_variables.contains_key(name) {
                     *self
                         .global_variables
-                        .get_mut(&name)
-                        .ok_or(anyhow!("Bad variable name while saving a variable!"))? = a;
+                        .get_mut(name)
+                        .ok_or_else(|| anyhow!("Bad variable name while saving a variable!"))? = a;
                 } else {
                     self.global_variables.insert(name.to_string(), a);
                 }
@@ -408,8 +1768,8 @@ impl ByteCodeMachine {
             ByteCode::GETVARGLOBAL(name) => {
                 let value = self
                     .global_variables
-                    .get(&name)
-                    .ok_or(anyhow!("Attempted to access an undefined variable!"))?
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Attempted to access an undefined variable!"))?
                     .clone();
                 let cloned_val = self.clone_value(&value);
                 self.push_to_stack(&cloned_val)?;
@@ -423,37 +1783,46 @@ impl ByteCodeMachine {
                     ));
                 }
                 let a = self.pop_from_stack()?;
-                let b = self
-                    .stack_frames
-                    .last()
-                    .ok_or(anyhow!(
-                        "Attempted to access stack frames while none are allocated!"
-                    ))?
-                    .local_variables
-                    .contains_key(&name);
+                let frame = self.stack_frames.last().ok_or_else(|| {
+                    anyhow!("Attempted to access stack frames while none are allocated!")
+                })?;
+                let b = frame.local_variables.contains_key(name);
+                // Inside a method, names that aren't locals are the receiver's fields.
+                if let (false, Some(receiver)) = (b, &frame.object) {
+                    let field = Value::String(name.as_str().into());
+                    if receiver.get(&field).is_ok() {
+                        receiver.modify(&field, a)?;
+                        return Ok(true);
+                    }
+                }
                 if b {
                     *self
                         .stack_frames
                         .last_mut()
-                        .ok_or(anyhow!(
-                            "Attempted to access stack frames while none are allocated!"
-                        ))?
+                        .ok_or_else(|| {
+                            anyhow!("Attempted to access stack frames while none are allocated!")
+                        })?
                         .local_variables
-                        .get_mut(&name)
-                        .ok_or(anyhow!(
+                        .get_mut(name)
+                        .ok_or_else(|| {
+                            anyhow!(
                             "Attempted to access stack frame variables while none are allocated!"
-                        ))? = a;
+                        )
+                        })? = a;
                 } else {
                     self.stack_frames
                         .last_mut()
-                        .ok_or(anyhow!(
-                            "Attempted to access stack frames while none are allocated!"
-                        ))?
+                        .ok_or_else(|| {
+                            anyhow!("Attempted to access stack frames while none are allocated!")
+                        })?
                         .local_variables
                         .insert(name.to_string(), a);
                 }
                 Ok(true)
             }
+            ByteCode::LOADSLOT(slot) => self.load_slot(*slot as usize),
+            ByteCode::STORESLOT(slot) => self.store_slot(*slot as usize),
+            ByteCode::DROPSLOT(slot) => self.drop_slot(*slot as usize),
             ByteCode::GETVARLOCAL(name) => {
                 if self.stack_frames.is_empty() {
                     return Err(anyhow!(
@@ -461,58 +1830,175 @@ impl ByteCodeMachine {
                     ));
                 }
 
-                let value = self
-                    .stack_frames
-                    .last()
-                    .ok_or(anyhow!(
-                        "Attempted to access stack frames while none are allocated!"
-                    ))?
-                    .local_variables
-                    .get(&name)
-                    .ok_or(anyhow!("Attempted to access an undefined variable!"))?
-                    .clone();
+                let frame = self.stack_frames.last().ok_or_else(|| {
+                    anyhow!("Attempted to access stack frames while none are allocated!")
+                })?;
+                let value = match (frame.local_variables.get(name), &frame.object) {
+                    (Some(value), _) => value.clone(),
+                    // Inside a method, names that aren't locals are the receiver's fields.
+                    (None, Some(receiver)) => receiver
+                        .get(&Value::String(name.as_str().into()))
+                        .map_err(|_| anyhow!("Attempted to access an undefined variable!"))?,
+                    (None, None) => bail!("Attempted to access an undefined variable!"),
+                };
                 let cloned_val = self.clone_value(&value);
                 self.push_to_stack(&cloned_val)?;
 
                 Ok(true)
             }
             ByteCode::CALL(func, argc) => {
-                if self.inbuilt_functions.contains_key(&func) {
-                    self.inbuilt_functions.get(&func).unwrap().1.clone()(self, argc)?;
+                if self.inbuilt_functions.contains_key(func) {
+                    self.check_capability(func)?;
+                    self.inbuilt_functions.get(func).unwrap().1.clone()(self, *argc)?;
                     return Ok(true);
                 }
-                let mut argv = Vec::new();
-                for _ in 0..argc {
-                    argv.push(self.pop_from_stack()?);
+                let frame = self.call_frame(index, func.clone(), *argc)?;
+                self.push_frame(frame)?;
+                self.pc = self.labels[func];
+
+                Ok(true)
+            }
+            ByteCode::CALLFN(func_index, argc) => {
+                let (name, address) = self
+                    .function_table
+                    .get(*func_index)
+                    .ok_or_else(|| anyhow!("Call to unknown function #{}", func_index))?;
+                let address =
+                    address.ok_or_else(|| anyhow!("Function '{}' has no entry address", name))?;
+                let name = name.clone();
+                #[cfg(feature = "jit")]
+                if self.call_native(address, *argc)? {
+                    return Ok(true);
                 }
-                self.stack_frames.push(StackFrame::new(
-                    index as i64,
-                    self.registers[10].expect_int()?,
-                    Some(&func),
-                ));
-                let new_bc_index = self.labels[&func] as i64 - 1;
-                self.registers[11] = Value::Integer(new_bc_index);
+                let frame = self.call_frame(index, name, *argc)?;
+                self.push_frame(frame)?;
+                self.pc = address;
 
                 Ok(true)
             }
+            ByteCode::CALLVIRT(method, argc) => {
+                let receiver = self.pop_from_stack()?.expect_ref()?;
+                let label = receiver.method_label(method)?;
+                let resolved = match receiver.get_type() {
+                    Type::Abra(class) => self.resolve_method(&class, method),
+                    _ => None,
+                };
+                let (name, address) =
+                    resolved.ok_or_else(|| anyhow!("Method '{}' is not defined", label))?;
+                self.enter_method(index, receiver, name, address, *argc)
+            }
+            ByteCode::CALLSUPER(class, method, argc) => {
+                let receiver = self
+                    .stack_frames
+                    .last()
+                    .and_then(|frame| frame.object.clone())
+                    .ok_or_else(|| anyhow!("'super.{}' called outside a method", method))?;
+                let (name, address) = self
+                    .resolve_method(class, method)
+                    .ok_or_else(|| anyhow!("Method '{}::{}' is not defined", class, method))?;
+                self.enter_method(index, receiver, name, address, *argc)
+            }
+            ByteCode::CLOSURE(label, ty, captures) => {
+                let mut captured = Vec::with_capacity(captures.len());
+                for name in captures.iter().rev() {
+                    captured.push((name.clone(), self.pop_from_stack()?));
+                }
+                captured.reverse();
+                let closure = RefHeader::closure(label.clone(), ty.clone(), captured);
+                let rf = self.heap.allocate(closure);
+                self.push_to_stack(&Value::Ref(rf))?;
+                Ok(true)
+            }
+            ByteCode::CALLINDIRECT(argc) => {
+                let callee = self.pop_from_stack()?;
+                let (label, captured) = match &callee {
+                    Value::Ref(rf) if !rf.is_null() => rf.closure_parts()?,
+                    _ => bail!("Cannot call '{}'; it is not a function", callee),
+                };
+                let address = *self
+                    .labels
+                    .get(&label)
+                    .ok_or_else(|| anyhow!("Function '{}' is not defined", label))?;
+                let mut frame = self.call_frame(index, label, *argc)?;
+                // The body binds the captures first, as hidden arguments.
+                for (_, value) in captured.into_iter().rev() {
+                    frame.arguments.push_front(value);
+                }
+                self.push_frame(frame)?;
+                self.pc = address;
+                Ok(true)
+            }
             ByteCode::RET(return_value) => {
                 let mut returning_value: Option<Value> = None;
-                if return_value {
+                if *return_value {
                     returning_value = Some(self.pop_from_stack()?);
                 }
                 self.unwind_stack()?;
-                if return_value {
+                if *return_value {
                     self.push_to_stack(returning_value.as_ref().unwrap())?;
                 }
                 Ok(true)
             }
+            ByteCode::PUSHEH(label) => {
+                let catch_address = *self
+                    .labels
+                    .get(label)
+                    .ok_or_else(|| anyhow!("Label '{}' is not defined", label))?;
+                let locals = self
+                    .stack_frames
+                    .last()
+                    .map(|frame| frame.local_variables.keys().cloned().collect())
+                    .unwrap_or_default();
+                self.handlers.push(ErrorHandler {
+                    catch_address,
+                    frame_depth: self.stack_frames.len(),
+                    stack_len: self.stack.len(),
+                    locals,
+                    native_depth: self.native_depth,
+                });
+                Ok(true)
+            }
+            ByteCode::POPEH => {
+                self.handlers
+                    .pop()
+                    .ok_or_else(|| anyhow!("Left a 'try' block that was never entered"))?;
+                Ok(true)
+            }
+            ByteCode::THROW => {
+                let message: String = self.pop_from_stack()?.try_into()?;
+                Err(anyhow::Error::new(Thrown(message)))
+            }
             ByteCode::EXIT => Ok(false),
             ByteCode::INSTANCE(typ, argc) => {
                 let mut acc = Vec::new();
-                for _ in 0..argc {
+                for _ in 0..*argc {
                     acc.push(self.pop_from_stack()?);
                 }
-                let rf = self.instance(typ, acc);
+                acc.reverse();
+                let rf = match typ {
+                    // Objects get their field initializers, the parent's first, then are
+                    // set up by `init`.
+                    Type::Abra(class) => {
+                        let classes = self.ancestry(class);
+                        let rf = self.instance(typ.clone(), Vec::new());
+                        for class in classes.iter().rev() {
+                            let name = format!("{}::{}", class, FIELD_INITIALIZER);
+                            if let Some(address) = self.labels.get(&name).copied() {
+                                self.run_call(address, &name, Some(rf.clone()), Vec::new())?;
+                            }
+                        }
+                        self.invoke_method(&rf, "init", acc)?;
+                        rf
+                    }
+                    // Map literals alternate keys and values.
+                    Type::Composite(composite) if matches!(**composite, Composite::Map(..)) => {
+                        for key in acc.iter().step_by(2) {
+                            self.prepare_map_key(key)?;
+                        }
+                        self.instance(typ.clone(), acc)
+                    }
+                    _ => self.instance(typ.clone(), acc),
+                };
                 self.push_to_stack(&Value::Ref(rf))?;
                 Ok(true)
             }
@@ -520,16 +2006,48 @@ impl ByteCodeMachine {
                 let value = {
                     let rf = self.pop_from_stack()?.expect_ref()?;
                     let offset = self.pop_from_stack()?;
+                    if rf.is_map() {
+                        self.prepare_map_key(&offset)?;
+                    }
                     rf.get(&offset)?
                 };
 
                 self.push_to_stack(&value)?;
                 Ok(true)
             }
+            ByteCode::SLICE => {
+                let collection = self.pop_from_stack()?;
+                let mut bounds = [None, None];
+                for bound in bounds.iter_mut() {
+                    *bound = match self.pop_from_stack()? {
+                        Value::Null => None,
+                        value => Some(value.expect_int()?),
+                    };
+                }
+                let [start, end] = bounds;
+                let sliced = match collection {
+                    Value::String(text) => {
+                        let chars: Vec<char> = text.chars().collect();
+                        let range = slice_range(start, end, chars.len());
+                        Value::String(chars[range].iter().collect::<String>().into())
+                    }
+                    Value::Ref(rf) => {
+                        let elements = rf.array_values()?;
+                        let range = slice_range(start, end, elements.len());
+                        Value::Ref(self.instance(rf.get_type(), elements[range].to_vec()))
+                    }
+                    other => bail!("Cannot slice {}", other),
+                };
+                self.push_to_stack(&sliced)?;
+                Ok(true)
+            }
             ByteCode::SAVETOREF => {
                 let value = self.pop_from_stack()?;
                 let rf = self.pop_from_stack()?.expect_ref()?;
                 let offset = self.pop_from_stack()?;
+                if rf.is_map() {
+                    self.prepare_map_key(&offset)?;
+                }
                 rf.modify(&offset, value)?;
 
                 Ok(true)
@@ -538,39 +2056,78 @@ impl ByteCodeMachine {
                 let val = self.pop_from_stack()?;
                 self.stack_frames
                     .last_mut()
-                    .ok_or(anyhow!(
-                        "Attempted to access stack frames while none are allocated!"
-                    ))?
+                    .ok_or_else(|| {
+                        anyhow!("Attempted to access stack frames while none are allocated!")
+                    })?
                     .local_variables
-                    .insert(string, val);
+                    .insert(string.clone(), val);
+                Ok(true)
+            }
+            ByteCode::DEFARG(slot, _t) => {
+                let frame = self.stack_frames.last_mut().ok_or_else(|| {
+                    anyhow!("Attempted to access stack frames while none are allocated!")
+                })?;
+                let value = frame
+                    .arguments
+                    .pop_front()
+                    .ok_or_else(|| anyhow!("Missing argument for parameter #{}", slot))?;
+                let slot = *slot as usize;
+                if slot >= frame.slots.len() {
+                    frame.slots.resize(slot + 1, Value::Null);
+                }
+                frame.slots[slot] = value;
                 Ok(true)
             }
             ByteCode::DROPVAR(string) => {
                 self.stack_frames
                     .last_mut()
-                    .ok_or(anyhow!(
-                        "Attempted to access stack frames while none are allocated!"
-                    ))?
+                    .ok_or_else(|| {
+                        anyhow!("Attempted to access stack frames while none are allocated!")
+                    })?
                     .local_variables
-                    .remove_entry(&string);
+                    .remove_entry(string);
                 Ok(true)
             }
             ByteCode::CAST(typ) => {
                 let val = self.pop_from_stack()?;
-                self.push_to_stack(&val.cast(typ)?)?;
+                let cast = match typ {
+                    Type::Primitive(Primitives::String) => {
+                        Value::String(self.display_value(&val)?.into())
+                    }
+                    typ => val.cast(typ.clone())?,
+                };
+                self.push_to_stack(&cast)?;
                 Ok(true)
             }
-            ByteCode::MOD => {
-                let a = self.pop_from_stack()?;
-                let b = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Integer(a.expect_int()? % b.expect_int()?))?;
+            ByteCode::MOD => self.stack_binary(BinaryOp::Mod),
+            ByteCode::VARIANT(tag, argc) => {
+                let mut payload = Vec::with_capacity(*argc);
+                for _ in 0..*argc {
+                    payload.push(self.pop_from_stack()?);
+                }
+                payload.reverse();
+                let rf = self.heap.allocate(RefHeader::variant(tag.clone(), payload));
+                self.push_to_stack(&Value::Ref(rf))?;
                 Ok(true)
             }
-            ByteCode::NOT => {
+            ByteCode::ISVARIANT(tag) => {
                 let val = self.pop_from_stack()?;
-                self.push_to_stack(&Value::Bool(!val.cast_to_bool()?))?;
+                let is_variant = match &val {
+                    Value::Ref(rf) => rf.variant_parts().is_ok_and(|(t, _)| t == *tag),
+                    _ => false,
+                };
+                self.push_to_stack(&Value::Bool(is_variant))?;
                 Ok(true)
             }
+            ByteCode::ISTYPE(typ) => {
+                let val = self.pop_from_stack()?;
+                self.push_to_stack(&Value::Bool(val.is_of_type(typ)))?;
+                Ok(true)
+            }
+            ByteCode::NOT => self.not(),
+            ByteCode::BINARY(op, first, second, result) => {
+                self.binary(*op, *first, *second, *result)
+            }
         }
     }
 }
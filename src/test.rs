@@ -1,7 +1,9 @@
-use crate::cli::{compile, run};
+use crate::cli::{compile, compile_source, repl::Repl, run, run_captured};
 use crate::compiler::Code;
+use crate::frontend::{parser::Parser, tokenizer::Tokenizer};
 use anyhow::Result;
 use paste::paste;
+use std::path::{Path, PathBuf};
 
 macro_rules! abra_compile_test {
     ($s1:ident $n1:ident; $($s2:ident $n2:ident);*) => {
@@ -69,7 +71,7 @@ macro_rules! abra_compile_test {
                 return (None,Err(err))
             }
         };
-        return (Some(code.clone()),Ok(run(&code,0).unwrap() as u64));
+        return (Some(code.clone()),Ok(run(code).unwrap() as u64));
         }
     };
 
@@ -88,5 +90,1864 @@ abra_compile_test! {
     ok var_decl;
     panic var_drop;
     ok fn_call;
+    panic dup_fn;
+    panic bad_compare;
+    panic bad_hash;
+    panic bad_equals;
+    panic bad_to_string;
+    panic while_int_cond;
+    panic break_outside_loop;
+    panic unknown_method;
+    panic push_wrong_element;
+    panic sort_bools;
+    panic sort_without_compare;
+    panic lambda_wrong_arg;
+    panic import_duplicate;
+    panic import_unknown_item;
+    panic throw_uncaught;
+    panic throw_not_string;
+    panic member_wrong_type;
+    panic index_assign_wrong_type;
+    panic compound_assign_wrong_type;
+    panic logical_wrong_type;
+    panic bitwise_wrong_type;
+    panic conditional_not_bool;
+    panic math_wrong_type;
+    panic match_wrong_type;
+    panic enum_wrong_payload;
+    panic field_initializer_wrong_type;
+    panic field_without_value;
+    panic override_wrong_signature;
+    panic interface_missing_method;
+    panic null_to_non_optional;
+    panic type_test_outside_branch;
+    panic narrowed_assign_wrong_type;
+    panic narrowed_after_assign;
+    panic array_literal_wrong_type;
+    panic slice_wrong_type;
+    panic matrix_wrong_type;
+    panic tuple_wrong_arity;
+    panic const_reassign;
+    panic read_before_assign;
     ok class
 }
+
+/// Programs whose observable behaviour is pinned by a sibling `.expected` file.
+const GOLDEN_DIR: &str = "tests/programs";
+/// Set to regenerate the `.expected` files instead of comparing against them.
+const GOLDEN_UPDATE_ENV: &str = "ABRA_UPDATE_GOLDEN";
+
+fn golden_programs() -> Vec<PathBuf> {
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(GOLDEN_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "abra"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn golden_output(program: &Path) -> String {
+    let code = match compile(program.to_str().unwrap(), 0) {
+        Ok(code) => code,
+        Err(err) => return format!("compile error: {}\n", err.root_cause()),
+    };
+    match run_captured(code) {
+        Ok((exit_code, stdout)) => format!("exit: {}\n--- stdout\n{}", exit_code, stdout),
+        // The panic report already starts with "runtime error:".
+        Err(err) => format!("{}\n", err),
+    }
+}
+
+#[test]
+fn test_golden_programs() {
+    let update = std::env::var_os(GOLDEN_UPDATE_ENV).is_some();
+    let mut failures = Vec::new();
+    for program in golden_programs() {
+        let actual = golden_output(&program);
+        let expected_path = program.with_extension("expected");
+        if update {
+            std::fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}:\n--- expected\n{}\n--- actual\n{}",
+                program.display(),
+                expected,
+                actual
+            )),
+            Err(_) => failures.push(format!(
+                "{}: missing {} (run with {}=1 to create it)",
+                program.display(),
+                expected_path.display(),
+                GOLDEN_UPDATE_ENV
+            )),
+        }
+    }
+    if !failures.is_empty() {
+        panic!("Golden output mismatch:\n{}", failures.join("\n"));
+    }
+}
+
+#[test]
+fn test_repl_keeps_state_between_inputs() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("let x: int = 5\n").unwrap(), None);
+    assert_eq!(repl.eval("x + 1\n").unwrap(), Some("6".into()));
+    repl.eval("func seven() -> int\n    return 7\n").unwrap();
+    assert_eq!(repl.eval("seven() + x\n").unwrap(), Some("12".into()));
+    repl.eval("while x < 8\n    x = x + 1\n").unwrap();
+    assert_eq!(repl.eval("x\n").unwrap(), Some("8".into()));
+    // Rejected inputs don't disturb what was already defined.
+    assert!(repl.eval("y\n").is_err());
+    assert!(repl.eval("return 1\n").is_err());
+    assert!(repl.eval("let y: int\n").is_err());
+    assert!(repl.eval("func seven() -> int\n    return 8\n").is_err());
+    assert_eq!(repl.eval("seven() + x\n").unwrap(), Some("15".into()));
+    repl.eval("enum Coin: Heads | Tails\n").unwrap();
+    repl.eval("let coin: Coin = Coin::Tails\n").unwrap();
+    assert_eq!(
+        repl.eval("coin == Coin::Tails\n").unwrap(),
+        Some("true".into())
+    );
+    repl.eval("interface Sized: func size() -> int\n").unwrap();
+    repl.eval("class Box implements Sized\n    func size() -> int\n        return 3\n")
+        .unwrap();
+    assert_eq!(repl.eval("new Box().size()\n").unwrap(), Some("3".into()));
+}
+
+#[test]
+fn test_frontend_errors_report_line_and_column() {
+    use crate::cli::render_error;
+
+    let source = "func main() -> int\n    let x: int = 1 +\n    return x\n";
+    let err = compile_source(source, 0).err().unwrap();
+    assert!(
+        err.root_cause().to_string().starts_with("2:21: "),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains(" --> <source>:2:21\n"), "{}", err);
+
+    let mut parser = Parser::new(Tokenizer::new(source)).with_source(source);
+    let err = parser.parse_program().unwrap_err();
+    let report = render_error(&err, "main.abra", source);
+    assert!(report.contains(" --> main.abra:2:21\n"), "{}", report);
+    assert!(
+        report.ends_with("2 |     let x: int = 1 +\n  |                     ^"),
+        "{}",
+        report
+    );
+
+    let err = Tokenizer::new("let c: char = ''\n")
+        .collect::<Result<Vec<_>>>()
+        .unwrap_err();
+    assert!(err.to_string().starts_with("1:15: "), "{}", err);
+}
+
+#[test]
+fn test_stack_limits_are_configurable() {
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let code = compile("tests/programs/deep_recursion.abra", 0).unwrap();
+    let mut machine = ByteCodeMachine::new(code);
+    machine.set_stdout(Box::new(std::io::sink()));
+    machine.set_max_call_depth(100);
+    assert_eq!(machine.run(), 1);
+    let report = machine.runtime_error().unwrap();
+    assert_eq!(report.message, "Stack overflow: more than 100 nested calls");
+}
+
+#[test]
+fn test_expression_statements_drop_their_values() {
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let source = "class Counter\n    let count: int = 0\n    func bump() -> int\n        count = count + 1\n        return count\n\
+        func twice(x: int) -> int\n    return x * 2\n\
+        func maybe(x: int) -> int?\n    if x > 0\n        return x\n\
+        func note(x: int) -> null\n    return null\n\
+        func main() -> int\n    let counter: Counter = new Counter()\n    let double: fn(int) -> int = fn(x: int) -> int: x * 2\n    \
+        let i: int = 0\n    while i < 1000\n        twice(i)\n        counter.bump()\n        double(i)\n        maybe(i)\n        note(i)\n        print(i)\n        i = i + 1\n    \
+        return counter.count\n";
+    let mut machine = ByteCodeMachine::new(compile_source(source, 0).unwrap());
+    machine.set_stdout(Box::new(std::io::sink()));
+    machine.set_max_stack_size(100);
+    assert_eq!(machine.run(), 1000, "{:?}", machine.runtime_error());
+}
+
+#[test]
+fn test_peephole_optimizer() {
+    use crate::compiler::ByteCode::*;
+    use crate::optimizer::optimize_bytecode;
+    use std::collections::HashMap;
+
+    let mut code = Code {
+        bytecode: vec![
+            JMPTO("hop".into()),
+            PUSH(0),
+            JMPTO("end".into()),
+            PUSH(4),
+            PUSH(1),
+            PUSH(2),
+            MULT,
+            ADD,
+            PUSH(3),
+            POP,
+            RET(true),
+            PUSH(5),
+            EXIT,
+        ],
+        labels: HashMap::from([("_start".into(), 0), ("hop".into(), 2), ("end".into(), 3)]),
+        functions: Vec::new(),
+        constants: vec![1.into(), 2.into(), 3.into(), 4.into(), 7.into(), 5.into()],
+        local_names: HashMap::new(),
+        types: Vec::new(),
+        locations: Vec::new(),
+        files: Vec::new(),
+        symbols: Vec::new(),
+    };
+    optimize_bytecode(&mut code);
+    assert_eq!(
+        format!("{:?}", code.bytecode),
+        format!(
+            "{:?}",
+            vec![JMPTO("end".into()), JMPTO("end".into()), PUSH(7), RET(true)]
+        )
+    );
+    assert_eq!(code.constants[7], 13.into());
+    assert_eq!(code.labels["hop"], 1);
+    assert_eq!(code.labels["end"], 2);
+}
+
+#[test]
+fn test_register_backend_matches_stack_backend() {
+    use crate::optimizer::lower_to_registers;
+
+    for program in golden_programs() {
+        let Ok(code) = compile(program.to_str().unwrap(), 0) else {
+            continue;
+        };
+        let mut lowered = code.clone();
+        lower_to_registers(&mut lowered);
+        let stack = run_captured(code).map_err(|err| err.to_string());
+        let register = run_captured(lowered).map_err(|err| err.to_string());
+        assert_eq!(stack, register, "{}", program.display());
+    }
+
+    let mut code = compile_source(include_str!("../benches/programs/arith.abra"), 0).unwrap();
+    let stack_len = code.bytecode.len();
+    lower_to_registers(&mut code);
+    assert!(code.bytecode.len() < stack_len);
+    assert_eq!(run_captured(code).unwrap().0, 0);
+}
+
+#[test]
+fn test_wasm_backend_imports_builtins() {
+    use crate::compiler::wasm_backend::compile_to_wasm;
+
+    let code = compile("tests/programs/logical_ops.abra", 0).unwrap();
+    let module = compile_to_wasm(&code).unwrap();
+    assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+    let contains = |name: &str| {
+        module
+            .windows(name.len())
+            .any(|bytes| bytes == name.as_bytes())
+    };
+    for name in ["abra", "print_string", "print_bool", "_start", "memory"] {
+        assert!(contains(name), "{}", name);
+    }
+
+    let code = compile("tests/programs/array_builtins.abra", 0).unwrap();
+    let err = compile_to_wasm(&code).unwrap_err().to_string();
+    assert!(
+        err.starts_with("Can't compile 'main' to WebAssembly"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_wasm_backend_traps_on_overflow() {
+    use crate::compiler::wasm_backend::compile_to_wasm;
+
+    let code = compile("tests/programs/sum_loop.abra", 0).unwrap();
+    let module = compile_to_wasm(&code).unwrap();
+    // `if (first ^ result) & (second ^ result) < 0: unreachable` after each addition.
+    let check = [0x83, 0x42, 0x00, 0x53, 0x04, 0x40, 0x00, 0x0b];
+    assert!(module.windows(check.len()).any(|bytes| bytes == check));
+}
+
+#[test]
+fn test_disassembly_resolves_jump_targets() {
+    let code = compile("tests/programs/while_loop.abra", 0).unwrap();
+    let listing = code.string_representation();
+    assert!(
+        listing.starts_with("0 | _start:\n0 | {\"CALLFN\":[0,0]}: -> "),
+        "{}",
+        listing
+    );
+    for entry in code.json_representation().as_array().unwrap() {
+        if let Some(label) = entry["instruction"].get("JMPTO") {
+            let target = code.labels[label.as_str().unwrap()];
+            assert_eq!(entry["target"], target);
+        }
+    }
+}
+
+#[test]
+fn test_input_builtins_read_stdin_lines() {
+    use crate::cli::compile_source;
+    use crate::runtime::{session::Session, vm::ByteCodeMachine};
+
+    let log = std::env::temp_dir().join(format!("abra_input_{}.log", std::process::id()));
+    std::fs::write(
+        &log,
+        "{\"Stdin\":\"abra\"}\n{\"Stdin\":\" 41 \"}\n{\"Stdin\":\"2.5\"}\n{\"Stdin\":\"four\"}\n",
+    )
+    .unwrap();
+    let source = "func main() -> int\n    print(input())\n    print(input_int() + 1)\n    print(input_float())\n    return input_int()\n";
+    let mut machine = ByteCodeMachine::new(compile_source(source, 0).unwrap());
+    machine.set_session(Session::replay(&log).unwrap());
+    machine.set_stdout(Box::new(std::io::sink()));
+    std::fs::remove_file(&log).unwrap();
+    assert_eq!(machine.run(), 1);
+    assert_eq!(
+        machine.runtime_error().unwrap().message,
+        "input_int() expected an integer, got 'four'"
+    );
+}
+
+#[test]
+fn test_playground_runs_on_an_in_memory_platform() {
+    use crate::playground::{compile_and_run, compile_and_run_with_input};
+
+    let source = "func main() -> int\n    print(input())\n    print(input_int() + 1)\n    sleep(60000)\n    return 0\n";
+    assert_eq!(compile_and_run_with_input(source, "abra\n41\n"), "abra42");
+    let output = compile_and_run(source);
+    assert!(
+        output.starts_with("runtime error: input() reached the end of stdin"),
+        "{}",
+        output
+    );
+    let output = compile_and_run("func main() -> int\n    return missing\n");
+    assert!(output.starts_with("error[E0"), "{}", output);
+}
+
+#[test]
+fn test_project_directory_runs_its_main() {
+    let dir = std::env::temp_dir().join(format!("abra_project_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(
+        dir.join("main.abra"),
+        "import \"lib/answer\"\n\nfunc main() -> int\n    print(answer::get())\n    return 0\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("lib/answer.abra"),
+        "func get() -> int\n    return 42\n",
+    )
+    .unwrap();
+    let code = compile(dir.to_str().unwrap(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (exit_code, stdout) = run_captured(code.unwrap()).unwrap();
+    assert_eq!((exit_code, stdout.as_str()), (0, "42"));
+}
+
+#[test]
+fn test_gc_frees_cycles_under_allocation_pressure() {
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let source = "class Node\n    let next: Node?\n\n    func link(other: Node) -> int\n        next = other\n        return 0\n\nfunc main() -> int\n    let i: int = 0\n    while i < 5000\n        let a: Node = new Node()\n        a.link(a)\n        i = i + 1\n    return 0\n";
+    let mut machine = ByteCodeMachine::new(compile_source(source, 0).unwrap());
+    machine.set_stdout(Box::new(std::io::sink()));
+    machine.set_gc_threshold(100);
+    assert_eq!(machine.run(), 0);
+    // Without collection all 5000 self-referencing nodes would still be alive.
+    assert!(
+        machine.live_objects() <= 100,
+        "{} live",
+        machine.live_objects()
+    );
+    machine.collect_garbage();
+    assert_eq!(machine.live_objects(), 0);
+}
+
+#[test]
+fn test_runtime_errors_point_at_source() {
+    use crate::runtime::debug::RuntimeError;
+
+    let code = compile("tests/programs/stack_overflow.abra", 0).unwrap();
+    let err = run(code).unwrap_err();
+    let err = err.downcast_ref::<RuntimeError>().unwrap();
+    let source = err.source().unwrap();
+    assert_eq!(
+        (source.file.as_str(), source.line),
+        ("tests/programs/stack_overflow.abra", 2)
+    );
+    assert!(err.bytecode_index().is_some());
+    assert!(err.frames.iter().all(|frame| frame.source.is_some()));
+    assert_eq!(err.excerpt.as_deref(), Some("    return forever() + 1"));
+    assert!(err
+        .to_string()
+        .contains("2 |     return forever() + 1\n  |     ^\n"));
+
+    // Without the source on disk the report leaves the excerpt out.
+    let source = "func main() -> int\n    print(char_at(\"\", 3))\n    return 0\n";
+    let code = crate::compile_source(source).unwrap();
+    let err = run(code).unwrap_err();
+    let err = err.downcast_ref::<RuntimeError>().unwrap();
+    assert_eq!(err.excerpt, None);
+    assert!(!err.to_string().contains(" | "));
+}
+
+#[test]
+fn test_embedding_api() {
+    use crate::compiler::typecheck::INTEGER_TYPE;
+    use crate::{AbraError, Value, Vm};
+
+    let source = "func grow(xs: [int], by: int) -> int\n    push(xs, by)\n    return len(xs) * by\n\nfunc fail() -> int\n    throw \"no\"\n    return 0\n\nfunc main() -> int\n    return 3\n";
+    let mut vm = Vm::new(crate::compile_source(source).unwrap());
+    vm.machine().set_stdout(Box::new(std::io::sink()));
+    let xs = vm.array(INTEGER_TYPE, vec![7.into()]);
+    let grown = vm
+        .call_function("grow", vec![xs.clone(), 21.into()])
+        .unwrap();
+    assert_eq!(TryInto::<i64>::try_into(grown).unwrap(), 42);
+    let elements: Vec<Value> = xs.try_into().unwrap();
+    assert_eq!(elements, vec![Value::from(7), Value::from(21)]);
+
+    match vm.call_function("fail", Vec::new()).unwrap_err() {
+        AbraError::Runtime(err) => assert_eq!(err.message, "no"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert!(vm.call_function("missing", Vec::new()).is_err());
+    assert_eq!(Value::from(None::<i64>), Value::Null);
+    assert_eq!(vm.run().unwrap(), 3);
+}
+
+#[test]
+fn test_sandboxed_vm_denies_capabilities_and_stops_at_limits() {
+    use crate::runtime::debug::RuntimeErrorKind;
+    use crate::runtime::platform::{InputTimedOut, Platform};
+    use crate::{AbraError, Capability, Vm, VmConfig};
+    use std::time::Duration;
+
+    let source = "func peek() -> bool\n    return file_exists(\"Cargo.toml\")\n\nfunc count() -> int\n    return len(args())\n\nfunc spin() -> int\n    let n: int = 0\n    while true\n        try\n            n += 1\n        catch e\n            return -1\n    return n\n\nfunc nap() -> int\n    sleep(9223372036854775807)\n    return 0\n\nfunc ask() -> string\n    return input()\n\nfunc main() -> int\n    return 0\n";
+    let mut vm = Vm::new(crate::compile_source(source).unwrap());
+    assert_eq!(vm.call_function("peek", Vec::new()).unwrap(), true.into());
+    vm.set_config(VmConfig::sandboxed());
+    assert!(!vm.machine().allows(Capability::Network));
+    for (func, denied) in [("peek", "file access"), ("count", "process access")] {
+        match vm.call_function(func, Vec::new()).unwrap_err() {
+            AbraError::Runtime(err) => {
+                assert_eq!(err.kind, RuntimeErrorKind::Fault);
+                let message = format!("this VM denies {}", denied);
+                assert!(err.message.ends_with(&message), "{}", err.message);
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    // A `try` doesn't catch running out of instructions or time.
+    vm.set_config(VmConfig {
+        max_instructions: Some(10_000),
+        ..VmConfig::sandboxed()
+    });
+    let err = vm.call_function("spin", Vec::new()).unwrap_err();
+    assert_eq!(err.code(), "E0504");
+    match err {
+        AbraError::Runtime(err) => {
+            assert_eq!(err.kind, RuntimeErrorKind::ResourceLimitExceeded);
+            let message = "Resource limit exceeded: ran more than 10000 instructions";
+            assert_eq!(err.message, message);
+        }
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    vm.set_config(VmConfig {
+        timeout: Some(Duration::from_millis(20)),
+        ..VmConfig::default()
+    });
+    let err = vm.call_function("spin", Vec::new()).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("ran for longer than 20ms"), "{}", message);
+    assert_eq!(vm.call_function("peek", Vec::new()).unwrap(), true.into());
+
+    // Sleeping and waiting for input stop at the timeout too.
+    struct Silent(Box<dyn std::io::Write>, std::time::Instant);
+    impl Platform for Silent {
+        fn stdout(&mut self) -> &mut Box<dyn std::io::Write> {
+            &mut self.0
+        }
+        fn stdin_line(&mut self) -> anyhow::Result<Option<String>> {
+            unreachable!("reads have a timeout")
+        }
+        fn stdin_line_within(&mut self, timeout: Duration) -> anyhow::Result<Option<String>> {
+            std::thread::sleep(timeout);
+            Err(InputTimedOut.into())
+        }
+        fn now_millis(&mut self) -> anyhow::Result<i64> {
+            Ok(0)
+        }
+        fn clock(&mut self) -> f64 {
+            self.1.elapsed().as_secs_f64()
+        }
+        fn sleep(&mut self, millis: u64) {
+            std::thread::sleep(Duration::from_millis(millis));
+        }
+    }
+    let silent = Silent(Box::new(std::io::sink()), std::time::Instant::now());
+    vm.machine().set_platform(Box::new(silent));
+    for func in ["nap", "ask"] {
+        let started = std::time::Instant::now();
+        let err = vm.call_function(func, Vec::new()).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let message = err.to_string();
+        assert!(message.contains("ran for longer than 20ms"), "{}", message);
+    }
+}
+
+#[test]
+fn test_heap_limits_raise_a_catchable_error_and_builtins_report_usage() {
+    use crate::{AbraError, Value, Vm, VmConfig};
+
+    let source = "func hoard(n: int) -> int\n    let kept: [[int]] = []\n    let i: int = 0\n    while i < n\n        push(kept, [i, i])\n        i = i + 1\n    return len(kept)\n\nfunc grow(n: int) -> int\n    let kept: [int] = []\n    let i: int = 0\n    while i < n\n        push(kept, i)\n        i = i + 1\n    return len(kept)\n\nfunc matrix(n: int) -> int\n    return len(new_matrix(n, n, 0))\n\nfunc repeat(n: int) -> int\n    return len(\"ab\" * n)\n\nfunc guarded(n: int) -> int\n    try\n        return hoard(n)\n    catch e\n        return -1\n\nfunc usage() -> [int]\n    let objects: int = obj_count()\n    let bytes: int = mem_used()\n    let kept: [string] = [\"some text\"]\n    return [obj_count() - objects, mem_used() - bytes, len(kept)]\n\nfunc main() -> int\n    return 0\n";
+    let mut vm = Vm::new(crate::compile_source(source).unwrap());
+    let usage = vm.call_function("usage", Vec::new()).unwrap();
+    let Value::Ref(usage) = usage else {
+        panic!("expected an array, got {:?}", usage);
+    };
+    let usage = usage.array_values().unwrap();
+    assert_eq!(usage[0], Value::Integer(1));
+    let grew = matches!(usage[1], Value::Integer(bytes) if bytes > 9);
+    assert!(grew, "{:?}", usage);
+
+    vm.set_config(VmConfig {
+        max_objects: Some(1_000),
+        ..VmConfig::default()
+    });
+    let hoarded = vm.call_function("hoard", vec![500.into()]).unwrap();
+    assert_eq!(hoarded, 500.into());
+    let guarded = vm.call_function("guarded", vec![5_000.into()]).unwrap();
+    assert_eq!(guarded, (-1).into());
+    match vm.call_function("hoard", vec![5_000.into()]).unwrap_err() {
+        AbraError::Runtime(err) => {
+            assert_eq!(err.kind, crate::runtime::debug::RuntimeErrorKind::Fault);
+            let message = "Out of memory: more than 1000 objects are alive";
+            assert_eq!(err.message, message);
+        }
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    // What the failed calls kept is garbage now.
+    let hoarded = vm.call_function("hoard", vec![500.into()]).unwrap();
+    assert_eq!(hoarded, 500.into());
+
+    // An array growing in place counts too, without allocating anything.
+    vm.set_config(VmConfig {
+        max_heap_bytes: Some(64 * 1024),
+        ..VmConfig::default()
+    });
+    let grown = vm.call_function("grow", vec![100.into()]).unwrap();
+    assert_eq!(grown, 100.into());
+    let err = vm.call_function("grow", vec![100_000.into()]).unwrap_err();
+    let message = err.to_string();
+    let expected = "take more than 65536 bytes";
+    assert!(message.contains(expected), "{}", message);
+    // Builtins making large arrays check before making them.
+    let matrix = vm.call_function("matrix", vec![10.into()]).unwrap();
+    assert_eq!(matrix, 10.into());
+    let err = vm.call_function("matrix", vec![1_000.into()]).unwrap_err();
+    let message = err.to_string();
+    let expected = "would take more than 65536 bytes";
+    assert!(message.contains(expected), "{}", message);
+    let repeated = vm.call_function("repeat", vec![10.into()]).unwrap();
+    assert_eq!(repeated, 20.into());
+    let err = vm
+        .call_function("repeat", vec![100_000.into()])
+        .unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains(expected), "{}", message);
+}
+
+#[test]
+fn test_profile_counts_calls_and_instructions_per_function_and_opcode() {
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let source = "func fib(n: int) -> int\n    if n < 2\n        return n\n    return fib(n - 1) + fib(n - 2)\n\nfunc main() -> int\n    return fib(10) - 55\n";
+    let mut machine = ByteCodeMachine::new(compile_source(source, 0).unwrap());
+    assert!(machine.profile().is_none());
+    machine.enable_profiling();
+    assert_eq!(machine.run(), 0);
+    let profile = machine.profile().unwrap();
+
+    let calls: Vec<(&str, u64)> = profile
+        .functions
+        .iter()
+        .map(|function| (function.name.as_str(), function.calls))
+        .collect();
+    assert_eq!(calls, vec![("main", 1), ("fib", 177)]);
+    // `fib` only calls itself, so all of its time is its own.
+    let fib = &profile.functions[1];
+    assert_eq!(fib.total, fib.own);
+    assert!(profile.functions[0].total >= fib.total);
+
+    let counted: u64 = profile.opcodes.iter().map(|opcode| opcode.count).sum();
+    assert_eq!(counted, profile.instructions);
+    // The calls to `fib` and the one to `main`.
+    let calls = profile.opcodes.iter().find(|op| op.opcode == "CALLFN");
+    assert_eq!(calls.map(|op| op.count), Some(178));
+
+    let folded = profile.folded();
+    let nested = folded.lines().any(|line| line.starts_with("main;fib;fib "));
+    assert!(nested, "{}", folded);
+    assert!(!folded.contains("fib;main"), "{}", folded);
+    assert_eq!(profile.to_json()["functions"][1]["calls"], 177);
+    let header = format!("Ran {} instructions", profile.instructions);
+    assert!(profile.to_string().starts_with(&header));
+}
+
+#[test]
+fn test_trace_logs_instructions_and_the_viewer_steps_through_them() {
+    use crate::cli::trace_viewer::run_trace_viewer;
+    use crate::runtime::trace::{read_trace, TraceEntry, Tracer};
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let source = "func pick(xs: [int], i: int) -> int\n    return xs[i]\n\nfunc main() -> int\n    let xs: [int] = [1, 2, 3]\n    return pick(xs, 1) + pick(xs, 3)\n";
+    let code = compile_source(source, 0).unwrap();
+    let log = std::env::temp_dir().join(format!("abra_trace_{}.log", std::process::id()));
+    let run = |tracer: Tracer| {
+        let mut machine = ByteCodeMachine::new(code.clone());
+        machine.set_tracer(tracer);
+        assert_eq!(machine.run(), 1);
+        machine.tracer().unwrap().finish().unwrap();
+        let steps = machine.tracer().unwrap().steps();
+        (read_trace(&log).unwrap(), steps)
+    };
+
+    let (entries, steps) = run(Tracer::stream(&log).unwrap());
+    assert_eq!(entries.len() as u64, steps);
+    assert_eq!(entries[0].function, "<start>");
+    let in_main = entries.iter().filter(|entry| entry.function == "main");
+    assert!(in_main.map(|entry| entry.depth).all(|depth| depth == 1));
+    let last = entries.last().unwrap();
+    assert_eq!((last.function.as_str(), last.depth), ("pick", 2));
+    let error = last.error.as_deref().unwrap();
+    assert!(error.contains("out of range"), "{}", error);
+    assert!(last.source.as_deref().unwrap().ends_with(":2:5"));
+
+    // The ring keeps the end of the run, numbered as in the full trace.
+    let (ring, _) = run(Tracer::ring(&log, 4));
+    // Room for the entries is only taken as they come.
+    let (whole, _) = run(Tracer::ring(&log, usize::MAX));
+    std::fs::remove_file(&log).unwrap();
+    let steps = |entries: &[TraceEntry]| -> Vec<(u64, usize)> {
+        entries.iter().map(|entry| (entry.step, entry.pc)).collect()
+    };
+    assert_eq!(steps(&ring), steps(&entries[entries.len() - 4..]));
+    assert_eq!(steps(&whole), steps(&entries));
+
+    let mut shown = Vec::new();
+    let commands = "e\nb 2\nr LOADSLOT\nn 1000\nn\nbogus\nq\n";
+    run_trace_viewer(&entries, &mut commands.as_bytes(), &mut shown).unwrap();
+    let shown = String::from_utf8(shown).unwrap();
+    let lines: Vec<&str> = shown.split("(trace) ").collect();
+    assert!(lines[0].starts_with(&format!("{} instructions", entries.len())));
+    assert_eq!(lines[1].trim_end(), last.to_string());
+    assert_eq!(lines[2].trim_end(), entries[entries.len() - 3].to_string());
+    assert!(lines[3].contains("LOADSLOT"), "{}", lines[3]);
+    assert_eq!(lines[4].trim_end(), last.to_string());
+    assert_eq!(lines[5], "At the end of the trace.\n");
+    assert!(lines[6].starts_with("Unknown command 'bogus'"));
+}
+
+#[test]
+fn test_binary_operators_take_the_left_operand_first() {
+    use crate::optimizer::lower_to_registers;
+    use crate::{Value, Vm};
+
+    let source = "func sub(a: int, b: int) -> int\n    return a - b\n\nfunc div(a: int, b: int) -> int\n    return a / b\n\nfunc rem(a: int, b: int) -> int\n    return a % b\n\nfunc compare(a: int, b: int) -> [bool]\n    return [a < b, a <= b, a > b, a >= b, a == b, a != b, not (a == b)]\n\nfunc main() -> int\n    return 10 - 4 - 3\n";
+    let expected = |a: i64, b: i64| -> Vec<Value> {
+        vec![
+            (a - b).into(),
+            (a / b).into(),
+            (a % b).into(),
+            format!(
+                "{:?}",
+                [a < b, a <= b, a > b, a >= b, a == b, a != b, a != b]
+            )
+            .into(),
+        ]
+    };
+    let code = crate::compile_source(source).unwrap();
+    let mut lowered = code.clone();
+    lower_to_registers(&mut lowered);
+    for code in [code, lowered] {
+        let mut vm = Vm::new(code.clone());
+        for (a, b) in [(3, 5), (5, 3), (4, 4), (-7, 2)] {
+            let args = || vec![Value::from(a), Value::from(b)];
+            let compared = vm.call_function("compare", args()).unwrap();
+            let actual = vec![
+                vm.call_function("sub", args()).unwrap(),
+                vm.call_function("div", args()).unwrap(),
+                vm.call_function("rem", args()).unwrap(),
+                vm.machine().display_value(&compared).unwrap().into(),
+            ];
+            assert_eq!(actual, expected(a, b), "{} and {}", a, b);
+        }
+        // Folded at compile time.
+        assert_eq!(run_captured(code).unwrap().0, 3);
+    }
+}
+
+#[test]
+fn test_int_overflow_errors_wraps_or_saturates() {
+    use crate::runtime::vm::IntOverflow;
+    use crate::{Value, Vm};
+
+    let source = "func add(a: int, b: int) -> int\n    return a + b\n\nfunc negate(a: int) -> int\n    return -a\n\nfunc main() -> int\n    return 0\n";
+    let mut vm = Vm::new(crate::compile_source(source).unwrap());
+    let max = || vec![Value::from(i64::MAX), Value::from(1)];
+    let err = vm.call_function("add", max()).unwrap_err().to_string();
+    assert!(
+        err.contains("Integer overflow: 9223372036854775807 + 1"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        vm.call_function("negate", vec![5.into()]).unwrap(),
+        (-5).into()
+    );
+
+    vm.machine().set_int_overflow(IntOverflow::Wrap);
+    assert_eq!(vm.call_function("add", max()).unwrap(), i64::MIN.into());
+    assert_eq!(
+        vm.call_function("negate", vec![i64::MIN.into()]).unwrap(),
+        i64::MIN.into()
+    );
+    vm.machine().set_int_overflow(IntOverflow::Saturate);
+    assert_eq!(vm.call_function("add", max()).unwrap(), i64::MAX.into());
+    assert_eq!(
+        vm.call_function("negate", vec![i64::MIN.into()]).unwrap(),
+        i64::MAX.into()
+    );
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit_matches_interpreter() {
+    use crate::{Value, Vm};
+
+    let source = "func fib(n: int) -> int\n    if n < 2\n        return n\n    return fib(n - 1) + fib(n - 2)\n\nfunc is_even(n: int) -> bool\n    let even: bool = n % 2 == 0\n    return even\n\nfunc count_even(n: int) -> int\n    let total: int = 0\n    for let i: int = 0, i < n, i += 1\n        if is_even(i / 3)\n            total += 1\n    return total\n\nfunc sum(n: int) -> int\n    if n == 3000\n        return 0\n    return n + sum(n + 1)\n\nfunc main() -> int\n    return 0\n";
+    let calls: [(&str, i64); 4] = [("fib", 15), ("count_even", 500), ("sum", 0), ("is_even", 7)];
+    let results = |jit: bool| -> Vec<Value> {
+        let mut vm = Vm::new(crate::compile_source(source).unwrap());
+        if jit {
+            vm.machine().enable_jit(1).unwrap();
+        }
+        let results = calls
+            .iter()
+            .map(|(name, arg)| vm.call_function(name, vec![(*arg).into()]).unwrap())
+            .collect();
+        // Everything but `count_even`, which only the host calls; `sum` recurses
+        // deeper than native code does, so its outer calls are handed back.
+        assert_eq!(vm.machine().jit_compiled_count(), if jit { 3 } else { 0 });
+        results
+    };
+    assert_eq!(results(true), results(false));
+}
+
+#[test]
+fn test_host_functions_are_typechecked_and_called() {
+    use crate::compiler::typecheck::{Type, INTEGER_TYPE, STRING_TYPE};
+    use crate::{Natives, Vm};
+    use std::{cell::RefCell, rc::Rc};
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    let mut natives = Natives::default();
+    natives
+        .register(
+            "host_scale",
+            Type::function(vec![INTEGER_TYPE, INTEGER_TYPE], INTEGER_TYPE),
+            |_, args| {
+                let (a, b) = (args[0].expect_int()?, args[1].expect_int()?);
+                Ok((a * b).into())
+            },
+        )
+        .unwrap()
+        .register(
+            "host_log",
+            Type::function(vec![STRING_TYPE], Type::Null),
+            move |_, args| {
+                log.borrow_mut().push(args[0].to_string());
+                Ok(crate::Value::Null)
+            },
+        )
+        .unwrap();
+
+    let source = "func main() -> int\n    host_log(\"scaling\")\n    return host_scale(6, 7)\n";
+    assert!(compile_source(source, 0).is_err());
+    let bad = "func main() -> int\n    return host_scale(\"6\", 7)\n";
+    assert!(crate::compile_source_with(bad, &natives).is_err());
+
+    let mut vm = Vm::new(crate::compile_source_with(source, &natives).unwrap());
+    vm.machine().set_stdout(Box::new(std::io::sink()));
+    vm.register_natives(&natives).unwrap();
+    let print = Type::function(vec![], Type::Null);
+    assert!(vm
+        .register_native("print", print, |_, _| Ok(crate::Value::Null))
+        .is_err());
+    assert_eq!(vm.run().unwrap(), 42);
+    assert_eq!(*seen.borrow(), vec!["scaling".to_string()]);
+}
+
+#[test]
+fn test_errors_have_kinds_and_codes() {
+    use crate::errors::{AbraError, CompileError, EmbedError, LexError, ParseError, TypeError};
+    use crate::runtime::debug::RuntimeErrorKind;
+    use crate::Vm;
+
+    let err = crate::compile_source("func main() -> int\n    return 'ab'\n")
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, AbraError::Lex(LexError::InvalidCharLiteral(..))),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.code(), "E0105");
+
+    let err = crate::compile_source("func main() -> int\n    let x: int = 1 +\n")
+        .err()
+        .unwrap();
+    match &err {
+        AbraError::Parse(ParseError::UnexpectedToken(span, _)) => {
+            assert_eq!((span.line, span.col), (2, 21))
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+
+    let source = "func main() -> int\n    print(y)\n    return true\n";
+    match crate::compile_source(source).err().unwrap() {
+        AbraError::Type(errors) => {
+            let codes: Vec<_> = errors.iter().map(TypeError::code).collect();
+            assert_eq!(codes, vec!["E0302", "E0301"]);
+            assert!(matches!(&errors[1], TypeError::Mismatch(span, _) if span.line == 3));
+        }
+        other => panic!("expected type errors, got {:?}", other),
+    }
+
+    // Diagnostics quote an assignment's target as it is written.
+    let source = "class P\n    let x: int = 0\n\nfunc main() -> int\n    let p: P = new P()\n    p.x = \"a\"\n    return 0\n";
+    match crate::compile_source(source).err().unwrap() {
+        AbraError::Type(errors) => assert!(errors[0].message().contains("'p.x'"), "{:?}", errors),
+        other => panic!("expected type errors, got {:?}", other),
+    }
+    let source = "func main() -> int\n    let a: [int] = [1]\n    a[0] = \"b\"\n    return 0\n";
+    match crate::compile_source(source).err().unwrap() {
+        AbraError::Type(errors) => assert!(errors[0].message().contains("'a[0]'"), "{:?}", errors),
+        other => panic!("expected type errors, got {:?}", other),
+    }
+    let source = "func main() -> int\n    let a: [int] = [1]\n    a[0] += \"b\"\n    return 0\n";
+    match crate::compile_source(source).err().unwrap() {
+        AbraError::Type(errors) => {
+            assert!(
+                errors[0].message().contains("'a[0] += \"b\"'"),
+                "{:?}",
+                errors
+            )
+        }
+        other => panic!("expected type errors, got {:?}", other),
+    }
+
+    let err = crate::compile_source("func helper() -> int\n    return 1\n")
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, AbraError::Compile(CompileError::MissingMain)),
+        "{:?}",
+        err
+    );
+
+    let source = "func fail() -> int\n    throw \"no\"\n    return 0\n\nfunc fault() -> char\n    return char_at(\"\", 3)\n\nfunc main() -> int\n    return 0\n";
+    let mut vm = Vm::new(crate::compile_source(source).unwrap());
+    match vm.call_function("fail", Vec::new()).unwrap_err() {
+        AbraError::Runtime(err) => assert_eq!(err.kind, RuntimeErrorKind::Thrown),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    let err = vm.call_function("fault", Vec::new()).unwrap_err();
+    assert_eq!(err.code(), "E0502");
+    let err = vm.call_function("missing", Vec::new()).unwrap_err();
+    assert!(
+        matches!(err, AbraError::Embed(EmbedError::UnknownFunction(_))),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn test_typecheck_runs_before_emission() {
+    use crate::cli::{compile_named, SOURCE_FILE};
+    use crate::compiler::{lint::LintLevels, typecheck::TypeCheckerMessage};
+    use crate::errors::{AbraError, TypeError};
+
+    // Type errors are reported even when the program couldn't be compiled anyway.
+    let source = "func helper() -> int\n    return \"one\"\n";
+    let lints = LintLevels::default();
+    let err = compile_named(source, SOURCE_FILE, 0, &Vec::new(), &lints)
+        .err()
+        .unwrap();
+    assert!(matches!(err, AbraError::Type(_)), "{:?}", err);
+
+    let source = "func main() -> int\n    let x: int = 1\n    try\n        throw \"a\"\n    catch x\n        print(x)\n    return x\n";
+    let (_, messages) = compile_named(source, SOURCE_FILE, 0, &Vec::new(), &lints).unwrap();
+    assert!(matches!(
+        messages.as_slice(),
+        [TypeCheckerMessage::Warning(_)]
+    ));
+    let lints = lints.with_warnings_as_errors(true);
+    match compile_named(source, SOURCE_FILE, 0, &Vec::new(), &lints)
+        .err()
+        .unwrap()
+    {
+        AbraError::Type(errors) => {
+            assert!(matches!(&errors[..], [TypeError::DeniedWarning(span, _)] if span.line == 3))
+        }
+        other => panic!("expected a denied warning, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_check_typechecks_without_compiling() {
+    use crate::cli::{check_named, SOURCE_FILE};
+    use crate::errors::AbraError;
+
+    let source = std::fs::read_to_string("tests/programs/members.abra").unwrap();
+    assert!(
+        check_named(&source, "tests/programs/members.abra", &Default::default())
+            .unwrap()
+            .is_empty()
+    );
+    let source = "func main() -> int\n    return \"one\"\n";
+    let err = check_named(source, SOURCE_FILE, &Default::default())
+        .err()
+        .unwrap();
+    assert!(matches!(err, AbraError::Type(_)), "{:?}", err);
+}
+
+#[test]
+fn test_program_receives_args() {
+    use crate::runtime::vm::ByteCodeMachine;
+
+    let source = "func main() -> int\n    let a: [string] = args()\n    if a[1] == \"b\"\n        return len(a)\n    return 0\n";
+    let mut machine = ByteCodeMachine::new(compile_source(source, 0).unwrap());
+    machine.set_args(vec!["a".into(), "b".into(), "c".into()]);
+    assert_eq!(machine.run(), 3);
+}
+
+#[test]
+fn test_match_exhaustiveness_warnings() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let warnings = |arms: &str, subject: &str| {
+        let source = format!(
+            "func main() -> int\n    let v: {}\n    match v\n{}    return 0\n",
+            subject, arms
+        );
+        check_named(&source, SOURCE_FILE, &Default::default())
+            .unwrap()
+            .iter()
+            .map(|message| message.to_string())
+            .collect::<Vec<_>>()
+    };
+    let missing = warnings("        true: print(1)\n", "bool = true");
+    assert!(
+        matches!(missing.as_slice(), [w] if w.contains("'false'")),
+        "{:?}",
+        missing
+    );
+    assert!(warnings(
+        "        true: print(1)\n        false: print(2)\n",
+        "bool = true"
+    )
+    .is_empty());
+    let missing = warnings("        is int: print(1)\n", "int | string = 1");
+    assert!(
+        matches!(missing.as_slice(), [w] if w.contains("'string'")),
+        "{:?}",
+        missing
+    );
+    assert!(warnings(
+        "        is int: print(1)\n        _: print(2)\n",
+        "int | string = 1"
+    )
+    .is_empty());
+    // Other types have no known cases to cover.
+    assert!(warnings("        1: print(1)\n", "int = 1").is_empty());
+    let unreachable = warnings("        _: print(1)\n        2: print(2)\n", "int = 1");
+    assert!(
+        matches!(unreachable.as_slice(), [w] if w.contains("unreachable")),
+        "{:?}",
+        unreachable
+    );
+}
+
+#[test]
+fn test_warnings_quote_expressions_as_source() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let warnings = |body: &str| {
+        let source = format!(
+            "class P\n    let x: int = 0\n\nfunc main() -> int\n{}    return 0\n",
+            body
+        );
+        check_named(&source, SOURCE_FILE, &Default::default())
+            .unwrap()
+            .iter()
+            .map(|message| message.to_string())
+            .collect::<Vec<_>>()
+    };
+    let safe = warnings("    let p: P = new P()\n    print(p?.x)\n");
+    assert!(
+        safe.iter().any(|w| w.contains("'?.' on 'p', of type 'P'")),
+        "{:?}",
+        safe
+    );
+    let test = warnings("    let x: int = 1\n    print(x is string)\n");
+    assert!(
+        test.iter()
+            .any(|w| w.contains("'x is string' is never true")),
+        "{:?}",
+        test
+    );
+}
+
+#[test]
+fn test_enum_match_warns_about_missing_variants() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let source = "enum Color: Red | Green | RGB(int, int, int)\n\nfunc main() -> int\n    let c: Color = Color::RGB(1, 2, 3)\n    match c\n        Color::Red: print(1)\n        Color::RGB(r, g, b): print(r + g + b)\n    return 0\n";
+    let messages = check_named(source, SOURCE_FILE, &Default::default()).unwrap();
+    let missing: Vec<String> = messages.iter().map(|message| message.to_string()).collect();
+    assert!(
+        matches!(missing.as_slice(), [w] if w.contains("'Color::Green'") && !w.contains("Red")),
+        "{:?}",
+        missing
+    );
+    let source = source.replace("        Color::Red: print(1)\n", "        _: print(1)\n");
+    let source = source.replace(
+        "        _: print(1)\n        Color::RGB(r, g, b): print(r + g + b)\n",
+        "        Color::RGB(r, g, b): print(r + g + b)\n        _: print(1)\n",
+    );
+    assert!(check_named(&source, SOURCE_FILE, &Default::default())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_generic_calls_infer_type_arguments() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let checks = |body: &str| {
+        let source = format!(
+            "func head<T>(xs: [T]) -> T\n    return xs[0]\n\nfunc same<T>(a: T, b: T) -> T\n    return a\n\nclass Box<T>\n    let value: T\n\n    func init(v: T) -> int\n        value = v\n        return 0\n\nfunc main() -> int\n{}    return 0\n",
+            body
+        );
+        check_named(&source, SOURCE_FILE, &Default::default()).is_ok()
+    };
+    assert!(checks("    let n: int = head(new [int](1, 2))\n"));
+    assert!(!checks("    let s: string = head(new [int](1, 2))\n"));
+    assert!(checks("    let n: int = same(1, 2)\n"));
+    assert!(!checks("    let n: int = same(1, \"two\")\n"));
+    assert!(checks(
+        "    let b: Box<int> = new Box<int>(1)\n    let n: int = b.value\n"
+    ));
+    assert!(checks("    let b: Box<string> = new Box(\"inferred\")\n"));
+    assert!(!checks("    let b: Box<int> = new Box<string>(\"a\")\n"));
+    assert!(!checks("    let b: Box<int> = new Box<int>(\"a\")\n"));
+    assert!(!checks(
+        "    let b: Box<int> = new Box<int>(1)\n    let s: string = b.value\n"
+    ));
+    assert!(!checks("    let b: Box<int, int> = new Box<int, int>(1)\n"));
+}
+
+#[test]
+fn test_named_arguments_and_defaults_are_checked() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let errors = |declarations: &str, call: &str| {
+        let source = format!(
+            "{}\n\nfunc main() -> int\n    {}\n    return 0\n",
+            declarations, call
+        );
+        match check_named(&source, SOURCE_FILE, &Default::default()) {
+            Ok(_) => String::new(),
+            Err(err) => err.to_string(),
+        }
+    };
+    let box_fn = "func box(w: int, h: int = 1, label: string = \"\") -> int\n    return w * h";
+    assert_eq!(errors(box_fn, "box(2, label: \"a\", h: 3)"), "");
+    assert!(errors(box_fn, "box()").contains("expected 1 to 3 arguments, but got 0"));
+    assert!(errors(box_fn, "box(1, 2, \"a\", 4)").contains("expected 1 to 3 arguments, but got 4"));
+    assert!(errors(box_fn, "box(h: 2)").contains("missing an argument for 'w'"));
+    assert!(errors(box_fn, "box(1, depth: 2)").contains("no parameter named 'depth'"));
+    assert!(errors(box_fn, "box(1, w: 2)").contains("got parameter 'w' twice"));
+    assert!(errors(box_fn, "box(h: 2, 1)").contains("positional argument after a named one"));
+    assert!(errors(box_fn, "box(1, label: 2)").contains("expected type 'string'"));
+    assert!(errors(box_fn, "println(value: 1)").contains("Named argument 'value'"));
+
+    let defaults =
+        |params: &str| errors(&format!("func f({}) -> int\n    return 0", params), "f(1)");
+    assert_eq!(defaults("a: int, b: int? = null"), "");
+    assert!(defaults("a: int = 1, b: int").contains("'b' needs a default value"));
+    assert!(defaults("a: int, b: string = 2").contains("expected type 'string'"));
+    assert!(defaults("a: int, b: [int] = [1]").contains("literals and constants"));
+    let method = "class C\n    func m(a: int = 1) -> int\n        return a";
+    assert!(errors(method, "print(1)").contains("can't have a default value"));
+}
+
+#[test]
+fn test_nested_functions_only_read_the_variables_around_them() {
+    use crate::cli::{check_named, SOURCE_FILE};
+
+    let errors = |body: &str| {
+        let source = format!(
+            "func main() -> int\n    let total: int = 0\n{}    return 0\n",
+            body
+        );
+        match check_named(&source, SOURCE_FILE, &Default::default()) {
+            Ok(_) => String::new(),
+            Err(err) => err.to_string(),
+        }
+    };
+    let assigns = |header: &str, body: &str| {
+        let nested = format!(
+            "    func f({}) -> null\n{}        total = 2\n",
+            header, body
+        );
+        errors(&nested).contains("Cannot assign to 'total'")
+    };
+    assert_eq!(
+        errors("    func f() -> int\n        return total + 1\n"),
+        ""
+    );
+    assert!(assigns("", ""));
+    assert!(!assigns("total: int", ""));
+    assert!(!assigns("", "        let total: int = 1\n"));
+    let loop_body = "    while true\n        func f() -> null\n            break\n        f()\n";
+    assert!(errors(loop_body).contains("outside of a loop"));
+    assert!(errors("    func f<T>(x: T) -> T\n        return x\n").contains("type parameters"));
+
+    let method = "class C\n    func m() -> int\n        func f() -> int\n            return 1\n        return f()\n\nfunc main() -> int\n    return 0\n";
+    let error = check_named(method, SOURCE_FILE, &Default::default())
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("can't be declared in a method"));
+}
+
+#[test]
+fn test_lints_warn_and_can_be_denied_or_allowed() {
+    use crate::cli::{check_named, SOURCE_FILE};
+    use crate::compiler::lint::{Lint, LintLevels};
+
+    let warnings = |body: &str, lints: &LintLevels| {
+        let source = format!(
+            "func unused() -> int\n    return 1\n\nfunc main() -> int\n{}    return 0\n",
+            body
+        );
+        match check_named(&source, SOURCE_FILE, lints) {
+            Ok(messages) => messages.iter().map(|m| m.to_string()).collect::<Vec<_>>(),
+            Err(err) => vec![format!("error: {}", err)],
+        }
+    };
+    let lints = LintLevels::default();
+    let unused = warnings("", &lints);
+    assert!(matches!(unused.as_slice(), [w] if w.ends_with("Function 'unused' is never called")));
+    let unread = warnings("    let x: int = 1\n    let _y: int = 2\n", &lints);
+    assert!(
+        unread
+            .iter()
+            .any(|w| w.ends_with("Variable 'x' is never read")),
+        "{:?}",
+        unread
+    );
+    assert!(!unread.iter().any(|w| w.contains("_y")), "{:?}", unread);
+    let leaves = warnings("    return 1\n    print(2)\n", &lints);
+    assert!(
+        leaves.iter().any(|w| w.contains("Unreachable statement")),
+        "{:?}",
+        leaves
+    );
+    let constant = warnings("    if 1 < 2\n        print(1)\n", &lints);
+    assert!(
+        constant.iter().any(|w| w.contains("is always true")),
+        "{:?}",
+        constant
+    );
+    assert_eq!(warnings("    while true\n        break\n", &lints).len(), 1);
+
+    let allowed = lints.clone().allow(Lint::UnusedFunction);
+    assert!(warnings("", &allowed).is_empty());
+    let denied = lints.clone().deny(Lint::UnusedVariable);
+    let error = warnings("    let x: int = 1\n", &denied);
+    assert!(
+        matches!(error.as_slice(), [e] if e.starts_with("error")),
+        "{:?}",
+        error
+    );
+    let strict = lints
+        .with_warnings_as_errors(true)
+        .allow(Lint::UnusedFunction);
+    assert!(warnings("", &strict).is_empty());
+}
+
+#[test]
+fn test_locals_are_assigned_on_every_path_before_they_are_read() {
+    use crate::cli::{check_named, SOURCE_FILE};
+    use crate::errors::{AbraError, TypeError};
+
+    let unassigned = |body: &str| {
+        let source = format!(
+            "func main() -> int\n    let n: int = len(args())\n    let x: int\n{}    return x\n",
+            body
+        );
+        // Only the final `return x` is reported.
+        let line = 4 + body.lines().count();
+        match check_named(&source, SOURCE_FILE, &Default::default()) {
+            Ok(_) => false,
+            Err(AbraError::Type(errors)) => {
+                matches!(&errors[..], [TypeError::Unassigned(span, _)] if span.line == line)
+            }
+            Err(other) => panic!("{}", other),
+        }
+    };
+    assert!(unassigned(""));
+    assert!(unassigned("    if n > 1\n        x = 1\n"));
+    assert!(!unassigned(
+        "    if n > 1\n        x = 1\n    else\n        x = 2\n"
+    ));
+    assert!(!unassigned(
+        "    if n > 1\n        x = 1\n    else\n        return 0\n"
+    ));
+    assert!(unassigned("    while n > 1\n        x = 1\n"));
+    assert!(!unassigned(
+        "    while true\n        x = 1\n        break\n"
+    ));
+    assert!(unassigned(
+        "    while true\n        if n > 1\n            break\n        x = 1\n        break\n"
+    ));
+    assert!(unassigned(
+        "    try\n        x = 1\n    catch e\n        print(e)\n"
+    ));
+    assert!(!unassigned(
+        "    try\n        x = 1\n    catch e\n        x = 2\n"
+    ));
+    assert!(unassigned("    match n\n        1: x = 1\n"));
+    assert!(!unassigned(
+        "    match n > 1\n        true: x = 1\n        false: x = 2\n"
+    ));
+    assert!(!unassigned(
+        "    match n\n        1: x = 1\n        _: x = 2\n"
+    ));
+    // A lambda parameter or a variable of an inner block is another variable.
+    assert!(!unassigned(
+        "    let f: fn(int) -> int = fn(x: int) -> int: x\n    x = f(1)\n"
+    ));
+    assert!(unassigned(
+        "    if n > 1\n        let x: int = 1\n        print(x)\n"
+    ));
+}
+
+#[test]
+fn test_language_server_answers_over_stdio() {
+    use crate::cli::lsp::Server;
+    use serde_json::{json, Value};
+
+    let source = "class Point\n    let x: int = 0\n\n    func norm() -> int\n        return x\n\nfunc twice(n: int) -> int\n    return n + n\n\nfunc main() -> int\n    let p: Point = new Point()\n    let total: int = twice(p.x)\n    return \"oops\"\n";
+    let uri = "file:///tmp/lsp%20test.abra";
+    let at = |line: u32, character: u32| json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+    let requests = [
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": { "uri": uri, "languageId": "abra", "version": 1, "text": source },
+        } }),
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": at(11, 11) }),
+        json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": at(11, 21) }),
+        json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/completion", "params": at(11, 29) }),
+        json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/completion", "params": at(11, 21) }),
+        json!({ "jsonrpc": "2.0", "id": 6, "method": "workspace/symbol", "params": {} }),
+        json!({ "jsonrpc": "2.0", "id": 7, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ];
+    let mut input = Vec::new();
+    for request in requests.iter() {
+        let body = request.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+    }
+    let mut output = Vec::new();
+    Server::default()
+        .serve(&mut input.as_slice(), &mut output)
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let messages: Vec<Value> = output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|framed| serde_json::from_str(framed.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect();
+    let response = |id: u64| {
+        messages
+            .iter()
+            .find(|message| message["id"] == id)
+            .unwrap_or_else(|| panic!("no response to {}", id))
+    };
+    assert_eq!(response(1)["result"]["capabilities"]["hoverProvider"], true);
+
+    let published = &messages[1];
+    assert_eq!(published["method"], "textDocument/publishDiagnostics");
+    let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 12, "character": 4 })
+    );
+    assert_eq!(diagnostics[1]["severity"], 2);
+    assert_eq!(diagnostics[1]["code"], "unused-variable");
+
+    let hover = response(2)["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("total: integer"), "{}", hover);
+
+    let definition = &response(3)["result"];
+    assert_eq!(definition["uri"], uri);
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 6, "character": 0 })
+    );
+
+    let labels = |id: u64| -> Vec<String> {
+        response(id)["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let members = labels(4);
+    assert!(
+        members.contains(&"x".to_string()) && members.contains(&"norm".to_string()),
+        "{:?}",
+        members
+    );
+    let names = labels(5);
+    for expected in ["p", "twice", "print", "Point"] {
+        assert!(
+            names.contains(&expected.to_string()),
+            "{} missing from {:?}",
+            expected,
+            names
+        );
+    }
+
+    assert_eq!(response(6)["error"]["code"], -32601);
+    assert_eq!(response(7)["result"], Value::Null);
+}
+
+#[test]
+fn test_debug_adapter_steps_through_source_lines() {
+    use crate::cli::dap::Adapter;
+    use serde_json::{json, Value};
+
+    let path = std::env::temp_dir().join(format!("abra_dap_{}.abra", std::process::id()));
+    std::fs::write(
+        &path,
+        "func double(n: int) -> int\n    let result: int = n * 2\n    return result\n\nfunc main() -> int\n    let x: int = 20\n    let y: int = double(x)\n    print(y)\n    return 0\n",
+    )
+    .unwrap();
+    let program = path.to_str().unwrap();
+    let requests = [
+        json!({ "command": "initialize", "arguments": { "adapterID": "abra" } }),
+        json!({ "command": "launch", "arguments": { "program": program } }),
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": program }, "breakpoints": [{ "line": 6 }, { "line": 4 }],
+        } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ];
+    let mut input = Vec::new();
+    for (seq, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["seq"] = (seq + 1).into();
+        request["type"] = "request".into();
+        let body = request.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+    }
+    let mut output = Vec::new();
+    let served = Adapter::default().serve(&mut input.as_slice(), &mut output);
+    std::fs::remove_file(&path).unwrap();
+    served.unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let messages: Vec<Value> = output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|framed| serde_json::from_str(framed.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect();
+    let response = |seq: u64| {
+        let found = messages
+            .iter()
+            .find(|message| message["request_seq"] == seq)
+            .unwrap_or_else(|| panic!("no response to {}", seq));
+        assert_eq!(found["success"], true, "{}", found);
+        &found["body"]
+    };
+    let events: Vec<(&str, &Value)> = messages
+        .iter()
+        .filter(|message| message["type"] == "event")
+        .map(|message| (message["event"].as_str().unwrap(), &message["body"]))
+        .collect();
+    let top_line = |seq: u64| response(seq)["stackFrames"][0]["line"].clone();
+
+    let breakpoints = &response(3)["breakpoints"];
+    assert_eq!(breakpoints[0], json!({ "verified": true, "line": 6 }));
+    assert_eq!(breakpoints[1], json!({ "verified": true, "line": 6 }));
+    let stops: Vec<&str> = events
+        .iter()
+        .filter(|(event, _)| *event == "stopped")
+        .map(|(_, body)| body["reason"].as_str().unwrap())
+        .collect();
+    assert_eq!(stops, ["breakpoint", "step", "step", "step"]);
+    assert_eq!(top_line(5), 6);
+    assert_eq!(response(5)["stackFrames"][0]["name"], "main");
+    assert_eq!(top_line(8), 2);
+    assert_eq!(response(8)["stackFrames"][1]["line"], 7);
+    assert_eq!(response(9)["scopes"][0]["variablesReference"], 1);
+    let locals = &response(10)["variables"];
+    assert!(
+        locals
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "name": "n", "value": "20", "variablesReference": 0 })),
+        "{}",
+        locals
+    );
+    assert_eq!(top_line(12), 7);
+    assert!(
+        events.contains(&("output", &json!({ "category": "stdout", "output": "40" }))),
+        "{:?}",
+        events
+    );
+    assert!(
+        events.contains(&("exited", &json!({ "exitCode": 0 }))),
+        "{:?}",
+        events
+    );
+    assert_eq!(events.last().unwrap().0, "terminated");
+}
+
+#[test]
+fn test_debug_repl_breaks_steps_watches_and_lists_source() {
+    use crate::cli::debug_repl::DebugRepl;
+    use crate::runtime::{debugger::Debugger, platform::SharedBuffer};
+
+    let path = std::env::temp_dir().join(format!("abra_debug_repl_{}.abra", std::process::id()));
+    std::fs::write(
+        &path,
+        "func double(n: int) -> int\n    let result: int = n * 2\n    return result\n\nfunc main() -> int\n    let total: int = 0\n    for let i: int = 0, i < 3, i = i + 1\n        total = total + double(i)\n    print(total)\n    return 0\n",
+    )
+    .unwrap();
+    let program = path.to_str().unwrap();
+    let code = compile(program, 0).unwrap();
+    let mut debugger = Debugger::new(code);
+    let printed = SharedBuffer::default();
+    debugger.machine_mut().set_stdout(Box::new(printed.clone()));
+    let commands = "break double\nbreak 4\nrun\ndelete 2\ncontinue\nbt\nprint n\nfinish\nwatch total\ndelete 1\ncontinue\n\nlist\n!7\nfrobnicate\nhistory\ncontinue\nstep\n";
+    let mut output = Vec::new();
+    let ran = DebugRepl::new(debugger).run(&mut commands.as_bytes(), &mut output);
+    std::fs::remove_file(&path).unwrap();
+    ran.unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let expected = [
+        "Breakpoint 1 at double, {file}:2",
+        "Breakpoint 2 at {file}:6",
+        "Breakpoint 2, main at {file}:6\n   6      let total: int = 0",
+        "Breakpoint 1, double at {file}:2\n   2      let result: int = n * 2",
+        "#0 double at {file}:2\n#1 main at {file}:8",
+        "n = 0",
+        "main at {file}:8\n   8          total = total + double(i)",
+        "Watch 3: total = 0",
+        "Watch 3: total\nOld value = 0\nNew value = 2\nmain at {file}:7",
+        "Watch 3: total\nOld value = 2\nNew value = 6\nmain at {file}:7",
+        "->    7      for let i: int = 0, i < 3, i = i + 1",
+        "print n\nNo variable 'n' here.",
+        "Unknown command 'frobnicate', try 'help'.",
+        "   1  break double\n   2  break 4\n",
+        "  15  history",
+        "Program exited with code 0.\n(abra) The program is not running.",
+    ];
+    for expected in expected {
+        let expected = expected.replace("{file}", program);
+        assert!(
+            output.contains(&expected),
+            "{:?} not in:\n{}",
+            expected,
+            output
+        );
+    }
+    assert_eq!(printed.contents(), "6");
+}
+
+#[test]
+fn test_debug_repl_breakpoints_stop_only_where_their_condition_holds() {
+    use crate::cli::debug_repl::DebugRepl;
+    use crate::runtime::{debugger::Debugger, platform::SharedBuffer};
+
+    let path = std::env::temp_dir().join(format!("abra_debug_cond_{}.abra", std::process::id()));
+    std::fs::write(
+        &path,
+        "func double(n: int) -> int\n    let result: int = n * 2\n    return result\n\nfunc main() -> int\n    let total: int = 0\n    for let i: int = 0, i < 3, i = i + 1\n        total = total + double(i)\n    print(total)\n    return 0\n",
+    )
+    .unwrap();
+    let program = path.to_str().unwrap();
+    let code = compile(program, 0).unwrap();
+    let mut debugger = Debugger::new(code);
+    let printed = SharedBuffer::default();
+    debugger.machine_mut().set_stdout(Box::new(printed.clone()));
+    let commands = "break 8 if i == 2\nbreak double if n > nope\nbreak double if n == 1\nbreak\nrun\nprint n\ncontinue\nprint i\ncondition 1 i ==\ncondition 2 n / (n - 2) == 0\ncontinue\ncondition 2\ncontinue\ncontinue\n";
+    let mut output = Vec::new();
+    let ran = DebugRepl::new(debugger).run(&mut commands.as_bytes(), &mut output);
+    std::fs::remove_file(&path).unwrap();
+    ran.unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let expected = [
+        "Breakpoint 1 at {file}:8",
+        "Can't use the condition: 1:1: Variable nope not found",
+        "Breakpoint 2 at double, {file}:2",
+        "1    breakpoint at {file}:8 if i == 2\n2    breakpoint at double if n == 1",
+        "Breakpoint 2, double at {file}:2\n   2      let result: int = n * 2\n(abra) n = 1",
+        "Breakpoint 1, main at {file}:8\n   8          total = total + double(i)\n(abra) i = 2",
+        "Can't use the condition: 1:5: Expected primary expression",
+        "Error in the condition of breakpoint 2: runtime error: Division by zero\nBreakpoint 2",
+        "Breakpoint 2 stops every time.",
+        "Program exited with code 0.",
+    ];
+    for expected in expected {
+        let expected = expected.replace("{file}", program);
+        assert!(
+            output.contains(&expected),
+            "{:?} not in:\n{}",
+            expected,
+            output
+        );
+    }
+    assert_eq!(printed.contents(), "6");
+}
+
+#[test]
+fn test_release_builds_strip_debug_info() {
+    use crate::runtime::debug::RuntimeError;
+
+    let mut code = compile("tests/programs/stack_overflow.abra", 0).unwrap();
+    let with_debug_info = bincode::serialize(&code).unwrap().len();
+    code.strip_debug_info();
+    assert!(bincode::serialize(&code).unwrap().len() < with_debug_info);
+    let err = run(code).unwrap_err();
+    let err = err.downcast_ref::<RuntimeError>().unwrap();
+    assert!(err.source().is_none());
+    assert!(err.bytecode_index().is_some());
+}
+
+#[test]
+fn test_bytecode_files_are_versioned_and_checksummed() {
+    use crate::compiler::container::{
+        self, ContainerError, FLAG_DEBUG_INFO, FLAG_SYMBOLS, FORMAT_VERSION,
+    };
+
+    let code = compile("tests/programs/stack_overflow.abra", 0).unwrap();
+    let bytes = container::encode(&code);
+    let header = container::read_header(&bytes).unwrap();
+    assert_eq!(header.format_version, FORMAT_VERSION);
+    assert_eq!(header.flags, FLAG_DEBUG_INFO | FLAG_SYMBOLS);
+    assert_eq!(header.compiler_version, env!("CARGO_PKG_VERSION"));
+    let names: Vec<&str> = header.sections.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["CODE", "DBUG", "SYMS"]);
+    assert!(header.to_string().contains("(debug-info, symbols)"));
+
+    let decoded = container::decode(&bytes).unwrap();
+    assert_eq!(
+        decoded.string_representation(),
+        code.string_representation()
+    );
+    assert_eq!(decoded.locations, code.locations);
+    assert_eq!(decoded.symbols, code.symbols);
+
+    // Symbols are needed to link against the code, so release builds keep them.
+    let mut stripped = code.clone();
+    stripped.strip_debug_info();
+    let header = container::read_header(&container::encode(&stripped)).unwrap();
+    assert_eq!((header.flags, header.sections.len()), (FLAG_SYMBOLS, 2));
+
+    let mut damaged = bytes.clone();
+    *damaged.last_mut().unwrap() ^= 0xff;
+    assert!(
+        matches!(container::decode(&damaged), Err(ContainerError::Corrupt(name)) if name == "SYMS")
+    );
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    let error = container::read_header(&newer).unwrap_err();
+    assert!(
+        matches!(error, ContainerError::UnsupportedVersion { found } if found == FORMAT_VERSION + 1)
+    );
+    assert!(error.to_string().contains("recompile"), "{}", error);
+    let legacy = bincode::serialize(&code).unwrap();
+    assert!(matches!(
+        container::decode(&legacy),
+        Err(ContainerError::NotBytecode)
+    ));
+    assert!(matches!(
+        container::decode(&bytes[..bytes.len() - 1]),
+        Err(ContainerError::Truncated(_))
+    ));
+}
+
+#[test]
+fn test_compiled_code_keeps_the_symbols_it_defines() {
+    use crate::compiler::Symbol;
+
+    let source = "class Point\n    let x: int = 0\n\n    func norm() -> int\n        return x\n\nfunc twice(n: int) -> int\n    return n + n\n\nfunc main() -> int\n    return twice(new Point().norm())\n";
+    let code = compile_source(source, 0).unwrap();
+    let names: Vec<&str> = code.symbols.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["Point", "main", "twice"]);
+    match code.symbol("twice") {
+        Some(Symbol::Function((Some(start), signature))) => {
+            assert_eq!(Some(start), code.labels.get("twice"));
+            assert_eq!(signature.parameters().len(), 1);
+        }
+        other => panic!("expected a compiled function, got {:?}", other),
+    }
+    assert!(
+        matches!(code.symbol("Point"), Some(Symbol::Class(def)) if def.variables.contains_key("x"))
+    );
+    // Builtins have no code to link against.
+    assert!(code.symbol("print").is_none());
+}
+
+#[test]
+fn test_linked_units_call_each_other_and_set_their_globals_before_main() {
+    use crate::cli::{compile_library, compile_named};
+    use crate::compiler::{
+        link::{link, LinkError},
+        lint::LintLevels,
+        Symbol,
+    };
+
+    let library = "let base: int = 10\n\nfunc twice(n: int) -> int\n    return n * 2 + base\n";
+    let (lib, _) = compile_library(library, "lib.abra").unwrap();
+    assert!(matches!(lib.symbol("twice"), Some(Symbol::Function(_))));
+    // Compiled against the library, the program calls `twice` by name.
+    let program = "let offset: int = twice(1)\n\nfunc main() -> int\n    return twice(offset)\n";
+    let (app, _) = compile_named(program, "main.abra", 0, &lib, &LintLevels::default()).unwrap();
+    assert!(link(vec![app.clone()]).is_err_and(|e| e == LinkError::UnresolvedCall("twice".into())));
+
+    let linked = link(vec![app.clone(), lib.clone()]).unwrap();
+    // The program, which defines `main`, goes last.
+    assert_eq!(linked.files, ["lib.abra", "main.abra"]);
+    assert_eq!(linked.locations.len(), linked.bytecode.len());
+    let names: Vec<&str> = linked
+        .symbols
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(names, ["main", "twice"]);
+    for (name, symbol) in linked.symbols.iter() {
+        let Symbol::Function((start, _)) = symbol else {
+            panic!("only functions are defined");
+        };
+        assert_eq!(start.as_ref(), linked.labels.get(name));
+    }
+    // The library's globals are set before the program's use them.
+    assert_eq!(run(linked).unwrap(), 34);
+
+    let twice_again = link(vec![lib.clone(), lib, app]);
+    assert!(twice_again.is_err_and(|e| e == LinkError::DuplicateSymbol("twice".into())));
+    assert!(link(Vec::new()).is_err_and(|e| e == LinkError::NoUnits));
+}
+
+#[test]
+fn test_verifier_accepts_compiled_programs_and_reports_broken_code() {
+    use crate::compiler::assembly::assemble;
+    use crate::compiler::verify::{verify, VerifyError};
+
+    for program in golden_programs().iter().map(|path| path.to_str().unwrap()) {
+        let Ok(code) = compile(program, 0) else {
+            continue;
+        };
+        assert_eq!(verify(&code), Ok(()), "{}", program);
+    }
+
+    let broken = [
+        ("main:\n    EXIT\n", VerifyError::MissingStart),
+        (
+            ".const {\"Integer\":1}\n_start:\n    PUSH 0\n    ADD\n    EXIT\n",
+            VerifyError::StackUnderflow(1, 2, 1),
+        ),
+        // The value is only pushed on the path that skips the jump's target.
+        (
+            ".const {\"Bool\":true}\n_start:\n    PUSH 0\n    JITL \"done\"\n    PUSH 0\ndone:\n    POP\n    POP\n    EXIT\n",
+            VerifyError::StackUnderflow(3, 1, 0),
+        ),
+        ("_start:\n    JMPTO \"nowhere\"\n", VerifyError::UnknownLabel(0, "nowhere".into())),
+        ("_start:\n    JMPABS 7\n", VerifyError::JumpOutOfRange(0, 7)),
+        ("_start:\n    PUSH 3\n    EXIT\n", VerifyError::UnknownConstant(0, 3)),
+        ("_start:\n    CALL \"missing\" 0\n    EXIT\n", VerifyError::UnknownCall(0, "missing".into())),
+        (
+            ".const \"Null\"\n_start:\n    PUSH 0\n    DEFVAR \"x\" \"Unknown\"\n    EXIT\n",
+            VerifyError::MalformedType(1, "unknown".into()),
+        ),
+        (".const \"Null\"\n_start:\n    PUSH 0\n    POP\n", VerifyError::RunsPastEnd(1)),
+    ];
+    for (text, error) in broken {
+        let code = assemble(text).unwrap_or_else(|e| panic!("{}: {:#}", text, e));
+        assert_eq!(verify(&code), Err(error), "{}", text);
+    }
+}
+
+#[test]
+fn test_assembly_round_trips_and_assembles_by_hand() {
+    use crate::compiler::assembly::{assemble, to_assembly};
+
+    let programs = golden_programs();
+    for program in programs.iter().map(|path| path.to_str().unwrap()) {
+        let Ok(code) = compile(program, 0) else {
+            continue;
+        };
+        let text = to_assembly(&code);
+        let assembled = assemble(&text).unwrap_or_else(|e| panic!("{}: {:#}", program, e));
+        assert_eq!(
+            assembled.string_representation(),
+            code.string_representation(),
+            "{}",
+            program
+        );
+        assert_eq!(assembled.locations, code.locations);
+        assert_eq!(assembled.files, code.files);
+        assert_eq!(assembled.local_names, code.local_names);
+        assert_eq!(assembled.symbols, code.symbols);
+        assert_eq!(to_assembly(&assembled), text);
+    }
+
+    let text = "; 2 + 40\n.const {\"Integer\":2}\n.const {\"Integer\":40}\n_start:\n    PUSH 0\n    PUSH 1 ; the answer; almost\n    ADD\n    EXIT\n";
+    let code = assemble(text).unwrap();
+    assert_eq!(run(code).unwrap(), 42);
+
+    let Err(error) = assemble("_start:\n    PUSH 0\n    FROB 1\n") else {
+        panic!("assembled an unknown instruction");
+    };
+    assert!(
+        format!("{:#}", error).starts_with("line 3: Invalid instruction 'FROB 1'"),
+        "{:#}",
+        error
+    );
+    let Err(error) = assemble("a:\na:\n") else {
+        panic!("assembled a label defined twice");
+    };
+    assert!(
+        format!("{:#}", error).contains("defined twice"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn test_formatter_is_stable_and_keeps_programs_the_same() {
+    use crate::cli::compile_named;
+    use crate::frontend::format::format_source;
+
+    let programs = golden_programs();
+    let compiled = |source: &str, program: &str| {
+        let (mut code, _) =
+            compile_named(source, program, 0, &Vec::new(), &Default::default()).ok()?;
+        code.strip_debug_info();
+        Some(code.string_representation())
+    };
+    for program in programs.iter().map(|path| path.to_str().unwrap()) {
+        let source = std::fs::read_to_string(program).unwrap();
+        let Ok(formatted) = format_source(&source) else {
+            continue;
+        };
+        assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", program);
+        assert_eq!(
+            compiled(&formatted, program),
+            compiled(&source, program),
+            "{}",
+            program
+        );
+    }
+
+    let source = "// sums\nfunc main()->int\n\tlet xs : [int] = 1->4 // a range\n\n\n\tif len(xs)>2 : return xs[0]+xs[1]\n\telse\n\t\t// none\n\t\treturn -( 1+2 )\n";
+    let expected = "// sums\nfunc main() -> int\n    let xs: [int] = 1 -> 4  // a range\n\n    if len(xs) > 2: return xs[0] + xs[1]\n    else\n        // none\n        return -(1 + 2)\n";
+    assert_eq!(format_source(source).unwrap(), expected);
+    assert!(format_source("func main() -> int\n    return (\n").is_err());
+}
+
+#[test]
+fn test_runner_reports_each_test_and_keeps_going_after_failures() {
+    use crate::cli::test_runner::{run_tests, Summary};
+
+    let dir = std::env::temp_dir().join(format!("abra_tests_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(
+        dir.join("math.abra"),
+        "let base: int = 40\n\nfunc test_add() -> null\n    assert_eq(base + 2, 42)\n\nfunc test_wrong() -> null\n    print(\"computing\")\n    assert_eq(\"a\" + \"b\", \"ba\")\n    fail(\"unreachable\")\n\nfunc test_caught() -> null\n    try\n        assert(1 > 2)\n    catch e\n        print(e)\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("lib/helpers.abra"),
+        "func fail() -> int\n    return 1\n",
+    )
+    .unwrap();
+    let mut report = Vec::new();
+    let summary = run_tests(dir.to_str().unwrap(), &mut report).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let report = String::from_utf8(report).unwrap();
+
+    assert_eq!(
+        summary,
+        Summary {
+            passed: 1,
+            failed: 2
+        },
+        "{}",
+        report
+    );
+    assert!(report.contains("running 3 tests from "), "{}", report);
+    assert!(
+        report.contains("test test_add ... ok\ntest test_wrong ... FAILED\n"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("assertion failed: \"ab\" != \"ba\"\n  at ")
+            && report.contains("math.abra:8:5\noutput:\ncomputing\n"),
+        "{}",
+        report
+    );
+    assert!(report.contains("assertion failed\n  at "), "{}", report);
+    assert!(
+        report.ends_with("test result: FAILED. 1 passed; 2 failed\n"),
+        "{}",
+        report
+    );
+}